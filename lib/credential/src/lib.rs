@@ -2,13 +2,13 @@ use std::num::{NonZeroU32, NonZeroUsize};
 
 use derive_more::Display;
 use ring::{digest, pbkdf2};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use tokio::sync::Semaphore;
 
 static PBKDF2_ALG: pbkdf2::Algorithm = pbkdf2::PBKDF2_HMAC_SHA256;
 const CREDENTIAL_LEN: usize = digest::SHA256_OUTPUT_LEN;
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
 #[serde(default)]
 pub struct CredentialConfig {
     pub secret: Option<String>,
@@ -1,9 +1,13 @@
 use std::collections::{HashMap, HashSet};
+use std::time::Duration;
 
 use chrono::{DateTime, Utc};
-use rusoto_core::credential::StaticProvider;
-use rusoto_dynamodb::{AttributeValue, DynamoDbClient, UpdateItemInput};
-use rusoto_util::{parse_region, CustomChainProvider};
+use rusoto_core::RusotoError;
+use rusoto_dynamodb::{
+    AttributeValue, DescribeTableError, DescribeTableInput, DynamoDb, DynamoDbClient,
+    ListTablesError, ListTablesInput, UpdateItemInput,
+};
+use rusoto_util::{client_config, Target};
 
 pub trait IntoAttribute {
     fn into_attribute(self) -> AttributeValue;
@@ -112,20 +116,40 @@ impl UpdateBuilder {
     }
 }
 
-pub fn dynamo_client(region: String, endpoint: Option<String>, local: bool) -> DynamoDbClient {
-    let region = parse_region(region, endpoint);
+pub fn dynamo_client(region: String, endpoint: Option<String>, target: Target) -> DynamoDbClient {
+    let (region, credentials) = client_config(region, endpoint, target);
     let dispatcher =
         rusoto_core::request::HttpClient::new().expect("failed to create request dispatcher");
 
-    if local {
-        return DynamoDbClient::new_with(
-            dispatcher,
-            StaticProvider::new_minimal("local".to_string(), "development".to_string()),
-            region,
-        );
-    }
+    DynamoDbClient::new_with(dispatcher, credentials, region)
+}
+
+/// Waits for DynamoDB to accept requests, such as whilst a LocalStack container starts
+pub async fn wait_ready(
+    client: &DynamoDbClient,
+    timeout: Duration,
+) -> Result<(), RusotoError<ListTablesError>> {
+    rusoto_util::wait_ready(timeout, || {
+        client.list_tables(ListTablesInput {
+            limit: Some(1),
+            ..Default::default()
+        })
+    })
+    .await?;
+    Ok(())
+}
 
-    DynamoDbClient::new_with(dispatcher, CustomChainProvider::new(), region)
+/// Checks that `table` exists and can be described, such as for a readiness probe
+pub async fn check_table(
+    client: &DynamoDbClient,
+    table: &str,
+) -> Result<(), RusotoError<DescribeTableError>> {
+    client
+        .describe_table(DescribeTableInput {
+            table_name: table.to_string(),
+        })
+        .await?;
+    Ok(())
 }
 
 #[cfg(test)]
@@ -0,0 +1,58 @@
+use serde_json::error::Category;
+
+use crate::Error;
+
+/// Treats the error as that of reading a request body, so that only a failure to read
+/// it is internal
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        match e.classify() {
+            Category::Io => Error::internal(e),
+            Category::Syntax | Category::Data | Category::Eof => {
+                Error::bad_request(format!("Invalid JSON: {}", e))
+            }
+        }
+    }
+}
+
+/// Treats the error as that of a request to an upstream
+#[cfg(feature = "reqwest")]
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_timeout() {
+            Error::status(504).with_cause(e)
+        } else {
+            Error::status(502).with_cause(e)
+        }
+    }
+}
+
+#[cfg(feature = "rusoto_core")]
+impl<E: std::error::Error + 'static> From<rusoto_core::RusotoError<E>> for Error {
+    fn from(e: rusoto_core::RusotoError<E>) -> Self {
+        use rusoto_core::RusotoError;
+
+        match &e {
+            RusotoError::HttpDispatch(_) => Error::status(503).with_cause(e),
+            RusotoError::Unknown(response) if response.status.as_u16() == 503 => {
+                Error::status(503).with_cause(e)
+            }
+            _ => Error::internal(e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serde_json() {
+        let error = Error::from(serde_json::from_str::<u32>("[").unwrap_err());
+        assert_eq!(error.status_code(), 400);
+        assert!(error.message().starts_with("Invalid JSON"));
+
+        let error = Error::from(serde_json::from_str::<u32>("\"a\"").unwrap_err());
+        assert_eq!(error.status_code(), 400);
+    }
+}
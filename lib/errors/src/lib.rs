@@ -0,0 +1,228 @@
+//! The errors of the HTTP APIs of services
+//!
+//! Every error is responded with its status and a common envelope, such as
+//!
+//! ```json
+//! {"code": "not_found", "message": "Not Found", "request_id": "4a6e4b4e-..."}
+//! ```
+//!
+//! where `code` identifies the error to clients, `request_id` is that of the request
+//! that failed, if known, and `details` holds any further information specific to the
+//! error. The cause of an internal error is logged rather than exposed
+//!
+//! The `rocket` and `actix-web` features add responders for each framework, and the
+//! `reqwest` and `rusoto_core` features conversions from the errors of their clients
+
+use std::borrow::Cow;
+use std::fmt::{self, Display, Formatter};
+
+use log::error;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use telemetry::IsErr;
+
+/// The header identifying a request, whose value is echoed in the envelope
+pub use telemetry::trace::REQUEST_ID;
+
+mod convert;
+mod respond;
+
+/// The envelope an error is responded with
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ErrorBody {
+    /// Identifies the error, such as `not_found`
+    pub code: String,
+    pub message: String,
+    /// The ID of the request that failed, if known
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    /// Information specific to the error, such as where an expression failed to parse
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub details: Option<Value>,
+}
+
+/// An error to respond to a request with
+#[derive(Debug)]
+pub struct Error {
+    status: u16,
+    code: Cow<'static, str>,
+    message: Cow<'static, str>,
+    details: Option<Value>,
+    headers: Vec<(&'static str, String)>,
+    /// Why the request failed, logged rather than exposed
+    cause: Option<String>,
+}
+
+impl Error {
+    pub fn new(
+        status: u16,
+        code: impl Into<Cow<'static, str>>,
+        message: impl Into<Cow<'static, str>>,
+    ) -> Error {
+        Error {
+            status,
+            code: code.into(),
+            message: message.into(),
+            details: None,
+            headers: vec![],
+            cause: None,
+        }
+    }
+
+    /// Returns the error of `status` with no more specific code, such as `not_found`
+    /// for 404
+    pub fn status(status: u16) -> Error {
+        let (code, message) = match status {
+            400 => ("invalid_request", "Invalid Request"),
+            401 => ("unauthorized", "Unauthorized"),
+            403 => ("forbidden", "Forbidden"),
+            404 => ("not_found", "Not Found"),
+            405 => ("method_not_allowed", "Method Not Allowed"),
+            409 => ("conflict", "Conflict"),
+            413 => ("payload_too_large", "Payload Too Large"),
+            422 => ("unprocessable_entity", "Unprocessable Entity"),
+            429 => ("too_many_requests", "Too Many Requests"),
+            502 => ("bad_gateway", "Bad Gateway"),
+            503 => ("unavailable", "Service Unavailable"),
+            504 => ("gateway_timeout", "Gateway Timeout"),
+            _ if status < 500 => ("client_error", "Client Error"),
+            _ => ("internal", "Internal Server Error"),
+        };
+        Error::new(status, code, message)
+    }
+
+    pub fn bad_request(message: impl Into<Cow<'static, str>>) -> Error {
+        Error::new(400, "invalid_request", message)
+    }
+
+    pub fn not_found() -> Error {
+        Error::status(404)
+    }
+
+    pub fn forbidden() -> Error {
+        Error::status(403)
+    }
+
+    pub fn unavailable(message: impl Into<Cow<'static, str>>) -> Error {
+        Error::new(503, "unavailable", message)
+    }
+
+    /// Returns an internal error, which is responded as a 500 without exposing `cause`
+    pub fn internal(cause: impl Display) -> Error {
+        Error::status(500).with_cause(cause)
+    }
+
+    /// Adds information specific to the error to its envelope
+    pub fn with_details(mut self, details: impl Serialize) -> Error {
+        self.details = Some(serde_json::to_value(details).expect("Failed to serialize details"));
+        self
+    }
+
+    /// Adds a header to the response, such as the `Retry-After` of a 429
+    pub fn with_header(mut self, name: &'static str, value: impl Into<String>) -> Error {
+        self.headers.push((name, value.into()));
+        self
+    }
+
+    /// Records why the request failed, which is logged when the error is responded
+    pub fn with_cause(mut self, cause: impl Display) -> Error {
+        self.cause = Some(cause.to_string());
+        self
+    }
+
+    pub fn status_code(&self) -> u16 {
+        self.status
+    }
+
+    pub fn code(&self) -> &str {
+        &self.code
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    pub fn headers(&self) -> &[(&'static str, String)] {
+        &self.headers
+    }
+
+    /// Returns the envelope of the error, logging its cause
+    pub fn body(&self, request_id: Option<&str>) -> ErrorBody {
+        if let Some(cause) = &self.cause {
+            error!(
+                "{} {} (request {}): {}",
+                self.status,
+                self.code,
+                request_id.unwrap_or("unknown"),
+                cause
+            );
+        }
+        ErrorBody {
+            code: self.code.to_string(),
+            message: self.message.to_string(),
+            request_id: request_id.map(ToString::to_string),
+            details: self.details.clone(),
+        }
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match &self.cause {
+            Some(cause) => write!(f, "{}: {}", self.message, cause),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Only server errors are failures, clients are at fault for the others
+impl IsErr for Error {
+    fn is_err(&self) -> bool {
+        self.status >= 500
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_body() {
+        let error = Error::internal("connection reset");
+        assert_eq!(error.status_code(), 500);
+        assert!(error.is_err());
+        assert_eq!(
+            serde_json::to_value(error.body(Some("abc"))).unwrap(),
+            json!({
+                "code": "internal",
+                "message": "Internal Server Error",
+                "request_id": "abc",
+            })
+        );
+
+        let error = Error::new(422, "syntax", "Unexpected \")\" at 4")
+            .with_details(json!({ "position": 4 }));
+        assert!(!error.is_err());
+        assert_eq!(
+            serde_json::to_value(error.body(None)).unwrap(),
+            json!({
+                "code": "syntax",
+                "message": "Unexpected \")\" at 4",
+                "details": { "position": 4 },
+            })
+        );
+    }
+
+    #[test]
+    fn test_status() {
+        assert_eq!(Error::status(404).code(), "not_found");
+        assert_eq!(Error::status(418).code(), "client_error");
+        assert_eq!(Error::status(599).code(), "internal");
+    }
+}
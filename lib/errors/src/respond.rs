@@ -0,0 +1,54 @@
+#[cfg(feature = "rocket")]
+mod rocket_response {
+    use rocket::http::Status;
+    use rocket::response::{self, content, Responder};
+    use rocket::Request;
+
+    use crate::{Error, REQUEST_ID};
+
+    impl From<Status> for Error {
+        fn from(status: Status) -> Self {
+            Error::status(status.code)
+        }
+    }
+
+    /// Responds with the envelope, echoing the `X-Request-Id` of the request
+    impl<'r> Responder<'r, 'static> for Error {
+        fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+            let status = Status::from_code(self.status).unwrap_or(Status::InternalServerError);
+            let body = self.body(req.headers().get_one(REQUEST_ID));
+            let body = serde_json::to_string(&body).expect("Failed to serialize error");
+
+            let mut response =
+                response::status::Custom(status, content::Json(body)).respond_to(req)?;
+            for (name, value) in self.headers {
+                response.set_raw_header(name, value);
+            }
+            Ok(response)
+        }
+    }
+}
+
+#[cfg(feature = "actix-web")]
+mod actix_response {
+    use actix_web::http::StatusCode;
+    use actix_web::{HttpResponse, ResponseError};
+
+    use crate::Error;
+
+    /// Responds with the envelope, without a request ID as actix doesn't pass the
+    /// request to the error
+    impl ResponseError for Error {
+        fn status_code(&self) -> StatusCode {
+            StatusCode::from_u16(self.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)
+        }
+
+        fn error_response(&self) -> HttpResponse {
+            let mut builder = HttpResponse::build(self.status_code());
+            for (name, value) in &self.headers {
+                builder.header(*name, value.as_str());
+            }
+            builder.json(self.body(None))
+        }
+    }
+}
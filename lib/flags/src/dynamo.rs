@@ -0,0 +1,129 @@
+//! Reads flags from the items of a DynamoDB table
+//!
+//! Each item is a flag, keyed by its `name` (S), with optional `enabled` (BOOL),
+//! `subjects` (SS) and `percentage` (N) attributes defaulting as in configuration
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::warn;
+use rusoto_dynamodb::{AttributeValue, DynamoDb, DynamoDbClient, ScanInput};
+use tokio::time::delay_for;
+
+use crate::{Flag, Flags};
+
+/// Returns the name and flag of an item of the table
+fn parse(item: &HashMap<String, AttributeValue>) -> Result<(String, Flag), String> {
+    let name = item
+        .get("name")
+        .and_then(|x| x.s.clone())
+        .ok_or_else(|| "Item without name".to_string())?;
+
+    let mut flag = Flag::default();
+    if let Some(enabled) = item.get("enabled") {
+        flag.enabled = enabled
+            .bool
+            .ok_or_else(|| format!("Flag {} has invalid enabled", name))?;
+    }
+    if let Some(subjects) = item.get("subjects") {
+        flag.subjects = subjects.ss.iter().flatten().cloned().collect();
+    }
+    if let Some(percentage) = item.get("percentage") {
+        let percentage = percentage.n.as_ref().and_then(|x| x.parse().ok());
+        flag.percentage =
+            Some(percentage.ok_or_else(|| format!("Flag {} has invalid percentage", name))?);
+    }
+    Ok((name, flag))
+}
+
+/// Returns the flags of every item of `table`, skipping those that are invalid
+async fn read(client: &DynamoDbClient, table: &str) -> Result<HashMap<String, Flag>, String> {
+    let mut flags = HashMap::new();
+    let mut exclusive_start_key = None;
+    loop {
+        let output = client
+            .scan(ScanInput {
+                table_name: table.to_string(),
+                exclusive_start_key,
+                ..Default::default()
+            })
+            .await
+            .map_err(|e| e.to_string())?;
+
+        for item in output.items.iter().flatten() {
+            match parse(item) {
+                Ok((name, flag)) => {
+                    flags.insert(name, flag);
+                }
+                Err(e) => warn!("Skipping flag: {}", e),
+            }
+        }
+
+        exclusive_start_key = output.last_evaluated_key;
+        if exclusive_start_key.is_none() {
+            return Ok(flags);
+        }
+    }
+}
+
+/// Reads the flags of `table` every `interval`, keeping the previous flags if it fails
+pub async fn poll(flags: Arc<Flags>, client: DynamoDbClient, table: String, interval: Duration) {
+    loop {
+        match read(&client, &table).await {
+            Ok(read) => flags.update(read),
+            Err(e) => warn!("Failed to read flags from {}: {}", table, e),
+        }
+        delay_for(interval).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attribute(f: impl FnOnce(&mut AttributeValue)) -> AttributeValue {
+        let mut value = AttributeValue::default();
+        f(&mut value);
+        value
+    }
+
+    #[test]
+    fn test_parse() {
+        let mut item = HashMap::new();
+        item.insert(
+            "name".to_string(),
+            attribute(|x| x.s = Some("auth.grant.password".to_string())),
+        );
+        assert_eq!(
+            parse(&item).unwrap(),
+            ("auth.grant.password".to_string(), Flag::default())
+        );
+
+        item.insert("enabled".to_string(), attribute(|x| x.bool = Some(false)));
+        item.insert(
+            "subjects".to_string(),
+            attribute(|x| x.ss = Some(vec!["my_client".to_string()])),
+        );
+        item.insert(
+            "percentage".to_string(),
+            attribute(|x| x.n = Some("12.5".to_string())),
+        );
+        let (_, flag) = parse(&item).unwrap();
+        assert!(!flag.enabled);
+        assert!(flag.subjects.contains("my_client"));
+        assert_eq!(flag.percentage, Some(12.5));
+
+        item.insert(
+            "percentage".to_string(),
+            attribute(|x| x.s = Some("half".to_string())),
+        );
+        assert_eq!(
+            parse(&item).unwrap_err(),
+            "Flag auth.grant.password has invalid percentage"
+        );
+
+        item.remove("name");
+        assert!(parse(&item).is_err());
+    }
+}
@@ -0,0 +1,288 @@
+//! Feature flags, turning behaviour on or off without a deploy
+//!
+//! A flag is looked up by a `Key`, declared alongside the code it gates with the value it
+//! has if it isn't configured
+//!
+//! ```ignore
+//! const PASSWORD_GRANT: Key = Key::new("auth.grant.password", true);
+//!
+//! if !flags.is_enabled(&PASSWORD_GRANT, Some(client_id)) { ... }
+//! ```
+//!
+//! Flags are read from the configuration of the service and, if a DynamoDB table is
+//! configured, from the items of the table, which are polled and take precedence
+//!
+//! A flag that isn't enabled is off for every subject, acting as a kill switch. Otherwise
+//! it is on for the subjects it lists, and for the `percentage` of other subjects whose
+//! hash with the name of the flag falls within it, so a subject sees the same value on
+//! every request and as the percentage is raised
+//!
+//! ```json
+//! {"enabled": true, "subjects": ["my_client"], "percentage": 10.0}
+//! ```
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+mod dynamo;
+
+pub use dynamo::poll;
+
+/// The number of buckets subjects are hashed into for percentage rollouts
+const BUCKETS: u64 = 10_000;
+
+/// A flag looked up by code, along with the value it has if it isn't configured
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Key {
+    pub name: &'static str,
+    pub default: bool,
+}
+
+impl Key {
+    pub const fn new(name: &'static str, default: bool) -> Key {
+        Key { name, default }
+    }
+}
+
+/// The rule deciding the subjects a flag is on for
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct Flag {
+    /// If the flag can be on for any subject
+    pub enabled: bool,
+    /// Subjects the flag is always on for, if enabled
+    pub subjects: HashSet<String>,
+    /// The percentage of other subjects the flag is on for, None for all of them
+    pub percentage: Option<f64>,
+}
+
+impl Default for Flag {
+    fn default() -> Flag {
+        Flag {
+            enabled: true,
+            subjects: HashSet::new(),
+            percentage: None,
+        }
+    }
+}
+
+impl Flag {
+    /// Returns if the flag named `name` is on for `subject`
+    ///
+    /// Without a subject, such as for an anonymous request, a partial rollout is off
+    pub fn evaluate(&self, name: &str, subject: Option<&str>) -> bool {
+        if !self.enabled {
+            return false;
+        }
+
+        let percentage = match self.percentage {
+            None => return true,
+            Some(percentage) if percentage >= 100. => return true,
+            Some(percentage) => percentage,
+        };
+
+        match subject {
+            Some(subject) if self.subjects.contains(subject) => true,
+            Some(subject) => (bucket(name, subject) as f64) < percentage * (BUCKETS / 100) as f64,
+            None => false,
+        }
+    }
+}
+
+/// Returns the bucket of `subject` for the flag named `name`
+///
+/// Uses FNV-1a as, unlike the hasher of std, it is stable across releases and processes.
+/// The name is included so that the subjects of different rollouts aren't the same
+fn bucket(name: &str, subject: &str) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in name.bytes().chain(Some(b':')).chain(subject.bytes()) {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash % BUCKETS
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct DynamoConfig {
+    pub region: String,
+    pub endpoint: Option<String>,
+    pub local: bool,
+    /// The table of flags, keyed by their `name`
+    pub table: String,
+    /// The number of seconds between reads of the table
+    pub poll_secs: u64,
+}
+
+impl Default for DynamoConfig {
+    fn default() -> DynamoConfig {
+        DynamoConfig {
+            region: "us-east-1".to_string(),
+            endpoint: None,
+            local: false,
+            table: "Flags".to_string(),
+            poll_secs: 30,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct FlagsConfig {
+    /// The flags by name, overridden by those of the table
+    pub flags: HashMap<String, Flag>,
+    /// The table flags are polled from, None to only use `flags`
+    pub dynamo: Option<DynamoConfig>,
+}
+
+/// The flags of a service
+#[derive(Debug, Default)]
+pub struct Flags {
+    /// The flags of the configuration
+    configured: HashMap<String, Flag>,
+    /// The configured flags overridden by those last read from the table
+    current: RwLock<HashMap<String, Flag>>,
+}
+
+impl Flags {
+    pub fn new(flags: HashMap<String, Flag>) -> Flags {
+        Flags {
+            current: RwLock::new(flags.clone()),
+            configured: flags,
+        }
+    }
+
+    /// Returns the flags of `config`, polling its table in the background if it has one
+    ///
+    /// Must be called within a tokio runtime if a table is configured
+    pub fn start(config: &FlagsConfig) -> Arc<Flags> {
+        let flags = Arc::new(Flags::new(config.flags.clone()));
+        if let Some(dynamo) = &config.dynamo {
+            let client = dynamo_util::dynamo_client(
+                dynamo.region.clone(),
+                dynamo.endpoint.clone(),
+                rusoto_util::Target::local(dynamo.local),
+            );
+            tokio::spawn(poll(
+                flags.clone(),
+                client,
+                dynamo.table.clone(),
+                Duration::from_secs(dynamo.poll_secs),
+            ));
+        }
+        flags
+    }
+
+    /// Returns if the flag of `key` is on for `subject`
+    pub fn is_enabled(&self, key: &Key, subject: Option<&str>) -> bool {
+        self.lookup(key.name, subject).unwrap_or(key.default)
+    }
+
+    /// Returns if the flag named `name` is on for `subject`, or None if it isn't
+    /// configured
+    pub fn lookup(&self, name: &str, subject: Option<&str>) -> Option<bool> {
+        let current = self.current.read().unwrap();
+        current.get(name).map(|flag| flag.evaluate(name, subject))
+    }
+
+    /// Replaces the flags read from the table with `flags`
+    pub fn update(&self, flags: HashMap<String, Flag>) {
+        let mut current = self.configured.clone();
+        current.extend(flags);
+        *self.current.write().unwrap() = current;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NEW_THING: Key = Key::new("new_thing", false);
+    const OLD_THING: Key = Key::new("old_thing", true);
+
+    fn subjects() -> impl Iterator<Item = String> {
+        (0..10_000).map(|x| format!("client_{}", x))
+    }
+
+    #[test]
+    fn test_percentage() {
+        let flag = Flag {
+            percentage: Some(25.),
+            ..Default::default()
+        };
+
+        let enabled = subjects()
+            .filter(|x| flag.evaluate("new_thing", Some(x)))
+            .count();
+        assert!((2_300..2_700).contains(&enabled), "{}", enabled);
+
+        // Raising the percentage keeps the subjects the flag was on for
+        let raised = Flag {
+            percentage: Some(50.),
+            ..Default::default()
+        };
+        for subject in subjects() {
+            if flag.evaluate("new_thing", Some(&subject)) {
+                assert!(raised.evaluate("new_thing", Some(&subject)));
+            }
+        }
+
+        // Different flags are rolled out to different subjects
+        let same = subjects()
+            .filter(|x| flag.evaluate("new_thing", Some(x)) == flag.evaluate("other", Some(x)))
+            .count();
+        assert!(same < 9_000, "{}", same);
+
+        assert!(!flag.evaluate("new_thing", None));
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let flag = Flag {
+            subjects: vec!["tester".to_string()].into_iter().collect(),
+            percentage: Some(0.),
+            ..Default::default()
+        };
+        assert!(flag.evaluate("new_thing", Some("tester")));
+        assert!(!flag.evaluate("new_thing", Some("client_1")));
+
+        let disabled = Flag {
+            enabled: false,
+            ..flag
+        };
+        assert!(!disabled.evaluate("new_thing", Some("tester")));
+
+        let full = Flag::default();
+        assert!(full.evaluate("new_thing", Some("client_1")));
+        assert!(full.evaluate("new_thing", None));
+    }
+
+    #[test]
+    fn test_flags() {
+        let mut configured = HashMap::new();
+        configured.insert("new_thing".to_string(), Flag::default());
+        let flags = Flags::new(configured);
+
+        assert!(flags.is_enabled(&NEW_THING, None));
+        assert!(flags.is_enabled(&OLD_THING, None));
+        assert_eq!(flags.lookup("missing", None), None);
+
+        let mut polled = HashMap::new();
+        let disabled = Flag {
+            enabled: false,
+            ..Default::default()
+        };
+        polled.insert("old_thing".to_string(), disabled);
+        flags.update(polled);
+
+        assert!(flags.is_enabled(&NEW_THING, None));
+        assert!(!flags.is_enabled(&OLD_THING, None));
+
+        // Flags removed from the table revert to their configuration
+        flags.update(HashMap::new());
+        assert!(flags.is_enabled(&OLD_THING, None));
+    }
+}
@@ -0,0 +1,281 @@
+//! The liveness, readiness and startup probes of services
+//!
+//! A service registers checks of its dependencies, such as that a DynamoDB table can be
+//! described, against the probes they gate
+//!
+//! - `/livez` fails if the service should be restarted, and so shouldn't check
+//!   dependencies that a restart won't fix
+//! - `/readyz` fails whilst the service can't serve requests, such as whilst a
+//!   dependency is down, taking it out of load balancing until it recovers
+//! - `/startupz` fails until the service has started, after which it passes without
+//!   rerunning its checks
+//!
+//! A probe runs its checks concurrently, responding 200 if every one passes within the
+//! timeout and 503 otherwise, with the status and latency of each
+//!
+//! ```json
+//! {"status": "failed", "checks": [{"name": "dynamo", "status": "failed", "latency_ms": 2000, "error": "Timed out"}]}
+//! ```
+//!
+//! The `rocket` and `actix-web` features add the endpoints for each framework, and the
+//! `openapi` feature `document` to describe them
+
+use std::fmt::Display;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use futures::future::{join_all, BoxFuture, FutureExt};
+use futures::Future;
+use log::warn;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tokio::time::timeout;
+
+mod respond;
+
+#[cfg(feature = "actix-web")]
+pub use respond::configure;
+#[cfg(feature = "rocket")]
+pub use respond::routes;
+
+/// How long a check is given to pass by default
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Probe {
+    Live,
+    Ready,
+    Startup,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Ok,
+    Failed,
+}
+
+/// The outcome of a single check
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct CheckReport {
+    pub name: String,
+    pub status: Status,
+    /// The number of milliseconds the check took
+    pub latency_ms: u64,
+    /// Why the check failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// The outcome of a probe, which is ok if every check is
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct Report {
+    pub status: Status,
+    pub checks: Vec<CheckReport>,
+}
+
+impl Report {
+    fn new(checks: Vec<CheckReport>) -> Report {
+        let status = if checks.iter().all(|x| x.status == Status::Ok) {
+            Status::Ok
+        } else {
+            Status::Failed
+        };
+        Report { status, checks }
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.status == Status::Ok
+    }
+}
+
+type CheckFn = Box<dyn Fn() -> BoxFuture<'static, Result<(), String>> + Send + Sync>;
+
+struct Check {
+    name: String,
+    probes: Vec<Probe>,
+    check: CheckFn,
+}
+
+/// The checks of a service, which are run by its probes
+pub struct Health {
+    checks: Vec<Check>,
+    timeout: Duration,
+    /// Set once the startup probe has passed
+    started: AtomicBool,
+}
+
+impl Default for Health {
+    fn default() -> Health {
+        Health::new()
+    }
+}
+
+impl Health {
+    pub fn new() -> Health {
+        Health {
+            checks: vec![],
+            timeout: DEFAULT_TIMEOUT,
+            started: AtomicBool::new(false),
+        }
+    }
+
+    /// Sets how long a check is given to pass before it fails
+    pub fn timeout(mut self, timeout: Duration) -> Health {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Registers a check that is run by each of `probes`, calling `check` each time
+    pub fn register<F, Fut, E>(
+        mut self,
+        name: impl Into<String>,
+        probes: &[Probe],
+        check: F,
+    ) -> Health
+    where
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), E>> + Send + 'static,
+        E: Display,
+    {
+        self.checks.push(Check {
+            name: name.into(),
+            probes: probes.to_vec(),
+            check: Box::new(move || check().map(|r| r.map_err(|e| e.to_string())).boxed()),
+        });
+        self
+    }
+
+    async fn run(&self, check: &Check) -> CheckReport {
+        let start = Instant::now();
+        let error = match timeout(self.timeout, (check.check)()).await {
+            Ok(Ok(())) => None,
+            Ok(Err(e)) => Some(e),
+            Err(_) => Some(format!("Timed out after {}ms", self.timeout.as_millis())),
+        };
+
+        if let Some(e) = &error {
+            warn!("{} check failed: {}", check.name, e);
+        }
+
+        CheckReport {
+            name: check.name.clone(),
+            status: match error {
+                Some(_) => Status::Failed,
+                None => Status::Ok,
+            },
+            latency_ms: start.elapsed().as_millis() as u64,
+            error,
+        }
+    }
+
+    /// Runs the checks registered with `probe` concurrently, returning their outcomes
+    ///
+    /// Once the startup probe has passed it passes without running any checks
+    pub async fn probe(&self, probe: Probe) -> Report {
+        if probe == Probe::Startup && self.started.load(Ordering::Relaxed) {
+            return Report::new(vec![]);
+        }
+
+        let checks = self
+            .checks
+            .iter()
+            .filter(|x| x.probes.contains(&probe))
+            .map(|x| self.run(x));
+        let report = Report::new(join_all(checks).await);
+
+        if probe == Probe::Startup && report.is_ok() {
+            self.started.store(true, Ordering::Relaxed);
+        }
+        report
+    }
+}
+
+/// Adds the operations of the probes to an OpenAPI document
+#[cfg(feature = "openapi")]
+pub fn document(api: &mut openapi::OpenApi) {
+    use serde_json::json;
+
+    let report = openapi::OpenApi::json(api.schema::<Report>());
+    for (path, summary) in &[
+        ("/livez", "Reports that the service is running"),
+        ("/readyz", "Reports that the service can serve requests"),
+        ("/startupz", "Reports that the service has started"),
+    ] {
+        api.operation(
+            "get",
+            path,
+            json!({
+                "summary": summary,
+                "description": "Runs the checks registered with the probe, such as that a \
+                    dependency can be reached",
+                "operationId": &path[1..],
+                "responses": {
+                    "200": { "description": "Every check passed", "content": report },
+                    "503": { "description": "A check failed", "content": report },
+                },
+            }),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::Arc;
+
+    use futures::future;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_probe() {
+        let health = Health::new()
+            .timeout(Duration::from_millis(10))
+            .register("ok", &[Probe::Ready], || future::ok::<_, String>(()))
+            .register("failed", &[Probe::Ready, Probe::Startup], || {
+                future::err("connection refused")
+            })
+            .register("slow", &[Probe::Ready], || {
+                tokio::time::delay_for(Duration::from_secs(1)).map(Ok::<_, String>)
+            });
+
+        let report = health.probe(Probe::Live).await;
+        assert!(report.is_ok());
+        assert!(report.checks.is_empty());
+
+        let report = health.probe(Probe::Ready).await;
+        assert_eq!(report.status, Status::Failed);
+
+        let statuses: Vec<_> = report
+            .checks
+            .iter()
+            .map(|x| (x.name.as_str(), x.status, x.error.as_deref()))
+            .collect();
+        assert_eq!(
+            statuses,
+            vec![
+                ("ok", Status::Ok, None),
+                ("failed", Status::Failed, Some("connection refused")),
+                ("slow", Status::Failed, Some("Timed out after 10ms")),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_startup() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let health = Health::new().register("dynamo", &[Probe::Startup], move || {
+            // Fails the first time it is called
+            let passed = counter.fetch_add(1, Ordering::Relaxed) > 0;
+            future::ready(if passed { Ok(()) } else { Err("not ready") })
+        });
+
+        assert!(!health.probe(Probe::Startup).await.is_ok());
+        assert!(health.probe(Probe::Startup).await.is_ok());
+        assert!(health.probe(Probe::Startup).await.is_ok());
+        assert_eq!(calls.load(Ordering::Relaxed), 2);
+    }
+}
@@ -0,0 +1,81 @@
+#[cfg(feature = "rocket")]
+mod rocket_routes {
+    use rocket::http;
+    use rocket::response::{content, status};
+    use rocket::{Route, State};
+
+    use crate::{Health, Probe, Report};
+
+    fn respond(report: Report) -> status::Custom<content::Json<String>> {
+        let status = if report.is_ok() {
+            http::Status::Ok
+        } else {
+            http::Status::ServiceUnavailable
+        };
+        let body = serde_json::to_string(&report).expect("Failed to serialize report");
+        status::Custom(status, content::Json(body))
+    }
+
+    #[rocket::get("/livez")]
+    async fn livez(health: State<'_, Health>) -> status::Custom<content::Json<String>> {
+        respond(health.probe(Probe::Live).await)
+    }
+
+    #[rocket::get("/readyz")]
+    async fn readyz(health: State<'_, Health>) -> status::Custom<content::Json<String>> {
+        respond(health.probe(Probe::Ready).await)
+    }
+
+    #[rocket::get("/startupz")]
+    async fn startupz(health: State<'_, Health>) -> status::Custom<content::Json<String>> {
+        respond(health.probe(Probe::Startup).await)
+    }
+
+    /// Returns the routes of the probes, which require a `Health` to be managed
+    pub fn routes() -> Vec<Route> {
+        rocket::routes![livez, readyz, startupz]
+    }
+}
+
+#[cfg(feature = "rocket")]
+pub use rocket_routes::routes;
+
+#[cfg(feature = "actix-web")]
+mod actix_routes {
+    use actix_web::http::StatusCode;
+    use actix_web::{web, HttpResponse};
+
+    use crate::{Health, Probe};
+
+    async fn respond(health: web::Data<Health>, probe: Probe) -> HttpResponse {
+        let report = health.probe(probe).await;
+        let status = if report.is_ok() {
+            StatusCode::OK
+        } else {
+            StatusCode::SERVICE_UNAVAILABLE
+        };
+        HttpResponse::build(status).json(report)
+    }
+
+    async fn livez(health: web::Data<Health>) -> HttpResponse {
+        respond(health, Probe::Live).await
+    }
+
+    async fn readyz(health: web::Data<Health>) -> HttpResponse {
+        respond(health, Probe::Ready).await
+    }
+
+    async fn startupz(health: web::Data<Health>) -> HttpResponse {
+        respond(health, Probe::Startup).await
+    }
+
+    /// Adds the routes of the probes, which require a `web::Data<Health>`
+    pub fn configure(cfg: &mut web::ServiceConfig) {
+        cfg.service(web::resource("/livez").route(web::get().to(livez)))
+            .service(web::resource("/readyz").route(web::get().to(readyz)))
+            .service(web::resource("/startupz").route(web::get().to(startupz)));
+    }
+}
+
+#[cfg(feature = "actix-web")]
+pub use actix_routes::configure;
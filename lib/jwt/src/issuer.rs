@@ -12,7 +12,7 @@ use crate::model::*;
 use crate::tag;
 use crate::{Validator, ValidatorConfig};
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
 #[serde(default)]
 pub struct IssuerConfig {
     pub secret: Option<String>,
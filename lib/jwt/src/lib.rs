@@ -3,7 +3,7 @@ use std::str::FromStr;
 
 pub use error::{IssuerError, ValidatorError};
 pub use issuer::{Issuer, IssuerConfig};
-pub use model::{DefaultClaims, Jwk, Jwks, JwtClaims, Scope};
+pub use model::{is_superuser, DefaultClaims, Jwk, Jwks, JwtClaims, Resources, Scope};
 pub use validator::{Validator, ValidatorConfig};
 
 mod error;
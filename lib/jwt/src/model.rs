@@ -1,5 +1,5 @@
 use std::collections::{HashMap, HashSet};
-use std::convert::TryInto;
+use std::convert::{TryFrom, TryInto};
 use std::hash::Hash;
 use std::str::FromStr;
 
@@ -7,11 +7,11 @@ use chrono::{DateTime, Utc};
 use ring::signature;
 use ring::signature::RsaPublicKeyComponents;
 use serde::{Deserialize, Serialize};
-use strum_macros::{AsRefStr, EnumString};
 
 use crate::tag;
 
 #[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 pub struct Jwk {
     pub kty: String,
     pub kid: String,
@@ -43,6 +43,7 @@ impl Jwk {
 }
 
 #[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 pub struct Jwks {
     pub keys: Vec<Jwk>,
 }
@@ -110,13 +111,171 @@ where
     }
 }
 
-#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, AsRefStr, EnumString, Serialize, Deserialize)]
-#[serde(rename_all = "snake_case")]
-#[strum(serialize_all = "snake_case")]
+const SUPERUSER: &str = "superuser";
+const OFFLINE_ACCESS: &str = "offline_access";
+
+/// A scope granted to a client or user
+#[derive(Debug, Clone, Hash, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
 pub enum Scope {
+    /// Grants access to everything
     Superuser,
     OfflineAccess,
+    /// A scope defined by a service, of the form `<service>:<permission>` with an optional
+    /// `:<resource>` suffix, e.g. `kinesis:write:<stream>`
+    Service(String),
+}
+
+impl Scope {
+    /// Returns the resource this scope grants access to if it is a service scope
+    /// starting with `prefix`, e.g. the stream of `kinesis:write:<stream>`
+    pub fn resource(&self, prefix: &str) -> Option<&str> {
+        match self {
+            Scope::Service(scope) => scope.strip_prefix(prefix).filter(|x| !x.is_empty()),
+            _ => None,
+        }
+    }
+}
+
+impl AsRef<str> for Scope {
+    fn as_ref(&self) -> &str {
+        match self {
+            Scope::Superuser => SUPERUSER,
+            Scope::OfflineAccess => OFFLINE_ACCESS,
+            Scope::Service(scope) => scope.as_str(),
+        }
+    }
+}
+
+impl FromStr for Scope {
+    type Err = strum::ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            SUPERUSER => Ok(Scope::Superuser),
+            OFFLINE_ACCESS => Ok(Scope::OfflineAccess),
+            _ if s.contains(':') && !s.contains(char::is_whitespace) => {
+                Ok(Scope::Service(s.to_string()))
+            }
+            _ => Err(strum::ParseError::VariantNotFound),
+        }
+    }
+}
+
+impl TryFrom<String> for Scope {
+    type Error = strum::ParseError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<Scope> for String {
+    fn from(scope: Scope) -> Self {
+        match scope {
+            Scope::Service(scope) => scope,
+            scope => scope.as_ref().to_string(),
+        }
+    }
+}
+
+#[cfg(feature = "schemars")]
+impl schemars::JsonSchema for Scope {
+    fn schema_name() -> String {
+        "Scope".to_string()
+    }
+
+    fn json_schema(gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        String::json_schema(gen)
+    }
 }
 
 #[allow(dead_code)]
 pub type DefaultClaims = JwtClaims<Scope>;
+
+/// Returns true if `scopes` include the superuser scope, which grants access to everything
+pub fn is_superuser(scopes: &HashSet<Scope>) -> bool {
+    scopes.contains(&Scope::Superuser)
+}
+
+/// The resources granted by the service scopes starting with a prefix, such as the
+/// streams of `kinesis:write:<stream>` scopes, or every resource for the superuser scope
+#[derive(Debug, Clone)]
+pub struct Resources {
+    superuser: bool,
+    resources: HashSet<String>,
+}
+
+impl Resources {
+    /// Returns the resources granted by the scopes of `scopes` starting with `prefix`
+    pub fn new(scopes: &HashSet<Scope>, prefix: &str) -> Resources {
+        Resources {
+            superuser: is_superuser(scopes),
+            resources: scopes
+                .iter()
+                .filter_map(|scope| scope.resource(prefix))
+                .map(ToString::to_string)
+                .collect(),
+        }
+    }
+
+    /// Returns true if access to `resource` is granted
+    pub fn allows(&self, resource: &str) -> bool {
+        self.superuser || self.resources.contains(resource)
+    }
+}
+
+impl JwtClaims<Scope> {
+    /// Returns true if these claims grant the superuser scope
+    pub fn is_superuser(&self) -> bool {
+        is_superuser(&self.scopes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use crate::tag;
+
+    use super::*;
+
+    #[test]
+    fn test_scope() -> Result<(), Box<dyn std::error::Error>> {
+        let scopes: HashSet<Scope> =
+            tag::parse_space_delimited("superuser offline_access kinesis:write:a")?;
+
+        assert!(scopes.contains(&Scope::Superuser));
+        assert!(scopes.contains(&Scope::OfflineAccess));
+        let service = Scope::Service("kinesis:write:a".to_string());
+        assert!(scopes.contains(&service));
+
+        assert_eq!(service.resource("kinesis:write:"), Some("a"));
+        assert_eq!(service.resource("kinesis:read:"), None);
+        assert_eq!(Scope::Superuser.resource(""), None);
+
+        assert!("illegal_variant".parse::<Scope>().is_err());
+        assert!(is_superuser(&scopes));
+        assert!(!is_superuser(&[service.clone()].iter().cloned().collect()));
+
+        let json = serde_json::to_string(&[Scope::OfflineAccess, service.clone()])?;
+        assert_eq!(json, r#"["offline_access","kinesis:write:a"]"#);
+        let back: Vec<Scope> = serde_json::from_str(&json)?;
+        assert_eq!(back, vec![Scope::OfflineAccess, service]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_resources() {
+        let scopes: HashSet<Scope> =
+            tag::parse_space_delimited("kinesis:read:a kinesis:write:b offline_access").unwrap();
+        let resources = Resources::new(&scopes, "kinesis:read:");
+
+        assert!(resources.allows("a"));
+        assert!(!resources.allows("b"));
+        assert!(!resources.allows("kinesis:read:a"));
+
+        let superuser: HashSet<Scope> = tag::parse_space_delimited("superuser").unwrap();
+        assert!(Resources::new(&superuser, "kinesis:read:").allows("b"));
+    }
+}
@@ -55,6 +55,14 @@ impl Validator {
         })
     }
 
+    /// Checks that keys were loaded from the JWKS, such as for a readiness probe
+    pub fn check(&self) -> Result<(), ValidatorError> {
+        if self.keys.is_empty() {
+            return Err(ValidatorError::ConfigError("No keys in JWKS".to_string()));
+        }
+        Ok(())
+    }
+
     pub fn validate<S: Sized + FromStr + Hash + Eq>(
         &self,
         jwt: &str,
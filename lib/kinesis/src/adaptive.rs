@@ -0,0 +1,242 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use stream::Rate;
+use tokio::time::Duration;
+use tracing::info;
+
+use crate::producer::RecordLimiter;
+use crate::stats::ShardStats;
+use crate::topology::{ShardId, TopologyService};
+
+/// The factor a shard's rate is multiplied by when it is throttled
+const DECREASE_FACTOR: f64 = 0.5;
+
+/// The fraction of the maximum rate a shard's rate is increased by each interval
+const INCREASE_FRACTION: f64 = 0.05;
+
+/// The lowest fraction of the maximum rate a shard's rate will be decreased to
+const MIN_FRACTION: f64 = 0.05;
+
+/// The interval between increases, and the minimum interval between decreases
+const INTERVAL: Duration = Duration::from_secs(1);
+
+struct ShardRate {
+    records: Rate,
+    bytes: Rate,
+    last_decrease: Option<Instant>,
+}
+
+/// Adjusts the rate limits of each shard in response to throttling, using additive
+/// increase and multiplicative decrease (AIMD)
+///
+/// Shards start at the configured limit, are halved when throttled and then gradually
+/// increased back towards the configured limit. Unpartitioned pipelines use a single
+/// rate keyed by None
+#[derive(Clone)]
+pub(crate) struct AdaptiveRates {
+    enabled: bool,
+    max_records: u64,
+    max_bytes: u64,
+    shards: Arc<Mutex<HashMap<Option<ShardId>, ShardRate>>>,
+}
+
+fn decrease(rate: &Rate, max: u64) {
+    let min = ((max as f64 * MIN_FRACTION) as u64).max(1);
+    let decreased = (rate.get() as f64 * DECREASE_FACTOR) as u64;
+    rate.set(decreased.max(min))
+}
+
+/// Increases `rate` towards `max`, returning true if it has reached `max`
+fn increase(rate: &Rate, max: u64) -> bool {
+    let step = ((max as f64 * INCREASE_FRACTION) as u64).max(1);
+    let increased = rate.get().saturating_add(step).min(max);
+    rate.set(increased);
+    increased == max
+}
+
+impl AdaptiveRates {
+    pub fn new(max_records: u64, max_bytes: u64, enabled: bool) -> AdaptiveRates {
+        AdaptiveRates {
+            enabled,
+            max_records,
+            max_bytes,
+            shards: Default::default(),
+        }
+    }
+
+    /// Creates a limiter for the given shard, sharing its rate with any other
+    /// limiters for the same shard
+    pub fn limiter(&self, shard_id: Option<ShardId>) -> RecordLimiter {
+        let mut shards = self.shards.lock().unwrap();
+        let shard = shards.entry(shard_id).or_insert_with(|| ShardRate {
+            records: Rate::per_second(self.max_records),
+            bytes: Rate::per_second(self.max_bytes),
+            last_decrease: None,
+        });
+
+        RecordLimiter::new(shard.records.clone(), shard.bytes.clone())
+    }
+
+    /// Decreases the rate of a shard that has been throttled
+    ///
+    /// Decreases are limited to one per interval, as a single batch may contain
+    /// many throttled records
+    pub fn throttled(&self, shard_id: Option<ShardId>) {
+        if !self.enabled {
+            return;
+        }
+
+        let mut shards = self.shards.lock().unwrap();
+        if let Some(shard) = shards.get_mut(&shard_id) {
+            let now = Instant::now();
+            if let Some(last_decrease) = shard.last_decrease {
+                if now.duration_since(last_decrease) < INTERVAL {
+                    return;
+                }
+            }
+
+            decrease(&shard.records, self.max_records);
+            decrease(&shard.bytes, self.max_bytes);
+            shard.last_decrease = Some(now);
+
+            info!(
+                shard_id = ?shard_id.map(|x| x.to_string()),
+                records_per_second = shard.records.get(),
+                bytes_per_second = shard.bytes.get(),
+                "decreased shard rate"
+            );
+        }
+    }
+
+    /// Returns the current rate of each shard
+    pub fn stats(&self) -> HashMap<String, ShardStats> {
+        let shards = self.shards.lock().unwrap();
+        shards
+            .iter()
+            .map(|(shard_id, shard)| {
+                let shard_id = shard_id
+                    .map(|x| x.to_string())
+                    .unwrap_or_else(|| "none".to_string());
+
+                let stats = ShardStats {
+                    records_per_second: shard.records.get(),
+                    bytes_per_second: shard.bytes.get(),
+                    throttled: shard.last_decrease.is_some(),
+                };
+                (shard_id, stats)
+            })
+            .collect()
+    }
+
+    fn increase(&self) {
+        let mut shards = self.shards.lock().unwrap();
+        for shard in shards.values_mut() {
+            let records = increase(&shard.records, self.max_records);
+            let bytes = increase(&shard.bytes, self.max_bytes);
+            if records && bytes {
+                shard.last_decrease = None;
+            }
+        }
+    }
+
+    /// Removes the rates of shards that are no longer open, e.g. following resharding
+    fn retain(&self, open_shards: &[ShardId]) {
+        let mut shards = self.shards.lock().unwrap();
+        shards.retain(|shard_id, _| match shard_id {
+            Some(shard_id) => open_shards.contains(shard_id),
+            None => true,
+        });
+    }
+
+    /// Periodically increases the rate of throttled shards, and removes those that
+    /// have left `topology`, until `finished`
+    pub async fn worker(self, topology: Option<TopologyService>, mut finished: shutdown::Receiver) {
+        if !self.enabled && topology.is_none() {
+            return;
+        }
+
+        let mut interval = tokio::time::interval(INTERVAL);
+        loop {
+            tokio::select! {
+                _ = &mut finished => break,
+                _ = interval.tick() => {
+                    if self.enabled {
+                        self.increase()
+                    }
+
+                    if let Some(open_shards) = topology.as_ref().and_then(|x| x.current_shards()) {
+                        self.retain(&open_shards)
+                    }
+                }
+            }
+        }
+
+        info!("adaptive rate worker exited")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rates(rates: &AdaptiveRates, shard_id: Option<ShardId>) -> (u64, u64) {
+        let shards = rates.shards.lock().unwrap();
+        let shard = &shards[&shard_id];
+        (shard.records.get(), shard.bytes.get())
+    }
+
+    #[test]
+    fn test_aimd() {
+        let adaptive = AdaptiveRates::new(1000, 10000, true);
+        let shard_id = Some("shardId-000000000001".parse().unwrap());
+
+        let _limiter = adaptive.limiter(shard_id);
+        assert_eq!(rates(&adaptive, shard_id), (1000, 10000));
+
+        adaptive.throttled(shard_id);
+        assert_eq!(rates(&adaptive, shard_id), (500, 5000));
+
+        let stats = adaptive.stats();
+        let shard = &stats["shardId-000000000001"];
+        assert_eq!(shard.records_per_second, 500);
+        assert!(shard.throttled);
+
+        // Only one decrease per interval
+        adaptive.throttled(shard_id);
+        assert_eq!(rates(&adaptive, shard_id), (500, 5000));
+
+        adaptive.increase();
+        assert_eq!(rates(&adaptive, shard_id), (550, 5500));
+
+        for _ in 0..20 {
+            adaptive.increase();
+        }
+        assert_eq!(rates(&adaptive, shard_id), (1000, 10000));
+    }
+
+    #[test]
+    fn test_retain() {
+        let adaptive = AdaptiveRates::new(1000, 10000, true);
+        let open = "shardId-000000000001".parse().unwrap();
+        let closed = "shardId-000000000002".parse().unwrap();
+
+        let _limiter = adaptive.limiter(Some(open));
+        let _limiter = adaptive.limiter(Some(closed));
+        adaptive.retain(&[open]);
+
+        let stats = adaptive.stats();
+        assert!(stats.contains_key("shardId-000000000001"));
+        assert!(!stats.contains_key("shardId-000000000002"));
+    }
+
+    #[test]
+    fn test_disabled() {
+        let adaptive = AdaptiveRates::new(1000, 10000, false);
+
+        let _limiter = adaptive.limiter(None);
+        adaptive.throttled(None);
+        assert_eq!(rates(&adaptive, None), (1000, 10000));
+    }
+}
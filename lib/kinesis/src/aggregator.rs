@@ -1,32 +1,54 @@
+//! Aggregates records in the format of the Kinesis Producer Library, so that they can be
+//! deaggregated by the KCL, the Lambda deaggregation libraries and `deaggregator`
+//!
+//! An aggregated record is the magic bytes `MAGIC`, followed by an `AggregatedRecord`
+//! protobuf message, see `proto/record.proto`, and the MD5 digest of the message
+//!
+//! Compressed records are wrapped in the format of `compression`, which only this crate
+//! can read
+
+use crate::compression::Compression;
 use crate::intern::StringInterner;
 use crate::producer::{Record, RecordBatcher};
 use bytes::{BufMut, BytesMut};
 use prost::Message;
 use stream::Reducer;
-use tracing::info;
+use tracing::{info, Span};
 
 pub(crate) mod proto {
     include!(concat!(env!("OUT_DIR"), "/aws.kinesis.rs"));
 }
 
+/// Prefixes a KPL aggregated record
+pub(crate) const MAGIC: [u8; 4] = [0xF3, 0x89, 0x9A, 0xC2];
+
+/// The length of the MD5 digest of the message that ends an aggregated record
+pub(crate) const DIGEST_LEN: usize = 16;
+
 pub(crate) struct RecordAggregator {
     inner: RecordBatcher,
+    compression: Compression,
 }
 
 impl RecordAggregator {
-    pub fn new(max_bytes: usize, max_records: usize) -> RecordAggregator {
+    pub fn new(max_bytes: usize, max_records: usize, compression: Compression) -> RecordAggregator {
         // Defaults from KPL
         RecordAggregator {
             inner: RecordBatcher::new(max_bytes, max_records),
+            compression,
         }
     }
 
     fn aggregate(&self, records: &[Record]) -> proto::AggregatedRecord {
-        let mut intern = StringInterner::new();
+        let mut partition_keys = StringInterner::new();
+        let mut hash_keys = StringInterner::new();
         let records = records
             .iter()
             .map(|record| proto::Record {
-                partition_key_index: intern.intern(&record.partition_key),
+                partition_key_index: partition_keys.intern(&record.partition_key),
+                explicit_hash_key_index: record
+                    .explicit_hash_key
+                    .map(|x| hash_keys.intern(&x.to_string())),
                 data: record.data.clone(),
                 ..Default::default()
             })
@@ -34,8 +56,8 @@ impl RecordAggregator {
 
         proto::AggregatedRecord {
             records,
-            partition_key_table: intern.take(),
-            ..Default::default()
+            partition_key_table: partition_keys.take(),
+            explicit_hash_key_table: hash_keys.take(),
         }
     }
 }
@@ -52,33 +74,49 @@ impl Reducer for RecordAggregator {
     fn take(&mut self) -> Option<Record> {
         let records = self.inner.take()?;
         let partition_key = records[0].partition_key.clone();
+        // Pin the aggregate to the shard predicted for its constituent records
+        let explicit_hash_key = Some(records[0].hash_key());
         let predicted_shard_id = records[0].predicted_shard_id.clone();
+        let enqueued = records.iter().map(|x| x.enqueued).min().unwrap();
+        let deadline = records.iter().filter_map(|x| x.deadline).min();
 
         let aggregated = self.aggregate(&records);
 
-        let capacity = aggregated.encoded_len() + 20;
+        let capacity = MAGIC.len() + aggregated.encoded_len() + DIGEST_LEN;
         let mut buf = BytesMut::with_capacity(capacity);
-        buf.put_slice(&[0xF3, 0x89, 0x9A, 0xC2]);
+        buf.put_slice(&MAGIC);
 
         aggregated.encode(&mut buf).unwrap();
 
-        let checksum = md5::compute(&buf[4..]);
+        let checksum = md5::compute(&buf[MAGIC.len()..]);
 
         buf.put_slice(&checksum.0);
 
+        let data = match self.compression.compress(&buf) {
+            Some(compressed) => compressed,
+            None => buf.freeze(),
+        };
+
         info!(
             capacity,
-            len = buf.len(),
+            len = data.len(),
             ?checksum,
             "produced aggregated record"
         );
 
         Some(Record {
             partition_key,
-            data: buf.freeze(),
+            explicit_hash_key,
+            data,
             predicted_shard_id,
             acker: None,
             children: records,
+            attempts: 0,
+            pending: None,
+            sequence: None,
+            deadline,
+            enqueued,
+            span: Span::none(),
         })
     }
 
@@ -89,8 +127,67 @@ impl Reducer for RecordAggregator {
 
 #[cfg(test)]
 mod test {
+    use bytes::Bytes;
+    use tokio::time::{Duration, Instant};
+
     use super::*;
 
+    fn record(partition_key: &str, data: &'static [u8]) -> Record {
+        Record {
+            partition_key: partition_key.to_string(),
+            explicit_hash_key: None,
+            data: Bytes::from_static(data),
+            predicted_shard_id: None,
+            acker: None,
+            children: vec![],
+            attempts: 0,
+            pending: None,
+            sequence: None,
+            deadline: None,
+            enqueued: Instant::now(),
+            span: Span::none(),
+        }
+    }
+
+    #[test]
+    fn test_kpl_format() {
+        let mut aggregator = RecordAggregator::new(51200, 100, Compression::None);
+        assert!(aggregator.try_push(record("a", b"x")).is_none());
+        assert!(aggregator.try_push(record("a", b"yz")).is_none());
+        let aggregated = aggregator.take().unwrap();
+
+        #[rustfmt::skip]
+        let expected: &[u8] = &[
+            // Magic
+            0xF3, 0x89, 0x9A, 0xC2,
+            // partition_key_table: ["a"]
+            0x0A, 0x01, 0x61,
+            // records: [{partition_key_index: 0, data: "x"}, {partition_key_index: 0, data: "yz"}]
+            0x1A, 0x05, 0x08, 0x00, 0x1A, 0x01, 0x78,
+            0x1A, 0x06, 0x08, 0x00, 0x1A, 0x02, 0x79, 0x7A,
+            // MD5 of the message
+            0x0A, 0x44, 0x48, 0xCF, 0xAB, 0xC0, 0xBB, 0x73,
+            0xFD, 0x54, 0x01, 0xAA, 0x85, 0x40, 0xE4, 0x21,
+        ];
+        assert_eq!(aggregated.data.as_ref(), expected);
+        assert_eq!(aggregated.children.len(), 2);
+    }
+
+    #[test]
+    fn test_deadline() {
+        let deadline = Instant::now() + Duration::from_secs(1);
+        let mut expiring = record("a", b"x");
+        expiring.deadline = Some(deadline);
+
+        let mut aggregator = RecordAggregator::new(51200, 100, Compression::None);
+        assert!(aggregator.try_push(record("a", b"y")).is_none());
+        assert!(aggregator.try_push(expiring).is_none());
+        assert_eq!(aggregator.take().unwrap().deadline, Some(deadline));
+
+        assert!(aggregator.try_push(record("a", b"z")).is_none());
+        assert_eq!(aggregator.take().unwrap().deadline, None);
+    }
+
     #[test]
     fn test_proto() {
         let mut aggregated = proto::AggregatedRecord::default();
@@ -0,0 +1,70 @@
+use async_trait::async_trait;
+use rusoto_core::RusotoError;
+use rusoto_kinesis::{
+    DescribeStreamSummaryError, DescribeStreamSummaryInput, DescribeStreamSummaryOutput, Kinesis,
+    KinesisClient, ListShardsError, ListShardsInput, ListShardsOutput, PutRecordsError,
+    PutRecordsInput, PutRecordsOutput,
+};
+use tracing::{info_span, Instrument, Span};
+
+/// Returns the span of a call to `method` of the Kinesis API
+fn call_span(method: &str) -> Span {
+    info_span!(
+        "kinesis",
+        otel.kind = "client",
+        rpc.system = "aws-api",
+        rpc.service = "Kinesis",
+        rpc.method = method
+    )
+}
+
+/// The subset of the Kinesis API used by a producer pipeline
+///
+/// This allows pipelines to be run against `MockKinesis` in tests
+#[async_trait]
+pub(crate) trait KinesisApi: Clone + Send + Sync + 'static {
+    async fn put_records(
+        &self,
+        input: PutRecordsInput,
+    ) -> Result<PutRecordsOutput, RusotoError<PutRecordsError>>;
+
+    async fn list_shards(
+        &self,
+        input: ListShardsInput,
+    ) -> Result<ListShardsOutput, RusotoError<ListShardsError>>;
+
+    async fn describe_stream_summary(
+        &self,
+        input: DescribeStreamSummaryInput,
+    ) -> Result<DescribeStreamSummaryOutput, RusotoError<DescribeStreamSummaryError>>;
+}
+
+#[async_trait]
+impl KinesisApi for KinesisClient {
+    async fn put_records(
+        &self,
+        input: PutRecordsInput,
+    ) -> Result<PutRecordsOutput, RusotoError<PutRecordsError>> {
+        Kinesis::put_records(self, input)
+            .instrument(call_span("PutRecords"))
+            .await
+    }
+
+    async fn list_shards(
+        &self,
+        input: ListShardsInput,
+    ) -> Result<ListShardsOutput, RusotoError<ListShardsError>> {
+        Kinesis::list_shards(self, input)
+            .instrument(call_span("ListShards"))
+            .await
+    }
+
+    async fn describe_stream_summary(
+        &self,
+        input: DescribeStreamSummaryInput,
+    ) -> Result<DescribeStreamSummaryOutput, RusotoError<DescribeStreamSummaryError>> {
+        Kinesis::describe_stream_summary(self, input)
+            .instrument(call_span("DescribeStreamSummary"))
+            .await
+    }
+}
@@ -0,0 +1,288 @@
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use rusoto_cloudwatch::{CloudWatch, CloudWatchClient, Dimension, MetricDatum, PutMetricDataInput};
+use tokio::time::{interval_at, Duration, Instant};
+use tracing::{error, info};
+
+use crate::metrics::{MetricsSnapshot, PipelineMetrics};
+
+/// The maximum number of metrics in a single PutMetricData request
+const MAX_METRICS_PER_REQUEST: usize = 20;
+
+/// The dimensions of the metrics a pipeline publishes to CloudWatch
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetricsGranularity {
+    /// No dimensions, aggregating all pipelines publishing to the namespace
+    Global,
+    /// Dimensioned by pipeline name
+    Pipeline,
+    /// Dimensioned by pipeline name, with throttled records additionally dimensioned by
+    /// stream and shard
+    Shard,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct CloudWatchConfig {
+    pub namespace: String,
+    pub granularity: MetricsGranularity,
+    pub interval: Duration,
+}
+
+/// The values of a pipeline's metrics when they were last published
+struct Published {
+    snapshot: MetricsSnapshot,
+    buffering: Vec<(f64, u64)>,
+}
+
+impl Published {
+    fn new(metrics: &PipelineMetrics) -> Published {
+        Published {
+            snapshot: metrics.snapshot(),
+            buffering: metrics.buffering_buckets(),
+        }
+    }
+}
+
+fn datum(name: &str, unit: &str, value: f64, dimensions: &[(&str, &str)]) -> MetricDatum {
+    MetricDatum {
+        metric_name: name.to_string(),
+        unit: Some(unit.to_string()),
+        value: Some(value),
+        dimensions: if dimensions.is_empty() {
+            None
+        } else {
+            Some(
+                dimensions
+                    .iter()
+                    .map(|(name, value)| Dimension {
+                        name: name.to_string(),
+                        value: value.to_string(),
+                    })
+                    .collect(),
+            )
+        },
+        ..Default::default()
+    }
+}
+
+/// Returns the metrics to publish for the changes between `previous` and `current`
+///
+/// Buffering time is published as the upper bound of each histogram bucket, with records
+/// exceeding the largest bucket reported at its upper bound
+fn metric_data(
+    pipeline: &str,
+    granularity: MetricsGranularity,
+    previous: &Published,
+    current: &Published,
+) -> Vec<MetricDatum> {
+    let dimensions = match granularity {
+        MetricsGranularity::Global => vec![],
+        _ => vec![("Pipeline", pipeline)],
+    };
+
+    let (prev, cur) = (&previous.snapshot, &current.snapshot);
+    let counters = [
+        ("UserRecordsReceived", prev.enqueued, cur.enqueued),
+        ("UserRecordsPut", prev.acked, cur.acked),
+        ("UserRecordsFailed", prev.dropped, cur.dropped),
+        (
+            "UserRecordsDeadLettered",
+            prev.dead_lettered.values().sum(),
+            cur.dead_lettered.values().sum(),
+        ),
+        ("KinesisRecordsPut", prev.sent, cur.sent),
+        ("Retries", prev.retried, cur.retried),
+    ];
+
+    let mut data: Vec<_> = counters
+        .iter()
+        .map(|(name, prev, cur)| datum(name, "Count", (cur - prev) as f64, &dimensions))
+        .collect();
+
+    let throttled = cur.throttled.iter().map(|(key, count)| {
+        let prev = prev.throttled.get(key).cloned().unwrap_or_default();
+        (key.0.as_str(), key.1.as_str(), count - prev)
+    });
+
+    match granularity {
+        MetricsGranularity::Shard => {
+            for (stream, shard, count) in throttled {
+                let mut dimensions = dimensions.clone();
+                dimensions.push(("StreamName", stream));
+                if shard != "none" {
+                    dimensions.push(("ShardId", shard));
+                }
+                data.push(datum(
+                    "ThrottledRecords",
+                    "Count",
+                    count as f64,
+                    &dimensions,
+                ))
+            }
+        }
+        _ => {
+            let count: u64 = throttled.map(|(_, _, count)| count).sum();
+            data.push(datum(
+                "ThrottledRecords",
+                "Count",
+                count as f64,
+                &dimensions,
+            ))
+        }
+    }
+
+    let mut values = Vec::new();
+    let mut counts = Vec::new();
+    let mut last_bound = 0.;
+    let mut last_count = 0;
+    for ((bound, cur), (_, prev)) in current.buffering.iter().zip(&previous.buffering) {
+        // Cumulative counts, so the records in this bucket are those not in the last
+        let count = (cur - prev) - last_count;
+        last_count = cur - prev;
+
+        if bound.is_finite() {
+            last_bound = *bound;
+        }
+
+        if count == 0 {
+            continue;
+        }
+
+        let value = last_bound * 1000.;
+        match (values.last(), counts.last_mut()) {
+            (Some(last), Some(last_count)) if *last == value => *last_count += count as f64,
+            _ => {
+                values.push(value);
+                counts.push(count as f64);
+            }
+        }
+    }
+
+    if !values.is_empty() {
+        data.push(MetricDatum {
+            values: Some(values),
+            counts: Some(counts),
+            value: None,
+            ..datum("BufferingTime", "Milliseconds", 0., &dimensions)
+        });
+    }
+
+    data
+}
+
+/// Returns a worker that publishes `metrics` to CloudWatch every interval until `finished`,
+/// at which point it publishes once more
+pub(crate) fn publisher(
+    client: CloudWatchClient,
+    config: CloudWatchConfig,
+    metrics: PipelineMetrics,
+    mut finished: shutdown::Receiver,
+) -> BoxFuture<'static, ()> {
+    async move {
+        let mut interval = interval_at(Instant::now() + config.interval, config.interval);
+        let mut previous = Published::new(&metrics);
+
+        loop {
+            let finishing = tokio::select! {
+                _ = interval.tick() => false,
+                _ = &mut finished => true,
+            };
+
+            let current = Published::new(&metrics);
+            let data = metric_data(metrics.name(), config.granularity, &previous, &current);
+            previous = current;
+
+            for chunk in data.chunks(MAX_METRICS_PER_REQUEST) {
+                let input = PutMetricDataInput {
+                    namespace: config.namespace.clone(),
+                    metric_data: chunk.to_vec(),
+                };
+
+                if let Err(e) = client.put_metric_data(input).await {
+                    error!("error publishing metrics to cloudwatch: {:?}", e)
+                }
+            }
+
+            if finishing {
+                break;
+            }
+        }
+
+        info!("cloudwatch publisher exited")
+    }
+    .boxed()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn published(acked: u64, throttled: &[(&str, u64)], buffering: &[u64]) -> Published {
+        Published {
+            snapshot: MetricsSnapshot {
+                acked,
+                throttled: throttled
+                    .iter()
+                    .map(|(shard, count)| (("test".to_string(), shard.to_string()), *count))
+                    .collect::<HashMap<_, _>>(),
+                ..Default::default()
+            },
+            buffering: [0.001, 0.002, f64::INFINITY]
+                .iter()
+                .cloned()
+                .zip(buffering.iter().cloned())
+                .collect(),
+        }
+    }
+
+    fn find<'a>(data: &'a [MetricDatum], name: &str) -> Vec<&'a MetricDatum> {
+        data.iter().filter(|x| x.metric_name == name).collect()
+    }
+
+    #[test]
+    fn test_metric_data() {
+        let previous = published(5, &[("shardId-000000000001", 1)], &[1, 2, 2]);
+        let current = published(
+            8,
+            &[("shardId-000000000001", 3), ("shardId-000000000002", 1)],
+            &[1, 4, 7],
+        );
+
+        let data = metric_data("test", MetricsGranularity::Pipeline, &previous, &current);
+
+        let acked = find(&data, "UserRecordsPut");
+        assert_eq!(acked.len(), 1);
+        assert_eq!(acked[0].value, Some(3.));
+        assert_eq!(
+            acked[0].dimensions,
+            Some(vec![Dimension {
+                name: "Pipeline".to_string(),
+                value: "test".to_string()
+            }])
+        );
+
+        let throttled = find(&data, "ThrottledRecords");
+        assert_eq!(throttled.len(), 1);
+        assert_eq!(throttled[0].value, Some(3.));
+
+        // Records beyond the largest bucket are reported at its upper bound
+        let buffering = find(&data, "BufferingTime");
+        assert_eq!(buffering[0].values, Some(vec![2.]));
+        assert_eq!(buffering[0].counts, Some(vec![5.]));
+        assert_eq!(buffering[0].value, None);
+
+        let data = metric_data("test", MetricsGranularity::Shard, &previous, &current);
+        let mut throttled: Vec<_> = find(&data, "ThrottledRecords")
+            .into_iter()
+            .map(|x| x.value.unwrap())
+            .collect();
+        throttled.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        assert_eq!(throttled, vec![1., 2.]);
+
+        let data = metric_data("test", MetricsGranularity::Global, &previous, &previous);
+        assert!(data.iter().all(|x| x.dimensions.is_none()));
+        assert!(find(&data, "BufferingTime").is_empty());
+    }
+}
@@ -0,0 +1,198 @@
+//! Compression of aggregated records, see `PipelineBuilder::compression`
+//!
+//! A compressed record is the magic bytes `MAGIC`, a byte identifying the encoding, 1 for
+//! gzip and 2 for zstd, the compressed aggregated record, and then the MD5 digest of the
+//! encoding byte and compressed record. Records that don't get smaller are sent uncompressed,
+//! and the deaggregator reads either
+//!
+//! A record is only treated as compressed if its digest matches, and one that then fails to
+//! decompress, or decompresses to more than `MAX_DECOMPRESSED_BYTES`, is read as a raw record
+//!
+//! The magic bytes differ from those of the KPL only in their last byte, so consumers that
+//! only understand the KPL format see compressed records as single, opaque user records
+
+use std::io::{Read, Write};
+
+use bytes::Bytes;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use tracing::warn;
+
+use crate::aggregator::DIGEST_LEN;
+
+/// Prefixes an aggregated record whose payload has been compressed
+///
+/// Followed by a single byte identifying the encoding, the compressed aggregated record
+/// and then the digest
+const MAGIC: [u8; 4] = [0xF3, 0x89, 0x9A, 0xC3];
+
+/// The largest record Kinesis accepts, and so the most a record may decompress to
+const MAX_DECOMPRESSED_BYTES: u64 = 1024 * 1024;
+
+const GZIP: u8 = 1;
+const ZSTD: u8 = 2;
+
+/// The compression applied to aggregated records
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Compression::None
+    }
+}
+
+impl Compression {
+    fn marker(&self) -> Option<u8> {
+        match self {
+            Compression::None => None,
+            Compression::Gzip => Some(GZIP),
+            Compression::Zstd => Some(ZSTD),
+        }
+    }
+
+    /// Compresses `data`, returning None if compression is disabled, fails or doesn't
+    /// reduce its size, in which case it is sent uncompressed
+    pub(crate) fn compress(&self, data: &[u8]) -> Option<Bytes> {
+        let marker = self.marker()?;
+
+        let mut buf = Vec::with_capacity(data.len());
+        buf.extend_from_slice(&MAGIC);
+        buf.push(marker);
+
+        let mut buf = match self.encode(buf, data) {
+            Ok(buf) => buf,
+            Err(e) => {
+                warn!(compression = ?self, "failed to compress record: {}", e);
+                return None;
+            }
+        };
+
+        let checksum = md5::compute(&buf[MAGIC.len()..]);
+        buf.extend_from_slice(&checksum.0);
+
+        if buf.len() >= data.len() {
+            return None;
+        }
+        Some(buf.into())
+    }
+
+    /// Appends `data` compressed to `buf`
+    fn encode(&self, buf: Vec<u8>, data: &[u8]) -> std::io::Result<Vec<u8>> {
+        match self {
+            Compression::None => unreachable!(),
+            Compression::Gzip => {
+                let mut encoder = GzEncoder::new(buf, flate2::Compression::default());
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+            Compression::Zstd => {
+                let mut encoder = zstd::Encoder::new(buf, 0)?;
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+        }
+    }
+}
+
+/// Returns true if `data` has the magic bytes and a matching digest
+pub(crate) fn is_compressed(data: &[u8]) -> bool {
+    if data.len() <= MAGIC.len() + DIGEST_LEN || data[..MAGIC.len()] != MAGIC {
+        return false;
+    }
+
+    let (message, digest) = data[MAGIC.len()..].split_at(data.len() - MAGIC.len() - DIGEST_LEN);
+    md5::compute(message).0 == digest
+}
+
+/// Decompresses data produced by `Compression::compress`, failing if it would
+/// decompress to more than `MAX_DECOMPRESSED_BYTES`
+pub(crate) fn decompress(data: &[u8]) -> std::io::Result<Bytes> {
+    let payload = &data[MAGIC.len() + 1..data.len() - DIGEST_LEN];
+    let mut out = Vec::with_capacity(payload.len() * 4);
+    let limit = MAX_DECOMPRESSED_BYTES + 1;
+
+    match data[MAGIC.len()] {
+        GZIP => GzDecoder::new(payload).take(limit).read_to_end(&mut out)?,
+        ZSTD => zstd::Decoder::new(payload)?
+            .take(limit)
+            .read_to_end(&mut out)?,
+        marker => {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("unknown compression marker: {}", marker),
+            ))
+        }
+    };
+
+    if out.len() as u64 > MAX_DECOMPRESSED_BYTES {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!(
+                "record decompresses to more than {} bytes",
+                MAX_DECOMPRESSED_BYTES
+            ),
+        ));
+    }
+
+    Ok(out.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        let data = "hello world ".repeat(100);
+
+        for compression in [Compression::Gzip, Compression::Zstd].iter() {
+            let compressed = compression.compress(data.as_bytes()).unwrap();
+            assert!(is_compressed(&compressed));
+            assert!(compressed.len() < data.len());
+
+            let decompressed = decompress(&compressed).unwrap();
+            assert_eq!(decompressed.as_ref(), data.as_bytes());
+        }
+    }
+
+    #[test]
+    fn test_incompressible() {
+        assert!(Compression::None.compress(b"hello").is_none());
+        assert!(Compression::Gzip.compress(b"hello").is_none());
+    }
+
+    #[test]
+    fn test_magic_prefix() {
+        let mut data = MAGIC.to_vec();
+        data.push(GZIP);
+        data.extend_from_slice(b"not actually compressed at all");
+        assert!(!is_compressed(&data));
+
+        let mut compressed = Compression::Gzip
+            .compress("hello world ".repeat(100).as_bytes())
+            .unwrap()
+            .to_vec();
+        compressed[MAGIC.len() + 1] ^= 0xFF;
+        assert!(!is_compressed(&compressed));
+    }
+
+    #[test]
+    fn test_decompression_limit() {
+        let data = vec![0; MAX_DECOMPRESSED_BYTES as usize + 1];
+
+        for compression in [Compression::Gzip, Compression::Zstd].iter() {
+            let compressed = compression.compress(&data).unwrap();
+            assert!(is_compressed(&compressed));
+            assert!(decompress(&compressed).is_err());
+        }
+
+        let data = vec![0; MAX_DECOMPRESSED_BYTES as usize];
+        let compressed = Compression::Zstd.compress(&data).unwrap();
+        assert_eq!(decompress(&compressed).unwrap().len(), data.len());
+    }
+}
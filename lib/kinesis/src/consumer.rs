@@ -0,0 +1,359 @@
+use std::collections::VecDeque;
+
+use futures::stream::{self, BoxStream, StreamExt};
+use rusoto_core::RusotoError;
+use rusoto_kinesis::{
+    DescribeStreamSummaryError, DescribeStreamSummaryInput, GetRecordsError, GetRecordsInput,
+    GetShardIteratorError, GetShardIteratorInput, Kinesis, KinesisClient, ListShardsError,
+    ListShardsInput,
+};
+use rusoto_util::Target;
+use tokio::time::{delay_for, Duration};
+use tracing::{error, info};
+
+use crate::deaggregator::{deaggregate_record, UserRecord};
+use crate::kinesis_client;
+use crate::topology::ShardId;
+
+#[derive(Debug, Clone)]
+pub enum Error {
+    GetShardIteratorError(String),
+    GetRecordsError(String),
+    ListShardsError(String),
+    DescribeStreamSummaryError(String),
+    /// The stream is being created or deleted
+    StreamNotActive(String),
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl From<RusotoError<GetShardIteratorError>> for Error {
+    fn from(e: RusotoError<GetShardIteratorError>) -> Self {
+        Error::GetShardIteratorError(e.to_string())
+    }
+}
+
+impl From<RusotoError<GetRecordsError>> for Error {
+    fn from(e: RusotoError<GetRecordsError>) -> Self {
+        Error::GetRecordsError(e.to_string())
+    }
+}
+
+impl From<RusotoError<ListShardsError>> for Error {
+    fn from(e: RusotoError<ListShardsError>) -> Self {
+        Error::ListShardsError(e.to_string())
+    }
+}
+
+impl From<RusotoError<DescribeStreamSummaryError>> for Error {
+    fn from(e: RusotoError<DescribeStreamSummaryError>) -> Self {
+        Error::DescribeStreamSummaryError(e.to_string())
+    }
+}
+
+/// The position in a shard to start reading from
+#[derive(Debug, Clone)]
+pub enum StartingPosition {
+    TrimHorizon,
+    Latest,
+    AtSequenceNumber(String),
+    AfterSequenceNumber(String),
+    /// The first record written at or after the given number of seconds since the unix epoch
+    AtTimestamp(f64),
+}
+
+impl StartingPosition {
+    fn shard_iterator_type(&self) -> &'static str {
+        match self {
+            StartingPosition::TrimHorizon => "TRIM_HORIZON",
+            StartingPosition::Latest => "LATEST",
+            StartingPosition::AtSequenceNumber(_) => "AT_SEQUENCE_NUMBER",
+            StartingPosition::AfterSequenceNumber(_) => "AFTER_SEQUENCE_NUMBER",
+            StartingPosition::AtTimestamp(_) => "AT_TIMESTAMP",
+        }
+    }
+
+    fn timestamp(&self) -> Option<f64> {
+        match self {
+            StartingPosition::AtTimestamp(timestamp) => Some(*timestamp),
+            _ => None,
+        }
+    }
+
+    fn sequence_number(&self) -> Option<String> {
+        match self {
+            StartingPosition::AtSequenceNumber(s) | StartingPosition::AfterSequenceNumber(s) => {
+                Some(s.clone())
+            }
+            _ => None,
+        }
+    }
+}
+
+pub struct ConsumerBuilder {
+    region: String,
+    stream: String,
+    endpoint: Option<String>,
+    target: Target,
+    max_records: i64,
+    poll_interval: Duration,
+}
+
+impl ConsumerBuilder {
+    /// Creates a new polling consumer
+    pub fn new(region: String, stream: String) -> ConsumerBuilder {
+        ConsumerBuilder {
+            region,
+            stream,
+            endpoint: None,
+            target: Target::Aws,
+            max_records: 10000,
+            poll_interval: Duration::from_secs(1),
+        }
+    }
+
+    /// Use local kinesalite endpoint
+    pub fn local(&mut self) -> &mut Self {
+        self.target = Target::Local;
+        self
+    }
+
+    /// Use a LocalStack container, at its default edge endpoint unless overridden
+    pub fn localstack(&mut self) -> &mut Self {
+        self.target = Target::LocalStack;
+        self
+    }
+
+    /// Override endpoint
+    pub fn endpoint(&mut self, endpoint: String) -> &mut Self {
+        self.endpoint = Some(endpoint);
+        self
+    }
+
+    /// Configures the maximum number of records returned by a single GetRecords call
+    pub fn max_records(&mut self, max_records: i64) -> &mut Self {
+        self.max_records = max_records;
+        self
+    }
+
+    /// Configures the delay between GetRecords calls that returned no records
+    pub fn poll_interval(&mut self, poll_interval: Duration) -> &mut Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    pub fn build(self) -> Consumer {
+        Consumer {
+            client: kinesis_client(self.region, self.endpoint, self.target),
+            stream_name: self.stream,
+            max_records: self.max_records,
+            poll_interval: self.poll_interval,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Consumer {
+    client: KinesisClient,
+    stream_name: String,
+    max_records: i64,
+    poll_interval: Duration,
+}
+
+impl Consumer {
+    /// Lists the shards of the stream, including closed shards still within the retention period
+    pub async fn shards(&self) -> Result<Vec<ShardId>> {
+        let lineage = self.lineage().await?;
+        Ok(lineage.into_iter().map(|(shard_id, _)| shard_id).collect())
+    }
+
+    /// Lists the shards of the stream as `shards` does, along with the shards each was
+    /// split or merged from
+    pub async fn lineage(&self) -> Result<Vec<(ShardId, Vec<ShardId>)>> {
+        let mut next_token = None;
+        let mut shards = Vec::new();
+
+        loop {
+            let input = if next_token.is_some() {
+                ListShardsInput {
+                    next_token,
+                    ..Default::default()
+                }
+            } else {
+                ListShardsInput {
+                    stream_name: Some(self.stream_name.clone()),
+                    ..Default::default()
+                }
+            };
+
+            let output = self.client.list_shards(input).await?;
+
+            for shard in output.shards.unwrap_or_default() {
+                let parents = shard
+                    .parent_shard_id
+                    .iter()
+                    .chain(shard.adjacent_parent_shard_id.iter())
+                    .map(|x| x.parse())
+                    .collect::<Result<_, _>>();
+
+                match (shard.shard_id.parse(), parents) {
+                    (Ok(shard_id), Ok(parents)) => shards.push((shard_id, parents)),
+                    _ => error!(shard_id = %shard.shard_id, "invalid shard id"),
+                }
+            }
+
+            if output.next_token.is_none() {
+                break;
+            }
+
+            next_token = output.next_token
+        }
+
+        Ok(shards)
+    }
+
+    /// Checks that the stream can be described and is active, such as for a readiness
+    /// probe
+    ///
+    /// A stream being resharded is updating, but can still be read
+    pub async fn check(&self) -> Result<()> {
+        let output = self
+            .client
+            .describe_stream_summary(DescribeStreamSummaryInput {
+                stream_name: self.stream_name.clone(),
+            })
+            .await?;
+
+        match output.stream_description_summary.stream_status.as_str() {
+            "ACTIVE" | "UPDATING" => Ok(()),
+            status => Err(Error::StreamNotActive(status.to_string())),
+        }
+    }
+
+    /// Returns a stream of the deaggregated user records in a shard
+    ///
+    /// The stream terminates once the end of a closed shard is reached
+    pub fn shard(
+        &self,
+        shard_id: ShardId,
+        position: StartingPosition,
+    ) -> BoxStream<'static, Result<UserRecord>> {
+        let reader = ShardReader {
+            client: self.client.clone(),
+            stream_name: self.stream_name.clone(),
+            max_records: self.max_records,
+            poll_interval: self.poll_interval,
+            shard_id,
+            position,
+            iterator: None,
+            buffer: Default::default(),
+            finished: false,
+        };
+
+        stream::unfold(reader, |mut reader| async move {
+            let next = reader.next().await?;
+            Some((next, reader))
+        })
+        .boxed()
+    }
+}
+
+struct ShardReader {
+    client: KinesisClient,
+    stream_name: String,
+    max_records: i64,
+    poll_interval: Duration,
+    shard_id: ShardId,
+    position: StartingPosition,
+    iterator: Option<String>,
+    buffer: VecDeque<UserRecord>,
+    finished: bool,
+}
+
+impl ShardReader {
+    async fn get_shard_iterator(&self) -> Result<String> {
+        let output = self
+            .client
+            .get_shard_iterator(GetShardIteratorInput {
+                shard_id: self.shard_id.to_string(),
+                shard_iterator_type: self.position.shard_iterator_type().to_string(),
+                starting_sequence_number: self.position.sequence_number(),
+                stream_name: self.stream_name.clone(),
+                timestamp: self.position.timestamp(),
+            })
+            .await?;
+
+        output.shard_iterator.ok_or_else(|| {
+            Error::GetShardIteratorError("GetShardIterator returned no iterator".to_string())
+        })
+    }
+
+    async fn poll(&mut self) -> Result<()> {
+        let shard_iterator = match self.iterator.take() {
+            Some(iterator) => iterator,
+            None => self.get_shard_iterator().await?,
+        };
+
+        let output = match self
+            .client
+            .get_records(GetRecordsInput {
+                limit: Some(self.max_records),
+                shard_iterator: shard_iterator.clone(),
+            })
+            .await
+        {
+            Ok(output) => output,
+            Err(RusotoError::Service(GetRecordsError::ExpiredIterator(_))) => {
+                info!(shard_id = ?self.shard_id, "shard iterator expired");
+                return Ok(());
+            }
+            Err(e) => {
+                self.iterator = Some(shard_iterator);
+                return Err(e.into());
+            }
+        };
+
+        for record in output.records {
+            let sequence_number = record.sequence_number.clone();
+            match deaggregate_record(record) {
+                Ok(records) => self.buffer.extend(records),
+                Err(e) => error!(
+                    %sequence_number,
+                    "failed to deaggregate record - skipping: {:?}", e
+                ),
+            }
+            self.position = StartingPosition::AfterSequenceNumber(sequence_number);
+        }
+
+        match output.next_shard_iterator {
+            Some(iterator) => self.iterator = Some(iterator),
+            None => {
+                info!(shard_id = ?self.shard_id, "reached end of closed shard");
+                self.finished = true
+            }
+        }
+        Ok(())
+    }
+
+    async fn next(&mut self) -> Option<Result<UserRecord>> {
+        loop {
+            if let Some(record) = self.buffer.pop_front() {
+                return Some(Ok(record));
+            }
+
+            if self.finished {
+                return None;
+            }
+
+            if let Err(e) = self.poll().await {
+                error!("error reading shard: {:?}", e);
+                delay_for(self.poll_interval).await;
+                return Some(Err(e));
+            }
+
+            if self.buffer.is_empty() && !self.finished {
+                delay_for(self.poll_interval).await;
+            }
+        }
+    }
+}
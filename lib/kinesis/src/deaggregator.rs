@@ -0,0 +1,226 @@
+use bytes::Bytes;
+use prost::Message;
+use tracing::warn;
+
+use crate::aggregator::{proto, DIGEST_LEN, MAGIC};
+use crate::compression;
+
+#[derive(Debug, Clone)]
+pub enum Error {
+    DecodeError(String),
+    InvalidPartitionKeyIndex(u64),
+    InvalidExplicitHashKeyIndex(u64),
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// An individual user record extracted from a Kinesis record
+#[derive(Debug, Clone)]
+pub struct UserRecord {
+    pub partition_key: String,
+    pub explicit_hash_key: Option<String>,
+    pub data: Bytes,
+    pub sequence_number: String,
+    /// The position of this record within its aggregated parent, None if not aggregated
+    pub sub_sequence_number: Option<u64>,
+}
+
+impl UserRecord {
+    pub fn aggregated(&self) -> bool {
+        self.sub_sequence_number.is_some()
+    }
+}
+
+fn is_aggregated(data: &[u8]) -> bool {
+    if data.len() < MAGIC.len() + DIGEST_LEN || data[..MAGIC.len()] != MAGIC {
+        return false;
+    }
+
+    let (message, digest) = data[MAGIC.len()..].split_at(data.len() - MAGIC.len() - DIGEST_LEN);
+    md5::compute(message).0 == digest
+}
+
+/// Expands a record into its constituent user records
+///
+/// Records that were not produced by a KPL-compatible aggregator, including those
+/// with a corrupt checksum or that fail to decompress, are returned unchanged as a
+/// single user record
+pub fn deaggregate(
+    partition_key: String,
+    sequence_number: String,
+    mut data: Bytes,
+) -> Result<Vec<UserRecord>> {
+    if compression::is_compressed(&data) {
+        match compression::decompress(&data) {
+            Ok(decompressed) => data = decompressed,
+            Err(e) => warn!(%sequence_number, "failed to decompress record: {}", e),
+        }
+    }
+
+    if !is_aggregated(&data) {
+        return Ok(vec![UserRecord {
+            partition_key,
+            explicit_hash_key: None,
+            data,
+            sequence_number,
+            sub_sequence_number: None,
+        }]);
+    }
+
+    let message = data.slice(MAGIC.len()..data.len() - DIGEST_LEN);
+    let aggregated =
+        proto::AggregatedRecord::decode(message).map_err(|e| Error::DecodeError(e.to_string()))?;
+
+    let partition_keys = aggregated.partition_key_table;
+    let hash_keys = aggregated.explicit_hash_key_table;
+
+    aggregated
+        .records
+        .into_iter()
+        .enumerate()
+        .map(|(idx, record)| {
+            let partition_key = partition_keys
+                .get(record.partition_key_index as usize)
+                .cloned()
+                .ok_or(Error::InvalidPartitionKeyIndex(record.partition_key_index))?;
+
+            let explicit_hash_key = match record.explicit_hash_key_index {
+                Some(hash_key_index) => Some(
+                    hash_keys
+                        .get(hash_key_index as usize)
+                        .cloned()
+                        .ok_or(Error::InvalidExplicitHashKeyIndex(hash_key_index))?,
+                ),
+                None => None,
+            };
+
+            Ok(UserRecord {
+                partition_key,
+                explicit_hash_key,
+                data: record.data,
+                sequence_number: sequence_number.clone(),
+                sub_sequence_number: Some(idx as u64),
+            })
+        })
+        .collect()
+}
+
+/// Expands a record returned by GetRecords into its constituent user records
+pub fn deaggregate_record(record: rusoto_kinesis::Record) -> Result<Vec<UserRecord>> {
+    deaggregate(record.partition_key, record.sequence_number, record.data)
+}
+
+#[cfg(test)]
+mod tests {
+    use stream::Reducer;
+    use tokio::time::Instant;
+
+    use crate::aggregator::RecordAggregator;
+    use crate::compression::Compression;
+    use crate::producer::Record;
+
+    use super::*;
+
+    fn record(partition_key: &str, data: &'static [u8]) -> Record {
+        Record {
+            partition_key: partition_key.to_string(),
+            explicit_hash_key: None,
+            data: Bytes::from_static(data),
+            predicted_shard_id: None,
+            acker: None,
+            children: vec![],
+            attempts: 0,
+            pending: None,
+            sequence: None,
+            deadline: None,
+            enqueued: Instant::now(),
+            span: tracing::Span::none(),
+        }
+    }
+
+    #[test]
+    fn test_roundtrip() -> std::result::Result<(), Box<dyn std::error::Error>> {
+        let mut aggregator = RecordAggregator::new(51200, 100, Compression::None);
+        assert!(aggregator.try_push(record("a", b"hello")).is_none());
+        assert!(aggregator.try_push(record("b", b"world")).is_none());
+        assert!(aggregator.try_push(record("a", b"foo")).is_none());
+
+        let aggregated = aggregator.take().unwrap();
+        let records = deaggregate(aggregated.partition_key, "123".to_string(), aggregated.data)
+            .map_err(|e| format!("{:?}", e))?;
+
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[0].partition_key, "a");
+        assert_eq!(records[1].partition_key, "b");
+        assert_eq!(records[2].partition_key, "a");
+        assert_eq!(records[0].data.as_ref(), b"hello");
+        assert_eq!(records[1].data.as_ref(), b"world");
+        assert_eq!(records[2].data.as_ref(), b"foo");
+        assert_eq!(records[2].sequence_number, "123");
+        assert_eq!(records[2].sub_sequence_number, Some(2));
+        Ok(())
+    }
+
+    #[test]
+    fn test_compressed_roundtrip() {
+        let mut aggregator = RecordAggregator::new(51200, 100, Compression::Zstd);
+        for _ in 0..10 {
+            assert!(aggregator
+                .try_push(record("a", br#"{"hello": "world"}"#))
+                .is_none());
+        }
+
+        let aggregated = aggregator.take().unwrap();
+        assert!(compression::is_compressed(&aggregated.data));
+
+        let records =
+            deaggregate(aggregated.partition_key, "123".to_string(), aggregated.data).unwrap();
+
+        assert_eq!(records.len(), 10);
+        assert_eq!(records[9].data.as_ref(), br#"{"hello": "world"}"#);
+        assert_eq!(records[9].sub_sequence_number, Some(9));
+    }
+
+    #[test]
+    fn test_explicit_hash_key() {
+        let mut pinned = record("a", b"hello");
+        pinned.explicit_hash_key = Some(1234);
+
+        let mut aggregator = RecordAggregator::new(51200, 100, Compression::None);
+        assert!(aggregator.try_push(pinned).is_none());
+        assert!(aggregator.try_push(record("b", b"world")).is_none());
+
+        let aggregated = aggregator.take().unwrap();
+        assert_eq!(aggregated.explicit_hash_key, Some(1234));
+
+        let records =
+            deaggregate(aggregated.partition_key, "123".to_string(), aggregated.data).unwrap();
+
+        assert_eq!(records[0].explicit_hash_key.as_deref(), Some("1234"));
+        assert_eq!(records[1].explicit_hash_key, None);
+    }
+
+    #[test]
+    fn test_not_aggregated() {
+        let records = deaggregate(
+            "a".to_string(),
+            "123".to_string(),
+            Bytes::from_static(b"hello world"),
+        )
+        .unwrap();
+
+        assert_eq!(records.len(), 1);
+        assert!(!records[0].aggregated());
+        assert_eq!(records[0].data.as_ref(), b"hello world");
+    }
+
+    #[test]
+    fn test_invalid_checksum() {
+        let mut data = MAGIC.to_vec();
+        data.extend_from_slice(&[0; 20]);
+
+        let records = deaggregate("a".to_string(), "123".to_string(), data.into()).unwrap();
+        assert_eq!(records.len(), 1);
+        assert!(!records[0].aggregated());
+    }
+}
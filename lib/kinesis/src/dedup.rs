@@ -0,0 +1,159 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::oneshot;
+use tokio::time::{Duration, Instant};
+
+use crate::producer::{Ack, Error};
+
+enum Entry {
+    /// The first record submitted with this id is yet to be acknowledged
+    InFlight(Vec<oneshot::Sender<Result<Ack, Error>>>),
+    /// A record with this id was delivered, and will be remembered until the given instant
+    Delivered(Ack, Instant),
+}
+
+struct Inner {
+    ttl: Duration,
+    entries: HashMap<String, Entry>,
+    /// Delivered ids in order of expiry
+    expiry: VecDeque<(Instant, String)>,
+}
+
+impl Inner {
+    fn expire(&mut self, now: Instant) {
+        while let Some((expires, _)) = self.expiry.front() {
+            if *expires > now {
+                break;
+            }
+
+            let (expires, id) = self.expiry.pop_front().unwrap();
+            if let Some(Entry::Delivered(_, current)) = self.entries.get(&id) {
+                // The id may have been resubmitted and delivered again since
+                if *current == expires {
+                    self.entries.remove(&id);
+                }
+            }
+        }
+    }
+}
+
+/// The outcome of checking an idempotency id against the cache
+pub(crate) enum Check {
+    /// The record should be sent, with its result passed to `Dedup::complete`. The
+    /// receiver resolves with that result
+    Send(oneshot::Receiver<Result<Ack, Error>>),
+    /// A record with the same id is in flight, the receiver resolves with its result
+    Wait(oneshot::Receiver<Result<Ack, Error>>),
+    /// A record with the same id has already been delivered
+    Delivered(Ack),
+}
+
+/// Deduplicates records submitted with the same idempotency id
+///
+/// Successfully delivered ids are remembered for a TTL, failed ids are forgotten so
+/// that they can be retried
+#[derive(Clone)]
+pub(crate) struct Dedup(Arc<Mutex<Inner>>);
+
+impl Dedup {
+    pub fn new(ttl: Duration) -> Dedup {
+        Dedup(Arc::new(Mutex::new(Inner {
+            ttl,
+            entries: Default::default(),
+            expiry: Default::default(),
+        })))
+    }
+
+    pub fn check(&self, id: &str) -> Check {
+        let mut inner = self.0.lock().unwrap();
+        inner.expire(Instant::now());
+
+        let (tx, rx) = oneshot::channel();
+        match inner.entries.get_mut(id) {
+            Some(Entry::InFlight(waiters)) => {
+                waiters.push(tx);
+                Check::Wait(rx)
+            }
+            Some(Entry::Delivered(ack, _)) => Check::Delivered(ack.clone()),
+            None => {
+                inner
+                    .entries
+                    .insert(id.to_string(), Entry::InFlight(vec![tx]));
+                Check::Send(rx)
+            }
+        }
+    }
+
+    /// Records the result of the record sent for `id`, notifying any waiters
+    pub fn complete(&self, id: String, result: Result<Ack, Error>) {
+        let mut inner = self.0.lock().unwrap();
+
+        if let Some(Entry::InFlight(waiters)) = inner.entries.remove(&id) {
+            for waiter in waiters {
+                let _ = waiter.send(result.clone());
+            }
+        }
+
+        if let Ok(ack) = result {
+            let expires = Instant::now() + inner.ttl;
+            inner.expiry.push_back((expires, id.clone()));
+            inner.entries.insert(id, Entry::Delivered(ack, expires));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ack(sequence_number: &str) -> Ack {
+        Ack {
+            shard_id: None,
+            sequence_number: sequence_number.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_dedup() {
+        let dedup = Dedup::new(Duration::from_millis(50));
+
+        let first = match dedup.check("a") {
+            Check::Send(rx) => rx,
+            _ => panic!("expected send"),
+        };
+        let second = match dedup.check("a") {
+            Check::Wait(rx) => rx,
+            _ => panic!("expected wait"),
+        };
+
+        dedup.complete("a".to_string(), Ok(ack("1")));
+        assert_eq!(first.await.unwrap().unwrap().sequence_number, "1");
+        assert_eq!(second.await.unwrap().unwrap().sequence_number, "1");
+
+        assert!(matches!(dedup.check("a"), Check::Delivered(ack) if ack.sequence_number == "1"));
+
+        tokio::time::delay_for(Duration::from_millis(60)).await;
+        assert!(matches!(dedup.check("a"), Check::Send(_)));
+    }
+
+    #[tokio::test]
+    async fn test_failure() {
+        let dedup = Dedup::new(Duration::from_secs(60));
+
+        assert!(matches!(dedup.check("a"), Check::Send(_)));
+        let waiter = match dedup.check("a") {
+            Check::Wait(rx) => rx,
+            _ => panic!("expected wait"),
+        };
+
+        dedup.complete("a".to_string(), Err(Error::RetriesExhausted));
+        assert!(matches!(
+            waiter.await.unwrap(),
+            Err(Error::RetriesExhausted)
+        ));
+
+        // Failed ids can be retried
+        assert!(matches!(dedup.check("a"), Check::Send(_)));
+    }
+}
@@ -0,0 +1,113 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use tokio::sync::Notify;
+use tokio::time::Duration;
+use tracing::{info, warn};
+
+struct Inner {
+    count: AtomicUsize,
+    notify: Notify,
+}
+
+/// Tracks the records that have been accepted by a pipeline but not yet acknowledged
+#[derive(Clone)]
+pub(crate) struct Pending(Arc<Inner>);
+
+/// Held by a pending record, decrementing the count when dropped
+#[derive(Debug)]
+pub(crate) struct PendingGuard(Arc<Inner>);
+
+impl std::fmt::Debug for Inner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Pending({})", self.count.load(Ordering::SeqCst))
+    }
+}
+
+impl Pending {
+    pub fn new() -> Pending {
+        Pending(Arc::new(Inner {
+            count: AtomicUsize::new(0),
+            notify: Notify::new(),
+        }))
+    }
+
+    pub fn track(&self) -> PendingGuard {
+        self.0.count.fetch_add(1, Ordering::SeqCst);
+        PendingGuard(self.0.clone())
+    }
+
+    pub fn count(&self) -> usize {
+        self.0.count.load(Ordering::SeqCst)
+    }
+
+    /// Resolves once there are no pending records
+    pub async fn drained(&self) {
+        while self.count() != 0 {
+            self.0.notify.notified().await
+        }
+    }
+}
+
+impl Drop for PendingGuard {
+    fn drop(&mut self) {
+        if self.0.count.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.0.notify.notify()
+        }
+    }
+}
+
+/// Resolves once shutdown has been requested and either all pending records
+/// have been acknowledged, or `timeout` has elapsed
+pub(crate) async fn drain(shutdown: shutdown::Receiver, pending: Pending, timeout: Duration) {
+    shutdown.await;
+    info!(pending = pending.count(), "draining pipeline");
+
+    if tokio::time::timeout(timeout, pending.drained())
+        .await
+        .is_err()
+    {
+        warn!(
+            pending = pending.count(),
+            "timed out draining pipeline - abandoning records"
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_drain() {
+        let pending = Pending::new();
+        let (shutdown_tx, shutdown_rx) = shutdown::channel();
+
+        let a = pending.track();
+        let b = pending.track();
+        assert_eq!(pending.count(), 2);
+
+        let drain = drain(shutdown_rx, pending.clone(), Duration::from_secs(60));
+        tokio::pin!(drain);
+
+        shutdown_tx.shutdown();
+        drop(a);
+        assert!(futures::poll!(&mut drain).is_pending());
+
+        drop(b);
+        drain.await;
+        assert_eq!(pending.count(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_drain_timeout() {
+        let pending = Pending::new();
+        let (shutdown_tx, shutdown_rx) = shutdown::channel();
+
+        let _a = pending.track();
+        shutdown_tx.shutdown();
+
+        drain(shutdown_rx, pending.clone(), Duration::from_millis(10)).await;
+        assert_eq!(pending.count(), 1);
+    }
+}
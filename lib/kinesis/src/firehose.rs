@@ -0,0 +1,156 @@
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures::prelude::*;
+use futures::stream::FuturesUnordered;
+use pin_project::pin_project;
+use rusoto_firehose::{
+    KinesisFirehose, KinesisFirehoseClient, PutRecordBatchInput, PutRecordBatchOutput,
+    PutRecordBatchResponseEntry,
+};
+use tokio::task::JoinHandle;
+use tracing::info;
+
+use crate::producer::{self, Ack, Record};
+use crate::request::RequestPolicy;
+use crate::sink::{self, ErrorHandler};
+
+/// The maximum size of a single Firehose record
+const MAX_RECORD_BYTES: usize = 1000 * 1024;
+
+pub(crate) fn validate(record: &Record) -> Result<(), producer::Error> {
+    if record.len() > MAX_RECORD_BYTES {
+        return Err(producer::Error::RecordTooLarge);
+    }
+    Ok(())
+}
+
+#[pin_project]
+pub(crate) struct FirehoseSink {
+    client: KinesisFirehoseClient,
+    delivery_stream_name: String,
+    error_handler: ErrorHandler,
+    request: RequestPolicy,
+
+    #[pin]
+    in_flight: FuturesUnordered<JoinHandle<()>>,
+}
+
+impl FirehoseSink {
+    pub fn new(
+        client: KinesisFirehoseClient,
+        delivery_stream_name: String,
+        error_handler: ErrorHandler,
+        request: RequestPolicy,
+    ) -> FirehoseSink {
+        FirehoseSink {
+            client,
+            delivery_stream_name,
+            error_handler,
+            request,
+            in_flight: Default::default(),
+        }
+    }
+}
+
+fn handle_record(response: PutRecordBatchResponseEntry) -> Result<Ack, sink::Error> {
+    match (response.record_id, response.error_code.as_deref()) {
+        (_, Some("ServiceUnavailableException")) => Err(sink::Error::ThroughputExceeded),
+        (_, Some(_)) | (None, None) => Err(sink::Error::InternalFailure),
+        (Some(record_id), None) => Ok(Ack {
+            shard_id: None,
+            sequence_number: record_id,
+        }),
+    }
+}
+
+async fn handle_response(
+    response: PutRecordBatchOutput,
+    records: Vec<Record>,
+    mut error_handler: ErrorHandler,
+) {
+    for (response, record) in response.request_responses.into_iter().zip(records) {
+        error_handler
+            .complete(record, handle_record(response))
+            .await;
+    }
+}
+
+impl Sink<Vec<Record>> for FirehoseSink {
+    type Error = ();
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.project();
+        this.request.poll_capacity(this.in_flight, cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: Vec<Record>) -> Result<(), Self::Error> {
+        if item.is_empty() {
+            return Ok(());
+        }
+
+        info!(count = item.len(), "submitting records to firehose");
+
+        let records = item
+            .iter()
+            .map(|record| rusoto_firehose::Record {
+                data: record.data.clone(),
+            })
+            .collect();
+
+        let input = PutRecordBatchInput {
+            delivery_stream_name: self.delivery_stream_name.clone(),
+            records,
+        };
+
+        let client = self.client.clone();
+        let task = tokio::spawn(sink::send_batch(
+            self.request,
+            self.error_handler.clone(),
+            item,
+            move || {
+                let client = client.clone();
+                let input = input.clone();
+                async move { client.put_record_batch(input).await }
+            },
+            handle_response,
+        ));
+
+        self.in_flight.push(task);
+
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        sink::poll_drain(self.project().in_flight, cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.poll_flush(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_handle_record() {
+        let ack = handle_record(PutRecordBatchResponseEntry {
+            record_id: Some("abc".to_string()),
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(ack.sequence_number, "abc");
+        assert!(ack.shard_id.is_none());
+
+        let err = handle_record(PutRecordBatchResponseEntry {
+            error_code: Some("ServiceUnavailableException".to_string()),
+            ..Default::default()
+        });
+        assert!(matches!(err, Err(sink::Error::ThroughputExceeded)));
+
+        let err = handle_record(PutRecordBatchResponseEntry::default());
+        assert!(matches!(err, Err(sink::Error::InternalFailure)));
+    }
+}
@@ -0,0 +1,1087 @@
+//! Coordinates the consumption of a stream by several workers, with a DynamoDB lease
+//! table compatible with that of the Kinesis Client Library
+//!
+//! Each shard has a lease, an item keyed by its shard ID in `leaseKey`, recording the
+//! worker holding it in `leaseOwner` and the position it has been processed up to in
+//! `checkpoint` and `checkpointSubSequenceNumber`. A worker renews the leases it holds
+//! by incrementing their `leaseCounter`, acting as its heartbeat, and a lease whose
+//! counter hasn't changed within the failover time has expired and may be taken by
+//! another worker. Workers balance the leases between them, taking expired leases first
+//! and otherwise stealing one at a time from the worker holding the most
+//!
+//! A lease is resumed after the record it was checkpointed at, or at an aggregated
+//! record checkpointed part way through, skipping the sub-records already processed. A
+//! shard split or merged from others isn't leased until they have been processed to
+//! `SHARD_END`, so that the records of a partition key are processed in order
+//!
+//! The table has a string hash key of `leaseKey`
+//!
+//! ```json
+//! {"leaseKey": "shardId-000000000001", "leaseOwner": "worker-a", "leaseCounter": 42, "checkpoint": "4959...", "checkpointSubSequenceNumber": 3, "ownerSwitchesSinceCheckpoint": 0, "parentShardId": ["shardId-000000000000"]}
+//! ```
+
+use std::collections::{HashMap, HashSet};
+use std::time::Instant;
+
+use rusoto_core::RusotoError;
+use rusoto_dynamodb::{
+    AttributeValue, DynamoDb, DynamoDbClient, PutItemError, PutItemInput, ScanError, ScanInput,
+    UpdateItemError, UpdateItemInput,
+};
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::{JoinError, JoinHandle};
+use tokio::time::{delay_for, timeout, Duration};
+use tracing::{error, info, warn};
+
+use dynamo_util::dynamo_client;
+use rusoto_util::Target;
+
+use crate::consumer::{Consumer, StartingPosition};
+use crate::deaggregator::UserRecord;
+use crate::topology::ShardId;
+
+/// The checkpoint recorded once a closed shard has been fully processed
+pub const SHARD_END: &str = "SHARD_END";
+
+const LEASE_KEY: &str = "leaseKey";
+const LEASE_OWNER: &str = "leaseOwner";
+const LEASE_COUNTER: &str = "leaseCounter";
+const CHECKPOINT: &str = "checkpoint";
+const CHECKPOINT_SUB_SEQUENCE_NUMBER: &str = "checkpointSubSequenceNumber";
+const OWNER_SWITCHES: &str = "ownerSwitchesSinceCheckpoint";
+const PARENT_SHARD_IDS: &str = "parentShardId";
+
+/// The checkpoint a lease is created with, as the KCL does for children of a shard
+const TRIM_HORIZON: &str = "TRIM_HORIZON";
+
+/// The checkpoints the KCL creates leases with, meaning that the shard should be read
+/// from the initial position of the consumer
+const INITIAL_POSITIONS: &[&str] = &[TRIM_HORIZON, "LATEST", "AT_TIMESTAMP"];
+
+#[derive(Debug, Clone)]
+pub enum Error {
+    ScanError(String),
+    PutItemError(String),
+    UpdateItemError(String),
+    InvalidLease(String),
+    LeaseLost,
+}
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl From<RusotoError<ScanError>> for Error {
+    fn from(e: RusotoError<ScanError>) -> Self {
+        Error::ScanError(e.to_string())
+    }
+}
+
+impl From<RusotoError<PutItemError>> for Error {
+    fn from(e: RusotoError<PutItemError>) -> Self {
+        Error::PutItemError(e.to_string())
+    }
+}
+
+impl From<RusotoError<UpdateItemError>> for Error {
+    fn from(e: RusotoError<UpdateItemError>) -> Self {
+        Error::UpdateItemError(e.to_string())
+    }
+}
+
+/// A row in the lease table
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lease {
+    pub shard_id: ShardId,
+    pub owner: Option<String>,
+    /// Incremented by every take, renewal and release
+    pub counter: u64,
+    pub checkpoint: Option<String>,
+    /// The position of the checkpointed record within its aggregated parent, None if
+    /// it was processed entirely
+    pub sub_sequence_number: Option<u64>,
+    /// The number of times the lease has been taken from another worker since it was
+    /// last checkpointed
+    pub owner_switches: u64,
+    /// The shards this shard was split or merged from
+    pub parents: Vec<ShardId>,
+}
+
+impl Lease {
+    fn finished(&self) -> bool {
+        self.checkpoint.as_deref() == Some(SHARD_END)
+    }
+
+    /// Returns the sequence number of the checkpoint, None if the shard hasn't been
+    /// processed from
+    fn sequence_number(&self) -> Option<&str> {
+        self.checkpoint
+            .as_deref()
+            .filter(|x| *x != SHARD_END && !INITIAL_POSITIONS.contains(x))
+    }
+
+    /// Returns the position to resume the shard from, or `default` if it hasn't been
+    /// processed from
+    fn starting_position(&self, default: StartingPosition) -> StartingPosition {
+        match (self.sequence_number(), self.sub_sequence_number) {
+            (Some(checkpoint), Some(_)) => {
+                StartingPosition::AtSequenceNumber(checkpoint.to_string())
+            }
+            (Some(checkpoint), None) => {
+                StartingPosition::AfterSequenceNumber(checkpoint.to_string())
+            }
+            // The records of a child follow on from those of its parents
+            (None, _) if !self.parents.is_empty() => StartingPosition::TrimHorizon,
+            (None, _) => default,
+        }
+    }
+
+    /// Returns true if `record` was processed before the checkpoint
+    fn processed(&self, record: &UserRecord) -> bool {
+        match (self.sequence_number(), self.sub_sequence_number) {
+            (Some(checkpoint), Some(sub_sequence_number)) => {
+                checkpoint == record.sequence_number
+                    && record.sub_sequence_number.unwrap_or(0) <= sub_sequence_number
+            }
+            _ => false,
+        }
+    }
+
+    fn from_item(mut item: HashMap<String, AttributeValue>) -> Result<Lease> {
+        let shard_id = item
+            .remove(LEASE_KEY)
+            .and_then(|x| x.s)
+            .and_then(|x| x.parse().ok())
+            .ok_or_else(|| Error::InvalidLease("missing or invalid lease key".to_string()))?;
+
+        let counter = item
+            .remove(LEASE_COUNTER)
+            .and_then(|x| x.n)
+            .and_then(|x| x.parse().ok())
+            .ok_or_else(|| Error::InvalidLease("missing or invalid lease counter".to_string()))?;
+
+        let number = |value: Option<AttributeValue>, name: &str| {
+            value
+                .and_then(|x| x.n)
+                .map(|x| {
+                    x.parse::<u64>()
+                        .map_err(|_| Error::InvalidLease(format!("invalid {}", name)))
+                })
+                .transpose()
+        };
+
+        let parents = item
+            .remove(PARENT_SHARD_IDS)
+            .and_then(|x| x.ss)
+            .unwrap_or_default()
+            .iter()
+            .map(|x| x.parse())
+            .collect::<Result<_, _>>()
+            .map_err(|_| Error::InvalidLease("invalid parent shard id".to_string()))?;
+
+        Ok(Lease {
+            shard_id,
+            owner: item.remove(LEASE_OWNER).and_then(|x| x.s),
+            counter,
+            checkpoint: item.remove(CHECKPOINT).and_then(|x| x.s),
+            sub_sequence_number: number(
+                item.remove(CHECKPOINT_SUB_SEQUENCE_NUMBER),
+                CHECKPOINT_SUB_SEQUENCE_NUMBER,
+            )?,
+            owner_switches: number(item.remove(OWNER_SWITCHES), OWNER_SWITCHES)?.unwrap_or(0),
+            parents,
+        })
+    }
+}
+
+fn string_attribute(s: String) -> AttributeValue {
+    AttributeValue {
+        s: Some(s),
+        ..Default::default()
+    }
+}
+
+fn number_attribute(n: u64) -> AttributeValue {
+    AttributeValue {
+        n: Some(n.to_string()),
+        ..Default::default()
+    }
+}
+
+fn string_set_attribute(ss: Vec<String>) -> AttributeValue {
+    AttributeValue {
+        ss: Some(ss),
+        ..Default::default()
+    }
+}
+
+fn is_conditional_check_failed<E>(e: &RusotoError<E>) -> bool
+where
+    E: ConditionalCheck,
+{
+    match e {
+        RusotoError::Service(e) => e.conditional_check_failed(),
+        _ => false,
+    }
+}
+
+trait ConditionalCheck {
+    fn conditional_check_failed(&self) -> bool;
+}
+
+impl ConditionalCheck for PutItemError {
+    fn conditional_check_failed(&self) -> bool {
+        matches!(self, PutItemError::ConditionalCheckFailed(_))
+    }
+}
+
+impl ConditionalCheck for UpdateItemError {
+    fn conditional_check_failed(&self) -> bool {
+        matches!(self, UpdateItemError::ConditionalCheckFailed(_))
+    }
+}
+
+#[derive(Clone)]
+struct LeaseTable {
+    client: DynamoDbClient,
+    table_name: String,
+}
+
+impl LeaseTable {
+    fn key(shard_id: ShardId) -> HashMap<String, AttributeValue> {
+        let mut key = HashMap::with_capacity(1);
+        key.insert(
+            LEASE_KEY.to_string(),
+            string_attribute(shard_id.to_string()),
+        );
+        key
+    }
+
+    async fn list(&self) -> Result<Vec<Lease>> {
+        let mut exclusive_start_key = None;
+        let mut leases = Vec::new();
+
+        loop {
+            let output = self
+                .client
+                .scan(ScanInput {
+                    table_name: self.table_name.clone(),
+                    consistent_read: Some(true),
+                    exclusive_start_key,
+                    ..Default::default()
+                })
+                .await?;
+
+            for item in output.items.unwrap_or_default() {
+                leases.push(Lease::from_item(item)?);
+            }
+
+            if output.last_evaluated_key.is_none() {
+                break;
+            }
+
+            exclusive_start_key = output.last_evaluated_key
+        }
+
+        Ok(leases)
+    }
+
+    /// Creates an unowned lease for the shard, returns false if it already exists
+    async fn create(&self, shard_id: ShardId, parents: &[ShardId]) -> Result<bool> {
+        let mut item = Self::key(shard_id);
+        item.insert(LEASE_COUNTER.to_string(), number_attribute(0));
+        item.insert(
+            CHECKPOINT.to_string(),
+            string_attribute(TRIM_HORIZON.to_string()),
+        );
+        item.insert(OWNER_SWITCHES.to_string(), number_attribute(0));
+        if !parents.is_empty() {
+            let parents = parents.iter().map(ToString::to_string).collect();
+            item.insert(PARENT_SHARD_IDS.to_string(), string_set_attribute(parents));
+        }
+
+        let result = self
+            .client
+            .put_item(PutItemInput {
+                item,
+                table_name: self.table_name.clone(),
+                condition_expression: Some(format!("attribute_not_exists({})", LEASE_KEY)),
+                ..Default::default()
+            })
+            .await;
+
+        match result {
+            Ok(_) => Ok(true),
+            Err(e) if is_conditional_check_failed(&e) => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Applies `update_expression` if the lease counter is unchanged, returning the updated lease
+    ///
+    /// Returns None if the lease was modified by another worker
+    async fn update_counted(
+        &self,
+        lease: &Lease,
+        update_expression: &str,
+        mut values: HashMap<String, AttributeValue>,
+    ) -> Result<Option<Lease>> {
+        values.insert(":counter".to_string(), number_attribute(lease.counter));
+        values.insert(":next".to_string(), number_attribute(lease.counter + 1));
+
+        let result = self
+            .client
+            .update_item(UpdateItemInput {
+                key: Self::key(lease.shard_id),
+                table_name: self.table_name.clone(),
+                update_expression: Some(format!(
+                    "SET {} = :next {}",
+                    LEASE_COUNTER, update_expression
+                )),
+                condition_expression: Some(format!("{} = :counter", LEASE_COUNTER)),
+                expression_attribute_values: Some(values),
+                return_values: Some("ALL_NEW".to_string()),
+                ..Default::default()
+            })
+            .await;
+
+        match result {
+            Ok(output) => Ok(Some(Lease::from_item(
+                output.attributes.unwrap_or_default(),
+            )?)),
+            Err(e) if is_conditional_check_failed(&e) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn take(&self, lease: &Lease, owner: &str) -> Result<Option<Lease>> {
+        let switches = match lease.owner.as_deref() {
+            Some(previous) if previous != owner => lease.owner_switches + 1,
+            _ => lease.owner_switches,
+        };
+
+        let mut values = HashMap::with_capacity(4);
+        values.insert(":owner".to_string(), string_attribute(owner.to_string()));
+        values.insert(":switches".to_string(), number_attribute(switches));
+        let update_expression =
+            format!(", {} = :owner, {} = :switches", LEASE_OWNER, OWNER_SWITCHES);
+        self.update_counted(lease, &update_expression, values).await
+    }
+
+    async fn renew(&self, lease: &Lease) -> Result<Option<Lease>> {
+        self.update_counted(lease, "", HashMap::with_capacity(2))
+            .await
+    }
+
+    async fn release(&self, lease: &Lease) -> Result<Option<Lease>> {
+        self.update_counted(
+            lease,
+            &format!("REMOVE {}", LEASE_OWNER),
+            HashMap::with_capacity(2),
+        )
+        .await
+    }
+
+    /// Records a checkpoint, failing with `Error::LeaseLost` if `owner` no longer holds the lease
+    async fn checkpoint(
+        &self,
+        shard_id: ShardId,
+        owner: &str,
+        checkpoint: String,
+        sub_sequence_number: Option<u64>,
+    ) -> Result<()> {
+        let mut values = HashMap::with_capacity(4);
+        values.insert(":owner".to_string(), string_attribute(owner.to_string()));
+        values.insert(":checkpoint".to_string(), string_attribute(checkpoint));
+        values.insert(":zero".to_string(), number_attribute(0));
+
+        let mut update_expression = format!(
+            "SET {} = :checkpoint, {} = :zero",
+            CHECKPOINT, OWNER_SWITCHES
+        );
+        match sub_sequence_number {
+            Some(sub_sequence_number) => {
+                values.insert(":sub".to_string(), number_attribute(sub_sequence_number));
+                update_expression += &format!(", {} = :sub", CHECKPOINT_SUB_SEQUENCE_NUMBER);
+            }
+            None => update_expression += &format!(" REMOVE {}", CHECKPOINT_SUB_SEQUENCE_NUMBER),
+        }
+
+        let result = self
+            .client
+            .update_item(UpdateItemInput {
+                key: Self::key(shard_id),
+                table_name: self.table_name.clone(),
+                update_expression: Some(update_expression),
+                condition_expression: Some(format!("{} = :owner", LEASE_OWNER)),
+                expression_attribute_values: Some(values),
+                ..Default::default()
+            })
+            .await;
+
+        match result {
+            Ok(_) => Ok(()),
+            Err(e) if is_conditional_check_failed(&e) => Err(Error::LeaseLost),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+/// Tracks the lease counters observed in the table to detect leases whose owner
+/// has stopped renewing them
+#[derive(Debug, Default)]
+struct ExpiryTracker {
+    observed: HashMap<ShardId, (u64, Instant)>,
+}
+
+impl ExpiryTracker {
+    /// Returns the shards whose leases are unowned or have not been renewed within `failover_time`
+    fn update(
+        &mut self,
+        leases: &[Lease],
+        now: Instant,
+        failover_time: Duration,
+    ) -> HashSet<ShardId> {
+        let mut observed = HashMap::with_capacity(leases.len());
+        let mut expired = HashSet::new();
+
+        for lease in leases {
+            let since = match self.observed.get(&lease.shard_id) {
+                Some((counter, since)) if *counter == lease.counter => *since,
+                _ => now,
+            };
+            observed.insert(lease.shard_id, (lease.counter, since));
+
+            if lease.owner.is_none() || now.duration_since(since) >= failover_time {
+                expired.insert(lease.shard_id);
+            }
+        }
+
+        self.observed = observed;
+        expired
+    }
+}
+
+/// Determines the leases `worker_id` should attempt to take to balance load across workers
+///
+/// Expired leases are preferred, a single lease is stolen from the most loaded worker only
+/// if there are no expired leases available
+///
+/// A lease isn't taken until the leases of its parents are finished, those no longer
+/// in the table having been trimmed from the stream
+fn select_leases(leases: &[Lease], expired: &HashSet<ShardId>, worker_id: &str) -> Vec<Lease> {
+    let unfinished: HashSet<ShardId> = leases
+        .iter()
+        .filter(|x| !x.finished())
+        .map(|x| x.shard_id)
+        .collect();
+
+    let active: Vec<&Lease> = leases
+        .iter()
+        .filter(|x| !x.finished())
+        .filter(|x| x.parents.iter().all(|parent| !unfinished.contains(parent)))
+        .collect();
+    if active.is_empty() {
+        return vec![];
+    }
+
+    let mut counts: HashMap<&str, usize> = HashMap::new();
+    counts.insert(worker_id, 0);
+    for lease in active.iter().filter(|x| !expired.contains(&x.shard_id)) {
+        if let Some(owner) = lease.owner.as_deref() {
+            *counts.entry(owner).or_default() += 1;
+        }
+    }
+
+    let target = (active.len() + counts.len() - 1) / counts.len();
+    let held = counts[worker_id];
+    if held >= target {
+        return vec![];
+    }
+
+    let available: Vec<Lease> = active
+        .iter()
+        .filter(|x| expired.contains(&x.shard_id))
+        .take(target - held)
+        .map(|x| (*x).clone())
+        .collect();
+
+    if !available.is_empty() {
+        return available;
+    }
+
+    let (busiest, busiest_count) = counts
+        .iter()
+        .max_by_key(|(_, count)| **count)
+        .map(|(owner, count)| (*owner, *count))
+        .unwrap();
+
+    if busiest == worker_id || busiest_count <= target {
+        return vec![];
+    }
+
+    active
+        .iter()
+        .find(|x| x.owner.as_deref() == Some(busiest) && !expired.contains(&x.shard_id))
+        .map(|x| vec![(*x).clone()])
+        .unwrap_or_default()
+}
+
+/// A lease on a shard held by this worker
+///
+/// Records should be checkpointed after they have been processed, and processing
+/// should stop once `revoked` completes. Dropping the lease hands it back to the
+/// coordinator which will release it for other workers
+pub struct ShardLease {
+    /// The lease as acquired, with the checkpoints recorded since
+    lease: Lease,
+    owner: String,
+    table: LeaseTable,
+    revoked: shutdown::Receiver,
+    _dropped: oneshot::Sender<()>,
+}
+
+impl ShardLease {
+    pub fn shard_id(&self) -> ShardId {
+        self.lease.shard_id
+    }
+
+    /// Returns the position to resume from, or `default` if no checkpoint has been recorded
+    ///
+    /// A shard split or merged from others is read from its start
+    pub fn starting_position(&self, default: StartingPosition) -> StartingPosition {
+        self.lease.starting_position(default)
+    }
+
+    /// Returns true if `record` was processed before the checkpoint, as the sub-records
+    /// of an aggregated record checkpointed part way through are read again on resuming
+    pub fn processed(&self, record: &UserRecord) -> bool {
+        self.lease.processed(record)
+    }
+
+    /// Persists that all records up to and including `sequence_number` have been processed
+    pub async fn checkpoint(&mut self, sequence_number: String) -> Result<()> {
+        self.write(sequence_number, None).await
+    }
+
+    /// Persists that all records up to and including `record` have been processed, which
+    /// may be part way through an aggregated record
+    pub async fn checkpoint_record(&mut self, record: &UserRecord) -> Result<()> {
+        self.write(record.sequence_number.clone(), record.sub_sequence_number)
+            .await
+    }
+
+    async fn write(&mut self, checkpoint: String, sub_sequence_number: Option<u64>) -> Result<()> {
+        self.table
+            .checkpoint(
+                self.lease.shard_id,
+                &self.owner,
+                checkpoint.clone(),
+                sub_sequence_number,
+            )
+            .await?;
+        self.lease.checkpoint = Some(checkpoint);
+        self.lease.sub_sequence_number = sub_sequence_number;
+        self.lease.owner_switches = 0;
+        Ok(())
+    }
+
+    /// Marks a closed shard as fully processed so that it is not leased again
+    pub async fn finish(mut self) -> Result<()> {
+        self.checkpoint(SHARD_END.to_string()).await
+    }
+
+    /// Returns true if the lease has been lost or the coordinator is shutting down
+    pub fn is_revoked(&self) -> bool {
+        self.revoked.terminating()
+    }
+
+    /// Completes once the lease has been lost or the coordinator is shutting down
+    pub async fn revoked(&self) {
+        self.revoked.clone().await
+    }
+}
+
+struct HeldLease {
+    lease: Lease,
+    revoke: shutdown::Sender,
+    dropped: oneshot::Receiver<()>,
+}
+
+impl HeldLease {
+    fn is_dropped(&mut self) -> bool {
+        matches!(
+            self.dropped.try_recv(),
+            Err(oneshot::error::TryRecvError::Closed)
+        )
+    }
+}
+
+pub struct LeaseCoordinatorHandle {
+    worker_handle: JoinHandle<()>,
+    worker_shutdown: shutdown::Sender,
+}
+
+impl LeaseCoordinatorHandle {
+    /// Revokes all held leases and waits for them to be dropped, before releasing
+    /// them for other workers to take over
+    pub async fn shutdown(self) -> Result<(), JoinError> {
+        self.worker_shutdown.shutdown();
+        self.worker_handle.await
+    }
+}
+
+pub struct LeaseCoordinatorBuilder {
+    region: String,
+    table_name: String,
+    worker_id: String,
+    endpoint: Option<String>,
+    target: Target,
+    failover_time: Duration,
+    shard_sync_interval: Duration,
+    handover_timeout: Duration,
+}
+
+impl LeaseCoordinatorBuilder {
+    /// Creates a new lease coordinator identified within the lease table by `worker_id`
+    pub fn new(region: String, table_name: String, worker_id: String) -> LeaseCoordinatorBuilder {
+        LeaseCoordinatorBuilder {
+            region,
+            table_name,
+            worker_id,
+            endpoint: None,
+            target: Target::Aws,
+            failover_time: Duration::from_secs(10),
+            shard_sync_interval: Duration::from_secs(60),
+            handover_timeout: Duration::from_secs(10),
+        }
+    }
+
+    /// Use local dynamodb endpoint
+    pub fn local(&mut self) -> &mut Self {
+        self.target = Target::Local;
+        self
+    }
+
+    /// Use a LocalStack container, at its default edge endpoint unless overridden
+    pub fn localstack(&mut self) -> &mut Self {
+        self.target = Target::LocalStack;
+        self
+    }
+
+    /// Override endpoint
+    pub fn endpoint(&mut self, endpoint: String) -> &mut Self {
+        self.endpoint = Some(endpoint);
+        self
+    }
+
+    /// Configures the time after which a lease that hasn't been renewed may be taken by another worker
+    ///
+    /// Leases are renewed every third of this interval
+    pub fn failover_time(&mut self, failover_time: Duration) -> &mut Self {
+        self.failover_time = failover_time;
+        self
+    }
+
+    /// Configures how often the shards of the stream are synced into the lease table
+    pub fn shard_sync_interval(&mut self, interval: Duration) -> &mut Self {
+        self.shard_sync_interval = interval;
+        self
+    }
+
+    /// Configures how long to wait on shutdown for held leases to be dropped before releasing them
+    pub fn handover_timeout(&mut self, handover_timeout: Duration) -> &mut Self {
+        self.handover_timeout = handover_timeout;
+        self
+    }
+
+    /// Starts the coordinator, returning a channel of the leases acquired by this worker
+    pub fn build(self, consumer: Consumer) -> (mpsc::Receiver<ShardLease>, LeaseCoordinatorHandle) {
+        let (shutdown_tx, shutdown_rx) = shutdown::channel();
+        let (sender, receiver) = mpsc::channel(100);
+
+        let coordinator = Coordinator {
+            table: LeaseTable {
+                client: dynamo_client(self.region, self.endpoint, self.target),
+                table_name: self.table_name,
+            },
+            worker_id: self.worker_id,
+            consumer,
+            failover_time: self.failover_time,
+            shard_sync_interval: self.shard_sync_interval,
+            handover_timeout: self.handover_timeout,
+            sender,
+            held: HashMap::new(),
+            tracker: Default::default(),
+            last_sync: None,
+        };
+
+        let worker_handle = tokio::spawn(coordinator.run(shutdown_rx));
+
+        (
+            receiver,
+            LeaseCoordinatorHandle {
+                worker_handle,
+                worker_shutdown: shutdown_tx,
+            },
+        )
+    }
+}
+
+struct Coordinator {
+    table: LeaseTable,
+    worker_id: String,
+    consumer: Consumer,
+    failover_time: Duration,
+    shard_sync_interval: Duration,
+    handover_timeout: Duration,
+    sender: mpsc::Sender<ShardLease>,
+    held: HashMap<ShardId, HeldLease>,
+    tracker: ExpiryTracker,
+    last_sync: Option<Instant>,
+}
+
+impl Coordinator {
+    async fn run(mut self, mut shutdown: shutdown::Receiver) {
+        loop {
+            if let Err(e) = self.tick().await {
+                error!("error coordinating leases: {:?}", e);
+            }
+
+            tokio::select! {
+                _ = &mut shutdown => break,
+                _ = delay_for(self.failover_time / 3) => {}
+            }
+        }
+
+        self.handover().await;
+        info!("lease coordinator shutdown")
+    }
+
+    async fn tick(&mut self) -> Result<()> {
+        if self
+            .last_sync
+            .map(|x| x.elapsed() >= self.shard_sync_interval)
+            .unwrap_or(true)
+        {
+            self.sync_shards().await?;
+        }
+
+        self.renew().await;
+
+        let leases = self.table.list().await?;
+        let expired = self
+            .tracker
+            .update(&leases, Instant::now(), self.failover_time);
+
+        for lease in select_leases(&leases, &expired, &self.worker_id) {
+            if self.held.contains_key(&lease.shard_id) {
+                continue;
+            }
+
+            if let Some(owner) = lease
+                .owner
+                .as_deref()
+                .filter(|_| !expired.contains(&lease.shard_id))
+            {
+                info!(shard_id = ?lease.shard_id, owner, "stealing lease");
+            }
+
+            match self.table.take(&lease, &self.worker_id).await? {
+                Some(lease) => self.acquired(lease).await,
+                None => info!(shard_id = ?lease.shard_id, "lost race to take lease"),
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn sync_shards(&mut self) -> Result<()> {
+        let shards = match self.consumer.lineage().await {
+            Ok(shards) => shards,
+            Err(e) => {
+                warn!("failed to list shards: {:?}", e);
+                return Ok(());
+            }
+        };
+
+        for (shard_id, parents) in shards {
+            if self.table.create(shard_id, &parents).await? {
+                info!(?shard_id, "created lease");
+            }
+        }
+
+        self.last_sync = Some(Instant::now());
+        Ok(())
+    }
+
+    async fn renew(&mut self) {
+        let shard_ids: Vec<ShardId> = self.held.keys().cloned().collect();
+
+        for shard_id in shard_ids {
+            let held = self.held.get_mut(&shard_id).unwrap();
+            if held.is_dropped() {
+                let held = self.held.remove(&shard_id).unwrap();
+                self.release(held).await;
+                continue;
+            }
+
+            match self.table.renew(&held.lease).await {
+                Ok(Some(lease)) => held.lease = lease,
+                Ok(None) => {
+                    warn!(?shard_id, "lease lost");
+                    let held = self.held.remove(&shard_id).unwrap();
+                    held.revoke.shutdown();
+                }
+                Err(e) => error!(?shard_id, "error renewing lease: {:?}", e),
+            }
+        }
+    }
+
+    async fn acquired(&mut self, lease: Lease) {
+        info!(shard_id = ?lease.shard_id, "acquired lease");
+
+        let (revoke, revoked) = shutdown::channel();
+        let (dropped_tx, dropped_rx) = oneshot::channel();
+
+        let shard_lease = ShardLease {
+            lease: lease.clone(),
+            owner: self.worker_id.clone(),
+            table: self.table.clone(),
+            revoked,
+            _dropped: dropped_tx,
+        };
+
+        self.held.insert(
+            lease.shard_id,
+            HeldLease {
+                lease,
+                revoke,
+                dropped: dropped_rx,
+            },
+        );
+
+        if self.sender.send(shard_lease).await.is_err() {
+            warn!("lease receiver dropped");
+        }
+    }
+
+    async fn release(&self, held: HeldLease) {
+        let shard_id = held.lease.shard_id;
+        match self.table.release(&held.lease).await {
+            Ok(Some(_)) => info!(?shard_id, "released lease"),
+            Ok(None) => warn!(?shard_id, "lease lost before release"),
+            Err(e) => error!(?shard_id, "error releasing lease: {:?}", e),
+        }
+    }
+
+    /// Revokes all held leases, waits for them to be dropped and then releases them
+    async fn handover(&mut self) {
+        for held in self.held.values() {
+            held.revoke.shutdown();
+        }
+
+        let held: Vec<HeldLease> = self.held.drain().map(|(_, held)| held).collect();
+        for mut held in held {
+            if timeout(self.handover_timeout, &mut held.dropped)
+                .await
+                .is_err()
+            {
+                warn!(shard_id = ?held.lease.shard_id, "lease not dropped before handover timeout");
+            }
+            self.release(held).await
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lease(id: &str, owner: Option<&str>, counter: u64) -> Lease {
+        Lease {
+            shard_id: shard(id),
+            owner: owner.map(ToString::to_string),
+            counter,
+            checkpoint: None,
+            sub_sequence_number: None,
+            owner_switches: 0,
+            parents: vec![],
+        }
+    }
+
+    fn shard(id: &str) -> ShardId {
+        id.parse().unwrap()
+    }
+
+    fn shard_ids(leases: Vec<Lease>) -> Vec<ShardId> {
+        leases.into_iter().map(|x| x.shard_id).collect()
+    }
+
+    #[test]
+    fn test_expiry() {
+        let failover = Duration::from_secs(10);
+        let start = Instant::now();
+        let mut tracker = ExpiryTracker::default();
+
+        let leases = vec![
+            lease("shardId-0", Some("a"), 1),
+            lease("shardId-1", None, 1),
+        ];
+        let expired = tracker.update(&leases, start, failover);
+        assert_eq!(expired.len(), 1);
+        assert!(expired.contains(&shard("shardId-1")));
+
+        let leases = vec![
+            lease("shardId-0", Some("a"), 1),
+            lease("shardId-1", None, 1),
+        ];
+        let expired = tracker.update(&leases, start + failover, failover);
+        assert_eq!(expired.len(), 2);
+
+        // Renewal resets expiry
+        let leases = vec![
+            lease("shardId-0", Some("a"), 2),
+            lease("shardId-1", None, 1),
+        ];
+        let expired = tracker.update(&leases, start + failover, failover);
+        assert_eq!(expired.len(), 1);
+    }
+
+    #[test]
+    fn test_select_expired() {
+        let leases = vec![
+            lease("shardId-0", None, 0),
+            lease("shardId-1", None, 0),
+            lease("shardId-2", Some("b"), 3),
+            lease("shardId-3", Some("b"), 3),
+        ];
+        let expired = vec![shard("shardId-0"), shard("shardId-1")]
+            .into_iter()
+            .collect();
+
+        let selected = shard_ids(select_leases(&leases, &expired, "a"));
+        assert_eq!(selected, vec![shard("shardId-0"), shard("shardId-1")]);
+    }
+
+    #[test]
+    fn test_select_steal() {
+        let leases = vec![
+            lease("shardId-0", Some("b"), 1),
+            lease("shardId-1", Some("b"), 1),
+            lease("shardId-2", Some("b"), 1),
+            lease("shardId-3", Some("b"), 1),
+        ];
+
+        let selected = select_leases(&leases, &HashSet::new(), "a");
+        assert_eq!(selected.len(), 1);
+        assert_eq!(selected[0].owner.as_deref(), Some("b"));
+
+        // Already balanced
+        let leases = vec![
+            lease("shardId-0", Some("a"), 1),
+            lease("shardId-1", Some("a"), 1),
+            lease("shardId-2", Some("b"), 1),
+            lease("shardId-3", Some("b"), 1),
+        ];
+        assert!(select_leases(&leases, &HashSet::new(), "a").is_empty());
+    }
+
+    #[test]
+    fn test_select_parents() {
+        let mut parent = lease("shardId-0", Some("b"), 1);
+        let mut child = lease("shardId-1", None, 0);
+        child.parents = vec![shard("shardId-0")];
+        let expired: HashSet<_> = vec![shard("shardId-1")].into_iter().collect();
+
+        let leases = vec![parent.clone(), child.clone()];
+        assert!(select_leases(&leases, &expired, "a").is_empty());
+
+        parent.checkpoint = Some(SHARD_END.to_string());
+        let leases = vec![parent, child.clone()];
+        let selected = shard_ids(select_leases(&leases, &expired, "a"));
+        assert_eq!(selected, vec![shard("shardId-1")]);
+
+        // The parent has been trimmed from the stream
+        let selected = shard_ids(select_leases(&[child], &expired, "a"));
+        assert_eq!(selected, vec![shard("shardId-1")]);
+    }
+
+    #[test]
+    fn test_from_item() {
+        let mut item = LeaseTable::key(shard("shardId-2"));
+        item.insert(LEASE_OWNER.to_string(), string_attribute("a".to_string()));
+        item.insert(LEASE_COUNTER.to_string(), number_attribute(7));
+        item.insert(CHECKPOINT.to_string(), string_attribute("123".to_string()));
+        item.insert(
+            CHECKPOINT_SUB_SEQUENCE_NUMBER.to_string(),
+            number_attribute(3),
+        );
+        item.insert(OWNER_SWITCHES.to_string(), number_attribute(1));
+        item.insert(
+            PARENT_SHARD_IDS.to_string(),
+            string_set_attribute(vec!["shardId-000000000000".to_string()]),
+        );
+
+        let lease = Lease::from_item(item.clone()).unwrap();
+        assert_eq!(lease.owner.as_deref(), Some("a"));
+        assert_eq!(lease.counter, 7);
+        assert_eq!(lease.checkpoint.as_deref(), Some("123"));
+        assert_eq!(lease.sub_sequence_number, Some(3));
+        assert_eq!(lease.owner_switches, 1);
+        assert_eq!(lease.parents, vec![shard("shardId-0")]);
+
+        // Leases written before the attributes were added
+        item.remove(CHECKPOINT_SUB_SEQUENCE_NUMBER);
+        item.remove(OWNER_SWITCHES);
+        item.remove(PARENT_SHARD_IDS);
+        let lease = Lease::from_item(item).unwrap();
+        assert_eq!(lease.sub_sequence_number, None);
+        assert_eq!(lease.owner_switches, 0);
+        assert!(lease.parents.is_empty());
+    }
+
+    fn record(sequence_number: &str, sub_sequence_number: Option<u64>) -> UserRecord {
+        UserRecord {
+            partition_key: "key".to_string(),
+            explicit_hash_key: None,
+            data: Default::default(),
+            sequence_number: sequence_number.to_string(),
+            sub_sequence_number,
+        }
+    }
+
+    #[test]
+    fn test_starting_position() {
+        let position =
+            |lease: &Lease| format!("{:?}", lease.starting_position(StartingPosition::Latest));
+
+        let mut lease = lease("shardId-0", None, 0);
+        assert_eq!(position(&lease), "Latest");
+
+        lease.checkpoint = Some(TRIM_HORIZON.to_string());
+        assert_eq!(position(&lease), "Latest");
+
+        lease.parents = vec![shard("shardId-1")];
+        assert_eq!(position(&lease), "TrimHorizon");
+
+        lease.checkpoint = Some("123".to_string());
+        assert_eq!(position(&lease), "AfterSequenceNumber(\"123\")");
+        assert!(!lease.processed(&record("123", None)));
+
+        // Resumes within an aggregated record
+        lease.sub_sequence_number = Some(1);
+        assert_eq!(position(&lease), "AtSequenceNumber(\"123\")");
+        assert!(lease.processed(&record("123", Some(0))));
+        assert!(lease.processed(&record("123", Some(1))));
+        assert!(!lease.processed(&record("123", Some(2))));
+        assert!(!lease.processed(&record("124", None)));
+    }
+
+    #[test]
+    fn test_select_finished() {
+        let mut finished = lease("shardId-0", None, 0);
+        finished.checkpoint = Some(SHARD_END.to_string());
+
+        let expired = vec![shard("shardId-0")].into_iter().collect();
+        assert!(select_leases(&[finished], &expired, "a").is_empty());
+    }
+}
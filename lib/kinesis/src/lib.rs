@@ -1,71 +1,195 @@
-use futures::StreamExt;
-use rusoto_core::credential::StaticProvider;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use futures::future::BoxFuture;
+use futures::{FutureExt, Sink, StreamExt};
+use rusoto_cloudwatch::CloudWatchClient;
+use rusoto_firehose::KinesisFirehoseClient;
 use rusoto_kinesis::KinesisClient;
-use tokio::sync::mpsc;
+use rusoto_sqs::SqsClient;
 use tokio::task::{JoinError, JoinHandle};
 use tokio::time::Duration;
 use tracing::info;
 
-use rusoto_util::{parse_region, CustomChainProvider};
+use rusoto_util::{client_config, Target as ClientTarget};
 use stream::{BatchStreamExt, LimitedStreamExt};
 
+use crate::adaptive::AdaptiveRates;
 use crate::aggregator::RecordAggregator;
-use crate::producer::{Producer, RecordBatcher, RecordLimiter};
-use crate::sink::{ErrorHandler, KinesisSink};
+use crate::client::KinesisApi;
+use crate::cloudwatch::CloudWatchConfig;
+use crate::dedup::Dedup;
+use crate::drain::Pending;
+use crate::firehose::FirehoseSink;
+use crate::metrics::PipelineMetrics;
+use crate::producer::{DeadLetter, Producer, RawRecord, Record, RecordBatcher, Route, Router};
+use crate::request::RequestPolicy;
+use crate::sequencer::Sequencer;
+use crate::sink::{self, ErrorHandler, KinesisSink};
+use crate::spill::Spill;
+use crate::sqs::SqsSink;
+use crate::stats::{AliveGuard, StreamStatsSource};
 use crate::topology::TopologyService;
 
+mod adaptive;
 mod aggregator;
+mod client;
+mod cloudwatch;
+mod compression;
+pub mod consumer;
+pub mod deaggregator;
+mod dedup;
+mod drain;
+mod firehose;
 mod intern;
+pub mod lease;
+mod metrics;
+#[cfg(test)]
+mod mock;
 pub mod producer;
-mod shutdown;
+mod queue;
+mod request;
+mod retry;
+mod sequencer;
 mod sink;
+mod spill;
+mod sqs;
+mod stats;
 mod topology;
+mod typed;
+
+pub use cloudwatch::MetricsGranularity;
+pub use compression::Compression;
+pub use metrics::MetricsSnapshot;
+pub use queue::Overflow;
+pub use retry::RetryPolicy;
+pub use stats::{PipelineStats, ShardStats, StreamStats, TopologyStats};
+pub use topology::ShardId;
+pub use typed::{Json, MessagePack, Protobuf, SerializeError, Serializer, TypedProducer};
 
 const BYTES_PER_MB: usize = 1024 * 1024;
 
 pub struct PipelineHandler {
     worker_handle: JoinHandle<()>,
     worker_shutdown: shutdown::Sender,
+    metrics: PipelineMetrics,
+    streams: HashMap<String, StreamStatsSource>,
+    alive: Arc<AtomicBool>,
 }
 
 impl PipelineHandler {
+    /// Shuts down the pipeline
+    ///
+    /// New records are rejected with `Error::Shutdown`, whilst those already submitted
+    /// are flushed and retried until acknowledged or the drain timeout elapses
     pub async fn shutdown(self) -> Result<(), JoinError> {
         self.worker_shutdown.shutdown();
         self.worker_handle.await
     }
+
+    /// Returns the current values of this pipeline's metrics
+    ///
+    /// These are also registered with the global prometheus registry, labelled by pipeline name
+    pub fn metrics(&self) -> MetricsSnapshot {
+        self.metrics.snapshot()
+    }
+
+    /// Returns the current state of this pipeline, such as queue depths and shard rate limits
+    pub fn stats(&self) -> PipelineStats {
+        PipelineStats {
+            alive: self.alive.load(Ordering::Relaxed),
+            in_flight: self.metrics.in_flight(),
+            streams: self
+                .streams
+                .iter()
+                .map(|(name, source)| (name.clone(), source.snapshot()))
+                .collect(),
+        }
+    }
 }
 
+#[derive(Clone, Copy)]
 struct ReducerConfig {
     max_records: usize,
     max_bytes: usize,
     max_wait: Duration,
 }
 
+enum Target {
+    Kinesis,
+    Firehose,
+    Sqs,
+}
+
 pub struct PipelineBuilder {
+    target: Target,
+    name: Option<String>,
     region: String,
     stream: String,
+    streams: Vec<String>,
+    route: Option<Route>,
     endpoint: Option<String>,
     rps_per_shard: u64,
     bps_per_shard: u64,
+    adaptive_rate_limit: bool,
+    strict_ordering: bool,
+    queue_capacity: usize,
+    overflow: Overflow,
 
     batch_config: ReducerConfig,
     aggregator_config: ReducerConfig,
+    compression: Compression,
 
-    retry_backoff: Duration,
-    local: bool,
+    failure_policy: RetryPolicy,
+    throughput_policy: RetryPolicy,
+    dead_letter: DeadLetter,
+    drain_timeout: Duration,
+    request_timeout: Duration,
+    hedge_after: Option<Duration>,
+    max_in_flight_requests: usize,
+    topology_refresh_interval: Duration,
+    dedup_ttl: Option<Duration>,
+    record_ttl: Option<Duration>,
+    spill: Option<(PathBuf, u64)>,
+    cloudwatch: Option<CloudWatchConfig>,
+    client_target: ClientTarget,
 }
 
 impl PipelineBuilder {
     /// Creates a new producer pipeline
     pub fn new(region: String, stream: String) -> PipelineBuilder {
         PipelineBuilder {
+            target: Target::Kinesis,
+            name: None,
             region,
             stream,
+            streams: vec![],
+            route: None,
             endpoint: None,
-            local: false,
+            client_target: ClientTarget::Aws,
             rps_per_shard: 1500,
             bps_per_shard: 7 * BYTES_PER_MB as u64,
-            retry_backoff: Duration::from_secs(1),
+            adaptive_rate_limit: true,
+            strict_ordering: false,
+            queue_capacity: 1000,
+            overflow: Overflow::Block,
+            failure_policy: RetryPolicy::default(),
+            throughput_policy: RetryPolicy::default()
+                .initial_backoff(Duration::from_millis(500))
+                .max_backoff(Duration::from_secs(30)),
+            dead_letter: DeadLetter::Fail,
+            drain_timeout: Duration::from_secs(30),
+            request_timeout: Duration::from_secs(30),
+            hedge_after: None,
+            max_in_flight_requests: 64,
+            topology_refresh_interval: Duration::from_secs(60),
+            dedup_ttl: None,
+            record_ttl: None,
+            spill: None,
+            cloudwatch: None,
+            compression: Compression::None,
             aggregator_config: ReducerConfig {
                 max_records: 4294967295,
                 max_bytes: 51200,
@@ -79,9 +203,73 @@ impl PipelineBuilder {
         }
     }
 
+    /// Creates a new producer pipeline delivering to a Firehose delivery stream
+    ///
+    /// Records are neither aggregated nor partitioned, and so partition keys are ignored
+    pub fn firehose(region: String, delivery_stream: String) -> PipelineBuilder {
+        PipelineBuilder {
+            target: Target::Firehose,
+            rps_per_shard: 100_000,
+            bps_per_shard: BYTES_PER_MB as u64,
+            ..PipelineBuilder::new(region, delivery_stream)
+        }
+    }
+
+    /// Creates a new producer pipeline delivering to an SQS queue
+    ///
+    /// Record data must be valid UTF-8. For FIFO queues the partition key is used as the
+    /// message group id, and the queue must have content-based deduplication enabled
+    pub fn sqs(region: String, queue_url: String) -> PipelineBuilder {
+        PipelineBuilder {
+            target: Target::Sqs,
+            rps_per_shard: 3000,
+            bps_per_shard: 64 * BYTES_PER_MB as u64,
+            batch_config: ReducerConfig {
+                max_records: 10,
+                max_bytes: sqs::MAX_MESSAGE_BYTES,
+                max_wait: Duration::from_millis(500),
+            },
+            ..PipelineBuilder::new(region, queue_url)
+        }
+    }
+
+    /// Configures the name used to label this pipeline's metrics, defaults to the stream name
+    pub fn name(&mut self, name: String) -> &mut Self {
+        self.name = Some(name);
+        self
+    }
+
+    /// Registers an additional stream that records can be routed to by setting `RawRecord::stream`
+    ///
+    /// Each stream has its own topology, aggregation, batching and rate limits, but shares
+    /// the pipeline's worker, configuration and shutdown. Ignored by Firehose and SQS pipelines
+    pub fn add_stream(&mut self, stream: String) -> &mut Self {
+        self.streams.push(stream);
+        self
+    }
+
+    /// Routes records that don't set `RawRecord::stream` with `route`, which returns the
+    /// stream to send a record to, or None for the pipeline's stream
+    ///
+    /// Streams must be registered with `add_stream`, records routed to other streams are
+    /// failed with `Error::InvalidRecord`. Ignored by Firehose and SQS pipelines
+    pub fn route<F>(&mut self, route: F) -> &mut Self
+    where
+        F: Fn(&RawRecord) -> Option<String> + Send + Sync + 'static,
+    {
+        self.route = Some(Arc::new(route));
+        self
+    }
+
     /// Use local kinesalite endpoint
     pub fn local(&mut self) -> &mut Self {
-        self.local = true;
+        self.client_target = ClientTarget::Local;
+        self
+    }
+
+    /// Use a LocalStack container, at its default edge endpoint unless overridden
+    pub fn localstack(&mut self) -> &mut Self {
+        self.client_target = ClientTarget::LocalStack;
         self
     }
 
@@ -93,6 +281,11 @@ impl PipelineBuilder {
 
     /// Set the rate per shard rate limits
     ///
+    /// For Firehose and SQS pipelines this limits the delivery stream or queue as a whole
+    ///
+    /// Unless adaptive rate limiting is disabled these are the upper bounds of the rates,
+    /// which are lowered for shards that are being throttled
+    ///
     /// Note: Records larger than bytes per second will be dropped - set the aggregation size accordingly
     pub fn shard_rate_limit(
         &mut self,
@@ -104,9 +297,143 @@ impl PipelineBuilder {
         self
     }
 
-    /// Configures the backoff delay following a PutRecords error
-    pub fn retry_backoff(&mut self, backoff: Duration) -> &mut Self {
-        self.retry_backoff = backoff;
+    /// Configures how often the stream's shards are listed to detect resharding
+    ///
+    /// The topology is also refreshed whenever a record is delivered to an unexpected shard
+    pub fn topology_refresh_interval(&mut self, interval: Duration) -> &mut Self {
+        self.topology_refresh_interval = interval;
+        self
+    }
+
+    /// Configures whether the rate limit of a shard is reduced when it is throttled,
+    /// and then gradually increased back to the configured limit, defaults to true
+    pub fn adaptive_rate_limit(&mut self, enabled: bool) -> &mut Self {
+        self.adaptive_rate_limit = enabled;
+        self
+    }
+
+    /// Configures the number of records that can be queued for each stream before the
+    /// overflow policy applies, defaults to 1000
+    pub fn queue_capacity(&mut self, capacity: usize) -> &mut Self {
+        assert!(capacity > 0, "capacity must be greater than 0");
+        self.queue_capacity = capacity;
+        self
+    }
+
+    /// Configures what happens when a record is submitted to a full queue, defaults
+    /// to `Overflow::Block`
+    ///
+    /// Blocking suits records that must not be lost, such as billing events, whereas
+    /// dropping the oldest records suits log shipping where recent records matter most
+    pub fn overflow(&mut self, overflow: Overflow) -> &mut Self {
+        self.overflow = overflow;
+        self
+    }
+
+    /// Configures whether records with the same partition key are delivered in the order
+    /// they were submitted, defaults to false
+    ///
+    /// By default a record that is retried may be delivered after newer records with the
+    /// same partition key. With strict ordering only one record per partition key is in
+    /// flight at once, with newer records held back until it has been acknowledged. This
+    /// limits each partition key to one record per request round trip, and the throughput
+    /// of a partition key stalls whilst its oldest record is retrying
+    ///
+    /// This is ignored by Firehose and SQS pipelines
+    pub fn strict_ordering(&mut self, enabled: bool) -> &mut Self {
+        self.strict_ordering = enabled;
+        self
+    }
+
+    /// Configures the retry policy for records that failed due to an error
+    pub fn retry_policy(&mut self, policy: RetryPolicy) -> &mut Self {
+        self.failure_policy = policy;
+        self
+    }
+
+    /// Configures the retry policy for records that were throttled
+    pub fn throughput_retry_policy(&mut self, policy: RetryPolicy) -> &mut Self {
+        self.throughput_policy = policy;
+        self
+    }
+
+    /// Configures where records that can never be delivered are sent, as they have
+    /// exhausted their retries or are too large for the destination
+    ///
+    /// Defaults to `DeadLetter::Fail`, only failing their acks
+    pub fn dead_letter(&mut self, dead_letter: DeadLetter) -> &mut Self {
+        self.dead_letter = dead_letter;
+        self
+    }
+
+    /// Configures how long shutdown waits for submitted records to be acknowledged
+    pub fn drain_timeout(&mut self, drain_timeout: Duration) -> &mut Self {
+        self.drain_timeout = drain_timeout;
+        self
+    }
+
+    /// Configures how long a request to the destination may take before it is cancelled
+    /// and its records are retried
+    pub fn request_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.request_timeout = timeout;
+        self
+    }
+
+    /// Sends a second, identical request if a request to the destination has not completed
+    /// after `delay`, using whichever response arrives first
+    ///
+    /// This reduces tail latency at the cost of additional requests, and records may be
+    /// delivered twice if both requests succeed
+    pub fn hedge_after(&mut self, delay: Duration) -> &mut Self {
+        self.hedge_after = Some(delay);
+        self
+    }
+
+    /// Configures the maximum number of requests to the destination in flight at once,
+    /// for each stream of a Kinesis pipeline
+    ///
+    /// Once reached, batches wait for a request to complete, so that a slow destination
+    /// causes backpressure rather than unbounded growth in memory. Defaults to 64
+    pub fn max_in_flight_requests(&mut self, max_in_flight: usize) -> &mut Self {
+        assert!(
+            max_in_flight > 0,
+            "max_in_flight_requests must be at least 1"
+        );
+        self.max_in_flight_requests = max_in_flight;
+        self
+    }
+
+    /// Configures the default time-to-live of records, after which they are failed with
+    /// `Error::Expired` rather than retried
+    ///
+    /// This can be overridden for individual records by `RawRecord::ttl_ms`, and by
+    /// default records are retried until their retry policy is exhausted
+    pub fn record_ttl(&mut self, ttl: Duration) -> &mut Self {
+        self.record_ttl = Some(ttl);
+        self
+    }
+
+    /// Enables deduplication of records by `RawRecord::idempotency_id`
+    ///
+    /// A record is not sent if a record with the same id is in flight, or was delivered
+    /// within `ttl`, instead resolving with the result of that record. This guards
+    /// against duplicates from upstreams that retry submissions, but not against
+    /// duplicates caused by retries of PutRecords itself
+    pub fn deduplicate(&mut self, ttl: Duration) -> &mut Self {
+        self.dedup_ttl = Some(ttl);
+        self
+    }
+
+    /// Configures a disk-backed buffer between the producer and the pipeline
+    ///
+    /// Submitted records are written to segment files in `dir` before being delivered,
+    /// allowing them to survive process restarts and outages that exceed the in-memory
+    /// buffering. Any records remaining in `dir` are replayed when the pipeline is built.
+    /// Once `max_bytes` are buffered, submissions fail with `Error::BufferFull`
+    ///
+    /// Records may be delivered more than once if the process exits before they are acknowledged
+    pub fn spill(&mut self, dir: PathBuf, max_bytes: u64) -> &mut Self {
+        self.spill = Some((dir, max_bytes));
         self
     }
 
@@ -121,6 +448,8 @@ impl PipelineBuilder {
     }
 
     /// Configures the how the pipeline should aggregate records for the same shard together
+    ///
+    /// Ignored by Firehose and SQS pipelines
     pub fn aggregate(
         &mut self,
         max_bytes: usize,
@@ -135,31 +464,215 @@ impl PipelineBuilder {
         self
     }
 
+    /// Configures compression of aggregated records
+    ///
+    /// Compressed records can only be read by this crate's deaggregator, and this is
+    /// ignored by Firehose and SQS pipelines
+    pub fn compression(&mut self, compression: Compression) -> &mut Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Publishes the pipeline's metrics to CloudWatch under `namespace` every `interval`,
+    /// in addition to the prometheus registry
+    ///
+    /// Counts are published as the change since the last publish, and buffering time as
+    /// the distribution of the time records spent in the pipeline before being sent
+    pub fn cloudwatch_metrics(
+        &mut self,
+        namespace: String,
+        granularity: MetricsGranularity,
+        interval: Duration,
+    ) -> &mut Self {
+        self.cloudwatch = Some(CloudWatchConfig {
+            namespace,
+            granularity,
+            interval,
+        });
+        self
+    }
+
+    /// Returns a worker publishing `metrics` to CloudWatch, if configured, until `finished`
+    fn metrics_publisher(
+        &self,
+        metrics: PipelineMetrics,
+        finished: shutdown::Receiver,
+    ) -> BoxFuture<'static, ()> {
+        match &self.cloudwatch {
+            Some(config) => {
+                let client = cloudwatch_client(
+                    self.region.clone(),
+                    self.endpoint.clone(),
+                    self.client_target,
+                );
+                cloudwatch::publisher(client, config.clone(), metrics, finished)
+            }
+            None => futures::future::ready(()).boxed(),
+        }
+    }
+
+    fn request_policy(&self) -> RequestPolicy {
+        RequestPolicy {
+            timeout: self.request_timeout,
+            hedge_after: self.hedge_after,
+            max_in_flight: self.max_in_flight_requests,
+        }
+    }
+
+    fn metrics(&self) -> PipelineMetrics {
+        PipelineMetrics::new(self.name.clone().unwrap_or_else(|| self.stream.clone()))
+    }
+
     pub fn build(self) -> (Producer, PipelineHandler) {
-        let client = kinesis_client(self.region, self.endpoint, self.local);
+        match self.target {
+            Target::Kinesis => self.build_kinesis(),
+            Target::Firehose => self.build_firehose(),
+            Target::Sqs => self.build_sqs(),
+        }
+    }
 
-        let (sender, receiver) = mpsc::channel(1000);
+    fn build_kinesis(self) -> (Producer, PipelineHandler) {
+        let client = kinesis_client(
+            self.region.clone(),
+            self.endpoint.clone(),
+            self.client_target,
+        );
+        self.build_kinesis_with(client)
+    }
+
+    fn build_kinesis_with<C: KinesisApi>(self, client: C) -> (Producer, PipelineHandler) {
+        let metrics = self.metrics();
+
+        let pending = Pending::new();
         let (shutdown_tx, shutdown_rx) = shutdown::channel();
 
-        let (topology, topology_worker) =
-            TopologyService::new(client.clone(), self.stream.clone(), shutdown_rx.clone());
+        let mut senders = HashMap::new();
+        let mut streams = HashMap::new();
+        let mut workers = Vec::new();
+        for stream in std::iter::once(&self.stream).chain(&self.streams) {
+            let (sender, stats, worker) = self.build_stream(
+                stream.clone(),
+                client.clone(),
+                metrics.clone(),
+                pending.clone(),
+                shutdown_rx.clone(),
+            );
+            senders.insert(stream.clone(), sender);
+            streams.insert(stream.clone(), stats);
+            workers.push(worker);
+        }
+
+        let topologies = streams
+            .iter()
+            .filter_map(|(stream, stats)| Some((stream.clone(), stats.topology.clone()?)))
+            .collect();
+        let router =
+            Router::new(self.stream.clone(), senders, topologies).with_route(self.route.clone());
+        let (finished_tx, finished_rx) = shutdown::channel();
+        let publisher = self.metrics_publisher(metrics.clone(), finished_rx);
+        let (producer, spill_worker) = producer(
+            self.spill,
+            self.dedup_ttl,
+            self.record_ttl,
+            router,
+            metrics.clone(),
+            pending,
+            shutdown_rx,
+        );
+
+        let (alive_guard, alive) = AliveGuard::new();
+        let worker_handle = tokio::spawn(async move {
+            let _alive = alive_guard;
+            let pipeline = async {
+                tokio::join!(futures::future::join_all(workers), spill_worker);
+                finished_tx.shutdown();
+            };
+            tokio::join!(pipeline, publisher);
+            info!("pipeline worker shutdown")
+        });
+
+        (
+            producer,
+            PipelineHandler {
+                worker_handle,
+                worker_shutdown: shutdown_tx,
+                metrics,
+                streams,
+                alive,
+            },
+        )
+    }
+
+    /// Builds the worker delivering records to a single Kinesis stream, returning it
+    /// along with the sender to submit records to it and the source of its statistics
+    fn build_stream<C: KinesisApi>(
+        &self,
+        stream: String,
+        client: C,
+        metrics: PipelineMetrics,
+        pending: Pending,
+        shutdown_rx: shutdown::Receiver,
+    ) -> (queue::Sender, StreamStatsSource, BoxFuture<'static, ()>) {
+        let rates = AdaptiveRates::new(
+            self.rps_per_shard,
+            self.bps_per_shard,
+            self.adaptive_rate_limit,
+        );
+        let (sender, receiver) = queue::channel(self.queue_capacity, self.overflow);
+        let (finished_tx, finished_rx) = shutdown::channel();
+
+        let (topology, topology_worker) = TopologyService::new(
+            client.clone(),
+            stream.clone(),
+            self.topology_refresh_interval,
+            finished_rx.clone(),
+        );
 
         let (retry, retry_worker) = ErrorHandler::new(
             sender.clone(),
-            topology.clone(),
-            self.retry_backoff,
-            shutdown_rx.clone(),
+            Some(topology.clone()),
+            self.failure_policy.clone(),
+            self.throughput_policy.clone(),
+            self.dead_letter.clone(),
+            metrics.for_stream(&stream),
+            rates.clone(),
+            finished_rx.clone(),
         );
-        let kinesis_sink = KinesisSink::new(client, self.stream, retry);
+        let stats = StreamStatsSource {
+            queue: sender.clone(),
+            retrying: retry.retrying(),
+            last_success: retry.last_success(),
+            rates: rates.clone(),
+            topology: Some(topology.clone()),
+        };
+        let rates_worker = rates.clone().worker(Some(topology.clone()), finished_rx);
+        let sequencer = if self.strict_ordering {
+            Some(Sequencer::new(sender.clone()))
+        } else {
+            None
+        };
+        let rejected = retry.clone();
+        let kinesis_sink = KinesisSink::new(client, stream.clone(), retry, self.request_policy());
 
-        let rps_per_shard = self.rps_per_shard;
-        let bps_per_shard = self.bps_per_shard;
         let batch_config = self.batch_config;
         let aggregator_config = self.aggregator_config;
+        let compression = self.compression;
+        let drain = drain::drain(shutdown_rx, pending, self.drain_timeout);
 
-        let worker_handle = tokio::spawn(Box::pin(async move {
+        let worker = async move {
             let fut1 = receiver
-                .take_until(shutdown_rx)
+                .take_until(drain)
+                .filter_map(move |record| {
+                    if let Err(e) = sink::validate(&record) {
+                        rejected.reject(record, e);
+                        return futures::future::ready(None);
+                    }
+                    let record = match &sequencer {
+                        Some(sequencer) => sequencer.admit(record),
+                        None => Some(record),
+                    };
+                    futures::future::ready(record)
+                })
                 .then(|mut record| {
                     let mut topology = topology.clone();
                     async move {
@@ -173,49 +686,408 @@ impl PipelineBuilder {
                         RecordAggregator::new(
                             aggregator_config.max_bytes,
                             aggregator_config.max_records,
+                            compression,
                         )
                     },
                     aggregator_config.max_wait,
                 )
-                .partition_limit(
-                    || RecordLimiter::new(rps_per_shard, bps_per_shard),
+                .partition_limit_keyed(
+                    move |shard_id| rates.limiter(Some(*shard_id)),
                     Duration::from_secs(1),
                 )
                 .batched(
-                    RecordBatcher::new(batch_config.max_bytes, batch_config.max_bytes),
+                    RecordBatcher::new(batch_config.max_bytes, batch_config.max_records),
                     batch_config.max_wait,
                 )
                 .map(Ok::<_, ()>)
-                .forward(kinesis_sink);
+                .forward(kinesis_sink)
+                .inspect(|_| finished_tx.shutdown());
 
-            let (worker, _, _) = tokio::join!(fut1, topology_worker, retry_worker);
+            let (worker, _, _, _) = tokio::join!(fut1, topology_worker, retry_worker, rates_worker);
+            worker.unwrap();
+
+            info!(%stream, "stream worker shutdown")
+        };
+
+        (sender, stats, worker.boxed())
+    }
+
+    fn build_firehose(self) -> (Producer, PipelineHandler) {
+        let client = firehose_client(
+            self.region.clone(),
+            self.endpoint.clone(),
+            self.client_target,
+        );
+        let delivery_stream = self.stream.clone();
+        let request = self.request_policy();
+
+        self.build_unpartitioned(firehose::validate, move |retry| {
+            FirehoseSink::new(client, delivery_stream, retry, request)
+        })
+    }
+
+    fn build_sqs(self) -> (Producer, PipelineHandler) {
+        let client = sqs_client(
+            self.region.clone(),
+            self.endpoint.clone(),
+            self.client_target,
+        );
+        let queue_url = self.stream.clone();
+        let request = self.request_policy();
+
+        self.build_unpartitioned(sqs::validate, move |retry| {
+            SqsSink::new(client, queue_url, retry, request)
+        })
+    }
+
+    /// Builds a pipeline that rate limits and batches records without regard to partition key
+    fn build_unpartitioned<F, S>(
+        self,
+        validate: fn(&Record) -> Result<(), producer::Error>,
+        sink_factory: F,
+    ) -> (Producer, PipelineHandler)
+    where
+        F: FnOnce(ErrorHandler) -> S,
+        S: Sink<Vec<Record>, Error = ()> + Send + 'static,
+    {
+        let metrics = self.metrics();
+        let pending = Pending::new();
+        let rates = AdaptiveRates::new(
+            self.rps_per_shard,
+            self.bps_per_shard,
+            self.adaptive_rate_limit,
+        );
+        let (sender, receiver) = queue::channel(self.queue_capacity, self.overflow);
+        let (shutdown_tx, shutdown_rx) = shutdown::channel();
+        let (finished_tx, finished_rx) = shutdown::channel();
+        let publisher = self.metrics_publisher(metrics.clone(), finished_rx.clone());
+
+        let (retry, retry_worker) = ErrorHandler::new(
+            sender.clone(),
+            None,
+            self.failure_policy,
+            self.throughput_policy,
+            self.dead_letter,
+            metrics.for_stream(&self.stream),
+            rates.clone(),
+            finished_rx.clone(),
+        );
+        let mut streams = HashMap::new();
+        streams.insert(
+            self.stream.clone(),
+            StreamStatsSource {
+                queue: sender.clone(),
+                retrying: retry.retrying(),
+                last_success: retry.last_success(),
+                rates: rates.clone(),
+                topology: None,
+            },
+        );
+        let rates_worker = rates.clone().worker(None, finished_rx);
+        let rejected = retry.clone();
+        let sink = sink_factory(retry);
+
+        let batch_config = self.batch_config;
+        let drain = drain::drain(shutdown_rx.clone(), pending.clone(), self.drain_timeout);
+        let mut senders = HashMap::new();
+        senders.insert(self.stream.clone(), sender);
+        let router = Router::new(self.stream, senders, HashMap::new());
+
+        let (producer, spill_worker) = producer(
+            self.spill,
+            self.dedup_ttl,
+            self.record_ttl,
+            router,
+            metrics.clone(),
+            pending,
+            shutdown_rx,
+        );
+
+        let (alive_guard, alive) = AliveGuard::new();
+        let worker_handle = tokio::spawn(Box::pin(async move {
+            let _alive = alive_guard;
+            let fut1 = receiver
+                .take_until(drain)
+                .filter_map(move |record| {
+                    if let Err(e) = validate(&record) {
+                        rejected.reject(record, e);
+                        return futures::future::ready(None);
+                    }
+                    futures::future::ready(Some(record))
+                })
+                .limit(rates.limiter(None))
+                .batched(
+                    RecordBatcher::new(batch_config.max_bytes, batch_config.max_records),
+                    batch_config.max_wait,
+                )
+                .map(Ok::<_, ()>)
+                .forward(sink)
+                .inspect(|_| finished_tx.shutdown());
+
+            let (worker, _, _, _, _) =
+                tokio::join!(fut1, retry_worker, spill_worker, rates_worker, publisher);
             worker.unwrap();
 
             info!("pipeline worker shutdown")
         }));
 
         (
-            Producer::new(sender),
+            producer,
             PipelineHandler {
                 worker_handle,
                 worker_shutdown: shutdown_tx,
+                metrics,
+                streams,
+                alive,
             },
         )
     }
 }
 
-fn kinesis_client(region: String, endpoint: Option<String>, local: bool) -> KinesisClient {
-    let region = parse_region(region, endpoint);
+/// Creates the producer for a pipeline, along with a worker forwarding records from
+/// the spill buffer into the pipeline if one is configured
+fn producer(
+    spill: Option<(PathBuf, u64)>,
+    dedup_ttl: Option<Duration>,
+    ttl: Option<Duration>,
+    router: Router,
+    metrics: PipelineMetrics,
+    pending: Pending,
+    shutdown: shutdown::Receiver,
+) -> (Producer, BoxFuture<'static, ()>) {
+    let dedup = dedup_ttl.map(Dedup::new);
+    match spill {
+        Some((dir, max_bytes)) => {
+            let spill = Spill::open(dir, max_bytes).expect("failed to open spill buffer");
+            let worker = spill::forward(
+                spill.clone(),
+                router.clone(),
+                pending.clone(),
+                shutdown.clone(),
+            );
+
+            (
+                Producer::new(router, metrics, pending, shutdown, Some(spill), dedup, ttl),
+                worker.boxed(),
+            )
+        }
+        None => (
+            Producer::new(router, metrics, pending, shutdown, None, dedup, ttl),
+            futures::future::ready(()).boxed(),
+        ),
+    }
+}
+
+fn kinesis_client(region: String, endpoint: Option<String>, target: ClientTarget) -> KinesisClient {
+    let (region, credentials) = client_config(region, endpoint, target);
     let dispatcher =
         rusoto_core::request::HttpClient::new().expect("failed to create request dispatcher");
 
-    if local {
-        return KinesisClient::new_with(
-            dispatcher,
-            StaticProvider::new_minimal("local".to_string(), "development".to_string()),
-            region,
-        );
+    KinesisClient::new_with(dispatcher, credentials, region)
+}
+
+fn firehose_client(
+    region: String,
+    endpoint: Option<String>,
+    target: ClientTarget,
+) -> KinesisFirehoseClient {
+    let (region, credentials) = client_config(region, endpoint, target);
+    let dispatcher =
+        rusoto_core::request::HttpClient::new().expect("failed to create request dispatcher");
+
+    KinesisFirehoseClient::new_with(dispatcher, credentials, region)
+}
+
+fn cloudwatch_client(
+    region: String,
+    endpoint: Option<String>,
+    target: ClientTarget,
+) -> CloudWatchClient {
+    let (region, credentials) = client_config(region, endpoint, target);
+    let dispatcher =
+        rusoto_core::request::HttpClient::new().expect("failed to create request dispatcher");
+
+    CloudWatchClient::new_with(dispatcher, credentials, region)
+}
+
+fn sqs_client(region: String, endpoint: Option<String>, target: ClientTarget) -> SqsClient {
+    let (region, credentials) = client_config(region, endpoint, target);
+    let dispatcher =
+        rusoto_core::request::HttpClient::new().expect("failed to create request dispatcher");
+
+    SqsClient::new_with(dispatcher, credentials, region)
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use crate::deaggregator::deaggregate;
+    use crate::mock::MockKinesis;
+    use crate::producer::RawRecord;
+
+    use super::*;
+
+    fn raw(partition_key: &str, data: String) -> RawRecord {
+        RawRecord {
+            partition_key: partition_key.to_string(),
+            data: Bytes::from(data),
+            explicit_hash_key: None,
+            stream: None,
+            idempotency_id: None,
+            ttl_ms: None,
+        }
+    }
+
+    fn builder(name: &str) -> PipelineBuilder {
+        let mut builder = PipelineBuilder::new("us-east-1".to_string(), "test".to_string());
+        builder
+            .name(name.to_string())
+            .aggregate(51200, 100, Duration::from_millis(10))
+            .batch(BYTES_PER_MB, 500, Duration::from_millis(10))
+            .throughput_retry_policy(RetryPolicy::fixed(Duration::from_millis(10)));
+        builder
+    }
+
+    /// Returns the shard, partition key and data of the user records received by `mock`
+    fn received(mock: &MockKinesis) -> Vec<(String, String, Bytes)> {
+        mock.received()
+            .into_iter()
+            .flat_map(|(shard_id, entry)| {
+                deaggregate(entry.partition_key, String::new(), entry.data)
+                    .unwrap()
+                    .into_iter()
+                    .map(move |x| (shard_id.clone(), x.partition_key, x.data))
+            })
+            .collect()
     }
 
-    KinesisClient::new_with(dispatcher, CustomChainProvider::new(), region)
+    #[tokio::test]
+    async fn test_pipeline() {
+        let mock = MockKinesis::new(2);
+        mock.throttle(1);
+
+        let (mut producer, handler) = builder("test_pipeline").build_kinesis_with(mock.clone());
+
+        let records = (0..20).map(|x| raw(&format!("key-{}", x), x.to_string()));
+        let results = producer.submit(records).await;
+        let acks: Vec<_> = results.into_iter().map(|x| x.unwrap()).collect();
+
+        let metrics = handler.metrics();
+        assert_eq!(metrics.throttled.values().sum::<u64>(), 1);
+        assert!(metrics.retried > 0);
+
+        let stats = handler.stats();
+        assert!(stats.alive);
+        let stream = &stats.streams["test"];
+        assert!(stream.since_success.is_some());
+        assert_eq!(stream.topology.as_ref().unwrap().open_shards, 2);
+        handler.shutdown().await.unwrap();
+
+        // Records are aggregated into at most one record per shard per request
+        let requests = mock.requests();
+        assert!(requests.iter().all(|x| x.records.len() <= 2));
+
+        let received = received(&mock);
+        assert_eq!(received.len(), 20);
+        for (idx, ack) in acks.iter().enumerate() {
+            let key = format!("key-{}", idx);
+            let (shard_id, _, data) = received.iter().find(|x| x.1 == key).unwrap();
+            assert_eq!(data.as_ref(), idx.to_string().as_bytes());
+            assert_eq!(&ack.shard_id.unwrap().to_string(), shard_id);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_batch_max_records() {
+        let mock = MockKinesis::new(1);
+
+        // Records aren't aggregated, and batches are only flushed by reaching their
+        // maximum number of records, or on shutdown
+        let mut builder = builder("test_batch_max_records");
+        builder
+            .aggregate(51200, 1, Duration::from_millis(10))
+            .batch(BYTES_PER_MB, 2, Duration::from_secs(3600))
+            .drain_timeout(Duration::from_millis(100));
+        let (mut producer, handler) = builder.build_kinesis_with(mock.clone());
+
+        let records = (0..5).map(|x| raw("key", x.to_string()));
+        let submitted = tokio::spawn(async move { producer.submit(records).await });
+
+        let flushed = async {
+            while mock.requests().len() < 2 {
+                tokio::time::delay_for(Duration::from_millis(10)).await
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(5), flushed)
+            .await
+            .expect("batches not flushed at max_records");
+
+        handler.shutdown().await.unwrap();
+        let results = submitted.await.unwrap();
+        assert!(results[..4].iter().all(|x| x.is_ok()));
+
+        let requests = mock.requests();
+        assert_eq!(requests[0].records.len(), 2);
+        assert_eq!(requests[1].records.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_strict_ordering() {
+        let mock = MockKinesis::new(1);
+        mock.fail_requests(1);
+        mock.throttle(1);
+
+        let mut builder = builder("test_strict_ordering");
+        builder
+            .strict_ordering(true)
+            .retry_policy(RetryPolicy::fixed(Duration::from_millis(10)));
+        let (mut producer, handler) = builder.build_kinesis_with(mock.clone());
+
+        let records = (0..5).map(|x| raw("key", x.to_string()));
+        for result in producer.submit(records).await {
+            result.unwrap();
+        }
+        handler.shutdown().await.unwrap();
+
+        // Each record is sent only once its predecessor is delivered
+        assert!(mock.requests().len() >= 7);
+        let data: Vec<_> = received(&mock).into_iter().map(|x| x.2).collect();
+        let expected: Vec<_> = (0..5).map(|x| Bytes::from(x.to_string())).collect();
+        assert_eq!(data, expected);
+    }
+
+    #[tokio::test]
+    async fn test_submit_to_shard() {
+        let mock = MockKinesis::new(4);
+        let (mut producer, handler) =
+            builder("test_submit_to_shard").build_kinesis_with(mock.clone());
+
+        let shards = producer.shards(None).await.unwrap();
+        assert_eq!(shards.len(), 4);
+        assert!(matches!(
+            producer.shards(Some("unknown")).await,
+            Err(producer::Error::InvalidRecord)
+        ));
+
+        let target = shards[2];
+        let records = (0..10).map(|x| raw(&format!("key-{}", x), x.to_string()));
+        for result in producer.submit_to_shard(target, records).await {
+            assert_eq!(result.unwrap().shard_id, Some(target));
+        }
+
+        // Shards that are not open fail without affecting other records
+        let closed = "shardId-000000000009".parse().unwrap();
+        let mut records = vec![raw("a", "a".to_string()), raw("b", "b".to_string())];
+        records[1].stream = Some("unknown".to_string());
+        let results = producer.submit_to_shard(closed, records.into_iter()).await;
+        assert!(matches!(results[0], Err(producer::Error::UnknownShard)));
+        assert!(matches!(results[1], Err(producer::Error::UnknownShard)));
+
+        handler.shutdown().await.unwrap();
+
+        let received = received(&mock);
+        assert_eq!(received.len(), 10);
+        assert!(received.iter().all(|x| x.0 == target.to_string()));
+    }
 }
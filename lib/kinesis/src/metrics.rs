@@ -0,0 +1,420 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use lazy_static::lazy_static;
+use telemetry::prometheus::core::{Collector, Metric};
+use telemetry::prometheus::{
+    exponential_buckets, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec,
+    IntGauge, IntGaugeVec, Opts,
+};
+
+use tokio::time::Instant;
+
+use crate::producer::{self, Record};
+use crate::topology::ShardId;
+
+fn counter(name: &str, help: &str, labels: &[&str]) -> IntCounterVec {
+    let counter = IntCounterVec::new(Opts::new(name, help), labels).unwrap();
+    telemetry::prometheus::register(Box::new(counter.clone())).unwrap();
+    counter
+}
+
+fn gauge(name: &str, help: &str, labels: &[&str]) -> IntGaugeVec {
+    let gauge = IntGaugeVec::new(Opts::new(name, help), labels).unwrap();
+    telemetry::prometheus::register(Box::new(gauge.clone())).unwrap();
+    gauge
+}
+
+fn histogram(name: &str, help: &str, buckets: Vec<f64>) -> HistogramVec {
+    let opts = HistogramOpts::new(name, help).buckets(buckets);
+    let histogram = HistogramVec::new(opts, &["pipeline"]).unwrap();
+    telemetry::prometheus::register(Box::new(histogram.clone())).unwrap();
+    histogram
+}
+
+lazy_static! {
+    static ref ENQUEUED: IntCounterVec = counter(
+        "kinesis_producer_enqueued_total",
+        "Records submitted to the pipeline",
+        &["pipeline"]
+    );
+    static ref SENT: IntCounterVec = counter(
+        "kinesis_producer_sent_total",
+        "Records sent to the destination, after aggregation",
+        &["pipeline"]
+    );
+    static ref SENT_BYTES: IntCounterVec = counter(
+        "kinesis_producer_sent_bytes_total",
+        "Bytes of record data sent to the destination, including retries",
+        &["pipeline"]
+    );
+    static ref ACKED: IntCounterVec = counter(
+        "kinesis_producer_acked_total",
+        "Records successfully delivered",
+        &["pipeline"]
+    );
+    static ref RETRIED: IntCounterVec = counter(
+        "kinesis_producer_retried_total",
+        "Records scheduled for retry",
+        &["pipeline"]
+    );
+    static ref DROPPED: IntCounterVec = counter(
+        "kinesis_producer_dropped_total",
+        "Records that failed to be delivered",
+        &["pipeline"]
+    );
+    static ref DEDUPLICATED: IntCounterVec = counter(
+        "kinesis_producer_deduplicated_total",
+        "Records not sent as a record with the same idempotency id was in flight or delivered",
+        &["pipeline"]
+    );
+    static ref TIMED_OUT: IntCounterVec = counter(
+        "kinesis_producer_timed_out_total",
+        "Requests to the destination cancelled after exceeding the request timeout",
+        &["pipeline"]
+    );
+    static ref HEDGED: IntCounterVec = counter(
+        "kinesis_producer_hedged_total",
+        "Requests to the destination hedged with a second request",
+        &["pipeline"]
+    );
+    static ref THROTTLED: IntCounterVec = counter(
+        "kinesis_producer_throttled_total",
+        "Records rejected due to exceeding throughput limits",
+        &["pipeline", "stream", "shard"]
+    );
+    static ref DEAD_LETTERED: IntCounterVec = counter(
+        "kinesis_producer_dead_lettered_total",
+        "Records that could never be delivered, sent to the dead letter",
+        &["pipeline", "reason"]
+    );
+    static ref IN_FLIGHT: IntGaugeVec = gauge(
+        "kinesis_producer_in_flight_requests",
+        "Outstanding requests to the destination",
+        &["pipeline"]
+    );
+    static ref TOPOLOGY_GENERATION: IntGaugeVec = gauge(
+        "kinesis_producer_topology_generation",
+        "The generation of the stream topology, incremented each time it changes",
+        &["stream"]
+    );
+    static ref OPEN_SHARDS: IntGaugeVec = gauge(
+        "kinesis_producer_open_shards",
+        "The number of open shards in the stream",
+        &["stream"]
+    );
+    static ref BATCH_RECORDS: HistogramVec = histogram(
+        "kinesis_producer_batch_records",
+        "Records per request",
+        exponential_buckets(1.0, 2.0, 10).unwrap()
+    );
+    static ref BATCH_BYTES: HistogramVec = histogram(
+        "kinesis_producer_batch_bytes",
+        "Bytes per request",
+        exponential_buckets(1024.0, 4.0, 8).unwrap()
+    );
+    static ref ACK_SECONDS: HistogramVec = histogram(
+        "kinesis_producer_ack_seconds",
+        "Time between a record being submitted and acknowledged, successfully or not",
+        exponential_buckets(0.001, 2.0, 16).unwrap()
+    );
+    static ref BUFFERING_SECONDS: HistogramVec = histogram(
+        "kinesis_producer_buffering_seconds",
+        "Time between a record being submitted and sent to the destination",
+        exponential_buckets(0.001, 2.0, 16).unwrap()
+    );
+}
+
+/// Records a change to the topology of `stream`
+pub(crate) fn topology_updated(stream: &str, generation: u64, open_shards: usize) {
+    TOPOLOGY_GENERATION
+        .with_label_values(&[stream])
+        .set(generation as i64);
+    OPEN_SHARDS
+        .with_label_values(&[stream])
+        .set(open_shards as i64);
+}
+
+/// A point-in-time snapshot of a pipeline's metrics
+#[derive(Debug, Clone, Default)]
+pub struct MetricsSnapshot {
+    pub enqueued: u64,
+    pub sent: u64,
+    pub sent_bytes: u64,
+    pub acked: u64,
+    pub retried: u64,
+    pub dropped: u64,
+    pub deduplicated: u64,
+    pub timed_out: u64,
+    pub hedged: u64,
+    /// Throttled records by stream and shard, unpartitioned pipelines report
+    /// under the shard "none"
+    pub throttled: HashMap<(String, String), u64>,
+    /// Dead-lettered records by reason, one of "retries_exhausted", "too_large" or "invalid"
+    pub dead_lettered: HashMap<String, u64>,
+    pub in_flight: i64,
+}
+
+/// The metrics of a single pipeline, labelled by pipeline name
+#[derive(Clone)]
+pub(crate) struct PipelineMetrics {
+    name: String,
+    /// The stream throttled records are attributed to, see `PipelineMetrics::for_stream`
+    stream: String,
+    enqueued: IntCounter,
+    sent: IntCounter,
+    sent_bytes: IntCounter,
+    acked: IntCounter,
+    retried: IntCounter,
+    dropped: IntCounter,
+    deduplicated: IntCounter,
+    timed_out: IntCounter,
+    hedged: IntCounter,
+    in_flight: IntGauge,
+    batch_records: Histogram,
+    batch_bytes: Histogram,
+    buffering_seconds: Histogram,
+    ack_seconds: Histogram,
+}
+
+impl PipelineMetrics {
+    pub fn new(name: String) -> PipelineMetrics {
+        let labels = [name.as_str()];
+        PipelineMetrics {
+            enqueued: ENQUEUED.with_label_values(&labels),
+            sent: SENT.with_label_values(&labels),
+            sent_bytes: SENT_BYTES.with_label_values(&labels),
+            acked: ACKED.with_label_values(&labels),
+            retried: RETRIED.with_label_values(&labels),
+            dropped: DROPPED.with_label_values(&labels),
+            deduplicated: DEDUPLICATED.with_label_values(&labels),
+            timed_out: TIMED_OUT.with_label_values(&labels),
+            hedged: HEDGED.with_label_values(&labels),
+            in_flight: IN_FLIGHT.with_label_values(&labels),
+            batch_records: BATCH_RECORDS.with_label_values(&labels),
+            batch_bytes: BATCH_BYTES.with_label_values(&labels),
+            buffering_seconds: BUFFERING_SECONDS.with_label_values(&labels),
+            ack_seconds: ACK_SECONDS.with_label_values(&labels),
+            name,
+            stream: "none".to_string(),
+        }
+    }
+
+    /// Returns a copy of these metrics that attributes throttled records to `stream`
+    pub fn for_stream(&self, stream: &str) -> PipelineMetrics {
+        PipelineMetrics {
+            stream: stream.to_string(),
+            ..self.clone()
+        }
+    }
+
+    pub fn enqueued(&self) {
+        self.enqueued.inc()
+    }
+
+    pub fn acked(&self) {
+        self.acked.inc()
+    }
+
+    pub fn dropped(&self) {
+        self.dropped.inc()
+    }
+
+    pub fn deduplicated(&self) {
+        self.deduplicated.inc()
+    }
+
+    pub fn timed_out(&self) {
+        self.timed_out.inc()
+    }
+
+    pub fn hedged(&self) {
+        self.hedged.inc()
+    }
+
+    pub fn retried(&self) {
+        self.retried.inc()
+    }
+
+    pub fn throttled(&self, shard_id: Option<ShardId>) {
+        let shard = shard_id
+            .map(|x| x.to_string())
+            .unwrap_or_else(|| "none".to_string());
+
+        THROTTLED
+            .with_label_values(&[self.name.as_str(), self.stream.as_str(), shard.as_str()])
+            .inc()
+    }
+
+    pub fn dead_lettered(&self, error: &producer::Error) {
+        let reason = match error {
+            producer::Error::RetriesExhausted => "retries_exhausted",
+            producer::Error::RecordTooLarge => "too_large",
+            _ => "invalid",
+        };
+
+        DEAD_LETTERED
+            .with_label_values(&[self.name.as_str(), reason])
+            .inc()
+    }
+
+    /// Records a batch being sent, returning a guard tracking the in-flight request
+    pub fn sent(&self, batch: &[Record]) -> InFlightGuard {
+        let bytes: usize = batch.iter().map(|x| x.len()).sum();
+
+        self.sent.inc_by(batch.len() as i64);
+        self.batch_records.observe(batch.len() as f64);
+        self.sent_bytes.inc_by(bytes as i64);
+        self.batch_bytes.observe(bytes as f64);
+        self.in_flight.inc();
+
+        let now = Instant::now();
+        for record in batch {
+            if record.children.is_empty() {
+                self.buffered(now - record.enqueued)
+            }
+            for child in &record.children {
+                self.buffered(now - child.enqueued)
+            }
+        }
+
+        InFlightGuard(self.in_flight.clone())
+    }
+
+    /// Records the acknowledgement of a record submitted at `submitted`
+    pub fn ack_latency(&self, submitted: Instant) {
+        self.ack_seconds.observe(submitted.elapsed().as_secs_f64())
+    }
+
+    fn buffered(&self, duration: Duration) {
+        self.buffering_seconds.observe(duration.as_secs_f64())
+    }
+
+    /// Returns the upper bound in seconds and cumulative count of each buffering time
+    /// bucket, ending with an unbounded bucket
+    pub fn buffering_buckets(&self) -> Vec<(f64, u64)> {
+        let metric = self.buffering_seconds.metric();
+        let histogram = metric.get_histogram();
+        histogram
+            .get_bucket()
+            .iter()
+            .map(|x| (x.get_upper_bound(), x.get_cumulative_count()))
+            .chain(std::iter::once((
+                f64::INFINITY,
+                histogram.get_sample_count(),
+            )))
+            .collect()
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn in_flight(&self) -> i64 {
+        self.in_flight.get()
+    }
+
+    /// Returns the value of each counter of `vec` for this pipeline, along with the
+    /// values of its `labels`
+    fn counters(&self, vec: &IntCounterVec, labels: &[&str]) -> Vec<(Vec<String>, u64)> {
+        let mut values = Vec::new();
+        for family in vec.collect() {
+            for metric in family.get_metric() {
+                let pairs = metric.get_label();
+                let label = |name: &str| {
+                    pairs
+                        .iter()
+                        .find(|x| x.get_name() == name)
+                        .map(|x| x.get_value().to_string())
+                };
+
+                if label("pipeline").as_deref() != Some(self.name.as_str()) {
+                    continue;
+                }
+
+                if let Some(key) = labels.iter().map(|x| label(x)).collect() {
+                    values.push((key, metric.get_counter().get_value() as u64));
+                }
+            }
+        }
+        values
+    }
+
+    /// Returns the value of each counter of `vec` for this pipeline, keyed by `label`
+    fn labelled(&self, vec: &IntCounterVec, label: &str) -> HashMap<String, u64> {
+        self.counters(vec, &[label])
+            .into_iter()
+            .map(|(mut key, value)| (key.remove(0), value))
+            .collect()
+    }
+
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            enqueued: self.enqueued.get() as u64,
+            sent: self.sent.get() as u64,
+            sent_bytes: self.sent_bytes.get() as u64,
+            acked: self.acked.get() as u64,
+            retried: self.retried.get() as u64,
+            dropped: self.dropped.get() as u64,
+            deduplicated: self.deduplicated.get() as u64,
+            timed_out: self.timed_out.get() as u64,
+            hedged: self.hedged.get() as u64,
+            throttled: self
+                .counters(&THROTTLED, &["stream", "shard"])
+                .into_iter()
+                .map(|(key, value)| ((key[0].clone(), key[1].clone()), value))
+                .collect(),
+            dead_lettered: self.labelled(&DEAD_LETTERED, "reason"),
+            in_flight: self.in_flight.get(),
+        }
+    }
+}
+
+/// Decrements the in-flight gauge when dropped
+pub(crate) struct InFlightGuard(IntGauge);
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.0.dec()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot() {
+        let metrics = PipelineMetrics::new("test_snapshot".to_string());
+        metrics.enqueued();
+        metrics.enqueued();
+        metrics.acked();
+        metrics
+            .for_stream("a")
+            .throttled(Some("shardId-000000000001".parse().unwrap()));
+        metrics.throttled(None);
+
+        let guard = metrics.sent(&[]);
+        assert_eq!(metrics.snapshot().in_flight, 1);
+        drop(guard);
+
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.enqueued, 2);
+        assert_eq!(snapshot.acked, 1);
+        assert_eq!(snapshot.in_flight, 0);
+        assert_eq!(snapshot.throttled.len(), 2);
+        let key = |stream: &str, shard: &str| (stream.to_string(), shard.to_string());
+        assert_eq!(snapshot.throttled[&key("a", "shardId-000000000001")], 1);
+        assert_eq!(snapshot.throttled[&key("none", "none")], 1);
+        assert!(snapshot.dead_lettered.is_empty());
+
+        metrics.dead_lettered(&producer::Error::RecordTooLarge);
+        metrics.dead_lettered(&producer::Error::RetriesExhausted);
+        metrics.dead_lettered(&producer::Error::RetriesExhausted);
+
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.dead_lettered.len(), 2);
+        assert_eq!(snapshot.dead_lettered["too_large"], 1);
+        assert_eq!(snapshot.dead_lettered["retries_exhausted"], 2);
+    }
+}
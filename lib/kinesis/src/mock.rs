@@ -0,0 +1,207 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use rusoto_core::RusotoError;
+use rusoto_kinesis::{
+    DescribeStreamSummaryError, DescribeStreamSummaryInput, DescribeStreamSummaryOutput,
+    HashKeyRange, ListShardsError, ListShardsInput, ListShardsOutput, PutRecordsError,
+    PutRecordsInput, PutRecordsOutput, PutRecordsRequestEntry, PutRecordsResultEntry,
+    SequenceNumberRange, Shard, StreamDescriptionSummary,
+};
+
+use crate::client::KinesisApi;
+
+const THROUGHPUT_EXCEEDED: &str = "ProvisionedThroughputExceededException";
+
+struct State {
+    /// The inclusive upper bound of each shard's hash key range
+    shards: Vec<u128>,
+    requests: Vec<PutRecordsInput>,
+    received: Vec<(String, PutRecordsRequestEntry)>,
+    /// Error codes to fail the next records with
+    record_errors: VecDeque<String>,
+    /// The number of PutRecords calls still to fail outright
+    request_errors: usize,
+    next_sequence: u64,
+    /// The status of the stream, its shards can only be listed if ACTIVE or UPDATING
+    status: String,
+}
+
+/// An in-memory Kinesis stream that records the requests made to it
+#[derive(Clone)]
+pub(crate) struct MockKinesis(Arc<Mutex<State>>);
+
+impl MockKinesis {
+    /// Creates a stream with `shards` open shards evenly dividing the hash key space
+    pub fn new(shards: usize) -> MockKinesis {
+        assert!(shards > 0, "stream must have at least one shard");
+
+        let width = u128::MAX / shards as u128;
+        let mut bounds: Vec<_> = (1..shards as u128).map(|x| x * width).collect();
+        bounds.push(u128::MAX);
+
+        MockKinesis(Arc::new(Mutex::new(State {
+            shards: bounds,
+            requests: vec![],
+            received: vec![],
+            record_errors: Default::default(),
+            request_errors: 0,
+            next_sequence: 0,
+            status: "ACTIVE".to_string(),
+        })))
+    }
+
+    /// Sets the status of the stream, such as CREATING
+    pub fn set_status(&self, status: &str) {
+        self.0.lock().unwrap().status = status.to_string();
+    }
+
+    /// Fails the next `count` records with `error_code`
+    pub fn fail_records(&self, count: usize, error_code: &str) {
+        let mut state = self.0.lock().unwrap();
+        for _ in 0..count {
+            state.record_errors.push_back(error_code.to_string());
+        }
+    }
+
+    /// Fails the next `count` records with a ProvisionedThroughputExceededException
+    pub fn throttle(&self, count: usize) {
+        self.fail_records(count, THROUGHPUT_EXCEEDED)
+    }
+
+    /// Fails the next `count` PutRecords calls
+    pub fn fail_requests(&self, count: usize) {
+        self.0.lock().unwrap().request_errors += count
+    }
+
+    /// Returns the PutRecords calls made, including those that failed
+    pub fn requests(&self) -> Vec<PutRecordsInput> {
+        self.0.lock().unwrap().requests.clone()
+    }
+
+    /// Returns the records accepted by the stream, along with the shard they were written to
+    pub fn received(&self) -> Vec<(String, PutRecordsRequestEntry)> {
+        self.0.lock().unwrap().received.clone()
+    }
+}
+
+fn shard_id(idx: usize) -> String {
+    format!("shardId-{:012}", idx)
+}
+
+fn hash_key(entry: &PutRecordsRequestEntry) -> u128 {
+    match &entry.explicit_hash_key {
+        Some(key) => key.parse().unwrap(),
+        None => u128::from_be_bytes(md5::compute(&entry.partition_key).0),
+    }
+}
+
+#[async_trait]
+impl KinesisApi for MockKinesis {
+    async fn put_records(
+        &self,
+        input: PutRecordsInput,
+    ) -> Result<PutRecordsOutput, RusotoError<PutRecordsError>> {
+        let mut state = self.0.lock().unwrap();
+        state.requests.push(input.clone());
+
+        if state.request_errors > 0 {
+            state.request_errors -= 1;
+            return Err(RusotoError::Service(PutRecordsError::ResourceNotFound(
+                "injected failure".to_string(),
+            )));
+        }
+
+        let mut failed = 0;
+        let records = input
+            .records
+            .into_iter()
+            .map(|entry| {
+                if let Some(error_code) = state.record_errors.pop_front() {
+                    failed += 1;
+                    return PutRecordsResultEntry {
+                        error_code: Some(error_code),
+                        error_message: Some("injected failure".to_string()),
+                        ..Default::default()
+                    };
+                }
+
+                let hash_key = hash_key(&entry);
+                let shard = state.shards.iter().position(|x| hash_key <= *x).unwrap();
+                let sequence_number = state.next_sequence.to_string();
+                state.next_sequence += 1;
+                state.received.push((shard_id(shard), entry));
+
+                PutRecordsResultEntry {
+                    sequence_number: Some(sequence_number),
+                    shard_id: Some(shard_id(shard)),
+                    ..Default::default()
+                }
+            })
+            .collect();
+
+        Ok(PutRecordsOutput {
+            failed_record_count: Some(failed),
+            records,
+            ..Default::default()
+        })
+    }
+
+    async fn list_shards(
+        &self,
+        _input: ListShardsInput,
+    ) -> Result<ListShardsOutput, RusotoError<ListShardsError>> {
+        let state = self.0.lock().unwrap();
+        if !matches!(state.status.as_str(), "ACTIVE" | "UPDATING") {
+            return Err(RusotoError::Service(ListShardsError::ResourceInUse(
+                format!("Stream is {}", state.status),
+            )));
+        }
+
+        let shards = state
+            .shards
+            .iter()
+            .enumerate()
+            .map(|(idx, ending_hash_key)| {
+                let starting_hash_key = match idx {
+                    0 => 0,
+                    _ => state.shards[idx - 1] + 1,
+                };
+
+                Shard {
+                    shard_id: shard_id(idx),
+                    hash_key_range: HashKeyRange {
+                        starting_hash_key: starting_hash_key.to_string(),
+                        ending_hash_key: ending_hash_key.to_string(),
+                    },
+                    sequence_number_range: SequenceNumberRange {
+                        starting_sequence_number: "0".to_string(),
+                        ending_sequence_number: None,
+                    },
+                    ..Default::default()
+                }
+            })
+            .collect();
+
+        Ok(ListShardsOutput {
+            shards: Some(shards),
+            next_token: None,
+        })
+    }
+
+    async fn describe_stream_summary(
+        &self,
+        input: DescribeStreamSummaryInput,
+    ) -> Result<DescribeStreamSummaryOutput, RusotoError<DescribeStreamSummaryError>> {
+        let state = self.0.lock().unwrap();
+        Ok(DescribeStreamSummaryOutput {
+            stream_description_summary: StreamDescriptionSummary {
+                stream_name: input.stream_name,
+                stream_status: state.status.clone(),
+                open_shard_count: state.shards.len() as i64,
+                ..Default::default()
+            },
+        })
+    }
+}
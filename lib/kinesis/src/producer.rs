@@ -1,38 +1,188 @@
-use crate::topology::{ShardId, TopologyGeneration};
+use crate::dedup::{Check, Dedup};
+use crate::drain::{Pending, PendingGuard};
+use crate::metrics::PipelineMetrics;
+use crate::queue::{self, Wait};
+use crate::sequencer::SequenceGuard;
+use crate::spill::Spill;
+use crate::topology::{ShardId, TopologyGeneration, TopologyService};
 use bytes::{Buf, Bytes};
-use futures::stream::FuturesUnordered;
-use futures::StreamExt;
+use futures::stream::FuturesOrdered;
+use futures::{future, FutureExt, Sink, SinkExt, StreamExt};
 use serde::{Deserialize, Serialize};
-use stream::{Limiter, LimiterError, Partitioned, Reducer, TokenBucket};
-use tokio::sync::{mpsc, oneshot};
-use tracing::info;
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::Arc;
+use stream::{Limiter, LimiterError, Partitioned, Rate, Reducer, TokenBucket};
+use tokio::sync::{oneshot, Mutex};
+use tokio::time::{Duration, Instant};
+use tracing::{error, info, warn, Span};
 
 #[derive(Debug, Clone)]
 pub enum Error {
     RecordTooLarge,
+    InvalidRecord,
+    RetriesExhausted,
     WorkerDead,
     AckDropped,
+    /// The spill buffer is full or could not be written
+    BufferFull,
+    /// The pipeline is shutting down, or shut down before the record could be delivered
+    Shutdown,
+    /// The pipeline has no capacity to accept the record without waiting
+    Busy,
+    /// The record's time-to-live elapsed before it could be delivered
+    Expired,
+    /// The record was evicted from a full queue by a newer record
+    Overflow,
+    /// The record targeted a shard that is not an open shard of the stream
+    UnknownShard,
 }
 
+/// The acknowledgement of a record
+///
+/// For Firehose and SQS pipelines `shard_id` is None and `sequence_number` holds the
+/// record or message id
 #[derive(Debug, Clone)]
 pub struct Ack {
-    pub shard_id: ShardId,
+    pub shard_id: Option<ShardId>,
     pub sequence_number: String,
 }
 
 #[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "schemars", derive(schemars::JsonSchema))]
 pub struct RawRecord {
     pub partition_key: String,
+    #[cfg_attr(feature = "schemars", schemars(with = "Vec<u8>"))]
     pub data: Bytes,
+    /// A decimal 128-bit hash key overriding the hash of the partition key, used to
+    /// pin a record to a particular shard
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub explicit_hash_key: Option<String>,
+    /// The stream to deliver this record to, which must have been registered with
+    /// the pipeline. Defaults to the pipeline's stream
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stream: Option<String>,
+    /// Identifies a record across submissions, if deduplication is enabled on the pipeline
+    /// records with the same id as one in flight or recently delivered are not resent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idempotency_id: Option<String>,
+    /// The number of milliseconds after submission that the record is failed with
+    /// `Error::Expired` instead of being retried, overriding the pipeline's TTL
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttl_ms: Option<u64>,
+}
+
+/// Where records that can never be delivered are sent, as they have exhausted their
+/// retry budget or were rejected by validation, e.g. for being too large
+///
+/// In all cases the record is acknowledged with the error, such as `Error::RetriesExhausted`
+/// or `Error::RecordTooLarge`, and counted by reason in `kinesis_producer_dead_lettered_total`
+#[derive(Clone)]
+pub enum DeadLetter {
+    /// Only return an error on the ack channel
+    Fail,
+    /// Invoke a callback with the record
+    Callback(Arc<dyn Fn(RawRecord) + Send + Sync>),
+    /// Submit the record to another pipeline, e.g. a dead-letter stream or queue
+    Producer(Box<Producer>),
+    /// Send the record to a sink, e.g. a channel or a writer to S3
+    Sink(Arc<Mutex<DeadLetterSink>>),
+}
+
+/// A sink of dead-lettered records, see `DeadLetter::sink`
+pub type DeadLetterSink = Pin<Box<dyn Sink<RawRecord, Error = String> + Send>>;
+
+impl DeadLetter {
+    pub fn callback<F: Fn(RawRecord) + Send + Sync + 'static>(callback: F) -> DeadLetter {
+        DeadLetter::Callback(Arc::new(callback))
+    }
+
+    pub fn producer(producer: Producer) -> DeadLetter {
+        DeadLetter::Producer(Box::new(producer))
+    }
+
+    /// Sends records to `sink`, such as the sender of a `futures::channel::mpsc` channel
+    ///
+    /// Records are sent in the background, errors sending them are logged
+    pub fn sink<S>(sink: S) -> DeadLetter
+    where
+        S: Sink<RawRecord> + Send + 'static,
+        S::Error: std::fmt::Debug,
+    {
+        let sink = sink.sink_map_err(|e| format!("{:?}", e));
+        DeadLetter::Sink(Arc::new(Mutex::new(Box::pin(sink))))
+    }
+
+    /// Sends `record` to the destination and fails it with `error`
+    pub(crate) fn send(&self, record: Record, error: Error, metrics: &PipelineMetrics) {
+        warn!(
+            attempts = record.attempts,
+            partition_key = %record.partition_key,
+            ?error,
+            "dead lettering record"
+        );
+        metrics.dead_lettered(&error);
+
+        match self {
+            DeadLetter::Fail => {}
+            DeadLetter::Callback(callback) => callback(record.raw()),
+            DeadLetter::Producer(producer) => {
+                let mut producer = producer.as_ref().clone();
+                let raw = record.raw();
+                tokio::spawn(async move {
+                    for result in producer.submit(std::iter::once(raw)).await {
+                        if let Err(e) = result {
+                            error!("failed to submit record to dead letter pipeline: {:?}", e)
+                        }
+                    }
+                });
+            }
+            DeadLetter::Sink(sink) => {
+                let sink = sink.clone();
+                let raw = record.raw();
+                tokio::spawn(async move {
+                    if let Err(e) = sink.lock().await.send(raw).await {
+                        error!("failed to send record to dead letter sink: {}", e)
+                    }
+                });
+            }
+        }
+
+        record.ack(Err(error));
+    }
+}
+
+impl std::fmt::Debug for DeadLetter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DeadLetter::Fail => write!(f, "Fail"),
+            DeadLetter::Callback(_) => write!(f, "Callback"),
+            DeadLetter::Producer(_) => write!(f, "Producer"),
+            DeadLetter::Sink(_) => write!(f, "Sink"),
+        }
+    }
 }
 
 #[derive(Debug)]
 pub(crate) struct Record {
     pub partition_key: String,
     pub data: Bytes,
+    pub explicit_hash_key: Option<u128>,
     pub predicted_shard_id: Option<(ShardId, TopologyGeneration)>,
     pub acker: Option<oneshot::Sender<Result<Ack, Error>>>,
     pub children: Vec<Record>,
+    /// The number of failed attempts to deliver this record
+    pub attempts: u32,
+    /// Keeps the pipeline from shutting down until this record is acknowledged
+    pub pending: Option<PendingGuard>,
+    /// Holds back newer records with the same partition key until this record is acknowledged
+    pub sequence: Option<SequenceGuard>,
+    /// The instant after which this record is failed rather than retried
+    pub deadline: Option<Instant>,
+    /// When this record was submitted to the pipeline
+    pub enqueued: Instant,
+    /// The span it was submitted within, which the requests delivering it follow from
+    pub span: Span,
 }
 
 impl Record {
@@ -44,13 +194,41 @@ impl Record {
         if let Some(ack) = self.acker.take() {
             let _ = ack.send(result);
         }
+
+        // Only allow the pipeline to finish draining once the ack has been sent
+        drop(self.sequence.take());
+        drop(self.pending.take());
+    }
+
+    pub fn raw(&self) -> RawRecord {
+        RawRecord {
+            partition_key: self.partition_key.clone(),
+            data: self.data.clone(),
+            explicit_hash_key: self.explicit_hash_key.map(|x| x.to_string()),
+            stream: None,
+            idempotency_id: None,
+            ttl_ms: None,
+        }
     }
 
     pub fn len(&self) -> usize {
         self.data.len()
     }
 
+    /// Records that `span` follows from the submission of this record, and of those
+    /// it aggregates
+    pub fn follows(&self, span: &Span) {
+        span.follows_from(&self.span);
+        for child in &self.children {
+            child.follows(span);
+        }
+    }
+
     pub fn hash_key(&self) -> u128 {
+        if let Some(explicit_hash_key) = self.explicit_hash_key {
+            return explicit_hash_key;
+        }
+
         let mut cursor = std::io::Cursor::new(md5::compute(&self.partition_key).0);
         cursor.get_u128()
     }
@@ -125,10 +303,10 @@ pub(crate) struct RecordLimiter {
 }
 
 impl RecordLimiter {
-    pub fn new(records_per_second: u64, bytes_per_second: u64) -> RecordLimiter {
+    pub fn new(records_per_second: Rate, bytes_per_second: Rate) -> RecordLimiter {
         RecordLimiter {
-            bytes: TokenBucket::per_second(bytes_per_second),
-            records: TokenBucket::per_second(records_per_second),
+            bytes: TokenBucket::with_rate(bytes_per_second),
+            records: TokenBucket::with_rate(records_per_second),
         }
     }
 }
@@ -146,41 +324,472 @@ impl Limiter for RecordLimiter {
     }
 }
 
+/// Returns the stream a record without `RawRecord::stream` is sent to, None for the
+/// pipeline's stream, see `PipelineBuilder::route`
+pub type Route = Arc<dyn Fn(&RawRecord) -> Option<String> + Send + Sync>;
+
+/// Routes records to the sub-pipeline of the stream they target
+#[derive(Clone)]
+pub(crate) struct Router {
+    default: String,
+    senders: HashMap<String, queue::Sender>,
+    /// The topology of each stream, empty for destinations without shards
+    topologies: HashMap<String, TopologyService>,
+    route: Option<Route>,
+}
+
+impl Router {
+    pub fn new(
+        default: String,
+        senders: HashMap<String, queue::Sender>,
+        topologies: HashMap<String, TopologyService>,
+    ) -> Router {
+        assert!(senders.contains_key(&default));
+        Router {
+            default,
+            senders,
+            topologies,
+            route: None,
+        }
+    }
+
+    /// Routes records that don't set their stream with `route`
+    pub fn with_route(self, route: Option<Route>) -> Router {
+        Router { route, ..self }
+    }
+
+    /// Sets the stream of `record` with the route, if it doesn't set one itself
+    pub fn resolve(&self, record: &mut RawRecord) {
+        if let (None, Some(route)) = (&record.stream, &self.route) {
+            record.stream = route(record);
+        }
+    }
+
+    pub fn contains(&self, stream: Option<&str>) -> bool {
+        stream.map(|x| self.senders.contains_key(x)).unwrap_or(true)
+    }
+
+    /// Returns the fraction of its capacity held by the fullest stream queue
+    pub fn saturation(&self) -> f64 {
+        self.senders
+            .values()
+            .map(|x| x.len() as f64 / x.capacity() as f64)
+            .fold(0., f64::max)
+    }
+
+    /// Returns the topology of the stream, if it has one
+    pub fn topology(&self, stream: Option<&str>) -> Option<TopologyService> {
+        let stream = stream.unwrap_or(&self.default);
+        self.topologies.get(stream).cloned()
+    }
+
+    pub async fn send(&mut self, stream: Option<&str>, record: Record) -> Result<(), Error> {
+        self.send_wait(stream, record, Wait::Forever).await
+    }
+
+    /// Sends a record, applying the overflow policy of the target queue if it is full
+    pub async fn send_wait(
+        &mut self,
+        stream: Option<&str>,
+        record: Record,
+        wait: Wait,
+    ) -> Result<(), Error> {
+        let stream = stream.unwrap_or(&self.default);
+        match self.senders.get(stream) {
+            Some(sender) => sender.send(record, wait).await,
+            None => {
+                record.ack(Err(Error::InvalidRecord));
+                Ok(())
+            }
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct Producer {
-    sender: mpsc::Sender<Record>,
+    router: Router,
+    metrics: PipelineMetrics,
+    pending: Pending,
+    shutdown: shutdown::Receiver,
+    /// If set records are written to the spill buffer instead of directly to the pipeline
+    spill: Option<Spill>,
+    /// If set records with an idempotency id are deduplicated
+    dedup: Option<Dedup>,
+    /// The default time-to-live of records
+    ttl: Option<Duration>,
 }
 
 impl Producer {
-    pub(crate) fn new(sender: mpsc::Sender<Record>) -> Producer {
-        Producer { sender }
+    pub(crate) fn new(
+        router: Router,
+        metrics: PipelineMetrics,
+        pending: Pending,
+        shutdown: shutdown::Receiver,
+        spill: Option<Spill>,
+        dedup: Option<Dedup>,
+        ttl: Option<Duration>,
+    ) -> Producer {
+        Producer {
+            router,
+            metrics,
+            pending,
+            shutdown,
+            spill,
+            dedup,
+            ttl,
+        }
     }
 
+    /// Submits records to the pipeline, applying its overflow policy if it is saturated
+    ///
+    /// Returns the results in the same order as the records
     pub async fn submit(
         &mut self,
         records: impl Iterator<Item = RawRecord>,
     ) -> Vec<Result<Ack, Error>> {
-        let stream = FuturesUnordered::new();
-        for record in records {
+        self.submit_wait(records, Wait::Forever).await
+    }
+
+    /// Submits records to the pipeline, failing any that cannot be immediately
+    /// accepted with `Error::Busy`
+    pub async fn try_submit(
+        &mut self,
+        records: impl Iterator<Item = RawRecord>,
+    ) -> Vec<Result<Ack, Error>> {
+        self.submit_wait(records, Wait::Never).await
+    }
+
+    /// Submits records to the pipeline, failing any that cannot be accepted within
+    /// `timeout` with `Error::Busy`
+    ///
+    /// The timeout bounds the time spent waiting for capacity, not for acknowledgement
+    pub async fn submit_with_timeout(
+        &mut self,
+        records: impl Iterator<Item = RawRecord>,
+        timeout: Duration,
+    ) -> Vec<Result<Ack, Error>> {
+        self.submit_wait(records, Wait::Until(Instant::now() + timeout))
+            .await
+    }
+
+    /// Returns the fraction of its capacity held by the fullest queue in the pipeline
+    ///
+    /// Once this reaches 1 submissions wait for capacity, or fail with `Error::Busy`
+    /// if made with `try_submit`. Retried records are requeued regardless of capacity,
+    /// and so this may exceed 1
+    pub fn saturation(&self) -> f64 {
+        self.router.saturation()
+    }
+
+    /// Returns the open shards of a stream registered with the pipeline, or of the
+    /// pipeline's stream if None
+    ///
+    /// Fails with `Error::InvalidRecord` if the pipeline does not deliver to a Kinesis stream
+    pub async fn shards(&self, stream: Option<&str>) -> Result<Vec<ShardId>, Error> {
+        match self.router.topology(stream) {
+            Some(mut topology) => Ok(topology.open_shards().await),
+            None => Err(Error::InvalidRecord),
+        }
+    }
+
+    /// Submits records to the pipeline, delivering them to `shard_id` of their stream
+    /// instead of the shard their partition key hashes to
+    ///
+    /// This replaces any explicit hash key with one owned by the shard in the current
+    /// topology. Records are failed with `Error::UnknownShard` if the shard is not open,
+    /// and if the shard is split or merged before delivery they are delivered to the
+    /// shard that takes over its hash key, as reported by their `Ack`
+    pub async fn submit_to_shard(
+        &mut self,
+        shard_id: ShardId,
+        records: impl Iterator<Item = RawRecord>,
+    ) -> Vec<Result<Ack, Error>> {
+        let mut targeted = Vec::new();
+        let mut failed = Vec::new();
+        for (idx, mut record) in records.enumerate() {
+            self.router.resolve(&mut record);
+            let hash_key = match self.router.topology(record.stream.as_deref()) {
+                Some(mut topology) => topology.shard_hash_key(shard_id).await.ok(),
+                None => None,
+            };
+
+            match hash_key {
+                Some(hash_key) => {
+                    record.explicit_hash_key = Some(hash_key.to_string());
+                    targeted.push(record);
+                }
+                None => {
+                    self.metrics.dropped();
+                    failed.push(idx)
+                }
+            }
+        }
+
+        let mut results = self.submit(targeted.into_iter()).await.into_iter();
+        let total = results.len() + failed.len();
+        let mut failed = failed.into_iter().peekable();
+        (0..total)
+            .map(|idx| match failed.peek() {
+                Some(failed_idx) if *failed_idx == idx => {
+                    failed.next();
+                    Err(Error::UnknownShard)
+                }
+                _ => results.next().unwrap(),
+            })
+            .collect()
+    }
+
+    /// Submits a single record to the pipeline, waiting for capacity
+    pub async fn submit_one(&mut self, record: RawRecord) -> Result<Ack, Error> {
+        self.submit(std::iter::once(record)).await.pop().unwrap()
+    }
+
+    async fn submit_wait(
+        &mut self,
+        records: impl Iterator<Item = RawRecord>,
+        wait: Wait,
+    ) -> Vec<Result<Ack, Error>> {
+        let mut results = Vec::new();
+        for mut record in records {
+            let submitted = Instant::now();
+            self.router.resolve(&mut record);
+            if self.shutdown.terminating() {
+                self.metrics.dropped();
+                results.push(future::ready(Err(Error::Shutdown)).boxed());
+                continue;
+            }
+
+            if !self.router.contains(record.stream.as_deref()) {
+                self.metrics.dropped();
+                results.push(future::ready(Err(Error::InvalidRecord)).boxed());
+                continue;
+            }
+
+            let explicit_hash_key = match record.explicit_hash_key.map(|x| x.parse()) {
+                Some(Ok(hash_key)) => Some(hash_key),
+                Some(Err(_)) => {
+                    self.metrics.dropped();
+                    results.push(future::ready(Err(Error::InvalidRecord)).boxed());
+                    continue;
+                }
+                None => None,
+            };
+
+            let dedup = match (&self.dedup, record.idempotency_id) {
+                (Some(dedup), Some(id)) => match dedup.check(&id) {
+                    Check::Send(waiter) => Some((dedup.clone(), id, waiter)),
+                    Check::Wait(waiter) => {
+                        self.metrics.deduplicated();
+                        results.push(waiter.map(|x| x.unwrap_or(Err(Error::AckDropped))).boxed());
+                        continue;
+                    }
+                    Check::Delivered(ack) => {
+                        self.metrics.deduplicated();
+                        results.push(future::ready(Ok(ack)).boxed());
+                        continue;
+                    }
+                },
+                _ => None,
+            };
+
             let (otx, orx) = oneshot::channel::<_>();
+            let ttl = record.ttl_ms.map(Duration::from_millis).or(self.ttl);
+            let deadline = ttl.map(|ttl| Instant::now() + ttl);
+
+            let send_result = match &self.spill {
+                Some(spill) => {
+                    spill
+                        .append(
+                            record.stream.as_deref(),
+                            &record.partition_key,
+                            explicit_hash_key,
+                            deadline,
+                            &record.data,
+                            otx,
+                        )
+                        .await
+                }
+                None => {
+                    let target = record.stream;
+                    let record = Record {
+                        partition_key: record.partition_key,
+                        explicit_hash_key,
+                        acker: Some(otx),
+                        predicted_shard_id: None,
+                        data: record.data,
+                        children: vec![],
+                        attempts: 0,
+                        pending: Some(self.pending.track()),
+                        sequence: None,
+                        deadline,
+                        enqueued: Instant::now(),
+                        span: Span::current(),
+                    };
 
-            let record = Record {
-                partition_key: record.partition_key,
-                acker: Some(otx),
-                predicted_shard_id: None,
-                data: record.data,
-                children: vec![],
+                    self.router.send_wait(target.as_deref(), record, wait).await
+                }
             };
 
-            let send_result = self.sender.send(record).await;
-            stream.push(async move {
+            if send_result.is_ok() {
+                self.metrics.enqueued();
+            }
+
+            let result = async move {
                 match send_result {
-                    Ok(()) => orx.await.map_err(|_| Error::AckDropped)?,
-                    Err(_) => Err(Error::WorkerDead),
+                    Ok(()) => orx.await.unwrap_or(Err(Error::AckDropped)),
+                    Err(e) => Err(e),
+                }
+            };
+
+            let result = match dedup {
+                Some((dedup, id, waiter)) => {
+                    // Complete the id even if the caller stops waiting for the result
+                    tokio::spawn(async move { dedup.complete(id, result.await) });
+                    waiter.map(|x| x.unwrap_or(Err(Error::AckDropped))).boxed()
                 }
-            });
+                None => result.boxed(),
+            };
+
+            let metrics = self.metrics.clone();
+            results.push(
+                async move {
+                    let result = result.await;
+                    metrics.ack_latency(submitted);
+                    match result {
+                        Ok(_) => metrics.acked(),
+                        Err(_) => metrics.dropped(),
+                    }
+                    result
+                }
+                .boxed(),
+            );
         }
 
-        stream.collect::<Vec<_>>().await
+        // Resolve concurrently, but return results in submission order
+        results
+            .into_iter()
+            .collect::<FuturesOrdered<_>>()
+            .collect::<Vec<_>>()
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::FutureExt;
+
+    use crate::queue::Overflow;
+
+    use super::*;
+
+    fn record() -> (Record, oneshot::Receiver<Result<Ack, Error>>) {
+        let (tx, rx) = oneshot::channel();
+        let record = Record {
+            partition_key: "a".to_string(),
+            explicit_hash_key: None,
+            data: Bytes::from_static(b"hello"),
+            predicted_shard_id: None,
+            acker: Some(tx),
+            children: vec![],
+            attempts: 0,
+            pending: None,
+            sequence: None,
+            deadline: None,
+            enqueued: Instant::now(),
+            span: Span::none(),
+        };
+        (record, rx)
+    }
+
+    #[tokio::test]
+    async fn test_router() {
+        let (a_tx, mut a_rx) = queue::channel(10, Overflow::Block);
+        let (b_tx, mut b_rx) = queue::channel(10, Overflow::Block);
+
+        let mut senders = HashMap::new();
+        senders.insert("a".to_string(), a_tx);
+        senders.insert("b".to_string(), b_tx);
+        let mut router = Router::new("a".to_string(), senders, HashMap::new());
+
+        assert!(router.contains(None));
+        assert!(router.contains(Some("b")));
+        assert!(!router.contains(Some("c")));
+
+        router.send(None, record().0).await.unwrap();
+        router.send(Some("b"), record().0).await.unwrap();
+        assert!(a_rx.next().now_or_never().is_some());
+        assert!(b_rx.next().now_or_never().is_some());
+
+        let (unknown, rx) = record();
+        router.send(Some("c"), unknown).await.unwrap();
+        assert!(matches!(rx.await.unwrap(), Err(Error::InvalidRecord)));
+    }
+
+    #[test]
+    fn test_route() {
+        let route: Route = Arc::new(|record: &RawRecord| {
+            if record.partition_key.starts_with("audit-") {
+                Some("b".to_string())
+            } else {
+                None
+            }
+        });
+        let (tx, _rx) = queue::channel(10, Overflow::Block);
+        let mut senders = HashMap::new();
+        senders.insert("a".to_string(), tx);
+        let router = Router::new("a".to_string(), senders, HashMap::new()).with_route(Some(route));
+
+        let raw = |partition_key: &str, stream: Option<&str>| RawRecord {
+            partition_key: partition_key.to_string(),
+            data: Bytes::from_static(b"hello"),
+            explicit_hash_key: None,
+            stream: stream.map(ToString::to_string),
+            idempotency_id: None,
+            ttl_ms: None,
+        };
+
+        let mut routed = raw("audit-1", None);
+        router.resolve(&mut routed);
+        assert_eq!(routed.stream.as_deref(), Some("b"));
+
+        let mut unrouted = raw("user-1", None);
+        router.resolve(&mut unrouted);
+        assert_eq!(unrouted.stream, None);
+
+        // A stream set on the record takes precedence
+        let mut explicit = raw("audit-2", Some("a"));
+        router.resolve(&mut explicit);
+        assert_eq!(explicit.stream.as_deref(), Some("a"));
+    }
+
+    #[tokio::test]
+    async fn test_busy() {
+        let (tx, mut rx) = queue::channel(1, Overflow::Block);
+
+        let mut senders = HashMap::new();
+        senders.insert("a".to_string(), tx);
+        let mut router = Router::new("a".to_string(), senders, HashMap::new());
+        assert_eq!(router.saturation(), 0.);
+
+        router
+            .send_wait(None, record().0, Wait::Never)
+            .await
+            .unwrap();
+        assert_eq!(router.saturation(), 1.);
+
+        let result = router.send_wait(None, record().0, Wait::Never).await;
+        assert!(matches!(result, Err(Error::Busy)));
+
+        let deadline = Instant::now() + Duration::from_millis(10);
+        let result = router
+            .send_wait(None, record().0, Wait::Until(deadline))
+            .await;
+        assert!(matches!(result, Err(Error::Busy)));
+
+        assert!(rx.next().now_or_never().is_some());
+        router
+            .send_wait(None, record().0, Wait::Never)
+            .await
+            .unwrap();
     }
 }
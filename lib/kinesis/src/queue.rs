@@ -0,0 +1,308 @@
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use futures::task::AtomicWaker;
+use futures::Stream;
+use tokio::sync::Notify;
+use tokio::time::{timeout_at, Instant};
+
+use crate::producer::{Error, Record};
+
+/// What happens when a record is submitted to a full queue
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overflow {
+    /// Wait for capacity, unless submitted with `Producer::try_submit` or
+    /// `Producer::submit_with_timeout`
+    Block,
+    /// Fail the record with `Error::Busy`
+    Error,
+    /// Evict the oldest queued record, failing it with `Error::Overflow`
+    DropOldest,
+}
+
+/// How long to wait for capacity in a queue using `Overflow::Block`
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Wait {
+    Forever,
+    Never,
+    Until(Instant),
+}
+
+struct State {
+    records: VecDeque<Record>,
+    closed: bool,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    capacity: usize,
+    overflow: Overflow,
+    senders: AtomicUsize,
+    /// Woken when a record is pushed, or the last sender is dropped
+    receiver: AtomicWaker,
+    /// Notified when a record is popped, or the receiver is dropped
+    capacity_notify: Notify,
+}
+
+/// The sending half of the queue feeding a pipeline
+pub(crate) struct Sender(Arc<Shared>);
+
+/// The receiving half of the queue feeding a pipeline
+pub(crate) struct Receiver(Arc<Shared>);
+
+/// Creates a bounded queue of records that applies `overflow` once it holds `capacity` records
+pub(crate) fn channel(capacity: usize, overflow: Overflow) -> (Sender, Receiver) {
+    assert!(capacity > 0, "capacity must be greater than 0");
+
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            records: VecDeque::with_capacity(capacity),
+            closed: false,
+        }),
+        capacity,
+        overflow,
+        senders: AtomicUsize::new(1),
+        receiver: AtomicWaker::new(),
+        capacity_notify: Notify::new(),
+    });
+
+    (Sender(shared.clone()), Receiver(shared))
+}
+
+impl Sender {
+    /// Returns the number of queued records
+    pub fn len(&self) -> usize {
+        self.0.state.lock().unwrap().records.len()
+    }
+
+    /// Returns the number of records the queue holds before applying its overflow policy
+    pub fn capacity(&self) -> usize {
+        self.0.capacity
+    }
+
+    /// Submits a new record to the queue, applying the overflow policy if it is full
+    pub async fn send(&self, mut record: Record, wait: Wait) -> Result<(), Error> {
+        loop {
+            record = match self.try_send(record)? {
+                Some(record) => record,
+                None => return Ok(()),
+            };
+
+            let notified = self.0.capacity_notify.notified();
+            match wait {
+                Wait::Never => return Err(Error::Busy),
+                Wait::Forever => notified.await,
+                Wait::Until(deadline) => timeout_at(deadline, notified)
+                    .await
+                    .map_err(|_| Error::Busy)?,
+            }
+        }
+    }
+
+    /// Pushes a record unless the queue is full and using `Overflow::Block`, in which
+    /// case the record is returned
+    fn try_send(&self, record: Record) -> Result<Option<Record>, Error> {
+        let evicted = {
+            let mut state = self.0.state.lock().unwrap();
+            if state.closed {
+                // Chain the wakeup to any other blocked senders
+                self.0.capacity_notify.notify();
+                return Err(Error::WorkerDead);
+            }
+
+            let evicted = if state.records.len() < self.0.capacity {
+                None
+            } else {
+                match self.0.overflow {
+                    Overflow::Block => return Ok(Some(record)),
+                    Overflow::Error => return Err(Error::Busy),
+                    Overflow::DropOldest => state.records.pop_front(),
+                }
+            };
+
+            state.records.push_back(record);
+            evicted
+        };
+
+        self.0.receiver.wake();
+        if let Some(evicted) = evicted {
+            evicted.ack(Err(Error::Overflow));
+        }
+        Ok(None)
+    }
+
+    /// Pushes a record that has already been accepted by the pipeline, such as a retry,
+    /// ignoring the capacity of the queue
+    ///
+    /// The record is acknowledged with `Error::Shutdown` if the receiver has been dropped
+    pub fn push(&self, record: Record) {
+        {
+            let mut state = self.0.state.lock().unwrap();
+            if !state.closed {
+                state.records.push_back(record);
+                drop(state);
+                self.0.receiver.wake();
+                return;
+            }
+        }
+        record.ack(Err(Error::Shutdown));
+    }
+}
+
+impl Clone for Sender {
+    fn clone(&self) -> Self {
+        self.0.senders.fetch_add(1, Ordering::SeqCst);
+        Sender(self.0.clone())
+    }
+}
+
+impl Drop for Sender {
+    fn drop(&mut self) {
+        if self.0.senders.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.0.receiver.wake()
+        }
+    }
+}
+
+impl Stream for Receiver {
+    type Item = Record;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.0.receiver.register(cx.waker());
+
+        let mut state = self.0.state.lock().unwrap();
+        match state.records.pop_front() {
+            Some(record) => {
+                drop(state);
+                self.0.capacity_notify.notify();
+                Poll::Ready(Some(record))
+            }
+            None if self.0.senders.load(Ordering::SeqCst) == 0 => Poll::Ready(None),
+            None => Poll::Pending,
+        }
+    }
+}
+
+impl Drop for Receiver {
+    fn drop(&mut self) {
+        let records = {
+            let mut state = self.0.state.lock().unwrap();
+            state.closed = true;
+            std::mem::take(&mut state.records)
+        };
+        self.0.capacity_notify.notify();
+
+        for record in records {
+            record.ack(Err(Error::Shutdown));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use futures::{FutureExt, StreamExt};
+    use tokio::sync::oneshot;
+    use tokio::time::Duration;
+
+    use crate::producer::Ack;
+
+    use super::*;
+
+    fn record(data: &'static [u8]) -> (Record, oneshot::Receiver<Result<Ack, Error>>) {
+        let (tx, rx) = oneshot::channel();
+        let record = Record {
+            partition_key: "a".to_string(),
+            explicit_hash_key: None,
+            data: Bytes::from_static(data),
+            predicted_shard_id: None,
+            acker: Some(tx),
+            children: vec![],
+            attempts: 0,
+            pending: None,
+            sequence: None,
+            deadline: None,
+            enqueued: Instant::now(),
+            span: tracing::Span::none(),
+        };
+        (record, rx)
+    }
+
+    #[tokio::test]
+    async fn test_block() {
+        let (tx, mut rx) = channel(1, Overflow::Block);
+
+        tx.send(record(b"1").0, Wait::Never).await.unwrap();
+        let result = tx.send(record(b"2").0, Wait::Never).await;
+        assert!(matches!(result, Err(Error::Busy)));
+        assert_eq!(tx.len(), 1);
+
+        let deadline = Instant::now() + Duration::from_millis(10);
+        let result = tx.send(record(b"2").0, Wait::Until(deadline)).await;
+        assert!(matches!(result, Err(Error::Busy)));
+
+        {
+            let blocked = tx.send(record(b"2").0, Wait::Forever);
+            tokio::pin!(blocked);
+            assert!(futures::poll!(&mut blocked).is_pending());
+
+            assert_eq!(rx.next().await.unwrap().data.as_ref(), b"1");
+            blocked.await.unwrap();
+        }
+        assert_eq!(rx.next().await.unwrap().data.as_ref(), b"2");
+
+        // Accepted records ignore capacity
+        tx.push(record(b"3").0);
+        tx.push(record(b"4").0);
+        assert_eq!(rx.next().await.unwrap().data.as_ref(), b"3");
+        assert_eq!(rx.next().await.unwrap().data.as_ref(), b"4");
+
+        drop(tx);
+        assert!(rx.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_error() {
+        let (tx, mut rx) = channel(1, Overflow::Error);
+
+        tx.send(record(b"1").0, Wait::Forever).await.unwrap();
+        let result = tx.send(record(b"2").0, Wait::Forever).await;
+        assert!(matches!(result, Err(Error::Busy)));
+
+        assert_eq!(rx.next().await.unwrap().data.as_ref(), b"1");
+    }
+
+    #[tokio::test]
+    async fn test_drop_oldest() {
+        let (tx, mut rx) = channel(2, Overflow::DropOldest);
+
+        let (first, first_rx) = record(b"1");
+        tx.send(first, Wait::Forever).await.unwrap();
+        tx.send(record(b"2").0, Wait::Forever).await.unwrap();
+        tx.send(record(b"3").0, Wait::Forever).await.unwrap();
+
+        assert!(matches!(first_rx.await.unwrap(), Err(Error::Overflow)));
+        assert_eq!(rx.next().await.unwrap().data.as_ref(), b"2");
+        assert_eq!(rx.next().await.unwrap().data.as_ref(), b"3");
+        assert!(rx.next().now_or_never().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_closed() {
+        let (tx, rx) = channel(2, Overflow::Block);
+
+        let (queued, queued_rx) = record(b"1");
+        tx.send(queued, Wait::Forever).await.unwrap();
+        drop(rx);
+
+        assert!(matches!(queued_rx.await.unwrap(), Err(Error::Shutdown)));
+        let result = tx.send(record(b"2").0, Wait::Forever).await;
+        assert!(matches!(result, Err(Error::WorkerDead)));
+        let (pushed, pushed_rx) = record(b"3");
+        tx.push(pushed);
+        assert!(matches!(pushed_rx.await.unwrap(), Err(Error::Shutdown)));
+    }
+}
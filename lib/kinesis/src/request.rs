@@ -0,0 +1,215 @@
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures::future::{select, Either};
+use futures::stream::FuturesUnordered;
+use futures::{pin_mut, Future, Stream};
+use tokio::time::{delay_for, timeout, Duration};
+use tracing::{info, warn};
+
+use crate::metrics::PipelineMetrics;
+
+/// Bounds how long a request to the destination may take, and optionally hedges slow
+/// requests with a second identical request
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct RequestPolicy {
+    pub timeout: Duration,
+    pub hedge_after: Option<Duration>,
+    /// The maximum number of requests a sink has in flight, at least 1
+    pub max_in_flight: usize,
+}
+
+#[derive(Debug)]
+pub(crate) enum RequestError<E> {
+    /// The request, including any hedged request, did not complete within the timeout
+    TimedOut,
+    Failed(E),
+}
+
+impl RequestPolicy {
+    /// Calls `request` to start a request, cancelling it if it does not complete within
+    /// the timeout
+    ///
+    /// If a hedge delay is configured and the first request has not completed once it
+    /// elapses, a second request is started and the first successful response is returned
+    pub async fn call<F, Fut, T, E>(
+        &self,
+        metrics: &PipelineMetrics,
+        request: F,
+    ) -> Result<T, RequestError<E>>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+    {
+        let hedged = hedged(self.hedge_after, metrics, request);
+        match timeout(self.timeout, hedged).await {
+            Ok(result) => result.map_err(RequestError::Failed),
+            Err(_) => {
+                warn!(timeout = ?self.timeout, "request timed out");
+                metrics.timed_out();
+                Err(RequestError::TimedOut)
+            }
+        }
+    }
+
+    /// Polls the requests of a sink until it has capacity for another, for the sink's
+    /// `poll_ready`, so that a slow destination applies backpressure
+    pub fn poll_capacity<F: Future>(
+        &self,
+        mut in_flight: Pin<&mut FuturesUnordered<F>>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), ()>> {
+        while in_flight.len() >= self.max_in_flight {
+            match in_flight.as_mut().poll_next(cx) {
+                Poll::Ready(Some(_)) => {}
+                Poll::Ready(None) => break,
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+async fn hedged<F, Fut, T, E>(
+    hedge_after: Option<Duration>,
+    metrics: &PipelineMetrics,
+    request: F,
+) -> Result<T, E>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    let first = request();
+    let hedge_after = match hedge_after {
+        Some(hedge_after) => hedge_after,
+        None => return first.await,
+    };
+
+    pin_mut!(first);
+    let delay = delay_for(hedge_after);
+    pin_mut!(delay);
+
+    let first = match select(first, delay).await {
+        Either::Left((result, _)) => return result,
+        Either::Right((_, first)) => first,
+    };
+
+    info!(?hedge_after, "hedging slow request");
+    metrics.hedged();
+
+    let second = request();
+    pin_mut!(second);
+
+    // Return the first success, or the last failure
+    match select(first, second).await {
+        Either::Left((Ok(response), _)) | Either::Right((Ok(response), _)) => Ok(response),
+        Either::Left((Err(_), remaining)) | Either::Right((Err(_), remaining)) => remaining.await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use futures::future::BoxFuture;
+    use futures::FutureExt;
+
+    use super::*;
+
+    /// Returns a request whose first call responds after `first` and subsequent calls
+    /// respond after `rest`, along with a count of the calls made
+    fn request(
+        first: Duration,
+        rest: Duration,
+    ) -> (
+        impl Fn() -> BoxFuture<'static, Result<usize, ()>>,
+        Arc<AtomicUsize>,
+    ) {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let captured = calls.clone();
+        let request = move || {
+            let call = captured.fetch_add(1, Ordering::SeqCst);
+            let delay = if call == 0 { first } else { rest };
+            let fut: BoxFuture<'static, _> = Box::pin(async move {
+                delay_for(delay).await;
+                Ok(call)
+            });
+            fut
+        };
+        (request, calls)
+    }
+
+    #[tokio::test]
+    async fn test_timeout() {
+        let metrics = PipelineMetrics::new("test_request_timeout".to_string());
+        let policy = RequestPolicy {
+            timeout: Duration::from_millis(20),
+            hedge_after: None,
+            max_in_flight: 1,
+        };
+
+        let (slow, calls) = request(Duration::from_secs(10), Duration::from_secs(10));
+        let result = policy.call(&metrics, slow).await;
+        assert!(matches!(result, Err(RequestError::TimedOut)));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        let (fast, _) = request(Duration::from_millis(1), Duration::from_millis(1));
+        assert_eq!(policy.call(&metrics, fast).await.unwrap(), 0);
+
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.timed_out, 1);
+        assert_eq!(snapshot.hedged, 0);
+    }
+
+    #[tokio::test]
+    async fn test_hedge() {
+        let metrics = PipelineMetrics::new("test_request_hedge".to_string());
+        let policy = RequestPolicy {
+            timeout: Duration::from_secs(10),
+            hedge_after: Some(Duration::from_millis(10)),
+            max_in_flight: 1,
+        };
+
+        // The hedged request overtakes the slow first request
+        let (slow, calls) = request(Duration::from_secs(5), Duration::from_millis(1));
+        assert_eq!(policy.call(&metrics, slow).await.unwrap(), 1);
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        // Requests completing before the hedge delay are not hedged
+        let (fast, calls) = request(Duration::from_millis(1), Duration::from_millis(1));
+        assert_eq!(policy.call(&metrics, fast).await.unwrap(), 0);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        assert_eq!(metrics.snapshot().hedged, 1);
+    }
+
+    #[tokio::test]
+    async fn test_poll_capacity() {
+        let policy = RequestPolicy {
+            timeout: Duration::from_secs(10),
+            hedge_after: None,
+            max_in_flight: 2,
+        };
+
+        let (tx, rx) = tokio::sync::oneshot::channel::<()>();
+        let mut in_flight = FuturesUnordered::new();
+        in_flight.push(rx.map(|_| ()).boxed());
+        in_flight.push(futures::future::pending::<()>().boxed());
+
+        // Saturated until a request completes
+        futures::future::poll_fn(|cx| {
+            assert!(policy
+                .poll_capacity(Pin::new(&mut in_flight), cx)
+                .is_pending());
+            Poll::Ready(())
+        })
+        .await;
+
+        tx.send(()).unwrap();
+        futures::future::poll_fn(|cx| policy.poll_capacity(Pin::new(&mut in_flight), cx))
+            .await
+            .unwrap();
+        assert_eq!(in_flight.len(), 1);
+    }
+}
@@ -0,0 +1,129 @@
+use rand::Rng;
+use tokio::time::Duration;
+
+/// Determines how long to wait before retrying a failed record, and how many
+/// attempts may be made before it is dead lettered
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    multiplier: f64,
+    jitter: f64,
+    max_attempts: Option<u32>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(10),
+            multiplier: 2.0,
+            jitter: 0.5,
+            max_attempts: None,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// A policy that always waits `backoff` between attempts
+    pub fn fixed(backoff: Duration) -> RetryPolicy {
+        RetryPolicy {
+            initial_backoff: backoff,
+            max_backoff: backoff,
+            multiplier: 1.0,
+            jitter: 0.0,
+            max_attempts: None,
+        }
+    }
+
+    /// Configures the backoff following the first failed attempt
+    pub fn initial_backoff(mut self, initial_backoff: Duration) -> Self {
+        self.initial_backoff = initial_backoff;
+        self
+    }
+
+    /// Configures the upper bound on the backoff between attempts
+    pub fn max_backoff(mut self, max_backoff: Duration) -> Self {
+        self.max_backoff = max_backoff;
+        self
+    }
+
+    /// Configures the factor the backoff grows by after each failed attempt
+    pub fn multiplier(mut self, multiplier: f64) -> Self {
+        assert!(multiplier >= 1.0, "multiplier must be at least 1");
+        self.multiplier = multiplier;
+        self
+    }
+
+    /// Configures the fraction of the backoff that is randomised, between 0 and 1
+    pub fn jitter(mut self, jitter: f64) -> Self {
+        assert!(
+            (0.0..=1.0).contains(&jitter),
+            "jitter must be between 0 and 1"
+        );
+        self.jitter = jitter;
+        self
+    }
+
+    /// Configures the number of failed attempts after which a record is dead lettered
+    ///
+    /// By default records are retried indefinitely
+    pub fn max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = Some(max_attempts);
+        self
+    }
+
+    /// Returns true if a record that has failed `attempts` times should not be retried
+    pub(crate) fn exhausted(&self, attempts: u32) -> bool {
+        self.max_attempts
+            .map(|max_attempts| attempts >= max_attempts)
+            .unwrap_or(false)
+    }
+
+    /// Returns the backoff before the next attempt of a record that has failed `attempts` times
+    pub(crate) fn backoff(&self, attempts: u32) -> Duration {
+        let jitter = rand::thread_rng().gen_range(0.0, 1.0);
+        self.backoff_with_jitter(attempts, jitter)
+    }
+
+    fn backoff_with_jitter(&self, attempts: u32, jitter: f64) -> Duration {
+        let exponent = attempts.saturating_sub(1).min(i32::MAX as u32) as i32;
+        let backoff = self.initial_backoff.as_secs_f64() * self.multiplier.powi(exponent);
+        let backoff = backoff.min(self.max_backoff.as_secs_f64());
+
+        Duration::from_secs_f64(backoff * (1.0 - self.jitter * jitter))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff() {
+        let policy = RetryPolicy::default()
+            .initial_backoff(Duration::from_secs(1))
+            .max_backoff(Duration::from_secs(5))
+            .multiplier(2.0)
+            .jitter(0.5);
+
+        assert_eq!(policy.backoff_with_jitter(1, 0.0), Duration::from_secs(1));
+        assert_eq!(policy.backoff_with_jitter(2, 0.0), Duration::from_secs(2));
+        assert_eq!(policy.backoff_with_jitter(3, 0.0), Duration::from_secs(4));
+        assert_eq!(policy.backoff_with_jitter(4, 0.0), Duration::from_secs(5));
+        assert_eq!(policy.backoff_with_jitter(100, 0.0), Duration::from_secs(5));
+        assert_eq!(policy.backoff_with_jitter(2, 1.0), Duration::from_secs(1));
+
+        let backoff = policy.backoff(3);
+        assert!(backoff >= Duration::from_secs(2) && backoff <= Duration::from_secs(4));
+    }
+
+    #[test]
+    fn test_exhausted() {
+        assert!(!RetryPolicy::default().exhausted(1000));
+
+        let policy = RetryPolicy::default().max_attempts(3);
+        assert!(!policy.exhausted(2));
+        assert!(policy.exhausted(3));
+    }
+}
@@ -0,0 +1,159 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use crate::producer::{self, Record};
+use crate::queue;
+
+struct Inner {
+    /// The records held back for each partition key with a record in flight
+    keys: Mutex<HashMap<String, VecDeque<Record>>>,
+    sender: queue::Sender,
+}
+
+impl Drop for Inner {
+    fn drop(&mut self) {
+        for (_, queue) in self.keys.get_mut().unwrap().drain() {
+            for record in queue {
+                record.ack(Err(producer::Error::Shutdown));
+            }
+        }
+    }
+}
+
+/// Serializes the delivery of records with the same partition key
+///
+/// Only one record per partition key is in flight at once, with newer records held back
+/// until it has been acknowledged, including any retries. Released records are sent
+/// back into the pipeline through `sender`
+#[derive(Clone)]
+pub(crate) struct Sequencer(Arc<Inner>);
+
+/// Held by the in-flight record of a partition key, releasing the next record when dropped
+pub(crate) struct SequenceGuard {
+    inner: Arc<Inner>,
+    partition_key: String,
+}
+
+impl std::fmt::Debug for SequenceGuard {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "SequenceGuard({})", self.partition_key)
+    }
+}
+
+impl Sequencer {
+    pub fn new(sender: queue::Sender) -> Sequencer {
+        Sequencer(Arc::new(Inner {
+            keys: Default::default(),
+            sender,
+        }))
+    }
+
+    /// Returns the record if it can be sent immediately, otherwise holds it back until
+    /// the in-flight record with the same partition key is acknowledged
+    ///
+    /// Records already holding a guard, i.e. retries and released records, are returned
+    pub fn admit(&self, mut record: Record) -> Option<Record> {
+        if record.sequence.is_some() {
+            return Some(record);
+        }
+
+        let mut keys = self.0.keys.lock().unwrap();
+        match keys.get_mut(&record.partition_key) {
+            Some(queue) => {
+                queue.push_back(record);
+                None
+            }
+            None => {
+                keys.insert(record.partition_key.clone(), VecDeque::new());
+                record.sequence = Some(SequenceGuard {
+                    inner: self.0.clone(),
+                    partition_key: record.partition_key.clone(),
+                });
+                Some(record)
+            }
+        }
+    }
+}
+
+impl Drop for SequenceGuard {
+    fn drop(&mut self) {
+        let next = {
+            let mut keys = self.inner.keys.lock().unwrap();
+            let next = keys
+                .get_mut(&self.partition_key)
+                .and_then(|queue| queue.pop_front());
+
+            if next.is_none() {
+                keys.remove(&self.partition_key);
+            }
+            next
+        };
+
+        if let Some(mut record) = next {
+            record.sequence = Some(SequenceGuard {
+                inner: self.inner.clone(),
+                partition_key: std::mem::take(&mut self.partition_key),
+            });
+
+            self.inner.sender.push(record);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use futures::{FutureExt, StreamExt};
+    use tokio::time::Instant;
+
+    use crate::queue::Overflow;
+
+    use super::*;
+
+    fn record(partition_key: &str, data: &'static [u8]) -> Record {
+        Record {
+            partition_key: partition_key.to_string(),
+            explicit_hash_key: None,
+            data: Bytes::from_static(data),
+            predicted_shard_id: None,
+            acker: None,
+            children: vec![],
+            attempts: 0,
+            pending: None,
+            sequence: None,
+            deadline: None,
+            enqueued: Instant::now(),
+            span: tracing::Span::none(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_sequencer() {
+        let (tx, mut rx) = queue::channel(10, Overflow::Block);
+        let sequencer = Sequencer::new(tx);
+
+        let a1 = sequencer.admit(record("a", b"1")).unwrap();
+        let b1 = sequencer.admit(record("b", b"1")).unwrap();
+        assert!(sequencer.admit(record("a", b"2")).is_none());
+        assert!(sequencer.admit(record("a", b"3")).is_none());
+
+        // A retry of an in-flight record is not held back
+        let a1 = sequencer.admit(a1).unwrap();
+
+        drop(b1);
+        a1.ack(Ok(producer::Ack {
+            shard_id: None,
+            sequence_number: "1".to_string(),
+        }));
+
+        let a2 = rx.next().await.unwrap();
+        assert_eq!(a2.data.as_ref(), b"2");
+        let a2 = sequencer.admit(a2).unwrap();
+
+        assert!(sequencer.admit(record("a", b"4")).is_none());
+        assert!(rx.next().now_or_never().is_none());
+
+        drop(a2);
+        assert_eq!(rx.next().await.unwrap().data.as_ref(), b"3");
+    }
+}
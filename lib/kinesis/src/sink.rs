@@ -1,4 +1,7 @@
+use std::collections::HashMap;
 use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 use std::task::{Context, Poll};
 use std::time::Duration;
 
@@ -7,26 +10,39 @@ use futures::prelude::*;
 use futures::stream::FuturesUnordered;
 use pin_project::pin_project;
 use rusoto_kinesis::{
-    Kinesis, KinesisClient, PutRecordsInput, PutRecordsOutput, PutRecordsRequestEntry,
-    PutRecordsResultEntry,
+    PutRecordsInput, PutRecordsOutput, PutRecordsRequestEntry, PutRecordsResultEntry,
 };
 use tokio::sync::mpsc;
 use tokio::task::JoinHandle;
-use tokio::time::DelayQueue;
-use tracing::{error, info};
+use tokio::time::{DelayQueue, Instant};
+use tracing::{error, info, info_span, warn, Instrument};
 
-use crate::producer::{Ack, Record};
-use crate::shutdown;
+use crate::adaptive::AdaptiveRates;
+use crate::client::KinesisApi;
+use crate::metrics::PipelineMetrics;
+use crate::producer::{self, Ack, DeadLetter, Record};
+use crate::queue;
+use crate::request::RequestPolicy;
+use crate::retry::RetryPolicy;
 use crate::topology::{TopologyGeneration, TopologyService};
 
 #[derive(Clone)]
 pub(crate) struct ErrorHandler {
-    retry: mpsc::Sender<Record>,
-    topology: TopologyService,
+    retry: mpsc::Sender<(Record, Duration)>,
+    topology: Option<TopologyService>,
+    failure_policy: RetryPolicy,
+    throughput_policy: RetryPolicy,
+    dead_letter: DeadLetter,
+    metrics: PipelineMetrics,
+    rates: AdaptiveRates,
+    /// The number of records awaiting retry
+    retrying: Arc<AtomicUsize>,
+    /// When a request to the destination last succeeded
+    last_success: Arc<Mutex<Option<Instant>>>,
 }
 
 #[derive(Debug)]
-enum Error {
+pub(crate) enum Error {
     ThroughputExceeded,
     InternalFailure,
     IncorrectShardPrediction(TopologyGeneration),
@@ -34,31 +50,50 @@ enum Error {
 }
 
 impl ErrorHandler {
+    /// Creates a new ErrorHandler and the worker that re-submits records after their backoff
+    ///
+    /// The worker runs until `finished`, at which point any records still awaiting retry
+    /// are acknowledged with `Error::Shutdown`
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
-        mut retry: mpsc::Sender<Record>,
-        topology: TopologyService,
-        backoff_delay: Duration,
-        mut shutdown: shutdown::Receiver,
+        retry: queue::Sender,
+        topology: Option<TopologyService>,
+        failure_policy: RetryPolicy,
+        throughput_policy: RetryPolicy,
+        dead_letter: DeadLetter,
+        metrics: PipelineMetrics,
+        rates: AdaptiveRates,
+        mut finished: shutdown::Receiver,
     ) -> (ErrorHandler, BoxFuture<'static, ()>) {
         let (tx, mut rx) = mpsc::channel(10);
 
-        let mut delay = DelayQueue::<Record>::new();
+        let mut delay = DelayQueue::<u64>::new();
+        let mut delayed = HashMap::<u64, Record>::new();
+        let mut next_id = 0;
+
+        let retrying = Arc::new(AtomicUsize::new(0));
+        let retrying_worker = retrying.clone();
 
         let worker = async move {
             loop {
                 tokio::select! {
-                    _ = &mut shutdown => break,
+                    _ = &mut finished => break,
                     recv = rx.recv() => match recv {
-                        Some(record) => {
-                            info!("adding record to backoff queue");
-                            delay.insert(record, backoff_delay);
+                        Some((record, backoff)) => {
+                            info!(?backoff, "adding record to backoff queue");
+                            delay.insert(next_id, backoff);
+                            delayed.insert(next_id, record);
+                            retrying_worker.store(delayed.len(), Ordering::Relaxed);
+                            next_id += 1;
                         },
                         None => break
                     },
                     next = poll_fn(|cx| Pin::new(&mut delay).poll_expired(cx)), if !delay.is_empty() => match next {
-                        Some(Ok(record)) => {
+                        Some(Ok(expired)) => {
                             info!("retrying record");
-                            let _ = retry.send(record.into_inner()).await;
+                            let record = delayed.remove(expired.get_ref()).unwrap();
+                            retrying_worker.store(delayed.len(), Ordering::Relaxed);
+                            retry.push(record);
                         },
                         Some(Err(e)) => {
                             error!("timeout error - dropping record: {:?}", e);
@@ -68,6 +103,14 @@ impl ErrorHandler {
                 }
             }
 
+            if !delayed.is_empty() {
+                warn!(count = delayed.len(), "abandoning records awaiting retry");
+            }
+
+            for (_, record) in delayed {
+                record.ack(Err(producer::Error::Shutdown));
+            }
+
             info!("retry worker exited")
         };
 
@@ -75,46 +118,193 @@ impl ErrorHandler {
             ErrorHandler {
                 retry: tx,
                 topology,
+                failure_policy,
+                throughput_policy,
+                dead_letter,
+                metrics,
+                rates,
+                retrying,
+                last_success: Default::default(),
             },
             Box::pin(worker),
         )
     }
 
-    async fn recover(&mut self, record: Record, error: Error) {
-        if let Error::IncorrectShardPrediction(generation) = error {
-            self.topology.invalidate(generation).await;
+    pub fn metrics(&self) -> &PipelineMetrics {
+        &self.metrics
+    }
+
+    /// Returns the number of records awaiting retry, shared with the retry worker
+    pub fn retrying(&self) -> Arc<AtomicUsize> {
+        self.retrying.clone()
+    }
+
+    /// Returns when a request to the destination last succeeded, shared with the sink
+    pub fn last_success(&self) -> Arc<Mutex<Option<Instant>>> {
+        self.last_success.clone()
+    }
+
+    /// Records a request to the destination succeeding, even if some of its records failed
+    pub fn succeeded(&self) {
+        *self.last_success.lock().unwrap() = Some(Instant::now());
+    }
+
+    pub async fn recover(&mut self, record: Record, error: Error) {
+        let policy = match error {
+            Error::ThroughputExceeded => {
+                let shard_id = record.predicted_shard_id.as_ref().map(|(id, _)| *id);
+                self.metrics.throttled(shard_id);
+                self.rates.throttled(shard_id);
+                self.throughput_policy.clone()
+            }
+            _ => self.failure_policy.clone(),
+        };
+
+        if let (Error::IncorrectShardPrediction(generation), Some(topology)) =
+            (error, self.topology.as_mut())
+        {
+            topology.invalidate(generation).await;
         }
 
         if !record.children.is_empty() {
             for child in record.children {
-                let _ = self.retry.send(child).await;
+                self.retry(child, &policy).await;
             }
         } else {
-            let _ = self.retry.send(record).await;
+            self.retry(record, &policy).await;
+        }
+    }
+
+    async fn retry(&mut self, mut record: Record, policy: &RetryPolicy) {
+        record.attempts += 1;
+
+        if policy.exhausted(record.attempts) {
+            self.dead_letter(record);
+            return;
+        }
+
+        let backoff = policy.backoff(record.attempts);
+        if let Some(deadline) = record.deadline {
+            if Instant::now() + backoff >= deadline {
+                warn!(
+                    attempts = record.attempts,
+                    partition_key = %record.partition_key,
+                    "record expired - failing record"
+                );
+                record.ack(Err(producer::Error::Expired));
+                return;
+            }
+        }
+
+        self.metrics.retried();
+        let _ = self.retry.send((record, backoff)).await;
+    }
+
+    fn dead_letter(&self, record: Record) {
+        self.dead_letter
+            .send(record, producer::Error::RetriesExhausted, &self.metrics);
+    }
+
+    /// Acknowledges `record` with its result from the destination, recovering it if it failed
+    pub async fn complete(&mut self, record: Record, result: Result<Ack, Error>) {
+        match result {
+            Ok(ack) => record.ack(Ok(ack)),
+            Err(e) => {
+                error!("record error: {:?}", e);
+                self.recover(record, e).await;
+            }
+        }
+    }
+
+    /// Fails `record`, rejected by validation, to the dead letter
+    pub(crate) fn reject(&self, record: Record, error: producer::Error) {
+        self.dead_letter.send(record, error, &self.metrics);
+    }
+}
+
+/// The maximum size of a single Kinesis record, including its partition key
+const MAX_RECORD_BYTES: usize = 1024 * 1024;
+
+pub(crate) fn validate(record: &Record) -> Result<(), producer::Error> {
+    if record.len() + record.partition_key.len() > MAX_RECORD_BYTES {
+        return Err(producer::Error::RecordTooLarge);
+    }
+    Ok(())
+}
+
+/// Sends a batch of records to the destination with `request`, tracking it as in flight
+///
+/// The response is passed to `handle_response` to acknowledge or recover each record, and
+/// if the request fails, including timing out, every record is recovered
+pub(crate) fn send_batch<F, Fut, T, E, H, HFut>(
+    policy: RequestPolicy,
+    mut error_handler: ErrorHandler,
+    records: Vec<Record>,
+    request: F,
+    handle_response: H,
+) -> impl Future<Output = ()>
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+    E: std::fmt::Debug,
+    H: FnOnce(T, Vec<Record>, ErrorHandler) -> HFut,
+    HFut: Future<Output = ()>,
+{
+    let in_flight = error_handler.metrics().sent(&records);
+    async move {
+        let _in_flight = in_flight;
+        match policy.call(error_handler.metrics(), request).await {
+            Ok(response) => {
+                error_handler.succeeded();
+                handle_response(response, records, error_handler).await
+            }
+            Err(e) => {
+                error!("error sending records: {:?}", e);
+                for record in records {
+                    error_handler.recover(record, Error::InternalFailure).await;
+                }
+            }
+        }
+    }
+}
+
+/// Polls the requests of a sink until they have all completed, for the sink's `poll_flush`
+pub(crate) fn poll_drain<F: Future>(
+    mut in_flight: Pin<&mut FuturesUnordered<F>>,
+    cx: &mut Context<'_>,
+) -> Poll<Result<(), ()>> {
+    loop {
+        match in_flight.as_mut().poll_next(cx) {
+            Poll::Ready(Some(_)) => {}
+            Poll::Ready(None) => return Poll::Ready(Ok(())),
+            Poll::Pending => return Poll::Pending,
         }
     }
 }
 
 #[pin_project]
-pub(crate) struct KinesisSink {
-    client: KinesisClient,
+pub(crate) struct KinesisSink<C> {
+    client: C,
     stream_name: String,
     error_handler: ErrorHandler,
+    request: RequestPolicy,
 
     #[pin]
     in_flight: FuturesUnordered<JoinHandle<()>>,
 }
 
-impl KinesisSink {
+impl<C: KinesisApi> KinesisSink<C> {
     pub fn new(
-        client: KinesisClient,
+        client: C,
         stream_name: String,
         error_handler: ErrorHandler,
-    ) -> KinesisSink {
+        request: RequestPolicy,
+    ) -> KinesisSink<C> {
         KinesisSink {
             client,
             stream_name,
             error_handler,
+            request,
             in_flight: Default::default(),
         }
     }
@@ -136,7 +326,7 @@ fn handle_record(response: PutRecordsResultEntry, record: &Record) -> Result<Ack
             }
 
             Ok(Ack {
-                shard_id,
+                shard_id: Some(shard_id),
                 sequence_number,
             })
         }
@@ -148,26 +338,20 @@ fn handle_record(response: PutRecordsResultEntry, record: &Record) -> Result<Ack
 async fn handle_response(
     response: PutRecordsOutput,
     records: Vec<Record>,
-    error_handler: &mut ErrorHandler,
+    mut error_handler: ErrorHandler,
 ) {
     for (response, record) in response.records.into_iter().zip(records.into_iter()) {
-        match handle_record(response, &record) {
-            Ok(ack) => {
-                record.ack(Ok(ack));
-            }
-            Err(e) => {
-                error!("record error: {:?}", e);
-                error_handler.recover(record, e).await;
-            }
-        }
+        let result = handle_record(response, &record);
+        error_handler.complete(record, result).await;
     }
 }
 
-impl Sink<Vec<Record>> for KinesisSink {
+impl<C: KinesisApi> Sink<Vec<Record>> for KinesisSink<C> {
     type Error = ();
 
-    fn poll_ready(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
-        Poll::Ready(Ok(()))
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.project();
+        this.request.poll_capacity(this.in_flight, cx)
     }
 
     fn start_send(self: Pin<&mut Self>, item: Vec<Record>) -> Result<(), Self::Error> {
@@ -181,7 +365,7 @@ impl Sink<Vec<Record>> for KinesisSink {
             .iter()
             .map(|record| PutRecordsRequestEntry {
                 data: record.data.clone(),
-                explicit_hash_key: None,
+                explicit_hash_key: record.explicit_hash_key.map(|x| x.to_string()),
                 partition_key: record.partition_key.clone(),
             })
             .collect();
@@ -191,20 +375,33 @@ impl Sink<Vec<Record>> for KinesisSink {
             stream_name: self.stream_name.clone(),
         };
 
-        let mut error_handler = self.error_handler.clone();
         let client = self.client.clone();
 
-        let task = tokio::spawn(async move {
-            match client.put_records(input).await {
-                Ok(response) => handle_response(response, item, &mut error_handler).await,
-                Err(e) => {
-                    error!("error putting records: {:?}", e);
-                    for record in item {
-                        error_handler.recover(record, Error::InternalFailure).await;
-                    }
-                }
-            }
-        });
+        // The request follows from the submission of each of its records, which may
+        // belong to different traces
+        let span = info_span!(
+            "put_records",
+            stream = self.stream_name.as_str(),
+            count = item.len()
+        );
+        for record in &item {
+            record.follows(&span);
+        }
+
+        let task = tokio::spawn(
+            send_batch(
+                self.request,
+                self.error_handler.clone(),
+                item,
+                move || {
+                    let client = client.clone();
+                    let input = input.clone();
+                    async move { client.put_records(input).await }
+                },
+                handle_response,
+            )
+            .instrument(span),
+        );
 
         self.in_flight.push(task);
 
@@ -212,17 +409,160 @@ impl Sink<Vec<Record>> for KinesisSink {
     }
 
     fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
-        let mut this = self.project();
-        loop {
-            match this.in_flight.as_mut().poll_next(cx) {
-                Poll::Ready(Some(_)) => {}
-                Poll::Ready(None) => return Poll::Ready(Ok(())),
-                Poll::Pending => return Poll::Pending,
-            }
-        }
+        poll_drain(self.project().in_flight, cx)
     }
 
     fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
         self.poll_flush(cx)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use tokio::sync::oneshot;
+
+    use crate::queue::Overflow;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_dead_letter() {
+        let (sender, mut receiver) = queue::channel(10, Overflow::Block);
+        let (_shutdown_tx, shutdown_rx) = shutdown::channel();
+
+        let dead_lettered = Arc::new(AtomicUsize::new(0));
+        let captured = dead_lettered.clone();
+
+        let (mut error_handler, worker) = ErrorHandler::new(
+            sender,
+            None,
+            RetryPolicy::fixed(Duration::from_millis(1)).max_attempts(2),
+            RetryPolicy::default(),
+            DeadLetter::callback(move |_| {
+                captured.fetch_add(1, Ordering::SeqCst);
+            }),
+            PipelineMetrics::new("test_dead_letter".to_string()),
+            AdaptiveRates::new(1, 1, false),
+            shutdown_rx,
+        );
+        tokio::spawn(worker);
+
+        let (tx, rx) = oneshot::channel();
+        let record = Record {
+            partition_key: "a".to_string(),
+            explicit_hash_key: None,
+            data: Bytes::from_static(b"hello"),
+            predicted_shard_id: None,
+            acker: Some(tx),
+            children: vec![],
+            attempts: 0,
+            pending: None,
+            sequence: None,
+            deadline: None,
+            enqueued: Instant::now(),
+            span: tracing::Span::none(),
+        };
+
+        error_handler.recover(record, Error::InternalFailure).await;
+        let record = receiver.next().await.unwrap();
+        assert_eq!(record.attempts, 1);
+        assert_eq!(dead_lettered.load(Ordering::SeqCst), 0);
+
+        error_handler.recover(record, Error::InternalFailure).await;
+        assert!(matches!(
+            rx.await.unwrap(),
+            Err(producer::Error::RetriesExhausted)
+        ));
+        assert_eq!(dead_lettered.load(Ordering::SeqCst), 1);
+        assert_eq!(error_handler.metrics().snapshot().retried, 1);
+    }
+
+    #[tokio::test]
+    async fn test_expired() {
+        let (sender, _receiver) = queue::channel(10, Overflow::Block);
+        let (_shutdown_tx, shutdown_rx) = shutdown::channel();
+
+        let (mut error_handler, worker) = ErrorHandler::new(
+            sender,
+            None,
+            RetryPolicy::fixed(Duration::from_secs(1)),
+            RetryPolicy::default(),
+            DeadLetter::Fail,
+            PipelineMetrics::new("test_expired".to_string()),
+            AdaptiveRates::new(1, 1, false),
+            shutdown_rx,
+        );
+        tokio::spawn(worker);
+
+        let (tx, rx) = oneshot::channel();
+        let record = Record {
+            partition_key: "a".to_string(),
+            explicit_hash_key: None,
+            data: Bytes::from_static(b"hello"),
+            predicted_shard_id: None,
+            acker: Some(tx),
+            children: vec![],
+            attempts: 0,
+            pending: None,
+            sequence: None,
+            // Would expire before the backoff elapses
+            deadline: Some(Instant::now() + Duration::from_millis(500)),
+            enqueued: Instant::now(),
+            span: tracing::Span::none(),
+        };
+
+        error_handler.recover(record, Error::InternalFailure).await;
+        assert!(matches!(rx.await.unwrap(), Err(producer::Error::Expired)));
+        assert_eq!(error_handler.metrics().snapshot().retried, 0);
+    }
+
+    #[tokio::test]
+    async fn test_reject() {
+        let (sender, _receiver) = queue::channel(10, Overflow::Block);
+        let (_shutdown_tx, shutdown_rx) = shutdown::channel();
+        let (dead_tx, mut dead_rx) = futures::channel::mpsc::channel(10);
+
+        let (error_handler, worker) = ErrorHandler::new(
+            sender,
+            None,
+            RetryPolicy::default(),
+            RetryPolicy::default(),
+            DeadLetter::sink(dead_tx),
+            PipelineMetrics::new("test_reject".to_string()),
+            AdaptiveRates::new(1, 1, false),
+            shutdown_rx,
+        );
+        tokio::spawn(worker);
+
+        let (tx, rx) = oneshot::channel();
+        let record = Record {
+            partition_key: "a".to_string(),
+            explicit_hash_key: None,
+            data: Bytes::from(vec![0; MAX_RECORD_BYTES]),
+            predicted_shard_id: None,
+            acker: Some(tx),
+            children: vec![],
+            attempts: 0,
+            pending: None,
+            sequence: None,
+            deadline: None,
+            enqueued: Instant::now(),
+            span: tracing::Span::none(),
+        };
+
+        let error = validate(&record).unwrap_err();
+        error_handler.reject(record, error);
+
+        assert!(matches!(
+            rx.await.unwrap(),
+            Err(producer::Error::RecordTooLarge)
+        ));
+        let dead = dead_rx.next().await.unwrap();
+        assert_eq!(dead.partition_key, "a");
+        assert_eq!(dead.data.len(), MAX_RECORD_BYTES);
+
+        let snapshot = error_handler.metrics().snapshot();
+        assert_eq!(snapshot.dead_lettered["too_large"], 1);
+    }
+}
@@ -0,0 +1,845 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use futures::stream::FuturesUnordered;
+use futures::{FutureExt, StreamExt};
+use tokio::sync::{oneshot, Notify};
+use tokio::time::{Duration, Instant};
+use tracing::{error, info, warn, Span};
+
+use crate::drain::Pending;
+use crate::producer::{Ack, Error, Record, Router};
+
+/// The size of the frame header, a 4 byte length followed by a 4 byte checksum
+const HEADER_BYTES: u64 = 8;
+
+/// The maximum size of a single segment file
+const MAX_SEGMENT_BYTES: u64 = 16 * 1024 * 1024;
+
+const SEGMENT_EXTENSION: &str = "log";
+
+/// Identifies a record by its segment and offset within that segment
+type Position = (u64, u64);
+
+type Acker = oneshot::Sender<Result<Ack, Error>>;
+
+/// A record read back from the spill buffer
+pub(crate) struct SpilledRecord {
+    pub stream: Option<String>,
+    pub partition_key: String,
+    pub explicit_hash_key: Option<u128>,
+    pub deadline: Option<Instant>,
+    pub data: Bytes,
+}
+
+struct Segment {
+    bytes: u64,
+    /// Records read from this segment that are yet to be acknowledged
+    outstanding: usize,
+    /// No more records will be appended to this segment
+    sealed: bool,
+    /// All records in this segment have been read
+    read: bool,
+}
+
+/// The segment being appended to
+struct Head {
+    id: u64,
+    file: File,
+}
+
+/// The segment being read, and the offset of its next record
+struct Cursor {
+    id: u64,
+    file: File,
+    offset: u64,
+}
+
+/// The in-memory state of the buffer, which is only locked to update it and never
+/// whilst performing file I/O
+struct Index {
+    dir: PathBuf,
+    max_bytes: u64,
+    segment_bytes: u64,
+    /// The bytes of every segment, and of the frames being appended
+    total_bytes: u64,
+    segments: BTreeMap<u64, Segment>,
+    /// The id of the next segment, which is never reused as deleting a segment's file
+    /// may race with creating the next one
+    next_id: u64,
+    ackers: HashMap<Position, Acker>,
+    /// The forwarder has exited and no more records will be read
+    closed: bool,
+}
+
+/// A disk-backed buffer of records awaiting delivery, stored as a sequence of
+/// append-only segment files
+///
+/// Segments are deleted once all their records have been acknowledged, and any
+/// segments remaining when the buffer is opened are replayed. Records that fail
+/// with `Error::Shutdown` are left on disk to be replayed on the next startup,
+/// and so records may be delivered more than once
+///
+/// Writes are not fsynced, and so records survive process restarts but not
+/// necessarily the loss of the host
+///
+/// Segments are written, read and deleted on blocking tasks, with `head` and `cursor`
+/// only ever locked by those tasks, so that file I/O never stalls the runtime nor
+/// holds up the lock of the index
+#[derive(Clone)]
+pub(crate) struct Spill {
+    index: Arc<Mutex<Index>>,
+    head: Arc<Mutex<Option<Head>>>,
+    cursor: Arc<Mutex<Option<Cursor>>>,
+    notify: Arc<Notify>,
+}
+
+fn segment_path(dir: &Path, id: u64) -> PathBuf {
+    dir.join(format!("{:020}.{}", id, SEGMENT_EXTENSION))
+}
+
+fn checksum(payload: &[u8]) -> u32 {
+    let mut digest = &md5::compute(payload).0[..];
+    digest.get_u32()
+}
+
+fn put_string(buf: &mut BytesMut, s: &str) {
+    buf.put_u32(s.len() as u32);
+    buf.put_slice(s.as_bytes());
+}
+
+fn get_string(buf: &mut Bytes) -> Option<String> {
+    if buf.remaining() < 4 {
+        return None;
+    }
+    let len = buf.get_u32() as usize;
+    if buf.remaining() < len {
+        return None;
+    }
+    String::from_utf8(buf.split_to(len).to_vec()).ok()
+}
+
+/// Converts `deadline` to milliseconds since the UNIX epoch, so that it survives restarts
+fn to_epoch_millis(deadline: Instant) -> u64 {
+    let expiry = SystemTime::now() + deadline.saturating_duration_since(Instant::now());
+    expiry
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+fn from_epoch_millis(millis: u64) -> Instant {
+    let expiry = UNIX_EPOCH + Duration::from_millis(millis);
+    Instant::now() + expiry.duration_since(SystemTime::now()).unwrap_or_default()
+}
+
+fn encode(
+    stream: Option<&str>,
+    partition_key: &str,
+    explicit_hash_key: Option<u128>,
+    deadline: Option<Instant>,
+    data: &[u8],
+) -> BytesMut {
+    let stream = stream.unwrap_or("");
+    let mut payload = BytesMut::with_capacity(stream.len() + partition_key.len() + data.len() + 34);
+    put_string(&mut payload, stream);
+    put_string(&mut payload, partition_key);
+    match explicit_hash_key {
+        Some(hash_key) => {
+            payload.put_u8(1);
+            payload.put_u128(hash_key);
+        }
+        None => payload.put_u8(0),
+    }
+    match deadline {
+        Some(deadline) => {
+            payload.put_u8(1);
+            payload.put_u64(to_epoch_millis(deadline));
+        }
+        None => payload.put_u8(0),
+    }
+    payload.put_slice(data);
+
+    let mut frame = BytesMut::with_capacity(payload.len() + HEADER_BYTES as usize);
+    frame.put_u32(payload.len() as u32);
+    frame.put_u32(checksum(&payload));
+    frame.put_slice(&payload);
+    frame
+}
+
+fn decode(mut payload: Bytes) -> Option<SpilledRecord> {
+    let stream = Some(get_string(&mut payload)?).filter(|x| !x.is_empty());
+    let partition_key = get_string(&mut payload)?;
+
+    if payload.remaining() < 1 {
+        return None;
+    }
+    let explicit_hash_key = match payload.get_u8() {
+        0 => None,
+        _ if payload.remaining() >= 16 => Some(payload.get_u128()),
+        _ => return None,
+    };
+
+    if payload.remaining() < 1 {
+        return None;
+    }
+    let deadline = match payload.get_u8() {
+        0 => None,
+        _ if payload.remaining() >= 8 => Some(from_epoch_millis(payload.get_u64())),
+        _ => return None,
+    };
+
+    Some(SpilledRecord {
+        stream,
+        partition_key,
+        explicit_hash_key,
+        deadline,
+        data: payload,
+    })
+}
+
+fn delete_segment(path: &Path) {
+    if let Err(e) = std::fs::remove_file(path) {
+        error!("failed to delete spill segment: {:?}", e)
+    }
+}
+
+impl Index {
+    /// Removes the segment `id` if all its records have been read and acknowledged,
+    /// returning the path of its file to delete
+    fn take_if_done(&mut self, id: u64) -> Option<PathBuf> {
+        let done = match self.segments.get(&id) {
+            Some(segment) => segment.sealed && segment.read && segment.outstanding == 0,
+            None => false,
+        };
+
+        if !done {
+            return None;
+        }
+
+        let segment = self.segments.remove(&id).unwrap();
+        self.total_bytes -= segment.bytes;
+        info!(id, "deleting spill segment");
+        Some(segment_path(&self.dir, id))
+    }
+}
+
+impl Spill {
+    /// Opens the spill buffer in `dir`, creating it if necessary, with any existing
+    /// segments queued for replay
+    pub fn open(dir: PathBuf, max_bytes: u64) -> std::io::Result<Spill> {
+        std::fs::create_dir_all(&dir)?;
+
+        let mut segments = BTreeMap::new();
+        let mut total_bytes = 0;
+
+        for entry in std::fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|x| x.to_str()) != Some(SEGMENT_EXTENSION) {
+                continue;
+            }
+
+            let id = match path.file_stem().and_then(|x| x.to_str()?.parse().ok()) {
+                Some(id) => id,
+                None => continue,
+            };
+
+            let bytes = std::fs::metadata(&path)?.len();
+            total_bytes += bytes;
+            segments.insert(
+                id,
+                Segment {
+                    bytes,
+                    outstanding: 0,
+                    sealed: true,
+                    read: false,
+                },
+            );
+        }
+
+        if !segments.is_empty() {
+            info!(
+                segments = segments.len(),
+                bytes = total_bytes,
+                "replaying spill buffer"
+            );
+        }
+
+        let next_id = segments.keys().next_back().map(|x| x + 1).unwrap_or(0);
+        Ok(Spill {
+            index: Arc::new(Mutex::new(Index {
+                dir,
+                max_bytes,
+                segment_bytes: (max_bytes / 8).clamp(1, MAX_SEGMENT_BYTES),
+                total_bytes,
+                segments,
+                next_id,
+                ackers: Default::default(),
+                closed: false,
+            })),
+            head: Default::default(),
+            cursor: Default::default(),
+            notify: Arc::new(Notify::new()),
+        })
+    }
+
+    /// Appends a record to the buffer, `acker` will be notified once it is delivered
+    ///
+    /// The record's `deadline` is persisted as wall-clock time, and so continues to
+    /// elapse whilst the process is stopped
+    pub async fn append(
+        &self,
+        stream: Option<&str>,
+        partition_key: &str,
+        explicit_hash_key: Option<u128>,
+        deadline: Option<Instant>,
+        data: &[u8],
+        acker: Acker,
+    ) -> Result<(), Error> {
+        let frame = encode(stream, partition_key, explicit_hash_key, deadline, data);
+        let len = frame.len() as u64;
+
+        {
+            let mut index = self.index.lock().unwrap();
+            if index.closed {
+                return Err(Error::Shutdown);
+            }
+
+            if index.total_bytes + len > index.max_bytes {
+                warn!("spill buffer full - rejecting record");
+                return Err(Error::BufferFull);
+            }
+
+            // Reserve the frame's bytes whilst it is written, so that concurrent appends
+            // can't together exceed `max_bytes`
+            index.total_bytes += len;
+        }
+
+        let spill = self.clone();
+        let written = tokio::task::spawn_blocking(move || spill.write(&frame, acker))
+            .await
+            .unwrap_or_else(|e| Err(std::io::Error::other(e)));
+
+        match written {
+            Ok(()) => {
+                self.notify.notify();
+                Ok(())
+            }
+            Err(e) => {
+                self.index.lock().unwrap().total_bytes -= len;
+                error!("failed to write to spill buffer: {:?}", e);
+                Err(Error::BufferFull)
+            }
+        }
+    }
+
+    /// Writes `frame` to the head segment, rotating it if necessary, and registers
+    /// `acker` once the frame is fully written, so that the frame is never read before
+    /// it can be acknowledged
+    ///
+    /// Blocks on file I/O, and so must only be called on a blocking task
+    fn write(&self, frame: &[u8], acker: Acker) -> std::io::Result<()> {
+        let mut head = self.head.lock().unwrap();
+        let len = frame.len() as u64;
+
+        let rotate = {
+            let index = self.index.lock().unwrap();
+            match head.as_ref().and_then(|head| index.segments.get(&head.id)) {
+                Some(segment) => {
+                    segment.sealed
+                        || (segment.bytes > 0 && segment.bytes + len > index.segment_bytes)
+                }
+                None => true,
+            }
+        };
+
+        if rotate {
+            let (id, path, deleted) = {
+                let mut index = self.index.lock().unwrap();
+                let deleted = head.take().and_then(|head| {
+                    if let Some(segment) = index.segments.get_mut(&head.id) {
+                        segment.sealed = true;
+                    }
+                    index.take_if_done(head.id)
+                });
+
+                let id = index.next_id;
+                index.next_id += 1;
+                (id, segment_path(&index.dir, id), deleted)
+            };
+
+            if let Some(deleted) = deleted {
+                delete_segment(&deleted);
+            }
+
+            let file = OpenOptions::new()
+                .create_new(true)
+                .append(true)
+                .open(path)?;
+
+            info!(id, "created spill segment");
+            self.index.lock().unwrap().segments.insert(
+                id,
+                Segment {
+                    bytes: 0,
+                    outstanding: 0,
+                    sealed: false,
+                    read: false,
+                },
+            );
+            *head = Some(Head { id, file });
+        }
+
+        let id = head.as_ref().unwrap().id;
+        let written = head.as_mut().unwrap().file.write_all(frame);
+
+        let mut index = self.index.lock().unwrap();
+        let segment = index.segments.get_mut(&id).filter(|x| !x.sealed);
+        let offset = match (written, segment) {
+            (Ok(()), Some(segment)) => {
+                let offset = segment.bytes;
+                segment.bytes += len;
+                offset
+            }
+            (written, segment) => {
+                // Seal the segment so that a partially written frame is never read
+                if let Some(segment) = segment {
+                    segment.sealed = true;
+                }
+                *head = None;
+                let sealed = || std::io::Error::other("spill segment sealed whilst appending");
+                return Err(written.err().unwrap_or_else(sealed));
+            }
+        };
+
+        if index.closed {
+            let _ = acker.send(Err(Error::Shutdown));
+        } else {
+            index.ackers.insert((id, offset), acker);
+        }
+        Ok(())
+    }
+
+    /// Reads the next unread record, returning None if there are none
+    ///
+    /// Blocks on file I/O, and so must only be called on a blocking task
+    fn read(&self) -> std::io::Result<Option<(Position, SpilledRecord)>> {
+        let mut current = self.cursor.lock().unwrap();
+        loop {
+            if current.is_none() {
+                let next = {
+                    let index = self.index.lock().unwrap();
+                    index
+                        .segments
+                        .iter()
+                        .find(|(_, segment)| !segment.read)
+                        .map(|(id, _)| (*id, segment_path(&index.dir, *id)))
+                };
+
+                let (id, path) = match next {
+                    Some(next) => next,
+                    None => return Ok(None),
+                };
+
+                *current = Some(Cursor {
+                    id,
+                    file: File::open(path)?,
+                    offset: 0,
+                });
+            }
+
+            let cursor = current.as_mut().unwrap();
+            let id = cursor.id;
+
+            // Segments are only deleted once read, and so this one remains in the index
+            let (bytes, sealed) = {
+                let index = self.index.lock().unwrap();
+                let segment = &index.segments[&id];
+                (segment.bytes, segment.sealed)
+            };
+
+            if cursor.offset + HEADER_BYTES <= bytes {
+                let mut header = [0; HEADER_BYTES as usize];
+                cursor.file.seek(SeekFrom::Start(cursor.offset))?;
+                cursor.file.read_exact(&mut header)?;
+
+                let mut header = &header[..];
+                let len = header.get_u32() as u64;
+                let expected = header.get_u32();
+
+                if cursor.offset + HEADER_BYTES + len <= bytes {
+                    let mut payload = vec![0; len as usize];
+                    cursor.file.read_exact(&mut payload)?;
+
+                    let position = (id, cursor.offset);
+                    cursor.offset += HEADER_BYTES + len;
+
+                    let decoded = match checksum(&payload) == expected {
+                        true => decode(payload.into()),
+                        false => None,
+                    };
+
+                    match decoded {
+                        Some(record) => {
+                            let mut index = self.index.lock().unwrap();
+                            index.segments.get_mut(&id).unwrap().outstanding += 1;
+                            return Ok(Some((position, record)));
+                        }
+                        None => {
+                            error!(id, "corrupt spill record - skipping");
+                            continue;
+                        }
+                    }
+                }
+
+                if sealed {
+                    warn!(id, "truncated spill segment");
+                } else {
+                    // A frame only counts towards the bytes of its segment once it is fully
+                    // written, and a failed append seals the segment, so this should only
+                    // occur if the file was modified externally. Seal the segment, so that
+                    // the next append rotates it, and drop the torn frame
+                    error!(
+                        id,
+                        offset = cursor.offset,
+                        "torn frame in head spill segment - truncating"
+                    );
+                    let path = {
+                        let mut index = self.index.lock().unwrap();
+                        let segment = index.segments.get_mut(&id).unwrap();
+                        let torn = segment.bytes - cursor.offset;
+                        segment.bytes = cursor.offset;
+                        segment.sealed = true;
+                        index.total_bytes -= torn;
+                        segment_path(&index.dir, id)
+                    };
+
+                    let truncated = OpenOptions::new()
+                        .write(true)
+                        .open(path)
+                        .and_then(|file| file.set_len(cursor.offset));
+
+                    if let Err(e) = truncated {
+                        error!("failed to truncate spill segment: {:?}", e)
+                    }
+                }
+            } else if !sealed {
+                return Ok(None);
+            }
+
+            let deleted = {
+                let mut index = self.index.lock().unwrap();
+                index.segments.get_mut(&id).unwrap().read = true;
+                index.take_if_done(id)
+            };
+            *current = None;
+
+            if let Some(deleted) = deleted {
+                delete_segment(&deleted);
+            }
+        }
+    }
+
+    /// Returns the next unread record, waiting for one to be appended if necessary
+    ///
+    /// The record is consumed even if the returned future is dropped once polled, and
+    /// so it must be polled to completion
+    pub async fn next(&self) -> (Position, SpilledRecord) {
+        loop {
+            let spill = self.clone();
+            let next = tokio::task::spawn_blocking(move || spill.read())
+                .await
+                .unwrap_or_else(|e| Err(std::io::Error::other(e)));
+
+            match next {
+                Ok(Some(next)) => return next,
+                Ok(None) => {}
+                Err(e) => error!("failed to read from spill buffer: {:?}", e),
+            }
+            self.notify.notified().await
+        }
+    }
+
+    /// Records the outcome of delivering a record
+    ///
+    /// Records that failed due to shutdown are retained to be replayed on restart
+    pub async fn complete(&self, position: Position, result: Result<Ack, Error>) {
+        let deleted = {
+            let mut index = self.index.lock().unwrap();
+            let retain = matches!(result, Err(Error::Shutdown) | Err(Error::AckDropped));
+
+            if let Some(acker) = index.ackers.remove(&position) {
+                let _ = acker.send(result);
+            }
+
+            if retain {
+                return;
+            }
+
+            let (id, _) = position;
+            if let Some(segment) = index.segments.get_mut(&id) {
+                segment.outstanding -= 1;
+            }
+            index.take_if_done(id)
+        };
+
+        if let Some(deleted) = deleted {
+            let deleted = tokio::task::spawn_blocking(move || delete_segment(&deleted)).await;
+            if let Err(e) = deleted {
+                error!("failed to delete spill segment: {:?}", e)
+            }
+        }
+    }
+
+    /// Fails the records yet to be read with `Error::Shutdown`, leaving them on disk
+    /// to be replayed on restart, and rejects any further appends
+    pub fn close(&self) {
+        let mut index = self.index.lock().unwrap();
+        index.closed = true;
+
+        if !index.ackers.is_empty() {
+            info!(
+                records = index.ackers.len(),
+                "closing spill buffer with unread records"
+            );
+        }
+
+        for (_, acker) in index.ackers.drain() {
+            let _ = acker.send(Err(Error::Shutdown));
+        }
+    }
+}
+
+/// Forwards records from the spill buffer into the pipeline until shutdown,
+/// waits for those already forwarded to be acknowledged, and then fails those
+/// remaining with `Error::Shutdown`
+pub(crate) async fn forward(
+    spill: Spill,
+    mut router: Router,
+    pending: Pending,
+    mut shutdown: shutdown::Receiver,
+) {
+    let mut acks = FuturesUnordered::new();
+    // The read in progress is kept across iterations, as dropping it would lose its record
+    let mut next = spill.next().boxed();
+
+    loop {
+        tokio::select! {
+            _ = &mut shutdown => break,
+            Some((position, result)) = acks.next(), if !acks.is_empty() => {
+                spill.complete(position, result).await
+            },
+            (position, spilled) = &mut next => {
+                next = spill.next().boxed();
+                let (tx, rx) = oneshot::channel();
+                let record = Record {
+                    partition_key: spilled.partition_key,
+                    explicit_hash_key: spilled.explicit_hash_key,
+                    data: spilled.data,
+                    predicted_shard_id: None,
+                    acker: Some(tx),
+                    children: vec![],
+                    attempts: 0,
+                    pending: Some(pending.track()),
+                    sequence: None,
+                    deadline: spilled.deadline,
+                    enqueued: Instant::now(),
+                    span: Span::none(),
+                };
+
+                acks.push(rx.map(move |result| (position, result.unwrap_or(Err(Error::AckDropped)))));
+                if router.send(spilled.stream.as_deref(), record).await.is_err() {
+                    break
+                }
+            }
+        }
+    }
+
+    while let Some((position, result)) = acks.next().await {
+        spill.complete(position, result).await
+    }
+
+    spill.close();
+    info!("spill forwarder exited")
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::Rng;
+
+    use super::*;
+
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new() -> TempDir {
+            let suffix: u64 = rand::thread_rng().gen();
+            TempDir(std::env::temp_dir().join(format!("kinesis-spill-{}", suffix)))
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn ack() -> Result<Ack, Error> {
+        Ok(Ack {
+            shard_id: None,
+            sequence_number: "1".to_string(),
+        })
+    }
+
+    fn segments(dir: &Path) -> usize {
+        std::fs::read_dir(dir).unwrap().count()
+    }
+
+    #[tokio::test]
+    async fn test_roundtrip() {
+        let dir = TempDir::new();
+        let spill = Spill::open(dir.0.clone(), 1024).unwrap();
+
+        let (tx, rx) = oneshot::channel();
+        let deadline = Instant::now() + Duration::from_secs(60);
+        spill
+            .append(Some("b"), "a", Some(5), Some(deadline), b"hello", tx)
+            .await
+            .unwrap();
+
+        let (position, record) = spill.next().await;
+        assert_eq!(record.stream.as_deref(), Some("b"));
+        assert_eq!(record.partition_key, "a");
+        assert_eq!(record.explicit_hash_key, Some(5));
+        let restored = record.deadline.unwrap();
+        assert!(restored <= deadline + Duration::from_millis(10));
+        assert!(restored + Duration::from_millis(10) >= deadline);
+        assert_eq!(record.data.as_ref(), b"hello");
+
+        spill.complete(position, ack()).await;
+        assert_eq!(rx.await.unwrap().unwrap().sequence_number, "1");
+
+        let (tx, _rx) = oneshot::channel();
+        assert!(matches!(
+            spill.append(None, "a", None, None, &[0; 1024], tx).await,
+            Err(Error::BufferFull)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_segments() {
+        let dir = TempDir::new();
+        let spill = Spill::open(dir.0.clone(), 8 * 64).unwrap();
+
+        let mut positions = vec![];
+        for _ in 0..3 {
+            let (tx, _rx) = oneshot::channel();
+            spill
+                .append(None, "a", None, None, &[0; 40], tx)
+                .await
+                .unwrap();
+            positions.push(spill.next().await.0);
+        }
+        assert_eq!(segments(&dir.0), 3);
+
+        for position in positions {
+            spill.complete(position, ack()).await;
+        }
+        // The head segment is retained until sealed
+        assert_eq!(segments(&dir.0), 1);
+    }
+
+    #[tokio::test]
+    async fn test_replay() {
+        let dir = TempDir::new();
+
+        {
+            let spill = Spill::open(dir.0.clone(), 1024).unwrap();
+            for key in &["a", "b"] {
+                let (tx, _rx) = oneshot::channel();
+                spill
+                    .append(None, key, None, None, b"data", tx)
+                    .await
+                    .unwrap();
+            }
+
+            let (position, _) = spill.next().await;
+            spill.complete(position, Err(Error::Shutdown)).await;
+        }
+
+        let spill = Spill::open(dir.0.clone(), 1024).unwrap();
+        assert_eq!(spill.next().await.1.partition_key, "a");
+        assert_eq!(spill.next().await.1.partition_key, "b");
+
+        let (tx, _rx) = oneshot::channel();
+        spill
+            .append(None, "c", None, None, b"data", tx)
+            .await
+            .unwrap();
+        assert_eq!(spill.next().await.1.partition_key, "c");
+    }
+
+    #[tokio::test]
+    async fn test_close() {
+        let dir = TempDir::new();
+        let spill = Spill::open(dir.0.clone(), 1024).unwrap();
+
+        let (tx, rx) = oneshot::channel();
+        spill
+            .append(None, "a", None, None, b"data", tx)
+            .await
+            .unwrap();
+        spill.close();
+        assert!(matches!(rx.await.unwrap(), Err(Error::Shutdown)));
+
+        let (tx, _rx) = oneshot::channel();
+        assert!(matches!(
+            spill.append(None, "b", None, None, b"data", tx).await,
+            Err(Error::Shutdown)
+        ));
+
+        // Unread records are retained for replay
+        let spill = Spill::open(dir.0.clone(), 1024).unwrap();
+        assert_eq!(spill.next().await.1.partition_key, "a");
+    }
+
+    #[tokio::test]
+    async fn test_torn_frame() {
+        let dir = TempDir::new();
+        let spill = Spill::open(dir.0.clone(), 1024).unwrap();
+
+        let (tx, _rx) = oneshot::channel();
+        spill
+            .append(None, "a", None, None, b"data", tx)
+            .await
+            .unwrap();
+
+        {
+            let mut head = spill.head.lock().unwrap();
+            let head = head.as_mut().unwrap();
+            // A header for a frame whose payload was never written
+            head.file.write_all(&[0, 0, 0, 100, 0, 0, 0, 0]).unwrap();
+
+            let mut index = spill.index.lock().unwrap();
+            index.segments.get_mut(&head.id).unwrap().bytes += HEADER_BYTES;
+            index.total_bytes += HEADER_BYTES;
+        }
+
+        assert_eq!(spill.next().await.1.partition_key, "a");
+        assert!(spill.read().unwrap().is_none());
+
+        // The torn segment is sealed and so records are appended to a new one
+        let (tx, _rx) = oneshot::channel();
+        spill
+            .append(None, "b", None, None, b"data", tx)
+            .await
+            .unwrap();
+        assert_eq!(spill.next().await.1.partition_key, "b");
+        assert_eq!(segments(&dir.0), 2);
+    }
+}
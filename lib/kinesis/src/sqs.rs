@@ -0,0 +1,261 @@
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use futures::prelude::*;
+use futures::stream::FuturesUnordered;
+use pin_project::pin_project;
+use rusoto_sqs::{
+    SendMessageBatchInput, SendMessageBatchOutput, SendMessageBatchRequestEntry, Sqs, SqsClient,
+};
+use tokio::task::JoinHandle;
+use tracing::{error, info};
+
+use crate::producer::{self, Ack, Record};
+use crate::request::RequestPolicy;
+use crate::sink::{self, ErrorHandler};
+
+/// The maximum size of a single SQS message
+pub(crate) const MAX_MESSAGE_BYTES: usize = 256 * 1024;
+
+pub(crate) fn validate(record: &Record) -> Result<(), producer::Error> {
+    if record.len() > MAX_MESSAGE_BYTES {
+        return Err(producer::Error::RecordTooLarge);
+    }
+
+    if std::str::from_utf8(&record.data).is_err() {
+        return Err(producer::Error::InvalidRecord);
+    }
+    Ok(())
+}
+
+#[pin_project]
+pub(crate) struct SqsSink {
+    client: SqsClient,
+    queue_url: String,
+    fifo: bool,
+    error_handler: ErrorHandler,
+    request: RequestPolicy,
+
+    #[pin]
+    in_flight: FuturesUnordered<JoinHandle<()>>,
+}
+
+impl SqsSink {
+    pub fn new(
+        client: SqsClient,
+        queue_url: String,
+        error_handler: ErrorHandler,
+        request: RequestPolicy,
+    ) -> SqsSink {
+        SqsSink {
+            fifo: queue_url.ends_with(".fifo"),
+            client,
+            queue_url,
+            error_handler,
+            request,
+            in_flight: Default::default(),
+        }
+    }
+}
+
+async fn handle_response(
+    response: SendMessageBatchOutput,
+    records: Vec<Record>,
+    mut error_handler: ErrorHandler,
+) {
+    let mut records: HashMap<String, Record> = records
+        .into_iter()
+        .enumerate()
+        .map(|(idx, record)| (idx.to_string(), record))
+        .collect();
+
+    for success in response.successful {
+        if let Some(record) = records.remove(&success.id) {
+            record.ack(Ok(Ack {
+                shard_id: None,
+                sequence_number: success.message_id,
+            }));
+        }
+    }
+
+    for failure in response.failed {
+        if let Some(record) = records.remove(&failure.id) {
+            error!(
+                code = %failure.code,
+                message = ?failure.message,
+                "message error"
+            );
+
+            if failure.sender_fault {
+                record.ack(Err(producer::Error::InvalidRecord));
+            } else {
+                error_handler
+                    .recover(record, sink::Error::InternalFailure)
+                    .await;
+            }
+        }
+    }
+
+    for (_, record) in records {
+        error!("message missing from response");
+        error_handler
+            .recover(record, sink::Error::InternalFailure)
+            .await;
+    }
+}
+
+impl Sink<Vec<Record>> for SqsSink {
+    type Error = ();
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        let this = self.project();
+        this.request.poll_capacity(this.in_flight, cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: Vec<Record>) -> Result<(), Self::Error> {
+        if item.is_empty() {
+            return Ok(());
+        }
+
+        info!(count = item.len(), "submitting messages");
+
+        let fifo = self.fifo;
+        let entries = item
+            .iter()
+            .enumerate()
+            .map(|(idx, record)| SendMessageBatchRequestEntry {
+                id: idx.to_string(),
+                // Records are validated as UTF-8 before batching
+                message_body: String::from_utf8_lossy(&record.data).into_owned(),
+                message_group_id: if fifo {
+                    Some(record.partition_key.clone())
+                } else {
+                    None
+                },
+                ..Default::default()
+            })
+            .collect();
+
+        let input = SendMessageBatchInput {
+            entries,
+            queue_url: self.queue_url.clone(),
+        };
+
+        let client = self.client.clone();
+        let task = tokio::spawn(sink::send_batch(
+            self.request,
+            self.error_handler.clone(),
+            item,
+            move || {
+                let client = client.clone();
+                let input = input.clone();
+                async move { client.send_message_batch(input).await }
+            },
+            handle_response,
+        ));
+
+        self.in_flight.push(task);
+
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        sink::poll_drain(self.project().in_flight, cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.poll_flush(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use futures::StreamExt;
+    use rusoto_sqs::{BatchResultErrorEntry, SendMessageBatchResultEntry};
+    use tokio::sync::oneshot;
+    use tokio::time::{Duration, Instant};
+
+    use crate::adaptive::AdaptiveRates;
+    use crate::metrics::PipelineMetrics;
+    use crate::producer::DeadLetter;
+    use crate::queue::{self, Overflow};
+    use crate::retry::RetryPolicy;
+
+    use super::*;
+
+    fn record(data: &'static [u8]) -> (Record, oneshot::Receiver<Result<Ack, producer::Error>>) {
+        let (tx, rx) = oneshot::channel();
+        let record = Record {
+            partition_key: "a".to_string(),
+            explicit_hash_key: None,
+            data: Bytes::from_static(data),
+            predicted_shard_id: None,
+            acker: Some(tx),
+            children: vec![],
+            attempts: 0,
+            pending: None,
+            sequence: None,
+            deadline: None,
+            enqueued: Instant::now(),
+            span: tracing::Span::none(),
+        };
+        (record, rx)
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(validate(&record(b"hello").0).is_ok());
+        assert!(matches!(
+            validate(&record(&[0xFF, 0xFE]).0),
+            Err(producer::Error::InvalidRecord)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_handle_response() {
+        let (sender, mut receiver) = queue::channel(10, Overflow::Block);
+        let (_shutdown_tx, shutdown_rx) = shutdown::channel();
+        let (error_handler, worker) = ErrorHandler::new(
+            sender,
+            None,
+            RetryPolicy::fixed(Duration::from_millis(1)),
+            RetryPolicy::default(),
+            DeadLetter::Fail,
+            PipelineMetrics::new("test_handle_response".to_string()),
+            AdaptiveRates::new(1, 1, false),
+            shutdown_rx,
+        );
+        tokio::spawn(worker);
+
+        let (a, a_rx) = record(b"a");
+        let (b, b_rx) = record(b"b");
+        let (c, _c_rx) = record(b"c");
+
+        let response = SendMessageBatchOutput {
+            successful: vec![SendMessageBatchResultEntry {
+                id: "0".to_string(),
+                message_id: "message".to_string(),
+                ..Default::default()
+            }],
+            failed: vec![BatchResultErrorEntry {
+                id: "1".to_string(),
+                code: "InvalidMessageContents".to_string(),
+                sender_fault: true,
+                ..Default::default()
+            }],
+        };
+
+        handle_response(response, vec![a, b, c], error_handler).await;
+
+        assert_eq!(a_rx.await.unwrap().unwrap().sequence_number, "message");
+        assert!(matches!(
+            b_rx.await.unwrap(),
+            Err(producer::Error::InvalidRecord)
+        ));
+
+        let retried = receiver.next().await.unwrap();
+        assert_eq!(retried.data.as_ref(), b"c");
+    }
+}
@@ -0,0 +1,89 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use tokio::time::{Duration, Instant};
+
+use crate::adaptive::AdaptiveRates;
+use crate::queue;
+use crate::topology::TopologyService;
+
+/// A point-in-time snapshot of the state of a pipeline
+#[derive(Debug, Clone, Default)]
+pub struct PipelineStats {
+    /// False if the pipeline worker has exited, following shutdown or a panic, after
+    /// which records are no longer delivered
+    pub alive: bool,
+    /// Requests to the destination awaiting a response
+    pub in_flight: i64,
+    /// The state of each destination, keyed by stream name
+    pub streams: HashMap<String, StreamStats>,
+}
+
+/// A point-in-time snapshot of the state of a single destination stream
+#[derive(Debug, Clone, Default)]
+pub struct StreamStats {
+    /// Records waiting to enter the pipeline
+    pub queued: usize,
+    /// Records waiting for their retry backoff to elapse
+    pub retrying: usize,
+    /// The time since a request to the destination last succeeded, None if yet to succeed
+    pub since_success: Option<Duration>,
+    /// The rate limit of each shard, unpartitioned pipelines report under "none"
+    pub shards: HashMap<String, ShardStats>,
+    /// None for Firehose and SQS pipelines
+    pub topology: Option<TopologyStats>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ShardStats {
+    pub records_per_second: u64,
+    pub bytes_per_second: u64,
+    /// If the rate limit is reduced following throttling
+    pub throttled: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct TopologyStats {
+    pub generation: u64,
+    pub open_shards: usize,
+    /// The time since the stream's shards were last listed, None if yet to succeed
+    pub since_refresh: Option<Duration>,
+}
+
+/// The components of a stream's pipeline that its statistics are collected from
+pub(crate) struct StreamStatsSource {
+    pub queue: queue::Sender,
+    pub retrying: Arc<AtomicUsize>,
+    pub last_success: Arc<Mutex<Option<Instant>>>,
+    pub rates: AdaptiveRates,
+    pub topology: Option<TopologyService>,
+}
+
+impl StreamStatsSource {
+    pub fn snapshot(&self) -> StreamStats {
+        StreamStats {
+            queued: self.queue.len(),
+            retrying: self.retrying.load(Ordering::Relaxed),
+            since_success: self.last_success.lock().unwrap().map(|x| x.elapsed()),
+            shards: self.rates.stats(),
+            topology: self.topology.as_ref().map(|x| x.stats()),
+        }
+    }
+}
+
+/// Clears the shared flag when dropped, including when the task owning it panics
+pub(crate) struct AliveGuard(pub Arc<AtomicBool>);
+
+impl AliveGuard {
+    pub fn new() -> (AliveGuard, Arc<AtomicBool>) {
+        let alive = Arc::new(AtomicBool::new(true));
+        (AliveGuard(alive.clone()), alive)
+    }
+}
+
+impl Drop for AliveGuard {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::Relaxed)
+    }
+}
@@ -1,12 +1,18 @@
-use crate::shutdown;
+use crate::client::KinesisApi;
+use crate::metrics;
+use crate::stats::TopologyStats;
 use futures::future::BoxFuture;
 use futures::FutureExt;
 use rusoto_core::RusotoError;
-use rusoto_kinesis::{Kinesis, KinesisClient, ListShardsError, ListShardsInput};
+use rusoto_kinesis::{
+    DescribeStreamSummaryError, DescribeStreamSummaryInput, ListShardsError, ListShardsInput,
+};
+use std::collections::HashSet;
 use std::convert::{TryFrom, TryInto};
 use std::str::FromStr;
+use std::sync::{Arc, Mutex};
 use tokio::sync::{mpsc, watch};
-use tokio::time::{delay_for, Duration};
+use tokio::time::{delay_for, interval_at, Duration, Instant};
 use tracing::{error, info};
 
 #[derive(Debug)]
@@ -22,6 +28,9 @@ pub enum Error {
     InvalidShard,
     InvalidShardMap,
     ListShardsError(String),
+    DescribeStreamSummaryError(String),
+    /// The stream's shards can't be listed as it has the given status, e.g. CREATING
+    StreamNotActive(String),
 }
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
@@ -32,14 +41,22 @@ impl From<RusotoError<ListShardsError>> for Error {
     }
 }
 
+impl From<RusotoError<DescribeStreamSummaryError>> for Error {
+    fn from(e: RusotoError<DescribeStreamSummaryError>) -> Self {
+        Error::DescribeStreamSummaryError(e.to_string())
+    }
+}
+
 #[derive(Debug, Clone, Copy, Hash, Eq, PartialEq)]
 pub struct ShardId(u64);
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Shard {
     id: ShardId,
     starting_hash_key: u128,
     ending_hash_key: u128,
+    /// The shards this shard was split or merged from
+    parents: Vec<ShardId>,
 }
 
 impl FromStr for ShardId {
@@ -79,21 +96,41 @@ impl TryFrom<rusoto_kinesis::Shard> for Shard {
             .parse()
             .map_err(|_| Error::InvalidShard)?;
 
+        let parents = shard
+            .parent_shard_id
+            .iter()
+            .chain(shard.adjacent_parent_shard_id.iter())
+            .map(|x| x.parse())
+            .collect::<Result<_>>()?;
+
         Ok(Shard {
             id,
             starting_hash_key,
             ending_hash_key,
+            parents,
         })
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Topology {
     open_shards: Vec<Shard>,
 }
 
 impl Topology {
-    fn new(mut open_shards: Vec<Shard>) -> Result<Topology> {
+    fn new(open_shards: Vec<Shard>) -> Result<Topology> {
+        // Whilst a split or merge is in progress ListShards may report both a parent
+        // and its children as open, in which case the children own the hash key range
+        let parents: HashSet<_> = open_shards
+            .iter()
+            .flat_map(|shard| shard.parents.iter().cloned())
+            .collect();
+
+        let mut open_shards: Vec<_> = open_shards
+            .into_iter()
+            .filter(|shard| !parents.contains(&shard.id))
+            .collect();
+
         open_shards.sort_by_key(|x| x.starting_hash_key);
 
         let topology = Topology { open_shards };
@@ -135,21 +172,103 @@ impl Topology {
             })
             .unwrap()
     }
+
+    /// Returns a hash key within `shard`, or None if it is not an open shard
+    pub fn shard_hash_key(&self, shard: ShardId) -> Option<u128> {
+        self.open_shards
+            .iter()
+            .find(|x| x.id == shard)
+            .map(|x| x.starting_hash_key)
+    }
+
+    pub fn shard_ids(&self) -> Vec<ShardId> {
+        self.open_shards.iter().map(|x| x.id).collect()
+    }
 }
 
 #[derive(Clone)]
 pub struct TopologyService {
     map: watch::Receiver<Option<(Topology, TopologyGeneration)>>,
     control: mpsc::Sender<ControlMessage>,
+    last_refresh: Arc<Mutex<Option<Instant>>>,
+}
+
+struct TopologyState {
+    stream_name: String,
+    tx: watch::Sender<Option<(Topology, TopologyGeneration)>>,
+    generation: u64,
+    current: Option<Topology>,
+    last_refresh: Arc<Mutex<Option<Instant>>>,
+}
+
+impl TopologyState {
+    /// Clears the published topology, blocking lookups until it is next updated
+    fn clear(&mut self) {
+        self.current = None;
+        self.tx.broadcast(None).unwrap();
+    }
+
+    /// Publishes `topology` as a new generation if it differs from the current topology
+    fn update(&mut self, topology: Topology) {
+        *self.last_refresh.lock().unwrap() = Some(Instant::now());
+        if self.current.as_ref() == Some(&topology) {
+            return;
+        }
+
+        self.generation += 1;
+        metrics::topology_updated(
+            &self.stream_name,
+            self.generation,
+            topology.open_shards.len(),
+        );
+        info!(
+            generation = self.generation,
+            open_shards = topology.open_shards.len(),
+            "stream topology updated"
+        );
+
+        self.current = Some(topology.clone());
+        self.tx
+            .broadcast(Some((topology, TopologyGeneration(self.generation))))
+            .unwrap();
+    }
 }
 
 #[derive(Clone)]
-struct TopologyClient {
-    client: KinesisClient,
+struct TopologyClient<C> {
+    client: C,
     stream_name: String,
 }
 
-impl TopologyClient {
+impl<C: KinesisApi> TopologyClient<C> {
+    /// Returns the topology of the stream
+    ///
+    /// The shards are listed with ListShards, which unlike DescribeStream is rate limited
+    /// per stream rather than per account, so that many pipelines can share an account.
+    /// If listing fails DescribeStreamSummary is used to check the stream is active
+    async fn refresh(&self) -> Result<Topology> {
+        match self.list_shards().await {
+            Err(Error::ListShardsError(e)) => match self.status().await {
+                Ok(status) if !matches!(status.as_str(), "ACTIVE" | "UPDATING") => {
+                    Err(Error::StreamNotActive(status))
+                }
+                _ => Err(Error::ListShardsError(e)),
+            },
+            result => result,
+        }
+    }
+
+    /// Returns the status of the stream, e.g. ACTIVE
+    async fn status(&self) -> Result<String> {
+        let output = self
+            .client
+            .describe_stream_summary(DescribeStreamSummaryInput {
+                stream_name: self.stream_name.clone(),
+            })
+            .await?;
+        Ok(output.stream_description_summary.stream_status)
+    }
+
     async fn list_shards(&self) -> Result<Topology> {
         let mut next_token = None;
         let mut open_shards: Vec<Shard> = Vec::new();
@@ -189,12 +308,17 @@ impl TopologyClient {
 }
 
 impl TopologyService {
-    pub(crate) fn new(
-        client: KinesisClient,
+    /// Creates a new TopologyService, which refreshes the topology when a misprediction
+    /// invalidates it and every `refresh_interval` to detect resharding
+    pub(crate) fn new<C: KinesisApi>(
+        client: C,
         stream_name: String,
+        refresh_interval: Duration,
         mut shutdown: shutdown::Receiver,
     ) -> (TopologyService, BoxFuture<'static, ()>) {
         let (tx, rx) = watch::channel(None);
+        let last_refresh = Arc::new(Mutex::new(None));
+        let worker_refresh = last_refresh.clone();
 
         let (mut control_tx, control_rx) = mpsc::channel(10);
         control_tx
@@ -203,33 +327,45 @@ impl TopologyService {
 
         let worker = async move {
             let mut control_rx = control_rx;
+            let mut refresh = interval_at(Instant::now() + refresh_interval, refresh_interval);
             let client = TopologyClient {
                 client,
                 stream_name,
             };
 
-            let mut generation: u64 = 0;
+            let mut state = TopologyState {
+                stream_name: client.stream_name.clone(),
+                tx,
+                generation: 0,
+                current: None,
+                last_refresh: worker_refresh,
+            };
 
             loop {
                 tokio::select! {
                     _ = &mut shutdown => break,
+                    _ = refresh.tick() => {
+                        info!("periodic refresh of stream topology");
+                        match client.refresh().await {
+                            Ok(topology) => state.update(topology),
+                            Err(e) => error!("error refreshing stream topology: {:?}", e),
+                        }
+                    }
                     msg = control_rx.recv() => {
                         match msg {
                             Some(ControlMessage::Flush(flush_generation)) => {
-                                if flush_generation.0 != generation {
+                                if flush_generation.0 != state.generation {
                                     info!("topology generation already flushed");
                                     continue
                                 }
 
-                                tx.broadcast(None).unwrap();
+                                state.clear();
 
                                 loop {
                                     info!("refreshing stream topology");
-                                    match client.list_shards().await {
-                                        Ok(shards) => {
-                                            generation += 1;
-                                            tx.broadcast(Some((shards, TopologyGeneration(generation)))).unwrap();
-                                            info!(generation, "stream topology updated");
+                                    match client.refresh().await {
+                                        Ok(topology) => {
+                                            state.update(topology);
                                             break;
                                         }
                                         Err(e) => {
@@ -252,12 +388,13 @@ impl TopologyService {
 
             info!("topology worker terminated")
         }
-            .boxed();
+        .boxed();
 
         (
             TopologyService {
                 map: rx,
                 control: control_tx,
+                last_refresh,
             },
             worker,
         )
@@ -273,7 +410,138 @@ impl TopologyService {
         }
     }
 
+    /// Returns the open shards of the stream, waiting for the topology if it is being refreshed
+    pub async fn open_shards(&mut self) -> Vec<ShardId> {
+        loop {
+            if let Some((topology, _)) = self.map.borrow().as_ref() {
+                return topology.shard_ids();
+            }
+
+            self.map.recv().await.unwrap();
+        }
+    }
+
+    /// Returns the open shards of the current topology, or None if it is being refreshed
+    pub fn current_shards(&self) -> Option<Vec<ShardId>> {
+        self.map
+            .borrow()
+            .as_ref()
+            .map(|(topology, _)| topology.shard_ids())
+    }
+
+    /// Returns a hash key that the current topology maps to `shard`
+    pub async fn shard_hash_key(&mut self, shard: ShardId) -> Result<u128> {
+        loop {
+            if let Some((topology, _)) = self.map.borrow().as_ref() {
+                return topology.shard_hash_key(shard).ok_or(Error::InvalidShard);
+            }
+
+            self.map.recv().await.unwrap();
+        }
+    }
+
+    pub fn stats(&self) -> TopologyStats {
+        let (generation, open_shards) = match self.map.borrow().as_ref() {
+            Some((topology, generation)) => (generation.0, topology.open_shards.len()),
+            None => (0, 0),
+        };
+
+        TopologyStats {
+            generation,
+            open_shards,
+            since_refresh: self.last_refresh.lock().unwrap().map(|x| x.elapsed()),
+        }
+    }
+
     pub async fn invalidate(&mut self, generation: TopologyGeneration) {
         let _ = self.control.send(ControlMessage::Flush(generation)).await;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::mock::MockKinesis;
+
+    use super::*;
+
+    fn shard(id: u64, start: u128, end: u128, parents: &[u64]) -> Shard {
+        Shard {
+            id: ShardId(id),
+            starting_hash_key: start,
+            ending_hash_key: end,
+            parents: parents.iter().map(|x| ShardId(*x)).collect(),
+        }
+    }
+
+    #[test]
+    fn test_topology() {
+        let mid = u128::MAX / 2;
+        let topology = Topology::new(vec![
+            shard(1, mid + 1, u128::MAX, &[]),
+            shard(0, 0, mid, &[]),
+        ])
+        .unwrap();
+
+        assert_eq!(topology.get_shard(0), ShardId(0));
+        assert_eq!(topology.get_shard(mid + 1), ShardId(1));
+
+        let hash_key = topology.shard_hash_key(ShardId(1)).unwrap();
+        assert_eq!(topology.get_shard(hash_key), ShardId(1));
+        assert_eq!(topology.shard_hash_key(ShardId(2)), None);
+        assert_eq!(topology.shard_ids(), vec![ShardId(0), ShardId(1)]);
+
+        assert!(Topology::new(vec![shard(0, 0, mid, &[])]).is_err());
+    }
+
+    #[test]
+    fn test_split() {
+        let mid = u128::MAX / 2;
+
+        // Parent still reported as open alongside its children
+        let topology = Topology::new(vec![
+            shard(0, 0, u128::MAX, &[]),
+            shard(1, 0, mid, &[0]),
+            shard(2, mid + 1, u128::MAX, &[0]),
+        ])
+        .unwrap();
+
+        assert_eq!(topology.open_shards.len(), 2);
+        assert_eq!(topology.get_shard(mid), ShardId(1));
+        assert_eq!(topology.get_shard(u128::MAX), ShardId(2));
+
+        // The parent no longer owns any hash keys
+        assert_eq!(topology.shard_hash_key(ShardId(0)), None);
+
+        // Merge of the children back into a single shard
+        let merged = Topology::new(vec![
+            shard(1, 0, mid, &[0]),
+            shard(2, mid + 1, u128::MAX, &[0]),
+            shard(3, 0, u128::MAX, &[1, 2]),
+        ])
+        .unwrap();
+
+        assert_eq!(merged.open_shards.len(), 1);
+        assert_ne!(topology, merged);
+    }
+
+    #[tokio::test]
+    async fn test_refresh() {
+        let mock = MockKinesis::new(2);
+        let client = TopologyClient {
+            client: mock.clone(),
+            stream_name: "test".to_string(),
+        };
+
+        let topology = client.refresh().await.unwrap();
+        assert_eq!(topology.shard_ids(), vec![ShardId(0), ShardId(1)]);
+
+        mock.set_status("CREATING");
+        match client.refresh().await {
+            Err(Error::StreamNotActive(status)) => assert_eq!(status, "CREATING"),
+            result => panic!("expected stream not active, got {:?}", result),
+        }
+
+        mock.set_status("UPDATING");
+        assert!(client.refresh().await.is_ok());
+    }
+}
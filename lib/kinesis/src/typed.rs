@@ -0,0 +1,239 @@
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use serde::Serialize;
+use tracing::error;
+
+use crate::producer::{Ack, Error, Producer, RawRecord};
+
+pub type SerializeError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Encodes values submitted to a `TypedProducer` as record data
+///
+/// This is implemented for closures, allowing custom formats without a new type
+pub trait Serializer<T>: Send + Sync {
+    fn serialize(&self, value: &T) -> Result<Bytes, SerializeError>;
+}
+
+impl<T, F> Serializer<T> for F
+where
+    F: Fn(&T) -> Result<Bytes, SerializeError> + Send + Sync,
+{
+    fn serialize(&self, value: &T) -> Result<Bytes, SerializeError> {
+        self(value)
+    }
+}
+
+/// Encodes values as JSON
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Json;
+
+impl<T: Serialize> Serializer<T> for Json {
+    fn serialize(&self, value: &T) -> Result<Bytes, SerializeError> {
+        Ok(serde_json::to_vec(value)?.into())
+    }
+}
+
+/// Encodes values as MessagePack, with struct fields encoded as maps
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MessagePack;
+
+impl<T: Serialize> Serializer<T> for MessagePack {
+    fn serialize(&self, value: &T) -> Result<Bytes, SerializeError> {
+        Ok(rmp_serde::to_vec_named(value)?.into())
+    }
+}
+
+/// Encodes protobuf messages
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Protobuf;
+
+impl<T: prost::Message> Serializer<T> for Protobuf {
+    fn serialize(&self, value: &T) -> Result<Bytes, SerializeError> {
+        let mut buf = Vec::with_capacity(value.encoded_len());
+        value.encode(&mut buf)?;
+        Ok(buf.into())
+    }
+}
+
+/// A `Producer` that accepts values of type `T`, encoding them with a `Serializer`
+/// and deriving their partition key with a user-provided function
+pub struct TypedProducer<T> {
+    producer: Producer,
+    serializer: Arc<dyn Serializer<T>>,
+    partition_key: Arc<dyn Fn(&T) -> String + Send + Sync>,
+    stream: Option<String>,
+    _phantom: PhantomData<fn(&T)>,
+}
+
+impl<T> Clone for TypedProducer<T> {
+    fn clone(&self) -> Self {
+        TypedProducer {
+            producer: self.producer.clone(),
+            serializer: self.serializer.clone(),
+            partition_key: self.partition_key.clone(),
+            stream: self.stream.clone(),
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl<T> TypedProducer<T> {
+    pub fn new<S, F>(producer: Producer, serializer: S, partition_key: F) -> TypedProducer<T>
+    where
+        S: Serializer<T> + 'static,
+        F: Fn(&T) -> String + Send + Sync + 'static,
+    {
+        TypedProducer {
+            producer,
+            serializer: Arc::new(serializer),
+            partition_key: Arc::new(partition_key),
+            stream: None,
+            _phantom: PhantomData,
+        }
+    }
+
+    /// Configures the stream records are delivered to, which must have been registered
+    /// with the pipeline. Defaults to the pipeline's stream
+    pub fn stream(mut self, stream: String) -> Self {
+        self.stream = Some(stream);
+        self
+    }
+
+    fn encode(&self, value: &T) -> Result<RawRecord, Error> {
+        let data = self.serializer.serialize(value).map_err(|e| {
+            error!("failed to serialize record: {}", e);
+            Error::InvalidRecord
+        })?;
+
+        Ok(RawRecord {
+            partition_key: (self.partition_key)(value),
+            data,
+            explicit_hash_key: None,
+            stream: self.stream.clone(),
+            idempotency_id: None,
+            ttl_ms: None,
+        })
+    }
+
+    /// Submits values to the pipeline, returning their results in the same order
+    ///
+    /// Values that fail to serialize are failed with `Error::InvalidRecord`
+    pub async fn submit<'a>(
+        &mut self,
+        values: impl IntoIterator<Item = &'a T>,
+    ) -> Vec<Result<Ack, Error>>
+    where
+        T: 'a,
+    {
+        let mut records = Vec::new();
+        let mut errors = Vec::new();
+        for value in values {
+            match self.encode(value) {
+                Ok(record) => {
+                    records.push(record);
+                    errors.push(None);
+                }
+                Err(e) => errors.push(Some(e)),
+            }
+        }
+
+        let mut submitted = self.producer.submit(records.into_iter()).await.into_iter();
+        errors
+            .into_iter()
+            .map(|x| match x {
+                Some(e) => Err(e),
+                None => submitted.next().unwrap(),
+            })
+            .collect()
+    }
+
+    /// Submits a single value to the pipeline
+    pub async fn submit_one(&mut self, value: &T) -> Result<Ack, Error> {
+        let record = self.encode(value)?;
+        self.producer.submit_one(record).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use futures::StreamExt;
+
+    use crate::drain::Pending;
+    use crate::metrics::PipelineMetrics;
+    use crate::producer::Router;
+    use crate::queue::{self, Overflow};
+
+    use super::*;
+
+    struct Event {
+        user: String,
+        value: u32,
+    }
+
+    fn serialize(event: &Event) -> Result<Bytes, SerializeError> {
+        if event.value == 0 {
+            return Err("zero value".into());
+        }
+        Ok(format!("{}={}", event.user, event.value).into())
+    }
+
+    #[tokio::test]
+    async fn test_typed() {
+        let (tx, mut rx) = queue::channel(10, Overflow::Block);
+        let (_shutdown_tx, shutdown_rx) = shutdown::channel();
+
+        let mut senders = HashMap::new();
+        senders.insert("a".to_string(), tx);
+        let producer = Producer::new(
+            Router::new("a".to_string(), senders, HashMap::new()),
+            PipelineMetrics::new("test_typed".to_string()),
+            Pending::new(),
+            shutdown_rx,
+            None,
+            None,
+            None,
+        );
+
+        let mut typed = TypedProducer::new(producer, serialize, |x: &Event| x.user.clone());
+        let events = vec![
+            Event {
+                user: "bob".to_string(),
+                value: 1,
+            },
+            Event {
+                user: "alice".to_string(),
+                value: 0,
+            },
+            Event {
+                user: "alice".to_string(),
+                value: 2,
+            },
+        ];
+
+        let acker = tokio::spawn(async move {
+            for sequence_number in 0..2 {
+                let record = rx.next().await.unwrap();
+                let expected = ["bob=1", "alice=2"][sequence_number];
+                assert_eq!(record.data.as_ref(), expected.as_bytes());
+                assert_eq!(record.partition_key, &expected[..expected.len() - 2]);
+
+                record.ack(Ok(Ack {
+                    shard_id: None,
+                    sequence_number: sequence_number.to_string(),
+                }));
+            }
+        });
+
+        let results = typed.submit(&events).await;
+        acker.await.unwrap();
+
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0].as_ref().unwrap().sequence_number, "0");
+        assert!(matches!(results[1], Err(Error::InvalidRecord)));
+        assert_eq!(results[2].as_ref().unwrap().sequence_number, "1");
+    }
+}
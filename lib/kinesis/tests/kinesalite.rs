@@ -0,0 +1,58 @@
+//! Runs a producer pipeline against a kinesalite container started by `testutil`
+
+use bytes::Bytes;
+use futures::StreamExt;
+use tokio::time::{timeout, Duration};
+
+use kinesis::consumer::{ConsumerBuilder, StartingPosition};
+use kinesis::producer::RawRecord;
+use kinesis::PipelineBuilder;
+use testutil::{Kinesalite, REGION};
+
+const STREAM_NAME: &str = "test";
+
+#[tokio::test]
+async fn test_pipeline() {
+    let kinesalite = Kinesalite::start();
+    kinesalite.create_stream(STREAM_NAME, 1).await;
+
+    let mut builder = PipelineBuilder::new(REGION.to_string(), STREAM_NAME.to_string());
+    builder.local().endpoint(kinesalite.endpoint()).batch(
+        1024 * 1024,
+        500,
+        Duration::from_millis(100),
+    );
+    let (mut producer, handler) = builder.build();
+
+    let records = (0..10).map(|idx| RawRecord {
+        partition_key: format!("key-{}", idx),
+        data: Bytes::from(idx.to_string()),
+        explicit_hash_key: None,
+        stream: None,
+        idempotency_id: None,
+        ttl_ms: None,
+    });
+
+    let mut shard_id = None;
+    for result in producer.submit(records).await {
+        shard_id = result.unwrap().shard_id;
+    }
+    handler.shutdown().await.unwrap();
+
+    let mut builder = ConsumerBuilder::new(REGION.to_string(), STREAM_NAME.to_string());
+    builder
+        .local()
+        .endpoint(kinesalite.endpoint())
+        .poll_interval(Duration::from_millis(100));
+    let consumer = builder.build();
+
+    let records = consumer
+        .shard(shard_id.unwrap(), StartingPosition::TrimHorizon)
+        .take(10)
+        .map(|x| x.unwrap().data)
+        .collect::<Vec<_>>();
+
+    let records = timeout(Duration::from_secs(30), records).await.unwrap();
+    let expected: Vec<_> = (0..10).map(|idx| Bytes::from(idx.to_string())).collect();
+    assert_eq!(records, expected);
+}
@@ -0,0 +1,107 @@
+//! Runs a producer pipeline against LocalStack
+//!
+//! These tests are ignored by default, run them with `make test_localstack`
+
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bytes::Bytes;
+use futures::StreamExt;
+use rusoto_core::request::HttpClient;
+use rusoto_kinesis::{CreateStreamInput, DescribeStreamSummaryInput, Kinesis, KinesisClient};
+use tokio::time::{timeout, Duration};
+
+use kinesis::consumer::{ConsumerBuilder, StartingPosition};
+use kinesis::producer::RawRecord;
+use kinesis::PipelineBuilder;
+use rusoto_util::{client_config, wait_ready, Target};
+
+const REGION: &str = "us-east-1";
+
+/// Creates a stream with a unique name, waiting for LocalStack to start if necessary
+async fn create_stream(shard_count: i64) -> String {
+    let (region, credentials) = client_config(REGION.to_string(), None, Target::LocalStack);
+    let client = KinesisClient::new_with(HttpClient::new().unwrap(), credentials, region);
+
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    let stream_name = format!("test-{}", nanos.as_nanos());
+
+    wait_ready(Duration::from_secs(60), || {
+        client.create_stream(CreateStreamInput {
+            shard_count,
+            stream_name: stream_name.clone(),
+        })
+    })
+    .await
+    .expect("failed to create stream - is LocalStack running?");
+
+    wait_ready(Duration::from_secs(30), || async {
+        let output = client
+            .describe_stream_summary(DescribeStreamSummaryInput {
+                stream_name: stream_name.clone(),
+            })
+            .await
+            .map_err(|e| e.to_string())?;
+
+        match output.stream_description_summary.stream_status.as_str() {
+            "ACTIVE" => Ok(()),
+            status => Err(format!("stream is {}", status)),
+        }
+    })
+    .await
+    .unwrap();
+
+    stream_name
+}
+
+#[tokio::test]
+#[ignore]
+async fn test_pipeline() {
+    let stream_name = create_stream(2).await;
+
+    let mut builder = PipelineBuilder::new(REGION.to_string(), stream_name.clone());
+    builder
+        .localstack()
+        .aggregate(51200, 100, Duration::from_millis(100))
+        .batch(1024 * 1024, 500, Duration::from_millis(100));
+    let (mut producer, handler) = builder.build();
+
+    let records = (0..100).map(|idx| RawRecord {
+        partition_key: format!("key-{}", idx),
+        data: Bytes::from(idx.to_string()),
+        explicit_hash_key: None,
+        stream: None,
+        idempotency_id: None,
+        ttl_ms: None,
+    });
+
+    let mut expected = HashMap::new();
+    for (idx, result) in producer.submit(records).await.into_iter().enumerate() {
+        let ack = result.unwrap();
+        expected
+            .entry(ack.shard_id.unwrap())
+            .or_insert_with(Vec::new)
+            .push((format!("key-{}", idx), Bytes::from(idx.to_string())));
+    }
+    handler.shutdown().await.unwrap();
+
+    let mut builder = ConsumerBuilder::new(REGION.to_string(), stream_name);
+    builder.localstack().poll_interval(Duration::from_millis(100));
+    let consumer = builder.build();
+
+    assert_eq!(consumer.shards().await.unwrap().len(), 2);
+    for (shard_id, expected) in expected {
+        let records = consumer
+            .shard(shard_id, StartingPosition::TrimHorizon)
+            .take(expected.len())
+            .map(|x| {
+                let record = x.unwrap();
+                (record.partition_key, record.data)
+            })
+            .collect::<Vec<_>>();
+
+        // Records for a shard are delivered in submission order
+        let records = timeout(Duration::from_secs(30), records).await.unwrap();
+        assert_eq!(records, expected);
+    }
+}
@@ -0,0 +1,232 @@
+//! The OpenAPI documents of the HTTP APIs of services
+//!
+//! Each route module documents its routes alongside them, with a `document` function
+//! adding their operations to an `OpenApi`, and a service combines those of its modules
+//! into a single document
+//!
+//! ```ignore
+//! let doc = OpenApi::default()
+//!     .with(api::document)
+//!     .with(health::document)
+//!     .build("Calculator", "Performs single operations on values");
+//! ```
+//!
+//! The schemas of requests and responses are generated from the types exchanged, so
+//! they can't drift from the implementation, and `undocumented` lists any routes
+//! missing from the document
+//!
+//! The `rocket` feature adds a route serving the document at `/openapi.json`
+
+use schemars::gen::{SchemaGenerator, SchemaSettings};
+use schemars::JsonSchema;
+use serde_json::{json, Map, Value};
+
+use errors::ErrorBody;
+
+#[cfg(feature = "rocket")]
+mod respond;
+
+#[cfg(feature = "rocket")]
+pub use respond::{routes, undocumented_routes, Spec};
+
+/// Builds an OpenAPI 3.0 document, generating the schemas of the types its operations
+/// take and return
+pub struct OpenApi {
+    generator: SchemaGenerator,
+    paths: Map<String, Value>,
+}
+
+impl Default for OpenApi {
+    fn default() -> Self {
+        OpenApi {
+            generator: SchemaSettings::openapi3().into_generator(),
+            paths: Map::new(),
+        }
+    }
+}
+
+impl OpenApi {
+    /// Adds the operations of a route module, documented by `document`
+    pub fn with(mut self, document: impl FnOnce(&mut OpenApi)) -> Self {
+        document(&mut self);
+        self
+    }
+
+    /// Returns a reference to the schema of `T`, adding it to the components of the
+    /// document
+    pub fn schema<T: JsonSchema>(&mut self) -> Value {
+        serde_json::to_value(self.generator.subschema_for::<T>())
+            .expect("Failed to serialize schema")
+    }
+
+    /// Returns the content of a request or response body of JSON matching `schema`
+    pub fn json(schema: Value) -> Value {
+        json!({ "application/json": { "schema": schema } })
+    }
+
+    /// Returns the content of a request body of a form matching `schema`
+    pub fn form(schema: Value) -> Value {
+        json!({ "application/x-www-form-urlencoded": { "schema": schema } })
+    }
+
+    /// Returns the parameter of a segment of the path, described by `description`
+    pub fn path_parameter(name: &str, description: &str) -> Value {
+        json!({
+            "name": name,
+            "in": "path",
+            "description": description,
+            "required": true,
+            "schema": { "type": "string" },
+        })
+    }
+
+    /// Returns a response of the common error envelope, described by `description`
+    pub fn error(&mut self, description: &str) -> Value {
+        let body = self.schema::<ErrorBody>();
+        json!({ "description": description, "content": OpenApi::json(body) })
+    }
+
+    /// Adds `operation`, an OpenAPI operation object, for `method` on `path`
+    pub fn operation(&mut self, method: &str, path: &str, operation: Value) {
+        let item = self
+            .paths
+            .entry(path.to_string())
+            .or_insert_with(|| json!({}));
+        item[method] = operation;
+    }
+
+    /// Returns the document, where operations requiring a JWT name the `bearer` security
+    /// scheme
+    pub fn build(self, title: &str, description: &str) -> Value {
+        let schemas = serde_json::to_value(self.generator.definitions())
+            .expect("Failed to serialize schemas");
+
+        json!({
+            "openapi": "3.0.3",
+            "info": {
+                "title": title,
+                "description": description,
+                "version": env!("CARGO_PKG_VERSION"),
+            },
+            "paths": self.paths,
+            "components": {
+                "schemas": schemas,
+                "securitySchemes": {
+                    "bearer": { "type": "http", "scheme": "bearer", "bearerFormat": "JWT" },
+                },
+            },
+        })
+    }
+}
+
+/// Adds the operation of the `/metrics` endpoint served by every service
+pub fn metrics(api: &mut OpenApi) {
+    api.operation(
+        "get",
+        "/metrics",
+        json!({
+            "summary": "Returns the Prometheus metrics of the service",
+            "operationId": "metrics",
+            "responses": {
+                "200": {
+                    "description": "The metrics, in the Prometheus text format",
+                    "content": { "text/plain": { "schema": { "type": "string" } } },
+                },
+            },
+        }),
+    );
+}
+
+/// Returns the OpenAPI path of a route's path, where parameters are written as
+/// `<name>`, or `<name..>` for those matching several segments
+fn path(route: &str) -> String {
+    route
+        .split('/')
+        .map(|segment| match segment.strip_prefix('<') {
+            Some(name) => format!("{{{}}}", name.trim_end_matches('>').trim_end_matches("..")),
+            None => segment.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Returns the routes, given as their method and path, that `doc` has no operation for
+pub fn undocumented<'a>(
+    doc: &Value,
+    routes: impl IntoIterator<Item = (&'a str, &'a str)>,
+) -> Vec<String> {
+    routes
+        .into_iter()
+        .filter(|(method, route)| {
+            let method = method.to_ascii_lowercase();
+            doc["paths"][path(route)].get(&method).is_none()
+        })
+        .map(|(method, route)| format!("{} {}", method, route))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(JsonSchema)]
+    #[allow(dead_code)]
+    struct Request {
+        operation: Operation,
+        value: u32,
+    }
+
+    #[derive(JsonSchema)]
+    #[allow(dead_code)]
+    enum Operation {
+        Add,
+        Sub,
+    }
+
+    fn document(api: &mut OpenApi) {
+        let request = api.schema::<Request>();
+        let not_found = api.error("The value doesn't exist");
+        api.operation(
+            "post",
+            "/api/v1/values/{id}",
+            json!({
+                "parameters": [OpenApi::path_parameter("id", "The id of the value")],
+                "requestBody": { "content": OpenApi::json(request) },
+                "responses": { "204": { "description": "Updated" }, "404": not_found },
+            }),
+        );
+    }
+
+    #[test]
+    fn test_build() {
+        let doc = OpenApi::default()
+            .with(document)
+            .build("Values", "Stores values");
+        let update = &doc["paths"]["/api/v1/values/{id}"]["post"];
+        let schema = &update["requestBody"]["content"]["application/json"]["schema"];
+        assert_eq!(schema["$ref"], "#/components/schemas/Request");
+
+        // Types referenced by others are included
+        let schemas = doc["components"]["schemas"].as_object().unwrap();
+        for name in &["Request", "Operation", "ErrorBody"] {
+            assert!(schemas.contains_key(*name), "missing {}", name);
+        }
+    }
+
+    #[test]
+    fn test_undocumented() {
+        let doc = OpenApi::default()
+            .with(document)
+            .build("Values", "Stores values");
+        let routes = vec![
+            ("POST", "/api/v1/values/<id>"),
+            ("GET", "/api/v1/values/<id>"),
+            ("GET", "/static/<path..>"),
+        ];
+        assert_eq!(
+            undocumented(&doc, routes),
+            vec!["GET /api/v1/values/<id>", "GET /static/<path..>"]
+        );
+        assert_eq!(path("/static/<path..>"), "/static/{path}");
+    }
+}
@@ -0,0 +1,35 @@
+use rocket::response::content;
+use rocket::{Route, State};
+use serde_json::Value;
+
+/// The document served at `/openapi.json`, which must be managed by rocket
+pub struct Spec {
+    body: String,
+}
+
+impl Spec {
+    pub fn new(doc: &Value) -> Spec {
+        Spec {
+            body: serde_json::to_string(doc).expect("Failed to serialize document"),
+        }
+    }
+}
+
+/// Returns the OpenAPI document describing the API
+#[rocket::get("/openapi.json")]
+fn openapi(spec: State<'_, Spec>) -> content::Json<String> {
+    content::Json(spec.body.clone())
+}
+
+pub fn routes() -> Vec<Route> {
+    rocket::routes![openapi]
+}
+
+/// Returns the routes that `doc` has no operation for
+pub fn undocumented_routes(doc: &Value, routes: &[Route]) -> Vec<String> {
+    let routes: Vec<_> = routes
+        .iter()
+        .map(|x| (x.method.as_str(), x.uri.path().to_string()))
+        .collect();
+    crate::undocumented(doc, routes.iter().map(|(m, p)| (*m, p.as_str())))
+}
@@ -1,13 +1,24 @@
+use std::collections::HashSet;
+use std::hash::Hash;
+use std::marker::PhantomData;
+use std::str::FromStr;
+
+use rocket::data::Data;
+use rocket::fairing::{Fairing, Info, Kind};
 use rocket::http::Status;
 use rocket::request::{FromRequest, Outcome};
-use rocket::Request;
+use rocket::{Request, Response};
+use tracing::Span;
 
-use jwt::{DefaultClaims, Validator, ValidatorError};
-use rocket::figment::{providers::Env, Figment};
+use jwt::{JwtClaims, Resources, Scope, Validator, ValidatorError};
 
-pub struct Authenticated {
+/// A request guard validating the bearer token of a request
+///
+/// Scopes are parsed as `S`, services with their own scope grammar can use
+/// `Authenticated<String>` to receive them unparsed
+pub struct Authenticated<S = Scope> {
     pub header: String,
-    pub claims: DefaultClaims,
+    pub claims: JwtClaims<S>,
 }
 
 #[derive(Debug)]
@@ -19,10 +30,13 @@ pub enum AuthenticatedError {
 }
 
 #[rocket::async_trait]
-impl<'a, 'r> FromRequest<'a, 'r> for Authenticated {
+impl<'a, 'r, S> FromRequest<'a, 'r> for Authenticated<S>
+where
+    S: FromStr + Hash + Eq + Send + 'static,
+{
     type Error = AuthenticatedError;
 
-    async fn from_request(request: &'a Request<'r>) -> Outcome<Authenticated, Self::Error> {
+    async fn from_request(request: &'a Request<'r>) -> Outcome<Authenticated<S>, Self::Error> {
         let validator = request
             .managed_state::<Validator>()
             .expect("No validator registered");
@@ -31,10 +45,14 @@ impl<'a, 'r> FromRequest<'a, 'r> for Authenticated {
                 return Outcome::Failure((Status::Unauthorized, AuthenticatedError::JwtMissing));
             }
             match validator.validate(auth[7..].trim()) {
-                Ok(claims) => Outcome::Success(Authenticated {
-                    header: auth.to_string(), // TODO: Avoid this copy
-                    claims,
-                }),
+                Ok(claims) => {
+                    let span = request_span(request);
+                    telemetry::trace::record_principal(&span, &claims.cid, claims.sub.as_deref());
+                    Outcome::Success(Authenticated {
+                        header: auth.to_string(), // TODO: Avoid this copy
+                        claims,
+                    })
+                }
                 Err(ValidatorError::JwtExpired) => {
                     Outcome::Failure((Status::Unauthorized, AuthenticatedError::JwtExpired))
                 }
@@ -54,6 +72,46 @@ impl<'a, 'r> FromRequest<'a, 'r> for Authenticated {
     }
 }
 
+/// The prefix of the scopes granting the resources of a `ResourceAccess`, such as
+/// `kinesis:write:`
+pub trait ScopePrefix {
+    const PREFIX: &'static str;
+}
+
+/// A request guard returning the resources a request may access, granted by the
+/// `<P::PREFIX><resource>` scopes of its bearer token, or every resource if it has the
+/// superuser scope
+#[derive(Debug)]
+pub struct ResourceAccess<P> {
+    resources: Resources,
+    prefix: PhantomData<fn() -> P>,
+}
+
+impl<P: ScopePrefix> ResourceAccess<P> {
+    pub fn new(scopes: &HashSet<Scope>) -> ResourceAccess<P> {
+        ResourceAccess {
+            resources: Resources::new(scopes, P::PREFIX),
+            prefix: PhantomData,
+        }
+    }
+
+    pub fn allows(&self, resource: &str) -> bool {
+        self.resources.allows(resource)
+    }
+}
+
+#[rocket::async_trait]
+impl<'a, 'r, P: ScopePrefix> FromRequest<'a, 'r> for ResourceAccess<P> {
+    type Error = AuthenticatedError;
+
+    async fn from_request(request: &'a Request<'r>) -> Outcome<ResourceAccess<P>, Self::Error> {
+        request
+            .guard::<Authenticated>()
+            .await
+            .map(|authenticated| ResourceAccess::new(&authenticated.claims.scopes))
+    }
+}
+
 #[derive(Debug)]
 pub struct UserAgent(pub String);
 
@@ -69,7 +127,53 @@ impl<'a, 'r> FromRequest<'a, 'r> for UserAgent {
     }
 }
 
-pub fn figment() -> Figment {
-    rocket::Config::figment()
-        .merge(Env::prefixed("APP_").map(|s| s.as_str().replacen('_', ".", 1).into()))
+/// A request guard returning the span of a request created by `RequestTracer`, which
+/// is disabled if the fairing isn't attached
+///
+/// Handlers instrument their work with the span, so that the requests they make to
+/// other services continue the trace of the request
+#[derive(Debug, Clone)]
+pub struct RequestSpan(pub Span);
+
+/// Returns the span of `request`, see `RequestSpan`
+pub fn request_span(request: &Request<'_>) -> Span {
+    request.local_cache(|| RequestSpan(Span::none())).0.clone()
+}
+
+#[rocket::async_trait]
+impl<'a, 'r> FromRequest<'a, 'r> for RequestSpan {
+    type Error = ();
+
+    async fn from_request(request: &'a Request<'r>) -> Outcome<Self, Self::Error> {
+        Outcome::Success(RequestSpan(request_span(request)))
+    }
+}
+
+/// Creates a span for every request, continuing the trace carried by its W3C trace
+/// context headers, and records the status of its response
+///
+/// The client and user of a request are recorded on its span by `Authenticated`
+pub struct RequestTracer;
+
+#[rocket::async_trait]
+impl Fairing for RequestTracer {
+    fn info(&self) -> Info {
+        Info {
+            name: "Request Tracer",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, _: &mut Data) {
+        let span = telemetry::trace::server_span(
+            request.method().as_str(),
+            request.uri().path(),
+            |name| request.headers().get_one(name),
+        );
+        request.local_cache(|| RequestSpan(span));
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        request_span(request).record("http.status_code", &response.status().code);
+    }
 }
@@ -1,11 +1,16 @@
 use async_trait::async_trait;
-use log::warn;
+use log::{info, warn};
 use rusoto_core::credential::{
-    AwsCredentials, ChainProvider, CredentialsError, ProvideAwsCredentials,
+    AwsCredentials, ChainProvider, CredentialsError, ProvideAwsCredentials, StaticProvider,
 };
 use rusoto_core::Region;
 use rusoto_sts::WebIdentityProvider;
+use std::future::Future;
 use std::time::Duration;
+use tokio::time::Instant;
+
+/// The edge endpoint of a LocalStack container, unless overridden by `LOCALSTACK_ENDPOINT`
+pub const LOCALSTACK_ENDPOINT: &str = "http://localhost:4566";
 
 // A custom chain provider incorporating web identity support
 // See - https://github.com/rusoto/rusoto/issues/1781
@@ -62,3 +67,102 @@ pub fn parse_region(region: String, endpoint: Option<String>) -> Region {
     }
     region.parse().expect("invalid region")
 }
+
+/// Where a client sends its requests
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    /// AWS, or a custom endpoint, authenticating with the default credential chain
+    Aws,
+    /// A standalone emulator such as kinesalite or DynamoDB Local, which requires an
+    /// endpoint and accepts any credentials
+    Local,
+    /// A LocalStack container, which serves every service from a single edge endpoint
+    /// without service-specific hostnames
+    LocalStack,
+}
+
+impl Target {
+    /// Returns the target for the `local` flag of a service's config
+    pub fn local(local: bool) -> Target {
+        if local {
+            Target::Local
+        } else {
+            Target::Aws
+        }
+    }
+}
+
+/// The credentials a client authenticates with, determined by its `Target`
+pub enum Credentials {
+    Static(StaticProvider),
+    Chain(CustomChainProvider),
+}
+
+#[async_trait]
+impl ProvideAwsCredentials for Credentials {
+    async fn credentials(&self) -> Result<AwsCredentials, CredentialsError> {
+        match self {
+            Credentials::Static(provider) => provider.credentials().await,
+            Credentials::Chain(provider) => provider.credentials().await,
+        }
+    }
+}
+
+/// Returns the region and credentials for a client sending requests to `target`
+///
+/// LocalStack clients default to its edge endpoint, and authenticate as LocalStack's
+/// default account as it derives the account from the access key
+pub fn client_config(
+    region: String,
+    endpoint: Option<String>,
+    target: Target,
+) -> (Region, Credentials) {
+    match target {
+        Target::Aws => (
+            parse_region(region, endpoint),
+            Credentials::Chain(CustomChainProvider::new()),
+        ),
+        Target::Local => (
+            parse_region(region, endpoint),
+            Credentials::Static(StaticProvider::new_minimal(
+                "local".to_string(),
+                "development".to_string(),
+            )),
+        ),
+        Target::LocalStack => {
+            let endpoint = endpoint.unwrap_or_else(|| {
+                std::env::var("LOCALSTACK_ENDPOINT")
+                    .unwrap_or_else(|_| LOCALSTACK_ENDPOINT.to_string())
+            });
+            (
+                parse_region(region, Some(endpoint)),
+                Credentials::Static(StaticProvider::new_minimal(
+                    "test".to_string(),
+                    "test".to_string(),
+                )),
+            )
+        }
+    }
+}
+
+/// Calls `probe` until it succeeds or `timeout` elapses, returning the last error
+///
+/// This is used to wait for a local emulator, such as LocalStack, to start the service under test
+pub async fn wait_ready<F, Fut, T, E>(timeout: Duration, mut probe: F) -> Result<T, E>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+    E: std::fmt::Display,
+{
+    let deadline = Instant::now() + timeout;
+    loop {
+        match probe().await {
+            Ok(result) => return Ok(result),
+            Err(e) if Instant::now() < deadline => {
+                info!("waiting for service to become ready - {}", e);
+                tokio::time::delay_for(Duration::from_millis(500)).await;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
@@ -0,0 +1,34 @@
+use derive_more::Display;
+
+#[derive(Debug, Display)]
+pub enum Error {
+    /// A value is missing or has the wrong type, the error names its key and source
+    #[display(fmt = "{}", _0)]
+    Figment(Box<figment::Error>),
+    #[display(fmt = "Failed to resolve `{}` of `{}`: {}", reference, key, message)]
+    Secret {
+        key: String,
+        reference: String,
+        message: String,
+    },
+    #[display(fmt = "Invalid value of `{}`: {}", key, message)]
+    Invalid { key: String, message: String },
+}
+
+impl std::error::Error for Error {}
+
+impl Error {
+    /// Returns an error describing why the value of `key` is invalid
+    pub fn invalid(key: impl Into<String>, message: impl ToString) -> Error {
+        Error::Invalid {
+            key: key.into(),
+            message: message.to_string(),
+        }
+    }
+}
+
+impl From<figment::Error> for Error {
+    fn from(e: figment::Error) -> Self {
+        Error::Figment(Box::new(e))
+    }
+}
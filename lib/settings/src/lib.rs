@@ -0,0 +1,228 @@
+//! Layered configuration of services
+//!
+//! The configuration of a service is read from, lowest precedence first:
+//!
+//! - the defaults of its configuration type
+//! - a base figment, such as Rocket's, which reads `Rocket.toml`
+//! - the TOML file named by `APP_CONFIG`, if set
+//! - `APP_` environment variables, the first `_` of which separates a section from
+//!   its key, so `APP_KINESIS_STREAM_NAME` sets `kinesis.stream_name`
+//!
+//! String values of the form `env:NAME` or `file:PATH` are references to secrets,
+//! replaced by the value of the environment variable `NAME` or the contents of the
+//! file at `PATH`, so that secrets needn't be written into the configuration
+//!
+//! A service started with `--print-config` prints its configuration and exits, with
+//! secrets shown as their references, and any other secrets or passwords redacted
+
+use std::path::Path;
+
+use figment::providers::{Env, Format, Toml};
+use figment::{Figment, Profile};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::secret::Secret;
+
+mod error;
+mod secret;
+
+pub use error::Error;
+
+/// The environment variable naming the configuration file
+const CONFIG_FILE: &str = "APP_CONFIG";
+
+/// The argument asking a service to print its configuration
+const PRINT_CONFIG: &str = "--print-config";
+
+/// The value printed in place of a secret that isn't a reference
+const REDACTED: &str = "[REDACTED]";
+
+/// Redacts the strings of `value` whose keys name a secret or password, at any depth
+fn redact(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                if value.is_string() && (key.contains("secret") || key.contains("password")) {
+                    *value = REDACTED.into();
+                } else {
+                    redact(value);
+                }
+            }
+        }
+        serde_json::Value::Array(values) => values.iter_mut().for_each(redact),
+        _ => {}
+    }
+}
+
+/// Checks the values of a configuration beyond what its type can, such as that
+/// URLs parse or that limits are in range
+pub trait Validate {
+    fn validate(&self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// The configuration of a service, along with the figment it was extracted from
+pub struct Settings<T> {
+    pub config: T,
+    /// The figment with secret references resolved, to configure Rocket
+    pub figment: Figment,
+    secrets: Vec<Secret>,
+}
+
+impl<T: Serialize> Settings<T> {
+    /// Returns the configuration as pretty JSON, with secrets shown as their
+    /// references, and any other secrets or passwords redacted
+    pub fn print(&self) -> String {
+        let mut value = serde_json::to_value(&self.config).expect("Failed to serialize config");
+        redact(&mut value);
+        for secret in &self.secrets {
+            let pointer = format!("/{}", secret.key.replace('.', "/"));
+            if let Some(x) = value.pointer_mut(&pointer) {
+                *x = secret.reference.clone().into();
+            }
+        }
+        serde_json::to_string_pretty(&value).unwrap()
+    }
+}
+
+/// Extracts the configuration of a service from `base` layered with the configuration
+/// file and environment, resolving secret references and validating it
+pub fn extract<T>(base: Figment) -> Result<Settings<T>, Error>
+where
+    T: DeserializeOwned + Validate,
+{
+    let mut figment = base;
+    if let Ok(path) = std::env::var(CONFIG_FILE) {
+        if !Path::new(&path).is_file() {
+            return Err(Error::invalid(CONFIG_FILE, format!("No such file {}", path)));
+        }
+        figment = figment.merge(Toml::file(path).profile(Profile::Global));
+    }
+
+    let env = Env::prefixed("APP_")
+        .filter(|x| !x.as_str().eq_ignore_ascii_case("config"))
+        .map(|x| x.as_str().replacen('_', ".", 1).into())
+        .profile(Profile::Global);
+
+    let (figment, secrets) = secret::resolve(figment.merge(env))?;
+    let config: T = figment.extract()?;
+    config.validate()?;
+
+    Ok(Settings {
+        config,
+        figment,
+        secrets,
+    })
+}
+
+/// Loads the configuration of a service layered onto `base`, returning it along with
+/// the figment to configure Rocket with
+///
+/// Exits with the reason if the configuration is invalid, or once it is printed if
+/// the service was started with `--print-config`
+pub fn load_with<T>(base: Figment) -> (T, Figment)
+where
+    T: DeserializeOwned + Serialize + Validate,
+{
+    let settings = match extract::<T>(base) {
+        Ok(settings) => settings,
+        Err(e) => {
+            eprintln!("Invalid configuration: {}", e);
+            std::process::exit(1)
+        }
+    };
+
+    if std::env::args().skip(1).any(|x| x == PRINT_CONFIG) {
+        println!("{}", settings.print());
+        std::process::exit(0)
+    }
+    (settings.config, settings.figment)
+}
+
+/// Loads the configuration of a service that doesn't use Rocket, see `load_with`
+pub fn load<T>() -> T
+where
+    T: DeserializeOwned + Serialize + Validate,
+{
+    load_with(Figment::new()).0
+}
+
+#[cfg(test)]
+mod tests {
+    use figment::providers::Serialized;
+    use serde::Deserialize;
+
+    use super::*;
+
+    #[derive(Debug, Deserialize, Serialize, Default)]
+    #[serde(default)]
+    struct DaoConfig {
+        table: String,
+        password: String,
+        secret: String,
+        max_connections: u32,
+    }
+
+    #[derive(Debug, Deserialize, Serialize, Default)]
+    #[serde(default)]
+    struct Config {
+        dao: DaoConfig,
+    }
+
+    impl Validate for Config {
+        fn validate(&self) -> Result<(), Error> {
+            if self.dao.max_connections == 0 {
+                return Err(Error::invalid("dao.max_connections", "must be positive"));
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_extract() {
+        std::env::set_var("APP_DAO_MAX_CONNECTIONS", "8");
+        std::env::set_var("SETTINGS_TEST_PASSWORD", "hunter2");
+
+        let base = Figment::from(Serialized::defaults(serde_json::json!({
+            "dao": {
+                "table": "users",
+                "password": "env:SETTINGS_TEST_PASSWORD",
+                "secret": "abc",
+                "max_connections": 4,
+            }
+        })));
+        let settings = extract::<Config>(base).unwrap();
+
+        // Environment variables take precedence over the base
+        assert_eq!(settings.config.dao.table, "users");
+        assert_eq!(settings.config.dao.password, "hunter2");
+        assert_eq!(settings.config.dao.max_connections, 8);
+
+        let printed: serde_json::Value = serde_json::from_str(&settings.print()).unwrap();
+        assert_eq!(
+            printed,
+            serde_json::json!({
+                "dao": {
+                    "table": "users",
+                    "password": "env:SETTINGS_TEST_PASSWORD",
+                    "secret": REDACTED,
+                    "max_connections": 8,
+                }
+            })
+        );
+
+        std::env::set_var("APP_DAO_MAX_CONNECTIONS", "0");
+        let error = extract::<Config>(Figment::new()).err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "Invalid value of `dao.max_connections`: must be positive"
+        );
+
+        std::env::set_var("APP_DAO_MAX_CONNECTIONS", "many");
+        let error = extract::<Config>(Figment::new()).err().unwrap();
+        assert!(matches!(error, Error::Figment(_)));
+        std::env::remove_var("APP_DAO_MAX_CONNECTIONS");
+    }
+}
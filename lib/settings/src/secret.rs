@@ -0,0 +1,126 @@
+use std::fs;
+
+use figment::providers::Serialized;
+use figment::value::{Dict, Value};
+use figment::Figment;
+
+use crate::Error;
+
+/// Where a secret is held
+#[derive(Debug, PartialEq)]
+enum Source<'a> {
+    Env(&'a str),
+    File(&'a str),
+}
+
+/// Returns where `value` refers to if it is a secret reference
+fn source(value: &str) -> Option<Source<'_>> {
+    // The URL of a file isn't a reference
+    if value.starts_with("file://") {
+        return None;
+    }
+    if let Some(name) = value.strip_prefix("env:") {
+        return Some(Source::Env(name));
+    }
+    value.strip_prefix("file:").map(Source::File)
+}
+
+/// A configuration value referring to a secret held elsewhere
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Secret {
+    /// The dotted key of the value
+    pub key: String,
+    /// The value, such as `env:DB_PASSWORD`
+    pub reference: String,
+}
+
+impl Secret {
+    fn read(&self) -> Result<String, Error> {
+        let error = |message: String| Error::Secret {
+            key: self.key.clone(),
+            reference: self.reference.clone(),
+            message,
+        };
+
+        match source(&self.reference) {
+            Some(Source::Env(name)) => std::env::var(name).map_err(|e| error(e.to_string())),
+            Some(Source::File(path)) => fs::read_to_string(path)
+                .map(|x| x.trim_end_matches(&['\r', '\n'][..]).to_string())
+                .map_err(|e| error(e.to_string())),
+            None => Err(error("Not a reference".to_string())),
+        }
+    }
+}
+
+/// Adds the secret references in `dict`, whose keys are nested under `prefix`, to
+/// `secrets`
+fn find(dict: &Dict, prefix: &str, secrets: &mut Vec<Secret>) {
+    for (name, value) in dict {
+        let key = match prefix {
+            "" => name.clone(),
+            _ => format!("{}.{}", prefix, name),
+        };
+        match value {
+            Value::String(_, value) if source(value).is_some() => secrets.push(Secret {
+                key,
+                reference: value.clone(),
+            }),
+            Value::Dict(_, dict) => find(dict, &key, secrets),
+            _ => {}
+        }
+    }
+}
+
+/// Replaces the secret references in `figment` with the secrets they refer to,
+/// returning the references replaced
+pub(crate) fn resolve(figment: Figment) -> Result<(Figment, Vec<Secret>), Error> {
+    let mut secrets = vec![];
+    find(&figment.extract::<Dict>()?, "", &mut secrets);
+
+    let mut figment = figment;
+    for secret in &secrets {
+        figment = figment.merge(Serialized::global(&secret.key, secret.read()?));
+    }
+    Ok((figment, secrets))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_source() {
+        assert_eq!(source("env:TOKEN"), Some(Source::Env("TOKEN")));
+        assert_eq!(source("file:/run/secrets/token"), Some(Source::File("/run/secrets/token")));
+        assert_eq!(source("file:///tmp/index.html"), None);
+        assert_eq!(source("http://localhost"), None);
+        assert_eq!(source("environment"), None);
+    }
+
+    #[test]
+    fn test_resolve() {
+        let path = std::env::temp_dir().join("settings_test_resolve");
+        fs::write(&path, "hunter2\n").unwrap();
+        std::env::set_var("SETTINGS_TEST_RESOLVE", "abc");
+
+        let figment = Figment::from(Serialized::defaults(serde_json::json!({
+            "dao": {
+                "password": format!("file:{}", path.display()),
+                "table": "users",
+            },
+            "token": "env:SETTINGS_TEST_RESOLVE",
+        })));
+        let (figment, secrets) = resolve(figment).unwrap();
+
+        assert_eq!(figment.extract_inner::<String>("dao.password").unwrap(), "hunter2");
+        assert_eq!(figment.extract_inner::<String>("dao.table").unwrap(), "users");
+        assert_eq!(figment.extract_inner::<String>("token").unwrap(), "abc");
+        assert_eq!(secrets.len(), 2);
+
+        let figment = Figment::from(Serialized::global("token", "env:SETTINGS_TEST_MISSING"));
+        match resolve(figment) {
+            Err(Error::Secret { key, .. }) => assert_eq!(key, "token"),
+            _ => panic!("expected secret error"),
+        }
+    }
+}
@@ -5,13 +5,15 @@ use std::pin::Pin;
 use std::task::{Context, Poll};
 use tokio::sync::watch;
 
+/// Completes once the corresponding `Sender` signals shutdown or is dropped
 #[pin_project]
 #[derive(Clone)]
-pub(crate) struct Receiver(#[pin] watch::Receiver<bool>);
+pub struct Receiver(#[pin] watch::Receiver<bool>);
 
-pub(crate) struct Sender(watch::Sender<bool>);
+/// Signals shutdown to every `Receiver` of the channel
+pub struct Sender(watch::Sender<bool>);
 
-pub(crate) fn channel() -> (Sender, Receiver) {
+pub fn channel() -> (Sender, Receiver) {
     let (shutdown_tx, shutdown_rx) = watch::channel(false);
 
     (Sender(shutdown_tx), Receiver(shutdown_rx))
@@ -0,0 +1,213 @@
+use std::future::Future;
+use std::time::Duration;
+
+use futures::future::{join_all, LocalBoxFuture};
+use futures::FutureExt;
+use serde::{Deserialize, Serialize};
+use tokio::signal::unix::SignalKind;
+use tokio::time::{timeout_at, Instant};
+use tracing::{info, warn};
+
+/// Configures how long a service is given to stop
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct ShutdownConfig {
+    /// The time in seconds the work in flight is given to finish once a service is
+    /// asked to stop, which should be less than the time before it is killed
+    pub deadline: u64,
+}
+
+impl Default for ShutdownConfig {
+    fn default() -> ShutdownConfig {
+        ShutdownConfig { deadline: 20 }
+    }
+}
+
+impl ShutdownConfig {
+    pub fn deadline(&self) -> Duration {
+        Duration::from_secs(self.deadline)
+    }
+}
+
+/// Completes once the process is asked to stop by SIGTERM or SIGINT
+pub async fn signal() {
+    let mut terminate =
+        tokio::signal::unix::signal(SignalKind::terminate()).expect("Failed to listen for SIGTERM");
+
+    tokio::select! {
+        _ = terminate.recv() => info!("Received SIGTERM"),
+        _ = tokio::signal::ctrl_c() => info!("Received SIGINT"),
+    }
+}
+
+/// The phases of a graceful shutdown, in the order they run
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    /// Stops taking new work, such as requests or messages
+    StopIntake,
+    /// Waits for the work in flight to finish
+    Drain,
+    /// Releases resources, such as connections
+    Close,
+}
+
+const PHASES: [Phase; 3] = [Phase::StopIntake, Phase::Drain, Phase::Close];
+
+/// Stops a service in phases, each of which starts once the tasks of the one before
+/// have completed
+///
+/// The deadline bounds `Phase::StopIntake` and `Phase::Drain`, whose tasks are
+/// abandoned once it passes. `Phase::Close` runs regardless, so that resources are
+/// released even if work in flight is abandoned
+pub struct GracefulShutdown<'a> {
+    deadline: Duration,
+    tasks: Vec<(Phase, LocalBoxFuture<'a, ()>)>,
+}
+
+impl<'a> GracefulShutdown<'a> {
+    pub fn new(deadline: Duration) -> GracefulShutdown<'a> {
+        GracefulShutdown {
+            deadline,
+            tasks: vec![],
+        }
+    }
+
+    /// Adds `task` to `phase`, it isn't polled until the phase starts
+    pub fn on(mut self, phase: Phase, task: impl Future<Output = ()> + 'a) -> Self {
+        self.tasks.push((phase, task.boxed_local()));
+        self
+    }
+
+    /// Runs the phases in order, returning false if the deadline passed before the
+    /// work in flight finished
+    pub async fn run(self) -> bool {
+        let deadline = Instant::now() + self.deadline;
+        let mut tasks = self.tasks;
+        let mut completed = true;
+
+        for phase in PHASES.iter().copied() {
+            let (current, remaining): (Vec<_>, Vec<_>) =
+                tasks.into_iter().partition(|(x, _)| *x == phase);
+            tasks = remaining;
+
+            if !completed && phase != Phase::Close {
+                continue;
+            }
+
+            info!(?phase, "Starting shutdown phase");
+            let current = join_all(current.into_iter().map(|(_, task)| task));
+            if phase == Phase::Close {
+                current.await;
+            } else if timeout_at(deadline, current).await.is_err() {
+                warn!(
+                    ?phase,
+                    "Shutdown deadline of {:?} passed, abandoning work in flight", self.deadline
+                );
+                completed = false;
+            }
+        }
+        completed
+    }
+
+    /// Runs `work` until it completes or the process receives SIGTERM or SIGINT, and
+    /// then runs the phases, `work` being added to `Phase::Drain` if it hasn't completed
+    ///
+    /// Returns the output of `work`, or None if it was abandoned at the deadline, in
+    /// which case it is dropped before `Phase::Close` runs
+    pub async fn run_until_signal<F: Future + 'a>(self, work: F) -> Option<F::Output> {
+        self.run_until(signal(), work).await
+    }
+
+    async fn run_until<F: Future + 'a>(self, signal: impl Future, work: F) -> Option<F::Output> {
+        let mut work = Box::pin(work);
+        let output = tokio::select! {
+            output = &mut work => Some(output),
+            _ = signal => None,
+        };
+        if output.is_some() {
+            self.run().await;
+            return output;
+        }
+
+        let mut output = None;
+        let drain = async {
+            output = Some(work.await);
+        };
+        self.on(Phase::Drain, drain).run().await;
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use futures::future::pending;
+    use tokio::time::delay_for;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_phases() {
+        let ran = RefCell::new(vec![]);
+        let record = |phase| {
+            let ran = &ran;
+            async move { ran.borrow_mut().push(phase) }
+        };
+
+        let completed = GracefulShutdown::new(Duration::from_secs(1))
+            .on(Phase::Close, record(Phase::Close))
+            .on(Phase::Drain, async {
+                delay_for(Duration::from_millis(10)).await;
+                record(Phase::Drain).await
+            })
+            .on(Phase::StopIntake, record(Phase::StopIntake))
+            .run()
+            .await;
+
+        assert!(completed);
+        assert_eq!(
+            ran.into_inner(),
+            vec![Phase::StopIntake, Phase::Drain, Phase::Close]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_deadline() {
+        let closed = RefCell::new(false);
+        let completed = GracefulShutdown::new(Duration::from_millis(10))
+            .on(Phase::Drain, pending())
+            .on(Phase::Close, async { *closed.borrow_mut() = true })
+            .run()
+            .await;
+
+        // Resources are closed even though the work in flight was abandoned
+        assert!(!completed);
+        assert!(closed.into_inner());
+    }
+
+    #[tokio::test]
+    async fn test_run_until() {
+        let shutdown = || GracefulShutdown::new(Duration::from_millis(50));
+
+        // Work that completes on its own still runs the phases
+        let stopped = RefCell::new(false);
+        let output = shutdown()
+            .on(Phase::StopIntake, async { *stopped.borrow_mut() = true })
+            .run_until(pending::<()>(), async { 1 })
+            .await;
+        assert_eq!(output, Some(1));
+        assert!(stopped.into_inner());
+
+        // Work in flight at the signal is drained
+        let work = async {
+            delay_for(Duration::from_millis(10)).await;
+            2
+        };
+        let output = shutdown().run_until(async {}, work).await;
+        assert_eq!(output, Some(2));
+
+        let output = shutdown().run_until(async {}, pending::<()>()).await;
+        assert_eq!(output, None);
+    }
+}
@@ -0,0 +1,11 @@
+//! Graceful shutdown of services
+//!
+//! A `channel` signals shutdown to the tasks of a service, while `GracefulShutdown`
+//! stops a service on SIGTERM or SIGINT in ordered phases: it stops taking new work,
+//! waits for the work in flight up to a deadline, and then closes its resources
+
+mod channel;
+mod graceful;
+
+pub use channel::{channel, Receiver, Sender};
+pub use graceful::{signal, GracefulShutdown, Phase, ShutdownConfig};
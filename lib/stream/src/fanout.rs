@@ -0,0 +1,295 @@
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+use futures::prelude::*;
+use futures::ready;
+use pin_project::pin_project;
+use tracing::warn;
+
+/// Determines how a subscriber with a full buffer is handled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SlowConsumerPolicy {
+    /// Stop pulling from the source until the subscriber has capacity
+    Block,
+    /// Discard the oldest buffered item to make room
+    DropOldest,
+    /// Terminate the subscriber once it has drained its buffer
+    Disconnect,
+}
+
+struct Shared<T> {
+    buffer: VecDeque<T>,
+    capacity: usize,
+    policy: SlowConsumerPolicy,
+    finished: bool,
+    disconnected: bool,
+    dropped: bool,
+    consumer: Option<Waker>,
+    producer: Option<Waker>,
+}
+
+impl<T> Shared<T> {
+    fn wake_consumer(&mut self) {
+        if let Some(waker) = self.consumer.take() {
+            waker.wake()
+        }
+    }
+
+    fn wake_producer(&mut self) {
+        if let Some(waker) = self.producer.take() {
+            waker.wake()
+        }
+    }
+}
+
+enum Delivery<T> {
+    Delivered,
+    Closed,
+    Full(T),
+}
+
+struct Subscription<T>(Arc<Mutex<Shared<T>>>);
+
+impl<T> Subscription<T> {
+    fn deliver(&self, item: T, cx: &mut Context<'_>) -> Delivery<T> {
+        let mut shared = self.0.lock().unwrap();
+        if shared.dropped || shared.disconnected {
+            return Delivery::Closed;
+        }
+
+        if shared.buffer.len() >= shared.capacity {
+            match shared.policy {
+                SlowConsumerPolicy::Block => {
+                    shared.producer = Some(cx.waker().clone());
+                    return Delivery::Full(item);
+                }
+                SlowConsumerPolicy::DropOldest => {
+                    warn!("subscriber buffer full - dropping oldest item");
+                    shared.buffer.pop_front();
+                }
+                SlowConsumerPolicy::Disconnect => {
+                    warn!("subscriber buffer full - disconnecting");
+                    shared.disconnected = true;
+                    shared.wake_consumer();
+                    return Delivery::Closed;
+                }
+            }
+        }
+
+        shared.buffer.push_back(item);
+        shared.wake_consumer();
+        Delivery::Delivered
+    }
+
+    fn finish(&self) {
+        let mut shared = self.0.lock().unwrap();
+        shared.finished = true;
+        shared.wake_consumer();
+    }
+}
+
+/// A future that drives a source stream, cloning each item to every subscriber
+///
+/// Items are delivered to subscribers in the order they were subscribed, if a
+/// blocking subscriber is full no further items will be pulled from the source
+#[pin_project]
+#[must_use = "futures do nothing unless polled"]
+pub struct FanOut<St: Stream> {
+    #[pin]
+    stream: stream::Fuse<St>,
+    subscriptions: Vec<Subscription<St::Item>>,
+    pending: Option<(St::Item, usize)>,
+}
+
+impl<St: Stream> FanOut<St>
+where
+    St::Item: Clone,
+{
+    fn new(stream: St) -> FanOut<St> {
+        FanOut {
+            stream: stream.fuse(),
+            subscriptions: vec![],
+            pending: None,
+        }
+    }
+
+    /// Registers a new subscriber with a buffer of `capacity` items
+    ///
+    /// Subscribers only receive items pulled from the source after they subscribe
+    pub fn subscribe(
+        &mut self,
+        capacity: usize,
+        policy: SlowConsumerPolicy,
+    ) -> Subscriber<St::Item> {
+        assert!(capacity > 0, "subscriber capacity must be non-zero");
+
+        let shared = Arc::new(Mutex::new(Shared {
+            buffer: VecDeque::with_capacity(capacity),
+            capacity,
+            policy,
+            finished: false,
+            disconnected: false,
+            dropped: false,
+            consumer: None,
+            producer: None,
+        }));
+
+        self.subscriptions.push(Subscription(shared.clone()));
+        Subscriber { shared }
+    }
+}
+
+impl<St: Stream> Future for FanOut<St>
+where
+    St::Item: Clone,
+{
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut this = self.project();
+        loop {
+            if let Some((item, mut idx)) = this.pending.take() {
+                while idx < this.subscriptions.len() {
+                    match this.subscriptions[idx].deliver(item.clone(), cx) {
+                        Delivery::Delivered => idx += 1,
+                        Delivery::Closed => {
+                            this.subscriptions.remove(idx);
+                        }
+                        Delivery::Full(_) => {
+                            *this.pending = Some((item, idx));
+                            return Poll::Pending;
+                        }
+                    }
+                }
+            }
+
+            match ready!(this.stream.as_mut().poll_next(cx)) {
+                Some(item) => *this.pending = Some((item, 0)),
+                None => {
+                    for subscription in this.subscriptions.drain(..) {
+                        subscription.finish()
+                    }
+                    return Poll::Ready(());
+                }
+            }
+        }
+    }
+}
+
+/// A stream of the items delivered to a subscriber of a `FanOut`
+pub struct Subscriber<T> {
+    shared: Arc<Mutex<Shared<T>>>,
+}
+
+impl<T> Subscriber<T> {
+    /// Returns true if this subscriber was disconnected for falling behind
+    pub fn disconnected(&self) -> bool {
+        self.shared.lock().unwrap().disconnected
+    }
+}
+
+impl<T> Stream for Subscriber<T> {
+    type Item = T;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut shared = self.shared.lock().unwrap();
+        if let Some(item) = shared.buffer.pop_front() {
+            shared.wake_producer();
+            return Poll::Ready(Some(item));
+        }
+
+        if shared.finished || shared.disconnected {
+            return Poll::Ready(None);
+        }
+
+        shared.consumer = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl<T> Drop for Subscriber<T> {
+    fn drop(&mut self) {
+        if let Ok(mut shared) = self.shared.lock() {
+            shared.dropped = true;
+            shared.buffer.clear();
+            shared.wake_producer();
+        }
+    }
+}
+
+pub trait FanOutStreamExt: Stream {
+    fn fan_out(self) -> FanOut<Self>
+    where
+        Self: Sized,
+        Self::Item: Clone,
+    {
+        FanOut::new(self)
+    }
+}
+impl<T: ?Sized> FanOutStreamExt for T where T: Stream {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_fan_out() {
+        let mut fan_out = stream::iter(0..5).fan_out();
+        let a = fan_out.subscribe(10, SlowConsumerPolicy::Block);
+        let b = fan_out.subscribe(10, SlowConsumerPolicy::Block);
+
+        fan_out.await;
+
+        assert_eq!(a.collect::<Vec<_>>().await, vec![0, 1, 2, 3, 4]);
+        assert_eq!(b.collect::<Vec<_>>().await, vec![0, 1, 2, 3, 4]);
+    }
+
+    #[tokio::test]
+    async fn test_block() {
+        let mut fan_out = stream::iter(0..100).fan_out();
+        let a = fan_out.subscribe(1, SlowConsumerPolicy::Block);
+        let b = fan_out.subscribe(3, SlowConsumerPolicy::Block);
+
+        let (_, a, b) = futures::join!(fan_out, a.collect::<Vec<_>>(), b.collect::<Vec<_>>());
+
+        assert_eq!(a, (0..100).collect::<Vec<_>>());
+        assert_eq!(b, (0..100).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn test_drop_oldest() {
+        let mut fan_out = stream::iter(0..5).fan_out();
+        let a = fan_out.subscribe(2, SlowConsumerPolicy::DropOldest);
+
+        fan_out.await;
+
+        assert_eq!(a.collect::<Vec<_>>().await, vec![3, 4]);
+    }
+
+    #[tokio::test]
+    async fn test_disconnect() {
+        let mut fan_out = stream::iter(0..5).fan_out();
+        let a = fan_out.subscribe(2, SlowConsumerPolicy::Disconnect);
+        let b = fan_out.subscribe(5, SlowConsumerPolicy::Block);
+
+        fan_out.await;
+
+        assert!(a.disconnected());
+        assert_eq!(a.collect::<Vec<_>>().await, vec![0, 1]);
+        assert_eq!(b.collect::<Vec<_>>().await, vec![0, 1, 2, 3, 4]);
+    }
+
+    #[tokio::test]
+    async fn test_dropped_subscriber() {
+        let mut fan_out = stream::iter(0..5).fan_out();
+        let a = fan_out.subscribe(1, SlowConsumerPolicy::Block);
+        let b = fan_out.subscribe(5, SlowConsumerPolicy::Block);
+        drop(a);
+
+        fan_out.await;
+
+        assert_eq!(b.collect::<Vec<_>>().await, vec![0, 1, 2, 3, 4]);
+    }
+}
@@ -1,7 +1,12 @@
 mod batch;
+mod fanout;
 mod limiter;
 
 pub use batch::{BatchStreamExt, Batched, PartitionBatched, Partitioned, Reducer};
-pub use limiter::{LimitedStream, LimitedStreamExt, Limiter, PartitionedLimiter, TokenBucket};
+pub use fanout::{FanOut, FanOutStreamExt, SlowConsumerPolicy, Subscriber};
+pub use limiter::{
+    KeyedFactory, LimitedStream, LimitedStreamExt, Limiter, LimiterFactory, PartitionedLimiter,
+    Rate, TokenBucket,
+};
 
 pub use limiter::Error as LimiterError;
@@ -2,6 +2,8 @@ use std::cmp::min;
 use std::collections::hash_map::Entry;
 use std::collections::HashMap;
 use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
 use std::task::{Context, Poll};
 use std::time::{Duration, Instant};
 
@@ -31,18 +33,40 @@ pub enum Error {
 
 pub type Result<T, E = Error> = std::result::Result<T, E>;
 
+/// A shared handle to the rate of a TokenBucket, allowing it to be adjusted whilst in use
+#[derive(Debug, Clone)]
+pub struct Rate(Arc<AtomicU64>);
+
+impl Rate {
+    pub fn per_second(rate: u64) -> Rate {
+        Rate(Arc::new(AtomicU64::new(rate.max(1))))
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Sets the rate, which must be at least 1 per second
+    pub fn set(&self, rate: u64) {
+        self.0.store(rate.max(1), Ordering::Relaxed)
+    }
+}
+
 pub struct TokenBucket {
     level: u64,
-    token_interval: u64,
+    rate: Rate,
     last_time: Instant,
 }
 
 impl TokenBucket {
     pub fn per_second(capacity: u64) -> TokenBucket {
-        let token_interval = NANOS_PER_SEC / capacity;
+        TokenBucket::with_rate(Rate::per_second(capacity))
+    }
 
+    /// Creates a TokenBucket whose capacity per second is determined by `rate`
+    pub fn with_rate(rate: Rate) -> TokenBucket {
         TokenBucket {
-            token_interval,
+            rate,
             level: 0,
             last_time: Instant::now().checked_sub(Duration::from_secs(1)).unwrap(),
         }
@@ -57,7 +81,8 @@ impl Limiter for TokenBucket {
     }
 
     fn try_take(&mut self, n: &u64) -> Result<()> {
-        let delta = self.token_interval * n;
+        let token_interval = NANOS_PER_SEC / self.rate.get();
+        let delta = token_interval.saturating_mul(*n);
         if delta > NANOS_PER_SEC {
             return Err(Error::CapacityExceeded);
         }
@@ -81,8 +106,31 @@ impl Limiter for TokenBucket {
     }
 }
 
-pub struct PartitionedLimiter<L: Limiter + Sized, F: Fn() -> L>
-where
+/// Creates the limiter for a partition of a PartitionedLimiter
+pub trait LimiterFactory<K, L> {
+    fn create(&self, key: &K) -> L;
+}
+
+impl<K, L, F: Fn() -> L> LimiterFactory<K, L> for F {
+    fn create(&self, _: &K) -> L {
+        self()
+    }
+}
+
+/// A LimiterFactory that is passed the key of the partition, see
+/// `LimitedStreamExt::partition_limit_keyed`
+pub struct KeyedFactory<F>(F);
+
+impl<K, L, F: Fn(&K) -> L> LimiterFactory<K, L> for KeyedFactory<F> {
+    fn create(&self, key: &K) -> L {
+        (self.0)(key)
+    }
+}
+
+pub struct PartitionedLimiter<
+    L: Limiter + Sized,
+    F: LimiterFactory<<L::Item as Partitioned>::Key, L>,
+> where
     L::Item: Partitioned,
 {
     inner: HashMap<<L::Item as Partitioned>::Key, L>,
@@ -91,7 +139,8 @@ where
     limiter_factory: F,
 }
 
-impl<L: Limiter + Sized, F: Fn() -> L> PartitionedLimiter<L, F>
+impl<L: Limiter + Sized, F: LimiterFactory<<L::Item as Partitioned>::Key, L>>
+    PartitionedLimiter<L, F>
 where
     L::Item: Partitioned,
 {
@@ -114,7 +163,8 @@ where
     }
 }
 
-impl<L: Limiter + Sized, F: Fn() -> L> Limiter for PartitionedLimiter<L, F>
+impl<L: Limiter + Sized, F: LimiterFactory<<L::Item as Partitioned>::Key, L>> Limiter
+    for PartitionedLimiter<L, F>
 where
     L::Item: Partitioned,
 {
@@ -129,7 +179,10 @@ where
         self.prune();
         match self.inner.entry(item.partition()) {
             Entry::Occupied(entry) => entry.into_mut().try_take(item),
-            Entry::Vacant(entry) => entry.insert((self.limiter_factory)()).try_take(item),
+            Entry::Vacant(entry) => {
+                let limiter = self.limiter_factory.create(entry.key());
+                entry.insert(limiter).try_take(item)
+            }
         }
     }
 }
@@ -211,5 +264,50 @@ pub trait LimitedStreamExt: Stream {
             PartitionedLimiter::new(limiter_factory, prune_interval),
         )
     }
+
+    /// Limits the stream with a separate limiter for each partition, created by
+    /// `limiter_factory` from the partition's key when an item is first seen for it
+    fn partition_limit_keyed<
+        L: Limiter<Item = Self::Item> + Sized,
+        F: Fn(&<Self::Item as Partitioned>::Key) -> L,
+    >(
+        self,
+        limiter_factory: F,
+        prune_interval: Duration,
+    ) -> LimitedStream<Self, PartitionedLimiter<L, KeyedFactory<F>>>
+    where
+        Self: Sized,
+        Self::Item: Partitioned,
+    {
+        LimitedStream::new(
+            self,
+            PartitionedLimiter::new(KeyedFactory(limiter_factory), prune_interval),
+        )
+    }
 }
 impl<T: ?Sized> LimitedStreamExt for T where T: Stream {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate() {
+        let rate = Rate::per_second(10);
+        let mut bucket = TokenBucket::with_rate(rate.clone());
+
+        for _ in 0..10 {
+            assert!(bucket.try_take(&1).is_ok());
+        }
+        assert!(matches!(bucket.try_take(&1), Err(Error::LimitExceeded(_))));
+
+        rate.set(20);
+        let mut bucket = TokenBucket::with_rate(rate);
+
+        for _ in 0..20 {
+            assert!(bucket.try_take(&1).is_ok());
+        }
+        assert!(matches!(bucket.try_take(&1), Err(Error::LimitExceeded(_))));
+        assert!(matches!(bucket.try_take(&40), Err(Error::CapacityExceeded)));
+    }
+}
@@ -1,13 +1,17 @@
 #[macro_use]
 extern crate lazy_static;
 #[macro_use]
-extern crate prometheus;
+pub extern crate prometheus;
 
 use std::convert::Infallible;
 use std::future::Future;
+use std::time::Duration;
 
 use prometheus::{Encoder, Histogram, HistogramVec, IntCounter, IntCounterVec, TextEncoder};
 
+pub mod log;
+pub mod trace;
+
 lazy_static! {
     static ref SUCCESS: IntCounterVec = register_int_counter_vec!(
         "success_counter",
@@ -78,6 +82,17 @@ impl Measure {
         }
         r
     }
+
+    /// Records an operation that took `duration`, for callers that time operations
+    /// themselves rather than through [`Measure::stats`]
+    pub fn observe(&self, duration: Duration, success: bool) {
+        self.timer.observe(duration.as_secs_f64());
+        if success {
+            self.success.inc()
+        } else {
+            self.failure.inc()
+        }
+    }
 }
 
 pub fn encode() -> Result<String, Box<dyn std::error::Error>> {
@@ -202,4 +217,24 @@ mod tests {
             1
         );
     }
+
+    #[test]
+    fn test_observe() {
+        let layer = "layer";
+        let function = "test_observe";
+
+        let m = Measure::new(layer, function);
+        m.observe(Duration::from_millis(1500), true);
+        m.observe(Duration::from_millis(500), false);
+
+        assert_eq!(SUCCESS.with_label_values(&[layer, function]).get(), 1);
+        assert_eq!(FAILURE.with_label_values(&[layer, function]).get(), 1);
+        assert_eq!(
+            TIMER
+                .with_label_values(&[layer, function])
+                .get_sample_sum()
+                .round() as i64,
+            2
+        );
+    }
 }
@@ -0,0 +1,254 @@
+//! Structured logging of events as JSON lines
+//!
+//! Each event is written as a single JSON object, along with the name and version of
+//! the service and the correlation fields of the spans it occurred within, so that the
+//! logs of a request can be joined by an aggregator, within a service by its request ID
+//! and across services by its trace ID
+//!
+//! ```json
+//! {"timestamp":"2020-11-02T10:15:00.120Z","level":"INFO","target":"auth::api::token","service":"auth","version":"0.1.0","request_id":"4a6e4b4e-...","trace_id":"0af7651916cd43dd8448eb211c80319c","cid":"my_client","span":"request","message":"Issued token"}
+//! ```
+//!
+//! Events of the log crate are written as those of tracing
+
+use std::fmt;
+use std::io::{self, Write};
+
+use chrono::{SecondsFormat, Utc};
+use serde_json::{Map, Value};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Subscriber};
+use tracing_log::NormalizeEvent;
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::layer::{Context, Layer};
+use tracing_subscriber::registry::LookupSpan;
+
+/// The fields of spans that are added to the events within them
+///
+/// - `request_id` the `X-Request-Id` of the request being handled
+/// - `trace_id` the ID of the W3C trace the request belongs to
+/// - `cid` and `sub` the client and user of the bearer token of the request
+pub const CORRELATION_FIELDS: [&str; 4] = ["request_id", "trace_id", "cid", "sub"];
+
+/// The correlation fields recorded on a span
+#[derive(Debug, Default)]
+struct Correlation(Vec<(&'static str, String)>);
+
+impl Correlation {
+    fn set(&mut self, name: &'static str, value: String) {
+        match self.0.iter_mut().find(|(x, _)| *x == name) {
+            Some((_, existing)) => *existing = value,
+            None => self.0.push((name, value)),
+        }
+    }
+}
+
+impl Visit for Correlation {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if CORRELATION_FIELDS.contains(&field.name()) {
+            self.set(field.name(), value.to_string())
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if CORRELATION_FIELDS.contains(&field.name()) {
+            self.set(field.name(), format!("{:?}", value))
+        }
+    }
+}
+
+/// The fields of an event, excluding those added by the log crate bridge
+struct Fields<'a>(&'a mut Map<String, Value>);
+
+impl<'a> Fields<'a> {
+    fn insert(&mut self, field: &Field, value: Value) {
+        if !field.name().starts_with("log.") {
+            self.0.insert(field.name().to_string(), value);
+        }
+    }
+}
+
+impl<'a> Visit for Fields<'a> {
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.insert(field, value.into())
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.insert(field, value.into())
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.insert(field, value.into())
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.insert(field, value.into())
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.insert(field, format!("{:?}", value).into())
+    }
+}
+
+/// A layer writing events as JSON lines, see the module documentation
+pub struct JsonLayer<W = fn() -> io::Stdout> {
+    service: String,
+    version: String,
+    make_writer: W,
+}
+
+impl JsonLayer {
+    /// Returns a layer writing the events of version `version` of `service` to stdout
+    pub fn new(service: &str, version: &str) -> JsonLayer {
+        JsonLayer {
+            service: service.to_string(),
+            version: version.to_string(),
+            make_writer: io::stdout,
+        }
+    }
+}
+
+impl<W> JsonLayer<W> {
+    /// Writes events to the writers returned by `make_writer` instead
+    pub fn with_writer<W2: MakeWriter>(self, make_writer: W2) -> JsonLayer<W2> {
+        JsonLayer {
+            service: self.service,
+            version: self.version,
+            make_writer,
+        }
+    }
+}
+
+impl<S, W> Layer<S> for JsonLayer<W>
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+    W: MakeWriter + 'static,
+{
+    fn new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
+        let span = ctx.span(id).expect("Span not found");
+        let mut correlation = Correlation::default();
+        attrs.record(&mut correlation);
+        span.extensions_mut().insert(correlation);
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let span = ctx.span(id).expect("Span not found");
+        let mut extensions = span.extensions_mut();
+        if let Some(correlation) = extensions.get_mut::<Correlation>() {
+            values.record(correlation);
+        }
+    }
+
+    fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        let normalized = event.normalized_metadata();
+        let metadata = normalized.as_ref().unwrap_or_else(|| event.metadata());
+
+        let mut object = Map::new();
+        let timestamp = Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true);
+        object.insert("timestamp".to_string(), timestamp.into());
+        object.insert("level".to_string(), metadata.level().to_string().into());
+        object.insert("target".to_string(), metadata.target().into());
+        object.insert("service".to_string(), self.service.as_str().into());
+        object.insert("version".to_string(), self.version.as_str().into());
+
+        // The scope is ordered from the root, so inner spans take precedence
+        for span in ctx.scope() {
+            if let Some(correlation) = span.extensions().get::<Correlation>() {
+                for (name, value) in &correlation.0 {
+                    object.insert(name.to_string(), value.as_str().into());
+                }
+            }
+        }
+        if let Some(span) = ctx.lookup_current() {
+            object.insert("span".to_string(), span.name().into());
+        }
+        event.record(&mut Fields(&mut object));
+
+        let mut line = serde_json::to_vec(&object).expect("Failed to serialize event");
+        line.push(b'\n');
+        // There is nowhere to report a failure to log
+        let _ = self.make_writer.make_writer().write_all(&line);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use tracing_subscriber::layer::SubscriberExt;
+
+    use super::*;
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Buffer {
+        fn lines(&self) -> Vec<Value> {
+            let buffer = self.0.lock().unwrap();
+            buffer
+                .split(|x| *x == b'\n')
+                .filter(|x| !x.is_empty())
+                .map(|x| serde_json::from_slice(x).unwrap())
+                .collect()
+        }
+    }
+
+    #[test]
+    fn test_json() {
+        let buffer = Buffer::default();
+        let writer = buffer.clone();
+        let layer = JsonLayer::new("auth", "0.1.0").with_writer(move || writer.clone());
+        let subscriber = tracing_subscriber::registry().with(layer);
+
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!(user = "a", "Outside");
+
+            let request = tracing::info_span!(
+                "request",
+                request_id = "abc",
+                trace_id = tracing::field::Empty,
+                cid = tracing::field::Empty,
+                path = "/api/v1/token",
+            );
+            request.record("cid", &"my_client");
+            let _entered = request.enter();
+
+            let inner = tracing::info_span!("upstream", trace_id = "0af7");
+            inner.in_scope(|| tracing::warn!(attempt = 2, retry = true, "Retrying"));
+        });
+
+        let lines = buffer.lines();
+        assert_eq!(lines.len(), 2);
+
+        assert_eq!(lines[0]["service"], "auth");
+        assert_eq!(lines[0]["version"], "0.1.0");
+        assert_eq!(lines[0]["level"], "INFO");
+        assert_eq!(lines[0]["message"], "Outside");
+        assert_eq!(lines[0]["user"], "a");
+        assert!(lines[0].get("request_id").is_none());
+        assert!(lines[0].get("span").is_none());
+
+        assert_eq!(lines[1]["level"], "WARN");
+        assert_eq!(lines[1]["message"], "Retrying");
+        assert_eq!(lines[1]["span"], "upstream");
+        assert_eq!(lines[1]["request_id"], "abc");
+        assert_eq!(lines[1]["trace_id"], "0af7");
+        assert_eq!(lines[1]["cid"], "my_client");
+        assert_eq!(lines[1]["attempt"], 2);
+        assert_eq!(lines[1]["retry"], true);
+        // Only correlation fields of spans are added to events
+        assert!(lines[1].get("path").is_none());
+        assert!(lines[1].get("sub").is_none());
+    }
+}
@@ -0,0 +1,225 @@
+//! Distributed tracing of requests across services
+//!
+//! The trace a request belongs to is propagated between services by the W3C trace
+//! context headers, `traceparent` and `tracestate`. Servers continue the trace of
+//! the requests they receive with `server_span`, and send it on with the requests
+//! they make by adding the `headers` of their span, the spans of the trace being
+//! exported to an OTLP collector if one is configured
+//!
+//! The span of a request records its `X-Request-Id`, its trace ID and the client and
+//! user of its bearer token, which are added to the events logged within it
+
+use std::collections::HashMap;
+
+use opentelemetry::sdk::propagation::TraceContextPropagator;
+use opentelemetry::sdk::{trace, Resource};
+use opentelemetry::{global, Context, KeyValue};
+use serde::{Deserialize, Serialize};
+use tracing::{field, Span, Subscriber};
+use tracing_opentelemetry::{OpenTelemetryLayer, OpenTelemetrySpanExt};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::EnvFilter;
+
+use crate::log::JsonLayer;
+
+/// The headers carrying the W3C trace context of a request
+pub const HEADERS: [&str; 2] = ["traceparent", "tracestate"];
+
+/// The header carrying the ID of a request, which is assigned by the gateway
+pub const REQUEST_ID: &str = "X-Request-Id";
+
+#[derive(Debug, Default, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct TracingConfig {
+    /// The OTLP collector spans are exported to, such as `http://collector:4317`,
+    /// spans aren't exported if None
+    pub endpoint: Option<String>,
+}
+
+/// Exports the spans of a service until dropped, when those not yet exported are
+/// flushed, and so must be held until the service exits
+pub struct Exporter {
+    _uninstall: Option<opentelemetry_otlp::Uninstall>,
+}
+
+/// Returns the layer exporting the spans of `service` to the collector configured by
+/// `config`, None if there isn't one, and installs the W3C trace context propagator
+///
+/// Services that format their events themselves add this to their subscriber, the
+/// others can use `init`
+pub fn layer<S>(
+    service: &str,
+    config: &TracingConfig,
+) -> (Option<OpenTelemetryLayer<S, trace::Tracer>>, Exporter)
+where
+    S: Subscriber + for<'span> LookupSpan<'span>,
+{
+    global::set_text_map_propagator(TraceContextPropagator::new());
+
+    let endpoint = match &config.endpoint {
+        Some(endpoint) => endpoint,
+        None => return (None, Exporter { _uninstall: None }),
+    };
+
+    let resource = Resource::new(vec![KeyValue::new("service.name", service.to_string())]);
+    let (tracer, uninstall) = opentelemetry_otlp::new_pipeline()
+        .with_endpoint(endpoint)
+        .with_trace_config(trace::config().with_resource(resource))
+        .install()
+        .expect("Failed to install OTLP exporter");
+
+    let layer = tracing_opentelemetry::layer().with_tracer(tracer);
+    let exporter = Exporter {
+        _uninstall: Some(uninstall),
+    };
+    (Some(layer), exporter)
+}
+
+/// Installs the global subscriber of version `version` of `service`, which logs events
+/// filtered by `RUST_LOG` as JSON lines and exports spans as configured by `config`
+///
+/// Events of the log crate are logged as those of tracing
+pub fn init(service: &str, version: &str, config: &TracingConfig) -> Exporter {
+    let (layer, exporter) = layer(service, config);
+    tracing_subscriber::registry()
+        .with(EnvFilter::from_default_env())
+        .with(JsonLayer::new(service, version))
+        .with(layer)
+        .init();
+    exporter
+}
+
+/// Returns the trace context carried by the headers returned by `header`
+fn extract<'a>(header: impl Fn(&str) -> Option<&'a str>) -> Context {
+    let carrier: HashMap<String, String> = HEADERS
+        .iter()
+        .filter_map(|name| Some((name.to_string(), header(name)?.to_string())))
+        .collect();
+    global::get_text_map_propagator(|propagator| propagator.extract(&carrier))
+}
+
+/// Returns the span of a request received by a server, which continues the trace
+/// carried by the headers returned by `header`, or starts one if they don't carry one
+///
+/// The status of the response is recorded as `http.status_code`, and the client and
+/// user of its bearer token by `record_principal`
+pub fn server_span<'a>(
+    method: &str,
+    target: &str,
+    header: impl Fn(&str) -> Option<&'a str>,
+) -> Span {
+    let span = tracing::info_span!(
+        "request",
+        otel.kind = "server",
+        http.method = method,
+        http.target = target,
+        http.status_code = field::Empty,
+        request_id = field::Empty,
+        trace_id = field::Empty,
+        cid = field::Empty,
+        sub = field::Empty,
+    );
+    span.set_parent(&extract(&header));
+
+    if let Some(request_id) = header(REQUEST_ID) {
+        span.record("request_id", &request_id);
+    }
+
+    // The span only has a trace context if it is exported, otherwise that of the
+    // request is logged
+    let traceparent = headers(&span).remove("traceparent");
+    let traceparent = traceparent.as_deref().or_else(|| header("traceparent"));
+    if let Some(trace_id) = traceparent.and_then(trace_id) {
+        span.record("trace_id", &trace_id);
+    }
+    span
+}
+
+/// Returns the trace ID of a `traceparent` header
+fn trace_id(traceparent: &str) -> Option<&str> {
+    traceparent.split('-').nth(1).filter(|x| x.len() == 32)
+}
+
+/// Records the client and user of the bearer token of a request on its span
+pub fn record_principal(span: &Span, cid: &str, sub: Option<&str>) {
+    span.record("cid", &cid);
+    if let Some(sub) = sub {
+        span.record("sub", &sub);
+    }
+}
+
+/// Returns the span of a request made to another service, which should be sent with
+/// the `headers` of the span so that the service continues its trace
+///
+/// The status of the response is recorded as `http.status_code`
+pub fn client_span(method: &str, url: &str) -> Span {
+    tracing::info_span!(
+        "upstream",
+        otel.kind = "client",
+        http.method = method,
+        http.url = url,
+        http.status_code = field::Empty,
+    )
+}
+
+/// Returns the W3C trace context headers of `span`, which are empty if it isn't
+/// exported
+pub fn headers(span: &Span) -> HashMap<String, String> {
+    let mut carrier = HashMap::new();
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&span.context(), &mut carrier)
+    });
+    carrier
+}
+
+#[cfg(test)]
+mod tests {
+    use opentelemetry::trace::TracerProvider;
+
+    use super::*;
+
+    #[test]
+    fn test_propagation() {
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        // The tracer only samples spans while its provider is alive
+        let provider = trace::TracerProvider::builder().build();
+        let tracer = provider.get_tracer("test", None);
+        let subscriber =
+            tracing_subscriber::registry().with(tracing_opentelemetry::layer().with_tracer(tracer));
+
+        tracing::subscriber::with_default(subscriber, || {
+            let trace_id = "0af7651916cd43dd8448eb211c80319c";
+            let parent = format!("00-{}-b7ad6b7169203331-01", trace_id);
+            let span = server_span("GET", "/", |name| match name {
+                "traceparent" => Some(parent.as_str()),
+                _ => None,
+            });
+
+            // The span continues the trace as a child of the parent
+            let traceparent = &headers(&span)["traceparent"];
+            assert!(traceparent.starts_with(&format!("00-{}-", trace_id)));
+            assert!(!traceparent.contains("b7ad6b7169203331"));
+
+            // The span of a request made within it continues the same trace
+            let client = span.in_scope(|| client_span("POST", "http://calculator/"));
+            assert!(headers(&client)["traceparent"].contains(trace_id));
+
+            // Requests without a trace context start their own
+            let span = server_span("GET", "/", |_| None);
+            assert!(!headers(&span)["traceparent"].contains(trace_id));
+        });
+    }
+
+    #[test]
+    fn test_trace_id() {
+        let traceparent = "00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01";
+        assert_eq!(
+            trace_id(traceparent),
+            Some("0af7651916cd43dd8448eb211c80319c")
+        );
+        assert_eq!(trace_id("00-0af7-b7ad6b7169203331-01"), None);
+        assert_eq!(trace_id(""), None);
+    }
+}
@@ -0,0 +1,59 @@
+use std::fs;
+use std::path::Path;
+
+use rusoto_dynamodb::{CreateTableInput, DynamoDb as _, DynamoDbClient};
+use rusoto_util::Target;
+use testcontainers::clients::Cli;
+use testcontainers::images::generic::{GenericImage, WaitFor};
+use testcontainers::{Container, Docker};
+
+use crate::{host, DOCKER, READY_TIMEOUT, REGION};
+
+const IMAGE: &str = "amazon/dynamodb-local:1.13.5";
+
+const PORT: u16 = 8000;
+
+/// A DynamoDB Local container, removed once dropped
+pub struct DynamoDb {
+    container: Container<'static, Cli, GenericImage>,
+}
+
+impl DynamoDb {
+    /// Starts DynamoDB Local, returning once it accepts requests
+    pub async fn start() -> DynamoDb {
+        let image = GenericImage::new(IMAGE)
+            .with_wait_for(WaitFor::message_on_stdout("Initializing DynamoDB Local"));
+        let dynamo = DynamoDb {
+            container: DOCKER.run(image),
+        };
+
+        dynamo_util::wait_ready(&dynamo.client(), READY_TIMEOUT)
+            .await
+            .expect("DynamoDB Local didn't accept requests");
+        dynamo
+    }
+
+    pub fn endpoint(&self) -> String {
+        format!("http://{}", host(self.container.get_host_port(PORT)))
+    }
+
+    pub fn client(&self) -> DynamoDbClient {
+        dynamo_util::dynamo_client(REGION.to_string(), Some(self.endpoint()), Target::Local)
+    }
+
+    /// Creates the table described by the JSON file at `path`, in the format of
+    /// `aws dynamodb create-table --cli-input-json`, so that tests create the same
+    /// tables as the scripts provisioning them
+    pub async fn create_table(&self, path: impl AsRef<Path>) {
+        let path = path.as_ref();
+        let json = fs::read_to_string(path)
+            .unwrap_or_else(|e| panic!("Failed to read {}: {}", path.display(), e));
+        let input: CreateTableInput = serde_json::from_str(&json)
+            .unwrap_or_else(|e| panic!("Invalid table {}: {}", path.display(), e));
+
+        self.client()
+            .create_table(input)
+            .await
+            .expect("Failed to create table");
+    }
+}
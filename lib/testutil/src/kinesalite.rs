@@ -0,0 +1,68 @@
+use rusoto_core::request::HttpClient;
+use rusoto_kinesis::{CreateStreamInput, DescribeStreamSummaryInput, Kinesis, KinesisClient};
+use rusoto_util::{client_config, wait_ready, Target};
+use testcontainers::clients::Cli;
+use testcontainers::images::generic::{GenericImage, WaitFor};
+use testcontainers::{Container, Docker};
+
+use crate::{host, DOCKER, READY_TIMEOUT, REGION};
+
+const IMAGE: &str = "instructure/kinesalite:latest";
+
+const PORT: u16 = 4567;
+
+/// A kinesalite container, removed once dropped
+///
+/// Pipelines deliver to it when built with `local()` and its `endpoint()`
+pub struct Kinesalite {
+    container: Container<'static, Cli, GenericImage>,
+}
+
+impl Kinesalite {
+    /// Starts kinesalite, returning once it is listening
+    pub fn start() -> Kinesalite {
+        let image = GenericImage::new(IMAGE).with_wait_for(WaitFor::message_on_stdout("Listening"));
+        Kinesalite {
+            container: DOCKER.run(image),
+        }
+    }
+
+    pub fn endpoint(&self) -> String {
+        format!("http://{}", host(self.container.get_host_port(PORT)))
+    }
+
+    pub fn client(&self) -> KinesisClient {
+        let (region, credentials) =
+            client_config(REGION.to_string(), Some(self.endpoint()), Target::Local);
+        let dispatcher = HttpClient::new().expect("failed to create request dispatcher");
+        KinesisClient::new_with(dispatcher, credentials, region)
+    }
+
+    /// Creates a stream of `shard_count` shards, returning once it is active
+    pub async fn create_stream(&self, stream_name: &str, shard_count: i64) {
+        let client = self.client();
+        client
+            .create_stream(CreateStreamInput {
+                shard_count,
+                stream_name: stream_name.to_string(),
+            })
+            .await
+            .expect("Failed to create stream");
+
+        wait_ready(READY_TIMEOUT, || async {
+            let output = client
+                .describe_stream_summary(DescribeStreamSummaryInput {
+                    stream_name: stream_name.to_string(),
+                })
+                .await
+                .map_err(|e| e.to_string())?;
+
+            match output.stream_description_summary.stream_status.as_str() {
+                "ACTIVE" => Ok(()),
+                status => Err(format!("stream is {}", status)),
+            }
+        })
+        .await
+        .expect("Stream didn't become active");
+    }
+}
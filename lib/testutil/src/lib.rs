@@ -0,0 +1,36 @@
+//! The dependencies of integration tests, run in Docker containers by testcontainers
+//!
+//! Each test starts the dependencies it needs, which are removed once it drops them,
+//! so that `cargo test` doesn't require them to already be running and tests don't
+//! share state. Docker must be installed, and its daemon running
+
+#[macro_use]
+extern crate lazy_static;
+
+use std::time::Duration;
+
+use testcontainers::clients::Cli;
+
+mod dynamodb;
+mod kinesalite;
+mod rabbitmq;
+
+pub use dynamodb::DynamoDb;
+pub use kinesalite::Kinesalite;
+pub use rabbitmq::RabbitMQ;
+
+/// The region of the clients of the emulators, which accept any region
+pub const REGION: &str = "us-east-1";
+
+/// How long a dependency is given to accept requests once its container has started
+const READY_TIMEOUT: Duration = Duration::from_secs(30);
+
+lazy_static! {
+    static ref DOCKER: Cli = Cli::default();
+}
+
+/// Returns the address on the host that a port of a container is published at
+fn host(port: Option<u16>) -> String {
+    let port = port.expect("Container port isn't published");
+    format!("127.0.0.1:{}", port)
+}
@@ -0,0 +1,45 @@
+use lapin::{Connection, ConnectionProperties};
+use testcontainers::clients::Cli;
+use testcontainers::images::generic::{GenericImage, WaitFor};
+use testcontainers::{Container, Docker};
+
+use crate::{host, DOCKER};
+
+const IMAGE: &str = "rabbitmq:3.8";
+
+const PORT: u16 = 5672;
+
+/// The credentials of the broker's user, as the default guest user may only connect
+/// from within the container
+const USER: &str = "rabbitmq";
+const PASSWORD: &str = "rabbitmq";
+
+/// A RabbitMQ container, removed once dropped
+pub struct RabbitMQ {
+    container: Container<'static, Cli, GenericImage>,
+}
+
+impl RabbitMQ {
+    /// Starts RabbitMQ, returning once it accepts connections
+    pub fn start() -> RabbitMQ {
+        let image = GenericImage::new(IMAGE)
+            .with_env_var("RABBITMQ_DEFAULT_USER", USER)
+            .with_env_var("RABBITMQ_DEFAULT_PASS", PASSWORD)
+            .with_wait_for(WaitFor::message_on_stdout("Server startup complete"));
+        RabbitMQ {
+            container: DOCKER.run(image),
+        }
+    }
+
+    /// Returns the AMQP URL of the broker's default virtual host
+    pub fn url(&self) -> String {
+        let host = host(self.container.get_host_port(PORT));
+        format!("amqp://{}:{}@{}/%2f", USER, PASSWORD, host)
+    }
+
+    pub async fn connect(&self) -> Connection {
+        Connection::connect(&self.url(), ConnectionProperties::default())
+            .await
+            .expect("Failed to connect to RabbitMQ")
+    }
+}
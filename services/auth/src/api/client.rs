@@ -4,12 +4,15 @@ use std::sync::Arc;
 use rocket::http::Status;
 use rocket::{Route, State};
 use rocket_contrib::json::Json;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 
+use errors::Error;
+use openapi::OpenApi;
 use rocket_util::Authenticated;
 use telemetry::Measure;
 
-use crate::api::error::ApiError;
 use crate::dao::ClientDao;
 use crate::model::{GrantType, Scope};
 use crate::policy;
@@ -20,7 +23,7 @@ lazy_static! {
     static ref UPDATE_MEASURE: Measure = Measure::new("controller", "client_update");
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 struct CreateClientRequest {
     client_name: String,
     scopes: HashSet<Scope>,
@@ -29,7 +32,7 @@ struct CreateClientRequest {
     credential: Option<bool>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 struct CreateClientResponse {
     client_id: String,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -41,7 +44,7 @@ async fn register(
     authenticated: Authenticated,
     form: Json<CreateClientRequest>,
     client_dao: State<'_, Arc<dyn ClientDao>>,
-) -> Result<Json<CreateClientResponse>, ApiError> {
+) -> Result<Json<CreateClientResponse>, Error> {
     REGISTER_MEASURE
         .stats(async move {
             policy::client::register(&authenticated.claims)?;
@@ -66,7 +69,7 @@ async fn register(
         .await
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 struct ClientResponse {
     client_id: String,
     client_name: String,
@@ -79,15 +82,15 @@ async fn get(
     client_id: String,
     authenticated: Authenticated,
     client_dao: State<'_, Arc<dyn ClientDao>>,
-) -> Result<Json<ClientResponse>, ApiError> {
+) -> Result<Json<ClientResponse>, Error> {
     GET_MEASURE
         .stats(async move {
-            policy::client::get(&authenticated.claims).map_err(ApiError::from)?;
+            policy::client::get(&authenticated.claims).map_err(Error::from)?;
 
             let client = client_dao
                 .lookup(&client_id)
                 .await?
-                .ok_or(ApiError::NotFound)?;
+                .ok_or_else(Error::not_found)?;
 
             Ok(Json(ClientResponse {
                 client_id: client.client_id,
@@ -113,11 +116,11 @@ async fn update(
     authenticated: Authenticated,
     client_dao: State<'_, Arc<dyn ClientDao>>,
     form: Json<CreateClientRequest>,
-) -> Result<Status, ApiError> {
+) -> Result<Status, Error> {
     UPDATE_MEASURE
         .stats(async move {
             let request = form.into_inner();
-            policy::client::update(&authenticated.claims).map_err(ApiError::from)?;
+            policy::client::update(&authenticated.claims).map_err(Error::from)?;
 
             client_dao
                 .update(
@@ -132,13 +135,70 @@ async fn update(
             Ok(Status::NoContent)
         })
         .await
-        .map_err(ApiError::into)
 }
 
 pub(crate) fn routes() -> Vec<Route> {
     routes![register, get, update]
 }
 
+pub(crate) fn document(api: &mut OpenApi) {
+    let request = api.schema::<CreateClientRequest>();
+    let created = api.schema::<CreateClientResponse>();
+    let client = api.schema::<ClientResponse>();
+    let client_id = OpenApi::path_parameter("client_id", "The id of the client");
+    let forbidden = api.error("The token lacks the scope to manage clients");
+    let not_found = api.error("The client doesn't exist");
+
+    api.operation(
+        "post",
+        "/api/v1/client",
+        json!({
+            "summary": "Registers a client, generating a credential if requested",
+            "operationId": "registerClient",
+            "security": [{ "bearer": [] }],
+            "requestBody": { "required": true, "content": OpenApi::json(request.clone()) },
+            "responses": {
+                "200": { "description": "The registered client", "content": OpenApi::json(created) },
+                "401": { "description": "The JWT is missing or invalid" },
+                "403": forbidden,
+            },
+        }),
+    );
+    api.operation(
+        "get",
+        "/api/v1/client/{client_id}",
+        json!({
+            "summary": "Returns a client",
+            "operationId": "getClient",
+            "security": [{ "bearer": [] }],
+            "parameters": [client_id],
+            "responses": {
+                "200": { "description": "The client", "content": OpenApi::json(client) },
+                "401": { "description": "The JWT is missing or invalid" },
+                "403": forbidden,
+                "404": not_found,
+            },
+        }),
+    );
+    api.operation(
+        "patch",
+        "/api/v1/client/{client_id}",
+        json!({
+            "summary": "Updates the name, scopes and grants of a client",
+            "operationId": "updateClient",
+            "security": [{ "bearer": [] }],
+            "parameters": [client_id],
+            "requestBody": { "required": true, "content": OpenApi::json(request) },
+            "responses": {
+                "204": { "description": "The client was updated" },
+                "401": { "description": "The JWT is missing or invalid" },
+                "403": forbidden,
+                "404": not_found,
+            },
+        }),
+    );
+}
+
 #[cfg(test)]
 mod tests {
     use std::error::Error;
@@ -1,100 +1,61 @@
-use std::borrow::Cow;
-
-use rocket::http::Status;
-use rocket::{response, Request};
-use rocket_contrib::json::Json;
-use serde::Serialize;
-
-use jwt::IssuerError;
-use telemetry::IsErr;
+use errors::Error;
 
 use crate::dao::DaoError;
 use crate::policy::PolicyError;
 use crate::service::AuthError;
 
-#[derive(Debug)]
-pub enum ApiError {
-    AlreadyExists,
-    NotFound,
-    InvalidCredential,
-    ExpiredCredential,
-    InvalidRequest,
-    Forbidden,
-    InternalError(String),
+/// The error of a request missing a parameter, or with an invalid one
+pub(crate) fn invalid_request() -> Error {
+    Error::bad_request("Invalid Request")
 }
 
-#[derive(Serialize)]
-struct ErrorResponse<'a> {
-    message: Cow<'a, str>,
+/// The error of a request for a grant type that is turned off
+pub(crate) fn unsupported_grant_type() -> Error {
+    Error::new(400, "unsupported_grant_type", "Unsupported Grant Type")
 }
 
-impl<'r> response::Responder<'r, 'static> for ApiError {
-    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
-        let (message, status) = match self {
-            ApiError::InternalError(e) => {
-                error!("InternalServerError: {}", e);
-                (
-                    Cow::Borrowed("Internal Server Error"),
-                    Status::InternalServerError,
-                )
-            }
-            ApiError::AlreadyExists => (Cow::Borrowed("Already Exists"), Status::BadRequest),
-            ApiError::NotFound => (Cow::Borrowed("Not Found"), Status::NotFound),
-            ApiError::InvalidCredential => {
-                (Cow::Borrowed("Invalid Credential"), Status::BadRequest)
-            }
-            ApiError::ExpiredCredential => {
-                (Cow::Borrowed("Expired Credential"), Status::Unauthorized)
-            }
-            ApiError::InvalidRequest => (Cow::Borrowed("Invalid Request"), Status::BadRequest),
-            ApiError::Forbidden => (Cow::Borrowed("Forbidden"), Status::Forbidden),
-        };
-        response::status::Custom(status, Json(ErrorResponse { message })).respond_to(req)
-    }
+fn already_exists() -> Error {
+    Error::new(400, "already_exists", "Already Exists")
 }
 
-impl From<IssuerError> for ApiError {
-    fn from(e: IssuerError) -> Self {
-        Self::InternalError(format!("IssuerError: {}", e))
-    }
+fn invalid_credential() -> Error {
+    Error::new(400, "invalid_credential", "Invalid Credential")
 }
 
-impl From<DaoError> for ApiError {
+fn expired_credential() -> Error {
+    Error::new(401, "expired_credential", "Expired Credential")
+}
+
+impl From<DaoError> for Error {
     fn from(e: DaoError) -> Self {
         match e {
-            DaoError::AlreadyExists => Self::AlreadyExists,
-            DaoError::InvalidCredential => Self::InvalidCredential,
-            DaoError::ExpiredCredential => Self::ExpiredCredential,
-            DaoError::NotFound => Self::NotFound,
-            DaoError::InternalError(e) => Self::InternalError(format!("DaoError: {}", e)),
+            DaoError::AlreadyExists => already_exists(),
+            DaoError::InvalidCredential => invalid_credential(),
+            DaoError::ExpiredCredential => expired_credential(),
+            DaoError::NotFound => Error::not_found(),
+            DaoError::InternalError(e) => Error::internal(format!("DaoError: {}", e)),
         }
     }
 }
 
-impl From<PolicyError> for ApiError {
+impl From<PolicyError> for Error {
     fn from(e: PolicyError) -> Self {
         match e {
-            PolicyError::PermissionDenied => Self::Forbidden,
+            PolicyError::PermissionDenied => Error::forbidden(),
         }
     }
 }
 
-impl From<AuthError> for ApiError {
+impl From<AuthError> for Error {
     fn from(e: AuthError) -> Self {
         match e {
-            AuthError::NotFound => Self::InvalidCredential,
-            AuthError::NotLoopback => Self::InvalidCredential,
-            AuthError::InvalidCredential => Self::InvalidCredential,
-            AuthError::IllegalScopes => Self::InvalidRequest,
-            AuthError::ExpiredCredential => Self::ExpiredCredential,
-            AuthError::AlreadyExists => Self::InvalidRequest,
-            AuthError::InternalError(e) => Self::InternalError(format!("AuthError: {}", e)),
+            AuthError::NotFound => invalid_credential(),
+            AuthError::NotLoopback => invalid_credential(),
+            AuthError::InvalidCredential => invalid_credential(),
+            AuthError::IllegalScopes => invalid_request(),
+            AuthError::ExpiredCredential => expired_credential(),
+            AuthError::AlreadyExists => invalid_request(),
+            AuthError::InternalError(e) => Error::internal(format!("AuthError: {}", e)),
         }
     }
 }
-
-impl IsErr for ApiError {
-    fn is_err(&self) -> bool {
-        matches!(self, ApiError::InternalError(_))
-    }
-}
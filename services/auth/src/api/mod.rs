@@ -1,9 +1,10 @@
 use rocket::http::Status;
 use rocket::response::content;
 use rocket::{Route, State};
-use rocket_contrib::json::JsonValue;
+use serde_json::json;
 
-use jwt::Issuer;
+use jwt::{Issuer, Jwks};
+use openapi::OpenApi;
 
 pub use crate::api::config::ApiConfig;
 use std::sync::Arc;
@@ -19,24 +20,45 @@ fn jwks(issuer: State<Arc<Issuer>>) -> content::Json<String> {
     content::Json(issuer.jwks().clone())
 }
 
-#[get("/status")]
-fn status() -> JsonValue {
-    json!({ "status": "ok" })
-}
-
 #[get("/metrics")]
 fn metrics() -> Result<String, Status> {
     telemetry::encode().map_err(|_| Status::InternalServerError)
 }
 
 pub fn routes() -> Vec<Route> {
-    let mut routes = routes![status, metrics, jwks];
+    let mut routes = routes![metrics, jwks];
+    routes.append(&mut health::routes());
     routes.append(&mut token::routes());
     routes.append(&mut client::routes());
     routes.append(&mut user::routes());
     routes
 }
 
+fn document_jwks(api: &mut OpenApi) {
+    let jwks = api.schema::<Jwks>();
+    api.operation(
+        "get",
+        "/.well-known/jwks.json",
+        json!({
+            "summary": "Returns the public keys that issued tokens can be validated with",
+            "operationId": "jwks",
+            "responses": {
+                "200": { "description": "The JSON Web Key Set", "content": OpenApi::json(jwks) },
+            },
+        }),
+    );
+}
+
+/// Adds the operations of the routes to an OpenAPI document
+pub fn document(api: &mut OpenApi) {
+    document_jwks(api);
+    openapi::metrics(api);
+    health::document(api);
+    token::document(api);
+    client::document(api);
+    user::document(api);
+}
+
 #[cfg(test)]
 mod tests {
     use std::error::Error;
@@ -44,26 +66,32 @@ mod tests {
 
     use ring::rand::SystemRandom;
     use rocket::local::blocking::Client;
-    use serde::Deserialize;
 
-    use jwt::Jwks;
+    use health::{Health, Probe, Report};
 
     use super::*;
 
-    #[derive(Deserialize)]
-    struct StatusResponse {
-        status: String,
-    }
-
     #[test]
-    fn test_status() -> Result<(), Box<dyn Error>> {
-        let rocket = rocket::ignite().mount("/", routes![status]);
+    fn test_health() -> Result<(), Box<dyn Error>> {
+        let health = Health::new().register("dynamo", &[Probe::Ready], || async {
+            Err("connection refused")
+        });
+        let rocket = rocket::ignite().manage(health).mount("/", health::routes());
         let client = Client::untracked(rocket).expect("valid rocket instance");
-        let response = client.get("/status").dispatch();
 
+        let response = client.get("/livez").dispatch();
         assert_eq!(response.status(), Status::Ok);
-        let decoded: StatusResponse = serde_json::from_reader(response)?;
-        assert_eq!(decoded.status, "ok");
+        let decoded: Report = serde_json::from_reader(response)?;
+        assert!(decoded.is_ok());
+
+        let response = client.get("/readyz").dispatch();
+        assert_eq!(response.status(), Status::ServiceUnavailable);
+        let decoded: Report = serde_json::from_reader(response)?;
+        assert_eq!(decoded.checks.len(), 1);
+        assert_eq!(
+            decoded.checks[0].error.as_deref(),
+            Some("connection refused")
+        );
         Ok(())
     }
 
@@ -83,4 +111,13 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn test_document() {
+        let doc = OpenApi::default().with(document).build("Auth", "");
+        assert_eq!(
+            openapi::undocumented_routes(&doc, &routes()),
+            Vec::<String>::new()
+        );
+    }
 }
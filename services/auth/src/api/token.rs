@@ -4,13 +4,18 @@ use std::net::SocketAddr;
 use rocket::request::Form;
 use rocket::{Route, State};
 use rocket_contrib::json::Json;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 
+use errors::Error;
+use flags::{Flags, Key};
 use jwt::tag;
+use openapi::OpenApi;
 use rocket_util::UserAgent;
 use telemetry::Measure;
 
-use crate::api::error::ApiError;
+use crate::api::error::{invalid_request, unsupported_grant_type};
 use crate::api::ApiConfig;
 use crate::model::{GrantType, Scope};
 use crate::service::AuthService;
@@ -20,7 +25,20 @@ lazy_static! {
     static ref TOKEN_MEASURE: Measure = Measure::new("controller", "token");
 }
 
-#[derive(Debug, Serialize, Deserialize, FromForm)]
+const PASSWORD_GRANT: Key = Key::new("auth.grant.password", true);
+const CLIENT_CREDENTIALS_GRANT: Key = Key::new("auth.grant.client_credentials", true);
+const REFRESH_TOKEN_GRANT: Key = Key::new("auth.grant.refresh_token", true);
+
+/// Returns the flag a grant type can be turned off for clients with
+fn grant_flag(grant_type: &GrantType) -> &'static Key {
+    match grant_type {
+        GrantType::Password => &PASSWORD_GRANT,
+        GrantType::ClientCredentials => &CLIENT_CREDENTIALS_GRANT,
+        GrantType::RefreshToken => &REFRESH_TOKEN_GRANT,
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, FromForm, JsonSchema)]
 struct TokenRequest {
     grant_type: GrantType,
     client_id: String,
@@ -32,7 +50,7 @@ struct TokenRequest {
     scope: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 struct TokenResponse {
     access_token: String,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -40,9 +58,9 @@ struct TokenResponse {
     expires_in: i64,
 }
 
-fn get_scopes(data: &TokenRequest) -> Result<HashSet<Scope>, ApiError> {
+fn get_scopes(data: &TokenRequest) -> Result<HashSet<Scope>, Error> {
     if let Some(scope_str) = data.scope.as_ref() {
-        return tag::parse_space_delimited(&scope_str).map_err(|_| ApiError::InvalidRequest);
+        return tag::parse_space_delimited(&scope_str).map_err(|_| invalid_request());
     }
     Ok(Default::default())
 }
@@ -63,33 +81,35 @@ async fn token(
     user_agent: Option<UserAgent>,
     auth: State<'_, Arc<AuthService>>,
     config: State<'_, ApiConfig>,
+    flags: State<'_, Arc<Flags>>,
     request: Form<TokenRequest>,
-) -> Result<Json<TokenResponse>, ApiError> {
+) -> Result<Json<TokenResponse>, Error> {
     TOKEN_MEASURE
         .stats(async move {
+            let flag = grant_flag(&request.grant_type);
+            if !flags.is_enabled(flag, Some(&request.client_id)) {
+                return Err(unsupported_grant_type());
+            }
+
             let scopes = get_scopes(&request.0)?;
             let authenticator = auth.get_authenticator(&request.0.client_id, &addr).await?;
 
             let authenticated = match request.grant_type {
                 GrantType::Password => {
-                    let username = request.username.as_ref().ok_or(ApiError::InvalidRequest)?;
-                    let password = request.password.as_ref().ok_or(ApiError::InvalidRequest)?;
+                    let username = request.username.as_ref().ok_or_else(invalid_request)?;
+                    let password = request.password.as_ref().ok_or_else(invalid_request)?;
                     auth.auth_password(authenticator, &username, &password, scopes)
                         .await?
                 }
                 GrantType::ClientCredentials => {
-                    let client_secret = request
-                        .client_secret
-                        .as_ref()
-                        .ok_or(ApiError::InvalidRequest)?;
+                    let client_secret =
+                        request.client_secret.as_ref().ok_or_else(invalid_request)?;
                     auth.auth_client_credential(authenticator, client_secret, scopes)
                         .await?
                 }
                 GrantType::RefreshToken => {
-                    let refresh_token = request
-                        .refresh_token
-                        .as_ref()
-                        .ok_or(ApiError::InvalidRequest)?;
+                    let refresh_token =
+                        request.refresh_token.as_ref().ok_or_else(invalid_request)?;
                     auth.auth_refresh_token(authenticator, &refresh_token, scopes)
                         .await?
                 }
@@ -117,8 +137,36 @@ pub(crate) fn routes() -> Vec<Route> {
     routes![token]
 }
 
+pub(crate) fn document(api: &mut OpenApi) {
+    let request = api.schema::<TokenRequest>();
+    let response = api.schema::<TokenResponse>();
+    let invalid = api.error(
+        "A parameter of the grant type is missing, the credentials are invalid, or the grant \
+            type is turned off for the client",
+    );
+    let expired = api.error("The refresh token has expired");
+
+    api.operation(
+        "post",
+        "/api/v1/token",
+        json!({
+            "summary": "Issues an access token, and a refresh token if offline access is requested",
+            "description": "The parameters required depend on the grant type, and scopes are \
+                given space delimited",
+            "operationId": "token",
+            "requestBody": { "required": true, "content": OpenApi::form(request) },
+            "responses": {
+                "200": { "description": "The issued tokens", "content": OpenApi::json(response) },
+                "400": invalid,
+                "401": expired,
+            },
+        }),
+    );
+}
+
 #[cfg(test)]
 mod test {
+    use std::collections::HashMap;
     use std::error::Error;
 
     use ring::rand::SystemRandom;
@@ -142,6 +190,10 @@ mod test {
 
     impl State {
         async fn new() -> State {
+            State::with_flags(Flags::default()).await
+        }
+
+        async fn with_flags(flags: Flags) -> State {
             let rand = Arc::new(SystemRandom::new());
             let token = Arc::new(TokenService::new(rand.clone()));
             let issuer = Arc::new(Issuer::test(rand).expect("Failed to setup issuer"));
@@ -161,6 +213,7 @@ mod test {
                 .manage(validator.clone())
                 .manage(auth_service)
                 .manage(ApiConfig::default())
+                .manage(Arc::new(flags))
                 .manage(client_dao.clone() as Arc<dyn ClientDao>)
                 .manage(renewal_dao.clone() as Arc<dyn RenewalTokenDao>)
                 .manage(user_dao.clone() as Arc<dyn UserDao>)
@@ -378,6 +431,29 @@ mod test {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_grant_turned_off() -> Result<(), Box<dyn Error>> {
+        let flag = flags::Flag {
+            enabled: false,
+            ..Default::default()
+        };
+        let mut configured = HashMap::new();
+        configured.insert(PASSWORD_GRANT.name.to_string(), flag);
+        let state = State::with_flags(Flags::new(configured)).await;
+
+        let scopes: HashSet<_> = [Scope::Superuser].iter().cloned().collect();
+        let grants: HashSet<_> = [GrantType::Password].iter().cloned().collect();
+        let client_id = state.init_client(scopes.clone(), grants).await?;
+
+        let request = state
+            .password_req(client_id.clone(), &scopes, &scopes, true)
+            .await?;
+        let data = state.do_request(&request, Status::BadRequest).await;
+        assert!(data.is_none());
+
+        Ok(())
+    }
+
     #[tokio::test]
     async fn test_password_offline() -> Result<(), Box<dyn Error>> {
         let state = State::new().await;
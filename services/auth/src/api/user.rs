@@ -4,12 +4,15 @@ use std::sync::Arc;
 use rocket::http::Status;
 use rocket::{Route, State};
 use rocket_contrib::json::Json;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 
+use errors::Error;
+use openapi::OpenApi;
 use rocket_util::Authenticated;
 use telemetry::Measure;
 
-use crate::api::error::ApiError;
 use crate::dao::UserDao;
 use crate::model::{Scope, User};
 use crate::policy;
@@ -23,7 +26,7 @@ lazy_static! {
     static ref CHANGE_SCOPES_MEASURE: Measure = Measure::new("controller", "change_scopes");
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 struct RegisterRequest {
     username: String,
     password: String,
@@ -34,7 +37,7 @@ struct RegisterRequest {
 async fn register(
     user_dao: State<'_, Arc<dyn UserDao>>,
     data: Json<RegisterRequest>,
-) -> Result<Status, ApiError> {
+) -> Result<Status, Error> {
     REGISTER_MEASURE
         .stats(async move {
             let user_id = user_dao.create_user(&data.full_name, None).await?;
@@ -53,23 +56,23 @@ async fn get_user(
     user_id: String,
     authenticated: Authenticated,
     user_dao: State<'_, Arc<dyn UserDao>>,
-) -> Result<Json<User>, ApiError> {
+) -> Result<Json<User>, Error> {
     GET_MEASURE
         .stats(async move {
-            policy::user::get(&user_id, &authenticated.claims).map_err(ApiError::from)?;
+            policy::user::get(&user_id, &authenticated.claims).map_err(Error::from)?;
 
             let user = user_dao
                 .get_user(&user_id)
                 .await
-                .map_err(ApiError::from)?
-                .ok_or(ApiError::NotFound)?;
+                .map_err(Error::from)?
+                .ok_or_else(Error::not_found)?;
 
             Ok(Json(user))
         })
         .await
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 struct UsernameResponse {
     user_id: String,
 }
@@ -79,17 +82,17 @@ async fn get_username(
     username: String,
     authenticated: Authenticated,
     user_dao: State<'_, Arc<dyn UserDao>>,
-) -> Result<Json<UsernameResponse>, ApiError> {
+) -> Result<Json<UsernameResponse>, Error> {
     GET_USERNAME_MEASURE
         .stats(async move {
             let credential = user_dao
                 .get_credential(&username)
                 .await
-                .map_err(ApiError::from)?
-                .ok_or(ApiError::NotFound)?;
+                .map_err(Error::from)?
+                .ok_or_else(Error::not_found)?;
 
             policy::user::get_username(&credential.user_id, &authenticated.claims)
-                .map_err(ApiError::from)?;
+                .map_err(Error::from)?;
 
             Ok(Json(UsernameResponse {
                 user_id: credential.user_id,
@@ -98,7 +101,7 @@ async fn get_username(
         .await
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 struct ChangePasswordRequest {
     current_password: String,
     new_password: String,
@@ -109,7 +112,7 @@ async fn change_password(
     username: String,
     user_dao: State<'_, Arc<dyn UserDao>>,
     data: Json<ChangePasswordRequest>,
-) -> Result<Status, ApiError> {
+) -> Result<Status, Error> {
     CHANGE_PASSWORD_MEASURE
         .stats(async move {
             user_dao.verify(&username, &data.current_password).await?;
@@ -123,7 +126,7 @@ async fn change_password(
         .await
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 struct ChangeUsername {
     new_username: String,
     current_password: String,
@@ -135,7 +138,7 @@ async fn change_username(
     username: String,
     user_dao: State<'_, Arc<dyn UserDao>>,
     data: Json<ChangeUsername>,
-) -> Result<Status, ApiError> {
+) -> Result<Status, Error> {
     CHANGE_USERNAME_MEASURE
         .stats(async move {
             let cred = user_dao.verify(&username, &data.current_password).await?;
@@ -156,7 +159,7 @@ async fn change_username(
         .await
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 struct ChangeScopes {
     scopes: HashSet<Scope>,
 }
@@ -167,10 +170,10 @@ async fn change_scopes(
     authenticated: Authenticated,
     user_dao: State<'_, Arc<dyn UserDao>>,
     data: Json<ChangeScopes>,
-) -> Result<Status, ApiError> {
+) -> Result<Status, Error> {
     CHANGE_SCOPES_MEASURE
         .stats(async move {
-            policy::user::change_scopes(&authenticated.claims).map_err(ApiError::from)?;
+            policy::user::change_scopes(&authenticated.claims).map_err(Error::from)?;
 
             let request = data.into_inner();
             user_dao.update_scopes(&username, request.scopes).await?;
@@ -191,6 +194,112 @@ pub fn routes() -> Vec<Route> {
     ]
 }
 
+pub(crate) fn document(api: &mut OpenApi) {
+    let register = api.schema::<RegisterRequest>();
+    let user = api.schema::<User>();
+    let username = api.schema::<UsernameResponse>();
+    let change_password = api.schema::<ChangePasswordRequest>();
+    let change_username = api.schema::<ChangeUsername>();
+    let change_scopes = api.schema::<ChangeScopes>();
+    let user_id = OpenApi::path_parameter("user_id", "The id of the user");
+    let username_parameter = OpenApi::path_parameter("username", "The username of the user");
+    let invalid_credential = api.error("The current password is incorrect");
+    let forbidden = api.error("The token doesn't belong to the user, or lacks the scope");
+    let not_found = api.error("The user doesn't exist");
+    let username_taken = api.error("The username is taken");
+
+    api.operation(
+        "post",
+        "/api/v1/register",
+        json!({
+            "summary": "Registers a user",
+            "operationId": "register",
+            "requestBody": { "required": true, "content": OpenApi::json(register) },
+            "responses": {
+                "204": { "description": "The user was registered" },
+                "400": username_taken,
+            },
+        }),
+    );
+    api.operation(
+        "get",
+        "/api/v1/user/{user_id}",
+        json!({
+            "summary": "Returns a user",
+            "operationId": "getUser",
+            "security": [{ "bearer": [] }],
+            "parameters": [user_id],
+            "responses": {
+                "200": { "description": "The user", "content": OpenApi::json(user) },
+                "401": { "description": "The JWT is missing or invalid" },
+                "403": forbidden,
+                "404": not_found,
+            },
+        }),
+    );
+    api.operation(
+        "get",
+        "/api/v1/username/{username}",
+        json!({
+            "summary": "Returns the id of the user with a username",
+            "operationId": "getUsername",
+            "security": [{ "bearer": [] }],
+            "parameters": [username_parameter],
+            "responses": {
+                "200": { "description": "The id of the user", "content": OpenApi::json(username) },
+                "401": { "description": "The JWT is missing or invalid" },
+                "403": forbidden,
+                "404": not_found,
+            },
+        }),
+    );
+    api.operation(
+        "patch",
+        "/api/v1/username/{username}",
+        json!({
+            "summary": "Changes the username of a user, along with their password",
+            "operationId": "changeUsername",
+            "parameters": [username_parameter],
+            "requestBody": { "required": true, "content": OpenApi::json(change_username) },
+            "responses": {
+                "204": { "description": "The username was changed" },
+                "400": invalid_credential,
+            },
+        }),
+    );
+    api.operation(
+        "patch",
+        "/api/v1/username/{username}/password",
+        json!({
+            "summary": "Changes the password of a user",
+            "operationId": "changePassword",
+            "parameters": [username_parameter],
+            "requestBody": { "required": true, "content": OpenApi::json(change_password) },
+            "responses": {
+                "204": { "description": "The password was changed" },
+                "400": invalid_credential,
+            },
+        }),
+    );
+    api.operation(
+        "patch",
+        "/api/v1/username/{username}/scopes",
+        json!({
+            "summary": "Replaces the scopes granted to a user",
+            "operationId": "changeScopes",
+            "security": [{ "bearer": [] }],
+            "parameters": [username_parameter],
+            "requestBody": { "required": true, "content": OpenApi::json(change_scopes) },
+            "responses": {
+                "204": { "description": "The scopes were changed" },
+                "401": { "description": "The JWT is missing or invalid" },
+                "403": forbidden,
+                "404": not_found,
+            },
+        }),
+    );
+}
+
 #[cfg(test)]
 mod tests {
     use std::error::Error;
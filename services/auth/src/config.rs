@@ -1,16 +1,38 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 use credential::CredentialConfig;
+use flags::FlagsConfig;
 use jwt::IssuerConfig;
+use settings::{Error, Validate};
+use shutdown::ShutdownConfig;
+use telemetry::trace::TracingConfig;
 
 use crate::api::ApiConfig;
 use crate::dao::DaoConfig;
 
-#[derive(Debug, Deserialize, Clone, Default)]
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
 #[serde(default)]
 pub struct Config {
     pub api: ApiConfig,
     pub issuer: IssuerConfig,
     pub dao: DaoConfig,
     pub credential: CredentialConfig,
+    pub flags: FlagsConfig,
+    pub shutdown: ShutdownConfig,
+    pub tracing: TracingConfig,
+}
+
+impl Validate for Config {
+    fn validate(&self) -> Result<(), Error> {
+        if self.api.access_token_ttl <= 0 {
+            return Err(Error::invalid("api.access_token_ttl", "must be positive"));
+        }
+        if self.api.refresh_token_ttl <= self.api.access_token_ttl {
+            return Err(Error::invalid(
+                "api.refresh_token_ttl",
+                "must be greater than api.access_token_ttl",
+            ));
+        }
+        Ok(())
+    }
 }
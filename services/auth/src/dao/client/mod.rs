@@ -50,33 +50,40 @@ mod test {
     use ring::rand::SystemRandom;
 
     use credential::CredentialService;
+    use testutil::DynamoDb;
 
+    use crate::dao::DaoConfig;
     use crate::service::token::TokenService;
 
     use super::*;
 
-    fn clients() -> Result<Vec<Box<dyn ClientDao>>, Box<dyn Error>> {
-        let figment = rocket::Config::figment();
-        let config: crate::config::Config = figment.extract().unwrap();
-        let client = Arc::new(config.dao.dynamo_client());
+    /// Returns the DAOs to test, along with the container of DynamoDB Local, which
+    /// must be held until the test completes
+    async fn clients() -> Result<(DynamoDb, Vec<Box<dyn ClientDao>>), Box<dyn Error>> {
+        let dynamo = DynamoDb::start().await;
+        let config = DaoConfig::test(&dynamo).await;
+        let client = Arc::new(config.dynamo_client());
         let rand = Arc::new(SystemRandom::new());
         let credential = Arc::new(CredentialService::test()?);
         let token = Arc::new(TokenService::new(rand));
 
-        Ok(vec![
-            Box::new(ClientDaoDynamo::new(
-                &config.dao,
-                client,
-                credential,
-                token.clone(),
-            )),
-            Box::new(ClientDaoMemory::new(token)),
-        ])
+        Ok((
+            dynamo,
+            vec![
+                Box::new(ClientDaoDynamo::new(
+                    &config,
+                    client,
+                    credential,
+                    token.clone(),
+                )),
+                Box::new(ClientDaoMemory::new(token)),
+            ],
+        ))
     }
 
     #[tokio::test]
     async fn test_client_register() -> Result<(), Box<dyn Error>> {
-        let clients = clients()?;
+        let (_dynamo, clients) = clients().await?;
 
         for client in clients.iter() {
             let grants: HashSet<_> = [GrantType::RefreshToken, GrantType::Password]
@@ -111,7 +118,7 @@ mod test {
 
     #[tokio::test]
     async fn test_update() -> Result<(), Box<dyn Error>> {
-        let clients = clients()?;
+        let (_dynamo, clients) = clients().await?;
 
         for client in clients.iter() {
             let grants: HashSet<_> = [GrantType::RefreshToken, GrantType::Password]
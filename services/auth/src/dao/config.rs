@@ -1,7 +1,8 @@
 use rusoto_dynamodb::DynamoDbClient;
-use serde::Deserialize;
+use rusoto_util::Target;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
 #[serde(default)]
 pub struct DaoConfig {
     pub region: String,
@@ -25,6 +26,29 @@ impl Default for DaoConfig {
 
 impl DaoConfig {
     pub fn dynamo_client(&self) -> DynamoDbClient {
-        dynamo_util::dynamo_client(self.region.clone(), self.endpoint.clone(), self.local)
+        dynamo_util::dynamo_client(
+            self.region.clone(),
+            self.endpoint.clone(),
+            Target::local(self.local),
+        )
+    }
+}
+
+#[cfg(test)]
+impl DaoConfig {
+    /// Returns the configuration of DAOs using `dynamo`, creating the service's table
+    pub async fn test(dynamo: &testutil::DynamoDb) -> DaoConfig {
+        dynamo
+            .create_table(concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/scripts/dynamodb.json"
+            ))
+            .await;
+
+        DaoConfig {
+            endpoint: Some(dynamo.endpoint()),
+            local: true,
+            ..Default::default()
+        }
     }
 }
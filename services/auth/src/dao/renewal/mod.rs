@@ -35,28 +35,35 @@ mod test {
     use ring::rand::SystemRandom;
 
     use credential::CredentialService;
+    use testutil::DynamoDb;
 
+    use crate::dao::DaoConfig;
     use crate::service::token::TokenService;
 
     use super::*;
 
-    fn clients() -> Result<Vec<Box<dyn RenewalTokenDao>>, Box<dyn Error>> {
-        let figment = rocket::Config::figment();
-        let config: crate::config::Config = figment.extract().unwrap();
-        let client = Arc::new(config.dao.dynamo_client());
+    /// Returns the DAOs to test, along with the container of DynamoDB Local, which
+    /// must be held until the test completes
+    async fn clients() -> Result<(DynamoDb, Vec<Box<dyn RenewalTokenDao>>), Box<dyn Error>> {
+        let dynamo = DynamoDb::start().await;
+        let config = DaoConfig::test(&dynamo).await;
+        let client = Arc::new(config.dynamo_client());
         let rand = Arc::new(SystemRandom::new());
         let credential = Arc::new(CredentialService::test()?);
         let token = Arc::new(TokenService::new(rand));
 
-        Ok(vec![
-            Box::new(RenewalTokenDaoDynamo::new(
-                &config.dao,
-                client,
-                credential,
-                token.clone(),
-            )),
-            Box::new(RenewalTokenDaoMemory::new(token)),
-        ])
+        Ok((
+            dynamo,
+            vec![
+                Box::new(RenewalTokenDaoDynamo::new(
+                    &config,
+                    client,
+                    credential,
+                    token.clone(),
+                )),
+                Box::new(RenewalTokenDaoMemory::new(token)),
+            ],
+        ))
     }
 
     async fn get_token(
@@ -78,7 +85,7 @@ mod test {
 
     #[tokio::test]
     async fn test_basic() -> Result<(), Box<dyn Error>> {
-        let clients = clients()?;
+        let (_dynamo, clients) = clients().await?;
 
         for client in clients.iter() {
             let token = get_token(client.as_ref(), 1000).await?;
@@ -90,7 +97,7 @@ mod test {
 
     #[tokio::test]
     async fn test_expiry() -> Result<(), Box<dyn Error>> {
-        let clients = clients()?;
+        let (_dynamo, clients) = clients().await?;
 
         for client in clients.iter() {
             let token = get_token(client.as_ref(), -1000).await?;
@@ -106,7 +113,7 @@ mod test {
 
     #[tokio::test]
     async fn test_duplicate_consume() -> Result<(), Box<dyn Error>> {
-        let clients = clients()?;
+        let (_dynamo, clients) = clients().await?;
 
         for client in clients.iter() {
             let token = get_token(client.as_ref(), 1000).await?;
@@ -123,7 +130,7 @@ mod test {
 
     #[tokio::test]
     async fn test_incorrect_client() -> Result<(), Box<dyn Error>> {
-        let clients = clients()?;
+        let (_dynamo, clients) = clients().await?;
 
         for client in clients.iter() {
             let token = get_token(client.as_ref(), 1000).await?;
@@ -46,24 +46,32 @@ mod test {
     use std::sync::Arc;
 
     use credential::CredentialService;
+    use testutil::DynamoDb;
+
+    use crate::dao::DaoConfig;
 
     use super::*;
 
-    fn clients() -> Result<Vec<Box<dyn UserDao>>, Box<dyn Error>> {
-        let figment = rocket::Config::figment();
-        let config: crate::config::Config = figment.extract().unwrap();
-        let client = Arc::new(config.dao.dynamo_client());
+    /// Returns the DAOs to test, along with the container of DynamoDB Local, which
+    /// must be held until the test completes
+    async fn clients() -> Result<(DynamoDb, Vec<Box<dyn UserDao>>), Box<dyn Error>> {
+        let dynamo = DynamoDb::start().await;
+        let config = DaoConfig::test(&dynamo).await;
+        let client = Arc::new(config.dynamo_client());
         let credential = Arc::new(CredentialService::test()?);
 
-        Ok(vec![
-            Box::new(UserDaoDynamo::new(&config.dao, client, credential)),
-            Box::new(UserDaoMemory::new()),
-        ])
+        Ok((
+            dynamo,
+            vec![
+                Box::new(UserDaoDynamo::new(&config, client, credential)),
+                Box::new(UserDaoMemory::new()),
+            ],
+        ))
     }
 
     #[tokio::test]
     async fn test_create_user() -> Result<(), Box<dyn Error>> {
-        let clients = clients()?;
+        let (_dynamo, clients) = clients().await?;
 
         for client in clients.iter() {
             let user_id = client.create_user("asdf", None).await?;
@@ -78,7 +86,7 @@ mod test {
 
     #[tokio::test]
     async fn test_create_user_credential() -> Result<(), Box<dyn Error>> {
-        let clients = clients()?;
+        let (_dynamo, clients) = clients().await?;
 
         for client in clients.iter() {
             let scopes: HashSet<_> = [Scope::OfflineAccess].iter().cloned().collect();
@@ -109,7 +117,7 @@ mod test {
 
     #[tokio::test]
     async fn test_credentials() -> Result<(), Box<dyn Error>> {
-        let clients = clients()?;
+        let (_dynamo, clients) = clients().await?;
 
         for client in clients.iter() {
             let scopes: HashSet<_> = [Scope::OfflineAccess].iter().cloned().collect();
@@ -137,7 +145,7 @@ mod test {
 
     #[tokio::test]
     async fn test_duplicate() -> Result<(), Box<dyn Error>> {
-        let clients = clients()?;
+        let (_dynamo, clients) = clients().await?;
 
         for client in clients.iter() {
             let scopes: HashSet<_> = [Scope::OfflineAccess].iter().cloned().collect();
@@ -169,7 +177,7 @@ mod test {
 
     #[tokio::test]
     async fn test_change_password() -> Result<(), Box<dyn Error>> {
-        let clients = clients()?;
+        let (_dynamo, clients) = clients().await?;
 
         for client in clients.iter() {
             let scopes: HashSet<_> = [Scope::OfflineAccess].iter().cloned().collect();
@@ -201,7 +209,7 @@ mod test {
 
     #[tokio::test]
     async fn test_change_scopes() -> Result<(), Box<dyn Error>> {
-        let clients = clients()?;
+        let (_dynamo, clients) = clients().await?;
 
         for client in clients.iter() {
             let scopes: HashSet<_> = [Scope::OfflineAccess].iter().cloned().collect();
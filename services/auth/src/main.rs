@@ -2,16 +2,20 @@
 extern crate lazy_static;
 #[macro_use]
 extern crate rocket;
-#[macro_use]
-extern crate rocket_contrib;
 
 use std::error::Error;
 use std::sync::Arc;
 
 use ring::rand::SystemRandom;
+use rusoto_dynamodb::DynamoDbClient;
 
 use credential::CredentialService;
-use jwt::Issuer;
+use flags::Flags;
+use health::{Health, Probe};
+use jwt::{Issuer, Validator};
+use openapi::{OpenApi, Spec};
+use rocket_util::RequestTracer;
+use shutdown::{GracefulShutdown, Phase};
 
 use crate::dao::{
     ClientDao, ClientDaoDynamo, RenewalTokenDao, RenewalTokenDaoDynamo, UserDao, UserDaoDynamo,
@@ -26,13 +30,27 @@ mod model;
 mod policy;
 mod service;
 
+/// Returns the checks of the DynamoDB table and of the keys tokens are validated with
+fn health(client: Arc<DynamoDbClient>, table: String, validator: Validator) -> Health {
+    let probes = [Probe::Ready, Probe::Startup];
+    Health::new()
+        .register("dynamo", &probes, move || {
+            let client = client.clone();
+            let table = table.clone();
+            async move { dynamo_util::check_table(&client, &table).await }
+        })
+        .register("jwks", &probes, move || {
+            let result = validator.check();
+            async move { result }
+        })
+}
+
 #[rocket::main]
 async fn main() -> Result<(), Box<dyn Error>> {
-    env_logger::init();
-    let figment = rocket_util::figment();
-
-    let config: config::Config = figment.extract().unwrap();
+    let (config, figment) = settings::load_with::<config::Config>(rocket::Config::figment());
+    let _exporter = telemetry::trace::init("auth", env!("CARGO_PKG_VERSION"), &config.tracing);
     let client = Arc::new(config.dao.dynamo_client());
+    let flags = Flags::start(&config.flags);
 
     let rand = Arc::new(SystemRandom::new());
     let credential = Arc::new(CredentialService::new(&config.credential)?);
@@ -40,6 +58,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
 
     let issuer = Arc::new(Issuer::new(&config.issuer, rand.clone())?);
     let validator = issuer.new_validator().expect("Failed to get issuer");
+    let health = health(client.clone(), config.dao.table.clone(), validator.clone());
     let user_dao = Arc::new(UserDaoDynamo::new(
         &config.dao,
         client.clone(),
@@ -73,18 +92,36 @@ async fn main() -> Result<(), Box<dyn Error>> {
         client_dao.seed().await?;
     }
 
-    rocket::custom(figment)
+    let doc = OpenApi::default().with(api::document).build(
+        "Auth",
+        "Issues and validates the tokens of users and clients",
+    );
+
+    let rocket = rocket::custom(figment)
         .manage(issuer)
         .manage(validator)
+        .manage(health)
         .manage(auth_service)
         .manage(config.api)
+        .manage(flags)
         .manage(client_dao as Arc<dyn ClientDao>)
         .manage(renewal_dao as Arc<dyn RenewalTokenDao>)
         .manage(user_dao as Arc<dyn UserDao>)
+        .manage(Spec::new(&doc))
+        .attach(RequestTracer)
         .mount("/", api::routes())
-        .launch()
-        .await
-        .expect("Rocket exited with error");
+        .mount("/", openapi::routes());
+
+    // On being asked to stop, rocket stops taking requests and is given until the
+    // deadline to finish those in flight
+    let server = rocket.shutdown();
+    let result = GracefulShutdown::new(config.shutdown.deadline())
+        .on(Phase::StopIntake, async move { server.shutdown() })
+        .run_until_signal(rocket.launch())
+        .await;
 
+    if let Some(result) = result {
+        result.expect("Rocket exited with error");
+    }
     Ok(())
 }
@@ -160,6 +160,30 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_service_scopes() -> Result<(), Box<dyn std::error::Error>> {
+        let val = Client {
+            client_id: "cli".to_string(),
+            client_name: "name".to_string(),
+            credential: None,
+            scopes: [Scope::Service("kinesis:write:stream".to_string())]
+                .iter()
+                .cloned()
+                .collect(),
+            grants: [GrantType::ClientCredentials].iter().cloned().collect(),
+            loopback: false,
+        };
+
+        let map: HashMap<String, AttributeValue> = val.clone().into();
+        let scopes = map.get("scopes").as_ref().unwrap().ss.as_ref().unwrap();
+        assert_eq!(scopes, &["kinesis:write:stream".to_string()]);
+
+        let back: Client = map.try_into()?;
+        assert_eq!(back.scopes, val.scopes);
+
+        Ok(())
+    }
+
     #[test]
     fn test_empty() -> Result<(), Box<dyn std::error::Error>> {
         let val = Client {
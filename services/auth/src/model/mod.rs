@@ -3,6 +3,7 @@ use std::str::FromStr;
 use derive_more::Display;
 use rocket::http::RawStr;
 use rocket::request::FromFormValue;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 use strum_macros::{AsRefStr, EnumString};
 
@@ -25,7 +26,19 @@ pub enum ModelError {
 }
 impl std::error::Error for ModelError {}
 
-#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, AsRefStr, EnumString, Serialize, Deserialize)]
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    Hash,
+    PartialEq,
+    Eq,
+    AsRefStr,
+    EnumString,
+    Serialize,
+    Deserialize,
+    JsonSchema,
+)]
 #[serde(rename_all = "snake_case")]
 #[strum(serialize_all = "snake_case")]
 pub enum GrantType {
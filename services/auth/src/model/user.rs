@@ -2,6 +2,7 @@ use std::collections::{HashMap, HashSet};
 use std::convert::TryFrom;
 
 use rusoto_dynamodb::AttributeValue;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 use dynamo_util::IntoAttribute;
@@ -9,7 +10,7 @@ use jwt::tag;
 
 use crate::model::{ModelError, Scope};
 
-#[derive(Serialize, Deserialize, Clone)]
+#[derive(Serialize, Deserialize, Clone, JsonSchema)]
 pub struct User {
     pub user_id: String,
     pub full_name: String,
@@ -1,9 +1,8 @@
 use crate::model::JwtClaims;
-use crate::model::Scope;
 use crate::policy::PolicyError;
 
 fn default(claims: &JwtClaims) -> Result<(), PolicyError> {
-    if claims.scopes.contains(&Scope::Superuser) {
+    if claims.is_superuser() {
         return Ok(());
     }
 
@@ -1,9 +1,8 @@
 use crate::model::JwtClaims;
-use crate::model::Scope;
 use crate::policy::PolicyError;
 
 fn default(user_id: &str, claims: &JwtClaims) -> Result<(), PolicyError> {
-    if claims.scopes.contains(&Scope::Superuser) {
+    if claims.is_superuser() {
         return Ok(());
     }
 
@@ -25,7 +24,7 @@ pub fn get_username(user_id: &str, claims: &JwtClaims) -> Result<(), PolicyError
 }
 
 pub fn change_scopes(claims: &JwtClaims) -> Result<(), PolicyError> {
-    if claims.scopes.contains(&Scope::Superuser) {
+    if claims.is_superuser() {
         return Ok(());
     }
     Err(PolicyError::PermissionDenied)
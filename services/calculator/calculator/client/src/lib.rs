@@ -1,57 +1,657 @@
-use std::ops::{Add, Div, Mul, Sub};
+use std::cmp::Ordering;
+use std::convert::TryFrom;
+use std::ops::{Add, BitAnd, BitOr, BitXor, Div, Mul, Neg, Rem, Shl, Shr, Sub};
 
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::{Decimal, MathematicalOps};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use strum_macros::{AsRefStr, EnumString};
 
-#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
-#[serde(rename_all = "snake_case")]
-pub struct ComputeRequest {
-    pub operation: ComputeOperation,
-    pub left: ComputeValue,
-    pub right: ComputeValue,
+/// A computation for the calculator to perform, either an operation on two values or
+/// a function of one
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, JsonSchema)]
+#[serde(untagged)]
+pub enum ComputeRequest {
+    Binary {
+        operation: ComputeOperation,
+        left: ComputeValue,
+        right: ComputeValue,
+    },
+    Unary {
+        function: ComputeFunction,
+        value: ComputeValue,
+    },
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 pub enum ComputeOperation {
     Add,
     Sub,
     Mul,
     Div,
+    /// Raises the left value to the power of the right
+    Pow,
+    /// The remainder of dividing the left value by the right, with the sign of the left
+    Mod,
+    /// The bitwise and of two integers
+    And,
+    /// The bitwise or of two integers
+    Or,
+    /// The bitwise exclusive or of two integers
+    Xor,
+    /// Shifts the bits of the left integer left by the right
+    Shl,
+    /// Shifts the bits of the left integer right by the right, preserving its sign
+    Shr,
+    Lt,
+    Le,
+    Eq,
+    Ne,
+    Gt,
+    Ge,
+}
+
+impl ComputeOperation {
+    /// Applies the operation to `left` and `right`
+    pub fn apply(
+        &self,
+        left: ComputeValue,
+        right: ComputeValue,
+    ) -> Result<ComputeValue, ComputeError> {
+        match self {
+            Self::Add => left + right,
+            Self::Sub => left - right,
+            Self::Mul => left * right,
+            Self::Div => left / right,
+            Self::Pow => left.pow(right),
+            Self::Mod => left % right,
+            Self::And => left & right,
+            Self::Or => left | right,
+            Self::Xor => left ^ right,
+            Self::Shl => left << right,
+            Self::Shr => left >> right,
+            Self::Lt => left.compare(right, |x| x == Ordering::Less),
+            Self::Le => left.compare(right, |x| x != Ordering::Greater),
+            Self::Eq => left.equals(right).map(ComputeValue::Bool),
+            Self::Ne => left.equals(right).map(|x| ComputeValue::Bool(!x)),
+            Self::Gt => left.compare(right, |x| x == Ordering::Greater),
+            Self::Ge => left.compare(right, |x| x != Ordering::Less),
+        }
+    }
+}
+
+/// A function of one value, named in expressions as it is serialized
+#[derive(
+    Debug, Clone, Copy, PartialEq, AsRefStr, EnumString, Serialize, Deserialize, JsonSchema,
+)]
+#[serde(rename_all = "snake_case")]
+#[strum(serialize_all = "snake_case")]
+pub enum ComputeFunction {
+    /// Negates the value, as written with a unary minus
+    Neg,
+    Abs,
+    Sqrt,
+    /// The natural logarithm
+    Ln,
+    Exp,
+    /// The sine of an angle in radians
+    Sin,
+    /// The cosine of an angle in radians
+    Cos,
+    /// The tangent of an angle in radians
+    Tan,
+}
+
+/// An error computing a value
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+#[serde(tag = "error")]
+pub enum ComputeError {
+    /// The result of an operation on integers does not fit in 64 bits, or that of an
+    /// operation on decimals in 96 bits
+    Overflow,
+    /// A value was divided by, or taken the remainder of, zero
+    DivisionByZero,
+    /// The result of an operation on floats is infinite or not a number
+    NonFinite,
+    /// A bitwise operation was applied to a value other than an integer or boolean
+    NotInteger,
+    /// An arithmetic operation or comparison was applied to a boolean
+    NotNumber,
+    /// The condition of an `if` was not a boolean
+    NotBoolean,
+}
+
+impl std::fmt::Display for ComputeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Overflow => write!(f, "Overflow"),
+            Self::DivisionByZero => write!(f, "Division by zero"),
+            Self::NonFinite => write!(f, "Result is not a finite number"),
+            Self::NotInteger => write!(f, "Bitwise operations require integer operands"),
+            Self::NotNumber => write!(f, "Arithmetic requires numeric operands"),
+            Self::NotBoolean => write!(f, "Conditions must be booleans"),
+        }
+    }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+impl std::error::Error for ComputeError {}
+
+/// A value, where operations on integers give integers and those involving a float
+/// give floats
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, JsonSchema)]
 #[serde(rename_all = "snake_case")]
 #[serde(tag = "type", content = "value")]
 pub enum ComputeValue {
-    Int(i32),
-    Float(f32),
+    Int(i64),
+    Float(f64),
+    /// An exact decimal, serialized as a string so that no precision is lost
+    Decimal(Decimal),
+    /// The result of a comparison
+    Bool(bool),
+}
+
+/// Compares the integer `i` to the float `f` exactly, which is None if `f` is NaN
+fn compare_int_float(i: i64, f: f64) -> Option<Ordering> {
+    // Floats from 2^63 are beyond any integer, while those below have integer parts
+    // that fit in an i64
+    const LIMIT: f64 = 9_223_372_036_854_775_808.;
+    if f.is_nan() {
+        return None;
+    } else if f >= LIMIT {
+        return Some(Ordering::Less);
+    } else if f < -LIMIT {
+        return Some(Ordering::Greater);
+    }
+
+    let truncated = f.trunc();
+    let fraction = f - truncated;
+    Some(
+        i.cmp(&(truncated as i64))
+            .then(0f64.partial_cmp(&fraction)?),
+    )
+}
+
+/// Returns `value` unless `overflow`, as returned by the overflowing integer methods
+fn checked((value, overflow): (i64, bool)) -> Result<ComputeValue, ComputeError> {
+    if overflow {
+        return Err(ComputeError::Overflow);
+    }
+    Ok(ComputeValue::Int(value))
+}
+
+/// Returns the float `f` unless it is infinite or NaN, which JSON can't represent
+fn finite(f: f64) -> Result<ComputeValue, ComputeError> {
+    if !f.is_finite() {
+        return Err(ComputeError::NonFinite);
+    }
+    Ok(ComputeValue::Float(f))
 }
 
 impl ComputeValue {
-    fn as_float(self) -> f32 {
+    fn is_zero(self) -> bool {
+        match self {
+            Self::Int(i) => i == 0,
+            Self::Float(f) => f == 0.,
+            Self::Decimal(d) => d.is_zero(),
+            Self::Bool(_) => false,
+        }
+    }
+
+    /// Returns `self` as a float, unless it is a boolean
+    fn as_float(self) -> Result<f64, ComputeError> {
+        match self {
+            Self::Int(i) => Ok(i as f64),
+            Self::Float(f) => Ok(f),
+            Self::Decimal(d) => Ok(d.to_f64().unwrap_or(f64::NAN)),
+            Self::Bool(_) => Err(ComputeError::NotNumber),
+        }
+    }
+
+    /// Returns `self` as a decimal, unless it is a float or boolean
+    fn as_decimal(self) -> Option<Decimal> {
         match self {
-            Self::Int(i) => i as f32,
-            Self::Float(f) => f,
+            Self::Int(i) => Some(i.into()),
+            Self::Decimal(d) => Some(d),
+            Self::Float(_) | Self::Bool(_) => None,
+        }
+    }
+
+    /// Orders `self` and `rhs`, which is None if either is NaN
+    ///
+    /// Integers and decimals are compared exactly, as are integers and floats, while
+    /// decimals and floats are compared as floats. Booleans aren't ordered
+    fn order(self, rhs: Self) -> Result<Option<Ordering>, ComputeError> {
+        Ok(match (self, rhs) {
+            (Self::Int(l), Self::Float(r)) => compare_int_float(l, r),
+            (Self::Float(l), Self::Int(r)) => compare_int_float(r, l).map(Ordering::reverse),
+            (l, r) => match (l.as_decimal(), r.as_decimal()) {
+                (Some(l), Some(r)) => Some(l.cmp(&r)),
+                _ => l.as_float()?.partial_cmp(&r.as_float()?),
+            },
+        })
+    }
+
+    /// Returns the boolean `predicate` of the ordering of `self` and `rhs`, which is
+    /// false if either is NaN
+    fn compare<F>(self, rhs: Self, predicate: F) -> Result<Self, ComputeError>
+    where
+        F: Fn(Ordering) -> bool,
+    {
+        match self.order(rhs)? {
+            Some(ordering) => Ok(Self::Bool(predicate(ordering))),
+            None => Ok(Self::Bool(false)),
+        }
+    }
+
+    /// Returns true if `self` equals `rhs`, where booleans can only be compared with
+    /// booleans, and NaN equals nothing
+    fn equals(self, rhs: Self) -> Result<bool, ComputeError> {
+        match (self, rhs) {
+            (Self::Bool(l), Self::Bool(r)) => Ok(l == r),
+            (l, r) => Ok(l.order(r)? == Some(Ordering::Equal)),
+        }
+    }
+
+    /// Rounds a decimal to `dp` decimal places, leaving other values unchanged
+    pub fn round_dp(self, dp: u32) -> Self {
+        match self {
+            Self::Decimal(d) => Self::Decimal(d.round_dp(dp).normalize()),
+            v => v,
+        }
+    }
+
+    /// Applies `function` to `self`, the result of which is a float other than when
+    /// negating a value or taking its absolute value
+    pub fn apply(self, function: ComputeFunction) -> Result<Self, ComputeError> {
+        match (function, self) {
+            (ComputeFunction::Neg, v) => -v,
+            (ComputeFunction::Abs, Self::Int(i)) => checked(i.overflowing_abs()),
+            (ComputeFunction::Abs, Self::Float(f)) => Ok(Self::Float(f.abs())),
+            (ComputeFunction::Abs, Self::Decimal(d)) => Ok(Self::Decimal(d.abs())),
+            (ComputeFunction::Abs, Self::Bool(_)) => Err(ComputeError::NotNumber),
+            (ComputeFunction::Sqrt, v) => finite(v.as_float()?.sqrt()),
+            (ComputeFunction::Ln, v) => finite(v.as_float()?.ln()),
+            (ComputeFunction::Exp, v) => finite(v.as_float()?.exp()),
+            (ComputeFunction::Sin, v) => finite(v.as_float()?.sin()),
+            (ComputeFunction::Cos, v) => finite(v.as_float()?.cos()),
+            (ComputeFunction::Tan, v) => finite(v.as_float()?.tan()),
+        }
+    }
+
+    /// Raises `self` to the power `rhs`, which is an integer if both are, unless the
+    /// exponent is negative, or a decimal if `self` is a decimal and `rhs` an integer
+    ///
+    /// Raising zero to a negative power is a division by zero
+    pub fn pow(self, rhs: Self) -> Result<Self, ComputeError> {
+        match (self, rhs) {
+            (Self::Int(l), Self::Int(r)) if r >= 0 => {
+                // Only the powers of -1, 0 and 1 fit for exponents beyond a u32, and
+                // those only depend on whether the exponent is odd
+                let r = u32::try_from(r).unwrap_or(u32::MAX - 1 + (r % 2) as u32);
+                checked(l.overflowing_pow(r))
+            }
+            (l, r) if l.is_zero() && r.as_float()? < 0. => Err(ComputeError::DivisionByZero),
+            (Self::Decimal(l), Self::Int(r)) => l
+                .checked_powi(r)
+                .map(Self::Decimal)
+                .ok_or(ComputeError::Overflow),
+            (l, r) => finite(l.as_float()?.powf(r.as_float()?)),
         }
     }
 }
 
 macro_rules! op {
+    ( $t: ty, $f: ident, $overflowing: ident, $checked: ident $(, $zero: ident)? ) => {
+        impl $t for ComputeValue {
+            type Output = Result<ComputeValue, ComputeError>;
+
+            fn $f(self, rhs: Self) -> Self::Output {
+                $(
+                    if rhs.is_zero() {
+                        return Err(ComputeError::$zero);
+                    }
+                )?
+                match (self, rhs) {
+                    (Self::Int(l), Self::Int(r)) => checked(l.$overflowing(r)),
+                    (l, r) => match (l.as_decimal(), r.as_decimal()) {
+                        (Some(l), Some(r)) => {
+                            l.$checked(r).map(Self::Decimal).ok_or(ComputeError::Overflow)
+                        }
+                        _ => finite(l.as_float()?.$f(r.as_float()?)),
+                    },
+                }
+            }
+        }
+    };
+}
+
+op!(Add, add, overflowing_add, checked_add);
+op!(Sub, sub, overflowing_sub, checked_sub);
+op!(Mul, mul, overflowing_mul, checked_mul);
+op!(Div, div, overflowing_div, checked_div, DivisionByZero);
+op!(Rem, rem, overflowing_rem, checked_rem, DivisionByZero);
+
+macro_rules! bitwise {
     ( $t: ty, $f: ident ) => {
         impl $t for ComputeValue {
-            type Output = ComputeValue;
+            type Output = Result<ComputeValue, ComputeError>;
+
+            /// Applies the operation to the bits of integers, or logically to booleans
+            fn $f(self, rhs: Self) -> Self::Output {
+                match (self, rhs) {
+                    (Self::Int(l), Self::Int(r)) => Ok(Self::Int(l.$f(r))),
+                    (Self::Bool(l), Self::Bool(r)) => Ok(Self::Bool(l.$f(r))),
+                    _ => Err(ComputeError::NotInteger),
+                }
+            }
+        }
+    };
+}
+
+bitwise!(BitAnd, bitand);
+bitwise!(BitOr, bitor);
+bitwise!(BitXor, bitxor);
+
+macro_rules! shift {
+    ( $t: ty, $f: ident, $checked: ident ) => {
+        impl $t for ComputeValue {
+            type Output = Result<ComputeValue, ComputeError>;
 
+            /// Shifts by between 0 and 63 bits, beyond which is an overflow
             fn $f(self, rhs: Self) -> Self::Output {
                 match (self, rhs) {
-                    (Self::Int(l), Self::Int(r)) => Self::Int(l.$f(r)),
-                    (l, r) => Self::Float(l.as_float().$f(r.as_float())),
+                    (Self::Int(l), Self::Int(r)) => u32::try_from(r)
+                        .ok()
+                        .and_then(|r| l.$checked(r))
+                        .map(Self::Int)
+                        .ok_or(ComputeError::Overflow),
+                    _ => Err(ComputeError::NotInteger),
                 }
             }
         }
     };
 }
 
-op!(Add, add);
-op!(Sub, sub);
-op!(Mul, mul);
-op!(Div, div);
+shift!(Shl, shl, checked_shl);
+shift!(Shr, shr, checked_shr);
+
+impl Neg for ComputeValue {
+    type Output = Result<ComputeValue, ComputeError>;
+
+    fn neg(self) -> Self::Output {
+        match self {
+            Self::Int(i) => checked(i.overflowing_neg()),
+            Self::Float(f) => Ok(Self::Float(-f)),
+            Self::Decimal(d) => Ok(Self::Decimal(-d)),
+            Self::Bool(_) => Err(ComputeError::NotNumber),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pow() -> Result<(), ComputeError> {
+        use ComputeValue::*;
+
+        assert_eq!(Int(2).pow(Int(10))?, Int(1024));
+        assert_eq!(Int(-3).pow(Int(3))?, Int(-27));
+        assert_eq!(Int(2).pow(Int(-1))?, Float(0.5));
+        assert_eq!(Float(4.).pow(Float(0.5))?, Float(2.));
+        assert_eq!(Int(9).pow(Float(0.5))?, Float(3.));
+
+        assert_eq!(Int(2).pow(Int(62))?, Int(1 << 62));
+        assert_eq!(Int(2).pow(Int(63)), Err(ComputeError::Overflow));
+        assert_eq!(Int(-1).pow(Int(1 << 40))?, Int(1));
+        assert_eq!(Int(-1).pow(Int((1 << 40) + 1))?, Int(-1));
+        assert_eq!(Int(2).pow(Int(1 << 40)), Err(ComputeError::Overflow));
+        Ok(())
+    }
+
+    #[test]
+    fn test_overflow() -> Result<(), ComputeError> {
+        use ComputeValue::*;
+
+        let max = Int(i64::MAX);
+        let min = Int(i64::MIN);
+
+        assert_eq!((max - Int(1))? + Int(1), Ok(max));
+        assert_eq!(max + Int(1), Err(ComputeError::Overflow));
+        assert_eq!(min - Int(1), Err(ComputeError::Overflow));
+        assert_eq!(max * Int(2), Err(ComputeError::Overflow));
+        assert_eq!(min / Int(-1), Err(ComputeError::Overflow));
+        assert_eq!(-min, Err(ComputeError::Overflow));
+        assert_eq!(min.apply(ComputeFunction::Abs), Err(ComputeError::Overflow));
+
+        // Values beyond 32 bits are exact
+        assert_eq!((Int(1 << 40) * Int(3))?, Int(3 << 40));
+        assert_eq!((Int(10_000_000_001) + Float(0.5))?, Float(10_000_000_001.5));
+        Ok(())
+    }
+
+    #[test]
+    fn test_promotion() -> Result<(), ComputeError> {
+        use ComputeValue::*;
+
+        let decimal = |s: &str| Decimal(s.parse().unwrap());
+
+        // Integers stay integers, floats are contagious, and decimals stay exact
+        // unless combined with a float
+        assert_eq!((Int(7) / Int(2))?, Int(3));
+        assert_eq!((Int(7) / Float(2.))?, Float(3.5));
+        assert_eq!((Float(7.) / Int(2))?, Float(3.5));
+        assert_eq!((Int(7) / decimal("2"))?, decimal("3.5"));
+        assert_eq!((decimal("7") / Float(2.))?, Float(3.5));
+        assert_eq!(Int(2).pow(Int(-2))?, Float(0.25));
+        assert_eq!(decimal("2").pow(Int(-2))?, decimal("0.25"));
+        assert_eq!(Int(2).pow(decimal("2"))?, Float(4.));
+        Ok(())
+    }
+
+    #[test]
+    fn test_decimal() -> Result<(), ComputeError> {
+        use ComputeValue::*;
+
+        let tenth = Decimal(rust_decimal::Decimal::new(1, 1));
+        let decimal = |s: &str| Decimal(s.parse().unwrap());
+
+        assert_eq!(((tenth + tenth)? + tenth)?, decimal("0.3"));
+        assert_eq!((tenth + Int(2))?, decimal("2.1"));
+        assert_eq!((Int(1) / decimal("4"))?, decimal("0.25"));
+        assert_eq!((decimal("7.5") % Int(2))?, decimal("1.5"));
+        assert_eq!(decimal("1.5").pow(Int(2))?, decimal("2.25"));
+        assert_eq!((-decimal("1.5"))?, decimal("-1.5"));
+        assert_eq!(decimal("-1.5").apply(ComputeFunction::Abs)?, decimal("1.5"));
+
+        // Floats are inexact, so combining one with a decimal gives a float
+        assert_eq!((tenth * Float(2.))?, Float(0.2));
+        assert_eq!(decimal("4").apply(ComputeFunction::Sqrt)?, Float(2.));
+
+        assert_eq!((Int(2) / decimal("3"))?.round_dp(4), decimal("0.6667"));
+        assert_eq!(decimal("1.50").round_dp(4), decimal("1.5"));
+        assert_eq!(Float(0.25).round_dp(1), Float(0.25));
+
+        let json = r#"{"type":"decimal","value":"0.3"}"#;
+        assert_eq!(serde_json::to_string(&decimal("0.3")).unwrap(), json);
+        assert_eq!(
+            serde_json::from_str::<ComputeValue>(json).unwrap(),
+            decimal("0.3")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_bitwise() -> Result<(), ComputeError> {
+        use ComputeValue::*;
+
+        assert_eq!((Int(0b1100) & Int(0b1010))?, Int(0b1000));
+        assert_eq!((Int(0b1100) | Int(0b1010))?, Int(0b1110));
+        assert_eq!((Int(0b1100) ^ Int(0b1010))?, Int(0b0110));
+        assert_eq!((Int(-1) & Int(0xFF))?, Int(0xFF));
+        assert_eq!((Int(1) << Int(62))?, Int(1 << 62));
+        assert_eq!((Int(-16) >> Int(2))?, Int(-4));
+        assert_eq!(ComputeOperation::Xor.apply(Int(5), Int(1))?, Int(4));
+
+        assert_eq!(Int(1) << Int(64), Err(ComputeError::Overflow));
+        assert_eq!(Int(1) >> Int(-1), Err(ComputeError::Overflow));
+        assert_eq!(Int(1) & Float(1.), Err(ComputeError::NotInteger));
+        assert_eq!(Decimal(1.into()) | Int(1), Err(ComputeError::NotInteger));
+        assert_eq!(Float(1.) << Int(1), Err(ComputeError::NotInteger));
+        Ok(())
+    }
+
+    #[test]
+    fn test_compare() -> Result<(), ComputeError> {
+        use ComputeOperation::*;
+        use ComputeValue::*;
+
+        let decimal = |s: &str| Decimal(s.parse().unwrap());
+        let compare = |op: ComputeOperation, l, r| op.apply(l, r);
+
+        assert_eq!(compare(Lt, Int(1), Int(2))?, Bool(true));
+        assert_eq!(compare(Ge, Int(1), Int(2))?, Bool(false));
+        assert_eq!(compare(Le, Int(2), Float(2.))?, Bool(true));
+        assert_eq!(compare(Eq, Int(2), Float(2.))?, Bool(true));
+        assert_eq!(compare(Gt, Float(2.5), Int(2))?, Bool(true));
+        assert_eq!(compare(Lt, Float(-2.5), Int(-2))?, Bool(true));
+        assert_eq!(compare(Eq, decimal("0.5"), Float(0.5))?, Bool(true));
+        assert_eq!(compare(Lt, Int(1), decimal("1.1"))?, Bool(true));
+        assert_eq!(compare(Ne, Bool(true), Bool(false))?, Bool(true));
+
+        // Integers beyond the precision of floats are compared exactly
+        let big = Int(i64::MAX);
+        assert_eq!(compare(Eq, big, Float(i64::MAX as f64))?, Bool(false));
+        assert_eq!(compare(Lt, big, Float(i64::MAX as f64))?, Bool(true));
+        assert_eq!(
+            compare(Gt, Int(i64::MIN + 1), Float(i64::MIN as f64))?,
+            Bool(true)
+        );
+        let odd = (1 << 53) + 1;
+        assert_eq!(compare(Eq, Int(odd), Float(odd as f64))?, Bool(false));
+
+        // NaN is unordered and equals nothing
+        let nan = Float(f64::NAN);
+        assert_eq!(compare(Lt, Int(1), nan)?, Bool(false));
+        assert_eq!(compare(Ge, nan, Int(1))?, Bool(false));
+        assert_eq!(compare(Eq, nan, nan)?, Bool(false));
+        assert_eq!(compare(Ne, nan, nan)?, Bool(true));
+
+        assert_eq!(
+            compare(Lt, Bool(false), Bool(true)),
+            Err(ComputeError::NotNumber)
+        );
+        assert_eq!(
+            compare(Eq, Bool(true), Int(1)),
+            Err(ComputeError::NotNumber)
+        );
+        assert_eq!(Bool(true) + Int(1), Err(ComputeError::NotNumber));
+        assert_eq!(Bool(true).pow(Int(1)), Err(ComputeError::NotNumber));
+        assert_eq!(-Bool(true), Err(ComputeError::NotNumber));
+        assert_eq!(
+            Bool(true).apply(ComputeFunction::Sqrt),
+            Err(ComputeError::NotNumber)
+        );
+        assert_eq!((Bool(true) & Bool(false))?, Bool(false));
+        assert_eq!((Bool(true) ^ Bool(true))?, Bool(false));
+        Ok(())
+    }
+
+    #[test]
+    fn test_apply() -> Result<(), ComputeError> {
+        use ComputeValue::*;
+
+        assert_eq!(Int(3).apply(ComputeFunction::Neg)?, Int(-3));
+        assert_eq!(Float(-2.5).apply(ComputeFunction::Abs)?, Float(2.5));
+        assert_eq!(Int(-2).apply(ComputeFunction::Abs)?, Int(2));
+        assert_eq!(Int(9).apply(ComputeFunction::Sqrt)?, Float(3.));
+        assert_eq!(Int(0).apply(ComputeFunction::Cos)?, Float(1.));
+        assert_eq!(Float(1.).apply(ComputeFunction::Ln)?, Float(0.));
+        assert_eq!("sqrt".parse(), Ok(ComputeFunction::Sqrt));
+        Ok(())
+    }
+
+    #[test]
+    fn test_request() {
+        let binary = r#"{"operation":"add","left":{"type":"int","value":1},"right":{"type":"float","value":2.5}}"#;
+        let unary = r#"{"function":"sqrt","value":{"type":"int","value":4}}"#;
+
+        let request: ComputeRequest = serde_json::from_str(binary).unwrap();
+        assert_eq!(
+            request,
+            ComputeRequest::Binary {
+                operation: ComputeOperation::Add,
+                left: ComputeValue::Int(1),
+                right: ComputeValue::Float(2.5),
+            }
+        );
+        assert_eq!(serde_json::to_string(&request).unwrap(), binary);
+
+        let request: ComputeRequest = serde_json::from_str(unary).unwrap();
+        assert_eq!(
+            request,
+            ComputeRequest::Unary {
+                function: ComputeFunction::Sqrt,
+                value: ComputeValue::Int(4),
+            }
+        );
+        assert_eq!(serde_json::to_string(&request).unwrap(), unary);
+
+        let error = serde_json::to_string(&ComputeError::Overflow).unwrap();
+        assert_eq!(error, r#"{"error":"overflow"}"#);
+    }
+
+    #[test]
+    fn test_rem() -> Result<(), ComputeError> {
+        use ComputeValue::*;
+
+        assert_eq!((Int(7) % Int(3))?, Int(1));
+        assert_eq!((Int(-7) % Int(3))?, Int(-1));
+        assert_eq!((Float(7.5) % Int(2))?, Float(1.5));
+        Ok(())
+    }
+
+    #[test]
+    fn test_division_by_zero() {
+        use ComputeValue::*;
+
+        let zero = Decimal(rust_decimal::Decimal::ZERO);
+        let max = Decimal(rust_decimal::Decimal::MAX);
+
+        assert_eq!(Int(1) / Int(0), Err(ComputeError::DivisionByZero));
+        assert_eq!(Int(1) % Int(0), Err(ComputeError::DivisionByZero));
+        assert_eq!(Float(1.) / Float(-0.), Err(ComputeError::DivisionByZero));
+        assert_eq!(Float(1.) % Int(0), Err(ComputeError::DivisionByZero));
+        assert_eq!(Int(1) / zero, Err(ComputeError::DivisionByZero));
+        assert_eq!(zero % zero, Err(ComputeError::DivisionByZero));
+        assert_eq!(Int(0).pow(Int(-1)), Err(ComputeError::DivisionByZero));
+        assert_eq!(zero.pow(Int(-2)), Err(ComputeError::DivisionByZero));
+        assert_eq!(Int(0).pow(Int(0)), Ok(Int(1)));
+
+        assert_eq!(max + Int(1), Err(ComputeError::Overflow));
+        assert_eq!(max * Int(2), Err(ComputeError::Overflow));
+        assert_eq!(max.pow(Int(2)), Err(ComputeError::Overflow));
+
+        assert_eq!(Float(f64::MAX) * Int(2), Err(ComputeError::NonFinite));
+        assert_eq!(Float(1e300).pow(Int(2)), Err(ComputeError::NonFinite));
+        assert_eq!(
+            Int(-1).apply(ComputeFunction::Sqrt),
+            Err(ComputeError::NonFinite)
+        );
+        assert_eq!(
+            Int(0).apply(ComputeFunction::Ln),
+            Err(ComputeError::NonFinite)
+        );
+        assert_eq!(
+            Int(1000).apply(ComputeFunction::Exp),
+            Err(ComputeError::NonFinite)
+        );
+
+        let json = serde_json::to_string(&ComputeError::DivisionByZero).unwrap();
+        assert_eq!(json, r#"{"error":"division_by_zero"}"#);
+    }
+}
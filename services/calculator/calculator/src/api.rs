@@ -1,20 +1,20 @@
 use rocket::http::Status;
-use rocket::Route;
-use rocket_contrib::json::{Json, JsonValue};
+use rocket::{Route, State};
+use rocket_contrib::json::Json;
+use serde_json::json;
 
-use calculator_client::{ComputeOperation, ComputeRequest, ComputeValue};
+use calculator_client::{ComputeError, ComputeRequest, ComputeValue};
+use openapi::OpenApi;
 use rocket_util::Authenticated;
 use telemetry::Measure;
 
+use crate::config::Config;
+use crate::error::ApiError;
+
 lazy_static! {
     static ref COMPUTE_MEASURE: Measure = Measure::new("controller", "compute");
 }
 
-#[get("/status")]
-fn status() -> JsonValue {
-    json!({ "status": "ok" })
-}
-
 #[get("/metrics")]
 fn metrics() -> Result<String, Status> {
     telemetry::encode().map_err(|_| Status::InternalServerError)
@@ -24,21 +24,71 @@ fn metrics() -> Result<String, Status> {
 pub async fn compute(
     _authenticated: Authenticated,
     request: Json<ComputeRequest>,
-) -> Result<Json<ComputeValue>, ()> {
+    config: State<'_, Config>,
+) -> Result<Json<ComputeValue>, ApiError> {
     COMPUTE_MEASURE
         .stats(async move {
-            let val = match request.operation {
-                ComputeOperation::Add => request.left + request.right,
-                ComputeOperation::Sub => request.left - request.right,
-                ComputeOperation::Mul => request.left * request.right,
-                ComputeOperation::Div => request.left / request.right,
-            };
-
-            Ok(Json(val))
+            let val = match request.into_inner() {
+                ComputeRequest::Binary {
+                    operation,
+                    left,
+                    right,
+                } => operation.apply(left, right),
+                ComputeRequest::Unary { function, value } => value.apply(function),
+            }?;
+
+            Ok(Json(val.round_dp(config.precision)))
         })
         .await
 }
 
 pub fn routes() -> Vec<Route> {
-    routes![status, metrics, compute]
+    let mut routes = routes![metrics, compute];
+    routes.append(&mut health::routes());
+    routes
+}
+
+/// Adds the operations of the routes to an OpenAPI document
+pub fn document(api: &mut OpenApi) {
+    let request = api.schema::<ComputeRequest>();
+    let value = api.schema::<ComputeValue>();
+    let error = api.schema::<ComputeError>();
+
+    api.operation(
+        "post",
+        "/api/v1/compute",
+        json!({
+            "summary": "Performs an operation on two values, or applies a function to one",
+            "operationId": "compute",
+            "security": [{ "bearer": [] }],
+            "requestBody": { "required": true, "content": OpenApi::json(request) },
+            "responses": {
+                "200": {
+                    "description": "The result, with decimals rounded to the configured precision",
+                    "content": OpenApi::json(value),
+                },
+                "401": { "description": "The JWT is missing or invalid" },
+                "422": {
+                    "description": "The value can't be computed, such as due to overflow",
+                    "content": OpenApi::json(error),
+                },
+            },
+        }),
+    );
+    openapi::metrics(api);
+    health::document(api);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_document() {
+        let doc = OpenApi::default().with(document).build("Calculator", "");
+        assert_eq!(
+            openapi::undocumented_routes(&doc, &routes()),
+            Vec::<String>::new()
+        );
+    }
 }
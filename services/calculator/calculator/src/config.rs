@@ -1,9 +1,37 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
 use jwt::ValidatorConfig;
+use settings::{Error, Validate};
+use telemetry::trace::TracingConfig;
 
-#[derive(Debug, Deserialize, Clone, Default)]
+/// The most decimal places a decimal can have
+const MAX_PRECISION: u32 = 28;
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
 #[serde(default)]
 pub struct Config {
     pub validator: ValidatorConfig,
+    /// The number of decimal places decimal results are rounded to
+    pub precision: u32,
+    pub tracing: TracingConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            validator: Default::default(),
+            precision: 20,
+            tracing: Default::default(),
+        }
+    }
+}
+
+impl Validate for Config {
+    fn validate(&self) -> Result<(), Error> {
+        if self.precision > MAX_PRECISION {
+            let message = format!("must be at most {}", MAX_PRECISION);
+            return Err(Error::invalid("precision", message));
+        }
+        Ok(())
+    }
 }
@@ -0,0 +1,33 @@
+use rocket::http::Status;
+use rocket::{response, Request};
+use rocket_contrib::json::Json;
+
+use calculator_client::ComputeError;
+use telemetry::IsErr;
+
+#[derive(Debug)]
+pub enum ApiError {
+    Compute(ComputeError),
+}
+
+impl From<ComputeError> for ApiError {
+    fn from(e: ComputeError) -> Self {
+        ApiError::Compute(e)
+    }
+}
+
+impl IsErr for ApiError {
+    fn is_err(&self) -> bool {
+        false
+    }
+}
+
+impl<'r> response::Responder<'r, 'static> for ApiError {
+    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
+        match self {
+            ApiError::Compute(e) => {
+                response::status::Custom(Status::UnprocessableEntity, Json(e)).respond_to(req)
+            }
+        }
+    }
+}
@@ -2,26 +2,45 @@
 extern crate lazy_static;
 #[macro_use]
 extern crate rocket;
-#[macro_use]
-extern crate rocket_contrib;
 
+use health::{Health, Probe};
 use jwt::Validator;
+use openapi::{OpenApi, Spec};
+use rocket_util::RequestTracer;
 
 mod api;
 mod config;
+mod error;
 
 #[rocket::main]
 async fn main() {
-    env_logger::init();
-
-    let figment = rocket_util::figment();
-    let config: config::Config = figment.extract().unwrap();
+    let (config, figment) = settings::load_with::<config::Config>(rocket::Config::figment());
+    let _exporter =
+        telemetry::trace::init("calculator", env!("CARGO_PKG_VERSION"), &config.tracing);
 
     let validator = Validator::new(&config.validator).expect("Failed to load JWT validator");
+    let health = {
+        let validator = validator.clone();
+        Health::new().register("jwks", &[Probe::Ready, Probe::Startup], move || {
+            let result = validator.check();
+            async move { result }
+        })
+    };
+
+    let doc = OpenApi::default().with(api::document).build(
+        "Calculator",
+        "Performs single operations on values for the gateway",
+    );
 
     let result = rocket::custom(figment)
         .manage(validator)
+        .manage(health)
+        .manage(config)
+        .manage(Spec::new(&doc))
+        .attach(RequestTracer)
         .mount("/", api::routes())
+        .mount("/", openapi::routes())
+        .mount("/api/v1", openapi::routes())
         .launch()
         .await;
 
@@ -1,28 +1,40 @@
 use std::sync::Arc;
 
+use futures::stream::{self, StreamExt};
 use futures::{future::BoxFuture, join, FutureExt};
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 
-use calculator_client::{ComputeRequest, ComputeValue};
+use calculator_client::{ComputeError, ComputeRequest, ComputeValue};
+use errors::Error;
+use openapi::OpenApi;
 use rocket::http::Status;
+use rocket::response::status;
 use rocket::{Route, State};
-use rocket_contrib::json::{Json, JsonValue};
-use rocket_util::Authenticated;
+use rocket_contrib::json::Json;
+use rocket_util::{Authenticated, RequestSpan};
 use telemetry::Measure;
+use tracing::Instrument;
 
 use crate::client::CalculatorClient;
-use crate::error::ApiError;
+use crate::config::BatchConfig;
+use crate::error;
 use crate::expression::{parse, Expr};
+use crate::health::{Health, HealthChecker};
+use crate::ratelimit::RateLimited;
+use crate::switch::Enabled;
 
 lazy_static! {
     static ref COMPUTE_MEASURE: Measure = Measure::new("controller", "compute");
+    static ref BATCH_MEASURE: Measure = Measure::new("controller", "evaluate_batch");
 }
 
-fn eval(
+pub fn eval(
     authorization: String,
     client: Arc<CalculatorClient>,
     e: &Expr,
-) -> BoxFuture<Result<ComputeValue, ApiError>> {
+) -> BoxFuture<Result<ComputeValue, Error>> {
     // As this method is self-recursive it returns a boxed future
     match e {
         Expr::Constant(v) => futures::future::ready(Ok(*v)).boxed(),
@@ -32,20 +44,64 @@ fn eval(
                 eval(authorization.clone(), client.clone(), r)
             );
 
-            let request = ComputeRequest {
+            let request = ComputeRequest::Binary {
                 operation: op.clone(),
                 left: left?,
                 right: right?,
             };
 
-            tokio::spawn(async move { client.compute(&request, authorization).await }).await?
+            let compute = async move { client.compute(&request, authorization).await };
+            tokio::spawn(compute.in_current_span())
+                .await
+                .map_err(Error::internal)?
         }),
+        Expr::Function(function, v) => Box::pin(async move {
+            let request = ComputeRequest::Unary {
+                function: *function,
+                value: eval(authorization.clone(), client.clone(), v).await?,
+            };
+
+            let compute = async move { client.compute(&request, authorization).await };
+            tokio::spawn(compute.in_current_span())
+                .await
+                .map_err(Error::internal)?
+        }),
+        Expr::If(condition, then, otherwise) => Box::pin(async move {
+            // Only the branch chosen by the condition is evaluated
+            let condition = eval(authorization.clone(), client.clone(), condition).await?;
+            match condition {
+                ComputeValue::Bool(true) => eval(authorization, client, then).await,
+                ComputeValue::Bool(false) => eval(authorization, client, otherwise).await,
+                _ => Err(error::compute(ComputeError::NotBoolean)),
+            }
+        }),
+        Expr::Variable(name) => {
+            let message = format!("Unknown variable \"{}\"", name);
+            futures::future::ready(Err(Error::bad_request(message))).boxed()
+        }
     }
 }
 
-#[get("/status")]
-fn status() -> JsonValue {
-    json!({ "status": "ok" })
+/// Parses and evaluates the expression `expr`
+async fn evaluate(
+    authorization: String,
+    client: Arc<CalculatorClient>,
+    expr: &str,
+) -> Result<ComputeValue, Error> {
+    let expr = parse(expr)?;
+    eval(authorization, client, &expr).await
+}
+
+/// Probes the upstreams, responding with a 503 if a critical upstream is down
+#[get("/healthz")]
+async fn healthz(checker: State<'_, Arc<HealthChecker>>) -> status::Custom<Json<Health>> {
+    let health = checker.check().await;
+    let status = if health.ready {
+        Status::Ok
+    } else {
+        Status::ServiceUnavailable
+    };
+    status::Custom(status, Json(health))
 }
 
 #[get("/metrics")]
@@ -53,28 +109,187 @@ fn metrics() -> Result<String, Status> {
     telemetry::encode().map_err(|_| Status::InternalServerError)
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// An expression to evaluate, such as `(1 + 2) * 3`
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "snake_case")]
-struct Expression {
+pub struct Expression {
     expr: String,
 }
 
 #[post("/api/v1/compute", format = "json", data = "<request>")]
 async fn compute(
+    enabled: Result<Enabled, Error>,
+    rate_limited: Result<RateLimited, Error>,
     authenticated: Authenticated,
     request: Json<Expression>,
     client: State<'_, Arc<CalculatorClient>>,
-) -> Result<Json<ComputeValue>, ApiError> {
+    span: RequestSpan,
+) -> Result<Json<ComputeValue>, Error> {
+    enabled?;
+    rate_limited?;
     COMPUTE_MEASURE
         .stats(async move {
-            let expr = parse(&request.expr)?;
-            let val = eval(authenticated.header, client.inner().clone(), &expr).await?;
+            let val = evaluate(authenticated.header, client.inner().clone(), &request.expr).await?;
 
             Ok(Json(val))
         })
+        .instrument(span.0)
+        .await
+}
+
+/// The result of evaluating one expression of a batch or session
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum EvaluateResult {
+    Value(ComputeValue),
+    Error(String),
+}
+
+impl From<Result<ComputeValue, Error>> for EvaluateResult {
+    fn from(result: Result<ComputeValue, Error>) -> Self {
+        match result {
+            Ok(value) => EvaluateResult::Value(value),
+            Err(e) => EvaluateResult::Error(e.body(None).message),
+        }
+    }
+}
+
+/// Evaluates a batch of expressions, returning the result of each in the order given
+///
+/// A failure to evaluate one expression doesn't fail the others
+#[post("/api/v1/evaluate/batch", format = "json", data = "<request>")]
+async fn evaluate_batch(
+    enabled: Result<Enabled, Error>,
+    rate_limited: Result<RateLimited, Error>,
+    authenticated: Authenticated,
+    request: Json<Vec<Expression>>,
+    client: State<'_, Arc<CalculatorClient>>,
+    config: State<'_, BatchConfig>,
+    span: RequestSpan,
+) -> Result<Json<Vec<EvaluateResult>>, Error> {
+    enabled?;
+    rate_limited?;
+    BATCH_MEASURE
+        .stats(async move {
+            if request.len() > config.limit {
+                return Err(Error::bad_request(format!(
+                    "A batch may have at most {} expressions",
+                    config.limit
+                )));
+            }
+
+            let authorization = &authenticated.header;
+            let client = client.inner();
+            let results = stream::iter(request.iter())
+                .map(|x| evaluate(authorization.clone(), client.clone(), &x.expr))
+                .buffered(config.concurrency.max(1))
+                .map(EvaluateResult::from)
+                .collect()
+                .await;
+
+            Ok(Json(results))
+        })
+        .instrument(span.0)
         .await
 }
 
 pub fn routes() -> Vec<Route> {
-    routes![status, metrics, compute]
+    let mut routes = routes![healthz, metrics, compute, evaluate_batch];
+    routes.append(&mut ::health::routes());
+    routes
+}
+
+/// Adds the operations of the routes to an OpenAPI document
+pub fn document(api: &mut OpenApi) {
+    let expression = api.schema::<Expression>();
+    let value = api.schema::<ComputeValue>();
+    let result = api.schema::<EvaluateResult>();
+    let unknown_variable = api.error("The expression uses an unknown variable");
+    let invalid = api.error(
+        "The expression failed to parse, with the position and expected tokens in the \
+            details, or a value can't be computed",
+    );
+    let too_many = api.error("The batch has too many expressions");
+    let mut rate_limited = api.error("The client is over the rate limit of the route");
+    rate_limited["headers"] = json!({
+        "Retry-After": {
+            "description": "The number of seconds to wait before retrying",
+            "schema": { "type": "integer" },
+        },
+    });
+    let disabled = api.error("The route is turned off for the client");
+
+    api.operation(
+        "post",
+        "/api/v1/compute",
+        json!({
+            "summary": "Evaluates an expression",
+            "operationId": "compute",
+            "security": [{ "bearer": [] }],
+            "requestBody": { "required": true, "content": OpenApi::json(expression.clone()) },
+            "responses": {
+                "200": { "description": "The value of the expression", "content": OpenApi::json(value) },
+                "400": unknown_variable,
+                "401": { "description": "The JWT is missing or invalid" },
+                "422": invalid,
+                "429": rate_limited,
+                "503": disabled,
+            },
+        }),
+    );
+    api.operation(
+        "post",
+        "/api/v1/evaluate/batch",
+        json!({
+            "summary": "Evaluates a batch of expressions",
+            "description": "A failure to evaluate one expression doesn't fail the others",
+            "operationId": "evaluateBatch",
+            "security": [{ "bearer": [] }],
+            "requestBody": {
+                "required": true,
+                "content": OpenApi::json(json!({ "type": "array", "items": expression })),
+            },
+            "responses": {
+                "200": {
+                    "description": "The result of each expression, in the order given",
+                    "content": OpenApi::json(json!({ "type": "array", "items": result })),
+                },
+                "400": too_many,
+                "401": { "description": "The JWT is missing or invalid" },
+                "429": rate_limited,
+                "503": disabled,
+            },
+        }),
+    );
+    let health = OpenApi::json(api.schema::<Health>());
+    api.operation(
+        "get",
+        "/healthz",
+        json!({
+            "summary": "Reports the health of the upstreams",
+            "description": "Requests the readiness probe of each calculator replica and the health \
+                endpoint of the upstreams of routes configured with one",
+            "operationId": "healthz",
+            "responses": {
+                "200": { "description": "Every critical upstream is up", "content": health },
+                "503": { "description": "A critical upstream is down", "content": health },
+            },
+        }),
+    );
+    openapi::metrics(api);
+    ::health::document(api);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_document() {
+        let doc = OpenApi::default().with(document).build("Gateway", "");
+        assert_eq!(
+            openapi::undocumented_routes(&doc, &routes()),
+            Vec::<String>::new()
+        );
+    }
 }
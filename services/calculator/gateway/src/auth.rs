@@ -0,0 +1,85 @@
+use std::collections::HashSet;
+
+use rocket::http::Status;
+use rocket::outcome::Outcome;
+use rocket::Request;
+
+use jwt::{JwtClaims, Scope, Validator};
+use rocket_util::Authenticated;
+
+/// The claims of the bearer token of a request, kept in its local cache so that the
+/// token is validated at most once
+struct Claims(Option<JwtClaims<String>>);
+
+/// Returns the claims of the bearer token of `request`, if it has a valid one
+pub fn claims<'r>(request: &'r Request<'_>) -> Option<&'r JwtClaims<String>> {
+    let claims = request.local_cache(|| {
+        let validator = request.managed_state::<Validator>();
+        let auth = request.headers().get_one("Authorization");
+        let claims = match (validator, auth) {
+            (Some(validator), Some(auth))
+                if auth.len() > 7 && auth[..7].eq_ignore_ascii_case("bearer ") =>
+            {
+                validator.validate(auth[7..].trim()).ok()
+            }
+            _ => None,
+        };
+        Claims(claims)
+    });
+    claims.0.as_ref()
+}
+
+/// Returns who made `request`, the client of its bearer token if it has a valid one,
+/// otherwise the IP address it was made from
+pub fn principal(request: &Request<'_>) -> String {
+    if let Some(claims) = claims(request) {
+        return format!("cid:{}", claims.cid);
+    }
+    match request.client_ip() {
+        Some(ip) => format!("ip:{}", ip),
+        None => "unknown".to_string(),
+    }
+}
+
+/// Returns true if `granted` includes every scope of `required`, or the superuser scope
+fn allows(granted: &HashSet<Scope>, required: &[Scope]) -> bool {
+    jwt::is_superuser(granted) || required.iter().all(|x| granted.contains(x))
+}
+
+/// Checks that `request` has a valid bearer token granting every scope of `required`,
+/// returning the status to respond with if it doesn't
+pub async fn authorize(request: &Request<'_>, required: &[Scope]) -> Result<(), Status> {
+    if required.is_empty() {
+        return Ok(());
+    }
+
+    match request.guard::<Authenticated>().await {
+        Outcome::Success(authenticated) if allows(&authenticated.claims.scopes, required) => Ok(()),
+        Outcome::Success(_) => Err(Status::Forbidden),
+        Outcome::Failure((status, _)) => Err(status),
+        Outcome::Forward(()) => Err(Status::Unauthorized),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_allows() {
+        let scopes = |x: &[&str]| -> Vec<Scope> { x.iter().map(|x| x.parse().unwrap()).collect() };
+        let granted: HashSet<_> = scopes(&["crawler:read", "offline_access"])
+            .into_iter()
+            .collect();
+
+        assert!(allows(&granted, &[]));
+        assert!(allows(&granted, &scopes(&["crawler:read"])));
+        assert!(!allows(
+            &granted,
+            &scopes(&["crawler:read", "crawler:write"])
+        ));
+
+        let superuser = scopes(&["superuser"]).into_iter().collect();
+        assert!(allows(&superuser, &scopes(&["crawler:write"])));
+    }
+}
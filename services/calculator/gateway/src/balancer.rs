@@ -0,0 +1,262 @@
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use reqwest::Url;
+use tokio::net::lookup_host;
+use tracing::warn;
+
+/// A calculator replica, along with its health
+pub struct Replica {
+    /// The base URL of the replica
+    url: Url,
+    /// The Host header of requests to the replica, if the host of its URL is an address
+    /// resolved from the host of the upstream
+    host: Option<String>,
+    /// The number of requests to the replica that have failed in a row
+    failures: AtomicU32,
+    /// When the replica was ejected, None if it is in use
+    ejected: Mutex<Option<Instant>>,
+}
+
+impl Replica {
+    fn new(url: Url, host: Option<String>) -> Replica {
+        Replica {
+            url,
+            host,
+            failures: AtomicU32::new(0),
+            ejected: Mutex::new(None),
+        }
+    }
+
+    pub fn url(&self) -> &Url {
+        &self.url
+    }
+
+    /// Returns the Host header to send to the replica, None to take it from its URL
+    pub fn host(&self) -> Option<&str> {
+        self.host.as_deref()
+    }
+}
+
+/// Spreads requests across calculator replicas in round robin order, leaving out
+/// replicas that fail `max_failures` requests in a row for the `ejection` period
+///
+/// Once the period ends an ejected replica is used again, but is ejected again by its
+/// next failure unless a request to it succeeds first
+pub struct Balancer {
+    replicas: RwLock<Vec<Arc<Replica>>>,
+    next: AtomicUsize,
+    max_failures: u32,
+    ejection: Duration,
+}
+
+impl Balancer {
+    pub fn new(urls: Vec<Url>, max_failures: u32, ejection: Duration) -> Balancer {
+        Balancer {
+            replicas: RwLock::new(
+                urls.into_iter()
+                    .map(|x| Arc::new(Replica::new(x, None)))
+                    .collect(),
+            ),
+            next: AtomicUsize::new(0),
+            max_failures: max_failures.max(1),
+            ejection,
+        }
+    }
+
+    /// Replaces the replicas with `updated`, keeping the health of those already known
+    pub fn update(&self, updated: Vec<Replica>) {
+        let mut replicas = self.replicas.write().unwrap();
+        let updated = updated
+            .into_iter()
+            .map(
+                |replica| match replicas.iter().find(|x| x.url == replica.url) {
+                    Some(known) => known.clone(),
+                    None => Arc::new(replica),
+                },
+            )
+            .collect();
+        *replicas = updated;
+    }
+
+    /// Returns the replicas, whether or not they are ejected
+    pub fn replicas(&self) -> Vec<Arc<Replica>> {
+        self.replicas.read().unwrap().clone()
+    }
+
+    fn is_ejected(&self, replica: &Replica, now: Instant) -> bool {
+        match *replica.ejected.lock().unwrap() {
+            Some(ejected) => now < ejected + self.ejection,
+            None => false,
+        }
+    }
+
+    /// Returns the next replica that isn't ejected, or the next replica if all are,
+    /// None if there are no replicas
+    pub fn pick(&self) -> Option<Arc<Replica>> {
+        let replicas = self.replicas.read().unwrap();
+        if replicas.is_empty() {
+            return None;
+        }
+
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        let now = Instant::now();
+        let healthy = (0..replicas.len())
+            .map(|x| &replicas[(start + x) % replicas.len()])
+            .find(|x| !self.is_ejected(x, now));
+
+        Some(healthy.unwrap_or(&replicas[start % replicas.len()]).clone())
+    }
+
+    /// Records whether a request to `replica` succeeded, ejecting it if it has failed
+    /// too many requests in a row
+    pub fn report(&self, replica: &Replica, success: bool) {
+        if success {
+            replica.failures.store(0, Ordering::Relaxed);
+            *replica.ejected.lock().unwrap() = None;
+            return;
+        }
+
+        let failures = replica.failures.fetch_add(1, Ordering::Relaxed) + 1;
+        if failures >= self.max_failures {
+            let now = Instant::now();
+            let mut ejected = replica.ejected.lock().unwrap();
+            if !matches!(*ejected, Some(ejected) if now < ejected + self.ejection) {
+                warn!(replica = %replica.url, failures, "Ejecting calculator replica");
+                *ejected = Some(now);
+            }
+        }
+    }
+}
+
+/// Returns the replicas behind `upstreams`, one for each address the host of an http
+/// upstream resolves to, which are sent the upstream's Host header
+///
+/// Other upstreams are kept as they are, as TLS needs the host name of the upstream to
+/// verify its certificate. Returns None if a host fails to resolve
+async fn resolve(upstreams: &[Url]) -> Option<Vec<Replica>> {
+    let mut replicas = Vec::new();
+    for upstream in upstreams {
+        let (host, port) = match (upstream.host_str(), upstream.port_or_known_default()) {
+            (Some(host), Some(port)) if upstream.scheme() == "http" => (host, port),
+            _ => {
+                replicas.push(Replica::new(upstream.clone(), None));
+                continue;
+            }
+        };
+
+        let header = match upstream.port() {
+            Some(port) => format!("{}:{}", host, port),
+            None => host.to_string(),
+        };
+
+        match lookup_host((host, port)).await {
+            Ok(addresses) => {
+                for address in addresses {
+                    let mut url = upstream.clone();
+                    if url.set_ip_host(address.ip()).is_ok() {
+                        replicas.push(Replica::new(url, Some(header.clone())));
+                    }
+                }
+            }
+            Err(e) => {
+                warn!(%host, error = %e, "Failed to resolve calculator host");
+                return None;
+            }
+        }
+    }
+    Some(replicas)
+}
+
+/// Periodically resolves the hosts of `upstreams`, updating the replicas of `balancer`
+/// to the addresses found, so that replicas behind a DNS name are balanced between
+pub async fn discover(balancer: Arc<Balancer>, upstreams: Vec<Url>, interval: Duration) {
+    loop {
+        // The replicas are kept if a host fails to resolve, such as if DNS is unavailable
+        if let Some(replicas) = resolve(&upstreams).await {
+            balancer.update(replicas);
+        }
+        tokio::time::delay_for(interval).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn urls(urls: &[&str]) -> Vec<Url> {
+        urls.iter().map(|x| Url::parse(x).unwrap()).collect()
+    }
+
+    fn pick(balancer: &Balancer) -> String {
+        balancer
+            .pick()
+            .unwrap()
+            .url()
+            .host_str()
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn test_round_robin() {
+        let balancer = Balancer::new(urls(&["http://a", "http://b"]), 2, Duration::from_secs(60));
+        let picked: Vec<_> = (0..4).map(|_| pick(&balancer)).collect();
+        assert_eq!(picked, vec!["a", "b", "a", "b"]);
+
+        // Failures short of the maximum leave a replica in use
+        let a = balancer.pick().unwrap();
+        balancer.report(&a, false);
+        assert_eq!(pick(&balancer), "b");
+        assert_eq!(pick(&balancer), "a");
+
+        let empty = Balancer::new(vec![], 2, Duration::from_secs(60));
+        assert!(empty.pick().is_none());
+    }
+
+    #[test]
+    fn test_ejection() {
+        let balancer = Balancer::new(urls(&["http://a", "http://b"]), 2, Duration::from_secs(60));
+        let a = balancer.pick().unwrap();
+        balancer.report(&a, false);
+        balancer.report(&a, false);
+        assert!((0..4).all(|_| pick(&balancer) == "b"));
+
+        // All replicas are used if all are ejected
+        let b = balancer.pick().unwrap();
+        balancer.report(&b, false);
+        balancer.report(&b, false);
+        let picked: Vec<_> = (0..2).map(|_| pick(&balancer)).collect();
+        assert_eq!(picked, vec!["a", "b"]);
+
+        // A success returns a replica to use
+        balancer.report(&a, true);
+        assert!((0..4).all(|_| pick(&balancer) == "a"));
+
+        // Health is kept across updates
+        let updated = urls(&["http://b", "http://c"]);
+        balancer.update(updated.into_iter().map(|x| Replica::new(x, None)).collect());
+        assert!((0..4).all(|_| pick(&balancer) == "c"));
+
+        // Replicas are used again once the ejection period ends
+        let balancer = Balancer::new(urls(&["http://a", "http://b"]), 1, Duration::from_secs(0));
+        let a = balancer.pick().unwrap();
+        balancer.report(&a, false);
+        let picked: Vec<_> = (0..2).map(|_| pick(&balancer)).collect();
+        assert_eq!(picked, vec!["b", "a"]);
+    }
+
+    #[tokio::test]
+    async fn test_resolve() {
+        let upstreams = urls(&["http://127.0.0.1:8080/api", "https://calculator"]);
+        let replicas = resolve(&upstreams).await.unwrap();
+
+        // Only http upstreams are resolved, keeping their Host header
+        assert_eq!(replicas.len(), 2);
+        assert_eq!(replicas[0].url().as_str(), "http://127.0.0.1:8080/api");
+        assert_eq!(replicas[0].host(), Some("127.0.0.1:8080"));
+        assert_eq!(replicas[1].url().as_str(), "https://calculator/");
+        assert_eq!(replicas[1].host(), None);
+    }
+}
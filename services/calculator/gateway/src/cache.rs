@@ -0,0 +1,421 @@
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use deadpool_redis::{cmd, Pool};
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::config::CacheConfig;
+
+/// The prefix of the keys of responses cached in Redis
+const REDIS_PREFIX: &str = "gateway:cache:";
+
+/// Returns the number of seconds since the UNIX epoch
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |x| x.as_secs())
+}
+
+/// A response of an upstream stored in a cache
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CachedResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+    /// The number of seconds since the UNIX epoch the response was stored at
+    pub stored: u64,
+}
+
+impl CachedResponse {
+    pub fn new(status: u16, headers: Vec<(String, String)>, body: Vec<u8>) -> CachedResponse {
+        CachedResponse {
+            status,
+            headers,
+            body,
+            stored: now(),
+        }
+    }
+
+    /// Returns the number of seconds since the response was stored
+    pub fn age(&self) -> u64 {
+        now().saturating_sub(self.stored)
+    }
+
+    /// Returns the approximate number of bytes the response takes up
+    fn size(&self) -> usize {
+        let headers: usize = self.headers.iter().map(|(k, v)| k.len() + v.len()).sum();
+        headers + self.body.len()
+    }
+}
+
+/// A store of responses, which are forgotten once their time to live passes
+///
+/// Failures of a store are logged and treated as misses, so that the cache can't fail
+/// a request that would otherwise succeed
+#[rocket::async_trait]
+pub trait ResponseCache: Send + Sync {
+    async fn get(&self, key: &str) -> Option<CachedResponse>;
+
+    async fn put(&self, key: &str, response: &CachedResponse, ttl: Duration);
+}
+
+struct Entry {
+    response: CachedResponse,
+    expires: Instant,
+    /// The tick the entry was last used at, its key in `Entries::lru`
+    used: u64,
+}
+
+#[derive(Default)]
+struct Entries {
+    entries: HashMap<String, Entry>,
+    /// The keys of the entries ordered by when they were last used
+    lru: BTreeMap<u64, String>,
+    /// The total size of the responses of the entries
+    size: usize,
+    tick: u64,
+}
+
+impl Entries {
+    fn remove(&mut self, key: &str) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.lru.remove(&entry.used);
+            self.size -= entry.response.size();
+        }
+    }
+}
+
+/// Caches responses in memory, evicting the least recently used once the total size
+/// of the responses would exceed `capacity` bytes
+pub struct MemoryCache {
+    capacity: usize,
+    entries: Mutex<Entries>,
+}
+
+impl MemoryCache {
+    pub fn new(capacity: usize) -> MemoryCache {
+        MemoryCache {
+            capacity,
+            entries: Mutex::new(Entries::default()),
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl ResponseCache for MemoryCache {
+    async fn get(&self, key: &str) -> Option<CachedResponse> {
+        let mut entries = self.entries.lock().unwrap();
+        let entries = &mut *entries;
+        let entry = entries.entries.get_mut(key)?;
+        if entry.expires <= Instant::now() {
+            entries.remove(key);
+            return None;
+        }
+
+        entries.tick += 1;
+        entries.lru.remove(&entry.used);
+        entries.lru.insert(entries.tick, key.to_string());
+        entry.used = entries.tick;
+        Some(entry.response.clone())
+    }
+
+    async fn put(&self, key: &str, response: &CachedResponse, ttl: Duration) {
+        let size = response.size();
+        if size > self.capacity {
+            return;
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+        entries.remove(key);
+        while entries.size + size > self.capacity {
+            let oldest = match entries.lru.values().next() {
+                Some(oldest) => oldest.clone(),
+                None => break,
+            };
+            entries.remove(&oldest);
+        }
+
+        entries.tick += 1;
+        let used = entries.tick;
+        entries.lru.insert(used, key.to_string());
+        entries.size += size;
+        entries.entries.insert(
+            key.to_string(),
+            Entry {
+                response: response.clone(),
+                expires: Instant::now() + ttl,
+                used,
+            },
+        );
+    }
+}
+
+/// Caches responses in Redis, so that they are shared by every gateway replica and
+/// evicted according to the memory policy of the server
+pub struct RedisCache {
+    pool: Pool,
+}
+
+impl RedisCache {
+    pub fn new(url: &str) -> RedisCache {
+        let config = deadpool_redis::Config {
+            url: Some(url.to_string()),
+            pool: None,
+        };
+        RedisCache {
+            pool: config.create_pool().expect("Invalid Redis URL"),
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl ResponseCache for RedisCache {
+    async fn get(&self, key: &str) -> Option<CachedResponse> {
+        let result: Result<Option<Vec<u8>>, String> = async {
+            let mut conn = self.pool.get().await.map_err(|e| e.to_string())?;
+            cmd("GET")
+                .arg(format!("{}{}", REDIS_PREFIX, key))
+                .query_async(&mut conn)
+                .await
+                .map_err(|e| e.to_string())
+        }
+        .await;
+
+        match result {
+            Ok(value) => serde_json::from_slice(&value?).ok(),
+            Err(e) => {
+                warn!(error = %e, "Failed to read cached response");
+                None
+            }
+        }
+    }
+
+    async fn put(&self, key: &str, response: &CachedResponse, ttl: Duration) {
+        let value = serde_json::to_vec(response).expect("Failed to serialize response");
+        let result: Result<(), String> = async {
+            let mut conn = self.pool.get().await.map_err(|e| e.to_string())?;
+            cmd("SET")
+                .arg(format!("{}{}", REDIS_PREFIX, key))
+                .arg(value)
+                .arg("PX")
+                .arg(ttl.as_millis() as u64)
+                .execute_async(&mut conn)
+                .await
+                .map_err(|e| e.to_string())
+        }
+        .await;
+
+        if let Err(e) = result {
+            warn!(error = %e, "Failed to cache response");
+        }
+    }
+}
+
+/// The cache shared by the routes that cache responses
+#[derive(Clone)]
+pub struct Cache {
+    pub store: Arc<dyn ResponseCache>,
+    /// The maximum size in bytes of the body of a response cached
+    pub max_size: usize,
+}
+
+impl Cache {
+    pub fn new(config: &CacheConfig) -> Cache {
+        let store: Arc<dyn ResponseCache> = match &config.redis {
+            Some(url) => Arc::new(RedisCache::new(url)),
+            None => Arc::new(MemoryCache::new(config.capacity)),
+        };
+        Cache {
+            store,
+            max_size: config.max_size,
+        }
+    }
+}
+
+/// The directives of `Cache-Control` headers that affect caching
+#[derive(Debug, Default, PartialEq)]
+pub struct CacheControl {
+    pub no_store: bool,
+    pub no_cache: bool,
+    pub private: bool,
+    pub public: bool,
+    pub max_age: Option<u64>,
+    pub s_maxage: Option<u64>,
+}
+
+impl CacheControl {
+    /// Parses the values of the `Cache-Control` headers of a message, ignoring
+    /// directives that aren't understood
+    pub fn parse<'a>(values: impl IntoIterator<Item = &'a str>) -> CacheControl {
+        let mut control = CacheControl::default();
+        for directive in values.into_iter().flat_map(|x| x.split(',')) {
+            let mut parts = directive.splitn(2, '=');
+            let name = parts.next().unwrap_or_default().trim().to_ascii_lowercase();
+            let seconds = parts
+                .next()
+                .and_then(|x| x.trim().trim_matches('"').parse().ok());
+
+            match name.as_str() {
+                "no-store" => control.no_store = true,
+                "no-cache" => control.no_cache = true,
+                "private" => control.private = true,
+                "public" => control.public = true,
+                "max-age" => control.max_age = seconds,
+                "s-maxage" => control.s_maxage = seconds,
+                _ => {}
+            }
+        }
+        control
+    }
+
+    /// Returns true if a request with these directives must be sent to the upstream
+    /// rather than answered from the cache
+    pub fn bypasses(&self) -> bool {
+        self.no_store || self.no_cache || self.max_age == Some(0)
+    }
+
+    /// Returns how long a response with these directives may be cached for, `default`
+    /// if it doesn't say, None if it mustn't be cached
+    ///
+    /// As the cache is shared, responses to requests with credentials are only cached
+    /// if the upstream explicitly allows it
+    pub fn ttl(&self, default: Duration, authorized: bool) -> Option<Duration> {
+        if self.no_store || self.no_cache || self.private {
+            return None;
+        }
+        if authorized && !self.public && self.s_maxage.is_none() {
+            return None;
+        }
+
+        let ttl = self
+            .s_maxage
+            .or(self.max_age)
+            .map_or(default, Duration::from_secs);
+        Some(ttl).filter(|x| *x > Duration::from_secs(0))
+    }
+}
+
+/// Returns true if a response that varies by the headers in `vary` can be cached under
+/// a key including the values of `headers`
+pub fn varies_within<'a>(vary: impl IntoIterator<Item = &'a str>, headers: &[String]) -> bool {
+    vary.into_iter()
+        .flat_map(|x| x.split(','))
+        .map(str::trim)
+        .filter(|x| !x.is_empty())
+        .all(|x| x != "*" && headers.iter().any(|h| h.eq_ignore_ascii_case(x)))
+}
+
+/// Returns the key of a request for `method` on `path` with `query`, and `headers`,
+/// the values of the request headers the response varies by
+pub fn key(method: &str, path: &str, query: Option<&str>, headers: &[(&str, Vec<&str>)]) -> String {
+    let mut key = format!("{} {}", method, path);
+    if let Some(query) = query {
+        key.push('?');
+        key.push_str(query);
+    }
+    for (name, values) in headers {
+        key.push_str(&format!(
+            "\n{}: {}",
+            name.to_ascii_lowercase(),
+            values.join(", ")
+        ));
+    }
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(body: &[u8]) -> CachedResponse {
+        CachedResponse::new(200, vec![], body.to_vec())
+    }
+
+    #[test]
+    fn test_cache_control() {
+        let control = CacheControl::parse(vec!["public, max-age=60", "s-maxage=\"120\""]);
+        assert_eq!(
+            control,
+            CacheControl {
+                public: true,
+                max_age: Some(60),
+                s_maxage: Some(120),
+                ..Default::default()
+            }
+        );
+
+        let default = Duration::from_secs(10);
+        let ttl =
+            |header: &str, authorized| CacheControl::parse(vec![header]).ttl(default, authorized);
+        assert_eq!(ttl("", false), Some(default));
+        assert_eq!(ttl("max-age=60", false), Some(Duration::from_secs(60)));
+        assert_eq!(
+            ttl("max-age=60, s-maxage=5", false),
+            Some(Duration::from_secs(5))
+        );
+        assert_eq!(ttl("max-age=0", false), None);
+        assert_eq!(ttl("No-Store", false), None);
+        assert_eq!(ttl("no-cache", false), None);
+        assert_eq!(ttl("private, max-age=60", false), None);
+
+        // Responses to requests with credentials must be marked as shareable
+        assert_eq!(ttl("max-age=60", true), None);
+        assert_eq!(ttl("public", true), Some(default));
+
+        assert!(CacheControl::parse(vec!["max-age=0"]).bypasses());
+        assert!(!CacheControl::parse(vec!["max-age=30"]).bypasses());
+    }
+
+    #[test]
+    fn test_key() {
+        let headers = vec!["Accept".to_string()];
+        assert!(varies_within(vec![], &headers));
+        assert!(varies_within(vec!["accept"], &headers));
+        assert!(!varies_within(vec!["Accept, Cookie"], &headers));
+        assert!(!varies_within(vec!["*"], &headers));
+
+        assert_eq!(key("GET", "/a", None, &[]), "GET /a");
+        assert_eq!(
+            key(
+                "HEAD",
+                "/a",
+                Some("b=1"),
+                &[("Accept", vec!["text/html", "*/*"])]
+            ),
+            "HEAD /a?b=1\naccept: text/html, */*"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_memory_cache() {
+        let ttl = Duration::from_secs(60);
+        let cache = MemoryCache::new(10);
+        cache.put("a", &response(b"aaaa"), ttl).await;
+        cache.put("b", &response(b"bbbb"), ttl).await;
+        assert_eq!(cache.get("a").await.unwrap().body, b"aaaa");
+
+        // The least recently used response is evicted to make room
+        cache.put("c", &response(b"cccc"), ttl).await;
+        assert!(cache.get("b").await.is_none());
+        assert!(cache.get("a").await.is_some());
+        assert!(cache.get("c").await.is_some());
+
+        // Replacing a response frees the space of the old one
+        cache.put("c", &response(b"cccccc"), ttl).await;
+        assert!(cache.get("a").await.is_some());
+        assert_eq!(cache.get("c").await.unwrap().body, b"cccccc");
+
+        // Responses larger than the cache aren't stored
+        cache.put("d", &response(b"ddddddddddd"), ttl).await;
+        assert!(cache.get("d").await.is_none());
+        assert!(cache.get("a").await.is_some());
+
+        cache
+            .put("e", &response(b"e"), Duration::from_secs(0))
+            .await;
+        assert!(cache.get("e").await.is_none());
+        assert!(cache.entries.lock().unwrap().size <= 10);
+    }
+}
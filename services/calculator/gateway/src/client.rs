@@ -1,32 +1,69 @@
-use crate::error::ApiError;
-use calculator_client::{ComputeRequest, ComputeValue};
+use std::sync::Arc;
+
+use reqwest::StatusCode;
+use tracing::Instrument;
+
+use calculator_client::{ComputeError, ComputeRequest, ComputeValue};
+use errors::Error;
+
+use crate::balancer::Balancer;
+use crate::error;
 
 pub struct CalculatorClient {
-    post_url: String,
+    balancer: Arc<Balancer>,
     client: reqwest::Client,
 }
 
 impl CalculatorClient {
-    pub fn new(client: reqwest::Client, upstream: String) -> CalculatorClient {
-        CalculatorClient {
-            post_url: format!("{}/api/v1/compute", upstream),
-            client,
-        }
+    pub fn new(client: reqwest::Client, balancer: Arc<Balancer>) -> CalculatorClient {
+        CalculatorClient { balancer, client }
     }
 
     pub async fn compute(
         &self,
         request: &ComputeRequest,
         authorization: String,
-    ) -> Result<ComputeValue, ApiError> {
-        self.client
-            .post(&self.post_url)
+    ) -> Result<ComputeValue, Error> {
+        let replica = self
+            .balancer
+            .pick()
+            .ok_or_else(|| Error::status(503).with_cause("No calculator replicas"))?;
+        let url = format!(
+            "{}/api/v1/compute",
+            replica.url().as_str().trim_end_matches('/')
+        );
+
+        // The calculator continues the trace of the request
+        let span = telemetry::trace::client_span("POST", &url);
+        let mut builder = self.client.post(&url);
+        for (name, value) in telemetry::trace::headers(&span) {
+            builder = builder.header(name.as_str(), value);
+        }
+        if let Some(host) = replica.host() {
+            builder = builder.header("Host", host);
+        }
+        let response = builder
             .header("Authorization", authorization)
             .json(request)
             .send()
-            .await?
-            .json::<ComputeValue>()
-            .await
-            .map_err(ApiError::from)
+            .instrument(span.clone())
+            .await;
+        if let Ok(response) = &response {
+            span.record("http.status_code", &response.status().as_u16());
+        }
+
+        // Only failures to respond count against a replica, not errors in the request
+        let failed = match &response {
+            Ok(response) => response.status().is_server_error(),
+            Err(_) => true,
+        };
+        self.balancer.report(&replica, !failed);
+        let response = response?;
+
+        if response.status() == StatusCode::UNPROCESSABLE_ENTITY {
+            return Err(error::compute(response.json::<ComputeError>().await?));
+        }
+
+        response.json::<ComputeValue>().await.map_err(Error::from)
     }
 }
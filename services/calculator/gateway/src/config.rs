@@ -1,25 +1,276 @@
-use serde::Deserialize;
+use std::collections::HashMap;
+use std::net::SocketAddr;
 
-use jwt::ValidatorConfig;
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
 
-#[derive(Deserialize, Clone)]
+use flags::FlagsConfig;
+use jwt::{Scope, ValidatorConfig};
+use settings::{Error, Validate};
+use telemetry::trace::TracingConfig;
+
+#[derive(Deserialize, Serialize, Clone)]
 #[serde(default)]
 pub struct UpstreamConfig {
+    /// The base URLs of the calculator replicas, separated by whitespace
     pub calculator: String,
+    /// Whether to balance between every address the hosts of `calculator` resolve to
+    pub discover: bool,
+    /// The interval in seconds between resolving the hosts of `calculator`
+    pub refresh: u64,
+    /// The number of requests to a replica that must fail in a row to eject it
+    pub max_failures: u32,
+    /// The number of seconds an ejected replica is left out for
+    pub ejection: u64,
 }
 
 impl Default for UpstreamConfig {
     fn default() -> Self {
         UpstreamConfig {
             calculator: "http://calculator".to_string(),
+            discover: false,
+            refresh: 30,
+            max_failures: 3,
+            ejection: 10,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct BatchConfig {
+    /// The maximum number of expressions in a batch
+    pub limit: usize,
+    /// The maximum number of expressions of a batch evaluated at once
+    pub concurrency: usize,
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        BatchConfig {
+            limit: 1000,
+            concurrency: 8,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct SessionConfig {
+    /// The address WebSocket sessions are accepted on
+    pub address: SocketAddr,
+    /// The maximum size in bytes of a message of a session, larger messages ending it
+    pub max_message_bytes: usize,
+    /// The maximum number of sessions open at once, after which new sessions are refused
+    pub max_sessions: usize,
+    /// The maximum number of variables of a session, after which assigning a new one
+    /// fails
+    pub max_variables: usize,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        SessionConfig {
+            address: ([0, 0, 0, 0], 8001).into(),
+            max_message_bytes: 64 * 1024,
+            max_sessions: 1000,
+            max_variables: 100,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct CacheConfig {
+    /// The maximum total size in bytes of the responses cached in memory
+    pub capacity: usize,
+    /// The maximum size in bytes of the body of a response cached
+    pub max_size: usize,
+    /// The URL of a Redis server to cache responses in rather than memory
+    pub redis: Option<String>,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig {
+            capacity: 64 * 1024 * 1024,
+            max_size: 1024 * 1024,
+            redis: None,
+        }
+    }
+}
+
+/// The caching of the responses of a route
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct RouteCacheConfig {
+    /// The number of seconds a response is cached for if the upstream doesn't say
+    pub ttl: u64,
+    /// The request headers responses vary by, whose values are part of the cache key
+    pub headers: Vec<String>,
+}
+
+impl Default for RouteCacheConfig {
+    fn default() -> Self {
+        RouteCacheConfig {
+            ttl: 60,
+            headers: vec!["Accept".to_string(), "Accept-Encoding".to_string()],
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct LoggingConfig {
+    /// Whether to log the headers of requests
+    pub headers: bool,
+    /// Whether to log the bodies of JSON requests, if small enough
+    pub body: bool,
+    /// The headers whose values are redacted, those whose names contain any of these
+    pub redact_headers: Vec<String>,
+    /// The body fields and query parameters whose values are redacted, those whose names
+    /// contain any of these
+    pub redact_fields: Vec<String>,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        let strings = |x: &[&str]| x.iter().map(ToString::to_string).collect();
+        LoggingConfig {
+            headers: true,
+            body: false,
+            redact_headers: strings(&["authorization", "cookie", "api-key", "token"]),
+            redact_fields: strings(&["password", "secret", "token", "api_key"]),
+        }
+    }
+}
+
+/// The retrying and hedging of the requests of a route that can be sent again
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct RetryConfig {
+    /// The maximum number of attempts of a request, including the first
+    pub attempts: u32,
+    /// The number of milliseconds to wait before retrying, doubled for each retry after
+    pub backoff: u64,
+    /// The number of milliseconds after which a second attempt is made if the first
+    /// hasn't responded, None to not hedge requests
+    pub hedge: Option<u64>,
+    /// The number of retries and hedged attempts allowed for each request made
+    pub budget: f64,
+    /// The number of retries and hedged attempts allowed per second beyond the budget
+    pub reserve: u64,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        RetryConfig {
+            attempts: 3,
+            backoff: 25,
+            hedge: None,
+            budget: 0.2,
+            reserve: 3,
         }
     }
 }
 
-#[derive(Deserialize, Clone, Default)]
+/// A route forwarding the requests under a path prefix to an upstream
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct RouteConfig {
+    /// The path prefix of the requests forwarded, such as `/auth`
+    pub prefix: String,
+    /// The base URL the prefix is replaced with when forwarding a request
+    pub upstream: String,
+    /// The number of seconds to wait for the upstream to respond
+    pub timeout: u64,
+    /// The caching of the responses to GET and HEAD requests, None to not cache them
+    pub cache: Option<RouteCacheConfig>,
+    /// The maximum number of requests per second of each client, None to not limit them
+    pub rate_limit: Option<u64>,
+    /// The scopes the bearer token of a request must grant for it to be forwarded, which
+    /// needn't have a token if there are none
+    pub scopes: Vec<Scope>,
+    /// The retrying of requests that fail, None to not retry them
+    pub retry: Option<RetryConfig>,
+    /// The path of the status endpoint of the upstream, resolved against `upstream`,
+    /// probed by the gateway's health check, None to not probe it
+    pub health: Option<String>,
+    /// Whether the gateway isn't ready while the status endpoint of the upstream fails
+    pub critical: bool,
+    /// The feature flag turning the route off for the clients it is off for, None if it
+    /// can't be turned off
+    pub flag: Option<String>,
+}
+
+impl Default for RouteConfig {
+    fn default() -> Self {
+        RouteConfig {
+            prefix: "/".to_string(),
+            upstream: String::new(),
+            timeout: 30,
+            cache: None,
+            rate_limit: None,
+            scopes: vec![],
+            retry: None,
+            health: None,
+            critical: true,
+            flag: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct HealthConfig {
+    /// The number of milliseconds to wait for an upstream's status endpoint to respond
+    pub timeout: u64,
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        HealthConfig { timeout: 2000 }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Default)]
 #[serde(default)]
 pub struct Config {
     pub port: Option<u16>,
     pub validator: ValidatorConfig,
     pub upstream: UpstreamConfig,
+    pub batch: BatchConfig,
+    pub session: SessionConfig,
+    /// The routes forwarded to other services, matched by their longest prefix after
+    /// the gateway's own routes
+    pub routes: Vec<RouteConfig>,
+    /// The maximum number of requests per second of each client to the gateway's own
+    /// routes, by the name of the route such as `compute`
+    pub rate_limits: HashMap<String, u64>,
+    /// The feature flags turning routes off, see `switch`
+    pub flags: FlagsConfig,
+    pub cache: CacheConfig,
+    pub logging: LoggingConfig,
+    pub health: HealthConfig,
+    pub tracing: TracingConfig,
+}
+
+impl Validate for Config {
+    fn validate(&self) -> Result<(), Error> {
+        for url in self.upstream.calculator.split_whitespace() {
+            url.parse::<Url>()
+                .map_err(|e| Error::invalid("upstream.calculator", format!("{}: {}", url, e)))?;
+        }
+
+        for route in &self.routes {
+            if !route.prefix.starts_with('/') {
+                let message = format!("{} doesn't start with /", route.prefix);
+                return Err(Error::invalid("routes.prefix", message));
+            }
+            route.upstream.parse::<Url>().map_err(|e| {
+                Error::invalid("routes.upstream", format!("{}: {}", route.upstream, e))
+            })?;
+        }
+        Ok(())
+    }
 }
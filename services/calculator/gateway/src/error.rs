@@ -1,63 +1,18 @@
-use std::borrow::Cow;
+use errors::Error;
 
-use log::error;
-use rocket::http::Status;
-use rocket::{response, Request};
-use rocket_contrib::json::Json;
-use serde::Serialize;
-
-use telemetry::IsErr;
+use calculator_client::ComputeError;
 
 use crate::expression::ParseError;
-use tokio::task::JoinError;
-
-#[derive(Debug)]
-pub enum ApiError {
-    InternalError(String),
-    InvalidExpression(String),
-}
-
-impl From<reqwest::Error> for ApiError {
-    fn from(e: reqwest::Error) -> Self {
-        ApiError::InternalError(format!("Reqwest Error: {}", e))
-    }
-}
 
-impl From<ParseError> for ApiError {
+/// An expression failed to parse, with where and why in the details
+impl From<ParseError> for Error {
     fn from(e: ParseError) -> Self {
-        ApiError::InvalidExpression(e.0)
+        Error::new(422, "syntax", e.to_string()).with_details(e)
     }
 }
 
-impl From<JoinError> for ApiError {
-    fn from(e: JoinError) -> Self {
-        ApiError::InternalError(format!("Join Error: {}", e))
-    }
-}
-
-impl IsErr for ApiError {
-    fn is_err(&self) -> bool {
-        matches!(self, ApiError::InternalError(_))
-    }
-}
-
-#[derive(Serialize)]
-struct ErrorResponse<'a> {
-    message: Cow<'a, str>,
-}
-
-impl<'r> response::Responder<'r, 'static> for ApiError {
-    fn respond_to(self, req: &'r Request<'_>) -> response::Result<'static> {
-        let (message, status) = match self {
-            ApiError::InternalError(e) => {
-                error!("Internal Error: {}", e);
-                (
-                    Cow::Borrowed("Internal Server Error"),
-                    Status::InternalServerError,
-                )
-            }
-            ApiError::InvalidExpression(e) => (Cow::Owned(e), Status::BadRequest),
-        };
-        response::status::Custom(status, Json(ErrorResponse { message })).respond_to(req)
-    }
+/// Returns the error of the calculator failing to compute a value, such as due to
+/// overflow, with the calculator's error in the details
+pub fn compute(e: ComputeError) -> Error {
+    Error::new(422, "compute", e.to_string()).with_details(e)
 }
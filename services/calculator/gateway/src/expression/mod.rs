@@ -1,5 +1,7 @@
-use calculator_client::{ComputeOperation, ComputeValue};
-pub use parser::{parse, ParseError};
+use std::collections::HashMap;
+
+use calculator_client::{ComputeFunction, ComputeOperation, ComputeValue};
+pub use parser::{parse, parse_statement, ParseError};
 
 mod parser;
 
@@ -7,4 +9,37 @@ mod parser;
 pub enum Expr {
     Constant(ComputeValue),
     Application(ComputeOperation, Box<Expr>, Box<Expr>),
+    Function(ComputeFunction, Box<Expr>),
+    /// A condition, and the expressions evaluated if it is true or false
+    If(Box<Expr>, Box<Expr>, Box<Expr>),
+    Variable(String),
+}
+
+impl Expr {
+    /// Returns the expression with the variables bound in `env` replaced by their values
+    pub fn bind(self, env: &HashMap<String, ComputeValue>) -> Expr {
+        match self {
+            Expr::Application(op, l, r) => {
+                Expr::Application(op, Box::new(l.bind(env)), Box::new(r.bind(env)))
+            }
+            Expr::Function(function, v) => Expr::Function(function, Box::new(v.bind(env))),
+            Expr::If(condition, then, otherwise) => Expr::If(
+                Box::new(condition.bind(env)),
+                Box::new(then.bind(env)),
+                Box::new(otherwise.bind(env)),
+            ),
+            Expr::Variable(name) => match env.get(&name) {
+                Some(v) => Expr::Constant(*v),
+                None => Expr::Variable(name),
+            },
+            e => e,
+        }
+    }
+}
+
+/// An expression, whose value may be assigned to a variable
+#[derive(Debug, Clone, PartialEq)]
+pub struct Statement {
+    pub name: Option<String>,
+    pub expr: Expr,
 }
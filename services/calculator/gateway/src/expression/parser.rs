@@ -1,119 +1,453 @@
+use std::cmp::Ordering;
+use std::collections::BTreeSet;
+
+use nom::error::ErrorKind;
 use nom::IResult;
 use nom::{
     branch::alt,
-    bytes::complete::tag,
-    character::complete::{char, digit1, space0},
-    combinator::{cut, map, map_res, opt, recognize},
-    multi::fold_many0,
-    number::complete::float,
-    sequence::{delimited, pair, preceded, separated_pair},
+    bytes::complete::{tag, tag_no_case},
+    character::complete::{alpha1, alphanumeric1, char, digit1, hex_digit1, one_of, space0},
+    combinator::{cut, map, map_opt, map_res, not, opt, recognize, value, verify},
+    multi::{fold_many0, many0},
+    number::complete::double,
+    sequence::{delimited, pair, preceded, terminated, tuple},
 };
 
-use calculator_client::{ComputeOperation, ComputeValue};
+use calculator_client::{ComputeFunction, ComputeOperation, ComputeValue};
+use schemars::JsonSchema;
+use serde::Serialize;
 
-use super::Expr;
+use super::{Expr, Statement};
 
-fn parse_constant(i: &str) -> IResult<&str, ComputeValue> {
-    let decimal = separated_pair(pair(opt(char('-')), digit1), char('.'), opt(digit1));
+/// The furthest position parsing failed at, and the tokens expected there
+#[derive(Debug, PartialEq)]
+struct SyntaxError<'a> {
+    /// The input from the position parsing failed at
+    input: &'a str,
+    expected: BTreeSet<String>,
+}
 
-    preceded(
+impl<'a> SyntaxError<'a> {
+    fn new(input: &'a str, expected: Option<String>) -> Self {
+        SyntaxError {
+            input: input.trim_start(),
+            expected: expected.into_iter().collect(),
+        }
+    }
+
+    /// Replaces the tokens expected with `expected` if parsing failed at the start of
+    /// `input`, rather than part way through
+    fn expecting(mut self, input: &str, expected: &str) -> Self {
+        if self.input.len() == input.trim_start().len() {
+            self.expected = std::iter::once(expected.to_string()).collect();
+        }
+        self
+    }
+}
+
+impl<'a> nom::error::ParseError<&'a str> for SyntaxError<'a> {
+    fn from_error_kind(input: &'a str, _: ErrorKind) -> Self {
+        SyntaxError::new(input, None)
+    }
+
+    fn append(_: &'a str, _: ErrorKind, other: Self) -> Self {
+        other
+    }
+
+    fn from_char(input: &'a str, c: char) -> Self {
+        SyntaxError::new(input, Some(format!("\"{}\"", c)))
+    }
+
+    /// Keeps the error of the alternative that got furthest, combining the tokens
+    /// expected if they failed at the same position
+    fn or(mut self, other: Self) -> Self {
+        match self.input.len().cmp(&other.input.len()) {
+            Ordering::Less => self,
+            Ordering::Greater => other,
+            Ordering::Equal => {
+                self.expected.extend(other.expected);
+                self
+            }
+        }
+    }
+}
+
+type ParseResult<'a, T> = IResult<&'a str, T, SyntaxError<'a>>;
+
+/// Describes what `parser` expects as `expected` if it fails at the start of its input
+fn expect<'a, O, F>(expected: &'static str, parser: F) -> impl Fn(&'a str) -> ParseResult<'a, O>
+where
+    F: Fn(&'a str) -> ParseResult<'a, O>,
+{
+    move |i: &'a str| {
+        parser(i).map_err(|e| match e {
+            nom::Err::Error(e) => nom::Err::Error(e.expecting(i, expected)),
+            nom::Err::Failure(e) => nom::Err::Failure(e.expecting(i, expected)),
+            e => e,
+        })
+    }
+}
+
+/// Parses digits, which may be separated by underscores as in `1_000`
+fn parse_digits(i: &str) -> ParseResult<'_, &str> {
+    recognize(pair(digit1, many0(alt((digit1, tag("_"))))))(i)
+}
+
+/// Parses the exponent of a number in scientific notation, such as the `e-4` of `2e-4`
+fn parse_exponent(i: &str) -> ParseResult<'_, &str> {
+    recognize(tuple((one_of("eE"), opt(one_of("+-")), parse_digits)))(i)
+}
+
+/// Returns the number `s` without the underscores separating its digits
+fn without_separators(s: &str) -> String {
+    s.replace('_', "")
+}
+
+fn parse_constant(i: &str) -> ParseResult<'_, ComputeValue> {
+    let fraction = pair(pair(char('.'), opt(parse_digits)), opt(parse_exponent));
+    let decimal = tuple((
+        opt(char('-')),
+        parse_digits,
+        alt((recognize(fraction), parse_exponent)),
+    ));
+    // A number suffixed with `d`, such as `0.1d`, is an exact decimal
+    let exact = tuple((
+        opt(char('-')),
+        parse_digits,
+        opt(pair(char('.'), parse_digits)),
+    ));
+    let hex = recognize(pair(hex_digit1, many0(alt((hex_digit1, tag("_"))))));
+
+    let constant = preceded(
         space0,
         alt((
+            map_res(
+                terminated(recognize(exact), char('d')),
+                |digit_str: &str| {
+                    without_separators(digit_str)
+                        .parse()
+                        .map(ComputeValue::Decimal)
+                },
+            ),
+            preceded(
+                tag_no_case("0x"),
+                cut(map_res(hex, |digit_str: &str| {
+                    i64::from_str_radix(&without_separators(digit_str), 16).map(ComputeValue::Int)
+                })),
+            ),
             map_res(recognize(decimal), |digit_str: &str| {
-                digit_str.parse().map(ComputeValue::Float)
+                without_separators(digit_str)
+                    .parse()
+                    .map(ComputeValue::Float)
             }),
-            map_res(digit1, |digit_str: &str| {
-                digit_str.parse().map(ComputeValue::Int)
-            }),
-            map_res(preceded(tag("-"), digit1), |digit_str: &str| {
-                digit_str.parse().map(|x: i32| ComputeValue::Int(-x))
-            }),
-            map(float, ComputeValue::Float),
+            map_res(
+                recognize(pair(opt(char('-')), parse_digits)),
+                |digit_str: &str| without_separators(digit_str).parse().map(ComputeValue::Int),
+            ),
+            map(double, ComputeValue::Float),
         )),
-    )(i)
+    );
+
+    // Floats too large to represent, such as `1e400`, are infinite
+    verify(constant, |v| match v {
+        ComputeValue::Float(f) => f.is_finite(),
+        _ => true,
+    })(i)
 }
 
-fn parse_multiply(i: &str) -> IResult<&str, Expr> {
-    let enclosed_expression = preceded(
+fn parse_parenthesized(i: &str) -> ParseResult<'_, Expr> {
+    preceded(
         space0,
         delimited(char('('), parse_expression, cut(char(')'))),
-    );
+    )(i)
+}
 
-    let inner = alt((map(parse_constant, Expr::Constant), enclosed_expression));
+/// Parses the application of a function, such as `sqrt(2)`
+fn parse_function(i: &str) -> ParseResult<'_, Expr> {
+    let name = map_res(alpha1, |name: &str| name.parse::<ComputeFunction>());
+    let (i, function) = preceded(space0, name)(i)?;
+    let (i, arg) = cut(parse_parenthesized)(i)?;
 
-    let (i, init) = inner(i)?;
+    Ok((i, Expr::Function(function, Box::new(arg))))
+}
 
-    fold_many0(
-        preceded(space0, pair(alt((char('*'), char('/'))), cut(inner))),
-        init,
-        |l, (op, r)| {
-            if op == '*' {
-                Expr::Application(ComputeOperation::Mul, Box::new(l), Box::new(r))
-            } else {
-                Expr::Application(ComputeOperation::Div, Box::new(l), Box::new(r))
-            }
-        },
-    )(i)
+/// Parses an identifier, such as the name of a variable or a keyword
+fn parse_identifier(i: &str) -> ParseResult<'_, &str> {
+    let identifier = recognize(pair(
+        alt((alpha1, tag("_"))),
+        many0(alt((alphanumeric1, tag("_")))),
+    ));
+
+    preceded(space0, identifier)(i)
 }
 
-fn parse_expression(i: &str) -> IResult<&str, Expr> {
-    let (i, init) = parse_multiply(i)?;
+/// Returns true if `name` is a keyword or the name of a function, so can't name a
+/// variable
+fn is_reserved(name: &str) -> bool {
+    matches!(name, "if" | "true" | "false") || name.parse::<ComputeFunction>().is_ok()
+}
+
+fn parse_variable(i: &str) -> ParseResult<'_, &str> {
+    verify(parse_identifier, |name: &str| !is_reserved(name))(i)
+}
+
+fn parse_bool(i: &str) -> ParseResult<'_, ComputeValue> {
+    map_opt(parse_identifier, |name| match name {
+        "true" => Some(ComputeValue::Bool(true)),
+        "false" => Some(ComputeValue::Bool(false)),
+        _ => None,
+    })(i)
+}
+
+/// Parses a conditional, `if(condition, then, otherwise)`
+fn parse_if(i: &str) -> ParseResult<'_, Expr> {
+    let (i, _) = verify(parse_identifier, |name: &str| name == "if")(i)?;
+
+    let comma = || preceded(space0, char(','));
+    let args = tuple((
+        parse_expression,
+        comma(),
+        parse_expression,
+        comma(),
+        parse_expression,
+    ));
+    let (i, (condition, _, then, _, otherwise)) = cut(delimited(
+        preceded(space0, char('(')),
+        args,
+        preceded(space0, char(')')),
+    ))(i)?;
+
+    Ok((
+        i,
+        Expr::If(Box::new(condition), Box::new(then), Box::new(otherwise)),
+    ))
+}
+
+fn parse_atom(i: &str) -> ParseResult<'_, Expr> {
+    alt((
+        parse_if,
+        parse_function,
+        map(parse_constant, Expr::Constant),
+        map(parse_bool, Expr::Constant),
+        map(parse_variable, |name| Expr::Variable(name.to_string())),
+        parse_parenthesized,
+    ))(i)
+}
+
+/// Parses exponentiation, which binds tighter than the other operators and is right
+/// associative, so that `2^3^2` is `2^(3^2)`
+fn parse_power(i: &str) -> ParseResult<'_, Expr> {
+    let (i, base) = parse_atom(i)?;
+    let (i, exponent) = opt(preceded(preceded(space0, char('^')), cut(parse_unary)))(i)?;
+
+    match exponent {
+        Some(exponent) => Ok((
+            i,
+            Expr::Application(ComputeOperation::Pow, Box::new(base), Box::new(exponent)),
+        )),
+        None => Ok((i, base)),
+    }
+}
+
+/// Negates `e`, folding the negation into a constant unless it overflows, which is
+/// left for the calculator to report
+fn negate(e: Expr) -> Expr {
+    if let Expr::Constant(v) = &e {
+        if let Ok(negated) = -*v {
+            return Expr::Constant(negated);
+        }
+    }
+    Expr::Function(ComputeFunction::Neg, Box::new(e))
+}
+
+/// Parses a unary minus, which binds looser than exponentiation, so that `-2^2` is
+/// `-(2^2)`
+fn parse_unary(i: &str) -> ParseResult<'_, Expr> {
+    let negated = preceded(preceded(space0, char('-')), cut(parse_unary));
+    expect("an expression", alt((map(negated, negate), parse_power)))(i)
+}
+
+/// Parses a left associative sequence of operations of the same precedence, such as
+/// `1 - 2 + 3`, with operators parsed by `operator` and operands by `operand`
+fn parse_operations<'a, O>(
+    i: &'a str,
+    operand: fn(&'a str) -> ParseResult<'a, Expr>,
+    operator: O,
+) -> ParseResult<'a, Expr>
+where
+    O: Fn(&'a str) -> ParseResult<'a, ComputeOperation>,
+{
+    let (i, init) = operand(i)?;
 
     fold_many0(
-        preceded(
-            space0,
-            pair(alt((char('+'), char('-'))), cut(parse_multiply)),
-        ),
+        pair(preceded(space0, operator), cut(operand)),
         init,
-        |l, (op, r)| {
-            if op == '+' {
-                Expr::Application(ComputeOperation::Add, Box::new(l), Box::new(r))
-            } else {
-                Expr::Application(ComputeOperation::Sub, Box::new(l), Box::new(r))
-            }
-        },
+        |l, (op, r)| Expr::Application(op, Box::new(l), Box::new(r)),
     )(i)
 }
 
-#[derive(Debug, Clone)]
-pub struct ParseError(pub String);
+fn parse_multiply(i: &str) -> ParseResult<'_, Expr> {
+    let operator = alt((
+        value(ComputeOperation::Mul, char('*')),
+        value(ComputeOperation::Div, char('/')),
+        value(ComputeOperation::Mod, char('%')),
+    ));
+    parse_operations(i, parse_unary, operator)
+}
+
+fn parse_sum(i: &str) -> ParseResult<'_, Expr> {
+    let operator = alt((
+        value(ComputeOperation::Add, char('+')),
+        value(ComputeOperation::Sub, char('-')),
+    ));
+    parse_operations(i, parse_multiply, operator)
+}
+
+fn parse_shift(i: &str) -> ParseResult<'_, Expr> {
+    let operator = alt((
+        value(ComputeOperation::Shl, tag("<<")),
+        value(ComputeOperation::Shr, tag(">>")),
+    ));
+    parse_operations(i, parse_sum, operator)
+}
+
+fn parse_and(i: &str) -> ParseResult<'_, Expr> {
+    parse_operations(i, parse_shift, value(ComputeOperation::And, char('&')))
+}
+
+/// Parses exclusive or, written `~` as `^` is exponentiation
+fn parse_xor(i: &str) -> ParseResult<'_, Expr> {
+    parse_operations(i, parse_and, value(ComputeOperation::Xor, char('~')))
+}
+
+fn parse_or(i: &str) -> ParseResult<'_, Expr> {
+    parse_operations(i, parse_xor, value(ComputeOperation::Or, char('|')))
+}
+
+/// Parses a comparison, which binds loosest and doesn't associate, so that `1 < 2 < 3`
+/// is invalid
+fn parse_expression(i: &str) -> ParseResult<'_, Expr> {
+    let operator = alt((
+        value(ComputeOperation::Le, tag("<=")),
+        value(ComputeOperation::Ge, tag(">=")),
+        value(ComputeOperation::Eq, tag("==")),
+        value(ComputeOperation::Ne, tag("!=")),
+        value(ComputeOperation::Lt, tag("<")),
+        value(ComputeOperation::Gt, tag(">")),
+    ));
+
+    let (i, left) = parse_or(i)?;
+    let (i, right) = opt(pair(preceded(space0, operator), cut(parse_or)))(i)?;
+
+    match right {
+        Some((op, right)) => Ok((i, Expr::Application(op, Box::new(left), Box::new(right)))),
+        None => Ok((i, left)),
+    }
+}
+
+/// The position an expression failed to parse at, the token found there, and the
+/// tokens that were expected in its place
+#[derive(Debug, Clone, PartialEq, Serialize, JsonSchema)]
+pub struct ParseError {
+    /// The offset in bytes from the start of the expression
+    pub position: usize,
+    /// The token found, None at the end of the expression
+    pub token: Option<String>,
+    pub expected: Vec<String>,
+}
+
+/// Returns the token at the start of `s`, a word or number, or otherwise a single
+/// character
+fn token(s: &str) -> Option<String> {
+    let word = s
+        .find(|c: char| !(c.is_alphanumeric() || c == '_' || c == '.'))
+        .unwrap_or(s.len());
+
+    match word {
+        0 => s.chars().next().map(String::from),
+        _ => Some(s[..word].to_string()),
+    }
+}
+
+impl ParseError {
+    fn new(input: &str, error: SyntaxError<'_>) -> Self {
+        ParseError {
+            position: input.len() - error.input.len(),
+            token: token(error.input),
+            expected: error.expected.into_iter().collect(),
+        }
+    }
+}
 
 impl std::fmt::Display for ParseError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "ParseError: {}", self.0)
+        match &self.token {
+            Some(token) => write!(f, "Unexpected \"{}\" at {}", token, self.position)?,
+            None => write!(f, "Unexpected end of input at {}", self.position)?,
+        }
+        if !self.expected.is_empty() {
+            write!(f, ", expected {}", self.expected.join(" or "))?;
+        }
+        Ok(())
     }
 }
 
 impl std::error::Error for ParseError {}
 
+/// Parses an assignment to a variable, such as `x = 1 + 2`, or an expression
+fn parse_assignment(i: &str) -> ParseResult<'_, Statement> {
+    let equals = terminated(char('='), not(char('=')));
+    let name = terminated(parse_variable, preceded(space0, equals));
+    let (i, name) = opt(name)(i)?;
+    let (i, expr) = parse_expression(i)?;
+
+    Ok((
+        i,
+        Statement {
+            name: name.map(ToString::to_string),
+            expr,
+        },
+    ))
+}
+
+/// Returns the result of parsing all of `input`, or where and why parsing failed
+fn complete<'a, T>(input: &'a str, result: ParseResult<'a, T>) -> Result<T, ParseError> {
+    let error = match result {
+        Ok((remaining, r)) if remaining.trim_start().is_empty() => return Ok(r),
+        Ok((remaining, _)) => SyntaxError::new(remaining, Some("end of input".to_string())),
+        Err(nom::Err::Error(e)) | Err(nom::Err::Failure(e)) => e,
+        Err(nom::Err::Incomplete(_)) => SyntaxError::new("", None),
+    };
+    Err(ParseError::new(input, error))
+}
+
 pub fn parse(i: &str) -> Result<Expr, ParseError> {
-    match parse_expression(i) {
-        Ok((remaining, r)) => {
-            if remaining != "" {
-                return Err(ParseError(format!("Unexpected token at \"{}\"", remaining)));
-            }
-            Ok(r)
-        }
-        Err(nom::Err::Error((i, _))) | Err(nom::Err::Failure((i, _))) => {
-            Err(ParseError(format!("Unexpected token at \"{}\"", i)))
-        }
-        Err(_) => Err(ParseError("Parse Error".to_string())),
-    }
+    complete(i, parse_expression(i))
+}
+
+pub fn parse_statement(i: &str) -> Result<Statement, ParseError> {
+    complete(i, parse_assignment(i))
 }
 
 #[cfg(test)]
 mod tests {
+    use std::collections::HashMap;
+
+    use calculator_client::ComputeError;
+
     use super::*;
 
-    fn eval(e: &Expr) -> ComputeValue {
+    fn eval(e: &Expr) -> Result<ComputeValue, ComputeError> {
         match e {
-            Expr::Constant(v) => *v,
-            Expr::Application(op, l, r) => match op {
-                ComputeOperation::Add => eval(l) + eval(r),
-                ComputeOperation::Sub => eval(l) - eval(r),
-                ComputeOperation::Mul => eval(l) * eval(r),
-                ComputeOperation::Div => eval(l) / eval(r),
+            Expr::Constant(v) => Ok(*v),
+            Expr::Application(op, l, r) => op.apply(eval(l)?, eval(r)?),
+            Expr::Function(f, v) => eval(v)?.apply(*f),
+            Expr::If(condition, then, otherwise) => match eval(condition)? {
+                ComputeValue::Bool(true) => eval(then),
+                ComputeValue::Bool(false) => eval(otherwise),
+                _ => Err(ComputeError::NotBoolean),
             },
+            Expr::Variable(name) => panic!("Unbound variable {}", name),
         }
     }
 
@@ -135,11 +469,62 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_parse_literals() -> Result<(), Box<dyn std::error::Error>> {
+        let constant = |s: &'static str| parse_constant(s).map(|(r, v)| (r.to_string(), v));
+        let float = |f| ("".to_string(), ComputeValue::Float(f));
+        let int = |i| ("".to_string(), ComputeValue::Int(i));
+
+        assert_eq!(constant("1.5e3")?, float(1500.));
+        assert_eq!(constant("2E-4")?, float(0.0002));
+        assert_eq!(constant("1e+3")?, float(1000.));
+        assert_eq!(constant("-2.e2")?, float(-200.));
+        assert_eq!(constant("0xFF")?, int(255));
+        assert_eq!(constant("0Xff")?, int(255));
+        assert_eq!(constant("0xdead_beef")?, int(0xdead_beef));
+        assert_eq!(constant("1_000_000")?, int(1_000_000));
+        assert_eq!(constant("1_000.5")?, float(1000.5));
+        assert_eq!(constant("-9223372036854775808")?, int(i64::MIN));
+        assert_eq!(
+            constant("1_000.25d")?,
+            ("".to_string(), ComputeValue::Decimal("1000.25".parse()?))
+        );
+
+        assert_eq!(parse("-0xFF")?, Expr::Constant(ComputeValue::Int(-255)));
+        assert_eq!(eval(&parse("2e3 / 0x10")?)?, ComputeValue::Float(125.));
+
+        assert!(parse("0x").is_err());
+        assert!(parse("0xG1").is_err());
+        assert!(parse("0x8000_0000_0000_0000").is_err());
+        assert!(parse("1e").is_err());
+        assert!(parse("1e400").is_err());
+        assert!(constant("_1").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_decimal() -> Result<(), Box<dyn std::error::Error>> {
+        let decimal = |s: &str| s.parse().map(ComputeValue::Decimal);
+
+        assert_eq!(parse_constant("0.1d")?, ("", decimal("0.1")?));
+        assert_eq!(parse_constant("-12d")?, ("", decimal("-12")?));
+        assert_eq!(eval(&parse_expression("0.1d + 0.2d")?.1)?, decimal("0.3")?);
+        assert_eq!(eval(&parse_expression("1d / 4")?.1)?, decimal("0.25")?);
+        assert_eq!(
+            eval(&parse_expression("0.5d * 2.0")?.1)?,
+            ComputeValue::Float(1.)
+        );
+
+        assert!(parse("1.d").is_err());
+        assert!(parse("0.1 d").is_err());
+        Ok(())
+    }
+
     #[test]
     fn test_parse_expression() -> Result<(), Box<dyn std::error::Error>> {
         let (r1, v1) = parse_expression("332+23.0- 15")?;
 
-        let evaluated = eval(&v1);
+        let evaluated = eval(&v1)?;
 
         assert_eq!(evaluated, ComputeValue::Float(340.0));
         assert_eq!(r1, "");
@@ -161,30 +546,238 @@ mod tests {
 
     #[test]
     fn test_eval() -> Result<(), Box<dyn std::error::Error>> {
-        assert_eq!(eval(&parse_expression("34/2")?.1), ComputeValue::Int(17));
+        assert_eq!(eval(&parse_expression("34/2")?.1)?, ComputeValue::Int(17));
         assert_eq!(
-            eval(&parse_expression("34 +6/ 2")?.1),
+            eval(&parse_expression("34 +6/ 2")?.1)?,
             ComputeValue::Int(37)
         );
         assert_eq!(
-            eval(&parse_expression("(34 +6)/ 2")?.1),
+            eval(&parse_expression("(34 +6)/ 2")?.1)?,
             ComputeValue::Int(20)
         );
         assert_eq!(
-            eval(&parse_expression("3 * 4 / (6+54.) * 5 - 1")?.1),
+            eval(&parse_expression("3 * 4 / (6+54.) * 5 - 1")?.1)?,
             ComputeValue::Float(0.0)
         );
         Ok(())
     }
 
+    #[test]
+    fn test_eval_pow_mod() -> Result<(), Box<dyn std::error::Error>> {
+        assert_eq!(
+            eval(&parse_expression("2 ^ 10")?.1)?,
+            ComputeValue::Int(1024)
+        );
+        assert_eq!(eval(&parse_expression("2^3^2")?.1)?, ComputeValue::Int(512));
+        assert_eq!(
+            eval(&parse_expression("2 * 3^2")?.1)?,
+            ComputeValue::Int(18)
+        );
+        assert_eq!(
+            eval(&parse_expression("(2 * 3)^2")?.1)?,
+            ComputeValue::Int(36)
+        );
+        assert_eq!(
+            eval(&parse_expression("7 % 4 * 2")?.1)?,
+            ComputeValue::Int(6)
+        );
+        assert_eq!(
+            eval(&parse_expression("10 - 7 % 4")?.1)?,
+            ComputeValue::Int(7)
+        );
+        assert_eq!(
+            eval(&parse_expression("2 ^ -1")?.1)?,
+            ComputeValue::Float(0.5)
+        );
+        assert!(parse("2 ^").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_eval_bitwise() -> Result<(), Box<dyn std::error::Error>> {
+        let int = |s| -> Result<ComputeValue, Box<dyn std::error::Error>> { Ok(eval(&parse(s)?)?) };
+
+        assert_eq!(int("0xF0 | 0x0F")?, ComputeValue::Int(0xFF));
+        assert_eq!(int("12 & 10 ~ 1")?, ComputeValue::Int(9));
+        // `1 | (2 ~ (3 & 4))`
+        assert_eq!(int("1 | 2 ~ 3 & 4")?, ComputeValue::Int(3));
+        assert_eq!(int("1 << 2 + 1")?, ComputeValue::Int(8));
+        assert_eq!(int("-16 >> 2")?, ComputeValue::Int(-4));
+        assert_eq!(int("255 & 0xF << 4")?, ComputeValue::Int(0xF0));
+        assert_eq!(eval(&parse("1.5 & 1")?), Err(ComputeError::NotInteger));
+
+        assert!(parse("1 <<").is_err());
+        assert!(parse("1 & ").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_eval_conditional() -> Result<(), Box<dyn std::error::Error>> {
+        let bool = |b| Ok(ComputeValue::Bool(b));
+
+        assert_eq!(eval(&parse("1 < 2")?), bool(true));
+        assert_eq!(eval(&parse("2 <= 2.0")?), bool(true));
+        assert_eq!(eval(&parse("1 + 1 == 2")?), bool(true));
+        assert_eq!(eval(&parse("3 != 1 | 2")?), bool(false));
+        assert_eq!(eval(&parse("1 << 2 > 3")?), bool(true));
+        assert_eq!(eval(&parse("2 >= 2.5")?), bool(false));
+        assert_eq!(eval(&parse("(1 < 2) == true")?), bool(true));
+        assert_eq!(eval(&parse("(1 < 2) & false")?), bool(false));
+
+        assert_eq!(
+            eval(&parse("if(2 > 1, 10, 1 / 0)")?)?,
+            ComputeValue::Int(10)
+        );
+        assert_eq!(
+            eval(&parse("1 + if ( false , 2, 3 ) * 2")?)?,
+            ComputeValue::Int(7)
+        );
+        assert_eq!(eval(&parse("if(1, 2, 3)")?), Err(ComputeError::NotBoolean));
+
+        assert!(parse("1 < 2 < 3").is_err());
+        assert!(parse("1 <").is_err());
+        assert!(parse("if(1, 2)").is_err());
+        assert!(parse("if").is_err());
+
+        let statement = parse_statement("x == 1")?;
+        assert_eq!(statement.name, None);
+        let statement = parse_statement("iffy = true")?;
+        assert_eq!(statement.name.as_deref(), Some("iffy"));
+        assert!(parse_statement("true = 1").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_eval_overflow() -> Result<(), Box<dyn std::error::Error>> {
+        assert_eq!(
+            eval(&parse_expression("3000000000 * 3")?.1)?,
+            ComputeValue::Int(9_000_000_000)
+        );
+        assert_eq!(
+            eval(&parse_expression("2^62 - 1 + 2^62")?.1)?,
+            ComputeValue::Int(i64::MAX)
+        );
+        assert_eq!(
+            eval(&parse_expression("2^62 * 2")?.1),
+            Err(ComputeError::Overflow)
+        );
+        assert_eq!(
+            eval(&parse_expression("2^62 * 2.0")?.1)?,
+            ComputeValue::Float(2f64.powi(63))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_eval_unary() -> Result<(), Box<dyn std::error::Error>> {
+        assert_eq!(
+            eval(&parse_expression("-(3 + 4)")?.1)?,
+            ComputeValue::Int(-7)
+        );
+        assert_eq!(eval(&parse_expression("-2^2")?.1)?, ComputeValue::Int(-4));
+        assert_eq!(eval(&parse_expression("2 * -3")?.1)?, ComputeValue::Int(-6));
+        assert_eq!(eval(&parse_expression("3 - -2")?.1)?, ComputeValue::Int(5));
+        assert_eq!(
+            eval(&parse_expression("sqrt(16)")?.1)?,
+            ComputeValue::Float(4.)
+        );
+        assert_eq!(
+            eval(&parse_expression("abs(3 - 10) + 1")?.1)?,
+            ComputeValue::Int(8)
+        );
+        assert_eq!(
+            eval(&parse_expression("-sqrt (4)")?.1)?,
+            ComputeValue::Float(-2.)
+        );
+        assert_eq!(
+            eval(&parse_expression("cos(0) * 2")?.1)?,
+            ComputeValue::Float(2.)
+        );
+
+        // Negated constants are folded
+        assert_eq!(parse("-3")?, Expr::Constant(ComputeValue::Int(-3)));
+        assert_eq!(parse("-(3)")?, Expr::Constant(ComputeValue::Int(-3)));
+        assert!(matches!(
+            parse("-(1 + 2)")?,
+            Expr::Function(ComputeFunction::Neg, _)
+        ));
+
+        assert!(parse("foo(2)").is_err());
+        assert!(parse("sqrt 2").is_err());
+        assert!(parse("-").is_err());
+        Ok(())
+    }
+
     #[test]
     fn test_parse() -> Result<(), Box<dyn std::error::Error>> {
-        let r1 = parse("34 +f6/ 2").unwrap_err();
+        let error = |position, token: Option<&str>, expected: &[&str]| ParseError {
+            position,
+            token: token.map(ToString::to_string),
+            expected: expected.iter().map(ToString::to_string).collect(),
+        };
+
+        let r1 = parse("34 +#6/ 2").unwrap_err();
         let r2 = parse("34a +f6/ 2").unwrap_err();
 
-        assert_eq!(r1.0, "Unexpected token at \"f6/ 2\"".to_string());
-        assert_eq!(r2.0, "Unexpected token at \"a +f6/ 2\"".to_string());
+        assert_eq!(r1, error(4, Some("#"), &["an expression"]));
+        assert_eq!(r2, error(2, Some("a"), &["end of input"]));
+        assert_eq!(
+            r1.to_string(),
+            "Unexpected \"#\" at 4, expected an expression"
+        );
+
+        assert_eq!(parse("").unwrap_err(), error(0, None, &["an expression"]));
+        assert_eq!(parse("(1 + 2").unwrap_err(), error(6, None, &["\")\""]));
+        assert_eq!(
+            parse("sqrt 2").unwrap_err(),
+            error(5, Some("2"), &["\"(\""])
+        );
+        assert_eq!(
+            parse("if(x > 1, 2.5)").unwrap_err(),
+            error(13, Some(")"), &["\",\""])
+        );
+        assert_eq!(
+            parse("1 + 2 3").unwrap_err(),
+            error(6, Some("3"), &["end of input"])
+        );
+        assert_eq!(
+            parse_statement("x = ").unwrap_err(),
+            error(4, None, &["an expression"])
+        );
+        assert_eq!(parse("1 + 2 ")?, parse("1+2")?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_statement() -> Result<(), Box<dyn std::error::Error>> {
+        let mut env = HashMap::new();
+        env.insert("x".to_string(), ComputeValue::Int(3));
+        env.insert("total_2".to_string(), ComputeValue::Int(4));
+
+        let statement = parse_statement("y = x * total_2 + 1")?;
+        assert_eq!(statement.name.as_deref(), Some("y"));
+        assert_eq!(eval(&statement.expr.bind(&env))?, ComputeValue::Int(13));
+
+        let statement = parse_statement("-x ^ 2")?;
+        assert_eq!(statement.name, None);
+        assert_eq!(eval(&statement.expr.bind(&env))?, ComputeValue::Int(-9));
+
+        // Unbound variables are left in place
+        let statement = parse_statement("x + z")?;
+        assert_eq!(
+            statement.expr.bind(&env),
+            Expr::Application(
+                ComputeOperation::Add,
+                Box::new(Expr::Constant(ComputeValue::Int(3))),
+                Box::new(Expr::Variable("z".to_string()))
+            )
+        );
 
+        // Functions can't be assigned to
+        assert!(parse_statement("sqrt = 2").is_err());
+        assert!(parse_statement("x = ").is_err());
+        assert!(parse_statement("1 = 2").is_err());
         Ok(())
     }
 }
@@ -0,0 +1,177 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use futures::future::join_all;
+use reqwest::{Client, Url};
+use schemars::JsonSchema;
+use serde::Serialize;
+
+use crate::balancer::Balancer;
+use crate::config::RouteConfig;
+
+/// The path of the readiness probe of the calculator
+const CALCULATOR_READY: &str = "/readyz";
+
+/// The health of an upstream, or of one replica of it
+#[derive(Debug, Clone, PartialEq, Serialize, JsonSchema)]
+pub struct UpstreamHealth {
+    /// The name of the upstream, `calculator` or the prefix of a route
+    pub name: String,
+    /// The endpoint probed
+    pub url: String,
+    pub healthy: bool,
+    /// Whether the gateway isn't ready while the upstream is down
+    pub critical: bool,
+    /// The number of milliseconds the probe took
+    pub latency_ms: u64,
+    /// Why the upstream is unhealthy
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// The health of the gateway's upstreams
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct Health {
+    /// Whether every critical upstream has a healthy replica
+    pub ready: bool,
+    pub upstreams: Vec<UpstreamHealth>,
+}
+
+impl Health {
+    fn new(upstreams: Vec<UpstreamHealth>) -> Health {
+        // An upstream with several replicas is up if any of them is
+        let ready = upstreams.iter().filter(|x| x.critical).all(|upstream| {
+            upstreams
+                .iter()
+                .any(|x| x.name == upstream.name && x.healthy)
+        });
+        Health { ready, upstreams }
+    }
+}
+
+/// An upstream health endpoint to probe
+struct Probe {
+    name: String,
+    url: Url,
+    critical: bool,
+}
+
+/// Probes the readiness of the calculator replicas and the health endpoints of the
+/// upstreams of the routes that have one
+pub struct HealthChecker {
+    client: Client,
+    timeout: Duration,
+    balancer: Arc<Balancer>,
+    probes: Vec<Probe>,
+}
+
+impl HealthChecker {
+    pub fn new(
+        client: Client,
+        timeout: Duration,
+        balancer: Arc<Balancer>,
+        routes: &[RouteConfig],
+    ) -> HealthChecker {
+        let probes = routes
+            .iter()
+            .filter_map(|route| {
+                let path = route.health.as_ref()?;
+                let upstream: Url = route.upstream.parse().expect("Invalid upstream URL");
+                Some(Probe {
+                    name: route.prefix.clone(),
+                    url: upstream.join(path).expect("Invalid health check path"),
+                    critical: route.critical,
+                })
+            })
+            .collect();
+
+        HealthChecker {
+            client,
+            timeout,
+            balancer,
+            probes,
+        }
+    }
+
+    /// Requests `url`, which is healthy if it responds successfully within the timeout
+    async fn probe(&self, name: &str, url: &Url, critical: bool) -> UpstreamHealth {
+        let start = Instant::now();
+        let response = self
+            .client
+            .get(url.clone())
+            .timeout(self.timeout)
+            .send()
+            .await;
+
+        let error = match response {
+            Ok(response) if response.status().is_success() => None,
+            Ok(response) => Some(format!("Responded with {}", response.status())),
+            Err(e) => Some(e.to_string()),
+        };
+        UpstreamHealth {
+            name: name.to_string(),
+            url: url.to_string(),
+            healthy: error.is_none(),
+            critical,
+            latency_ms: start.elapsed().as_millis() as u64,
+            error,
+        }
+    }
+
+    /// Probes every upstream at once, returning their health
+    pub async fn check(&self) -> Health {
+        let calculator: Vec<_> = self
+            .balancer
+            .replicas()
+            .iter()
+            .filter_map(|x| x.url().join(CALCULATOR_READY).ok())
+            .collect();
+
+        let probes = calculator
+            .iter()
+            .map(|url| self.probe("calculator", url, true))
+            .chain(
+                self.probes
+                    .iter()
+                    .map(|x| self.probe(&x.name, &x.url, x.critical)),
+            );
+        Health::new(join_all(probes).await)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn upstream(name: &str, healthy: bool, critical: bool) -> UpstreamHealth {
+        UpstreamHealth {
+            name: name.to_string(),
+            url: format!("http://{}/readyz", name),
+            healthy,
+            critical,
+            latency_ms: 0,
+            error: None,
+        }
+    }
+
+    #[test]
+    fn test_ready() {
+        let health = |upstreams| Health::new(upstreams).ready;
+
+        assert!(health(vec![]));
+        assert!(health(vec![
+            upstream("calculator", true, true),
+            upstream("calculator", false, true),
+            upstream("/crawler", false, false),
+        ]));
+        assert!(!health(vec![
+            upstream("calculator", false, true),
+            upstream("calculator", false, true),
+            upstream("/crawler", true, false),
+        ]));
+        assert!(!health(vec![
+            upstream("calculator", true, true),
+            upstream("/auth", false, true),
+        ]));
+    }
+}
@@ -0,0 +1,301 @@
+use std::time::Instant;
+
+use rocket::data::Data;
+use rocket::fairing::{Fairing, Info, Kind};
+use rocket::http::Header;
+use rocket::{Request, Response};
+use serde_json::{Map, Value};
+use tokio_tungstenite::tungstenite::handshake::server::Request as Handshake;
+use tokio_tungstenite::tungstenite::http::StatusCode;
+use tracing::info;
+use uuid::Uuid;
+
+use errors::REQUEST_ID;
+use jwt::{JwtClaims, Scope};
+use rocket_util::request_span;
+
+use crate::auth::claims;
+use crate::config::LoggingConfig;
+
+/// The value logged in place of a redacted value
+const REDACTED: &str = "[REDACTED]";
+
+/// The maximum number of bytes of a request body that are logged, those of larger
+/// bodies aren't logged
+const MAX_BODY: usize = 512;
+
+/// Returns true if the value of `name` must be redacted, as it contains one of `redact`
+fn is_redacted(name: &str, redact: &[String]) -> bool {
+    let name = name.to_ascii_lowercase();
+    redact
+        .iter()
+        .any(|x| name.contains(x.to_ascii_lowercase().as_str()))
+}
+
+/// Returns `headers` as a JSON object of lowercase names to values, with the values of
+/// headers named in `redact` redacted
+fn redact_headers<'a>(
+    headers: impl IntoIterator<Item = (&'a str, &'a str)>,
+    redact: &[String],
+) -> Value {
+    let mut map = Map::new();
+    for (name, value) in headers {
+        let name = name.to_ascii_lowercase();
+        let value = if is_redacted(&name, redact) {
+            REDACTED
+        } else {
+            value
+        };
+        match map.get_mut(&name) {
+            Some(Value::String(existing)) => {
+                existing.push_str(", ");
+                existing.push_str(value);
+            }
+            _ => {
+                map.insert(name, value.into());
+            }
+        }
+    }
+    Value::Object(map)
+}
+
+/// Returns `query` with the values of the parameters named in `redact` redacted
+fn redact_query(query: &str, redact: &[String]) -> String {
+    query
+        .split('&')
+        .map(|pair| match pair.split('=').next() {
+            Some(name) if is_redacted(name, redact) => format!("{}={}", name, REDACTED),
+            _ => pair.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+/// Redacts the values of the fields of `value` named in `redact`, at any depth
+fn redact_json(value: &mut Value, redact: &[String]) {
+    match value {
+        Value::Object(map) => {
+            for (name, value) in map.iter_mut() {
+                if is_redacted(name, redact) {
+                    *value = REDACTED.into();
+                } else {
+                    redact_json(value, redact);
+                }
+            }
+        }
+        Value::Array(values) => values.iter_mut().for_each(|x| redact_json(x, redact)),
+        _ => {}
+    }
+}
+
+/// Returns true if `id` can be used as the ID of a request
+fn is_valid_id(id: &str) -> bool {
+    !id.is_empty() && id.len() <= 128 && id.bytes().all(|x| x.is_ascii_graphic())
+}
+
+/// What is known about a request when it is received, kept in its local cache until
+/// the response is logged
+struct Received {
+    start: Instant,
+    id: String,
+    /// The request body, redacted, if it is logged
+    body: Option<String>,
+}
+
+/// Logs every request and its response as a JSON event, with the values of sensitive
+/// headers, query parameters and body fields redacted
+///
+/// Each request is given an ID, taken from its `X-Request-Id` header if it has one,
+/// which is added to the request before it is handled or forwarded, and to its response
+pub struct RequestLogger {
+    config: LoggingConfig,
+}
+
+impl RequestLogger {
+    pub fn new(config: LoggingConfig) -> RequestLogger {
+        RequestLogger { config }
+    }
+}
+
+#[rocket::async_trait]
+impl Fairing for RequestLogger {
+    fn info(&self) -> Info {
+        Info {
+            name: "Request Logger",
+            kind: Kind::Request | Kind::Response,
+        }
+    }
+
+    async fn on_request(&self, request: &mut Request<'_>, data: &mut Data) {
+        let start = Instant::now();
+        let id = match request.headers().get_one(REQUEST_ID) {
+            Some(id) if is_valid_id(id) => id.to_string(),
+            _ => {
+                let id = Uuid::new_v4().to_string();
+                request.replace_header(Header::new(REQUEST_ID, id.clone()));
+                id
+            }
+        };
+
+        let is_json = request.content_type().map_or(false, |x| x.is_json());
+        let body = if self.config.body && is_json {
+            let peeked = data.peek(MAX_BODY).await;
+            match serde_json::from_slice(peeked) {
+                Ok(mut body) if data.peek_complete() => {
+                    redact_json(&mut body, &self.config.redact_fields);
+                    Some(body.to_string())
+                }
+                _ => None,
+            }
+        } else {
+            None
+        };
+
+        request.local_cache(|| Received { start, id, body });
+    }
+
+    async fn on_response<'r>(&self, request: &'r Request<'_>, response: &mut Response<'r>) {
+        let received = request.local_cache(|| Received {
+            start: Instant::now(),
+            id: Uuid::new_v4().to_string(),
+            body: None,
+        });
+        response.set_raw_header(REQUEST_ID, received.id.clone());
+
+        let claims = claims(request);
+        let query = request
+            .uri()
+            .query()
+            .map(|x| redact_query(x, &self.config.redact_fields));
+        let headers = if self.config.headers {
+            let headers = request
+                .headers()
+                .iter()
+                .filter(|x| !x.name().as_str().eq_ignore_ascii_case(REQUEST_ID));
+            let headers = headers.map(|x| (x.name().as_str(), x.value()));
+            Some(redact_headers(headers, &self.config.redact_headers).to_string())
+        } else {
+            None
+        };
+
+        // Logged within the span of the request, so as to include its trace ID
+        let span = request_span(request);
+        let _entered = span.enter();
+        info!(
+            request_id = received.id.as_str(),
+            method = request.method().as_str(),
+            path = request.uri().path(),
+            query = query.as_deref(),
+            status = response.status().code,
+            latency_ms = received.start.elapsed().as_secs_f64() * 1000.,
+            cid = claims.map(|x| x.cid.as_str()),
+            sub = claims.and_then(|x| x.sub.as_deref()),
+            headers = headers.as_deref(),
+            body = received.body.as_deref(),
+            "Handled request"
+        );
+    }
+}
+
+/// Logs the handshake of a WebSocket session and the status it was answered with, as
+/// `RequestLogger` logs a request
+pub fn log_handshake(
+    config: &LoggingConfig,
+    request: &Handshake,
+    status: StatusCode,
+    start: Instant,
+    claims: Option<&JwtClaims<Scope>>,
+) {
+    let id = request
+        .headers()
+        .get(REQUEST_ID)
+        .and_then(|x| x.to_str().ok())
+        .filter(|x| is_valid_id(x))
+        .map(ToString::to_string)
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+
+    let query = request
+        .uri()
+        .query()
+        .map(|x| redact_query(x, &config.redact_fields));
+    let headers = if config.headers {
+        let headers = request
+            .headers()
+            .iter()
+            .filter(|(name, _)| !name.as_str().eq_ignore_ascii_case(REQUEST_ID))
+            .filter_map(|(name, value)| Some((name.as_str(), value.to_str().ok()?)));
+        Some(redact_headers(headers, &config.redact_headers).to_string())
+    } else {
+        None
+    };
+
+    info!(
+        request_id = id.as_str(),
+        method = request.method().as_str(),
+        path = request.uri().path(),
+        query = query.as_deref(),
+        status = status.as_u16(),
+        latency_ms = start.elapsed().as_secs_f64() * 1000.,
+        cid = claims.map(|x| x.cid.as_str()),
+        sub = claims.and_then(|x| x.sub.as_deref()),
+        headers = headers.as_deref(),
+        "Handled session handshake"
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_redact() {
+        let config = LoggingConfig::default();
+
+        let headers = vec![
+            ("Authorization", "Bearer abc"),
+            ("Accept", "text/html"),
+            ("accept", "*/*"),
+            ("X-Api-Key", "abc"),
+        ];
+        assert_eq!(
+            redact_headers(headers, &config.redact_headers),
+            json!({
+                "authorization": REDACTED,
+                "accept": "text/html, */*",
+                "x-api-key": REDACTED,
+            })
+        );
+
+        assert_eq!(
+            redact_query("a=1&access_token=abc&Password", &config.redact_fields),
+            "a=1&access_token=[REDACTED]&Password=[REDACTED]"
+        );
+
+        let mut body = json!({
+            "username": "a",
+            "password": "b",
+            "tokens": [{ "refresh_token": "c" }],
+            "nested": { "client_secret": "d", "scope": "e" },
+        });
+        redact_json(&mut body, &config.redact_fields);
+        assert_eq!(
+            body,
+            json!({
+                "username": "a",
+                "password": REDACTED,
+                "tokens": REDACTED,
+                "nested": { "client_secret": REDACTED, "scope": "e" },
+            })
+        );
+    }
+
+    #[test]
+    fn test_request_id() {
+        assert!(is_valid_id("4a6e4b4e-8f43-4bb1-a58c-0ad2e1bb0a3d"));
+        assert!(!is_valid_id(""));
+        assert!(!is_valid_id("a b"));
+        assert!(!is_valid_id(&"a".repeat(129)));
+    }
+}
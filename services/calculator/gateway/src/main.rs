@@ -2,43 +2,151 @@
 extern crate lazy_static;
 #[macro_use]
 extern crate rocket;
-#[macro_use]
-extern crate rocket_contrib;
 
-use reqwest::ClientBuilder;
+use reqwest::redirect::Policy;
+use reqwest::{ClientBuilder, Url};
+use tokio::net::TcpListener;
 use tokio::time::Duration;
 
+use crate::balancer::Balancer;
+use crate::cache::Cache;
 use crate::client::CalculatorClient;
+use crate::health::HealthChecker;
+use crate::logging::RequestLogger;
+use crate::proxy::Proxy;
+use crate::ratelimit::RateLimits;
+use crate::session::Sessions;
+use ::health::{Health, Probe};
+use flags::Flags;
 use jwt::Validator;
+use openapi::{OpenApi, Spec};
+use rocket_util::RequestTracer;
 use std::sync::Arc;
 
 mod api;
+mod auth;
+mod balancer;
+mod cache;
 mod client;
 mod config;
 mod error;
 mod expression;
+mod health;
+mod logging;
+mod proxy;
+mod ratelimit;
+mod retry;
+mod session;
+mod switch;
+
+/// Returns the checks of the keys tokens are validated with, and of the upstreams the
+/// gateway isn't ready without
+fn probes(checker: Arc<HealthChecker>, validator: Validator) -> Health {
+    let probes = [Probe::Ready, Probe::Startup];
+    Health::new()
+        .register("jwks", &probes, move || {
+            let result = validator.check();
+            async move { result }
+        })
+        .register("upstreams", &probes, move || {
+            let checker = checker.clone();
+            async move {
+                if !checker.check().await.ready {
+                    return Err("A critical upstream is down");
+                }
+                Ok(())
+            }
+        })
+}
 
 #[rocket::main]
 async fn main() {
-    env_logger::init();
-    let figment = rocket_util::figment();
-    let config: config::Config = figment.extract().unwrap();
+    let (config, figment) = settings::load_with::<config::Config>(rocket::Config::figment());
+
+    let _exporter = telemetry::trace::init("gateway", env!("CARGO_PKG_VERSION"), &config.tracing);
 
     let validator = Validator::new(&config.validator).expect("Failed to load JWT validator");
+    let flags = Flags::start(&config.flags);
 
     let http_client = ClientBuilder::new()
         .timeout(Duration::from_secs(5))
         .build()
         .expect("Failed to build HTTP Client");
 
-    let client = CalculatorClient::new(http_client, config.upstream.calculator.clone());
+    let upstream = &config.upstream;
+    let upstreams: Vec<Url> = upstream
+        .calculator
+        .split_whitespace()
+        .map(|x| x.parse().expect("Invalid calculator URL"))
+        .collect();
+
+    let balancer = Arc::new(Balancer::new(
+        upstreams.clone(),
+        upstream.max_failures,
+        Duration::from_secs(upstream.ejection),
+    ));
+    if upstream.discover {
+        let refresh = Duration::from_secs(upstream.refresh);
+        tokio::spawn(balancer::discover(balancer.clone(), upstreams, refresh));
+    }
+
+    let checker = Arc::new(HealthChecker::new(
+        http_client.clone(),
+        Duration::from_millis(config.health.timeout),
+        balancer.clone(),
+        &config.routes,
+    ));
+    let health = probes(checker.clone(), validator.clone());
+    let client = Arc::new(CalculatorClient::new(http_client, balancer));
+
+    let listener = TcpListener::bind(config.session.address)
+        .await
+        .expect("Failed to bind session listener");
+    let sessions = Sessions::new(
+        config.session.clone(),
+        config.logging.clone(),
+        validator.clone(),
+        client.clone(),
+        flags.clone(),
+        config.rate_limits.get(session::SESSION_ROUTE).copied(),
+    );
+    tokio::spawn(session::serve(listener, sessions));
 
-    let result = rocket::custom(figment)
+    // Upstreams respond to redirects forwarded to them by the client
+    let proxy_client = ClientBuilder::new()
+        .redirect(Policy::none())
+        .build()
+        .expect("Failed to build HTTP Client");
+
+    let doc = OpenApi::default().with(api::document).build(
+        "Calculator Gateway",
+        "Evaluates expressions using the calculator. Statements may also be evaluated over \
+            WebSocket sessions, which OpenAPI can't describe",
+    );
+
+    let mut rocket = rocket::custom(figment)
         .manage(validator)
-        .manage(Arc::new(client))
+        .manage(client)
+        .manage(config.batch)
+        .manage(checker)
+        .manage(health)
+        .manage(RateLimits::new(&config.rate_limits))
+        .manage(flags.clone())
+        .manage(Spec::new(&doc))
+        .attach(RequestLogger::new(config.logging))
+        .attach(RequestTracer)
         .mount("/", api::routes())
-        .launch()
-        .await;
+        .mount("/", openapi::routes())
+        .mount("/api/v1", openapi::routes());
+
+    let cache = Cache::new(&config.cache);
+    for route in &config.routes {
+        let proxy = Proxy::new(route, proxy_client.clone(), &cache, flags.clone());
+        let base = proxy.base().to_string();
+        rocket = rocket.mount(&base, proxy.routes());
+    }
+
+    let result = rocket.launch().await;
 
     assert!(result.is_ok());
 }
@@ -0,0 +1,424 @@
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+
+use errors::Error;
+use flags::Flags;
+use futures::{StreamExt, TryStreamExt};
+use jwt::Scope;
+use reqwest::{Body, Client, Url};
+use rocket::data::{Data, ToByteUnit};
+use rocket::handler::{Handler, Outcome};
+use rocket::http::{Method, Status};
+use rocket::response::Response;
+use rocket::{Request, Route};
+use rocket_util::request_span;
+use tracing::{warn, Instrument};
+
+use crate::auth::authorize;
+use crate::cache::{self, Cache, CacheControl, CachedResponse};
+use crate::config::RouteConfig;
+use crate::ratelimit::{rate_limited, RateLimiter};
+use crate::retry::{is_idempotent, RetryPolicy};
+use crate::switch::{is_enabled, route_disabled};
+
+/// The rank of the routes of a proxy for the root, so that the gateway's own routes
+/// take precedence
+const RANK: isize = 20;
+
+/// Returns true if `header` describes a single connection rather than the message, so
+/// mustn't be forwarded
+fn is_hop_by_hop(header: &str) -> bool {
+    const HEADERS: &[&str] = &[
+        "connection",
+        "content-length",
+        "host",
+        "keep-alive",
+        "proxy-authenticate",
+        "proxy-authorization",
+        "te",
+        "trailer",
+        "transfer-encoding",
+        "upgrade",
+    ];
+    HEADERS.iter().any(|x| x.eq_ignore_ascii_case(header))
+}
+
+/// Returns true if `header` carries the trace context of the request, which is replaced
+/// by that of the span forwarding it
+fn is_trace_context(header: &str) -> bool {
+    telemetry::trace::HEADERS
+        .iter()
+        .any(|x| x.eq_ignore_ascii_case(header))
+}
+
+/// Returns true if `header` is particular to the client a response is sent to, and so
+/// is never served from the cache
+fn is_private(header: &str) -> bool {
+    header.eq_ignore_ascii_case("set-cookie") || header.eq_ignore_ascii_case("set-cookie2")
+}
+
+/// The statuses of the responses that are cached
+const CACHEABLE: &[u16] = &[200, 203, 204, 300, 301, 404, 410];
+
+/// Returns the headers of `response` that are forwarded to the client
+fn forwarded_headers(response: &reqwest::Response) -> Vec<(String, String)> {
+    response
+        .headers()
+        .iter()
+        .filter(|(name, _)| !is_hop_by_hop(name.as_str()))
+        .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+        .collect()
+}
+
+/// Returns the response to the client for a cached response, `hit` if it was read from
+/// the cache rather than just stored
+fn cached_response<'r>(cached: CachedResponse, hit: bool) -> Response<'r> {
+    let mut builder = Response::build();
+    builder.status(Status::from_code(cached.status).unwrap_or(Status::BadGateway));
+    for (name, value) in cached.headers.iter().filter(|(name, _)| !is_private(name)) {
+        builder.raw_header_adjoin(name.clone(), value.clone());
+    }
+    if hit {
+        builder.raw_header("Age", cached.age().to_string());
+    }
+    builder.raw_header("X-Cache", if hit { "HIT" } else { "MISS" });
+    builder.streamed_body(io::Cursor::new(cached.body));
+    builder.finalize()
+}
+
+/// The caching of the responses of a route
+#[derive(Clone)]
+struct RouteCache {
+    cache: Cache,
+    ttl: Duration,
+    headers: Vec<String>,
+}
+
+/// Forwards the requests under a path prefix to an upstream, streaming the bodies of
+/// the request and response
+///
+/// If the route caches responses, those to GET and HEAD requests allowed to be cached
+/// by their `Cache-Control` header are buffered and stored in the cache instead
+#[derive(Clone)]
+pub struct Proxy {
+    prefix: String,
+    upstream: Url,
+    timeout: Duration,
+    client: Client,
+    cache: Option<RouteCache>,
+    rate_limit: Option<Arc<RateLimiter>>,
+    /// The scopes requests must be authorized for
+    scopes: Vec<Scope>,
+    retry: Option<Arc<RetryPolicy>>,
+    flags: Arc<Flags>,
+    /// The flag turning the route off, if it can be
+    flag: Option<String>,
+}
+
+impl Proxy {
+    pub fn new(config: &RouteConfig, client: Client, cache: &Cache, flags: Arc<Flags>) -> Proxy {
+        let prefix = config.prefix.trim_end_matches('/').to_string();
+        let rate_limit = config
+            .rate_limit
+            .map(|x| Arc::new(RateLimiter::new(&config.prefix, x)));
+        Proxy {
+            prefix,
+            upstream: config.upstream.parse().expect("Invalid upstream URL"),
+            timeout: Duration::from_secs(config.timeout),
+            client,
+            cache: config.cache.as_ref().map(|x| RouteCache {
+                cache: cache.clone(),
+                ttl: Duration::from_secs(x.ttl),
+                headers: x.headers.clone(),
+            }),
+            rate_limit,
+            scopes: config.scopes.clone(),
+            retry: config
+                .retry
+                .as_ref()
+                .map(|x| Arc::new(RetryPolicy::new(&config.prefix, x))),
+            flags,
+            flag: config.flag.clone(),
+        }
+    }
+
+    /// Returns the path the routes of the proxy are mounted at
+    pub fn base(&self) -> &str {
+        match self.prefix.as_str() {
+            "" => "/",
+            prefix => prefix,
+        }
+    }
+
+    /// Returns the routes forwarding every method under the prefix, to be mounted at
+    /// the base of the proxy
+    pub fn routes(self) -> Vec<Route> {
+        use Method::*;
+
+        // Routes with longer prefixes are ranked first
+        let depth = self.prefix.split('/').filter(|x| !x.is_empty()).count();
+        let rank = RANK - depth as isize;
+
+        let methods = [Get, Put, Post, Delete, Patch, Head, Options];
+        methods
+            .iter()
+            .flat_map(|method| {
+                vec![
+                    Route::ranked(rank, *method, "/", self.clone()),
+                    Route::ranked(rank, *method, "/<path..>", self.clone()),
+                ]
+            })
+            .collect()
+    }
+
+    /// Returns the URL to forward a request for `path` to, where the prefix of the path
+    /// is replaced by the upstream, None if the path doesn't have the prefix
+    fn url(&self, path: &str, query: Option<&str>) -> Option<Url> {
+        let rest = path.strip_prefix(&self.prefix)?;
+        if !rest.is_empty() && !rest.starts_with('/') {
+            return None;
+        }
+
+        let mut url = self.upstream.clone();
+        url.set_path(&format!(
+            "{}{}",
+            self.upstream.path().trim_end_matches('/'),
+            rest
+        ));
+        url.set_query(query);
+        Some(url)
+    }
+
+    /// Returns the key the response to `request` is cached under, None if it mustn't
+    /// be cached
+    fn cache_key(&self, request: &Request<'_>) -> Option<String> {
+        let cache = self.cache.as_ref()?;
+        if !matches!(request.method(), Method::Get | Method::Head) {
+            return None;
+        }
+        if CacheControl::parse(request.headers().get("Cache-Control")).no_store {
+            return None;
+        }
+
+        let headers: Vec<_> = cache
+            .headers
+            .iter()
+            .map(|x| (x.as_str(), request.headers().get(x).collect()))
+            .collect();
+        Some(cache::key(
+            request.method().as_str(),
+            request.uri().path(),
+            request.uri().query(),
+            &headers,
+        ))
+    }
+
+    /// Returns how long `response` to `request` may be cached for, None if it mustn't
+    /// be cached
+    fn cache_ttl(&self, request: &Request<'_>, response: &reqwest::Response) -> Option<Duration> {
+        let cache = self.cache.as_ref()?;
+        if !CACHEABLE.contains(&response.status().as_u16()) {
+            return None;
+        }
+        // Responses of unknown length, such as those streamed by the upstream, aren't
+        // buffered to find out
+        let max_size = cache.cache.max_size as u64;
+        if response.content_length().map_or(true, |x| x > max_size) {
+            return None;
+        }
+
+        // Responses setting cookies are for a single client, so are never shared
+        let headers = response.headers();
+        if headers.keys().any(|x| is_private(x.as_str())) {
+            return None;
+        }
+
+        let values = |name: &str| {
+            headers
+                .get_all(name)
+                .iter()
+                .filter_map(|x| x.to_str().ok())
+                .collect::<Vec<_>>()
+        };
+        if !cache::varies_within(values("Vary"), &cache.headers) {
+            return None;
+        }
+
+        let authorized = request.headers().contains("Authorization");
+        CacheControl::parse(values("Cache-Control")).ttl(cache.ttl, authorized)
+    }
+
+    async fn forward<'r>(
+        &self,
+        request: &'r Request<'_>,
+        data: Data,
+    ) -> Result<Response<'r>, Error> {
+        if let Some(flag) = &self.flag {
+            if !is_enabled(&self.flags, flag, request) {
+                return Err(route_disabled());
+            }
+        }
+        if let Some(limiter) = &self.rate_limit {
+            if let Err(wait) = limiter.take_request(request) {
+                return Err(rate_limited(wait));
+            }
+        }
+        authorize(request, &self.scopes).await?;
+
+        let url = self
+            .url(request.uri().path(), request.uri().query())
+            .ok_or_else(Error::not_found)?;
+        let method = reqwest::Method::from_bytes(request.method().as_str().as_bytes())
+            .map_err(|_| Error::status(405))?;
+
+        let key = self.cache_key(request);
+        if let (Some(cache), Some(key)) = (&self.cache, &key) {
+            let control = CacheControl::parse(request.headers().get("Cache-Control"));
+            if !control.bypasses() {
+                if let Some(cached) = cache.cache.store.get(key).await {
+                    return Ok(cached_response(cached, true));
+                }
+            }
+        }
+
+        let idempotent = is_idempotent(&method);
+        let span = telemetry::trace::client_span(method.as_str(), url.as_str());
+        let mut upstream = self.client.request(method, url).timeout(self.timeout);
+        for header in request.headers().iter() {
+            let name = header.name().as_str();
+            if !is_hop_by_hop(name) && !is_trace_context(name) {
+                upstream = upstream.header(name, header.value());
+            }
+        }
+        for (name, value) in telemetry::trace::headers(&span) {
+            upstream = upstream.header(name.as_str(), value);
+        }
+        if let Some(ip) = request.client_ip() {
+            upstream = upstream.header("X-Forwarded-For", ip.to_string());
+        }
+
+        let headers = request.headers();
+        let has_body = headers.contains("Content-Length") || headers.contains("Transfer-Encoding");
+        if has_body {
+            // The body is read by a task sending it on, as the stream of a body must be
+            // Sync to send it with reqwest
+            let limit = request
+                .limits()
+                .get("proxy")
+                .unwrap_or_else(|| 8.mebibytes());
+            let mut body = tokio::io::reader_stream(data.open(limit));
+            let (mut tx, rx) = tokio::sync::mpsc::channel(4);
+            tokio::spawn(async move {
+                while let Some(chunk) = body.next().await {
+                    if tx.send(chunk).await.is_err() {
+                        break;
+                    }
+                }
+            });
+            upstream = upstream.body(Body::wrap_stream(rx));
+        }
+
+        // Streamed bodies can't be sent again, so only requests without one are retried
+        let response = match &self.retry {
+            Some(retry) if idempotent && !has_body => {
+                retry.send(upstream).instrument(span.clone()).await
+            }
+            _ => upstream.send().instrument(span.clone()).await,
+        };
+        let response = response.map_err(|e| {
+            warn!(upstream = %self.upstream, error = %e, "Failed to forward request");
+            if e.is_timeout() {
+                Error::status(504)
+            } else {
+                Error::status(502)
+            }
+        })?;
+
+        let status = response.status().as_u16();
+        span.record("http.status_code", &status);
+        let headers = forwarded_headers(&response);
+
+        if let (Some(cache), Some(key)) = (&self.cache, &key) {
+            if let Some(ttl) = self.cache_ttl(request, &response) {
+                let body = response.bytes().await.map_err(|e| {
+                    warn!(upstream = %self.upstream, error = %e, "Failed to read response");
+                    Error::status(502)
+                })?;
+                let headers = headers.into_iter().filter(|(x, _)| !is_private(x));
+                let cached = CachedResponse::new(status, headers.collect(), body.to_vec());
+                cache.cache.store.put(key, &cached, ttl).await;
+                return Ok(cached_response(cached, false));
+            }
+        }
+
+        let mut builder = Response::build();
+        builder.status(Status::from_code(status).unwrap_or(Status::BadGateway));
+        for (name, value) in headers {
+            builder.raw_header_adjoin(name, value);
+        }
+        if key.is_some() {
+            builder.raw_header("X-Cache", "MISS");
+        }
+
+        let body = response
+            .bytes_stream()
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e));
+        builder.streamed_body(tokio::io::stream_reader(body));
+        Ok(builder.finalize())
+    }
+}
+
+#[rocket::async_trait]
+impl Handler for Proxy {
+    async fn handle<'r, 's: 'r>(&'s self, request: &'r Request<'_>, data: Data) -> Outcome<'r> {
+        let forward = self.forward(request, data);
+        match forward.instrument(request_span(request)).await {
+            Ok(response) => Outcome::Success(response),
+            Err(e) => Outcome::from(request, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_url() {
+        let proxy = |prefix: &str, upstream: &str| {
+            let config = RouteConfig {
+                prefix: prefix.to_string(),
+                upstream: upstream.to_string(),
+                ..Default::default()
+            };
+            let cache = Cache::new(&Default::default());
+            Proxy::new(&config, Client::new(), &cache, Default::default())
+        };
+        let url = |proxy: &Proxy, path, query| proxy.url(path, query).map(|x| x.to_string());
+
+        let auth = proxy("/auth/", "http://auth:8000");
+        assert_eq!(
+            url(&auth, "/auth/oauth/token", Some("a=1")).as_deref(),
+            Some("http://auth:8000/oauth/token?a=1")
+        );
+        assert_eq!(
+            url(&auth, "/auth", None).as_deref(),
+            Some("http://auth:8000/")
+        );
+        assert_eq!(url(&auth, "/authors", None), None);
+
+        let crawler = proxy("/crawler", "http://crawler/api/");
+        assert_eq!(
+            url(&crawler, "/crawler/v1/links", None).as_deref(),
+            Some("http://crawler/api/v1/links")
+        );
+    }
+
+    #[test]
+    fn test_is_private() {
+        assert!(is_private("Set-Cookie"));
+        assert!(is_private("set-cookie2"));
+        assert!(!is_private("Cache-Control"));
+    }
+}
@@ -0,0 +1,182 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use errors::Error;
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome};
+use rocket::Request;
+use stream::{Limiter, LimiterError, Rate, TokenBucket};
+use telemetry::prometheus::{register_int_counter_vec, IntCounterVec};
+
+use crate::auth::principal;
+
+/// The interval between removing the buckets of principals that are no longer making
+/// requests
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+lazy_static! {
+    static ref RATE_LIMIT: IntCounterVec = register_int_counter_vec!(
+        "rate_limit_counter",
+        "Requests Subject To Rate Limits",
+        &["route", "outcome"]
+    )
+    .unwrap();
+}
+
+/// Returns the value of a `Retry-After` header for waiting `wait`, the number of
+/// seconds rounded up
+pub fn retry_after(wait: Duration) -> String {
+    let seconds = wait.as_secs() + u64::from(wait.subsec_nanos() > 0);
+    seconds.max(1).to_string()
+}
+
+struct Buckets {
+    buckets: HashMap<String, TokenBucket>,
+    last_prune: Instant,
+}
+
+/// Limits the requests each principal makes to a route to `rate` per second, allowing
+/// bursts of up to a second's worth of requests
+pub struct RateLimiter {
+    route: String,
+    rate: Rate,
+    buckets: Mutex<Buckets>,
+}
+
+impl RateLimiter {
+    pub fn new(route: &str, rate: u64) -> RateLimiter {
+        RateLimiter {
+            route: route.to_string(),
+            rate: Rate::per_second(rate),
+            buckets: Mutex::new(Buckets {
+                buckets: HashMap::new(),
+                last_prune: Instant::now(),
+            }),
+        }
+    }
+
+    /// Takes a request of `principal` from its limit, returning how long it must wait
+    /// to make the request if it is over the limit
+    pub fn take(&self, principal: &str) -> Result<(), Duration> {
+        let mut buckets = self.buckets.lock().unwrap();
+        let now = Instant::now();
+        if now.duration_since(buckets.last_prune) > PRUNE_INTERVAL {
+            buckets.buckets.retain(|_, bucket| bucket.active());
+            buckets.last_prune = now;
+        }
+
+        let rate = &self.rate;
+        let bucket = buckets
+            .buckets
+            .entry(principal.to_string())
+            .or_insert_with(|| TokenBucket::with_rate(rate.clone()));
+
+        let result = match bucket.try_take(&1) {
+            Ok(()) => Ok(()),
+            Err(LimiterError::LimitExceeded(wait)) => Err(wait),
+            Err(LimiterError::CapacityExceeded) => Err(Duration::from_secs(1)),
+        };
+
+        let outcome = if result.is_ok() {
+            "allowed"
+        } else {
+            "throttled"
+        };
+        RATE_LIMIT.with_label_values(&[&self.route, outcome]).inc();
+        result
+    }
+
+    /// Takes `request` from the limit of the principal that made it
+    pub fn take_request(&self, request: &Request<'_>) -> Result<(), Duration> {
+        self.take(&principal(request))
+    }
+}
+
+/// Returns the error of a request over its rate limit, which may be retried after
+/// `wait`
+pub fn rate_limited(wait: Duration) -> Error {
+    Error::new(429, "rate_limited", "Too many requests")
+        .with_header("Retry-After", retry_after(wait))
+}
+
+/// The rate limits of the gateway's own routes, by the name of the route
+pub struct RateLimits(HashMap<String, RateLimiter>);
+
+impl RateLimits {
+    pub fn new(rates: &HashMap<String, u64>) -> RateLimits {
+        let limiters = rates
+            .iter()
+            .map(|(route, rate)| (route.clone(), RateLimiter::new(route, *rate)))
+            .collect();
+        RateLimits(limiters)
+    }
+}
+
+/// A request guard taking the request from the rate limit of its route, if it has one,
+/// failing with `rate_limited` if the principal is over its limit
+///
+/// Routes take it as a `Result` to respond with the error, and must take it before
+/// other guards so that requests they reject are also limited
+pub struct RateLimited;
+
+#[rocket::async_trait]
+impl<'a, 'r> FromRequest<'a, 'r> for RateLimited {
+    type Error = Error;
+
+    async fn from_request(request: &'a Request<'r>) -> Outcome<RateLimited, Error> {
+        let limits = request
+            .managed_state::<RateLimits>()
+            .expect("No rate limits registered");
+        let limiter = request
+            .route()
+            .and_then(|x| x.name)
+            .and_then(|x| limits.0.get(x));
+
+        match limiter.map(|x| x.take_request(request)) {
+            Some(Err(wait)) => Outcome::Failure((Status::TooManyRequests, rate_limited(wait))),
+            _ => Outcome::Success(RateLimited),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retry_after() {
+        assert_eq!(retry_after(Duration::from_secs(0)), "1");
+        assert_eq!(retry_after(Duration::from_millis(200)), "1");
+        assert_eq!(retry_after(Duration::from_secs(2)), "2");
+        assert_eq!(retry_after(Duration::from_millis(2001)), "3");
+    }
+
+    #[test]
+    fn test_rate_limited() {
+        let error = rate_limited(Duration::from_millis(1500));
+        assert_eq!(error.status_code(), 429);
+        assert_eq!(error.headers(), &[("Retry-After", "2".to_string())]);
+    }
+
+    #[test]
+    fn test_rate_limiter() {
+        let limiter = RateLimiter::new("test_rate_limiter", 2);
+        assert!(limiter.take("a").is_ok());
+        assert!(limiter.take("a").is_ok());
+
+        let wait = limiter.take("a").unwrap_err();
+        assert!(wait > Duration::from_secs(0) && wait <= Duration::from_millis(500));
+
+        // Principals have separate limits
+        assert!(limiter.take("b").is_ok());
+
+        let count = |outcome| {
+            RATE_LIMIT
+                .with_label_values(&["test_rate_limiter", outcome])
+                .get()
+        };
+        assert_eq!(count("allowed"), 3);
+        assert_eq!(count("throttled"), 1);
+    }
+}
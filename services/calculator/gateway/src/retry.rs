@@ -0,0 +1,214 @@
+use std::sync::Mutex;
+use std::time::Duration;
+
+use futures::stream::{FuturesUnordered, StreamExt};
+use reqwest::{Method, RequestBuilder, Response};
+use stream::{Limiter, TokenBucket};
+use telemetry::prometheus::{register_int_counter_vec, IntCounterVec};
+use tokio::time::delay_for;
+
+use crate::config::RetryConfig;
+
+/// The most retries a run of requests can add to a budget, so that a quiet period
+/// doesn't allow a burst of retries once an upstream starts failing
+const MAX_BALANCE: f64 = 100.;
+
+lazy_static! {
+    static ref RETRIES: IntCounterVec =
+        register_int_counter_vec!("retry_counter", "Upstream Retries", &["route", "kind"]).unwrap();
+}
+
+/// Returns true if a request with `method` can be sent again without changing its
+/// effect
+pub fn is_idempotent(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::OPTIONS | Method::PUT | Method::DELETE | Method::TRACE
+    )
+}
+
+/// Returns true if the result of an attempt is a failure that another attempt might
+/// not have, such as the replica it reached being down
+fn is_retryable(result: &Result<Response, reqwest::Error>) -> bool {
+    match result {
+        Ok(response) => matches!(response.status().as_u16(), 502 | 503 | 504),
+        Err(e) => e.is_connect() || e.is_timeout(),
+    }
+}
+
+struct Balance {
+    balance: f64,
+    reserve: Option<TokenBucket>,
+}
+
+/// Limits the retries made to a fraction of the requests made, along with a reserve
+/// of retries per second, so that an upstream failing every request isn't sent
+/// several times its usual load
+pub struct RetryBudget {
+    ratio: f64,
+    balance: Mutex<Balance>,
+}
+
+impl RetryBudget {
+    pub fn new(ratio: f64, reserve: u64) -> RetryBudget {
+        RetryBudget {
+            ratio,
+            balance: Mutex::new(Balance {
+                balance: 0.,
+                reserve: Some(reserve)
+                    .filter(|x| *x > 0)
+                    .map(TokenBucket::per_second),
+            }),
+        }
+    }
+
+    /// Records a request, adding to the retries that may be made
+    pub fn deposit(&self) {
+        let mut balance = self.balance.lock().unwrap();
+        balance.balance = (balance.balance + self.ratio).min(MAX_BALANCE);
+    }
+
+    /// Takes a retry from the budget, returning false if there are none left
+    pub fn withdraw(&self) -> bool {
+        let mut balance = self.balance.lock().unwrap();
+        if balance.balance >= 1. {
+            balance.balance -= 1.;
+            return true;
+        }
+        match &mut balance.reserve {
+            Some(reserve) => reserve.try_take(&1).is_ok(),
+            None => false,
+        }
+    }
+}
+
+/// How the requests to a route are retried and hedged
+pub struct RetryPolicy {
+    route: String,
+    /// The maximum number of attempts of a request, including the first
+    attempts: u32,
+    /// The delay before the first retry, doubled for each retry after
+    backoff: Duration,
+    /// The delay after which a second attempt is made if the first hasn't responded
+    hedge: Option<Duration>,
+    budget: RetryBudget,
+}
+
+impl RetryPolicy {
+    pub fn new(route: &str, config: &RetryConfig) -> RetryPolicy {
+        RetryPolicy {
+            route: route.to_string(),
+            attempts: config.attempts.max(1),
+            backoff: Duration::from_millis(config.backoff),
+            hedge: config.hedge.map(Duration::from_millis),
+            budget: RetryBudget::new(config.budget, config.reserve),
+        }
+    }
+
+    /// Returns the delay before the retry following `attempts` attempts
+    fn backoff(&self, attempts: u32) -> Duration {
+        self.backoff * 2_u32.saturating_pow(attempts.saturating_sub(1))
+    }
+
+    /// Takes a retry of `kind` from the budget, returning false if there are none left
+    fn withdraw(&self, kind: &str) -> bool {
+        let allowed = self.budget.withdraw();
+        let kind = if allowed { kind } else { "exhausted" };
+        RETRIES.with_label_values(&[&self.route, kind]).inc();
+        allowed
+    }
+
+    /// Sends `request`, which must be idempotent and able to be cloned, retrying it
+    /// while it fails with an error another attempt might not have, and hedging it if
+    /// it doesn't respond quickly enough, for as long as the budget allows
+    ///
+    /// Returns the first response that isn't retried, or that of the last attempt
+    pub async fn send(&self, request: RequestBuilder) -> Result<Response, reqwest::Error> {
+        self.budget.deposit();
+        let attempt = || {
+            request
+                .try_clone()
+                .expect("Request can't be retried")
+                .send()
+        };
+
+        let mut pending = FuturesUnordered::new();
+        pending.push(attempt());
+        let mut attempts = 1;
+
+        let hedge = delay_for(self.hedge.unwrap_or_default());
+        tokio::pin!(hedge);
+        let mut hedged = self.hedge.is_none();
+
+        loop {
+            tokio::select! {
+                Some(result) = pending.next() => {
+                    if !is_retryable(&result) {
+                        return result;
+                    }
+                    // A hedged attempt still in flight may yet succeed
+                    if !pending.is_empty() {
+                        continue;
+                    }
+                    if attempts >= self.attempts || !self.withdraw("retry") {
+                        return result;
+                    }
+
+                    delay_for(self.backoff(attempts)).await;
+                    pending.push(attempt());
+                    attempts += 1;
+                    // A retry isn't also hedged
+                    hedged = true;
+                }
+                _ = &mut hedge, if !hedged => {
+                    hedged = true;
+                    if attempts < self.attempts && self.withdraw("hedge") {
+                        pending.push(attempt());
+                        attempts += 1;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_budget() {
+        let budget = RetryBudget::new(0.5, 0);
+        assert!(!budget.withdraw());
+
+        budget.deposit();
+        budget.deposit();
+        assert!(budget.withdraw());
+        assert!(!budget.withdraw());
+
+        // Deposits are capped
+        (0..1000).for_each(|_| budget.deposit());
+        assert_eq!((0..1000).filter(|_| budget.withdraw()).count(), 100);
+
+        // The reserve allows retries without deposits
+        let budget = RetryBudget::new(0., 2);
+        assert_eq!((0..4).filter(|_| budget.withdraw()).count(), 2);
+    }
+
+    #[test]
+    fn test_policy() {
+        let config = RetryConfig {
+            attempts: 4,
+            backoff: 10,
+            ..Default::default()
+        };
+        let policy = RetryPolicy::new("test_policy", &config);
+        let backoff: Vec<_> = (1..4).map(|x| policy.backoff(x).as_millis()).collect();
+        assert_eq!(backoff, vec![10, 20, 40]);
+
+        assert!(is_idempotent(&Method::GET));
+        assert!(is_idempotent(&Method::PUT));
+        assert!(!is_idempotent(&Method::POST));
+        assert!(!is_idempotent(&Method::PATCH));
+    }
+}
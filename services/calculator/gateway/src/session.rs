@@ -0,0 +1,340 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use futures::{SinkExt, StreamExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_tungstenite::tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tokio_tungstenite::tungstenite::http::{HeaderValue, StatusCode};
+use tokio_tungstenite::tungstenite::protocol::WebSocketConfig;
+use tokio_tungstenite::tungstenite::{Error, Message};
+use tracing::warn;
+
+use calculator_client::ComputeValue;
+use errors::Error as ApiError;
+use flags::Flags;
+use jwt::{JwtClaims, Scope, Validator};
+use telemetry::Measure;
+
+use crate::api::{eval, EvaluateResult};
+use crate::client::CalculatorClient;
+use crate::config::{LoggingConfig, SessionConfig};
+use crate::expression::parse_statement;
+use crate::logging::log_handshake;
+use crate::ratelimit::{rate_limited, retry_after, RateLimiter};
+use crate::switch::{route_disabled, route_flag};
+
+lazy_static! {
+    static ref SESSION_MEASURE: Measure = Measure::new("session", "evaluate");
+}
+
+/// The name of sessions as a route of the gateway, whose rate limit in `rate_limits`
+/// limits the handshakes and statements of each client, and whose flag turns them off
+pub const SESSION_ROUTE: &str = "session";
+
+/// Returns the authorization of the handshake `request` and its validated claims, from
+/// either its `Authorization` header or its `access_token` query parameter, as browsers
+/// can't set headers on WebSocket requests
+fn authorize(request: &Request, validator: &Validator) -> Option<(String, JwtClaims<Scope>)> {
+    let header = request
+        .headers()
+        .get("authorization")
+        .and_then(|x| x.to_str().ok())
+        .map(ToString::to_string);
+
+    let query = request.uri().query().and_then(|query| {
+        query
+            .split('&')
+            .find_map(|x| x.strip_prefix("access_token="))
+            .map(|x| format!("Bearer {}", x))
+    });
+
+    let authorization = header.or(query)?;
+    let claims = jwt::extract_jwt::<Scope>(Some(&authorization), validator).ok()?;
+    Some((authorization, claims))
+}
+
+/// Returns the response refusing a handshake with `status`
+fn refuse(status: StatusCode) -> ErrorResponse {
+    let mut response = ErrorResponse::new(None);
+    *response.status_mut() = status;
+    response
+}
+
+/// Counts the sessions open at once
+#[derive(Clone, Default)]
+struct OpenSessions(Arc<AtomicUsize>);
+
+/// A session counted by `OpenSessions`, until it is dropped
+struct OpenSession(Arc<AtomicUsize>);
+
+impl OpenSessions {
+    /// Counts a new session, returning None if `max` sessions are already open
+    fn open(&self, max: usize) -> Option<OpenSession> {
+        let open = self.0.fetch_add(1, Ordering::SeqCst);
+        let session = OpenSession(self.0.clone());
+        if open < max {
+            Some(session)
+        } else {
+            None
+        }
+    }
+}
+
+impl Drop for OpenSession {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// What the sessions of the gateway share
+pub struct Sessions {
+    config: SessionConfig,
+    logging: LoggingConfig,
+    validator: Validator,
+    client: Arc<CalculatorClient>,
+    flags: Arc<Flags>,
+    /// Limits the handshakes and statements of each client, as it limits the requests
+    /// of a route
+    limiter: Option<RateLimiter>,
+    open: OpenSessions,
+}
+
+impl Sessions {
+    /// Creates the sessions of the gateway, where `rate_limit` is the maximum number of
+    /// handshakes and statements per second of each client, None to not limit them
+    pub fn new(
+        config: SessionConfig,
+        logging: LoggingConfig,
+        validator: Validator,
+        client: Arc<CalculatorClient>,
+        flags: Arc<Flags>,
+        rate_limit: Option<u64>,
+    ) -> Sessions {
+        Sessions {
+            config,
+            logging,
+            validator,
+            client,
+            flags,
+            limiter: rate_limit.map(|rate| RateLimiter::new(SESSION_ROUTE, rate)),
+            open: OpenSessions::default(),
+        }
+    }
+
+    /// Returns true if sessions are turned on for the client `cid`
+    fn is_enabled(&self, cid: Option<&str>) -> bool {
+        let flag = route_flag(SESSION_ROUTE);
+        self.flags.lookup(&flag, cid).unwrap_or(true)
+    }
+
+    /// Takes a handshake or statement of `principal` from its rate limit, returning how
+    /// long it must wait if it is over the limit
+    fn take(&self, principal: &str) -> Result<(), Duration> {
+        self.limiter.as_ref().map_or(Ok(()), |x| x.take(principal))
+    }
+
+    /// Decides whether to accept the handshake `request` from `peer`, returning its
+    /// authorization and claims if it is accepted
+    ///
+    /// As for the requests of a route, handshakes are refused if sessions are turned off
+    /// for their client before they are rate limited, and are taken from the rate limit
+    /// of their client before they are authorized, so that handshakes refused for other
+    /// reasons are also limited
+    fn accept(
+        &self,
+        request: &Request,
+        peer: SocketAddr,
+        full: bool,
+    ) -> Result<(String, JwtClaims<Scope>), ErrorResponse> {
+        let authorized = authorize(request, &self.validator);
+        let cid = authorized.as_ref().map(|(_, claims)| claims.cid.as_str());
+        if !self.is_enabled(cid) {
+            return Err(refuse(StatusCode::SERVICE_UNAVAILABLE));
+        }
+
+        let principal = match cid {
+            Some(cid) => format!("cid:{}", cid),
+            None => format!("ip:{}", peer.ip()),
+        };
+        if let Err(wait) = self.take(&principal) {
+            let mut response = refuse(StatusCode::TOO_MANY_REQUESTS);
+            let retry_after = HeaderValue::from_str(&retry_after(wait))
+                .expect("Retry-After is a valid header value");
+            response.headers_mut().insert("Retry-After", retry_after);
+            return Err(response);
+        }
+
+        let authorized = authorized.ok_or_else(|| refuse(StatusCode::UNAUTHORIZED))?;
+        if full {
+            return Err(refuse(StatusCode::SERVICE_UNAVAILABLE));
+        }
+        Ok(authorized)
+    }
+}
+
+/// Returns true if `name` can be assigned in `env`, which is either already a variable
+/// or a new one within the `max` variables of a session
+fn is_assignable<V>(env: &HashMap<String, V>, name: &str, max: usize) -> bool {
+    env.contains_key(name) || env.len() < max
+}
+
+/// Evaluates the statement `input`, assigning its value to any variable it names in `env`,
+/// which may have at most `max_variables` variables
+async fn execute(
+    authorization: &str,
+    client: &Arc<CalculatorClient>,
+    env: &mut HashMap<String, ComputeValue>,
+    max_variables: usize,
+    input: &str,
+) -> Result<ComputeValue, ApiError> {
+    let statement = parse_statement(input.trim())?;
+    if let Some(name) = &statement.name {
+        if !is_assignable(env, name, max_variables) {
+            let message = format!("A session may have at most {} variables", max_variables);
+            return Err(ApiError::new(422, "too_many_variables", message));
+        }
+    }
+
+    let expr = statement.expr.bind(env);
+    let value = eval(authorization.to_string(), client.clone(), &expr).await?;
+
+    if let Some(name) = statement.name {
+        env.insert(name, value);
+    }
+    Ok(value)
+}
+
+async fn session(
+    stream: TcpStream,
+    peer: SocketAddr,
+    sessions: Arc<Sessions>,
+) -> Result<(), Error> {
+    let start = Instant::now();
+    let open = sessions.open.open(sessions.config.max_sessions);
+
+    let mut authorization = None;
+    let callback = |request: &Request, response: Response| {
+        let accepted = sessions.accept(request, peer, open.is_none());
+        let (status, claims) = match &accepted {
+            Ok((_, claims)) => (response.status(), Some(claims)),
+            Err(refused) => (refused.status(), None),
+        };
+        log_handshake(&sessions.logging, request, status, start, claims);
+
+        let (accepted, claims) = accepted?;
+        authorization = Some((accepted, claims.cid));
+        Ok(response)
+    };
+
+    // Messages are limited in size, so that a client can't exhaust the gateway's memory
+    let max_bytes = sessions.config.max_message_bytes;
+    let config = WebSocketConfig {
+        max_send_queue: None,
+        max_message_size: Some(max_bytes),
+        max_frame_size: Some(max_bytes),
+    };
+
+    let mut socket =
+        tokio_tungstenite::accept_hdr_async_with_config(stream, callback, Some(config)).await?;
+    let (authorization, cid) = authorization.expect("Session accepted without authorization");
+    let principal = format!("cid:{}", cid);
+
+    let mut env = HashMap::new();
+    while let Some(message) = socket.next().await {
+        let input = match message? {
+            Message::Text(input) => input,
+            Message::Close(_) => break,
+            // Pings are answered by the socket
+            _ => continue,
+        };
+
+        // Sessions already open end once they are turned off for the client
+        if !sessions.is_enabled(Some(&cid)) {
+            socket.send(respond(Err(route_disabled()))).await?;
+            socket.send(Message::Close(None)).await?;
+            break;
+        }
+
+        // Each statement is evaluated by the calculator, so is limited as a request
+        let result = match sessions.take(&principal) {
+            Ok(()) => {
+                let max_variables = sessions.config.max_variables;
+                let executed = execute(
+                    &authorization,
+                    &sessions.client,
+                    &mut env,
+                    max_variables,
+                    &input,
+                );
+                SESSION_MEASURE.stats(executed).await
+            }
+            Err(wait) => Err(rate_limited(wait)),
+        };
+        socket.send(respond(result)).await?;
+    }
+    Ok(())
+}
+
+/// Returns the message answering a statement with `result`
+fn respond(result: Result<ComputeValue, ApiError>) -> Message {
+    let response =
+        serde_json::to_string(&EvaluateResult::from(result)).expect("Failed to serialize result");
+    Message::Text(response)
+}
+
+/// Accepts WebSocket sessions from `listener`, in each of which every text message is
+/// a statement to evaluate, such as `x = 1 + 2` or `x * 2`, and is answered with its
+/// result
+///
+/// Each session has its own variables, which last as long as the session
+pub async fn serve(mut listener: TcpListener, sessions: Sessions) {
+    let sessions = Arc::new(sessions);
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                warn!(error = %e, "Failed to accept session");
+                continue;
+            }
+        };
+
+        let sessions = sessions.clone();
+        tokio::spawn(async move {
+            if let Err(e) = session(stream, peer, sessions).await {
+                warn!(%peer, error = %e, "Session failed");
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_open_sessions() {
+        let sessions = OpenSessions::default();
+        let a = sessions.open(2).unwrap();
+        let _b = sessions.open(2).unwrap();
+        assert!(sessions.open(2).is_none());
+
+        drop(a);
+        assert!(sessions.open(2).is_some());
+    }
+
+    #[test]
+    fn test_is_assignable() {
+        let mut env = HashMap::new();
+        env.insert("x".to_string(), ());
+        assert!(is_assignable(&env, "y", 2));
+
+        env.insert("y".to_string(), ());
+        assert!(!is_assignable(&env, "z", 2));
+
+        // Variables can be assigned again once the limit is reached
+        assert!(is_assignable(&env, "x", 2));
+    }
+}
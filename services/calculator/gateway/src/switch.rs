@@ -0,0 +1,71 @@
+//! Kill switches, turning routes off without a deploy
+//!
+//! The gateway's own routes are turned off by the flag `gateway.route.<name>`, such as
+//! `gateway.route.compute`, and proxied routes by the flag they are configured with.
+//! Routes are on unless their flag is configured, and the subject of a flag is the
+//! client of the request's bearer token
+
+use std::sync::Arc;
+
+use errors::Error;
+use flags::Flags;
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome};
+use rocket::Request;
+
+use crate::auth::claims;
+
+/// Returns the name of the flag of the gateway's own route named `route`
+pub fn route_flag(route: &str) -> String {
+    format!("gateway.route.{}", route)
+}
+
+/// Returns the error of a request to a route that is turned off
+pub fn route_disabled() -> Error {
+    Error::new(503, "route_disabled", "The route is turned off")
+}
+
+/// Returns true if `flag` is on for the client of `request`
+pub fn is_enabled(flags: &Flags, flag: &str, request: &Request<'_>) -> bool {
+    let subject = claims(request).map(|x| x.cid.as_str());
+    flags.lookup(flag, subject).unwrap_or(true)
+}
+
+/// A request guard failing with `route_disabled` if the flag of the route is off
+///
+/// Routes take it as a `Result` to respond with the error, and must take it first so
+/// that requests to a route that is turned off aren't rate limited
+pub struct Enabled;
+
+#[rocket::async_trait]
+impl<'a, 'r> FromRequest<'a, 'r> for Enabled {
+    type Error = Error;
+
+    async fn from_request(request: &'a Request<'r>) -> Outcome<Enabled, Error> {
+        let flags = request
+            .managed_state::<Arc<Flags>>()
+            .expect("No flags registered");
+        let enabled = match request.route().and_then(|x| x.name) {
+            Some(name) => is_enabled(flags, &route_flag(name), request),
+            None => true,
+        };
+
+        if enabled {
+            Outcome::Success(Enabled)
+        } else {
+            Outcome::Failure((Status::ServiceUnavailable, route_disabled()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_route_disabled() {
+        let error = route_disabled();
+        assert_eq!(error.status_code(), 503);
+        assert_eq!(route_flag("compute"), "gateway.route.compute");
+    }
+}
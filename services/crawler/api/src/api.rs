@@ -1,53 +1,92 @@
 use std::rc::Rc;
 
-use actix_http::ResponseBuilder;
-use actix_web::http::{header, StatusCode};
-use actix_web::{error, web, HttpResponse, Responder};
-use derive_more::Display;
-use serde::Deserialize;
+use actix_web::http::header;
+use actix_web::{web, HttpResponse, Responder};
+use serde::{Deserialize, Serialize};
 
+use errors::Error;
 use log::error;
-use shared::dao::LinkDao;
+use shared::dao::{Crawl, CrawlFilter, Job, JobDao, LinkDao, Progress};
+use shared::filter::UrlFilter;
+use shared::graph::{BrokenLink, Graph, GraphFilter};
+use shared::jobs::start_job;
 use shared::metrics::MetricsService;
-use shared::mq::MessageQueue;
+use shared::mq::{CrawlLimits, DeadLetter, Message, MessageQueue};
+use shared::normalize::normalize_str;
+use shared::search::{SearchHit, SearchIndex};
+
+/// The number of results returned by a search that does not specify a limit
+const DEFAULT_SEARCH_LIMIT: usize = 10;
+
+/// The maximum number of results a search may return
+const MAX_SEARCH_LIMIT: usize = 100;
+
+/// The maximum number of pages in an exported link graph
+const MAX_GRAPH_NODES: usize = 10_000;
+
+/// The number of crawls listed by a request that does not specify a limit
+const DEFAULT_LINKS_LIMIT: usize = 100;
+
+/// The maximum number of crawls a request may list
+const MAX_LINKS_LIMIT: usize = 1000;
+
+/// The number of dead letters returned or requeued by a request that does not specify a limit
+const DEFAULT_DEAD_LETTER_LIMIT: usize = 100;
+
+/// The maximum number of dead letters a request may return or requeue
+const MAX_DEAD_LETTER_LIMIT: usize = 1000;
 
 #[derive(Clone)]
 pub(crate) struct ApiState {
     dao: Rc<dyn LinkDao>,
+    jobs: Rc<dyn JobDao>,
     publisher: Rc<dyn MessageQueue>,
+    /// The index of crawled pages, None if pages are not indexed
+    search: Option<Rc<dyn SearchIndex>>,
+    limits: CrawlLimits,
 }
 
 impl ApiState {
-    pub fn new(dao: Box<dyn LinkDao>, publisher: Box<dyn MessageQueue>) -> ApiState {
+    pub fn new(
+        dao: Box<dyn LinkDao>,
+        jobs: Box<dyn JobDao>,
+        publisher: Box<dyn MessageQueue>,
+        search: Option<Box<dyn SearchIndex>>,
+        limits: CrawlLimits,
+    ) -> ApiState {
         ApiState {
             dao: dao.into(),
+            jobs: jobs.into(),
             publisher: publisher.into(),
+            search: search.map(Into::into),
+            limits,
         }
     }
-}
 
-#[derive(Debug, Display)]
-enum ApiError {
-    #[display(fmt = "An internal error occurred. Please try again later.")]
-    InternalError,
-}
-
-impl error::ResponseError for ApiError {
-    fn status_code(&self) -> StatusCode {
-        match *self {
-            ApiError::InternalError { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+    /// Returns the limits of a crawl requesting `depth` and `pages`
+    fn limits(&self, depth: Option<u32>, pages: Option<u64>) -> CrawlLimits {
+        CrawlLimits {
+            depth: limit(depth, self.limits.depth),
+            pages: limit(pages, self.limits.pages),
         }
     }
-    fn error_response(&self) -> HttpResponse {
-        ResponseBuilder::new(self.status_code())
-            .set_header(header::CONTENT_TYPE, "text/html; charset=utf-8")
-            .body(self.to_string())
-    }
 }
 
 #[derive(Deserialize)]
 struct IndexRequest {
     url: String,
+    /// The maximum number of links to follow from `url`
+    max_depth: Option<u32>,
+    /// The maximum number of pages to crawl
+    max_pages: Option<u64>,
+}
+
+/// Returns the requested limit, capped at the configured limit if any
+fn limit<T: Ord>(requested: Option<T>, configured: Option<T>) -> Option<T> {
+    match (requested, configured) {
+        (Some(requested), Some(configured)) => Some(requested.min(configured)),
+        (requested, configured) => requested.or(configured),
+    }
 }
 
 async fn index_post(
@@ -57,19 +96,493 @@ async fn index_post(
 ) -> impl Responder {
     metrics
         .stats("index_post".to_string(), move || async move {
+            let limits = state.limits(req.max_depth, req.max_pages);
+            let url = normalize_str(&req.url).map_err(|_| Error::bad_request("invalid URL"))?;
+
             state
                 .publisher
-                .queue_index(req.url.to_string())
+                .queue_index(Message::seed(url, limits))
                 .await
                 .map(|_| HttpResponse::NoContent())
-                .map_err(|e| {
-                    error!("index_post: {}", e);
-                    ApiError::InternalError
-                })
+                .map_err(|e| Error::internal(format!("index_post: {}", e)))
+        })
+        .await
+}
+
+#[derive(Deserialize)]
+struct CreateJobRequest {
+    seeds: Vec<String>,
+    /// The maximum number of links to follow from each seed
+    max_depth: Option<u32>,
+    /// The maximum number of pages to crawl across all seeds
+    max_pages: Option<u64>,
+    /// The age in seconds beyond which pages crawled before are crawled again
+    max_age_secs: Option<u64>,
+    /// The patterns one of which a link must match to be followed
+    #[serde(default)]
+    include: Vec<String>,
+    /// The patterns of links not to follow
+    #[serde(default)]
+    exclude: Vec<String>,
+    /// Whether to render pages in a headless browser before extracting links
+    #[serde(default)]
+    render: bool,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "snake_case")]
+enum JobStatus {
+    Running,
+    Stopped,
+    Finished,
+}
+
+#[derive(Serialize)]
+struct JobResponse {
+    id: String,
+    status: JobStatus,
+    seeds: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_depth: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_pages: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_age_secs: Option<u64>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    include: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    exclude: Vec<String>,
+    render: bool,
+    queued: i64,
+    crawled: i64,
+    failed: i64,
+}
+
+impl From<Job> for JobResponse {
+    fn from(job: Job) -> Self {
+        let status = if job.stopped {
+            JobStatus::Stopped
+        } else if job.running() {
+            JobStatus::Running
+        } else {
+            JobStatus::Finished
+        };
+
+        JobResponse {
+            id: job.job_id,
+            status,
+            seeds: job.seeds,
+            max_depth: job.max_depth,
+            max_pages: job.max_pages,
+            max_age_secs: job.max_age_secs,
+            include: job.include,
+            exclude: job.exclude,
+            render: job.render,
+            queued: job.queued,
+            crawled: job.crawled,
+            failed: job.failed,
+        }
+    }
+}
+
+async fn jobs_post(
+    metrics: web::Data<MetricsService>,
+    state: web::Data<ApiState>,
+    req: web::Json<CreateJobRequest>,
+) -> impl Responder {
+    metrics
+        .stats("jobs_post".to_string(), move || async move {
+            let req = req.into_inner();
+            if req.seeds.is_empty() {
+                return Err(Error::bad_request("no seed URLs"));
+            }
+
+            let seeds = req
+                .seeds
+                .iter()
+                .map(|x| normalize_str(x))
+                .collect::<Result<_, _>>()
+                .map_err(|_| Error::bad_request("invalid seed URL"))?;
+
+            if UrlFilter::new(&req.include, &req.exclude).is_err() {
+                return Err(Error::bad_request("invalid pattern"));
+            }
+
+            let limits = state.limits(req.max_depth, req.max_pages);
+            let mut job = Job::new(seeds, limits, req.max_age_secs);
+            job.include = req.include;
+            job.exclude = req.exclude;
+            job.render = req.render;
+
+            let job = start_job(state.jobs.as_ref(), state.publisher.as_ref(), job)
+                .await
+                .map_err(|e| Error::internal(format!("jobs_post: {}", e)))?;
+
+            Ok(HttpResponse::Created().json(JobResponse::from(job)))
+        })
+        .await
+}
+
+async fn jobs_get(
+    metrics: web::Data<MetricsService>,
+    state: web::Data<ApiState>,
+    job_id: web::Path<String>,
+) -> impl Responder {
+    metrics
+        .stats("jobs_get".to_string(), move || async move {
+            let job = state
+                .jobs
+                .get_job(&job_id)
+                .await
+                .map_err(|e| Error::internal(format!("jobs_get: {}", e)))?;
+
+            match job {
+                Some(job) => Ok(HttpResponse::Ok().json(JobResponse::from(job))),
+                None => Err(Error::not_found()),
+            }
+        })
+        .await
+}
+
+async fn jobs_stop(
+    metrics: web::Data<MetricsService>,
+    state: web::Data<ApiState>,
+    job_id: web::Path<String>,
+) -> impl Responder {
+    metrics
+        .stats("jobs_stop".to_string(), move || async move {
+            let stopped = state
+                .jobs
+                .stop_job(&job_id)
+                .await
+                .map_err(|e| Error::internal(format!("jobs_stop: {}", e)))?;
+
+            if stopped {
+                Ok(HttpResponse::NoContent())
+            } else {
+                Err(Error::not_found())
+            }
+        })
+        .await
+}
+
+#[derive(Deserialize)]
+struct SearchQuery {
+    q: String,
+    /// The maximum number of results to return
+    limit: Option<usize>,
+}
+
+#[derive(Serialize)]
+struct SearchResponse {
+    results: Vec<SearchHit>,
+}
+
+async fn search_get(
+    metrics: web::Data<MetricsService>,
+    state: web::Data<ApiState>,
+    query: web::Query<SearchQuery>,
+) -> impl Responder {
+    metrics
+        .stats("search_get".to_string(), move || async move {
+            let search = state
+                .search
+                .as_ref()
+                .ok_or_else(|| Error::unavailable("Search is not enabled"))?;
+
+            let q = query.q.trim();
+            if q.is_empty() {
+                return Err(Error::bad_request("empty query"));
+            }
+
+            let limit = query
+                .limit
+                .unwrap_or(DEFAULT_SEARCH_LIMIT)
+                .min(MAX_SEARCH_LIMIT);
+
+            let results = search
+                .search(q, limit)
+                .await
+                .map_err(|e| Error::internal(format!("search_get: {}", e)))?;
+
+            Ok(HttpResponse::Ok().json(SearchResponse { results }))
+        })
+        .await
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum GraphFormat {
+    Dot,
+    GraphML,
+}
+
+impl Default for GraphFormat {
+    fn default() -> GraphFormat {
+        GraphFormat::Dot
+    }
+}
+
+#[derive(Deserialize)]
+struct GraphQuery {
+    /// The URL of the page to export the graph of the pages reachable from
+    root: String,
+    #[serde(default)]
+    format: GraphFormat,
+    /// The maximum number of links to follow from `root`
+    depth: Option<u32>,
+    /// The domain pages must be on, or a subdomain of
+    domain: Option<String>,
+}
+
+async fn graph_get(
+    metrics: web::Data<MetricsService>,
+    state: web::Data<ApiState>,
+    query: web::Query<GraphQuery>,
+) -> impl Responder {
+    metrics
+        .stats("graph_get".to_string(), move || async move {
+            let root = normalize_str(&query.root).map_err(|_| Error::bad_request("invalid URL"))?;
+            let filter = GraphFilter::new(query.depth, query.domain.as_deref());
+
+            let graph = Graph::load(state.dao.as_ref(), &root, &filter, MAX_GRAPH_NODES)
+                .await
+                .map_err(|e| Error::internal(format!("graph_get: {}", e)))?;
+
+            let (content_type, body) = match query.format {
+                GraphFormat::Dot => ("text/vnd.graphviz; charset=utf-8", graph.to_dot()),
+                GraphFormat::GraphML => {
+                    ("application/graphml+xml; charset=utf-8", graph.to_graphml())
+                }
+            };
+
+            let mut response = HttpResponse::Ok();
+            response.set_header(header::CONTENT_TYPE, content_type);
+            if graph.truncated {
+                response.set_header("X-Graph-Truncated", "true");
+            }
+            Ok(response.body(body))
+        })
+        .await
+}
+
+#[derive(Deserialize)]
+struct BrokenLinksQuery {
+    /// The URL of the page to report the broken links reachable from
+    root: String,
+    /// The maximum number of links to follow from `root`
+    depth: Option<u32>,
+    /// The domain pages must be on, or a subdomain of
+    domain: Option<String>,
+}
+
+#[derive(Serialize)]
+struct BrokenLinksResponse {
+    broken: Vec<BrokenLink>,
+    /// True if pages were left out to stay within the maximum number of pages checked
+    truncated: bool,
+}
+
+async fn broken_links_get(
+    metrics: web::Data<MetricsService>,
+    state: web::Data<ApiState>,
+    query: web::Query<BrokenLinksQuery>,
+) -> impl Responder {
+    metrics
+        .stats("broken_links_get".to_string(), move || async move {
+            let root = normalize_str(&query.root).map_err(|_| Error::bad_request("invalid URL"))?;
+            let filter = GraphFilter::new(query.depth, query.domain.as_deref());
+            let dao = state.dao.as_ref();
+
+            let graph = Graph::load(dao, &root, &filter, MAX_GRAPH_NODES)
+                .await
+                .map_err(|e| Error::internal(format!("broken_links_get: {}", e)))?;
+
+            let broken = graph
+                .broken_links(dao)
+                .await
+                .map_err(|e| Error::internal(format!("broken_links_get: {}", e)))?;
+
+            Ok(HttpResponse::Ok().json(BrokenLinksResponse {
+                broken,
+                truncated: graph.truncated,
+            }))
+        })
+        .await
+}
+
+#[derive(Deserialize)]
+struct LinksQuery {
+    /// The host of the URLs to list, not including subdomains
+    domain: Option<String>,
+    /// The HTTP status of the crawls to list
+    status: Option<u16>,
+    limit: Option<usize>,
+    /// The cursor returned by the previous request, to list the crawls that follow
+    cursor: Option<String>,
+}
+
+#[derive(Serialize)]
+struct LinkResponse {
+    url: String,
+    crawled_at: u64,
+    status: Option<u16>,
+    /// The URL the page redirects to, if any
+    redirect: Option<String>,
+    title: Option<String>,
+    description: Option<String>,
+}
+
+impl LinkResponse {
+    fn new(url: String, crawl: Crawl) -> LinkResponse {
+        LinkResponse {
+            url,
+            crawled_at: crawl.crawled_at,
+            status: crawl.status,
+            redirect: crawl.redirect,
+            title: crawl.metadata.title,
+            description: crawl.metadata.description,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct LinksResponse {
+    links: Vec<LinkResponse>,
+    /// The cursor to list the crawls that follow with, None if there are none
+    cursor: Option<String>,
+}
+
+async fn links_get(
+    metrics: web::Data<MetricsService>,
+    state: web::Data<ApiState>,
+    query: web::Query<LinksQuery>,
+) -> impl Responder {
+    metrics
+        .stats("links_get".to_string(), move || async move {
+            let limit = query.limit.unwrap_or(DEFAULT_LINKS_LIMIT);
+            if limit == 0 {
+                return Err(Error::bad_request("limit must be positive"));
+            }
+
+            let filter = CrawlFilter {
+                domain: query.domain.as_deref().map(str::to_ascii_lowercase),
+                status: query.status,
+            };
+            let listing = state
+                .dao
+                .list_crawls(&filter, limit.min(MAX_LINKS_LIMIT), query.cursor.as_deref())
+                .await
+                .map_err(|e| Error::internal(format!("links_get: {}", e)))?;
+
+            let links = listing.crawls.into_iter();
+            Ok(HttpResponse::Ok().json(LinksResponse {
+                links: links
+                    .map(|(url, crawl)| LinkResponse::new(url, crawl))
+                    .collect(),
+                cursor: listing.cursor,
+            }))
+        })
+        .await
+}
+
+#[derive(Deserialize)]
+struct DeadLetterQuery {
+    /// The maximum number of dead letters to return or requeue
+    limit: Option<usize>,
+}
+
+impl DeadLetterQuery {
+    fn limit(&self) -> usize {
+        self.limit
+            .unwrap_or(DEFAULT_DEAD_LETTER_LIMIT)
+            .min(MAX_DEAD_LETTER_LIMIT)
+    }
+}
+
+#[derive(Serialize)]
+struct DeadLettersResponse {
+    dead_letters: Vec<DeadLetter>,
+}
+
+#[derive(Serialize)]
+struct RequeueResponse {
+    requeued: usize,
+}
+
+async fn dead_letters_get(
+    metrics: web::Data<MetricsService>,
+    state: web::Data<ApiState>,
+    query: web::Query<DeadLetterQuery>,
+) -> impl Responder {
+    metrics
+        .stats("dead_letters_get".to_string(), move || async move {
+            let dead_letters = state
+                .publisher
+                .dead_letters(query.limit())
+                .await
+                .map_err(|e| Error::internal(format!("dead_letters_get: {}", e)))?;
+
+            Ok(HttpResponse::Ok().json(DeadLettersResponse { dead_letters }))
+        })
+        .await
+}
+
+async fn dead_letters_requeue(
+    metrics: web::Data<MetricsService>,
+    state: web::Data<ApiState>,
+    query: web::Query<DeadLetterQuery>,
+) -> impl Responder {
+    metrics
+        .stats("dead_letters_requeue".to_string(), move || async move {
+            let requeued = state
+                .publisher
+                .requeue_dead_letters(query.limit())
+                .await
+                .map_err(|e| Error::internal(format!("dead_letters_requeue: {}", e)))?;
+
+            // Requeued URLs are no longer failed, but queued once more
+            for job_id in requeued.iter().filter_map(|x| x.job_id.as_ref()) {
+                let progress = Progress {
+                    queued: 1,
+                    failed: -1,
+                    ..Default::default()
+                };
+                if let Err(e) = state.jobs.record_progress(job_id, progress).await {
+                    error!("dead_letters_requeue: {}", e);
+                }
+            }
+
+            Ok(HttpResponse::Ok().json(RequeueResponse {
+                requeued: requeued.len(),
+            }))
         })
         .await
 }
 
+/// Returns the Prometheus metrics of the process
+async fn metrics_get() -> impl Responder {
+    telemetry::encode()
+        .map(|metrics| {
+            HttpResponse::Ok()
+                .set_header(header::CONTENT_TYPE, "text/plain; version=0.0.4")
+                .body(metrics)
+        })
+        .map_err(|e| Error::internal(format!("metrics_get: {}", e)))
+}
+
 pub fn api_factory(cfg: &mut web::ServiceConfig) {
-    cfg.service(web::resource("/index").route(web::post().to(index_post)));
+    cfg.service(web::resource("/index").route(web::post().to(index_post)))
+        .service(web::resource("/jobs").route(web::post().to(jobs_post)))
+        .service(web::resource("/jobs/{id}").route(web::get().to(jobs_get)))
+        .service(web::resource("/jobs/{id}/stop").route(web::post().to(jobs_stop)))
+        .service(web::resource("/search").route(web::get().to(search_get)))
+        .service(web::resource("/graph").route(web::get().to(graph_get)))
+        .service(web::resource("/broken-links").route(web::get().to(broken_links_get)))
+        .service(web::resource("/links").route(web::get().to(links_get)))
+        .service(web::resource("/metrics").route(web::get().to(metrics_get)))
+        .service(web::resource("/dead-letters").route(web::get().to(dead_letters_get)))
+        .service(
+            web::resource("/dead-letters/requeue").route(web::post().to(dead_letters_requeue)),
+        );
 }
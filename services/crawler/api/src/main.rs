@@ -1,27 +1,80 @@
+use std::sync::Arc;
+
 use crate::api::{api_factory, ApiState};
+use actix_web::dev::Service;
 use actix_web::{middleware, web, App, HttpServer};
-use shared::dao::LinkDaoDynamo;
+use health::{Health, Probe};
+use shared::config::Config;
+use shared::dao::{JobDaoDynamo, LinkDaoDynamo};
 use shared::metrics::MetricsService;
-use shared::mq::{RabbitMQChannel, RabbitMQConnection};
+use shared::mq::QueueConnection;
+use shared::search::{SearchIndex, SearchIndexElastic};
+use tracing::Instrument;
 
 mod api;
 
+/// Returns the checks of the queue and the DynamoDB tables the API depends on
+fn health(config: &Config, connection: QueueConnection) -> Health {
+    let connection = Arc::new(connection);
+    let links = Arc::new(LinkDaoDynamo::new(&config.dynamo));
+    let jobs = Arc::new(JobDaoDynamo::new(&config.dynamo));
+    let probes = [Probe::Ready, Probe::Startup];
+
+    Health::new()
+        .register("queue", &probes, move || {
+            let connection = connection.clone();
+            async move { connection.check().await }
+        })
+        .register("links", &probes, move || {
+            let links = links.clone();
+            async move { links.check().await }
+        })
+        .register("jobs", &probes, move || {
+            let jobs = jobs.clone();
+            async move { jobs.check().await }
+        })
+}
+
 #[actix_rt::main]
 async fn main() -> std::io::Result<()> {
-    env_logger::init();
-    let config = shared::config::Config::from_env().unwrap();
+    let config: Config = settings::load();
+    let _exporter =
+        telemetry::trace::init("crawler-api", env!("CARGO_PKG_VERSION"), &config.tracing);
     let metrics = web::Data::new(MetricsService::new(&config.metrics));
-    let connection = RabbitMQConnection::new(&config.rabbit);
+    let connection = QueueConnection::new(&config);
+    let health = web::Data::new(health(&config, connection.clone()));
 
     HttpServer::new(move || {
         let dao = Box::new(LinkDaoDynamo::new(&config.dynamo));
-        let publisher = Box::new(RabbitMQChannel::new(&connection.clone()));
+        let jobs = Box::new(JobDaoDynamo::new(&config.dynamo));
+        let publisher = connection.channel();
+        let search = config.search.url.clone().map(|url| {
+            Box::new(SearchIndexElastic::new(&config.search, url)) as Box<dyn SearchIndex>
+        });
 
         App::new()
             .wrap(middleware::Logger::default())
-            .data(ApiState::new(dao, publisher))
+            // Each request is handled within a span continuing the trace it carries
+            .wrap_fn(|request, service| {
+                let span = telemetry::trace::server_span(
+                    request.method().as_str(),
+                    request.path(),
+                    |name| request.headers().get(name).and_then(|x| x.to_str().ok()),
+                );
+                let response = service.call(request).instrument(span.clone());
+                async move {
+                    let response = response.await;
+                    if let Ok(response) = &response {
+                        span.record("http.status_code", &response.status().as_u16());
+                    }
+                    response
+                }
+            })
+            .data(ApiState::new(dao, jobs, publisher, search, config.limits))
             .app_data(metrics.clone())
+            .app_data(health.clone())
             .configure(api_factory)
+            .configure(health::configure)
     })
     .bind("127.0.0.1:8080")?
     .run()
@@ -1,22 +1,39 @@
+use async_trait::async_trait;
 use derive_more::Display;
-use std::collections::HashSet;
+use mime::Mime;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::error::Error;
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 
-use reqwest::Response;
+use reqwest::header::{
+    HeaderMap, HeaderName, CONTENT_TYPE, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED,
+    LOCATION,
+};
+use reqwest::redirect::Policy;
+use reqwest::{Client, Response, StatusCode};
+use shared::config::FetchConfig;
+use shared::dao::Validators;
+use shared::normalize::normalize;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
 use url::Url;
 
-use crate::decoder::streaming_decode;
-use crate::parser::Parser;
+use crate::decoder::read_body;
+use crate::extract::Extractor;
+use crate::metrics::{FETCH_DURATION, PAGES_FETCHED, RESPONSES};
 
 #[derive(Debug, Display, PartialEq)]
 pub enum CrawlError {
-    #[display(fmt = "Non text response")]
-    NonHtmlContent,
+    #[display(fmt = "Unsupported content")]
+    UnsupportedContent,
     #[display(fmt = "Error encountered decoding data")]
     DecodeError,
     #[display(fmt = "Error making request")]
     RequestError(String),
+    #[display(fmt = "Redirect loop")]
+    RedirectLoop,
+    #[display(fmt = "Too many redirects")]
+    TooManyRedirects,
 }
 impl Error for CrawlError {}
 
@@ -26,38 +43,372 @@ impl From<reqwest::Error> for CrawlError {
     }
 }
 
-pub async fn crawl(base: &Url) -> Result<HashSet<Url>, CrawlError> {
-    let client = reqwest::ClientBuilder::new()
-        .connect_timeout(Duration::from_secs(5))
-        .build()
-        .expect("Failed to build client");
+/// The response to a request for a URL, after following any redirects
+pub struct Fetched {
+    /// The normalized URL of the response
+    pub url: Url,
+    /// The normalized URLs redirected from to reach `url`, starting with the URL requested
+    pub redirects: Vec<Url>,
+    response: Response,
+    /// The response of a renderer for `url`, whose body is read in place of the body
+    /// of `response`
+    rendered: Option<Response>,
+    /// Held until the response is read, limiting the number of pages read at once
+    _permit: Permit,
+}
+
+impl Fetched {
+    /// Returns the HTTP status of the response
+    pub fn status(&self) -> u16 {
+        self.response.status().as_u16()
+    }
+
+    /// Returns true if the page is unchanged since the response the request's
+    /// validators were taken from
+    pub fn not_modified(&self) -> bool {
+        self.response.status() == StatusCode::NOT_MODIFIED
+    }
+
+    /// Returns true if the response is a successful response with an HTML page
+    pub fn is_html(&self) -> bool {
+        self.response.status().is_success()
+            && content_type(self.response.headers()).map_or(false, |x| {
+                x.type_() == mime::TEXT && x.subtype() == mime::HTML
+            })
+    }
+
+    /// Reads the page from `rendered`, the response of a renderer, rather than from
+    /// the response fetched
+    pub fn set_rendered(&mut self, rendered: Response) {
+        self.rendered = Some(rendered)
+    }
+}
+
+/// A fetched document
+pub struct Page {
+    pub status: u16,
+    pub headers: BTreeMap<String, String>,
+    pub body: Vec<u8>,
+    pub validators: Validators,
+    pub links: HashSet<Url>,
+    /// The URL of the page this is a duplicate of, if declared
+    pub canonical: Option<Url>,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub language: Option<String>,
+    /// The Open Graph properties of the page, by property name
+    pub open_graph: BTreeMap<String, String>,
+    /// The visible text of the page
+    pub text: String,
+    /// The importance of the links that declare it, between 0 and 1
+    pub scores: HashMap<Url, f64>,
+}
+
+/// Returns the content type in `headers`, if any
+fn content_type(headers: &HeaderMap) -> Option<Mime> {
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|x| x.to_str().ok())
+        .and_then(|x| x.parse::<Mime>().ok())
+}
+
+/// Returns `headers` keyed by lowercase name, joining repeated headers with commas
+fn headers(headers: &HeaderMap) -> BTreeMap<String, String> {
+    let mut ret: BTreeMap<String, String> = BTreeMap::new();
+    for (name, value) in headers {
+        let value = String::from_utf8_lossy(value.as_bytes());
+        ret.entry(name.to_string())
+            .and_modify(|x| {
+                x.push_str(", ");
+                x.push_str(&value)
+            })
+            .or_insert_with(|| value.to_string());
+    }
+    ret
+}
+
+/// Returns the validators of a response with `headers`
+fn validators(headers: &HeaderMap) -> Validators {
+    let header = |name: HeaderName| {
+        headers
+            .get(name)
+            .and_then(|x| x.to_str().ok())
+            .map(ToString::to_string)
+    };
 
-    let mut parser = Parser::new(base.clone());
-    let mut res: Response = client.get(base.as_str()).send().await?;
-    streaming_decode(&mut res, |x| parser.feed(x)).await?;
+    Validators {
+        etag: header(ETAG),
+        last_modified: header(LAST_MODIFIED),
+    }
+}
+
+/// Returns the URL `response` to a request for `url` redirects to, None if it is not a
+/// redirect
+fn redirect(url: &Url, response: &Response) -> Option<Url> {
+    if !response.status().is_redirection() {
+        return None;
+    }
+    let location = response.headers().get(LOCATION)?.to_str().ok()?;
+    url.join(location).ok()
+}
+
+/// Limits the number of pages a worker fetches at once, in total and from each host
+struct Limits {
+    worker: Arc<Semaphore>,
+    /// The semaphores of the hosts being fetched from, or waited on
+    hosts: Mutex<HashMap<String, Arc<Semaphore>>>,
+    /// The maximum number of pages fetched at once from each host
+    connections: usize,
+}
+
+/// The permits to fetch a page, released once it has been read
+struct Permit {
+    _host: OwnedSemaphorePermit,
+    _worker: OwnedSemaphorePermit,
+}
 
-    Ok(parser.finalize())
+impl Limits {
+    fn new(concurrency: usize, connections: usize) -> Limits {
+        Limits {
+            worker: Arc::new(Semaphore::new(concurrency.max(1))),
+            hosts: Default::default(),
+            connections: connections.max(1),
+        }
+    }
+
+    fn host(&self, host: &str) -> Arc<Semaphore> {
+        let mut hosts = self.hosts.lock().expect("host limits poisoned");
+
+        // A semaphore only referenced by the map has no permits held or waited on
+        hosts.retain(|_, x| Arc::strong_count(x) > 1);
+        hosts
+            .entry(host.to_string())
+            .or_insert_with(|| Arc::new(Semaphore::new(self.connections)))
+            .clone()
+    }
+
+    /// Waits until a page may be fetched from the host of `url`
+    async fn acquire(&self, url: &Url) -> Permit {
+        // The host's permit is acquired first, so fetches waiting on a busy host don't
+        // hold the permits of the worker that fetches from other hosts could use
+        let host = self.host(url.host_str().unwrap_or_default());
+        let host = host.acquire_owned().await;
+        let worker = self.worker.clone().acquire_owned().await;
+        Permit {
+            _host: host,
+            _worker: worker,
+        }
+    }
+}
+
+/// Fetches the pages at URLs
+#[async_trait(?Send)]
+pub trait Fetcher {
+    /// Requests `url`, following any redirects
+    ///
+    /// The request for `url` is conditional on the page having changed since the
+    /// response `validators` are taken from, see [`Fetched::not_modified`]. The requests
+    /// of any redirects are not, as the validators belong to `url`
+    async fn fetch(&self, url: &Url, validators: &Validators) -> Result<Fetched, CrawlError>;
+}
+
+/// Fetches pages over a pool of connections shared by all of a worker's fetches
+pub struct HttpFetcher {
+    client: Client,
+    max_redirects: usize,
+    limits: Limits,
+}
+
+impl HttpFetcher {
+    pub fn new(config: &FetchConfig) -> HttpFetcher {
+        // Redirects are followed here, rather than by the client, to record them
+        let client = reqwest::ClientBuilder::new()
+            .connect_timeout(Duration::from_secs(config.connect))
+            .timeout(Duration::from_secs(config.timeout))
+            .pool_max_idle_per_host(config.connections)
+            .redirect(Policy::none())
+            .build()
+            .expect("Failed to build client");
+
+        HttpFetcher {
+            client,
+            max_redirects: config.redirects,
+            limits: Limits::new(config.concurrency, config.connections),
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl Fetcher for HttpFetcher {
+    /// Requests `url`, following up to the configured number of redirects
+    async fn fetch(&self, url: &Url, validators: &Validators) -> Result<Fetched, CrawlError> {
+        let mut url = normalize(url);
+        let mut redirects = Vec::new();
+        loop {
+            // The permit of a redirect is released before following it, so a fetch
+            // never holds more than one
+            let permit = self.limits.acquire(&url).await;
+
+            let mut request = self.client.get(url.as_str());
+            if redirects.is_empty() {
+                if let Some(etag) = &validators.etag {
+                    request = request.header(IF_NONE_MATCH, etag.as_str());
+                }
+                if let Some(last_modified) = &validators.last_modified {
+                    request = request.header(IF_MODIFIED_SINCE, last_modified.as_str());
+                }
+            }
+
+            let timer = FETCH_DURATION.start_timer();
+            let response = request.send().await?;
+            timer.observe_duration();
+            RESPONSES
+                .with_label_values(&[response.status().as_str()])
+                .inc();
+
+            let target = match redirect(&url, &response) {
+                Some(target) => normalize(&target),
+                None => {
+                    PAGES_FETCHED.inc();
+                    return Ok(Fetched {
+                        url,
+                        redirects,
+                        response,
+                        rendered: None,
+                        _permit: permit,
+                    });
+                }
+            };
+
+            redirects.push(url);
+            if redirects.contains(&target) {
+                return Err(CrawlError::RedirectLoop);
+            }
+            if redirects.len() > self.max_redirects {
+                return Err(CrawlError::TooManyRedirects);
+            }
+            url = target;
+        }
+    }
+}
+
+/// Reads the document of `fetched`, extracting it with the first of `extractors` that
+/// accepts its content type
+pub async fn read(fetched: Fetched, extractors: &[Box<dyn Extractor>]) -> Result<Page, CrawlError> {
+    let response = fetched.response;
+    let status = response.status();
+    let validators = validators(response.headers());
+    let headers = headers(response.headers());
+
+    // Error pages are not the document at the URL, and so are neither indexed nor
+    // followed
+    if !status.is_success() {
+        return Err(CrawlError::UnsupportedContent);
+    }
+
+    // Documents without a content type are assumed to be HTML
+    let mut res = fetched.rendered.unwrap_or(response);
+    let content_type = content_type(res.headers()).unwrap_or(mime::TEXT_HTML);
+    let extractor = extractors
+        .iter()
+        .find(|x| x.accepts(&content_type))
+        .ok_or(CrawlError::UnsupportedContent)?;
+
+    let body = read_body(&mut res).await?;
+    let extracted = extractor.extract(&fetched.url, &content_type, &body)?;
+    Ok(Page {
+        status: status.as_u16(),
+        headers,
+        body,
+        validators,
+        links: extracted.links,
+        canonical: extracted.canonical,
+        title: extracted.title,
+        description: extracted.description,
+        language: extracted.language,
+        open_graph: extracted.open_graph,
+        text: extracted.text,
+        scores: extracted.scores,
+    })
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::extract::extractors;
+
+    fn fetcher() -> HttpFetcher {
+        HttpFetcher::new(&FetchConfig::default())
+    }
+
+    async fn fetch_and_read(url: &Url) -> Result<Page, CrawlError> {
+        let fetched = fetcher().fetch(url, &Default::default()).await?;
+        read(fetched, &extractors()).await
+    }
 
     #[tokio::test]
     async fn test_crawl() -> Result<(), Box<dyn Error>> {
         let url = Url::parse("https://accounts.google.com/ServiceLogin?hl=en&passive=true&continue=https://www.google.co.uk/")?;
-        let res = crawl(&url).await;
+        let res = fetch_and_read(&url).await;
         assert!(res.is_ok());
         Ok(())
     }
 
     #[tokio::test]
-    async fn test_nonhtml() -> Result<(), Box<dyn Error>> {
-        let url = Url::parse("https://monzo.com/documents/pillar_3_2019.pdf")?;
-        let res = crawl(&url).await;
+    async fn test_unsupported() -> Result<(), Box<dyn Error>> {
+        let url = Url::parse("https://www.rust-lang.org/static/images/rust-logo-blk.svg")?;
+        let res = fetch_and_read(&url).await;
 
         assert!(res.is_err());
-        assert_eq!(res.unwrap_err(), CrawlError::NonHtmlContent);
+        assert_eq!(res.unwrap_err(), CrawlError::UnsupportedContent);
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_limits() {
+        let limits = Limits::new(3, 2);
+        let timeout = Duration::from_millis(50);
+        let a = Url::parse("https://a.com/").unwrap();
+        let b = Url::parse("https://b.com/").unwrap();
+
+        let _a1 = limits.acquire(&a).await;
+        let a2 = limits.acquire(&a).await;
+
+        // A host's pages are fetched at most two at a time
+        let a3 = tokio::time::timeout(timeout, limits.acquire(&a)).await;
+        assert!(a3.is_err());
+
+        // The worker fetches at most three pages at a time
+        let b1 = limits.acquire(&b).await;
+        let b2 = tokio::time::timeout(timeout, limits.acquire(&b)).await;
+        assert!(b2.is_err());
+
+        drop(b1);
+        drop(a2);
+        let a3 = tokio::time::timeout(timeout, limits.acquire(&a)).await;
+        assert!(a3.is_ok());
+        assert_eq!(limits.hosts.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_headers() {
+        let mut map = HeaderMap::new();
+        map.insert("Content-Type", "text/html".parse().unwrap());
+        map.append("Set-Cookie", "a=1".parse().unwrap());
+        map.append("Set-Cookie", "b=2".parse().unwrap());
+
+        let headers = headers(&map);
+        assert_eq!(headers["content-type"], "text/html");
+        assert_eq!(headers["set-cookie"], "a=1, b=2");
+    }
+
+    #[test]
+    fn test_validators() {
+        let mut map = HeaderMap::new();
+        map.insert("ETag", "\"abc\"".parse().unwrap());
+
+        let validators = validators(&map);
+        assert_eq!(validators.etag.as_deref(), Some("\"abc\""));
+        assert_eq!(validators.last_modified, None);
+    }
 }
@@ -1,86 +1,137 @@
 use crate::crawler::CrawlError;
+use crate::metrics::BYTES_DOWNLOADED;
+use chardetng::EncodingDetector;
 use encoding_rs::*;
 use mime::Mime;
 use reqwest::Response;
+use url::Url;
 
-fn get_encoding(res: &Response) -> Result<&'static Encoding, CrawlError> {
-    let content_type: Option<Mime> = res
-        .headers()
-        .get(reqwest::header::CONTENT_TYPE)
-        .and_then(|value| value.to_str().ok())
-        .and_then(|value| value.parse::<Mime>().ok());
+/// The number of bytes at the start of an HTML document searched for a `<meta>`
+/// declaring its encoding
+const PRESCAN_LEN: usize = 1024;
 
-    if let Some(m) = content_type {
-        if m.type_() != mime::TEXT || m.subtype() != mime::HTML {
-            return Err(CrawlError::NonHtmlContent);
-        }
+/// Reads the body of `res`
+pub(crate) async fn read_body(res: &mut Response) -> Result<Vec<u8>, CrawlError> {
+    let mut body = Vec::new();
+    while let Some(chunk) = res.chunk().await? {
+        BYTES_DOWNLOADED.inc_by(chunk.len() as i64);
+        body.extend_from_slice(&chunk);
+    }
+    Ok(body)
+}
+
+/// Returns the encoding named by the charset parameter of `content_type`, if known
+fn charset(content_type: &Mime) -> Option<&'static Encoding> {
+    let charset = content_type.get_param(mime::CHARSET)?;
+    Encoding::for_label(charset.as_str().as_bytes())
+}
+
+/// Returns the encoding declared by a `<meta charset>` or `<meta http-equiv>` tag at
+/// the start of `html`, if known
+fn meta_charset(html: &[u8]) -> Option<&'static Encoding> {
+    let head = &html[..html.len().min(PRESCAN_LEN)];
+    let head = String::from_utf8_lossy(head).to_ascii_lowercase();
 
-        if let Some(charset) = m.get_param("charset") {
-            if let Some(e) = Encoding::for_label(charset.as_str().as_bytes()) {
-                return Ok(e);
-            }
+    for tag in head.split("<meta").skip(1) {
+        let tag = &tag[..tag.find('>').unwrap_or(tag.len())];
+        let value = match tag.find("charset") {
+            Some(idx) => tag[idx + "charset".len()..].trim_start(),
+            None => continue,
+        };
+        let value = match value.strip_prefix('=') {
+            Some(value) => value.trim_start().trim_start_matches(&['"', '\''][..]),
+            None => continue,
+        };
+        let end = value
+            .find(|c: char| c == '"' || c == '\'' || c == ';' || c.is_whitespace())
+            .unwrap_or(value.len());
+
+        if let Some(encoding) = Encoding::for_label(&value.as_bytes()[..end]) {
+            // A declaration read as ASCII can't be in UTF-16, so is taken to mean UTF-8
+            return Some(match encoding {
+                e if e == UTF_16BE || e == UTF_16LE => UTF_8,
+                e if e == X_USER_DEFINED => WINDOWS_1252,
+                e => e,
+            });
         }
     }
-    Ok(UTF_8)
+    None
 }
 
-pub(crate) async fn streaming_decode(
-    res: &mut Response,
-    mut flush: impl FnMut(&str),
-) -> Result<(), CrawlError> {
-    let encoding = get_encoding(res)?;
-
-    let mut decoder = encoding.new_decoder();
-    let mut bytes_in_buffer = 0usize;
-    let mut buffer_bytes = [0u8; 2048];
-    let buffer: &mut str = std::str::from_utf8_mut(&mut buffer_bytes[..]).unwrap();
-
-    while let Some(req_chunk) = res.chunk().await? {
-        let mut total_read_from_current_input = 0usize;
-
-        loop {
-            let (result, read, written, had_errors) = decoder.decode_to_str(
-                &req_chunk[total_read_from_current_input..],
-                &mut buffer[bytes_in_buffer..],
-                false,
-            );
-            if had_errors {
-                return Err(CrawlError::DecodeError);
-            }
-            total_read_from_current_input += read;
-            bytes_in_buffer += written;
-            match result {
-                CoderResult::InputEmpty => {
-                    break;
-                }
-                CoderResult::OutputFull => {
-                    flush(&mut buffer[..bytes_in_buffer]);
-                    bytes_in_buffer = 0usize;
-                    continue;
-                }
-            }
-        }
+/// Guesses the encoding of `body` from its content, and the top-level domain of `url`
+fn sniff(url: &Url, body: &[u8]) -> &'static Encoding {
+    let mut detector = EncodingDetector::new();
+    detector.feed(body, true);
+    let tld = url.domain().and_then(|x| x.rsplit('.').next());
+    detector.guess(tld.map(str::as_bytes), true)
+}
+
+/// Decodes `body`, the text document at `url`, replacing any malformed sequences
+///
+/// The encoding is that of the document's byte order mark if any, otherwise that of
+/// the charset of `content_type`, a `<meta>` declaration if `html`, or else guessed
+/// from the content
+pub(crate) fn decode(url: &Url, content_type: &Mime, body: &[u8], html: bool) -> String {
+    let encoding = charset(content_type)
+        .or_else(|| if html { meta_charset(body) } else { None })
+        .unwrap_or_else(|| sniff(url, body));
+
+    // A byte order mark takes precedence over the encoding given
+    let (text, _, _) = encoding.decode(body);
+    text.into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_meta_charset() {
+        let meta = |html: &str| meta_charset(html.as_bytes());
+        assert_eq!(meta("<meta charset=\"ISO-8859-1\">"), Some(WINDOWS_1252));
+        assert_eq!(meta("<META CHARSET=shift_jis>"), Some(SHIFT_JIS));
+        assert_eq!(
+            meta("<meta http-equiv=\"Content-Type\" content=\"text/html; charset=koi8-r\">"),
+            Some(KOI8_R)
+        );
+        assert_eq!(meta("<meta charset=\"utf-16le\">"), Some(UTF_8));
+        assert_eq!(meta("<meta name=\"description\" content=\"x\">"), None);
+        assert_eq!(meta("<meta charset=\"unknown\">"), None);
+
+        // Declarations beyond the start of the document are ignored
+        let late = format!("{}<meta charset=\"koi8-r\">", " ".repeat(PRESCAN_LEN));
+        assert_eq!(meta(&late), None);
     }
 
-    // EOF
-    loop {
-        let (result, _, written, had_errors) =
-            decoder.decode_to_str(b"", &mut buffer[bytes_in_buffer..], true);
-        if had_errors {
-            return Err(CrawlError::DecodeError);
-        }
-        bytes_in_buffer += written;
-
-        flush(&buffer[..bytes_in_buffer]);
-        bytes_in_buffer = 0usize;
-        match result {
-            CoderResult::InputEmpty => {
-                break;
-            }
-            CoderResult::OutputFull => {
-                continue;
-            }
-        }
+    #[test]
+    fn test_decode() {
+        let url = Url::parse("https://example.com/").unwrap();
+        let html: Mime = "text/html".parse().unwrap();
+        let latin1: Mime = "text/html; charset=iso-8859-1".parse().unwrap();
+
+        // The charset of the content type is used over any declared in the document
+        let body = b"<meta charset=\"utf-8\">caf\xe9";
+        assert_eq!(
+            decode(&url, &latin1, body, true),
+            "<meta charset=\"utf-8\">café"
+        );
+
+        let body = b"<meta charset=\"iso-8859-1\">caf\xe9";
+        assert_eq!(
+            decode(&url, &html, body, true),
+            "<meta charset=\"iso-8859-1\">café"
+        );
+
+        // A byte order mark takes precedence
+        assert_eq!(
+            decode(&url, &latin1, b"\xef\xbb\xbfcaf\xc3\xa9", true),
+            "café"
+        );
+
+        // Otherwise the encoding is guessed
+        let text = "crème brûlée à la carte, déjà vu";
+        let (body, _, _) = WINDOWS_1252.encode(text);
+        assert_eq!(decode(&url, &html, &body, false), text);
+        assert_eq!(decode(&url, &html, text.as_bytes(), false), text);
     }
-    Ok(())
 }
@@ -0,0 +1,373 @@
+use async_trait::async_trait;
+use log::{error, info};
+use reqwest::Url;
+use shared::config::{FetchConfig, RenderConfig};
+use shared::content::{ContentStore, Snapshot};
+use shared::dao::{unix_time, Crawl, JobDao, LinkDao, PageMetadata, Progress};
+use shared::filter::UrlFilter;
+use shared::mq::*;
+use shared::normalize::normalize;
+use shared::search::{Document, SearchIndex};
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::rc::Rc;
+use tracing::{debug, info_span, Instrument};
+
+use crate::crawler::{self, CrawlError, Fetcher, HttpFetcher};
+use crate::extract::{extractors, Extractor};
+use crate::metrics::PARSE_FAILURES;
+use crate::render::RenderingFetcher;
+
+/// Crawls the URLs consumed from the message queue, recording their links and queueing
+/// those not yet crawled
+pub struct Delegate {
+    dao: Box<dyn LinkDao>,
+    jobs: Box<dyn JobDao>,
+    /// Where to store the content of crawled pages, None to not store content
+    content: Option<Box<dyn ContentStore>>,
+    /// Where to index the text of crawled pages, None to not index text
+    search: Option<Box<dyn SearchIndex>>,
+    channel: Box<dyn MessageQueue>,
+    fetcher: Rc<dyn Fetcher>,
+    /// Renders pages before they are parsed, None if pages are not rendered
+    renderer: Option<RenderingFetcher>,
+    /// The extractors of the content types whose links are followed
+    extractors: Vec<Box<dyn Extractor>>,
+    /// The URLs links are followed to in every crawl
+    filter: UrlFilter,
+    /// The time in seconds to claim the URL of each message for
+    claim: u64,
+}
+
+/// Returns true if `crawl` was at or after the unix time `stale_before`, and so the
+/// page need not be crawled again
+fn indexed(crawl: Option<&Crawl>, stale_before: u64) -> bool {
+    crawl.map_or(false, |x| x.crawled_at >= stale_before)
+}
+
+/// The outcome of consuming a message
+enum Outcome {
+    /// The URL was not crawled, as it was recently indexed, is being crawled by
+    /// another worker or its job was stopped
+    Skipped,
+    /// The URL was crawled, queueing the given number of links
+    Crawled(usize),
+}
+
+impl Delegate {
+    pub fn new(
+        dao: Box<dyn LinkDao>,
+        jobs: Box<dyn JobDao>,
+        content: Option<Box<dyn ContentStore>>,
+        search: Option<Box<dyn SearchIndex>>,
+        channel: Box<dyn MessageQueue>,
+        fetch: FetchConfig,
+        filter: UrlFilter,
+    ) -> Delegate {
+        Delegate {
+            dao,
+            jobs,
+            content,
+            search,
+            channel,
+            fetcher: Rc::new(HttpFetcher::new(&fetch)),
+            renderer: None,
+            extractors: extractors(),
+            filter,
+            claim: fetch.claim,
+        }
+    }
+
+    /// Renders pages with the service at `endpoint` before they are parsed, those of
+    /// jobs that request rendering and those on the domains of `config`
+    pub fn with_renderer(mut self, config: &RenderConfig, endpoint: Url) -> Delegate {
+        let renderer = RenderingFetcher::new(self.fetcher.clone(), config, endpoint);
+        self.renderer = Some(renderer);
+        self
+    }
+
+    /// Returns the fetcher of `url`, which renders it if `render` is true or it is on
+    /// a domain that is always rendered, and pages are rendered at all
+    fn fetcher(&self, url: &Url, render: bool) -> &dyn Fetcher {
+        match &self.renderer {
+            Some(renderer) if render || renderer.renders(url) => renderer,
+            _ => self.fetcher.as_ref(),
+        }
+    }
+
+    async fn record_progress(&self, job_id: &str, progress: Progress) {
+        if let Err(e) = self.jobs.record_progress(job_id, progress).await {
+            error!("Failed to record progress of job {}: {}", job_id, e);
+        }
+    }
+
+    /// Crawls the URL of `message` unless it is already being crawled, recording the
+    /// progress of its job
+    async fn process(&self, message: Message) -> Result<(), Box<dyn Error>> {
+        debug!(url = message.url.as_str(), "Processing message");
+        let url = normalize(&Url::parse(&message.url)?).to_string();
+
+        // The URL is claimed before checking whether it has been crawled, so that only
+        // one worker crawls it at a time, any others skipping it
+        let outcome = match self.dao.claim(&url, self.claim).await? {
+            Some(token) => {
+                let outcome = self.crawl(&message).await;
+                if let Err(e) = self.dao.release(&url, &token).await {
+                    error!("Failed to release claim on {}: {}", &url, e);
+                }
+                outcome?
+            }
+            None => {
+                info!("Already being crawled {}", &url);
+                Outcome::Skipped
+            }
+        };
+
+        if let Some(job_id) = &message.job_id {
+            // This message is no longer queued
+            let mut progress = Progress {
+                queued: -1,
+                ..Default::default()
+            };
+
+            if let Outcome::Crawled(queued) = outcome {
+                progress.queued += queued as i64;
+                progress.crawled = 1;
+            }
+            self.record_progress(job_id, progress).await;
+        }
+        Ok(())
+    }
+
+    async fn crawl(&self, message: &Message) -> Result<Outcome, Box<dyn Error>> {
+        let job = match &message.job_id {
+            Some(job_id) => match self.jobs.get_job(job_id).await? {
+                Some(job) if !job.stopped => Some(job),
+                _ => {
+                    info!("Job {} stopped, skipping {}", job_id, &message.url);
+                    return Ok(Outcome::Skipped);
+                }
+            },
+            None => None,
+        };
+
+        // The patterns of the job limit the links followed, in addition to those of
+        // every crawl
+        let job_filter = match &job {
+            Some(job) => Some(UrlFilter::new(&job.include, &job.exclude)?),
+            None => None,
+        };
+        let render = job.as_ref().map_or(false, |x| x.render);
+
+        let base = normalize(&Url::parse(&message.url)?);
+        let url = base.to_string();
+
+        // Pages indexed before the message's staleness threshold are crawled again
+        let stale_before = message.stale_before(unix_time());
+        let previous = self.dao.get_crawl(&url).await?;
+        if indexed(previous.as_ref(), stale_before) {
+            info!("Already indexed {}", &url);
+            return Ok(Outcome::Skipped);
+        }
+
+        // Pages crawled before are only fetched again if they have changed
+        let validators = previous
+            .as_ref()
+            .map(|x| x.validators.clone())
+            .unwrap_or_default();
+
+        let fetched_at = unix_time();
+        let fetched = match self.fetcher(&base, render).fetch(&base, &validators).await {
+            Ok(fetched) => fetched,
+            // Retrying would follow the same redirects, so the URL is recorded as crawled
+            Err(e @ CrawlError::RedirectLoop) | Err(e @ CrawlError::TooManyRedirects) => {
+                error!("{} fetching {}", e, &url);
+                let links = Default::default();
+                self.dao
+                    .set_links(
+                        url,
+                        links,
+                        None,
+                        Default::default(),
+                        None,
+                        Default::default(),
+                    )
+                    .await?;
+                return Ok(Outcome::Crawled(0));
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        // The server reports the page unchanged since it was last crawled, which saves
+        // downloading it to compare its content hash below
+        if fetched.not_modified() {
+            info!("Not modified {}", &url);
+            self.dao.touch(&url).await?;
+            return Ok(Outcome::Crawled(0));
+        }
+
+        // The URLs redirected from are recorded as redirecting to the URL of the
+        // response, which is crawled in their place
+        let (base, url, previous) = if fetched.redirects.is_empty() {
+            (base, url, previous)
+        } else {
+            let target = fetched.url.to_string();
+            for from in &fetched.redirects {
+                self.dao
+                    .set_redirect(from.to_string(), target.clone())
+                    .await?;
+            }
+
+            // Links within a site that redirect to another are external, and so are
+            // not followed, though seeds may redirect anywhere
+            if message.depth > 0 && fetched.url.origin() != base.origin() {
+                info!("{} redirects to external {}", &url, &target);
+                return Ok(Outcome::Crawled(0));
+            }
+
+            let previous = self.dao.get_crawl(&target).await?;
+            if indexed(previous.as_ref(), stale_before) {
+                info!("Already indexed {}, redirected from {}", &target, &url);
+                return Ok(Outcome::Skipped);
+            }
+            (fetched.url.clone(), target, previous)
+        };
+
+        // The status is recorded whether or not the document is extracted, for
+        // reporting broken links
+        let status = fetched.status();
+        let (urls, scores, content_hash, validators, metadata) =
+            match crawler::read(fetched, &self.extractors).await {
+                Ok(page) => {
+                    let canonical = page.canonical.as_ref().map(normalize);
+                    let metadata = PageMetadata {
+                        title: page.title.clone(),
+                        description: page.description,
+                        canonical: canonical.as_ref().map(Url::to_string),
+                        language: page.language,
+                        open_graph: page.open_graph,
+                    };
+
+                    // A page declaring a different canonical URL on the same site is a
+                    // duplicate of that page, and so only the canonical URL is followed
+                    let canonical = canonical.filter(|x| x.origin() == base.origin() && *x != base);
+
+                    if let Some(canonical) = canonical {
+                        info!("{} is a duplicate of {}", &url, canonical);
+                        let urls = std::iter::once(canonical).collect();
+                        (urls, Default::default(), None, Default::default(), metadata)
+                    } else {
+                        let snapshot = Snapshot::new(
+                            url.clone(),
+                            page.status,
+                            page.headers,
+                            fetched_at,
+                            page.body,
+                        );
+                        let content_hash = snapshot.metadata.content_hash.clone();
+
+                        // A page whose content is unchanged since it was last crawled has the
+                        // same links, and so neither it nor its children need processing again
+                        let previous_hash = previous.and_then(|x| x.content_hash);
+                        if previous_hash.as_ref() == Some(&content_hash) {
+                            info!("Unchanged {}", &url);
+                            self.dao.touch(&url).await?;
+                            return Ok(Outcome::Crawled(0));
+                        }
+
+                        if let Some(content) = &self.content {
+                            content.put(&snapshot).await?;
+                        }
+                        if let Some(search) = &self.search {
+                            let document = Document {
+                                url: url.clone(),
+                                title: page.title,
+                                text: page.text,
+                                crawled_at: fetched_at,
+                            };
+                            search.index(&document).await?;
+                        }
+                        let content_hash = Some(content_hash);
+                        (
+                            page.links,
+                            page.scores,
+                            content_hash,
+                            page.validators,
+                            metadata,
+                        )
+                    }
+                }
+                Err(CrawlError::UnsupportedContent) => Default::default(),
+                Err(CrawlError::DecodeError) => {
+                    error!("Error decoding url content: {}", &url);
+                    PARSE_FAILURES.inc();
+                    Default::default()
+                }
+                Err(e) => return Err(e.into()),
+            };
+
+        // Links are normalized so that trivially different URLs of a page are only
+        // crawled once
+        let urls: HashSet<Url> = urls.iter().map(normalize).collect();
+        let scores: HashMap<String, f64> = scores
+            .iter()
+            .map(|(url, score)| (normalize(url).to_string(), *score))
+            .collect();
+
+        let filtered_urls: HashSet<String> = urls
+            .iter()
+            .filter(|x| x.origin() == base.origin())
+            .map(|x| x.to_string())
+            .filter(|x| self.filter.allows(x))
+            .filter(|x| job_filter.as_ref().map_or(true, |filter| filter.allows(x)))
+            .collect();
+
+        let links = urls.iter().map(|x| x.to_string()).collect();
+        self.dao
+            .set_links(url, links, content_hash, validators, Some(status), metadata)
+            .await?;
+
+        let crawled = self.dao.get_multiple(&filtered_urls, stale_before).await?;
+        let next = filtered_urls.difference(&crawled).map(|url| {
+            let score = scores.get(url).copied();
+            (url.clone(), score)
+        });
+        let children = message.scored_children(next);
+        for child in &children {
+            debug!(
+                url = child.url.as_str(),
+                depth = child.depth,
+                "Queueing link"
+            );
+        }
+        let queued = children.len();
+        self.channel.queue_batch(children).await?;
+        Ok(Outcome::Crawled(queued))
+    }
+}
+
+#[async_trait(?Send)]
+impl ConsumerDelegate for Delegate {
+    async fn consume(&self, message: Message) -> Result<(), Box<dyn Error>> {
+        let span = info_span!(
+            "consume",
+            otel.kind = "consumer",
+            url = message.url.as_str(),
+            job_id = message.job_id.as_deref(),
+            depth = message.depth,
+            attempts = message.attempts,
+        );
+        self.process(message).instrument(span).await
+    }
+
+    async fn dead_lettered(&self, message: &Message) {
+        if let Some(job_id) = &message.job_id {
+            let progress = Progress {
+                queued: -1,
+                failed: 1,
+                ..Default::default()
+            };
+            self.record_progress(job_id, progress).await;
+        }
+    }
+}
@@ -0,0 +1,310 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use encoding_rs::{UTF_16BE, WINDOWS_1252};
+use mime::Mime;
+use pdf_extract::{Document, Object, PlainTextOutput};
+use url::Url;
+
+use crate::crawler::CrawlError;
+use crate::decoder::decode;
+use crate::parser::Parser;
+use crate::sitemap;
+
+/// The maximum length of the text extracted from a document
+const MAX_TEXT_LEN: usize = 100_000;
+
+/// The links, text and metadata of a document
+#[derive(Debug, Default)]
+pub(crate) struct Extracted {
+    pub links: HashSet<Url>,
+    /// The URL of the page this is a duplicate of, from `<link rel="canonical">`
+    pub canonical: Option<Url>,
+    pub title: Option<String>,
+    /// The description of the page, from `<meta name="description">`
+    pub description: Option<String>,
+    /// The language of the page, from `<html lang>`
+    pub language: Option<String>,
+    /// The Open Graph properties of the page, from `<meta property="og:...">`
+    pub open_graph: BTreeMap<String, String>,
+    /// The visible text of the document
+    pub text: String,
+    /// The importance of the links that declare it, between 0 and 1
+    pub scores: HashMap<Url, f64>,
+}
+
+/// Accumulates text, collapsing runs of whitespace into a single space
+#[derive(Default)]
+pub(crate) struct Text {
+    text: String,
+}
+
+impl Text {
+    pub(crate) fn push(&mut self, s: &str) {
+        for c in s.chars() {
+            if c.is_whitespace() {
+                self.separate();
+            } else if self.text.len() < MAX_TEXT_LEN {
+                self.text.push(c);
+            }
+        }
+    }
+
+    /// Separates the text pushed before from that pushed after
+    pub(crate) fn separate(&mut self) {
+        if !self.text.is_empty() && !self.text.ends_with(' ') && self.text.len() < MAX_TEXT_LEN {
+            self.text.push(' ');
+        }
+    }
+
+    pub(crate) fn finish(self) -> String {
+        self.text.trim_end().to_string()
+    }
+}
+
+/// Returns `s` with runs of whitespace collapsed into a single space
+pub(crate) fn collapse(s: &str) -> String {
+    let mut text = Text::default();
+    text.push(s);
+    text.finish()
+}
+
+/// Returns the absolute HTTP URLs written in `text`
+fn find_urls(text: &str) -> HashSet<Url> {
+    text.split_whitespace()
+        .filter_map(|word| {
+            let start = word.find("http://").or_else(|| word.find("https://"))?;
+            let url = word[start..].trim_end_matches(|c| ".,;:!?)]}>'\"".contains(c));
+            Url::parse(url).ok()
+        })
+        .collect()
+}
+
+/// Extracts the links and text of documents of some content types
+pub(crate) trait Extractor {
+    /// Returns true if documents of `content_type` are extracted
+    fn accepts(&self, content_type: &Mime) -> bool;
+
+    /// Extracts `body`, the document at `url` of `content_type`
+    fn extract(&self, url: &Url, content_type: &Mime, body: &[u8])
+        -> Result<Extracted, CrawlError>;
+}
+
+/// Returns the extractors of every content type crawled
+pub(crate) fn extractors() -> Vec<Box<dyn Extractor>> {
+    vec![
+        Box::new(HtmlExtractor),
+        Box::new(TextExtractor),
+        Box::new(PdfExtractor),
+        Box::new(SitemapExtractor),
+    ]
+}
+
+/// Extracts HTML pages, whose links are followed
+struct HtmlExtractor;
+
+impl Extractor for HtmlExtractor {
+    fn accepts(&self, content_type: &Mime) -> bool {
+        match (content_type.type_(), content_type.subtype()) {
+            (mime::TEXT, mime::HTML) => true,
+            (mime::APPLICATION, subtype) => subtype == "xhtml",
+            _ => false,
+        }
+    }
+
+    fn extract(
+        &self,
+        url: &Url,
+        content_type: &Mime,
+        body: &[u8],
+    ) -> Result<Extracted, CrawlError> {
+        let mut parser = Parser::new(url.clone());
+        parser.feed(&decode(url, content_type, body, true));
+        Ok(parser.finalize())
+    }
+}
+
+/// Extracts plain text documents, whose links are any absolute URLs in the text
+struct TextExtractor;
+
+impl Extractor for TextExtractor {
+    fn accepts(&self, content_type: &Mime) -> bool {
+        content_type.type_() == mime::TEXT && content_type.subtype() == mime::PLAIN
+    }
+
+    fn extract(
+        &self,
+        url: &Url,
+        content_type: &Mime,
+        body: &[u8],
+    ) -> Result<Extracted, CrawlError> {
+        let text = decode(url, content_type, body, false);
+        Ok(Extracted {
+            links: find_urls(&text),
+            text: collapse(&text),
+            ..Default::default()
+        })
+    }
+}
+
+/// Extracts PDF documents, whose links are those of link annotations along with any
+/// absolute URLs in the text
+struct PdfExtractor;
+
+/// Decodes a text string of a PDF, which is either UTF-16 with a byte order mark or
+/// in PDFDocEncoding, of which Windows-1252 is a close superset
+fn pdf_string(bytes: &[u8]) -> String {
+    let encoding = if bytes.starts_with(b"\xfe\xff") {
+        UTF_16BE
+    } else {
+        WINDOWS_1252
+    };
+    let (text, _, _) = encoding.decode(bytes);
+    text.into_owned()
+}
+
+/// Returns the title in the document information dictionary of `doc`, if any
+fn pdf_title(doc: &Document) -> Option<String> {
+    let title = doc
+        .trailer
+        .get(b"Info")
+        .and_then(|x| doc.dereference(x))
+        .and_then(|(_, x)| x.as_dict())
+        .and_then(|x| x.get(b"Title"))
+        .and_then(Object::as_str)
+        .ok()?;
+
+    Some(collapse(&pdf_string(title))).filter(|x| !x.is_empty())
+}
+
+/// Returns the URLs of the link annotations of `doc`, resolved against `url`
+fn pdf_links(url: &Url, doc: &Document) -> HashSet<Url> {
+    let mut links = HashSet::new();
+    for page in doc.get_pages().values() {
+        for annotation in doc.get_page_annotations(*page).unwrap_or_default() {
+            let uri = annotation
+                .get(b"A")
+                .and_then(|x| doc.dereference(x))
+                .and_then(|(_, x)| x.as_dict())
+                .and_then(|x| x.get(b"URI"))
+                .and_then(Object::as_str);
+
+            if let Ok(link) = uri.map(pdf_string) {
+                links.extend(url.join(&link).ok());
+            }
+        }
+    }
+    links
+}
+
+fn extract_pdf(url: &Url, body: &[u8]) -> Result<Extracted, CrawlError> {
+    let doc = Document::load_mem(body).map_err(|_| CrawlError::DecodeError)?;
+
+    let mut text = String::new();
+    pdf_extract::output_doc(&doc, &mut PlainTextOutput::new(&mut text))
+        .map_err(|_| CrawlError::DecodeError)?;
+
+    let mut links = pdf_links(url, &doc);
+    links.extend(find_urls(&text));
+    Ok(Extracted {
+        links,
+        title: pdf_title(&doc),
+        text: collapse(&text),
+        ..Default::default()
+    })
+}
+
+impl Extractor for PdfExtractor {
+    fn accepts(&self, content_type: &Mime) -> bool {
+        content_type.type_() == mime::APPLICATION && content_type.subtype() == mime::PDF
+    }
+
+    fn extract(&self, url: &Url, _: &Mime, body: &[u8]) -> Result<Extracted, CrawlError> {
+        // The PDF parser panics on some malformed documents, which must not bring
+        // down the worker
+        std::panic::catch_unwind(|| extract_pdf(url, body)).unwrap_or(Err(CrawlError::DecodeError))
+    }
+}
+
+/// Extracts XML sitemaps, whose links are the pages they list along with their
+/// priorities, or the sitemaps listed by a sitemap index
+struct SitemapExtractor;
+
+impl Extractor for SitemapExtractor {
+    fn accepts(&self, content_type: &Mime) -> bool {
+        matches!(content_type.type_(), mime::TEXT | mime::APPLICATION)
+            && content_type.subtype() == mime::XML
+    }
+
+    fn extract(
+        &self,
+        url: &Url,
+        content_type: &Mime,
+        body: &[u8],
+    ) -> Result<Extracted, CrawlError> {
+        let mut extracted = Extracted::default();
+        for (loc, priority) in sitemap::parse(&decode(url, content_type, body, false)) {
+            if let Ok(link) = url.join(&loc) {
+                if let Some(priority) = priority {
+                    extracted.scores.insert(link.clone(), priority);
+                }
+                extracted.links.insert(link);
+            }
+        }
+        Ok(extracted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn extract(content_type: &str, body: &[u8]) -> Result<Extracted, CrawlError> {
+        let url = Url::parse("https://example.com/docs/").unwrap();
+        let content_type: Mime = content_type.parse().unwrap();
+        let extractors = extractors();
+        let extractor = extractors.iter().find(|x| x.accepts(&content_type));
+        extractor.unwrap().extract(&url, &content_type, body)
+    }
+
+    #[test]
+    fn test_extractors() {
+        let url = |s: &str| Url::parse(s).unwrap();
+
+        let html = extract("text/html", b"<title>Docs</title><a href=\"a\">A</a>").unwrap();
+        assert_eq!(html.title.as_deref(), Some("Docs"));
+        assert_eq!(
+            html.links,
+            vec![url("https://example.com/docs/a")]
+                .into_iter()
+                .collect()
+        );
+
+        let text = b"See https://a.com/x, and\n\n(https://b.com/) or ftp://c.com/";
+        let text = extract("text/plain; charset=utf-8", text).unwrap();
+        assert_eq!(
+            text.text,
+            "See https://a.com/x, and (https://b.com/) or ftp://c.com/"
+        );
+        let expected = vec![url("https://a.com/x"), url("https://b.com/")];
+        assert_eq!(text.links, expected.into_iter().collect());
+
+        let pdf = include_bytes!("../tests/fixtures/site/report.pdf");
+        let pdf = extract("application/pdf", pdf).unwrap();
+        assert_eq!(pdf.title.as_deref(), Some("Quarterly Report"));
+        assert_eq!(pdf.text, "Quarterly report, see the about page");
+        let expected = vec![url("https://example.com/about.html")];
+        assert_eq!(pdf.links, expected.into_iter().collect());
+
+        let sitemap = b"<urlset><url><loc>/a</loc><priority>0.8</priority></url></urlset>";
+        let sitemap = extract("application/xml", sitemap).unwrap();
+        let a = url("https://example.com/a");
+        assert_eq!(sitemap.links, vec![a.clone()].into_iter().collect());
+        assert_eq!(sitemap.scores[&a], 0.8);
+
+        let invalid = extract("application/pdf", b"%PDF-1.4");
+        assert_eq!(invalid.unwrap_err(), CrawlError::DecodeError);
+
+        let image: Mime = "image/png".parse().unwrap();
+        assert!(extractors().iter().all(|x| !x.accepts(&image)));
+    }
+}
@@ -0,0 +1,10 @@
+pub use delegate::Delegate;
+
+mod crawler;
+mod decoder;
+mod delegate;
+mod extract;
+pub mod metrics;
+mod parser;
+mod render;
+mod sitemap;
@@ -1,72 +1,83 @@
-use async_trait::async_trait;
-use crawler::CrawlError;
-use log::{error, info};
-use reqwest::Url;
-use shared::dao::{LinkDao, LinkDaoDynamo};
-use shared::mq::*;
-use std::collections::HashSet;
+use crawler::{metrics, Delegate};
+use log::{error, info, warn};
+use shared::content::{ContentStore, ContentStoreS3};
+use shared::dao::{JobDaoDynamo, LinkDaoDynamo};
+use shared::mq::{QueueConnection, Shutdown};
+use shared::search::{SearchIndex, SearchIndexElastic};
+use shutdown::{GracefulShutdown, Phase};
 use std::error::Error;
+use std::net::SocketAddr;
+use std::time::Duration;
+use url::Url;
 
-mod crawler;
-mod decoder;
-mod parser;
-
-struct Delegate {
-    dao: LinkDaoDynamo,
-    channel: RabbitMQChannel,
-}
-
-#[async_trait(?Send)]
-impl ConsumerDelegate for Delegate {
-    async fn consume(&self, message: Message) -> Result<(), Box<dyn Error>> {
-        println!("{}", &message.url);
-        if self.dao.get_links(&message.url).await?.is_some() {
-            info!("Already indexed {}", &message.url);
-        } else {
-            let base = Url::parse(&message.url)?;
-
-            let urls = match crawler::crawl(&base).await {
-                Ok(urls) => urls,
-                Err(CrawlError::NonHtmlContent) => Default::default(),
-                Err(CrawlError::DecodeError) => {
-                    error!("Error decoding url content: {}", message.url);
-                    Default::default()
-                }
-                Err(e) => return Err(e.into()),
-            };
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let config: shared::config::Config = settings::load();
+    let _exporter = telemetry::trace::init("crawler", env!("CARGO_PKG_VERSION"), &config.tracing);
 
-            let filtered_urls: HashSet<String> = urls
-                .iter()
-                .filter(|x| x.origin() == base.origin())
-                .map(|x| x.to_string())
-                .collect();
+    let listen: SocketAddr = config.metrics.listen.parse()?;
+    tokio::spawn(async move {
+        if let Err(e) = metrics::serve(listen).await {
+            error!("Metrics server error: {}", e);
+        }
+    });
 
-            let links = urls.iter().map(|x| x.to_string()).collect();
-            self.dao.set_links(message.url, links).await?;
+    let connection = QueueConnection::new(&config);
+    let send = connection.channel();
+    let recv = connection.channel();
+    let dao = LinkDaoDynamo::new(&config.dynamo);
+    let jobs = JobDaoDynamo::new(&config.dynamo);
+    let content = config.content.bucket.clone().map(|bucket| {
+        Box::new(ContentStoreS3::new(&config.content, bucket)) as Box<dyn ContentStore>
+    });
+    let search =
+        config.search.url.clone().map(|url| {
+            Box::new(SearchIndexElastic::new(&config.search, url)) as Box<dyn SearchIndex>
+        });
+    let filter = config.filter.url_filter()?;
+    let deadline = Duration::from_secs(config.fetch.shutdown);
 
-            let crawled = self.dao.get_multiple(&filtered_urls).await?;
-            for next in filtered_urls.difference(&crawled) {
-                println!("{}", next);
-                self.channel.queue_index(next.clone()).await?;
-            }
-        }
-        Ok(())
+    let mut delegate = Delegate::new(
+        Box::new(dao),
+        Box::new(jobs),
+        content,
+        search,
+        send,
+        config.fetch,
+        filter,
+    );
+    if let Some(url) = &config.render.url {
+        delegate = delegate.with_renderer(&config.render, Url::parse(url)?);
     }
-}
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn Error>> {
-    env_logger::init();
-    let config = shared::config::Config::from_env().unwrap();
-    let connection = RabbitMQConnection::new(&config.rabbit);
-    let send = RabbitMQChannel::new(&connection);
-    let recv = RabbitMQChannel::new(&connection);
-    let dao = LinkDaoDynamo::new(&config.dynamo);
+    let consumer = recv.consume(Box::new(delegate)).await?;
+    let (trigger, stop) = Shutdown::new();
 
-    let delegate = Box::new(Delegate { dao, channel: send });
+    // On being asked to stop, no more messages are taken, and those being processed
+    // are given until the deadline to finish
+    let mut closed = Ok(());
+    let shutdown = GracefulShutdown::new(deadline)
+        .on(Phase::StopIntake, async {
+            info!(
+                "Shutting down, waiting {:?} for messages in flight",
+                deadline
+            );
+            trigger.trigger()
+        })
+        // Abandoned messages are returned to the queue to be processed by another worker
+        .on(Phase::Close, async { closed = connection.close().await });
 
-    let res = recv.consume(delegate).await?;
-    res.block_on().await;
+    if shutdown
+        .run_until_signal(consumer.block_on(stop))
+        .await
+        .is_none()
+    {
+        warn!(
+            "Messages still in flight after {:?}, abandoning them",
+            deadline
+        );
+    }
 
+    closed?;
     Ok(())
 }
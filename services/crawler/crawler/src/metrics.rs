@@ -0,0 +1,62 @@
+use std::convert::Infallible;
+use std::net::SocketAddr;
+
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use lazy_static::lazy_static;
+use prometheus::{
+    register_histogram, register_int_counter, register_int_counter_vec, Histogram, IntCounter,
+    IntCounterVec,
+};
+
+lazy_static! {
+    pub(crate) static ref PAGES_FETCHED: IntCounter = register_int_counter!(
+        "crawler_pages_fetched_total",
+        "The number of pages fetched, after following any redirects"
+    )
+    .unwrap();
+    pub(crate) static ref RESPONSES: IntCounterVec = register_int_counter_vec!(
+        "crawler_responses_total",
+        "The number of responses received, including redirects, by status code",
+        &["status"]
+    )
+    .unwrap();
+    pub(crate) static ref FETCH_DURATION: Histogram = register_histogram!(
+        "crawler_fetch_duration_seconds",
+        "The time taken to receive the headers of each response"
+    )
+    .unwrap();
+    pub(crate) static ref BYTES_DOWNLOADED: IntCounter = register_int_counter!(
+        "crawler_bytes_downloaded_total",
+        "The number of bytes of page content downloaded"
+    )
+    .unwrap();
+    pub(crate) static ref PARSE_FAILURES: IntCounter = register_int_counter!(
+        "crawler_parse_failures_total",
+        "The number of pages whose content could not be decoded"
+    )
+    .unwrap();
+}
+
+async fn handle(request: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let response = match (request.method(), request.uri().path()) {
+        (&Method::GET, "/metrics") => match telemetry::encode() {
+            Ok(metrics) => Response::new(Body::from(metrics)),
+            Err(_) => Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::empty())
+                .unwrap(),
+        },
+        _ => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::empty())
+            .unwrap(),
+    };
+    Ok(response)
+}
+
+/// Serves the Prometheus metrics of the process on `addr`, at `/metrics`
+pub async fn serve(addr: SocketAddr) -> Result<(), hyper::Error> {
+    let make_service = make_service_fn(|_| async { Ok::<_, Infallible>(service_fn(handle)) });
+    Server::try_bind(&addr)?.serve(make_service).await
+}
@@ -1,9 +1,13 @@
-use html5ever::local_name;
 use html5ever::tendril::*;
-use html5ever::tokenizer::TagKind::StartTag;
-use html5ever::tokenizer::{BufferQueue, Token, TokenSink, TokenSinkResult, Tokenizer};
+use html5ever::tokenizer::states::RawKind;
+use html5ever::tokenizer::TagKind::{EndTag, StartTag};
+use html5ever::tokenizer::{BufferQueue, Tag, Token, TokenSink, TokenSinkResult, Tokenizer};
+use html5ever::{local_name, LocalName};
 use reqwest::Url;
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashSet};
+use tracing::warn;
+
+use crate::extract::{collapse, Extracted, Text};
 
 pub(crate) struct Parser {
     tokenizer: Tokenizer<Sink>,
@@ -24,15 +28,41 @@ impl Parser {
         assert!(self.queue.is_empty());
     }
 
-    pub(crate) fn finalize(mut self) -> HashSet<Url> {
+    pub(crate) fn finalize(mut self) -> Extracted {
         self.tokenizer.end();
-        self.tokenizer.sink.links
+        let sink = self.tokenizer.sink;
+        let title = sink.title.finish();
+
+        Extracted {
+            links: sink.links,
+            canonical: sink.canonical,
+            title: if title.is_empty() { None } else { Some(title) },
+            description: sink.description,
+            language: sink.language,
+            open_graph: sink.open_graph,
+            text: sink.text.finish(),
+            scores: Default::default(),
+        }
     }
 }
 
+/// The element whose raw text content is being tokenized
+enum RawElement {
+    /// An element whose content is not visible, such as a script
+    Hidden,
+    Title,
+}
+
 pub struct Sink {
     base: Url,
     links: HashSet<Url>,
+    canonical: Option<Url>,
+    title: Text,
+    description: Option<String>,
+    language: Option<String>,
+    open_graph: BTreeMap<String, String>,
+    text: Text,
+    raw: Option<RawElement>,
 }
 
 impl Sink {
@@ -40,34 +70,199 @@ impl Sink {
         Sink {
             base,
             links: Default::default(),
+            canonical: None,
+            title: Default::default(),
+            description: None,
+            language: None,
+            open_graph: Default::default(),
+            text: Default::default(),
+            raw: None,
         }
     }
+
+    /// Returns the URL of the `href` attribute of `tag`, if any
+    fn href(&self, tag: &Tag) -> Option<Url> {
+        let link = tag
+            .attrs
+            .iter()
+            .find(|x| x.name.local == local_name!("href"))?;
+
+        match self.base.join(&link.value) {
+            Ok(v) => Some(v),
+            Err(e) => {
+                warn!(base = %self.base, href = &*link.value, error = %e, "Invalid href");
+                None
+            }
+        }
+    }
+
+    fn process_link(&mut self, tag: &Tag) {
+        if let Some(link) = self.href(tag) {
+            self.links.insert(link);
+        }
+    }
+
+    /// Records the URL of a `<link rel="canonical">` tag, the first taking precedence
+    fn process_canonical(&mut self, tag: &Tag) {
+        let canonical = tag.attrs.iter().any(|x| {
+            x.name.local == local_name!("rel")
+                && x.value
+                    .split_ascii_whitespace()
+                    .any(|x| x.eq_ignore_ascii_case("canonical"))
+        });
+
+        if canonical && self.canonical.is_none() {
+            self.canonical = self.href(tag);
+        }
+    }
+
+    /// Records the language of an `<html lang>` tag
+    fn process_html(&mut self, tag: &Tag) {
+        if let Some(lang) = attr(tag, local_name!("lang")) {
+            self.language = Some(lang.to_string()).filter(|x| !x.is_empty());
+        }
+    }
+
+    /// Records the description or Open Graph property of a `<meta>` tag, the first of
+    /// each taking precedence
+    fn process_meta(&mut self, tag: &Tag) {
+        let content = match attr(tag, local_name!("content")) {
+            Some(content) => collapse(content),
+            None => return,
+        };
+        if content.is_empty() {
+            return;
+        }
+
+        let name = attr(tag, local_name!("name")).map(str::to_ascii_lowercase);
+        if name.as_deref() == Some("description") && self.description.is_none() {
+            self.description = Some(content);
+            return;
+        }
+
+        // Open Graph properties are given by the `property` attribute, though some
+        // pages use `name` instead
+        let property = attr(tag, local_name!("property"))
+            .map(str::to_ascii_lowercase)
+            .or(name);
+        if let Some(property) = property.filter(|x| x.starts_with("og:")) {
+            self.open_graph.entry(property).or_insert(content);
+        }
+    }
+}
+
+/// Returns the value of the attribute of `tag` named `name`, trimmed, if any
+fn attr(tag: &Tag, name: LocalName) -> Option<&str> {
+    tag.attrs
+        .iter()
+        .find(|x| x.name.local == name)
+        .map(|x| x.value.trim())
+}
+
+/// Returns true if `tag` is typically displayed inline with the surrounding text
+fn is_inline(tag: &Tag) -> bool {
+    matches!(
+        tag.name,
+        local_name!("a")
+            | local_name!("abbr")
+            | local_name!("b")
+            | local_name!("code")
+            | local_name!("em")
+            | local_name!("i")
+            | local_name!("mark")
+            | local_name!("small")
+            | local_name!("span")
+            | local_name!("strong")
+            | local_name!("sub")
+            | local_name!("sup")
+            | local_name!("u")
+    )
 }
 
 impl TokenSink for Sink {
     type Handle = ();
 
     fn process_token(&mut self, token: Token, _line_number: u64) -> TokenSinkResult<()> {
-        if let Token::TagToken(tag) = token {
-            if tag.kind == StartTag && tag.name == local_name!("a") {
-                let value = tag
-                    .attrs
-                    .into_iter()
-                    .find(|x| x.name.local == local_name!("href"))
-                    .map(|x| x.value.to_string());
-
-                if let Some(link) = value {
-                    match self.base.join(&link) {
-                        Ok(v) => {
-                            self.links.insert(v);
+        match token {
+            Token::TagToken(tag) => {
+                // Text in different blocks is displayed apart, and so forms separate words
+                if !is_inline(&tag) {
+                    self.text.separate();
+                }
+
+                match tag.kind {
+                    StartTag => match tag.name {
+                        local_name!("a") => self.process_link(&tag),
+                        local_name!("link") => self.process_canonical(&tag),
+                        local_name!("html") => self.process_html(&tag),
+                        local_name!("meta") => self.process_meta(&tag),
+                        // The tokenizer must be told to treat the content of these
+                        // elements as text, which would otherwise be parsed as markup
+                        local_name!("script") => {
+                            self.raw = Some(RawElement::Hidden);
+                            return TokenSinkResult::RawData(RawKind::ScriptData);
+                        }
+                        local_name!("style") | local_name!("noscript") => {
+                            self.raw = Some(RawElement::Hidden);
+                            return TokenSinkResult::RawData(RawKind::Rawtext);
                         }
-                        Err(e) => {
-                            println!("Invalid href: {}", e);
+                        local_name!("title") => {
+                            self.raw = Some(RawElement::Title);
+                            return TokenSinkResult::RawData(RawKind::Rcdata);
                         }
-                    }
+                        _ => {}
+                    },
+                    // A raw text element can only be ended by its own end tag
+                    EndTag => self.raw = None,
                 }
             }
+            Token::CharacterTokens(text) => match self.raw {
+                Some(RawElement::Hidden) => {}
+                Some(RawElement::Title) => self.title.push(&text),
+                None => self.text.push(&text),
+            },
+            _ => {}
         }
         TokenSinkResult::Continue
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let mut parser = Parser::new(Url::parse("https://example.com/a/").unwrap());
+        parser.feed("<html lang=\"en-GB\"><head><title> Example\n Page </title>");
+        parser.feed("<meta name=\"Description\" content=\" An  example\n\">");
+        parser.feed("<meta property=\"og:title\" content=\"Example\">");
+        parser.feed("<meta property=\"og:title\" content=\"Ignored\">");
+        parser.feed("<meta name=\"og:image\" content=\"/a.png\">");
+        parser.feed("<meta name=\"keywords\" content=\"a, b\">");
+        parser.feed("<link rel=\"stylesheet\" href=\"/style.css\">");
+        parser.feed("<link rel=\"Canonical\" href=\"/a/\">");
+        parser.feed("<style>p { color: red; }</style>");
+        parser.feed("<script>if (a <b) { document.write('<a href=\"/x\">'); }</script>");
+        parser.feed("</head><body><h1>Hello</h1><p>Hello   <b>wor");
+        parser.feed("ld</b>,\nsee <a href=\"b\">here</a></p></body></html>");
+
+        let parsed = parser.finalize();
+        assert_eq!(parsed.title.as_deref(), Some("Example Page"));
+        assert_eq!(parsed.canonical.unwrap().as_str(), "https://example.com/a/");
+        assert_eq!(parsed.text, "Hello Hello world, see here");
+        assert_eq!(parsed.description.as_deref(), Some("An example"));
+        assert_eq!(parsed.language.as_deref(), Some("en-GB"));
+
+        let open_graph: Vec<_> = parsed.open_graph.into_iter().collect();
+        let expected = vec![("og:image", "/a.png"), ("og:title", "Example")];
+        let expected: Vec<_> = expected
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        assert_eq!(open_graph, expected);
+
+        let links: Vec<_> = parsed.links.iter().map(|x| x.as_str()).collect();
+        assert_eq!(links, vec!["https://example.com/a/b"]);
+    }
+}
@@ -0,0 +1,89 @@
+use std::rc::Rc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use log::warn;
+use reqwest::{Client, Response};
+use serde::Serialize;
+use shared::config::RenderConfig;
+use shared::dao::Validators;
+use shared::normalize::within_domain;
+use url::Url;
+
+use crate::crawler::{CrawlError, Fetched, Fetcher};
+
+#[derive(Serialize)]
+struct ContentRequest<'a> {
+    url: &'a str,
+}
+
+/// Renders the HTML pages fetched by another fetcher in a headless browser, so that
+/// links added by scripts are found
+///
+/// Pages are rendered by a service compatible with the `/content` endpoint of
+/// browserless, which loads a page in Chrome and returns its HTML once rendered. A
+/// page that fails to render is read as fetched
+pub struct RenderingFetcher {
+    inner: Rc<dyn Fetcher>,
+    client: Client,
+    endpoint: Url,
+    /// The domains whose pages are always rendered
+    domains: Vec<String>,
+}
+
+impl RenderingFetcher {
+    pub fn new(inner: Rc<dyn Fetcher>, config: &RenderConfig, endpoint: Url) -> RenderingFetcher {
+        let client = reqwest::ClientBuilder::new()
+            .timeout(Duration::from_secs(config.timeout))
+            .build()
+            .expect("Failed to build client");
+
+        RenderingFetcher {
+            inner,
+            client,
+            endpoint,
+            domains: config
+                .domains
+                .split_whitespace()
+                .map(|x| x.to_ascii_lowercase())
+                .collect(),
+        }
+    }
+
+    /// Returns true if `url` is on one of the domains whose pages are always rendered
+    pub fn renders(&self, url: &Url) -> bool {
+        self.domains.iter().any(|x| within_domain(url, x))
+    }
+
+    async fn render(&self, url: &Url) -> Result<Response, CrawlError> {
+        let request = ContentRequest { url: url.as_str() };
+        let response = self
+            .client
+            .post(self.endpoint.clone())
+            .json(&request)
+            .send()
+            .await?;
+        Ok(response.error_for_status()?)
+    }
+}
+
+#[async_trait(?Send)]
+impl Fetcher for RenderingFetcher {
+    async fn fetch(&self, url: &Url, validators: &Validators) -> Result<Fetched, CrawlError> {
+        let mut fetched = self.inner.fetch(url, validators).await?;
+
+        // Redirects, errors and other content are read as fetched
+        if !fetched.is_html() {
+            return Ok(fetched);
+        }
+
+        match self.render(&fetched.url).await {
+            Ok(rendered) => fetched.set_rendered(rendered),
+            Err(e) => warn!(
+                "Failed to render {}, reading as fetched: {}",
+                &fetched.url, e
+            ),
+        }
+        Ok(fetched)
+    }
+}
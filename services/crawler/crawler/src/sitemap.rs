@@ -0,0 +1,95 @@
+use html5ever::tendril::*;
+use html5ever::tokenizer::TagKind::{EndTag, StartTag};
+use html5ever::tokenizer::{BufferQueue, Token, TokenSink, TokenSinkResult, Tokenizer};
+
+/// The element of a sitemap entry whose text is being read
+enum Field {
+    Loc,
+    Priority,
+}
+
+/// Collects the entries of a sitemap, or the sitemaps listed by a sitemap index
+#[derive(Default)]
+struct Sink {
+    field: Option<Field>,
+    loc: String,
+    priority: String,
+    entries: Vec<(String, Option<f64>)>,
+}
+
+impl TokenSink for Sink {
+    type Handle = ();
+
+    fn process_token(&mut self, token: Token, _line_number: u64) -> TokenSinkResult<()> {
+        match token {
+            Token::TagToken(tag) => match (tag.kind, &*tag.name) {
+                (StartTag, "url") | (StartTag, "sitemap") => {
+                    self.loc.clear();
+                    self.priority.clear();
+                }
+                (StartTag, "loc") => self.field = Some(Field::Loc),
+                (StartTag, "priority") => self.field = Some(Field::Priority),
+                (EndTag, "url") | (EndTag, "sitemap") => {
+                    let loc = self.loc.trim();
+                    if !loc.is_empty() {
+                        let priority = self.priority.trim().parse().ok();
+                        self.entries.push((loc.to_string(), priority));
+                    }
+                }
+                _ => self.field = None,
+            },
+            Token::CharacterTokens(text) => match self.field {
+                Some(Field::Loc) => self.loc.push_str(&text),
+                Some(Field::Priority) => self.priority.push_str(&text),
+                None => {}
+            },
+            _ => {}
+        }
+        TokenSinkResult::Continue
+    }
+}
+
+/// Returns the locations of the entries of the sitemap `xml`, along with their
+/// priorities between 0 and 1 if given
+///
+/// The entries of a sitemap index are the sitemaps it lists
+pub(crate) fn parse(xml: &str) -> Vec<(String, Option<f64>)> {
+    let mut tokenizer = Tokenizer::new(Sink::default(), Default::default());
+    let mut queue = BufferQueue::new();
+    queue.push_back(StrTendril::from_slice(xml));
+    let _ = tokenizer.feed(&mut queue);
+    tokenizer.end();
+    tokenizer.sink.entries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let sitemap = r#"<?xml version="1.0" encoding="UTF-8"?>
+<urlset xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+  <url>
+    <loc>https://example.com/</loc>
+    <lastmod>2020-01-01</lastmod>
+    <priority>1.0</priority>
+  </url>
+  <url><loc> https://example.com/a?x=1&amp;y=2 </loc></url>
+  <url><priority>0.5</priority></url>
+</urlset>"#;
+        assert_eq!(
+            parse(sitemap),
+            vec![
+                ("https://example.com/".to_string(), Some(1.)),
+                ("https://example.com/a?x=1&y=2".to_string(), None),
+            ]
+        );
+
+        let index = r#"<sitemapindex xmlns="http://www.sitemaps.org/schemas/sitemap/0.9">
+  <sitemap><loc>https://example.com/sitemap1.xml</loc></sitemap>
+</sitemapindex>"#;
+        let expected = vec![("https://example.com/sitemap1.xml".to_string(), None)];
+        assert_eq!(parse(index), expected);
+    }
+}
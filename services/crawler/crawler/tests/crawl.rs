@@ -0,0 +1,300 @@
+//! Crawls a fixture site served locally, using the in-memory queue and stores
+
+use std::convert::Infallible;
+use std::path::Path;
+
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use url::Url;
+
+use crawler::Delegate;
+use shared::config::{FetchConfig, RenderConfig};
+use shared::dao::{Job, JobDao, JobDaoMemory, LinkDao, LinkDaoMemory, PageMetadata};
+use shared::filter::UrlFilter;
+use shared::graph::{BrokenLink, Graph, GraphFilter};
+use shared::jobs::start_job;
+use shared::mq::{CrawlLimits, MemoryQueue, MessageQueue, Shutdown};
+
+/// The paths of the fixture site that redirect, along with where they redirect to
+const REDIRECTS: &[(&str, &str)] = &[
+    ("old.html", "/about.html"),
+    ("loop/a.html", "b.html"),
+    ("loop/b.html", "a.html"),
+];
+
+/// Returns the path of the fixture file served for the URL `path`
+fn fixture_path(path: &str) -> &str {
+    match path.trim_start_matches('/') {
+        "" => "index.html",
+        path => path,
+    }
+}
+
+/// Returns the response serving the fixture file at `path` within the fixture `dir`,
+/// None if there is no such file
+fn fixture(dir: &str, path: &str) -> Option<Response<Body>> {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+    let body = std::fs::read(root.join(dir).join(path)).ok()?;
+    let content_type = match Path::new(path).extension().and_then(|x| x.to_str()) {
+        Some("html") => "text/html; charset=utf-8",
+        Some("pdf") => "application/pdf",
+        Some("xml") => "application/xml",
+        _ => "application/octet-stream",
+    };
+    let response = Response::builder()
+        .header("Content-Type", content_type)
+        .body(Body::from(body));
+    Some(response.unwrap())
+}
+
+fn not_found() -> Response<Body> {
+    let response = Response::builder()
+        .status(StatusCode::NOT_FOUND)
+        .header("Content-Type", "text/plain")
+        .body(Body::from("Not Found"));
+    response.unwrap()
+}
+
+/// Renders the page named by a request to the renderer, serving the page's fixture
+/// in the `rendered` directory if any, or the page as served otherwise
+async fn render(request: Request<Body>) -> Response<Body> {
+    let body = hyper::body::to_bytes(request.into_body()).await.unwrap();
+    let request: serde_json::Value = serde_json::from_slice(&body).unwrap();
+    let url = Url::parse(request["url"].as_str().unwrap()).unwrap();
+
+    let path = fixture_path(url.path());
+    fixture("rendered", path)
+        .or_else(|| fixture("site", path))
+        .unwrap_or_else(not_found)
+}
+
+/// Serves the file of the fixture site at the request's path, along with a renderer
+/// of its pages at `/content`
+async fn serve(request: Request<Body>) -> Result<Response<Body>, Infallible> {
+    if request.method() == Method::POST && request.uri().path() == "/content" {
+        return Ok(render(request).await);
+    }
+
+    let path = fixture_path(request.uri().path());
+    if let Some((_, location)) = REDIRECTS.iter().find(|(from, _)| *from == path) {
+        let response = Response::builder()
+            .status(StatusCode::MOVED_PERMANENTLY)
+            .header("Location", *location)
+            .body(Body::empty());
+        return Ok(response.unwrap());
+    }
+
+    Ok(fixture("site", path).unwrap_or_else(not_found))
+}
+
+/// Serves the fixture site on a free port, returning its root URL
+fn serve_site() -> Url {
+    let make_service = make_service_fn(|_| async { Ok::<_, Infallible>(service_fn(serve)) });
+    let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+    let url = Url::parse(&format!("http://{}/", server.local_addr())).unwrap();
+
+    tokio::spawn(server);
+    url
+}
+
+/// Runs `job` to completion, rendering pages with the renderer at `renderer` if any,
+/// returning the links recorded and the job as finished
+async fn crawl(job: Job, renderer: Option<Url>) -> (LinkDaoMemory, Job) {
+    let queue = MemoryQueue::new(3, 10);
+    let links = LinkDaoMemory::new();
+    let jobs = JobDaoMemory::new();
+
+    let job = start_job(&jobs, &queue, job).await.unwrap();
+
+    let mut delegate = Delegate::new(
+        Box::new(links.clone()),
+        Box::new(jobs.clone()),
+        None,
+        None,
+        Box::new(queue.clone()),
+        FetchConfig::default(),
+        UrlFilter::default(),
+    );
+    if let Some(renderer) = renderer {
+        delegate = delegate.with_renderer(&RenderConfig::default(), renderer);
+    }
+    let consumer = queue.consume(Box::new(delegate)).await.unwrap();
+    consumer.block_on(Shutdown::never()).await;
+
+    assert!(queue.dead_letters(10).await.unwrap().is_empty());
+    let job = jobs.get_job(&job.job_id).await.unwrap().unwrap();
+    (links, job)
+}
+
+#[tokio::test]
+async fn test_crawl_site() {
+    let root = serve_site();
+    let url = |path: &str| root.join(path).unwrap().to_string();
+
+    let job = Job::new(vec![url("/")], CrawlLimits::default(), None);
+    let (links, job) = crawl(job, None).await;
+
+    // Every page on the site is crawled once, including those that are not HTML
+    assert_eq!(job.crawled, 7);
+    assert_eq!(job.queued, 0);
+    assert_eq!(job.failed, 0);
+
+    // Links are recorded normalized
+    let home = links.get_links(&url("/")).await.unwrap().unwrap();
+    let expected = vec![
+        url("/"),
+        url("/about.html"),
+        url("/blog/post.html"),
+        url("/report.pdf"),
+        url("/old.html"),
+        url("/loop/a.html"),
+        "https://example.com/".to_string(),
+    ];
+    assert_eq!(home, expected.into_iter().collect());
+
+    // Links to other sites are recorded but not followed
+    let external = links.get_crawl("https://example.com/").await.unwrap();
+    assert_eq!(external, None);
+
+    // The links of documents other than HTML pages are followed
+    let report = links.get_links(&url("/report.pdf")).await.unwrap().unwrap();
+    assert_eq!(report, vec![url("/about.html")].into_iter().collect());
+    let crawl = links.get_crawl(&url("/report.pdf")).await.unwrap().unwrap();
+    assert!(crawl.content_hash.is_some());
+
+    // Error pages have no links or content hash
+    let missing = url("/blog/missing.html");
+    let crawl = links.get_crawl(&missing).await.unwrap().unwrap();
+    assert_eq!(crawl.content_hash, None);
+    assert!(links.get_links(&missing).await.unwrap().unwrap().is_empty());
+
+    // The status of each page is recorded, for reporting broken links
+    assert_eq!(crawl.status, Some(404));
+    let graph = Graph::load(&links, &url("/"), &GraphFilter::default(), 100);
+    let broken = graph.await.unwrap().broken_links(&links).await.unwrap();
+    let expected = BrokenLink {
+        url: missing.clone(),
+        status: 404,
+        linked_from: vec![url("/blog/post.html")],
+    };
+    assert_eq!(broken, vec![expected]);
+
+    let post = links.get_crawl(&url("/blog/post.html")).await.unwrap();
+    assert!(post.unwrap().content_hash.is_some());
+
+    // A duplicate of a canonical page only links to the canonical page
+    let print = links.get_links(&url("/blog/post.html?print=1")).await;
+    let expected = vec![url("/blog/post.html")];
+    assert_eq!(print.unwrap().unwrap(), expected.into_iter().collect());
+    let print = links.get_crawl(&url("/blog/post.html?print=1")).await;
+    let metadata = print.unwrap().unwrap().metadata;
+    assert_eq!(metadata.canonical, Some(url("/blog/post.html")));
+
+    // The metadata of pages is recorded
+    let about = links.get_crawl(&url("/about.html")).await.unwrap().unwrap();
+    let expected = PageMetadata {
+        title: Some("About".to_string()),
+        description: Some("Who we are".to_string()),
+        canonical: None,
+        language: Some("en".to_string()),
+        open_graph: vec![("og:type".to_string(), "website".to_string())]
+            .into_iter()
+            .collect(),
+    };
+    assert_eq!(about.metadata, expected);
+
+    // Redirects are recorded, with the page crawled under the URL redirected to
+    let old = links.get_crawl(&url("/old.html")).await.unwrap().unwrap();
+    assert_eq!(old.redirect, Some(url("/about.html")));
+    assert!(links
+        .get_links(&url("/about.html"))
+        .await
+        .unwrap()
+        .is_some());
+
+    // Redirect loops are crawled without links
+    let looped = links.get_links(&url("/loop/a.html")).await.unwrap();
+    assert!(looped.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_crawl_patterns() {
+    let root = serve_site();
+    let url = |path: &str| root.join(path).unwrap().to_string();
+
+    let mut job = Job::new(vec![url("/")], CrawlLimits::default(), None);
+    job.exclude = vec!["*/loop/*".to_string(), "re:\\.pdf$".to_string()];
+    let (links, job) = crawl(job, None).await;
+    assert_eq!(job.queued, 0);
+
+    // Excluded links are recorded but not followed
+    let home = links.get_links(&url("/")).await.unwrap().unwrap();
+    assert!(home.contains(&url("/report.pdf")));
+    for excluded in &["/report.pdf", "/loop/a.html"] {
+        assert_eq!(links.get_crawl(&url(*excluded)).await.unwrap(), None);
+    }
+    assert!(links
+        .get_crawl(&url("/about.html"))
+        .await
+        .unwrap()
+        .is_some());
+}
+
+#[tokio::test]
+async fn test_crawl_sitemap() {
+    let root = serve_site();
+    let url = |path: &str| root.join(path).unwrap().to_string();
+    let limits = CrawlLimits {
+        depth: Some(1),
+        pages: Some(2),
+    };
+
+    // The budget left after the sitemap is spent on the page with the higher priority
+    let job = Job::new(vec![url("/sitemap.xml")], limits, None);
+    let (links, job) = crawl(job, None).await;
+    assert_eq!(job.crawled, 2);
+
+    let sitemap = links
+        .get_links(&url("/sitemap.xml"))
+        .await
+        .unwrap()
+        .unwrap();
+    let expected = vec![url("/about.html"), url("/blog/post.html")];
+    assert_eq!(sitemap, expected.into_iter().collect());
+    assert!(links
+        .get_crawl(&url("/about.html"))
+        .await
+        .unwrap()
+        .is_some());
+    assert_eq!(
+        links.get_crawl(&url("/blog/post.html")).await.unwrap(),
+        None
+    );
+}
+
+#[tokio::test]
+async fn test_crawl_rendered() {
+    let root = serve_site();
+    let url = |path: &str| root.join(path).unwrap().to_string();
+    let limits = CrawlLimits {
+        depth: Some(1),
+        pages: None,
+    };
+
+    let mut job = Job::new(vec![url("/app.html")], limits, None);
+    job.render = true;
+
+    // The links of rendered pages include those added by scripts
+    let (links, crawled) = crawl(job.clone(), Some(root.join("/content").unwrap())).await;
+    let app = links.get_links(&url("/app.html")).await.unwrap().unwrap();
+    assert_eq!(app, vec![url("/about.html")].into_iter().collect());
+    assert_eq!(crawled.crawled, 2);
+
+    // Pages are read as fetched without a renderer, or if they fail to render
+    for renderer in vec![None, Some(root.join("/missing").unwrap())] {
+        let (links, crawled) = crawl(job.clone(), renderer).await;
+        let app = links.get_links(&url("/app.html")).await.unwrap().unwrap();
+        assert!(app.is_empty());
+        assert_eq!(crawled.crawled, 1);
+    }
+}
@@ -0,0 +1,35 @@
+use std::error::Error;
+
+use futures::future::join_all;
+use shared::dao::JobDaoDynamo;
+use shared::mq::QueueConnection;
+
+use crate::schedule::{run, SchedulerConfig};
+
+mod schedule;
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let config: shared::config::Config = settings::load();
+    let _exporter = telemetry::trace::init(
+        "crawler-scheduler",
+        env!("CARGO_PKG_VERSION"),
+        &config.tracing,
+    );
+    let path = std::env::var("SCHEDULER_CONFIG").unwrap_or_else(|_| "scheduler".to_string());
+    let scheduler = SchedulerConfig::load(&path)?;
+
+    let connection = QueueConnection::new(&config);
+    let channel = connection.channel();
+    let jobs = JobDaoDynamo::new(&config.dynamo);
+
+    let mut runs = Vec::with_capacity(scheduler.crawls.len());
+    for crawl in scheduler.crawls {
+        let crawl = crawl.normalize()?;
+        let schedule = crawl.schedule()?;
+        runs.push(run(crawl, schedule, config.limits, &jobs, channel.as_ref()));
+    }
+
+    join_all(runs).await;
+    Ok(())
+}
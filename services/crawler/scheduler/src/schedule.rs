@@ -0,0 +1,187 @@
+use std::str::FromStr;
+
+use chrono::Utc;
+use cron::Schedule;
+use log::{error, info};
+use serde::Deserialize;
+use tokio::time::delay_for;
+
+use shared::dao::{Job, JobDao};
+use shared::filter::UrlFilter;
+use shared::jobs::start_job;
+use shared::mq::{CrawlLimits, MessageQueue};
+use shared::normalize::normalize_str;
+
+/// A crawl started on a recurring schedule
+#[derive(Debug, Clone, Deserialize)]
+pub struct ScheduledCrawl {
+    pub name: String,
+    /// When to start the crawl, as a cron expression with a leading seconds field
+    pub schedule: String,
+    pub seeds: Vec<String>,
+    /// The maximum number of links to follow from each seed
+    #[serde(default)]
+    pub max_depth: Option<u32>,
+    /// The maximum number of pages to crawl across all seeds
+    #[serde(default)]
+    pub max_pages: Option<u64>,
+    /// The age in seconds beyond which pages crawled before are crawled again, None
+    /// if pages are only crawled once
+    #[serde(default)]
+    pub max_age_secs: Option<u64>,
+    /// The patterns one of which a link must match to be followed
+    #[serde(default)]
+    pub include: Vec<String>,
+    /// The patterns of links not to follow
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    /// Whether to render pages in a headless browser before extracting links
+    #[serde(default)]
+    pub render: bool,
+}
+
+impl ScheduledCrawl {
+    pub fn schedule(&self) -> Result<Schedule, String> {
+        Schedule::from_str(&self.schedule)
+            .map_err(|e| format!("invalid schedule for {}: {}", self.name, e))
+    }
+
+    /// Returns this crawl with its seed URLs normalized, failing if any are invalid or
+    /// if its patterns are invalid
+    pub fn normalize(mut self) -> Result<ScheduledCrawl, String> {
+        UrlFilter::new(&self.include, &self.exclude)
+            .map_err(|e| format!("{} for {}", e, self.name))?;
+
+        self.seeds = self
+            .seeds
+            .iter()
+            .map(|x| {
+                normalize_str(x)
+                    .map_err(|e| format!("invalid seed URL {} for {}: {}", x, self.name, e))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(self)
+    }
+
+    /// Returns a new job for this crawl, using `defaults` for unspecified limits
+    fn job(&self, defaults: CrawlLimits) -> Job {
+        let limits = CrawlLimits {
+            depth: self.max_depth.or(defaults.depth),
+            pages: self.max_pages.or(defaults.pages),
+        };
+        let mut job = Job::new(self.seeds.clone(), limits, self.max_age_secs);
+        job.include = self.include.clone();
+        job.exclude = self.exclude.clone();
+        job.render = self.render;
+        job
+    }
+}
+
+#[derive(Debug, Deserialize, Default)]
+#[serde(default)]
+pub struct SchedulerConfig {
+    pub crawls: Vec<ScheduledCrawl>,
+}
+
+impl SchedulerConfig {
+    /// Loads the scheduled crawls from the file at `path`, in any format supported
+    /// by the config crate
+    pub fn load(path: &str) -> Result<Self, ::config::ConfigError> {
+        let mut cfg = ::config::Config::new();
+        cfg.merge(::config::File::with_name(path))?;
+        cfg.try_into()
+    }
+}
+
+/// Starts a job for `crawl` each time `schedule` fires
+///
+/// A run is skipped if the job started by the previous run is still running, so slow
+/// crawls don't pile up
+pub async fn run(
+    crawl: ScheduledCrawl,
+    schedule: Schedule,
+    defaults: CrawlLimits,
+    jobs: &dyn JobDao,
+    queue: &dyn MessageQueue,
+) {
+    let mut previous: Option<String> = None;
+    for next in schedule.upcoming(Utc) {
+        if let Ok(delay) = (next - Utc::now()).to_std() {
+            delay_for(delay).await;
+        }
+
+        if let Some(job_id) = &previous {
+            match jobs.get_job(job_id).await {
+                Ok(Some(job)) if job.running() => {
+                    info!("Job {} for {} still running, skipping", job_id, crawl.name);
+                    continue;
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    error!("Failed to get job {} for {}: {}", job_id, crawl.name, e);
+                    continue;
+                }
+            }
+        }
+
+        match start_job(jobs, queue, crawl.job(defaults)).await {
+            Ok(job) => {
+                info!("Started job {} for {}", job.job_id, crawl.name);
+                previous = Some(job.job_id);
+            }
+            Err(e) => error!("Failed to start job for {}: {}", crawl.name, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ::config::{File, FileFormat};
+
+    use super::*;
+
+    #[test]
+    fn test_config() {
+        let mut cfg = ::config::Config::new();
+        cfg.merge(File::from_str(
+            r#"
+            [[crawls]]
+            name = "example"
+            schedule = "0 0 3 * * *"
+            seeds = ["https://Example.com/#top"]
+            max_pages = 100
+            max_age_secs = 86400
+            exclude = ["*/calendar/*"]
+            "#,
+            FileFormat::Toml,
+        ))
+        .unwrap();
+        let config: SchedulerConfig = cfg.try_into().unwrap();
+
+        let crawl = config.crawls[0].clone().normalize().unwrap();
+        assert!(crawl.schedule().is_ok());
+
+        let defaults = CrawlLimits {
+            depth: Some(3),
+            pages: Some(1000),
+        };
+        let job = crawl.job(defaults);
+        assert_eq!(job.seeds, vec!["https://example.com/".to_string()]);
+        assert_eq!(job.max_depth, Some(3));
+        assert_eq!(job.max_pages, Some(100));
+        assert_eq!(job.max_age_secs, Some(86400));
+        assert_eq!(job.exclude, vec!["*/calendar/*".to_string()]);
+
+        let mut invalid = crawl.clone();
+        invalid.schedule = "every day".to_string();
+        assert!(invalid.schedule().is_err());
+
+        let mut invalid = crawl.clone();
+        invalid.seeds = vec!["example.com".to_string()];
+        assert!(invalid.normalize().is_err());
+
+        let mut invalid = crawl.clone();
+        invalid.include = vec!["re:(".to_string()];
+        assert!(invalid.normalize().is_err());
+    }
+}
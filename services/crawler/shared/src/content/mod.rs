@@ -0,0 +1,115 @@
+use std::collections::BTreeMap;
+use std::error::Error;
+
+use async_trait::async_trait;
+use derive_more::Display;
+use rusoto_core::RusotoError;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+pub use s3::ContentStoreS3;
+
+mod s3;
+
+#[derive(Debug, Display)]
+pub struct ContentError {
+    message: String,
+}
+
+impl std::error::Error for ContentError {}
+
+impl<E: Error + 'static> From<RusotoError<E>> for ContentError {
+    fn from(e: RusotoError<E>) -> Self {
+        ContentError {
+            message: e.to_string(),
+        }
+    }
+}
+
+impl From<serde_json::Error> for ContentError {
+    fn from(e: serde_json::Error) -> Self {
+        ContentError {
+            message: e.to_string(),
+        }
+    }
+}
+
+/// Returns the hex encoded SHA-256 hash of `data`
+fn sha256(data: &[u8]) -> String {
+    hex::encode(Sha256::digest(data))
+}
+
+/// Returns the key the content of `url` is stored under
+pub fn url_key(url: &str) -> String {
+    sha256(url.as_bytes())
+}
+
+/// The response a page's body was fetched with
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Metadata {
+    pub url: String,
+    pub status: u16,
+    pub headers: BTreeMap<String, String>,
+    /// The unix time the page was fetched
+    pub fetched_at: u64,
+    /// The hex encoded SHA-256 hash of the body
+    pub content_hash: String,
+}
+
+/// The content of a fetched page
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub metadata: Metadata,
+    pub body: Vec<u8>,
+}
+
+impl Snapshot {
+    pub fn new(
+        url: String,
+        status: u16,
+        headers: BTreeMap<String, String>,
+        fetched_at: u64,
+        body: Vec<u8>,
+    ) -> Snapshot {
+        let metadata = Metadata {
+            url,
+            status,
+            headers,
+            fetched_at,
+            content_hash: sha256(&body),
+        };
+        Snapshot { metadata, body }
+    }
+}
+
+#[async_trait(?Send)]
+pub trait ContentStore {
+    /// Stores `snapshot`, replacing any previous snapshot of the same URL
+    async fn put(&self, snapshot: &Snapshot) -> Result<(), ContentError>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot() {
+        let snapshot = Snapshot::new(
+            "https://example.com".to_string(),
+            200,
+            Default::default(),
+            1000,
+            b"hello".to_vec(),
+        );
+
+        assert_eq!(
+            snapshot.metadata.content_hash,
+            "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+        );
+        assert_eq!(url_key("https://example.com").len(), 64);
+        assert_ne!(
+            url_key("https://example.com"),
+            url_key("https://example.org")
+        );
+    }
+}
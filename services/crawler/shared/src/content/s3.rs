@@ -0,0 +1,62 @@
+use async_trait::async_trait;
+use rusoto_s3::{PutObjectRequest, S3Client, S3};
+
+use crate::config::ContentConfig;
+use crate::content::{url_key, ContentError, ContentStore, Snapshot};
+
+/// Stores page content in S3
+///
+/// Each page is stored under the hash of its URL, with the body at `<hash>/body` and
+/// its metadata as JSON at `<hash>/metadata.json`. The metadata is written last, and so
+/// its presence implies the body is complete
+pub struct ContentStoreS3 {
+    client: S3Client,
+    bucket: String,
+    prefix: String,
+}
+
+impl ContentStoreS3 {
+    pub fn new(config: &ContentConfig, bucket: String) -> ContentStoreS3 {
+        ContentStoreS3 {
+            client: config.s3_client(),
+            bucket,
+            prefix: config.prefix.clone(),
+        }
+    }
+
+    async fn put_object(
+        &self,
+        key: String,
+        body: Vec<u8>,
+        content_type: Option<String>,
+    ) -> Result<(), ContentError> {
+        self.client
+            .put_object(PutObjectRequest {
+                bucket: self.bucket.clone(),
+                key,
+                body: Some(body.into()),
+                content_type,
+                ..Default::default()
+            })
+            .await?;
+        Ok(())
+    }
+}
+
+#[async_trait(?Send)]
+impl ContentStore for ContentStoreS3 {
+    async fn put(&self, snapshot: &Snapshot) -> Result<(), ContentError> {
+        let key = format!("{}{}", self.prefix, url_key(&snapshot.metadata.url));
+        let content_type = snapshot.metadata.headers.get("content-type").cloned();
+
+        self.put_object(format!("{}/body", key), snapshot.body.clone(), content_type)
+            .await?;
+
+        self.put_object(
+            format!("{}/metadata.json", key),
+            serde_json::to_vec(&snapshot.metadata)?,
+            Some("application/json".to_string()),
+        )
+        .await
+    }
+}
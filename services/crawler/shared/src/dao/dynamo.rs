@@ -1,25 +1,107 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 
+use rusoto_core::RusotoError;
 use rusoto_dynamodb::{
-    AttributeValue, BatchGetItemInput, DynamoDb, DynamoDbClient, GetItemInput, KeysAndAttributes,
-    PutItemInput,
+    AttributeValue, BatchGetItemInput, DeleteItemError, DeleteItemInput, DynamoDb, DynamoDbClient,
+    GetItemInput, KeysAndAttributes, PutItemError, PutItemInput, QueryInput, ScanInput,
+    UpdateItemInput,
 };
+use uuid::Uuid;
 
 use async_trait::async_trait;
 
 use crate::config::DynamoConfig;
-use crate::dao::{LinkDao, LinkDaoError};
+use crate::dao::{
+    domain, unix_time, Crawl, CrawlFilter, CrawlListing, DaoError, LinkDao, PageMetadata,
+    Validators,
+};
 use futures::future::join_all;
 use serde::{Deserialize, Serialize};
 
 const TABLE_NAME: &str = "crawler";
 const PRIMARY_KEY: &str = "Url";
+/// The global secondary index of the crawler table keyed by the `Domain` of each URL,
+/// and sorted by URL, projecting all attributes
+const DOMAIN_INDEX: &str = "Domain";
+/// The table of the URLs being crawled, keyed by URL, whose items expire with the
+/// claims they record through the `ExpiresAt` time to live attribute
+const CLAIM_TABLE_NAME: &str = "crawler_claims";
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 #[serde(rename_all = "PascalCase")]
 struct CrawlEntry {
     url: String,
+    /// The host of `url`, by which crawls are listed, which can't be null as it is a
+    /// key of the domain index
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    domain: Option<String>,
     links: HashSet<String>,
+    /// The unix time the URL was crawled, 0 if crawled before crawl times were recorded
+    #[serde(default)]
+    crawled_at: u64,
+    #[serde(default)]
+    content_hash: Option<String>,
+    /// The URL this redirects to, None if it does not redirect
+    #[serde(default)]
+    redirect: Option<String>,
+    #[serde(default)]
+    etag: Option<String>,
+    #[serde(default)]
+    last_modified: Option<String>,
+    #[serde(default)]
+    status: Option<u16>,
+    #[serde(default)]
+    title: Option<String>,
+    #[serde(default)]
+    description: Option<String>,
+    #[serde(default)]
+    canonical: Option<String>,
+    #[serde(default)]
+    language: Option<String>,
+    #[serde(default)]
+    open_graph: BTreeMap<String, String>,
+}
+
+impl CrawlEntry {
+    /// Returns an entry for `url` crawled now, with no links or metadata
+    fn new(url: String) -> CrawlEntry {
+        CrawlEntry {
+            domain: domain(&url),
+            url,
+            links: Default::default(),
+            crawled_at: unix_time(),
+            content_hash: None,
+            redirect: None,
+            etag: None,
+            last_modified: None,
+            status: None,
+            title: None,
+            description: None,
+            canonical: None,
+            language: None,
+            open_graph: Default::default(),
+        }
+    }
+
+    fn into_crawl(self) -> Crawl {
+        Crawl {
+            crawled_at: self.crawled_at,
+            content_hash: self.content_hash,
+            redirect: self.redirect,
+            validators: Validators {
+                etag: self.etag,
+                last_modified: self.last_modified,
+            },
+            status: self.status,
+            metadata: PageMetadata {
+                title: self.title,
+                description: self.description,
+                canonical: self.canonical,
+                language: self.language,
+                open_graph: self.open_graph,
+            },
+        }
+    }
 }
 
 pub struct LinkDaoDynamo {
@@ -32,10 +114,42 @@ impl LinkDaoDynamo {
         LinkDaoDynamo { client }
     }
 
+    /// Checks that the tables can be described, such as for a readiness probe
+    pub async fn check(&self) -> Result<(), DaoError> {
+        dynamo_util::check_table(&self.client, TABLE_NAME).await?;
+        dynamo_util::check_table(&self.client, CLAIM_TABLE_NAME).await?;
+        Ok(())
+    }
+
+    async fn get_entry(&self, url: &str) -> Result<Option<CrawlEntry>, DaoError> {
+        self.client
+            .get_item(GetItemInput {
+                key: get_key(url),
+                table_name: String::from(TABLE_NAME),
+                ..Default::default()
+            })
+            .await?
+            .item
+            .map(serde_dynamodb::from_hashmap)
+            .transpose()
+            .map_err(DaoError::from)
+    }
+
+    async fn put_entry(&self, entry: &CrawlEntry) -> Result<(), DaoError> {
+        self.client
+            .put_item(PutItemInput {
+                item: serde_dynamodb::to_hashmap(entry)?,
+                table_name: String::from(TABLE_NAME),
+                ..Default::default()
+            })
+            .await?;
+        Ok(())
+    }
+
     async fn get_batch(
         &self,
         keys: &[HashMap<String, AttributeValue>],
-    ) -> Result<Vec<String>, LinkDaoError> {
+    ) -> Result<Vec<CrawlEntry>, DaoError> {
         let request_items = [(
             String::from(TABLE_NAME),
             KeysAndAttributes {
@@ -59,18 +173,29 @@ impl LinkDaoDynamo {
             Some(responses) => match responses.get(TABLE_NAME) {
                 Some(items) => items
                     .iter()
-                    .map(|item| {
-                        let entry: CrawlEntry = serde_dynamodb::from_hashmap(item.clone())?;
-                        Ok(entry.url)
-                    })
+                    .map(|item| serde_dynamodb::from_hashmap(item.clone()).map_err(DaoError::from))
                     .collect::<Result<Vec<_>, _>>(),
-                None => Err(LinkDaoError::new("Response missing table name".to_string())),
+                None => Err(DaoError::new("Response missing table name".to_string())),
             },
             None => Ok(Default::default()),
         }
     }
 }
 
+fn string(value: &str) -> AttributeValue {
+    AttributeValue {
+        s: Some(value.to_string()),
+        ..Default::default()
+    }
+}
+
+fn number(value: u64) -> AttributeValue {
+    AttributeValue {
+        n: Some(value.to_string()),
+        ..Default::default()
+    }
+}
+
 fn get_key(url: &str) -> HashMap<String, AttributeValue> {
     [(
         String::from(PRIMARY_KEY),
@@ -86,44 +211,223 @@ fn get_key(url: &str) -> HashMap<String, AttributeValue> {
 
 #[async_trait(? Send)]
 impl LinkDao for LinkDaoDynamo {
-    async fn get_links(&self, url: &str) -> Result<Option<HashSet<String>>, LinkDaoError> {
-        self.client
-            .get_item(GetItemInput {
-                key: get_key(url),
-                table_name: String::from(TABLE_NAME),
-                ..Default::default()
-            })
-            .await?
-            .item
-            .map_or(Ok(None), |item| {
-                let entry: CrawlEntry = serde_dynamodb::from_hashmap(item)?;
-                Ok(Some(entry.links))
-            })
+    async fn get_links(&self, url: &str) -> Result<Option<HashSet<String>>, DaoError> {
+        Ok(self.get_entry(url).await?.map(|entry| entry.links))
+    }
+
+    async fn get_crawl(&self, url: &str) -> Result<Option<Crawl>, DaoError> {
+        Ok(self.get_entry(url).await?.map(CrawlEntry::into_crawl))
     }
 
-    async fn get_multiple(&self, urls: &HashSet<String>) -> Result<HashSet<String>, LinkDaoError> {
+    async fn get_multiple(
+        &self,
+        urls: &HashSet<String>,
+        since: u64,
+    ) -> Result<HashSet<String>, DaoError> {
         let keys: Vec<HashMap<String, AttributeValue>> = urls.iter().map(|k| get_key(k)).collect();
         let results = join_all(keys.chunks(100).map(|chunk| self.get_batch(chunk))).await;
 
         let mut ret: HashSet<String> = HashSet::with_capacity(urls.len());
         for r in results {
             match r {
-                Ok(set) => ret.extend(set),
+                Ok(entries) => ret.extend(
+                    entries
+                        .into_iter()
+                        .filter(|entry| entry.crawled_at >= since)
+                        .map(|entry| entry.url),
+                ),
                 Err(e) => return Err(e),
             }
         }
         Ok(ret)
     }
 
-    async fn set_links(&self, url: String, links: HashSet<String>) -> Result<(), LinkDaoError> {
-        let entry = CrawlEntry { url, links };
+    async fn set_links(
+        &self,
+        url: String,
+        links: HashSet<String>,
+        content_hash: Option<String>,
+        validators: Validators,
+        status: Option<u16>,
+        metadata: PageMetadata,
+    ) -> Result<(), DaoError> {
+        self.put_entry(&CrawlEntry {
+            links,
+            content_hash,
+            etag: validators.etag,
+            last_modified: validators.last_modified,
+            status,
+            title: metadata.title,
+            description: metadata.description,
+            canonical: metadata.canonical,
+            language: metadata.language,
+            open_graph: metadata.open_graph,
+            ..CrawlEntry::new(url)
+        })
+        .await
+    }
+
+    /// Lists crawls by querying the domain index if filtered by domain, in the order
+    /// of their URLs, otherwise by scanning the table
+    async fn list_crawls(
+        &self,
+        filter: &CrawlFilter,
+        limit: usize,
+        cursor: Option<&str>,
+    ) -> Result<CrawlListing, DaoError> {
+        let mut names = HashMap::with_capacity(2);
+        let mut values = HashMap::with_capacity(2);
+        let mut start = cursor.map(get_key);
+
+        // Status is a reserved word, as is Domain
+        let filter_expression = filter.status.map(|status| {
+            names.insert("#status".to_string(), "Status".to_string());
+            values.insert(":status".to_string(), number(u64::from(status)));
+            "#status = :status".to_string()
+        });
+
+        let (items, last) = match &filter.domain {
+            Some(domain) => {
+                names.insert("#domain".to_string(), "Domain".to_string());
+                values.insert(":domain".to_string(), string(domain));
+                // The key of an index includes the key of the table
+                if let Some(start) = &mut start {
+                    start.insert("Domain".to_string(), string(domain));
+                }
+
+                let output = self
+                    .client
+                    .query(QueryInput {
+                        table_name: String::from(TABLE_NAME),
+                        index_name: Some(String::from(DOMAIN_INDEX)),
+                        key_condition_expression: Some("#domain = :domain".to_string()),
+                        filter_expression,
+                        expression_attribute_names: Some(names),
+                        expression_attribute_values: Some(values),
+                        exclusive_start_key: start,
+                        limit: Some(limit as i64),
+                        ..Default::default()
+                    })
+                    .await?;
+                (output.items, output.last_evaluated_key)
+            }
+            None => {
+                // Expressions may not be given empty maps of names and values
+                let output = self
+                    .client
+                    .scan(ScanInput {
+                        table_name: String::from(TABLE_NAME),
+                        filter_expression,
+                        expression_attribute_names: Some(names).filter(|x| !x.is_empty()),
+                        expression_attribute_values: Some(values).filter(|x| !x.is_empty()),
+                        exclusive_start_key: start,
+                        limit: Some(limit as i64),
+                        ..Default::default()
+                    })
+                    .await?;
+                (output.items, output.last_evaluated_key)
+            }
+        };
+
+        let crawls = items
+            .unwrap_or_default()
+            .into_iter()
+            .map(|item| {
+                let entry: CrawlEntry = serde_dynamodb::from_hashmap(item)?;
+                Ok((entry.url.clone(), entry.into_crawl()))
+            })
+            .collect::<Result<_, DaoError>>()?;
+
+        let cursor = last
+            .and_then(|mut key| key.remove(PRIMARY_KEY))
+            .and_then(|x| x.s);
+        Ok(CrawlListing { crawls, cursor })
+    }
+
+    async fn touch(&self, url: &str) -> Result<(), DaoError> {
+        let values = [(
+            ":crawled_at".to_string(),
+            AttributeValue {
+                n: Some(unix_time().to_string()),
+                ..Default::default()
+            },
+        )]
+        .iter()
+        .cloned()
+        .collect();
+
         self.client
-            .put_item(PutItemInput {
-                item: serde_dynamodb::to_hashmap(&entry)?,
+            .update_item(UpdateItemInput {
+                key: get_key(url),
                 table_name: String::from(TABLE_NAME),
+                update_expression: Some("SET CrawledAt = :crawled_at".to_string()),
+                expression_attribute_values: Some(values),
                 ..Default::default()
             })
             .await?;
         Ok(())
     }
+
+    async fn set_redirect(&self, url: String, target: String) -> Result<(), DaoError> {
+        self.put_entry(&CrawlEntry {
+            redirect: Some(target),
+            ..CrawlEntry::new(url)
+        })
+        .await
+    }
+
+    async fn claim(&self, url: &str, ttl: u64) -> Result<Option<String>, DaoError> {
+        let token = Uuid::new_v4().to_string();
+        let now = unix_time();
+
+        let mut item = get_key(url);
+        item.insert("ClaimToken".to_string(), string(&token));
+        item.insert("ExpiresAt".to_string(), number(now + ttl));
+
+        // Items are deleted some time after they expire, so expired claims may remain
+        let mut values = HashMap::with_capacity(1);
+        values.insert(":now".to_string(), number(now));
+
+        let result = self
+            .client
+            .put_item(PutItemInput {
+                item,
+                table_name: String::from(CLAIM_TABLE_NAME),
+                condition_expression: Some(format!(
+                    "attribute_not_exists({}) OR ExpiresAt <= :now",
+                    PRIMARY_KEY
+                )),
+                expression_attribute_values: Some(values),
+                ..Default::default()
+            })
+            .await;
+
+        match result {
+            Ok(_) => Ok(Some(token)),
+            Err(RusotoError::Service(PutItemError::ConditionalCheckFailed(_))) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn release(&self, url: &str, token: &str) -> Result<(), DaoError> {
+        let mut values = HashMap::with_capacity(1);
+        values.insert(":token".to_string(), string(token));
+
+        let result = self
+            .client
+            .delete_item(DeleteItemInput {
+                key: get_key(url),
+                table_name: String::from(CLAIM_TABLE_NAME),
+                condition_expression: Some("ClaimToken = :token".to_string()),
+                expression_attribute_values: Some(values),
+                ..Default::default()
+            })
+            .await;
+
+        // The claim expired and was taken by another worker, which it is left to
+        match result {
+            Ok(_) | Err(RusotoError::Service(DeleteItemError::ConditionalCheckFailed(_))) => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
 }
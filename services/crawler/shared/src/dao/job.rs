@@ -0,0 +1,131 @@
+use std::collections::HashMap;
+
+use rusoto_core::RusotoError;
+use rusoto_dynamodb::{
+    AttributeValue, DynamoDb, DynamoDbClient, GetItemInput, PutItemInput, UpdateItemError,
+    UpdateItemInput,
+};
+
+use async_trait::async_trait;
+
+use crate::config::DynamoConfig;
+use crate::dao::{DaoError, Job, JobDao, Progress};
+
+const TABLE_NAME: &str = "crawler_jobs";
+const PRIMARY_KEY: &str = "JobId";
+
+pub struct JobDaoDynamo {
+    client: DynamoDbClient,
+}
+
+impl JobDaoDynamo {
+    pub fn new(config: &DynamoConfig) -> JobDaoDynamo {
+        let client = config.dynamo_client();
+        JobDaoDynamo { client }
+    }
+
+    /// Checks that the table can be described, such as for a readiness probe
+    pub async fn check(&self) -> Result<(), DaoError> {
+        dynamo_util::check_table(&self.client, TABLE_NAME).await?;
+        Ok(())
+    }
+}
+
+fn get_key(job_id: &str) -> HashMap<String, AttributeValue> {
+    [(
+        String::from(PRIMARY_KEY),
+        AttributeValue {
+            s: Some(String::from(job_id)),
+            ..Default::default()
+        },
+    )]
+    .iter()
+    .cloned()
+    .collect()
+}
+
+fn number(value: i64) -> AttributeValue {
+    AttributeValue {
+        n: Some(value.to_string()),
+        ..Default::default()
+    }
+}
+
+#[async_trait(?Send)]
+impl JobDao for JobDaoDynamo {
+    async fn create_job(&self, job: &Job) -> Result<(), DaoError> {
+        self.client
+            .put_item(PutItemInput {
+                item: serde_dynamodb::to_hashmap(job)?,
+                table_name: String::from(TABLE_NAME),
+                ..Default::default()
+            })
+            .await?;
+        Ok(())
+    }
+
+    async fn get_job(&self, job_id: &str) -> Result<Option<Job>, DaoError> {
+        self.client
+            .get_item(GetItemInput {
+                key: get_key(job_id),
+                table_name: String::from(TABLE_NAME),
+                consistent_read: Some(true),
+                ..Default::default()
+            })
+            .await?
+            .item
+            .map_or(Ok(None), |item| {
+                Ok(Some(serde_dynamodb::from_hashmap(item)?))
+            })
+    }
+
+    async fn stop_job(&self, job_id: &str) -> Result<bool, DaoError> {
+        let mut values = HashMap::with_capacity(1);
+        values.insert(
+            ":stopped".to_string(),
+            AttributeValue {
+                bool: Some(true),
+                ..Default::default()
+            },
+        );
+
+        let result = self
+            .client
+            .update_item(UpdateItemInput {
+                key: get_key(job_id),
+                table_name: String::from(TABLE_NAME),
+                update_expression: Some("SET Stopped = :stopped".to_string()),
+                condition_expression: Some(format!("attribute_exists({})", PRIMARY_KEY)),
+                expression_attribute_values: Some(values),
+                ..Default::default()
+            })
+            .await;
+
+        match result {
+            Ok(_) => Ok(true),
+            Err(RusotoError::Service(UpdateItemError::ConditionalCheckFailed(_))) => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn record_progress(&self, job_id: &str, progress: Progress) -> Result<(), DaoError> {
+        let mut values = HashMap::with_capacity(3);
+        values.insert(":queued".to_string(), number(progress.queued));
+        values.insert(":crawled".to_string(), number(progress.crawled));
+        values.insert(":failed".to_string(), number(progress.failed));
+
+        self.client
+            .update_item(UpdateItemInput {
+                key: get_key(job_id),
+                table_name: String::from(TABLE_NAME),
+                update_expression: Some(
+                    "ADD Queued :queued, Crawled :crawled, Failed :failed".to_string(),
+                ),
+                condition_expression: Some(format!("attribute_exists({})", PRIMARY_KEY)),
+                expression_attribute_values: Some(values),
+                ..Default::default()
+            })
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,352 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use async_trait::async_trait;
+use uuid::Uuid;
+
+use crate::dao::{
+    unix_time, Crawl, CrawlFilter, CrawlListing, DaoError, Job, JobDao, LinkDao, PageMetadata,
+    Progress, Validators,
+};
+
+struct LinkEntry {
+    links: HashSet<String>,
+    crawl: Crawl,
+}
+
+/// A claim on a URL being crawled
+struct Claim {
+    token: String,
+    /// The unix time the claim expires
+    expires_at: u64,
+}
+
+/// A link store held in memory, allowing the crawler to be run without DynamoDB, such
+/// as in tests
+///
+/// Clones share the same links and claims
+#[derive(Clone, Default)]
+pub struct LinkDaoMemory {
+    entries: Arc<Mutex<HashMap<String, LinkEntry>>>,
+    claims: Arc<Mutex<HashMap<String, Claim>>>,
+}
+
+impl LinkDaoMemory {
+    pub fn new() -> LinkDaoMemory {
+        Default::default()
+    }
+
+    fn entries(&self) -> MutexGuard<'_, HashMap<String, LinkEntry>> {
+        self.entries.lock().expect("link store poisoned")
+    }
+
+    fn claims(&self) -> MutexGuard<'_, HashMap<String, Claim>> {
+        self.claims.lock().expect("claims poisoned")
+    }
+}
+
+#[async_trait(?Send)]
+impl LinkDao for LinkDaoMemory {
+    async fn get_links(&self, url: &str) -> Result<Option<HashSet<String>>, DaoError> {
+        Ok(self.entries().get(url).map(|entry| entry.links.clone()))
+    }
+
+    async fn get_crawl(&self, url: &str) -> Result<Option<Crawl>, DaoError> {
+        Ok(self.entries().get(url).map(|entry| entry.crawl.clone()))
+    }
+
+    async fn get_multiple(
+        &self,
+        urls: &HashSet<String>,
+        since: u64,
+    ) -> Result<HashSet<String>, DaoError> {
+        let entries = self.entries();
+        Ok(urls
+            .iter()
+            .filter(|url| {
+                entries
+                    .get(*url)
+                    .map_or(false, |entry| entry.crawl.crawled_at >= since)
+            })
+            .cloned()
+            .collect())
+    }
+
+    async fn set_links(
+        &self,
+        url: String,
+        links: HashSet<String>,
+        content_hash: Option<String>,
+        validators: Validators,
+        status: Option<u16>,
+        metadata: PageMetadata,
+    ) -> Result<(), DaoError> {
+        let crawl = Crawl {
+            crawled_at: unix_time(),
+            content_hash,
+            redirect: None,
+            validators,
+            status,
+            metadata,
+        };
+        self.entries().insert(url, LinkEntry { links, crawl });
+        Ok(())
+    }
+
+    /// Lists crawls in the order of their URLs
+    async fn list_crawls(
+        &self,
+        filter: &CrawlFilter,
+        limit: usize,
+        cursor: Option<&str>,
+    ) -> Result<CrawlListing, DaoError> {
+        let entries = self.entries();
+        let mut urls: Vec<_> = entries
+            .iter()
+            .filter(|(url, _)| cursor.map_or(true, |cursor| url.as_str() > cursor))
+            .filter(|(url, entry)| filter.allows(url, &entry.crawl))
+            .map(|(url, _)| url)
+            .collect();
+        urls.sort();
+
+        let more = urls.len() > limit;
+        let crawls: Vec<_> = urls
+            .into_iter()
+            .take(limit)
+            .map(|url| (url.clone(), entries[url].crawl.clone()))
+            .collect();
+        let cursor = crawls.last().map(|(url, _)| url.clone()).filter(|_| more);
+        Ok(CrawlListing { crawls, cursor })
+    }
+
+    async fn touch(&self, url: &str) -> Result<(), DaoError> {
+        let crawled_at = unix_time();
+        self.entries()
+            .entry(url.to_string())
+            .and_modify(|entry| entry.crawl.crawled_at = crawled_at)
+            .or_insert_with(|| LinkEntry {
+                links: Default::default(),
+                crawl: Crawl {
+                    crawled_at,
+                    content_hash: None,
+                    redirect: None,
+                    validators: Default::default(),
+                    status: None,
+                    metadata: Default::default(),
+                },
+            });
+        Ok(())
+    }
+
+    async fn set_redirect(&self, url: String, target: String) -> Result<(), DaoError> {
+        let crawl = Crawl {
+            crawled_at: unix_time(),
+            content_hash: None,
+            redirect: Some(target),
+            validators: Default::default(),
+            status: None,
+            metadata: Default::default(),
+        };
+        let links = Default::default();
+        self.entries().insert(url, LinkEntry { links, crawl });
+        Ok(())
+    }
+
+    async fn claim(&self, url: &str, ttl: u64) -> Result<Option<String>, DaoError> {
+        let now = unix_time();
+        let mut claims = self.claims();
+        if matches!(claims.get(url), Some(claim) if claim.expires_at > now) {
+            return Ok(None);
+        }
+
+        let token = Uuid::new_v4().to_string();
+        let claim = Claim {
+            token: token.clone(),
+            expires_at: now + ttl,
+        };
+        claims.insert(url.to_string(), claim);
+        Ok(Some(token))
+    }
+
+    async fn release(&self, url: &str, token: &str) -> Result<(), DaoError> {
+        let mut claims = self.claims();
+        if matches!(claims.get(url), Some(claim) if claim.token == token) {
+            claims.remove(url);
+        }
+        Ok(())
+    }
+}
+
+/// A job store held in memory, allowing the crawler to be run without DynamoDB, such
+/// as in tests
+///
+/// Clones share the same jobs
+#[derive(Clone, Default)]
+pub struct JobDaoMemory {
+    jobs: Arc<Mutex<HashMap<String, Job>>>,
+}
+
+impl JobDaoMemory {
+    pub fn new() -> JobDaoMemory {
+        Default::default()
+    }
+
+    fn jobs(&self) -> MutexGuard<'_, HashMap<String, Job>> {
+        self.jobs.lock().expect("job store poisoned")
+    }
+}
+
+#[async_trait(?Send)]
+impl JobDao for JobDaoMemory {
+    async fn create_job(&self, job: &Job) -> Result<(), DaoError> {
+        self.jobs().insert(job.job_id.clone(), job.clone());
+        Ok(())
+    }
+
+    async fn get_job(&self, job_id: &str) -> Result<Option<Job>, DaoError> {
+        Ok(self.jobs().get(job_id).cloned())
+    }
+
+    async fn stop_job(&self, job_id: &str) -> Result<bool, DaoError> {
+        match self.jobs().get_mut(job_id) {
+            Some(job) => {
+                job.stopped = true;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    async fn record_progress(&self, job_id: &str, progress: Progress) -> Result<(), DaoError> {
+        match self.jobs().get_mut(job_id) {
+            Some(job) => {
+                job.queued += progress.queued;
+                job.crawled += progress.crawled;
+                job.failed += progress.failed;
+                Ok(())
+            }
+            None => Err(DaoError::new(format!("Job {} not found", job_id))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn urls(urls: &[&str]) -> HashSet<String> {
+        urls.iter().map(|x| x.to_string()).collect()
+    }
+
+    #[tokio::test]
+    async fn test_links() {
+        let dao = LinkDaoMemory::new();
+        let links = urls(&["https://example.com/b"]);
+        let url = "https://example.com/a".to_string();
+        let metadata = PageMetadata {
+            title: Some("A".to_string()),
+            ..Default::default()
+        };
+        let (validators, status) = (Default::default(), Some(200));
+        dao.set_links(
+            url,
+            links.clone(),
+            None,
+            validators,
+            status,
+            metadata.clone(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            dao.get_links("https://example.com/a").await.unwrap(),
+            Some(links)
+        );
+        let crawl = dao.get_crawl("https://example.com/a").await.unwrap();
+        assert_eq!(crawl.unwrap().metadata, metadata);
+        assert_eq!(dao.get_links("https://example.com/b").await.unwrap(), None);
+
+        let queried = urls(&["https://example.com/a", "https://example.com/b"]);
+        let crawled = dao.get_multiple(&queried, 0).await.unwrap();
+        assert_eq!(crawled, urls(&["https://example.com/a"]));
+
+        // Crawls before `since` are stale
+        let crawled = dao.get_multiple(&queried, unix_time() + 60).await.unwrap();
+        assert!(crawled.is_empty());
+    }
+
+    /// Returns the URLs of the first two crawls listed, and the cursor of the next
+    async fn list(
+        dao: &LinkDaoMemory,
+        filter: &CrawlFilter,
+        cursor: Option<&str>,
+    ) -> (Vec<String>, Option<String>) {
+        let listing = dao.list_crawls(filter, 2, cursor).await.unwrap();
+        let urls = listing.crawls.into_iter().map(|(url, _)| url).collect();
+        (urls, listing.cursor)
+    }
+
+    #[tokio::test]
+    async fn test_list_crawls() {
+        let dao = LinkDaoMemory::new();
+        let pages = vec![
+            ("https://a.com/1", 200),
+            ("https://a.com/2", 404),
+            ("https://a.com/3", 200),
+            ("https://sub.a.com/", 200),
+            ("https://b.com/", 200),
+        ];
+        for (url, status) in pages {
+            let (links, validators, metadata) = Default::default();
+            dao.set_links(url.into(), links, None, validators, Some(status), metadata)
+                .await
+                .unwrap();
+        }
+
+        let filter = CrawlFilter {
+            domain: Some("a.com".to_string()),
+            status: None,
+        };
+        let (urls, cursor) = list(&dao, &filter, None).await;
+        assert_eq!(urls, vec!["https://a.com/1", "https://a.com/2"]);
+        let (urls, cursor) = list(&dao, &filter, cursor.as_deref()).await;
+        assert_eq!(urls, vec!["https://a.com/3"]);
+        assert_eq!(cursor, None);
+
+        let filter = CrawlFilter {
+            domain: None,
+            status: Some(404),
+        };
+        let (urls, cursor) = list(&dao, &filter, None).await;
+        assert_eq!(urls, vec!["https://a.com/2"]);
+        assert_eq!(cursor, None);
+    }
+
+    #[tokio::test]
+    async fn test_claim() {
+        let dao = LinkDaoMemory::new();
+        let url = "https://example.com/a";
+
+        // A URL can only be claimed once until the claim is released
+        let token = dao.claim(url, 60).await.unwrap().unwrap();
+        assert_eq!(dao.claim(url, 60).await.unwrap(), None);
+        assert!(dao
+            .claim("https://example.com/b", 60)
+            .await
+            .unwrap()
+            .is_some());
+
+        // Only the holder of a claim can release it
+        dao.release(url, "other").await.unwrap();
+        assert_eq!(dao.claim(url, 60).await.unwrap(), None);
+        dao.release(url, &token).await.unwrap();
+        let token = dao.claim(url, 0).await.unwrap().unwrap();
+
+        // Expired claims can be taken by others, and no longer released
+        let taken = dao.claim(url, 60).await.unwrap().unwrap();
+        dao.release(url, &token).await.unwrap();
+        assert_eq!(dao.claim(url, 60).await.unwrap(), None);
+        dao.release(url, &taken).await.unwrap();
+    }
+}
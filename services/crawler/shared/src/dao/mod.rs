@@ -4,34 +4,52 @@ use rusoto_core::RusotoError;
 
 use async_trait::async_trait;
 use derive_more::Display;
-use std::collections::HashSet;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::mq::CrawlLimits;
+use std::collections::{BTreeMap, HashSet};
+use std::time::{SystemTime, UNIX_EPOCH};
+use url::Url;
 
 pub use dynamo::LinkDaoDynamo;
+pub use job::JobDaoDynamo;
+pub use memory::{JobDaoMemory, LinkDaoMemory};
 
 mod dynamo;
+mod job;
+mod memory;
 
 #[derive(Debug, Display)]
-pub struct LinkDaoError {
+pub struct DaoError {
     message: String,
 }
 
-impl LinkDaoError {
-    fn new(message: String) -> LinkDaoError {
-        LinkDaoError { message }
+impl DaoError {
+    fn new(message: String) -> DaoError {
+        DaoError { message }
     }
 }
 
-impl std::error::Error for LinkDaoError {}
+impl std::error::Error for DaoError {}
+
+/// Returns the number of seconds since the unix epoch
+pub fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("system time before unix epoch")
+        .as_secs()
+}
 
-impl From<serde_dynamodb::error::Error> for LinkDaoError {
+impl From<serde_dynamodb::error::Error> for DaoError {
     fn from(e: serde_dynamodb::error::Error) -> Self {
-        LinkDaoError { message: e.message }
+        DaoError { message: e.message }
     }
 }
 
-impl<E: Error + 'static> From<RusotoError<E>> for LinkDaoError {
+impl<E: Error + 'static> From<RusotoError<E>> for DaoError {
     fn from(e: RusotoError<E>) -> Self {
-        LinkDaoError {
+        DaoError {
             message: e.to_string(),
         }
     }
@@ -39,9 +57,217 @@ impl<E: Error + 'static> From<RusotoError<E>> for LinkDaoError {
 
 #[async_trait(?Send)]
 pub trait LinkDao {
-    async fn get_links(&self, url: &str) -> Result<Option<HashSet<String>>, LinkDaoError>;
+    async fn get_links(&self, url: &str) -> Result<Option<HashSet<String>>, DaoError>;
+
+    /// Returns the last crawl of `url`, None if it has not been crawled
+    async fn get_crawl(&self, url: &str) -> Result<Option<Crawl>, DaoError>;
+
+    /// Returns the URLs of `urls` last crawled at or after the unix time `since`
+    async fn get_multiple(
+        &self,
+        urls: &HashSet<String>,
+        since: u64,
+    ) -> Result<HashSet<String>, DaoError>;
+
+    /// Records the links found at `url`, the hash of its content if any, the validators
+    /// and status of the response, the metadata of the page, and that it was crawled now
+    async fn set_links(
+        &self,
+        url: String,
+        links: HashSet<String>,
+        content_hash: Option<String>,
+        validators: Validators,
+        status: Option<u16>,
+        metadata: PageMetadata,
+    ) -> Result<(), DaoError>;
+
+    /// Returns up to `limit` of the crawled URLs allowed by `filter` in no particular
+    /// order, along with their crawls, continuing from the listing that returned
+    /// `cursor` if any
+    ///
+    /// A listing may have fewer than `limit` crawls even if more follow, which they do
+    /// as long as it returns a cursor
+    async fn list_crawls(
+        &self,
+        filter: &CrawlFilter,
+        limit: usize,
+        cursor: Option<&str>,
+    ) -> Result<CrawlListing, DaoError>;
+
+    /// Records that `url` was crawled now, without changing its links
+    async fn touch(&self, url: &str) -> Result<(), DaoError>;
+
+    /// Records that `url` redirects to `target`, and that it was crawled now
+    async fn set_redirect(&self, url: String, target: String) -> Result<(), DaoError>;
+
+    /// Claims `url` for `ttl` seconds, so that no other worker crawls it at the same
+    /// time, returning the token to release the claim with
+    ///
+    /// Returns None if another unexpired claim is held on `url`
+    async fn claim(&self, url: &str, ttl: u64) -> Result<Option<String>, DaoError>;
+
+    /// Releases the claim on `url` made with `token`, if it is still held
+    async fn release(&self, url: &str, token: &str) -> Result<(), DaoError>;
+}
+
+/// The last crawl of a URL
+#[derive(Clone, Debug, PartialEq)]
+pub struct Crawl {
+    /// The unix time of the crawl, 0 if crawled before crawl times were recorded
+    pub crawled_at: u64,
+    /// The hex encoded SHA-256 hash of the page's body, None if it was not recorded
+    /// or the page could not be decoded
+    pub content_hash: Option<String>,
+    /// The URL the page redirected to, under which its links are recorded, None if it
+    /// did not redirect
+    pub redirect: Option<String>,
+    pub validators: Validators,
+    /// The HTTP status of the response, None if it was not recorded
+    pub status: Option<u16>,
+    pub metadata: PageMetadata,
+}
+
+/// The validators of a response, with which a later request can ask for the page only
+/// if it has changed since
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Validators {
+    /// The `ETag` header of the response
+    pub etag: Option<String>,
+    /// The `Last-Modified` header of the response
+    pub last_modified: Option<String>,
+}
+
+/// Returns the lowercase host of `url`, by which crawls are listed
+pub fn domain(url: &str) -> Option<String> {
+    let url = Url::parse(url).ok()?;
+    url.host_str().map(|x| x.to_ascii_lowercase())
+}
+
+/// Limits the crawls listed
+#[derive(Clone, Debug, Default)]
+pub struct CrawlFilter {
+    /// The host of the URLs crawled, not including subdomains, None for any host
+    pub domain: Option<String>,
+    /// The HTTP status of the responses, None for any status
+    pub status: Option<u16>,
+}
+
+impl CrawlFilter {
+    /// Returns true if the crawl of `url` may be listed
+    pub fn allows(&self, url: &str, crawl: &Crawl) -> bool {
+        let domain = self
+            .domain
+            .as_ref()
+            .map_or(true, |x| domain(url).as_ref() == Some(x));
+        let status = self.status.map_or(true, |x| crawl.status == Some(x));
+        domain && status
+    }
+}
+
+/// A page of the crawls listed
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CrawlListing {
+    /// The URLs crawled along with their crawls
+    pub crawls: Vec<(String, Crawl)>,
+    /// The cursor with which to list the crawls that follow, None if there are none
+    pub cursor: Option<String>,
+}
+
+/// The metadata a page declares about itself, empty for documents other than HTML
+/// pages beyond their title
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PageMetadata {
+    pub title: Option<String>,
+    /// The content of `<meta name="description">`
+    pub description: Option<String>,
+    /// The normalized URL of `<link rel="canonical">`
+    pub canonical: Option<String>,
+    /// The language of the page, from the `lang` attribute of `<html>`
+    pub language: Option<String>,
+    /// The Open Graph properties of the page, such as `og:image`, by property name
+    pub open_graph: BTreeMap<String, String>,
+}
+
+/// A crawl of the pages reachable from a set of seed URLs
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Job {
+    pub job_id: String,
+    pub seeds: Vec<String>,
+    pub max_depth: Option<u32>,
+    pub max_pages: Option<u64>,
+    /// The age in seconds beyond which pages crawled before are crawled again, None
+    /// if pages are only crawled once
+    #[serde(default)]
+    pub max_age_secs: Option<u64>,
+    /// The patterns one of which a link must match to be followed, any link if empty
+    #[serde(default)]
+    pub include: Vec<String>,
+    /// The patterns of links not to follow
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    /// If the job's pages are rendered in a headless browser, when a renderer is
+    /// configured
+    #[serde(default)]
+    pub render: bool,
+    /// If the job has been stopped, after which its queued URLs are discarded
+    pub stopped: bool,
+    /// The number of URLs waiting to be crawled
+    pub queued: i64,
+    /// The number of URLs crawled
+    pub crawled: i64,
+    /// The number of URLs that could not be crawled
+    pub failed: i64,
+}
+
+impl Job {
+    /// Creates a job crawling from `seeds`, with a newly generated id
+    pub fn new(seeds: Vec<String>, limits: CrawlLimits, max_age_secs: Option<u64>) -> Job {
+        Job {
+            job_id: Uuid::new_v4().to_string(),
+            seeds,
+            max_depth: limits.depth,
+            max_pages: limits.pages,
+            max_age_secs,
+            include: vec![],
+            exclude: vec![],
+            render: false,
+            stopped: false,
+            queued: 0,
+            crawled: 0,
+            failed: 0,
+        }
+    }
+
+    pub fn limits(&self) -> CrawlLimits {
+        CrawlLimits {
+            depth: self.max_depth,
+            pages: self.max_pages,
+        }
+    }
+
+    /// Returns true if the job has not been stopped and has URLs waiting to be crawled
+    pub fn running(&self) -> bool {
+        !self.stopped && self.queued > 0
+    }
+}
+
+/// A change to the counts of a job's URLs
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Progress {
+    pub queued: i64,
+    pub crawled: i64,
+    pub failed: i64,
+}
+
+#[async_trait(?Send)]
+pub trait JobDao {
+    async fn create_job(&self, job: &Job) -> Result<(), DaoError>;
+
+    async fn get_job(&self, job_id: &str) -> Result<Option<Job>, DaoError>;
 
-    async fn get_multiple(&self, urls: &HashSet<String>) -> Result<HashSet<String>, LinkDaoError>;
+    /// Marks a job as stopped, returning false if it does not exist
+    async fn stop_job(&self, job_id: &str) -> Result<bool, DaoError>;
 
-    async fn set_links(&self, url: String, links: HashSet<String>) -> Result<(), LinkDaoError>;
+    async fn record_progress(&self, job_id: &str, progress: Progress) -> Result<(), DaoError>;
 }
@@ -0,0 +1,108 @@
+use derive_more::Display;
+use regex::Regex;
+
+/// The prefix of patterns that are regular expressions, rather than globs
+const REGEX_PREFIX: &str = "re:";
+
+#[derive(Debug, Display)]
+#[display(fmt = "invalid pattern {}: {}", pattern, message)]
+pub struct PatternError {
+    pattern: String,
+    message: String,
+}
+impl std::error::Error for PatternError {}
+
+/// Returns a regular expression matching the whole of a URL against `glob`, where `*`
+/// matches any characters, including `/`, and `?` matches any one character
+fn glob_regex(glob: &str) -> String {
+    let mut out = String::from("^");
+    for c in glob.chars() {
+        match c {
+            '*' => out.push_str(".*"),
+            '?' => out.push('.'),
+            c => out.push_str(&regex::escape(c.encode_utf8(&mut [0; 4]))),
+        }
+    }
+    out.push('$');
+    out
+}
+
+/// Compiles `pattern`, a regular expression matching anywhere within a URL if prefixed
+/// with `re:`, otherwise a glob matching the whole URL
+fn compile(pattern: &str) -> Result<Regex, PatternError> {
+    let regex = match pattern.strip_prefix(REGEX_PREFIX) {
+        Some(regex) => Regex::new(regex),
+        None => Regex::new(&glob_regex(pattern)),
+    };
+    regex.map_err(|e| PatternError {
+        pattern: pattern.to_string(),
+        message: e.to_string(),
+    })
+}
+
+/// Patterns deciding which of the links found by a crawl are followed, allowing it to
+/// avoid traps such as calendars, logout links and endless combinations of facets
+#[derive(Debug, Clone, Default)]
+pub struct UrlFilter {
+    /// If not empty, the patterns one of which a URL must match to be followed
+    include: Vec<Regex>,
+    /// The patterns of URLs that are not followed, even if included
+    exclude: Vec<Regex>,
+}
+
+impl UrlFilter {
+    pub fn new<S: AsRef<str>>(include: &[S], exclude: &[S]) -> Result<UrlFilter, PatternError> {
+        let compile_all = |patterns: &[S]| {
+            patterns
+                .iter()
+                .map(|x| compile(x.as_ref()))
+                .collect::<Result<Vec<_>, _>>()
+        };
+
+        Ok(UrlFilter {
+            include: compile_all(include)?,
+            exclude: compile_all(exclude)?,
+        })
+    }
+
+    /// Returns true if links to `url` may be followed
+    pub fn allows(&self, url: &str) -> bool {
+        let included = self.include.is_empty() || self.include.iter().any(|x| x.is_match(url));
+        included && !self.exclude.iter().any(|x| x.is_match(url))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter() {
+        let filter = UrlFilter::new(
+            &["https://example.com/*"],
+            &["*/calendar/*", "*logout*", r"re:[?&]color=.*&size="],
+        )
+        .unwrap();
+
+        assert!(filter.allows("https://example.com/"));
+        assert!(filter.allows("https://example.com/blog/post?page=2"));
+        assert!(!filter.allows("https://other.com/"));
+        assert!(!filter.allows("https://example.com/events/calendar/2020/01"));
+        assert!(!filter.allows("https://example.com/account/logout"));
+        assert!(!filter.allows("https://example.com/shop?color=red&size=m"));
+        assert!(filter.allows("https://example.com/shop?color=red"));
+
+        // Globs match the whole URL, with other characters matched literally
+        let filter = UrlFilter::new::<&str>(&[], &["https://example.com/?"]).unwrap();
+        assert!(!filter.allows("https://example.com/a"));
+        assert!(filter.allows("https://example.com/ab"));
+        assert!(filter.allows("https://example.com/"));
+
+        let filter = UrlFilter::new::<&str>(&[], &["*.pdf"]).unwrap();
+        assert!(!filter.allows("https://example.com/report.pdf"));
+        assert!(filter.allows("https://example.com/report_pdf"));
+
+        assert!(UrlFilter::default().allows("https://example.com/"));
+        assert!(UrlFilter::new::<&str>(&["re:("], &[]).is_err());
+    }
+}
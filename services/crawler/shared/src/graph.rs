@@ -0,0 +1,375 @@
+use std::collections::{BTreeSet, HashMap};
+
+use futures::future::try_join_all;
+use futures::stream::{self, StreamExt, TryStreamExt};
+use serde::Serialize;
+use url::Url;
+
+use crate::dao::{DaoError, LinkDao};
+use crate::normalize::within_domain;
+
+/// The number of crawls read at once when finding the broken pages of a graph
+const CONCURRENCY: usize = 32;
+
+/// Returns true if a response with `status` means links to the page are broken
+pub fn is_broken(status: u16) -> bool {
+    status == 404 || status == 410 || status >= 500
+}
+
+/// A page that was broken when crawled, along with the pages in the graph linking to it
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BrokenLink {
+    pub url: String,
+    pub status: u16,
+    /// The URLs of the pages linking to `url`, in the order they were reached
+    pub linked_from: Vec<String>,
+}
+
+/// Limits the pages included in a link graph
+#[derive(Debug, Clone, Default)]
+pub struct GraphFilter {
+    /// The maximum number of links followed from the root, None if unlimited
+    depth: Option<u32>,
+    /// The domain pages must be on, or a subdomain of, None for any domain
+    domain: Option<String>,
+}
+
+impl GraphFilter {
+    pub fn new(depth: Option<u32>, domain: Option<&str>) -> GraphFilter {
+        GraphFilter {
+            depth,
+            domain: domain.map(|x| x.to_ascii_lowercase()),
+        }
+    }
+
+    /// Returns true if the page at `url` may be included in the graph
+    fn allows(&self, url: &str) -> bool {
+        let domain = match &self.domain {
+            Some(domain) => domain,
+            None => return true,
+        };
+
+        match Url::parse(url) {
+            Ok(url) => within_domain(&url, domain),
+            Err(_) => false,
+        }
+    }
+}
+
+/// A graph of pages and the links between them
+#[derive(Debug, Default, PartialEq)]
+pub struct Graph {
+    /// The URLs of the pages, in the order they were reached from the root
+    pub nodes: Vec<String>,
+    /// The links between pages, as indexes into `nodes`
+    pub edges: BTreeSet<(usize, usize)>,
+    /// True if pages were left out to stay within the maximum number of pages
+    pub truncated: bool,
+}
+
+/// Returns the links recorded for `url` in sorted order, or the URL it redirects to
+async fn links(dao: &dyn LinkDao, url: &str) -> Result<Vec<String>, DaoError> {
+    let links = dao.get_links(url).await?.unwrap_or_default();
+    if links.is_empty() {
+        let redirect = dao.get_crawl(url).await?.and_then(|x| x.redirect);
+        return Ok(redirect.into_iter().collect());
+    }
+
+    let mut links: Vec<_> = links.into_iter().collect();
+    links.sort();
+    Ok(links)
+}
+
+impl Graph {
+    /// Returns the graph of the pages allowed by `filter` that are reachable from `root`
+    /// through the links recorded in `dao`, with at most `max_nodes` pages
+    ///
+    /// A page that redirects is linked to the page it redirects to. Pages at the
+    /// maximum depth are only linked to pages already in the graph
+    pub async fn load(
+        dao: &dyn LinkDao,
+        root: &str,
+        filter: &GraphFilter,
+        max_nodes: usize,
+    ) -> Result<Graph, DaoError> {
+        let mut graph = Graph::default();
+        if !filter.allows(root) || max_nodes == 0 {
+            return Ok(graph);
+        }
+
+        let mut index: HashMap<String, usize> = HashMap::new();
+        graph.nodes.push(root.to_string());
+        index.insert(root.to_string(), 0);
+
+        let mut level = vec![0];
+        let mut depth = 0;
+        while !level.is_empty() {
+            let pending = level.iter().map(|x| links(dao, &graph.nodes[*x]));
+            let results = try_join_all(pending).await?;
+            let expand = filter.depth.map_or(true, |max| depth < max);
+
+            let mut next = Vec::new();
+            for (from, links) in level.iter().zip(results) {
+                for link in links.into_iter().filter(|x| filter.allows(x)) {
+                    let to = match index.get(&link) {
+                        Some(to) => *to,
+                        None if !expand => continue,
+                        None if graph.nodes.len() >= max_nodes => {
+                            graph.truncated = true;
+                            continue;
+                        }
+                        None => {
+                            let to = graph.nodes.len();
+                            graph.nodes.push(link.clone());
+                            index.insert(link, to);
+                            next.push(to);
+                            to
+                        }
+                    };
+                    graph.edges.insert((*from, to));
+                }
+            }
+
+            level = next;
+            depth += 1;
+        }
+        Ok(graph)
+    }
+
+    /// Returns the pages of the graph that were broken when last crawled, in the order
+    /// they were reached from the root, using the statuses recorded in `dao`
+    ///
+    /// Pages that have not been crawled, such as those on other sites, are left out
+    pub async fn broken_links(&self, dao: &dyn LinkDao) -> Result<Vec<BrokenLink>, DaoError> {
+        let statuses: Vec<_> = stream::iter(&self.nodes)
+            .map(|url| dao.get_crawl(url))
+            .buffered(CONCURRENCY)
+            .map_ok(|crawl| crawl.and_then(|x| x.status))
+            .try_collect()
+            .await?;
+
+        let broken = statuses
+            .into_iter()
+            .enumerate()
+            .filter_map(|(idx, status)| match status {
+                Some(status) if is_broken(status) => Some((idx, status)),
+                _ => None,
+            })
+            .map(|(to, status)| BrokenLink {
+                url: self.nodes[to].clone(),
+                status,
+                // Edges are ordered by the page they link from
+                linked_from: self
+                    .edges
+                    .iter()
+                    .filter(|(from, x)| *x == to && *from != to)
+                    .map(|(from, _)| self.nodes[*from].clone())
+                    .collect(),
+            });
+        Ok(broken.collect())
+    }
+
+    /// Formats the graph in the DOT language of Graphviz, labelling each node with
+    /// its URL
+    pub fn to_dot(&self) -> String {
+        let mut out = String::from("digraph links {\n");
+        for (idx, url) in self.nodes.iter().enumerate() {
+            let label = url.replace('\\', "\\\\").replace('"', "\\\"");
+            out.push_str(&format!("    n{} [label=\"{}\"];\n", idx, label));
+        }
+        for (from, to) in &self.edges {
+            out.push_str(&format!("    n{} -> n{};\n", from, to));
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    /// Formats the graph as GraphML, with each node's URL as its `url` attribute
+    pub fn to_graphml(&self) -> String {
+        let mut out = String::from(concat!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+            "<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n",
+            "  <key id=\"url\" for=\"node\" attr.name=\"url\" attr.type=\"string\"/>\n",
+            "  <graph id=\"links\" edgedefault=\"directed\">\n",
+        ));
+        for (idx, url) in self.nodes.iter().enumerate() {
+            out.push_str(&format!(
+                "    <node id=\"n{}\"><data key=\"url\">{}</data></node>\n",
+                idx,
+                escape_xml(url)
+            ));
+        }
+        for (from, to) in &self.edges {
+            out.push_str(&format!(
+                "    <edge source=\"n{}\" target=\"n{}\"/>\n",
+                from, to
+            ));
+        }
+        out.push_str("  </graph>\n</graphml>\n");
+        out
+    }
+}
+
+fn escape_xml(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&apos;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::dao::LinkDaoMemory;
+
+    async fn site() -> LinkDaoMemory {
+        let dao = LinkDaoMemory::new();
+        let pages = vec![
+            ("https://a.com/", vec!["https://a.com/b", "https://a.com/c"]),
+            (
+                "https://a.com/b",
+                vec!["https://a.com/", "https://other.com/"],
+            ),
+            ("https://a.com/c", vec!["https://www.a.com/d"]),
+        ];
+        for (url, links) in pages {
+            let links: HashSet<_> = links.into_iter().map(ToString::to_string).collect();
+            let (validators, metadata) = Default::default();
+            dao.set_links(
+                url.to_string(),
+                links,
+                None,
+                validators,
+                Some(200),
+                metadata,
+            )
+            .await
+            .unwrap();
+        }
+        dao
+    }
+
+    #[tokio::test]
+    async fn test_load() {
+        let dao = site().await;
+        let root = "https://a.com/";
+
+        let graph = Graph::load(&dao, root, &GraphFilter::default(), 100)
+            .await
+            .unwrap();
+        assert_eq!(graph.nodes.len(), 5);
+        assert_eq!(graph.edges.len(), 5);
+        assert!(!graph.truncated);
+
+        // Pages at the maximum depth only link to pages already in the graph
+        let filter = GraphFilter::new(Some(1), None);
+        let graph = Graph::load(&dao, root, &filter, 100).await.unwrap();
+        assert_eq!(
+            graph.nodes,
+            vec!["https://a.com/", "https://a.com/b", "https://a.com/c"]
+        );
+        let edges: Vec<_> = graph.edges.into_iter().collect();
+        assert_eq!(edges, vec![(0, 1), (0, 2), (1, 0)]);
+
+        // Subdomains are included
+        let filter = GraphFilter::new(None, Some("A.com"));
+        let graph = Graph::load(&dao, root, &filter, 100).await.unwrap();
+        assert_eq!(graph.nodes.len(), 4);
+        assert!(graph.nodes.iter().all(|x| !x.contains("other.com")));
+
+        let graph = Graph::load(&dao, root, &GraphFilter::default(), 2)
+            .await
+            .unwrap();
+        assert_eq!(graph.nodes.len(), 2);
+        assert!(graph.truncated);
+    }
+
+    #[tokio::test]
+    async fn test_broken_links() {
+        let dao = site().await;
+        let links = HashSet::new();
+        dao.set_links(
+            "https://a.com/b".into(),
+            links.clone(),
+            None,
+            Default::default(),
+            Some(404),
+            Default::default(),
+        )
+        .await
+        .unwrap();
+        dao.set_links(
+            "https://www.a.com/d".into(),
+            links,
+            None,
+            Default::default(),
+            Some(503),
+            Default::default(),
+        )
+        .await
+        .unwrap();
+        dao.set_redirect("https://a.com/c".into(), "https://www.a.com/d".into())
+            .await
+            .unwrap();
+
+        let graph = Graph::load(&dao, "https://a.com/", &GraphFilter::default(), 100)
+            .await
+            .unwrap();
+        let broken = graph.broken_links(&dao).await.unwrap();
+        assert_eq!(
+            broken,
+            vec![
+                BrokenLink {
+                    url: "https://a.com/b".to_string(),
+                    status: 404,
+                    linked_from: vec!["https://a.com/".to_string()],
+                },
+                BrokenLink {
+                    url: "https://www.a.com/d".to_string(),
+                    status: 503,
+                    linked_from: vec!["https://a.com/c".to_string()],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_format() {
+        let graph = Graph {
+            nodes: vec![
+                "https://a.com/".to_string(),
+                "https://a.com/?q=\"x\"&y".to_string(),
+            ],
+            edges: vec![(0, 1)].into_iter().collect(),
+            truncated: false,
+        };
+
+        assert_eq!(
+            graph.to_dot(),
+            concat!(
+                "digraph links {\n",
+                "    n0 [label=\"https://a.com/\"];\n",
+                "    n1 [label=\"https://a.com/?q=\\\"x\\\"&y\"];\n",
+                "    n0 -> n1;\n",
+                "}\n",
+            )
+        );
+
+        let graphml = graph.to_graphml();
+        assert!(graphml.contains(
+            "<node id=\"n1\"><data key=\"url\">https://a.com/?q=&quot;x&quot;&amp;y</data></node>"
+        ));
+        assert!(graphml.contains("<edge source=\"n0\" target=\"n1\"/>"));
+        assert!(graphml.ends_with("</graph>\n</graphml>\n"));
+    }
+}
@@ -0,0 +1,32 @@
+use std::error::Error;
+
+use crate::dao::{Job, JobDao, Progress};
+use crate::mq::{Message, MessageQueue};
+
+/// Records `job` and queues its seed URLs, returning the job as recorded
+///
+/// The seeds are queued one at a time, so that if queueing fails part way the job only
+/// counts the seeds that were queued, rather than waiting forever on the rest
+pub async fn start_job(
+    jobs: &dyn JobDao,
+    queue: &dyn MessageQueue,
+    mut job: Job,
+) -> Result<Job, Box<dyn Error>> {
+    let messages = Message::seeds(&job);
+    job.queued = messages.len() as i64;
+
+    jobs.create_job(&job).await?;
+    let mut unsent = messages.len() as i64;
+    for message in messages {
+        if let Err(e) = queue.queue_index(message).await {
+            let progress = Progress {
+                queued: -unsent,
+                ..Default::default()
+            };
+            jobs.record_progress(&job.job_id, progress).await?;
+            return Err(e.into());
+        }
+        unsent -= 1;
+    }
+    Ok(job)
+}
@@ -1,4 +1,10 @@
 pub mod config;
+pub mod content;
 pub mod dao;
+pub mod filter;
+pub mod graph;
+pub mod jobs;
 pub mod metrics;
 pub mod mq;
+pub mod normalize;
+pub mod search;
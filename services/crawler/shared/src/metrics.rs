@@ -7,6 +7,7 @@ use std::net::UdpSocket;
 use crate::config::MetricsConfig;
 use std::future::Future;
 use std::time::{Duration, Instant};
+use telemetry::Measure;
 
 struct MetricsClient {
     wrapped: StatsdClient,
@@ -94,6 +95,8 @@ impl MetricsService {
         }
     }
 
+    /// Records the duration and outcome of `f`, both to StatsD and to the Prometheus
+    /// metrics of the process
     pub async fn stats<F, R, T, E>(&self, name: String, f: F) -> Result<T, E>
     where
         F: FnOnce() -> R,
@@ -102,7 +105,9 @@ impl MetricsService {
         let start = Instant::now();
         let result = f().await;
 
-        self.client.timer(&name, start.elapsed());
+        let elapsed = start.elapsed();
+        Measure::new("controller", &name).observe(elapsed, result.is_ok());
+        self.client.timer(&name, elapsed);
         if result.is_ok() {
             self.client.success(&name);
         } else {
@@ -0,0 +1,353 @@
+use std::error::Error;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use futures::future::{join_all, select};
+use futures::{pin_mut, StreamExt};
+use log::error;
+use rdkafka::config::ClientConfig;
+use rdkafka::consumer::{CommitMode, Consumer as _, StreamConsumer};
+use rdkafka::error::KafkaError;
+use rdkafka::message::BorrowedMessage;
+use rdkafka::producer::{FutureProducer, FutureRecord};
+use rdkafka::{Message as _, Offset};
+use serde::Serialize;
+use tokio::time::{delay_for, timeout};
+
+use crate::config::KafkaConfig;
+use crate::dao::unix_time;
+use crate::mq::{
+    Consumer, ConsumerDelegate, DeadLetter, MQError, Message, MessageQueue, RetryPolicy, Shutdown,
+};
+
+/// The delay before trying again to relay a retry that failed to send
+const RELAY_ERROR_DELAY: Duration = Duration::from_secs(1);
+
+/// The delay before consuming a message again after failing to schedule its retry
+const RETRY_ERROR_DELAY: Duration = Duration::from_secs(1);
+
+/// How long to wait to seek back to a message to consume it again
+const SEEK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long to wait for the first dead letter, allowing time for partitions to be assigned
+const DEAD_LETTER_WAIT: Duration = Duration::from_secs(10);
+
+/// How long to wait for each subsequent dead letter before assuming there are no more
+const DEAD_LETTER_LINGER: Duration = Duration::from_secs(1);
+
+impl From<KafkaError> for MQError {
+    fn from(e: KafkaError) -> Self {
+        MQError {
+            message: e.to_string(),
+        }
+    }
+}
+
+/// A message queue stored in Kafka
+///
+/// Messages are keyed by URL, and so are spread across the partitions of the topic and
+/// the workers consuming them. Each worker runs a number of consumers within the same
+/// consumer group, each of which processes the messages of its partitions in order.
+///
+/// A failed message is published to a retry topic per delay, `<topic>_retry_<delay>`,
+/// from which it is returned to the topic once the delay has elapsed since it was
+/// published. Messages that fail on every attempt are published to `<topic>_dead`
+#[derive(Clone)]
+pub struct KafkaQueue {
+    config: KafkaConfig,
+    producer: FutureProducer,
+    retry: RetryPolicy,
+}
+
+impl KafkaQueue {
+    pub fn new(config: &KafkaConfig) -> KafkaQueue {
+        let producer = ClientConfig::new()
+            .set("bootstrap.servers", &config.brokers)
+            .create()
+            .expect("Failed to create Kafka producer");
+
+        KafkaQueue {
+            config: config.clone(),
+            producer,
+            retry: RetryPolicy::new(config.attempts, config.backoff),
+        }
+    }
+
+    fn retry_topic(&self, delay: u64) -> String {
+        format!("{}_retry_{}", self.config.topic, delay)
+    }
+
+    fn dead_letter_topic(&self) -> String {
+        format!("{}_dead", self.config.topic)
+    }
+
+    /// Returns a consumer of `topic` in the consumer group `group`, which does not
+    /// commit offsets automatically
+    fn consumer(&self, group: &str, topic: &str) -> Result<StreamConsumer, KafkaError> {
+        let consumer: StreamConsumer = ClientConfig::new()
+            .set("bootstrap.servers", &self.config.brokers)
+            .set("group.id", group)
+            .set("enable.auto.commit", "false")
+            .set("auto.offset.reset", "earliest")
+            .create()?;
+
+        consumer.subscribe(&[topic])?;
+        Ok(consumer)
+    }
+
+    async fn send(&self, topic: &str, key: &str, payload: &[u8]) -> Result<(), MQError> {
+        let record = FutureRecord::to(topic).key(key).payload(payload);
+        self.producer
+            .send(record, Duration::from_secs(0))
+            .await
+            .map_err(|(e, _)| e)?;
+        Ok(())
+    }
+
+    async fn publish<T: Serialize>(
+        &self,
+        topic: &str,
+        key: &str,
+        value: &T,
+    ) -> Result<(), MQError> {
+        let payload = serde_json::to_vec(value)?;
+        self.send(topic, key, &payload).await
+    }
+
+    /// Schedules `message` to be retried after a delay, or publishes it as a dead
+    /// letter if it has no attempts remaining, returning the dead letter if so
+    async fn retry(
+        &self,
+        mut message: Message,
+        error: String,
+    ) -> Result<Option<DeadLetter>, MQError> {
+        message.attempts += 1;
+        match self.retry.delay(message.attempts) {
+            Some(delay) => {
+                let topic = self.retry_topic(delay);
+                self.publish(&topic, &message.url, &message).await?;
+                Ok(None)
+            }
+            None => {
+                let letter = DeadLetter {
+                    message,
+                    error,
+                    failed_at: unix_time(),
+                };
+                let topic = self.dead_letter_topic();
+                self.publish(&topic, &letter.message.url, &letter).await?;
+                Ok(Some(letter))
+            }
+        }
+    }
+
+    /// Reads up to `limit` dead letters, committing their offsets if `commit` is true
+    ///
+    /// Dead letters are read by a consumer group of their own, and so those not
+    /// committed are read again by the next call
+    async fn read_dead_letters(
+        &self,
+        limit: usize,
+        commit: bool,
+    ) -> Result<Vec<DeadLetter>, MQError> {
+        let group = format!("{}_dead", self.config.group);
+        let consumer = self.consumer(&group, &self.dead_letter_topic())?;
+        let mut stream = consumer.start();
+
+        let mut letters = Vec::new();
+        let mut wait = DEAD_LETTER_WAIT;
+        while letters.len() < limit {
+            let message = match timeout(wait, stream.next()).await {
+                Ok(Some(message)) => message?,
+                _ => break,
+            };
+            wait = DEAD_LETTER_LINGER;
+
+            let payload = message.payload().unwrap_or_default();
+            let letter: DeadLetter = serde_json::from_slice(payload)?;
+            if commit {
+                let reset = Message {
+                    attempts: 0,
+                    ..letter.message.clone()
+                };
+                self.publish(&self.config.topic, &reset.url, &reset).await?;
+                consumer.commit_message(&message, CommitMode::Sync)?;
+            }
+            letters.push(letter);
+        }
+        Ok(letters)
+    }
+}
+
+#[async_trait(?Send)]
+impl MessageQueue for KafkaQueue {
+    async fn queue_index(&self, message: Message) -> Result<(), MQError> {
+        self.publish(&self.config.topic, &message.url, &message)
+            .await
+    }
+
+    async fn dead_letters(&self, limit: usize) -> Result<Vec<DeadLetter>, MQError> {
+        self.read_dead_letters(limit, false).await
+    }
+
+    async fn requeue_dead_letters(&self, limit: usize) -> Result<Vec<Message>, MQError> {
+        let letters = self.read_dead_letters(limit, true).await?;
+        Ok(letters
+            .into_iter()
+            .map(|letter| Message {
+                attempts: 0,
+                ..letter.message
+            })
+            .collect())
+    }
+
+    async fn consume(
+        &self,
+        delegate: Box<dyn ConsumerDelegate>,
+    ) -> Result<Box<dyn Consumer>, Box<dyn Error>> {
+        let consumers = (0..self.config.consumers.max(1))
+            .map(|_| self.consumer(&self.config.group, &self.config.topic))
+            .collect::<Result<_, _>>()?;
+
+        let relays = self
+            .retry
+            .delays()
+            .map(|delay| {
+                let topic = self.retry_topic(delay);
+                let group = format!("{}_{}", self.config.group, topic);
+                self.consumer(&group, &topic)
+                    .map(|consumer| (delay, consumer))
+            })
+            .collect::<Result<_, KafkaError>>()?;
+
+        Ok(Box::new(ConsumerKafka {
+            queue: self.clone(),
+            consumers,
+            relays,
+            delegate,
+        }))
+    }
+}
+
+struct ConsumerKafka {
+    queue: KafkaQueue,
+    consumers: Vec<StreamConsumer>,
+    /// The consumers of each retry topic, along with the topic's delay in milliseconds
+    relays: Vec<(u64, StreamConsumer)>,
+    delegate: Box<dyn ConsumerDelegate>,
+}
+
+impl ConsumerKafka {
+    /// Processes a received message, returning false if it was neither processed nor
+    /// scheduled for a retry, and so must be consumed again
+    async fn process(&self, received: &BorrowedMessage<'_>) -> bool {
+        let payload = received.payload().unwrap_or_default();
+        let message: Message = match serde_json::from_slice(payload) {
+            Ok(message) => message,
+            Err(e) => {
+                error!("Failed to deserialize message: {}", e);
+                return true;
+            }
+        };
+
+        if let Err(e) = self.delegate.consume(message.clone()).await {
+            error!("Delegate Error: {}", e);
+            match self.queue.retry(message, e.to_string()).await {
+                Ok(Some(letter)) => self.delegate.dead_lettered(&letter.message).await,
+                Ok(None) => {}
+                Err(e) => {
+                    error!("Failed to schedule retry: {}", e);
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    /// Processes the messages of the partitions assigned to `consumer` in order, until
+    /// `shutdown` is triggered
+    async fn run(&self, consumer: &StreamConsumer, shutdown: Shutdown) {
+        let stream = consumer.start().take_until(shutdown.triggered());
+        pin_mut!(stream);
+        while let Some(received) = stream.next().await {
+            match received {
+                Ok(received) => {
+                    if !self.process(&received).await {
+                        // Committing the message would lose it, so seek back to consume
+                        // it again, as a nack would requeue it with RabbitMQ
+                        let seek = consumer.seek(
+                            received.topic(),
+                            received.partition(),
+                            Offset::Offset(received.offset()),
+                            SEEK_TIMEOUT,
+                        );
+                        if let Err(e) = seek {
+                            error!("Failed to seek to message: {}", e);
+                        }
+                        delay_for(RETRY_ERROR_DELAY).await;
+                        continue;
+                    }
+
+                    if let Err(e) = consumer.commit_message(&received, CommitMode::Async) {
+                        error!("Failed to commit message: {}", e);
+                    }
+                }
+                Err(e) => error!("Kafka Error: {}", e),
+            }
+        }
+    }
+
+    /// Returns the messages of a retry topic to the main topic once `delay` milliseconds
+    /// have elapsed since they were published
+    ///
+    /// Every message of a retry topic has the same delay, and so they become due in the
+    /// order they were published
+    async fn relay(&self, delay: u64, consumer: &StreamConsumer) {
+        let mut stream = consumer.start();
+        while let Some(received) = stream.next().await {
+            let received = match received {
+                Ok(received) => received,
+                Err(e) => {
+                    error!("Kafka Error: {}", e);
+                    continue;
+                }
+            };
+
+            let published = received.timestamp().to_millis().unwrap_or_default().max(0) as u64;
+            let now = unix_time() * 1000;
+            let due = published.saturating_add(delay);
+            if due > now {
+                delay_for(Duration::from_millis(due - now)).await;
+            }
+
+            let key = String::from_utf8_lossy(received.key().unwrap_or_default());
+            let payload = received.payload().unwrap_or_default();
+            // Committing a later message would skip this one, so keep trying
+            while let Err(e) = self
+                .queue
+                .send(&self.queue.config.topic, &key, payload)
+                .await
+            {
+                error!("Failed to relay retry: {}", e);
+                delay_for(RELAY_ERROR_DELAY).await;
+            }
+
+            if let Err(e) = consumer.commit_message(&received, CommitMode::Async) {
+                error!("Failed to commit message: {}", e);
+            }
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl Consumer for ConsumerKafka {
+    async fn block_on(&self, shutdown: Shutdown) {
+        let consumers = join_all(self.consumers.iter().map(|x| self.run(x, shutdown.clone())));
+
+        // Relays may be waiting for a retry to become due, and so are stopped at once,
+        // leaving any retry not yet committed to be relayed again by another worker
+        let relays = join_all(self.relays.iter().map(|(delay, x)| self.relay(*delay, x)));
+        let relays = select(Box::pin(relays), Box::pin(shutdown.triggered()));
+        futures::future::join(consumers, relays).await;
+    }
+}
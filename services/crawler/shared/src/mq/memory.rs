@@ -0,0 +1,302 @@
+use std::collections::VecDeque;
+use std::error::Error;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use async_trait::async_trait;
+use futures::future::select;
+use log::error;
+use tokio::time::{delay_until, Instant};
+
+use crate::dao::unix_time;
+use crate::mq::{
+    Consumer, ConsumerDelegate, DeadLetter, MQError, Message, MessageQueue, RetryPolicy, Shutdown,
+};
+
+#[derive(Default)]
+struct State {
+    queue: VecDeque<Message>,
+    /// Failed messages waiting to be retried, along with when they are due
+    retries: Vec<(Instant, Message)>,
+    dead_letters: VecDeque<DeadLetter>,
+}
+
+/// What a consumer should do next
+enum Next {
+    Process(Message),
+    /// Wait until the next retry is due
+    Wait(Instant),
+    /// Stop, as there are no messages left to process
+    Idle,
+}
+
+/// Removes the first of the messages in `queue` with the highest priority
+fn pop(queue: &mut VecDeque<Message>) -> Option<Message> {
+    let max = queue.iter().map(|x| x.priority).max()?;
+    let idx = queue.iter().position(|x| x.priority == max)?;
+    queue.remove(idx)
+}
+
+/// A message queue held in memory, allowing the crawler to be run without a message
+/// broker, such as in tests
+///
+/// Clones share the same messages. Its consumer processes messages one at a time,
+/// highest priority first, and returns once no messages are queued or waiting to be
+/// retried, or once shut down
+#[derive(Clone)]
+pub struct MemoryQueue {
+    state: Arc<Mutex<State>>,
+    retry: RetryPolicy,
+}
+
+impl MemoryQueue {
+    /// Creates an empty queue, which dead-letters messages that fail `attempts` times
+    /// and waits `backoff` milliseconds before the first retry, doubling with each retry
+    pub fn new(attempts: u32, backoff: u64) -> MemoryQueue {
+        MemoryQueue {
+            state: Default::default(),
+            retry: RetryPolicy::new(attempts, backoff),
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().expect("memory queue poisoned")
+    }
+
+    /// Returns the next message to process, queueing the retries that are due first
+    fn next(&self) -> Next {
+        let mut state = self.state();
+        let now = Instant::now();
+
+        let (due, pending): (Vec<_>, Vec<_>) =
+            state.retries.drain(..).partition(|(at, _)| *at <= now);
+        state.retries = pending;
+        state
+            .queue
+            .extend(due.into_iter().map(|(_, message)| message));
+
+        if let Some(message) = pop(&mut state.queue) {
+            return Next::Process(message);
+        }
+
+        match state.retries.iter().map(|(at, _)| *at).min() {
+            Some(at) => Next::Wait(at),
+            None => Next::Idle,
+        }
+    }
+
+    /// Schedules `message`, which has failed with `error`, to be retried after a delay,
+    /// or moves it to the dead letters if it has no attempts remaining, returning the
+    /// dead letter if so
+    fn retry(&self, mut message: Message, error: String) -> Option<DeadLetter> {
+        message.attempts += 1;
+        let mut state = self.state();
+        match self.retry.delay(message.attempts) {
+            Some(delay) => {
+                let at = Instant::now() + Duration::from_millis(delay);
+                state.retries.push((at, message));
+                None
+            }
+            None => {
+                let letter = DeadLetter {
+                    message,
+                    error,
+                    failed_at: unix_time(),
+                };
+                state.dead_letters.push_back(letter.clone());
+                Some(letter)
+            }
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl MessageQueue for MemoryQueue {
+    async fn queue_index(&self, message: Message) -> Result<(), MQError> {
+        self.state().queue.push_back(message);
+        Ok(())
+    }
+
+    async fn queue_batch(&self, messages: Vec<Message>) -> Result<(), MQError> {
+        self.state().queue.extend(messages);
+        Ok(())
+    }
+
+    async fn dead_letters(&self, limit: usize) -> Result<Vec<DeadLetter>, MQError> {
+        Ok(self
+            .state()
+            .dead_letters
+            .iter()
+            .take(limit)
+            .cloned()
+            .collect())
+    }
+
+    async fn requeue_dead_letters(&self, limit: usize) -> Result<Vec<Message>, MQError> {
+        let mut state = self.state();
+        let count = limit.min(state.dead_letters.len());
+        let requeued: Vec<_> = state
+            .dead_letters
+            .drain(..count)
+            .map(|letter| Message {
+                attempts: 0,
+                ..letter.message
+            })
+            .collect();
+
+        state.queue.extend(requeued.iter().cloned());
+        Ok(requeued)
+    }
+
+    async fn consume(
+        &self,
+        delegate: Box<dyn ConsumerDelegate>,
+    ) -> Result<Box<dyn Consumer>, Box<dyn Error>> {
+        Ok(Box::new(ConsumerMemory {
+            queue: self.clone(),
+            delegate,
+        }))
+    }
+}
+
+struct ConsumerMemory {
+    queue: MemoryQueue,
+    delegate: Box<dyn ConsumerDelegate>,
+}
+
+#[async_trait(?Send)]
+impl Consumer for ConsumerMemory {
+    async fn block_on(&self, shutdown: Shutdown) {
+        while !shutdown.is_triggered() {
+            let message = match self.queue.next() {
+                Next::Process(message) => message,
+                Next::Wait(at) => {
+                    select(delay_until(at), Box::pin(shutdown.clone().triggered())).await;
+                    continue;
+                }
+                Next::Idle => return,
+            };
+
+            if let Err(e) = self.delegate.consume(message.clone()).await {
+                error!("Delegate Error: {}", e);
+                if let Some(letter) = self.queue.retry(message, e.to_string()) {
+                    self.delegate.dead_lettered(&letter.message).await;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::mq::CrawlLimits;
+
+    /// Fails to process every message other than `https://example.com/ok`, recording
+    /// the URLs processed and dead-lettered
+    #[derive(Clone, Default)]
+    struct Recorder {
+        consumed: Rc<RefCell<Vec<String>>>,
+        dead_lettered: Rc<RefCell<Vec<String>>>,
+    }
+
+    #[async_trait(?Send)]
+    impl ConsumerDelegate for Recorder {
+        async fn consume(&self, message: Message) -> Result<(), Box<dyn Error>> {
+            self.consumed.borrow_mut().push(message.url.clone());
+            match message.url.as_str() {
+                "https://example.com/ok" => Ok(()),
+                _ => Err("failed".into()),
+            }
+        }
+
+        async fn dead_lettered(&self, message: &Message) {
+            self.dead_lettered.borrow_mut().push(message.url.clone());
+        }
+    }
+
+    fn message(url: &str) -> Message {
+        Message::seed(url.to_string(), CrawlLimits::default())
+    }
+
+    #[tokio::test]
+    async fn test_retry() {
+        let queue = MemoryQueue::new(3, 10);
+        let batch = vec![
+            message("https://example.com/ok"),
+            message("https://example.com/fail"),
+        ];
+        queue.queue_batch(batch).await.unwrap();
+
+        let recorder = Recorder::default();
+        let consumer = queue.consume(Box::new(recorder.clone())).await.unwrap();
+        consumer.block_on(Shutdown::never()).await;
+
+        // The failing message is attempted three times before being dead-lettered
+        assert_eq!(
+            *recorder.consumed.borrow(),
+            vec![
+                "https://example.com/ok",
+                "https://example.com/fail",
+                "https://example.com/fail",
+                "https://example.com/fail"
+            ]
+        );
+        assert_eq!(
+            *recorder.dead_lettered.borrow(),
+            vec!["https://example.com/fail"]
+        );
+
+        let letters = queue.dead_letters(10).await.unwrap();
+        assert_eq!(letters.len(), 1);
+        assert_eq!(letters[0].message.attempts, 3);
+        assert_eq!(letters[0].error, "failed");
+
+        let requeued = queue.requeue_dead_letters(10).await.unwrap();
+        assert_eq!(requeued, vec![message("https://example.com/fail")]);
+        assert!(queue.dead_letters(10).await.unwrap().is_empty());
+
+        // Requeued messages are attempted again
+        recorder.consumed.borrow_mut().clear();
+        consumer.block_on(Shutdown::never()).await;
+        assert_eq!(recorder.consumed.borrow().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_shutdown() {
+        let queue = MemoryQueue::new(1, 10);
+        let batch = vec![message("https://example.com/ok"); 2];
+        queue.queue_batch(batch).await.unwrap();
+
+        let recorder = Recorder::default();
+        let consumer = queue.consume(Box::new(recorder.clone())).await.unwrap();
+        let (trigger, shutdown) = Shutdown::new();
+        trigger.trigger();
+        consumer.block_on(shutdown).await;
+        assert!(recorder.consumed.borrow().is_empty());
+
+        // Messages not taken before shutting down remain queued
+        consumer.block_on(Shutdown::never()).await;
+        assert_eq!(recorder.consumed.borrow().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_priority() {
+        let queue = MemoryQueue::new(1, 10);
+        let mut low = message("https://example.com/low");
+        low.priority = 1;
+        let batch = vec![low, message("https://example.com/ok")];
+        queue.queue_batch(batch).await.unwrap();
+
+        let recorder = Recorder::default();
+        let consumer = queue.consume(Box::new(recorder.clone())).await.unwrap();
+        consumer.block_on(Shutdown::never()).await;
+        assert_eq!(
+            *recorder.consumed.borrow(),
+            vec!["https://example.com/ok", "https://example.com/low"]
+        );
+    }
+}
@@ -0,0 +1,91 @@
+use std::error::Error;
+
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use prometheus::{register_int_counter, register_int_counter_vec, IntCounter, IntCounterVec};
+
+use crate::mq::{Consumer, ConsumerDelegate, DeadLetter, MQError, Message, MessageQueue};
+
+lazy_static! {
+    static ref PUBLISHED: IntCounter = register_int_counter!(
+        "crawler_messages_published_total",
+        "The number of messages published to the queue"
+    )
+    .unwrap();
+    static ref CONSUMED: IntCounterVec = register_int_counter_vec!(
+        "crawler_messages_consumed_total",
+        "The number of messages consumed from the queue, by whether they were processed",
+        &["result"]
+    )
+    .unwrap();
+    static ref DEAD_LETTERED: IntCounter = register_int_counter!(
+        "crawler_messages_dead_lettered_total",
+        "The number of messages that failed on every attempt"
+    )
+    .unwrap();
+}
+
+/// Counts the messages published to and consumed from the queue it wraps, in the
+/// Prometheus metrics of the process
+pub struct MeteredQueue {
+    inner: Box<dyn MessageQueue>,
+}
+
+impl MeteredQueue {
+    pub fn new(inner: Box<dyn MessageQueue>) -> MeteredQueue {
+        MeteredQueue { inner }
+    }
+}
+
+#[async_trait(?Send)]
+impl MessageQueue for MeteredQueue {
+    async fn queue_index(&self, message: Message) -> Result<(), MQError> {
+        self.inner.queue_index(message).await?;
+        PUBLISHED.inc();
+        Ok(())
+    }
+
+    async fn queue_batch(&self, messages: Vec<Message>) -> Result<(), MQError> {
+        let count = messages.len() as i64;
+        self.inner.queue_batch(messages).await?;
+        PUBLISHED.inc_by(count);
+        Ok(())
+    }
+
+    async fn dead_letters(&self, limit: usize) -> Result<Vec<DeadLetter>, MQError> {
+        self.inner.dead_letters(limit).await
+    }
+
+    async fn requeue_dead_letters(&self, limit: usize) -> Result<Vec<Message>, MQError> {
+        let requeued = self.inner.requeue_dead_letters(limit).await?;
+        PUBLISHED.inc_by(requeued.len() as i64);
+        Ok(requeued)
+    }
+
+    async fn consume(
+        &self,
+        delegate: Box<dyn ConsumerDelegate>,
+    ) -> Result<Box<dyn Consumer>, Box<dyn Error>> {
+        let delegate = Box::new(MeteredDelegate { inner: delegate });
+        self.inner.consume(delegate).await
+    }
+}
+
+struct MeteredDelegate {
+    inner: Box<dyn ConsumerDelegate>,
+}
+
+#[async_trait(?Send)]
+impl ConsumerDelegate for MeteredDelegate {
+    async fn consume(&self, message: Message) -> Result<(), Box<dyn Error>> {
+        let result = self.inner.consume(message).await;
+        let label = if result.is_ok() { "success" } else { "failure" };
+        CONSUMED.with_label_values(&[label]).inc();
+        result
+    }
+
+    async fn dead_lettered(&self, message: &Message) {
+        DEAD_LETTERED.inc();
+        self.inner.dead_lettered(message).await
+    }
+}
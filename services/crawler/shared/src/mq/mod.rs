@@ -1,11 +1,26 @@
 use async_trait::async_trait;
 use derive_more::Display;
 
+mod kafka;
+mod memory;
+mod metered;
 mod rabbitmq;
+mod sqs;
 
+pub use kafka::KafkaQueue;
+pub use memory::MemoryQueue;
+pub use metered::MeteredQueue;
 pub use rabbitmq::{RabbitMQChannel, RabbitMQConnection};
+use rusoto_core::RusotoError;
 use serde::{Deserialize, Serialize};
+pub use sqs::SqsQueue;
+use std::collections::HashMap;
 use std::error::Error;
+use tokio::sync::watch;
+
+use crate::config::{Config, QueueBackend};
+
+use crate::dao::Job;
 
 #[derive(Debug, Display)]
 pub struct MQError {
@@ -21,6 +36,14 @@ impl From<lapin::Error> for MQError {
     }
 }
 
+impl<E: Error + 'static> From<RusotoError<E>> for MQError {
+    fn from(e: RusotoError<E>) -> Self {
+        MQError {
+            message: e.to_string(),
+        }
+    }
+}
+
 impl From<serde_json::Error> for MQError {
     fn from(e: serde_json::Error) -> Self {
         MQError {
@@ -29,14 +52,238 @@ impl From<serde_json::Error> for MQError {
     }
 }
 
-#[derive(Serialize, Deserialize)]
+/// The highest priority of a message, given to the seeds of a crawl
+pub const MAX_PRIORITY: u8 = 9;
+
+/// Returns the priority of a page `depth` links from the seed of its crawl, where
+/// `score` is its importance between 0 and 1 if known, such as its sitemap priority
+///
+/// Pages nearer the seed have higher priorities, with a known score counting equally
+pub fn priority(depth: u32, score: Option<f64>) -> u8 {
+    let by_depth = MAX_PRIORITY.saturating_sub(depth.min(MAX_PRIORITY as u32) as u8);
+    match score {
+        Some(score) => {
+            let by_score = (score.max(0.).min(1.) * MAX_PRIORITY as f64).round() as u8;
+            (by_depth + by_score + 1) / 2
+        }
+        None => by_depth,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Message {
     pub url: String,
+    /// The crawl job this URL was queued by, None if queued outside of a job
+    #[serde(default)]
+    pub job_id: Option<String>,
+    /// The number of links followed from the seed URL of the crawl to reach `url`
+    #[serde(default)]
+    pub depth: u32,
+    /// The maximum depth of URLs the crawl may enqueue, None if unlimited
+    #[serde(default)]
+    pub max_depth: Option<u32>,
+    /// The number of pages, including `url`, this branch of the crawl may fetch,
+    /// None if unlimited
+    #[serde(default)]
+    pub budget: Option<u64>,
+    /// The number of times processing this message has failed
+    #[serde(default)]
+    pub attempts: u32,
+    /// The age in seconds beyond which pages crawled before are crawled again, None
+    /// if pages are only crawled once
+    #[serde(default)]
+    pub max_age_secs: Option<u64>,
+    /// How soon to crawl `url`, from 0 to `MAX_PRIORITY`, queues that support
+    /// priorities delivering messages with higher priorities first
+    #[serde(default)]
+    pub priority: u8,
+}
+
+/// Divides `budget` between `urls`, dropping those beyond it
+fn divide(
+    urls: impl IntoIterator<Item = String>,
+    budget: Option<u64>,
+) -> Vec<(String, Option<u64>)> {
+    let budget = match budget {
+        Some(budget) => budget,
+        None => return urls.into_iter().map(|url| (url, None)).collect(),
+    };
+
+    let urls: Vec<_> = urls.into_iter().take(budget as usize).collect();
+    if urls.is_empty() {
+        return vec![];
+    }
+
+    let share = budget / urls.len() as u64;
+    let extra = budget % urls.len() as u64;
+    urls.into_iter()
+        .enumerate()
+        .map(|(idx, url)| {
+            let budget = share + if (idx as u64) < extra { 1 } else { 0 };
+            (url, Some(budget))
+        })
+        .collect()
+}
+
+impl Message {
+    /// Creates the message starting a crawl from `url`
+    pub fn seed(url: String, limits: CrawlLimits) -> Message {
+        Message {
+            url,
+            job_id: None,
+            depth: 0,
+            max_depth: limits.depth,
+            budget: limits.pages,
+            attempts: 0,
+            max_age_secs: None,
+            priority: MAX_PRIORITY,
+        }
+    }
+
+    /// Creates the messages starting `job` from its seed URLs, dividing the page limit
+    /// between them
+    pub fn seeds(job: &Job) -> Vec<Message> {
+        divide(job.seeds.iter().cloned(), job.max_pages)
+            .into_iter()
+            .map(|(url, budget)| Message {
+                url,
+                job_id: Some(job.job_id.clone()),
+                depth: 0,
+                max_depth: job.max_depth,
+                budget,
+                attempts: 0,
+                max_age_secs: job.max_age_secs,
+                priority: MAX_PRIORITY,
+            })
+            .collect()
+    }
+
+    /// Returns the unix time before which pages crawled are stale and should be crawled
+    /// again, given the current unix time `now`
+    ///
+    /// Returns 0 if pages are only crawled once
+    pub fn stale_before(&self, now: u64) -> u64 {
+        self.max_age_secs
+            .map_or(0, |max_age| now.saturating_sub(max_age))
+    }
+
+    /// Returns the messages to enqueue for the links found at this message's URL
+    ///
+    /// This message's page consumes one unit of its budget, with the remainder divided
+    /// between the children, ensuring the crawl as a whole never exceeds its page limit.
+    /// Links beyond the remaining budget or maximum depth are not followed
+    pub fn children(&self, urls: impl IntoIterator<Item = String>) -> Vec<Message> {
+        self.scored_children(urls.into_iter().map(|url| (url, None)))
+    }
+
+    /// Returns the messages to enqueue for the links found at this message's URL, along
+    /// with the scores of those whose importance is known, see [`priority`]
+    ///
+    /// As [`Message::children`], except that links with higher priorities are followed
+    /// first, and so are the ones followed if the remaining budget can't cover them all
+    pub fn scored_children(
+        &self,
+        urls: impl IntoIterator<Item = (String, Option<f64>)>,
+    ) -> Vec<Message> {
+        if matches!(self.max_depth, Some(max_depth) if self.depth >= max_depth) {
+            return vec![];
+        }
+
+        let depth = self.depth + 1;
+        let mut urls: Vec<_> = urls
+            .into_iter()
+            .map(|(url, score)| (url, priority(depth, score)))
+            .collect();
+        urls.sort_by(|a, b| b.1.cmp(&a.1));
+        let priorities: HashMap<_, _> = urls.iter().cloned().collect();
+
+        let remaining = self.budget.map(|budget| budget.saturating_sub(1));
+        divide(urls.into_iter().map(|(url, _)| url), remaining)
+            .into_iter()
+            .map(|(url, budget)| Message {
+                priority: priorities[&url],
+                url,
+                job_id: self.job_id.clone(),
+                depth,
+                max_depth: self.max_depth,
+                budget,
+                attempts: 0,
+                max_age_secs: self.max_age_secs,
+            })
+            .collect()
+    }
+}
+
+/// The limits of a crawl started from a seed URL
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct CrawlLimits {
+    /// The maximum number of links followed from the seed URL, None if unlimited
+    pub depth: Option<u32>,
+    /// The maximum number of pages fetched, None if unlimited
+    pub pages: Option<u64>,
+}
+
+/// A message that failed on every attempt
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DeadLetter {
+    pub message: Message,
+    /// The error of the last attempt
+    pub error: String,
+    /// The unix time of the last attempt
+    pub failed_at: u64,
+}
+
+/// How failed messages are retried
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct RetryPolicy {
+    /// The number of attempts before a message is dead-lettered
+    attempts: u32,
+    /// The delay before the first retry, doubling with each retry
+    backoff: u64,
+}
+
+impl RetryPolicy {
+    pub(crate) fn new(attempts: u32, backoff: u64) -> RetryPolicy {
+        RetryPolicy {
+            attempts: attempts.max(1),
+            backoff,
+        }
+    }
+
+    /// Returns the delay before retrying a message that has failed `failures` times,
+    /// None if it has no attempts remaining
+    pub(crate) fn delay(&self, failures: u32) -> Option<u64> {
+        if failures >= self.attempts {
+            return None;
+        }
+        let exponent = failures.saturating_sub(1);
+        Some(self.backoff.saturating_mul(2u64.saturating_pow(exponent)))
+    }
+
+    /// Returns the delays of all retries
+    pub(crate) fn delays(&self) -> impl Iterator<Item = u64> + '_ {
+        (1..self.attempts).filter_map(move |failures| self.delay(failures))
+    }
 }
 
 #[async_trait(?Send)]
 pub trait MessageQueue {
-    async fn queue_index(&self, url: String) -> Result<(), MQError>;
+    async fn queue_index(&self, message: Message) -> Result<(), MQError>;
+
+    async fn queue_batch(&self, messages: Vec<Message>) -> Result<(), MQError> {
+        for message in messages {
+            self.queue_index(message).await?;
+        }
+        Ok(())
+    }
+
+    /// Returns up to `limit` dead letters, oldest first, leaving them in the queue
+    async fn dead_letters(&self, limit: usize) -> Result<Vec<DeadLetter>, MQError>;
+
+    /// Returns up to `limit` dead letters to the index queue with their attempts
+    /// reset, oldest first, returning the messages requeued
+    async fn requeue_dead_letters(&self, limit: usize) -> Result<Vec<Message>, MQError>;
 
     async fn consume(
         &self,
@@ -44,12 +291,244 @@ pub trait MessageQueue {
     ) -> Result<Box<dyn Consumer>, Box<dyn Error>>;
 }
 
+/// A connection to the message queue backend selected by the config
+#[derive(Clone)]
+pub enum QueueConnection {
+    RabbitMQ(RabbitMQConnection),
+    Sqs(SqsQueue),
+    Kafka(KafkaQueue),
+}
+
+impl QueueConnection {
+    pub fn new(config: &Config) -> QueueConnection {
+        match config.queue {
+            QueueBackend::RabbitMQ => {
+                QueueConnection::RabbitMQ(RabbitMQConnection::new(&config.rabbit))
+            }
+            QueueBackend::Sqs => QueueConnection::Sqs(SqsQueue::new(&config.sqs)),
+            QueueBackend::Kafka => QueueConnection::Kafka(KafkaQueue::new(&config.kafka)),
+        }
+    }
+
+    /// Returns a new channel to send and consume messages with, counting the messages
+    /// sent and consumed in the Prometheus metrics of the process
+    pub fn channel(&self) -> Box<dyn MessageQueue> {
+        let channel: Box<dyn MessageQueue> = match self {
+            QueueConnection::RabbitMQ(connection) => Box::new(RabbitMQChannel::new(connection)),
+            QueueConnection::Sqs(queue) => Box::new(queue.clone()),
+            QueueConnection::Kafka(queue) => Box::new(queue.clone()),
+        };
+        Box::new(MeteredQueue::new(channel))
+    }
+
+    /// Checks that the queue can be reached, such as for a readiness probe
+    pub async fn check(&self) -> Result<(), MQError> {
+        match self {
+            QueueConnection::RabbitMQ(connection) => connection.check(),
+            QueueConnection::Sqs(queue) => queue.check().await,
+            // The producer connects to the brokers on demand
+            QueueConnection::Kafka(_) => Ok(()),
+        }
+    }
+
+    pub async fn close(&self) -> Result<(), MQError> {
+        match self {
+            QueueConnection::RabbitMQ(connection) => connection.close().await,
+            QueueConnection::Sqs(_) | QueueConnection::Kafka(_) => Ok(()),
+        }
+    }
+}
+
+/// Triggers the `Shutdown` it was created with
+pub struct ShutdownTrigger(watch::Sender<bool>);
+
+impl ShutdownTrigger {
+    pub fn trigger(&self) {
+        // Fails only if every consumer has already returned
+        let _ = self.0.broadcast(true);
+    }
+}
+
+/// Tells consumers to stop taking new messages, once triggered
+#[derive(Clone)]
+pub struct Shutdown(watch::Receiver<bool>);
+
+impl Shutdown {
+    pub fn new() -> (ShutdownTrigger, Shutdown) {
+        let (sender, receiver) = watch::channel(false);
+        (ShutdownTrigger(sender), Shutdown(receiver))
+    }
+
+    /// Returns a shutdown that is never triggered
+    pub fn never() -> Shutdown {
+        Shutdown::new().1
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        *self.0.borrow()
+    }
+
+    /// Completes once triggered, never if the trigger is dropped first
+    pub async fn triggered(mut self) {
+        loop {
+            match self.0.recv().await {
+                Some(true) => return,
+                Some(false) => continue,
+                None => futures::future::pending().await,
+            }
+        }
+    }
+}
+
 #[async_trait(?Send)]
 pub trait Consumer {
-    async fn block_on(&self);
+    /// Processes messages until `shutdown` is triggered, then returns once the
+    /// messages being processed have been acknowledged, or scheduled for a retry
+    async fn block_on(&self, shutdown: Shutdown);
 }
 
 #[async_trait(?Send)]
 pub trait ConsumerDelegate {
+    /// Processes `message`, which is retried after a delay if this returns an error
     async fn consume(&self, message: Message) -> Result<(), Box<dyn Error>>;
+
+    /// Called when `message` has failed on every attempt, and so has been moved to the
+    /// dead-letter queue
+    async fn dead_lettered(&self, _message: &Message) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn urls(count: usize) -> Vec<String> {
+        (0..count)
+            .map(|idx| format!("https://example.com/{}", idx))
+            .collect()
+    }
+
+    #[test]
+    fn test_depth() {
+        let limits = CrawlLimits {
+            depth: Some(1),
+            pages: None,
+        };
+        let seed = Message::seed("https://example.com".to_string(), limits);
+
+        let children = seed.children(urls(3));
+        assert_eq!(children.len(), 3);
+        assert!(children.iter().all(|x| x.depth == 1 && x.budget.is_none()));
+
+        assert!(children[0].children(urls(3)).is_empty());
+    }
+
+    #[test]
+    fn test_budget() {
+        let limits = CrawlLimits {
+            depth: None,
+            pages: Some(8),
+        };
+        let seed = Message::seed("https://example.com".to_string(), limits);
+
+        // The remaining budget of 7 is divided between the children
+        let budgets: Vec<_> = seed.children(urls(3)).iter().map(|x| x.budget).collect();
+        assert_eq!(budgets, vec![Some(3), Some(2), Some(2)]);
+
+        // Links beyond the budget are not followed
+        let children = seed.children(urls(10));
+        assert_eq!(children.len(), 7);
+        assert!(children.iter().all(|x| x.budget == Some(1)));
+        assert!(children[0].children(urls(10)).is_empty());
+    }
+
+    #[test]
+    fn test_seeds() {
+        let limits = CrawlLimits {
+            depth: Some(2),
+            pages: Some(5),
+        };
+        let mut job = Job::new(urls(2), limits, None);
+        job.job_id = "job".to_string();
+        let seeds = Message::seeds(&job);
+
+        assert_eq!(seeds.len(), 2);
+        assert_eq!(seeds[0].budget, Some(3));
+        assert_eq!(seeds[1].budget, Some(2));
+        assert!(seeds.iter().all(|x| x.depth == 0 && x.max_depth == Some(2)));
+
+        // Children belong to the same job
+        let children = seeds[0].children(urls(1));
+        assert_eq!(children[0].job_id.as_deref(), Some("job"));
+        assert_eq!(children[0].budget, Some(2));
+    }
+
+    #[test]
+    fn test_deserialize() {
+        // Messages queued before limits were introduced are unlimited
+        let message: Message = serde_json::from_str(r#"{"url":"https://example.com"}"#).unwrap();
+        assert_eq!(message.job_id, None);
+        assert_eq!(message.depth, 0);
+        assert_eq!(message.max_depth, None);
+        assert_eq!(message.budget, None);
+        assert_eq!(message.attempts, 0);
+        assert_eq!(message.max_age_secs, None);
+        assert_eq!(message.priority, 0);
+        assert_eq!(message.stale_before(1000), 0);
+    }
+
+    #[test]
+    fn test_priority() {
+        assert_eq!(priority(0, None), MAX_PRIORITY);
+        assert_eq!(priority(2, None), 7);
+        assert_eq!(priority(20, None), 0);
+        assert_eq!(priority(2, Some(1.)), 8);
+        assert_eq!(priority(2, Some(0.)), 4);
+        assert_eq!(priority(20, Some(5.)), 5);
+
+        let limits = CrawlLimits {
+            depth: None,
+            pages: Some(3),
+        };
+        let seed = Message::seed("https://example.com".to_string(), limits);
+        assert_eq!(seed.priority, MAX_PRIORITY);
+
+        // The links with the highest priorities are followed within the budget
+        let urls = urls(3);
+        let scored = vec![
+            (urls[0].clone(), Some(0.)),
+            (urls[1].clone(), None),
+            (urls[2].clone(), Some(1.)),
+        ];
+        let children = seed.scored_children(scored);
+        let children: Vec<_> = children.iter().map(|x| (&x.url, x.priority)).collect();
+        assert_eq!(children, vec![(&urls[2], 9), (&urls[1], 8)]);
+    }
+
+    #[test]
+    fn test_stale_before() {
+        let limits = CrawlLimits::default();
+        let job = Job::new(urls(1), limits, Some(60));
+        let seeds = Message::seeds(&job);
+        assert_eq!(seeds[0].stale_before(1000), 940);
+
+        // Children inherit the staleness threshold
+        let children = seeds[0].children(urls(1));
+        assert_eq!(children[0].stale_before(1000), 940);
+        assert_eq!(children[0].stale_before(10), 0);
+    }
+
+    #[test]
+    fn test_retry_policy() {
+        let policy = RetryPolicy::new(4, 1000);
+
+        assert_eq!(policy.delay(1), Some(1000));
+        assert_eq!(policy.delay(2), Some(2000));
+        assert_eq!(policy.delay(3), Some(4000));
+        assert_eq!(policy.delay(4), None);
+        assert_eq!(policy.delays().collect::<Vec<_>>(), vec![1000, 2000, 4000]);
+
+        let policy = RetryPolicy::new(0, 1000);
+        assert_eq!(policy.delay(1), None);
+        assert_eq!(policy.delays().count(), 0);
+    }
 }
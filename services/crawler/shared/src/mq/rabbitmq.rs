@@ -1,19 +1,37 @@
 use crate::config::RabbitMQConfig;
-use crate::mq::{Consumer, ConsumerDelegate, MQError, Message, MessageQueue};
+use crate::dao::unix_time;
+use crate::mq::{
+    Consumer, ConsumerDelegate, DeadLetter, MQError, Message, MessageQueue, RetryPolicy, Shutdown,
+    MAX_PRIORITY,
+};
 use async_trait::async_trait;
 use futures::stream::StreamExt;
 use lapin::{
-    options::*, types::FieldTable, BasicProperties, Channel, Connection, ConnectionProperties,
+    options::*,
+    types::{AMQPValue, FieldTable},
+    BasicProperties, Channel, Connection, ConnectionProperties,
 };
 use log::error;
+use serde::Serialize;
 use std::error::Error;
 
 const EXCHANGE: &str = "";
 const QUEUE_NAME: &str = "index";
+/// The queue of messages that failed on every attempt
+const DEAD_LETTER_QUEUE: &str = "index_dead";
+
+/// Returns the name of the queue holding messages to retry after `delay` milliseconds
+///
+/// The delay is part of the name as a queue's arguments cannot be changed once declared
+fn retry_queue(delay: u64) -> String {
+    format!("{}_retry_{}", QUEUE_NAME, delay)
+}
 
 #[derive(Debug, Clone)]
 pub struct RabbitMQConnection {
     connection: Connection,
+    retry: RetryPolicy,
+    prefetch: u16,
 }
 
 impl RabbitMQConnection {
@@ -21,13 +39,37 @@ impl RabbitMQConnection {
         let connection = Connection::connect(&config.url, ConnectionProperties::default())
             .wait()
             .expect("Failed to connect to RabbitMQ");
-        RabbitMQConnection { connection }
+        let retry = RetryPolicy::new(config.attempts, config.backoff);
+        RabbitMQConnection {
+            connection,
+            retry,
+            prefetch: config.prefetch,
+        }
+    }
+
+    /// Checks that the connection is open, such as for a readiness probe
+    pub fn check(&self) -> Result<(), MQError> {
+        let status = self.connection.status();
+        if !status.connected() {
+            return Err(MQError {
+                message: format!("Connection is {:?}", status.state()),
+            });
+        }
+        Ok(())
+    }
+
+    /// Closes the connection along with its channels, returning any messages delivered
+    /// to them but not yet acknowledged to the queue
+    pub async fn close(&self) -> Result<(), MQError> {
+        self.connection.close(200, "Shutting down").await?;
+        Ok(())
     }
 }
 
 #[derive(Debug, Clone)]
 pub struct RabbitMQChannel {
     channel: Channel,
+    retry: RetryPolicy,
 }
 
 impl RabbitMQChannel {
@@ -38,39 +80,179 @@ impl RabbitMQChannel {
             .wait()
             .expect("Failed to create channel");
 
+        // Messages with higher priorities are delivered first
+        let mut args = FieldTable::default();
+        args.insert(
+            "x-max-priority".into(),
+            AMQPValue::ShortShortUInt(MAX_PRIORITY),
+        );
+        channel
+            .queue_declare(QUEUE_NAME, QueueDeclareOptions::default(), args)
+            .wait()
+            .expect("Failed to declare queue");
+
         channel
             .queue_declare(
-                QUEUE_NAME,
+                DEAD_LETTER_QUEUE,
                 QueueDeclareOptions::default(),
                 FieldTable::default(),
             )
             .wait()
             .expect("Failed to declare queue");
 
+        // Retry queues have no consumers, their messages are returned to the index
+        // queue by RabbitMQ once they expire, keeping their priority
+        for delay in conn.retry.delays() {
+            let mut args = FieldTable::default();
+            args.insert("x-message-ttl".into(), AMQPValue::LongLongInt(delay as i64));
+            args.insert(
+                "x-dead-letter-exchange".into(),
+                AMQPValue::LongString(EXCHANGE.into()),
+            );
+            args.insert(
+                "x-dead-letter-routing-key".into(),
+                AMQPValue::LongString(QUEUE_NAME.into()),
+            );
+
+            channel
+                .queue_declare(&retry_queue(delay), QueueDeclareOptions::default(), args)
+                .wait()
+                .expect("Failed to declare retry queue");
+        }
+
         channel
-            .basic_qos(5, BasicQosOptions { global: false })
+            .basic_qos(conn.prefetch, BasicQosOptions { global: false })
             .wait()
             .expect("Failed to set prefetch count");
 
-        RabbitMQChannel { channel }
+        RabbitMQChannel {
+            channel,
+            retry: conn.retry,
+        }
+    }
+}
+
+/// Publishes `value` to `queue`, with the priority of the message it is or contains
+async fn publish<T: Serialize>(
+    channel: &Channel,
+    queue: &str,
+    value: &T,
+    priority: u8,
+) -> Result<(), MQError> {
+    let encoded = serde_json::to_vec(value)?;
+
+    channel
+        .basic_publish(
+            EXCHANGE,
+            queue,
+            BasicPublishOptions::default(),
+            encoded,
+            BasicProperties::default().with_priority(priority),
+        )
+        .await?;
+    Ok(())
+}
+
+/// Schedules `message` to be retried after a delay, or moves it to the dead-letter
+/// queue if it has no attempts remaining, returning the dead letter if so
+async fn retry(
+    channel: &Channel,
+    policy: &RetryPolicy,
+    mut message: Message,
+    error: String,
+) -> Result<Option<DeadLetter>, MQError> {
+    message.attempts += 1;
+    match policy.delay(message.attempts) {
+        Some(delay) => {
+            publish(channel, &retry_queue(delay), &message, message.priority).await?;
+            Ok(None)
+        }
+        None => {
+            let letter = DeadLetter {
+                message,
+                error,
+                failed_at: unix_time(),
+            };
+            publish(channel, DEAD_LETTER_QUEUE, &letter, letter.message.priority).await?;
+            Ok(Some(letter))
+        }
     }
 }
 
 #[async_trait(?Send)]
 impl MessageQueue for RabbitMQChannel {
-    async fn queue_index(&self, url: String) -> Result<(), MQError> {
-        let encoded = serde_json::to_vec(&Message { url })?;
+    async fn queue_index(&self, message: Message) -> Result<(), MQError> {
+        publish(&self.channel, QUEUE_NAME, &message, message.priority).await
+    }
 
-        self.channel
-            .basic_publish(
-                EXCHANGE,
-                QUEUE_NAME,
-                BasicPublishOptions::default(),
-                encoded,
-                BasicProperties::default(),
-            )
-            .await?;
-        Ok(())
+    async fn dead_letters(&self, limit: usize) -> Result<Vec<DeadLetter>, MQError> {
+        let mut deliveries = Vec::new();
+        while deliveries.len() < limit {
+            match self
+                .channel
+                .basic_get(DEAD_LETTER_QUEUE, BasicGetOptions::default())
+                .await?
+            {
+                Some(message) => deliveries.push(message.delivery),
+                None => break,
+            }
+        }
+
+        // Return the messages to the queue, they are only being inspected
+        for delivery in &deliveries {
+            let options = BasicNackOptions {
+                multiple: false,
+                requeue: true,
+            };
+            self.channel
+                .basic_nack(delivery.delivery_tag, options)
+                .await?;
+        }
+
+        let letters = deliveries
+            .iter()
+            .map(|delivery| serde_json::from_slice(&delivery.data))
+            .collect::<Result<_, _>>()?;
+        Ok(letters)
+    }
+
+    async fn requeue_dead_letters(&self, limit: usize) -> Result<Vec<Message>, MQError> {
+        let mut requeued = Vec::new();
+        while requeued.len() < limit {
+            let delivery = match self
+                .channel
+                .basic_get(DEAD_LETTER_QUEUE, BasicGetOptions::default())
+                .await?
+            {
+                Some(message) => message.delivery,
+                None => break,
+            };
+
+            let letter: DeadLetter = match serde_json::from_slice(&delivery.data) {
+                Ok(letter) => letter,
+                Err(e) => {
+                    let options = BasicNackOptions {
+                        multiple: false,
+                        requeue: true,
+                    };
+                    self.channel
+                        .basic_nack(delivery.delivery_tag, options)
+                        .await?;
+                    return Err(e.into());
+                }
+            };
+
+            let message = Message {
+                attempts: 0,
+                ..letter.message
+            };
+            publish(&self.channel, QUEUE_NAME, &message, message.priority).await?;
+            self.channel
+                .basic_ack(delivery.delivery_tag, BasicAckOptions::default())
+                .await?;
+            requeued.push(message);
+        }
+        Ok(requeued)
     }
 
     async fn consume(
@@ -89,6 +271,7 @@ impl MessageQueue for RabbitMQChannel {
             .await?;
         Ok(Box::new(ConsumerRabbitMQ {
             channel: self.channel.clone(),
+            retry: self.retry,
             inner: consumer,
             delegate,
         }))
@@ -97,15 +280,18 @@ impl MessageQueue for RabbitMQChannel {
 
 struct ConsumerRabbitMQ {
     channel: Channel,
+    retry: RetryPolicy,
     inner: lapin::Consumer,
     delegate: Box<dyn ConsumerDelegate>,
 }
 
 #[async_trait(?Send)]
 impl Consumer for ConsumerRabbitMQ {
-    async fn block_on(&self) {
+    async fn block_on(&self, shutdown: Shutdown) {
+        // Deliveries already taken are processed before returning
         self.inner
             .clone()
+            .take_until(shutdown.triggered())
             .for_each_concurrent(None, |x| async move {
                 match x {
                     Ok(delivery) => {
@@ -119,29 +305,39 @@ impl Consumer for ConsumerRabbitMQ {
                             }
                         };
 
-                        match self.delegate.consume(value).await {
-                            Ok(_) => {
-                                let ack = self
-                                    .channel
-                                    .basic_ack(tag, BasicAckOptions::default())
-                                    .await;
-
-                                if let Err(e) = ack {
-                                    error!("Failed to ack message: {}", e)
+                        let result = self.delegate.consume(value.clone()).await;
+                        if let Err(e) = result {
+                            error!("Delegate Error: {}", e);
+                            match retry(&self.channel, &self.retry, value, e.to_string()).await {
+                                Ok(Some(letter)) => {
+                                    self.delegate.dead_lettered(&letter.message).await
                                 }
-                            }
-                            Err(e) => {
-                                error!("Delegate Error: {}", e);
-                                let ack = self
-                                    .channel
-                                    .basic_nack(tag, BasicNackOptions::default())
-                                    .await;
-
-                                if let Err(e) = ack {
-                                    error!("Failed to nack message: {}", e)
+                                Ok(None) => {}
+                                Err(e) => {
+                                    // Leave the message to be redelivered immediately
+                                    // rather than lose it
+                                    error!("Failed to schedule retry: {}", e);
+                                    let options = BasicNackOptions {
+                                        multiple: false,
+                                        requeue: true,
+                                    };
+                                    if let Err(e) = self.channel.basic_nack(tag, options).await {
+                                        error!("Failed to nack message: {}", e)
+                                    }
+                                    return;
                                 }
                             }
                         }
+
+                        // The message has been processed, or scheduled for a retry
+                        let ack = self
+                            .channel
+                            .basic_ack(tag, BasicAckOptions::default())
+                            .await;
+
+                        if let Err(e) = ack {
+                            error!("Failed to ack message: {}", e)
+                        }
                     }
                     Err(e) => {
                         error!("RabbitMQ Error: {}", e);
@@ -0,0 +1,373 @@
+use std::collections::HashSet;
+use std::error::Error;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use futures::stream::{self, StreamExt};
+use log::error;
+use rusoto_sqs::{
+    ChangeMessageVisibilityRequest, DeleteMessageRequest, GetQueueAttributesRequest,
+    ReceiveMessageRequest, SendMessageBatchRequest, SendMessageBatchRequestEntry,
+    SendMessageRequest, Sqs, SqsClient,
+};
+use serde::Serialize;
+
+use crate::config::SqsConfig;
+use crate::dao::unix_time;
+use crate::mq::{
+    Consumer, ConsumerDelegate, DeadLetter, MQError, Message, MessageQueue, RetryPolicy, Shutdown,
+};
+
+/// The maximum number of messages SQS sends or receives in a single request
+const MAX_BATCH: usize = 10;
+
+/// The maximum visibility timeout SQS accepts, in seconds
+const MAX_VISIBILITY_TIMEOUT: u64 = 43200;
+
+/// The delay before receiving again after a failed receive
+const RECEIVE_ERROR_DELAY: Duration = Duration::from_secs(5);
+
+/// The time in seconds to wait for dead letters, long enough to query every server
+/// holding the queue's messages without blocking for long if there are none
+const DEAD_LETTER_WAIT: i64 = 1;
+
+const RECEIVE_COUNT: &str = "ApproximateReceiveCount";
+
+/// A message queue stored in SQS
+///
+/// A failed message is retried by setting its visibility timeout to the backoff delay,
+/// after which SQS delivers it again. Messages that fail on every attempt are moved to
+/// the dead-letter queue by the consumer, rather than by a redrive policy, so their
+/// last error is recorded along with them
+#[derive(Clone)]
+pub struct SqsQueue {
+    client: SqsClient,
+    url: String,
+    dlq: String,
+    wait: i64,
+    concurrency: usize,
+    retry: RetryPolicy,
+}
+
+impl SqsQueue {
+    pub fn new(config: &SqsConfig) -> SqsQueue {
+        SqsQueue {
+            client: config.sqs_client(),
+            url: config.url.clone().expect("SQS queue URL not configured"),
+            dlq: config
+                .dlq
+                .clone()
+                .expect("SQS dead-letter queue URL not configured"),
+            wait: config.wait,
+            concurrency: config.concurrency.max(1),
+            retry: RetryPolicy::new(config.attempts, config.backoff),
+        }
+    }
+
+    /// Checks that the queue can be reached, such as for a readiness probe
+    pub async fn check(&self) -> Result<(), MQError> {
+        self.client
+            .get_queue_attributes(GetQueueAttributesRequest {
+                queue_url: self.url.clone(),
+                attribute_names: Some(vec!["QueueArn".to_string()]),
+            })
+            .await?;
+        Ok(())
+    }
+
+    async fn send<T: Serialize>(&self, queue_url: &str, value: &T) -> Result<(), MQError> {
+        self.client
+            .send_message(SendMessageRequest {
+                queue_url: queue_url.to_string(),
+                message_body: serde_json::to_string(value)?,
+                ..Default::default()
+            })
+            .await?;
+        Ok(())
+    }
+
+    async fn receive(
+        &self,
+        queue_url: &str,
+        max: usize,
+        wait: i64,
+        visibility_timeout: Option<i64>,
+    ) -> Result<Vec<rusoto_sqs::Message>, MQError> {
+        let result = self
+            .client
+            .receive_message(ReceiveMessageRequest {
+                queue_url: queue_url.to_string(),
+                max_number_of_messages: Some(max.min(MAX_BATCH) as i64),
+                wait_time_seconds: Some(wait),
+                visibility_timeout,
+                attribute_names: Some(vec![RECEIVE_COUNT.to_string()]),
+                ..Default::default()
+            })
+            .await?;
+        Ok(result.messages.unwrap_or_default())
+    }
+
+    async fn delete(&self, queue_url: &str, message: &rusoto_sqs::Message) -> Result<(), MQError> {
+        let receipt_handle = message.receipt_handle.clone().unwrap_or_default();
+        self.client
+            .delete_message(DeleteMessageRequest {
+                queue_url: queue_url.to_string(),
+                receipt_handle,
+            })
+            .await?;
+        Ok(())
+    }
+
+    /// Schedules `message`, which has failed with `error`, to be received again after
+    /// a delay, or moves it to the dead-letter queue if it has no attempts remaining,
+    /// returning the dead letter if so
+    async fn retry(
+        &self,
+        received: &rusoto_sqs::Message,
+        message: Message,
+        error: String,
+    ) -> Result<Option<DeadLetter>, MQError> {
+        match self.retry.delay(message.attempts + 1) {
+            Some(delay) => {
+                let receipt_handle = received.receipt_handle.clone().unwrap_or_default();
+                self.client
+                    .change_message_visibility(ChangeMessageVisibilityRequest {
+                        queue_url: self.url.clone(),
+                        receipt_handle,
+                        visibility_timeout: delay.min(MAX_VISIBILITY_TIMEOUT) as i64,
+                    })
+                    .await?;
+                Ok(None)
+            }
+            None => {
+                let letter = DeadLetter {
+                    message: Message {
+                        attempts: message.attempts + 1,
+                        ..message
+                    },
+                    error,
+                    failed_at: unix_time(),
+                };
+                self.send(&self.dlq, &letter).await?;
+                self.delete(&self.url, received).await?;
+                Ok(Some(letter))
+            }
+        }
+    }
+}
+
+/// Returns the message body of `received`, with its attempts taken from the number of
+/// times SQS has delivered it
+fn decode(received: &rusoto_sqs::Message) -> Result<Message, MQError> {
+    let body = received.body.as_deref().unwrap_or_default();
+    let message: Message = serde_json::from_str(body)?;
+
+    let receive_count = received
+        .attributes
+        .as_ref()
+        .and_then(|x| x.get(RECEIVE_COUNT))
+        .and_then(|x| x.parse::<u32>().ok())
+        .unwrap_or(1);
+
+    Ok(Message {
+        attempts: message.attempts + receive_count.saturating_sub(1),
+        ..message
+    })
+}
+
+#[async_trait(?Send)]
+impl MessageQueue for SqsQueue {
+    async fn queue_index(&self, message: Message) -> Result<(), MQError> {
+        self.send(&self.url, &message).await
+    }
+
+    async fn queue_batch(&self, messages: Vec<Message>) -> Result<(), MQError> {
+        for chunk in messages.chunks(MAX_BATCH) {
+            let entries = chunk
+                .iter()
+                .enumerate()
+                .map(|(idx, message)| {
+                    Ok(SendMessageBatchRequestEntry {
+                        id: idx.to_string(),
+                        message_body: serde_json::to_string(message)?,
+                        ..Default::default()
+                    })
+                })
+                .collect::<Result<_, MQError>>()?;
+
+            let result = self
+                .client
+                .send_message_batch(SendMessageBatchRequest {
+                    queue_url: self.url.clone(),
+                    entries,
+                })
+                .await?;
+
+            if let Some(failed) = result.failed.first() {
+                return Err(MQError {
+                    message: format!(
+                        "failed to send {} of {} messages: {}",
+                        result.failed.len(),
+                        chunk.len(),
+                        failed.message.as_deref().unwrap_or(&failed.code)
+                    ),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    async fn dead_letters(&self, limit: usize) -> Result<Vec<DeadLetter>, MQError> {
+        // Messages received with a visibility timeout of 0 are left visible, and so
+        // may be received again by a later request
+        let mut seen = HashSet::new();
+        let mut letters = Vec::new();
+        while letters.len() < limit {
+            let received = self
+                .receive(&self.dlq, limit - letters.len(), DEAD_LETTER_WAIT, Some(0))
+                .await?;
+            let mut new = received
+                .into_iter()
+                .filter(|x| seen.insert(x.message_id.clone()))
+                .peekable();
+
+            if new.peek().is_none() {
+                break;
+            }
+
+            for received in new {
+                let body = received.body.as_deref().unwrap_or_default();
+                letters.push(serde_json::from_str(body)?);
+            }
+        }
+        Ok(letters)
+    }
+
+    async fn requeue_dead_letters(&self, limit: usize) -> Result<Vec<Message>, MQError> {
+        let mut requeued = Vec::new();
+        while requeued.len() < limit {
+            let received = self
+                .receive(&self.dlq, limit - requeued.len(), DEAD_LETTER_WAIT, None)
+                .await?;
+            if received.is_empty() {
+                break;
+            }
+
+            for received in received {
+                let body = received.body.as_deref().unwrap_or_default();
+                let letter: DeadLetter = serde_json::from_str(body)?;
+
+                let message = Message {
+                    attempts: 0,
+                    ..letter.message
+                };
+                self.send(&self.url, &message).await?;
+                self.delete(&self.dlq, &received).await?;
+                requeued.push(message);
+            }
+        }
+        Ok(requeued)
+    }
+
+    async fn consume(
+        &self,
+        delegate: Box<dyn ConsumerDelegate>,
+    ) -> Result<Box<dyn Consumer>, Box<dyn Error>> {
+        Ok(Box::new(ConsumerSqs {
+            queue: self.clone(),
+            delegate,
+        }))
+    }
+}
+
+struct ConsumerSqs {
+    queue: SqsQueue,
+    delegate: Box<dyn ConsumerDelegate>,
+}
+
+impl ConsumerSqs {
+    async fn receive(&self) -> Vec<rusoto_sqs::Message> {
+        match self
+            .queue
+            .receive(&self.queue.url, MAX_BATCH, self.queue.wait, None)
+            .await
+        {
+            Ok(messages) => messages,
+            Err(e) => {
+                error!("SQS Error: {}", e);
+                tokio::time::delay_for(RECEIVE_ERROR_DELAY).await;
+                vec![]
+            }
+        }
+    }
+
+    async fn process(&self, received: rusoto_sqs::Message) {
+        let message = match decode(&received) {
+            Ok(message) => message,
+            Err(e) => {
+                // Left to be retried until SQS discards it
+                error!("Failed to deserialize message: {}", e);
+                return;
+            }
+        };
+
+        let result = match self.delegate.consume(message.clone()).await {
+            Ok(_) => self.queue.delete(&self.queue.url, &received).await,
+            Err(e) => {
+                error!("Delegate Error: {}", e);
+                match self.queue.retry(&received, message, e.to_string()).await {
+                    Ok(Some(letter)) => {
+                        self.delegate.dead_lettered(&letter.message).await;
+                        Ok(())
+                    }
+                    Ok(None) => Ok(()),
+                    Err(e) => Err(e),
+                }
+            }
+        };
+
+        // The message is received again once its visibility timeout expires
+        if let Err(e) = result {
+            error!("Failed to complete message: {}", e);
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl Consumer for ConsumerSqs {
+    async fn block_on(&self, shutdown: Shutdown) {
+        // Messages received but not yet processed when shutting down are received
+        // again once their visibility timeout expires
+        stream::repeat(())
+            .then(|_| self.receive())
+            .flat_map(stream::iter)
+            .take_until(shutdown.triggered())
+            .for_each_concurrent(self.queue.concurrency, |x| self.process(x))
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        let attributes: HashMap<_, _> = vec![(RECEIVE_COUNT.to_string(), "3".to_string())]
+            .into_iter()
+            .collect();
+
+        let received = rusoto_sqs::Message {
+            body: Some(r#"{"url":"https://example.com"}"#.to_string()),
+            attributes: Some(attributes),
+            ..Default::default()
+        };
+
+        // Two previous deliveries failed
+        let message = decode(&received).unwrap();
+        assert_eq!(message.url, "https://example.com");
+        assert_eq!(message.attempts, 2);
+    }
+}
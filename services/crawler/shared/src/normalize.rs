@@ -0,0 +1,113 @@
+use url::{ParseError, Url};
+
+/// Query parameters added to track where a visit came from, which don't change the
+/// page returned
+const TRACKING_PARAMS: &[&str] = &[
+    "_ga", "dclid", "fbclid", "gclid", "igshid", "mc_cid", "mc_eid", "msclkid", "yclid",
+];
+
+/// Prefixes of families of tracking query parameters, such as `utm_source`
+const TRACKING_PREFIXES: &[&str] = &["utm_"];
+
+fn is_tracking(name: &str) -> bool {
+    TRACKING_PARAMS.contains(&name) || TRACKING_PREFIXES.iter().any(|x| name.starts_with(x))
+}
+
+/// Returns the normal form of `url`, such that trivially different URLs of the same
+/// page are equal
+///
+/// Parsing a `Url` already lowercases the host, removes the scheme's default port and
+/// resolves dot segments in the path. This additionally removes the fragment and any
+/// tracking query parameters, and sorts the remaining parameters by name
+pub fn normalize(url: &Url) -> Url {
+    let mut url = url.clone();
+    url.set_fragment(None);
+
+    if url.query().is_some() {
+        let mut params: Vec<(String, String)> = url
+            .query_pairs()
+            .filter(|(name, _)| !is_tracking(name))
+            .map(|(name, value)| (name.into_owned(), value.into_owned()))
+            .collect();
+
+        if params.is_empty() {
+            url.set_query(None);
+        } else {
+            // The sort is stable, preserving the order of repeated parameters
+            params.sort_by(|a, b| a.0.cmp(&b.0));
+            url.query_pairs_mut().clear().extend_pairs(params);
+        }
+    }
+    url
+}
+
+/// Parses and normalizes `url`, see [`normalize`]
+pub fn normalize_str(url: &str) -> Result<String, ParseError> {
+    Ok(normalize(&Url::parse(url)?).into_string())
+}
+
+/// Returns true if `url` is on `domain`, or one of its subdomains, where `domain` is
+/// lowercase
+pub fn within_domain(url: &Url, domain: &str) -> bool {
+    match url.host_str() {
+        Some(host) => {
+            host == domain
+                || (host.ends_with(domain) && host[..host.len() - domain.len()].ends_with('.'))
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(url: &str, expected: &str) {
+        assert_eq!(normalize_str(url).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_normalize() {
+        check("HTTPS://Example.COM:443", "https://example.com/");
+        check("http://example.com:80/a/./b/../c", "http://example.com/a/c");
+        check("http://example.com:8080/", "http://example.com:8080/");
+        check(
+            "https://example.com/page#section",
+            "https://example.com/page",
+        );
+        check(
+            "https://example.com/?utm_source=x&b=2&gclid=y&a=1&b=1",
+            "https://example.com/?a=1&b=2&b=1",
+        );
+        check(
+            "https://example.com/?utm_medium=email",
+            "https://example.com/",
+        );
+        check("https://example.com/?", "https://example.com/");
+
+        // Normalizing is idempotent
+        let url = "https://example.com/search?q=a+b&page=2";
+        let normalized = normalize_str(url).unwrap();
+        assert_eq!(normalize_str(&normalized).unwrap(), normalized);
+
+        assert!(normalize_str("not a url").is_err());
+    }
+
+    #[test]
+    fn test_within_domain() {
+        let url = |x: &str| Url::parse(x).unwrap();
+        assert!(within_domain(&url("https://example.com/a"), "example.com"));
+        assert!(within_domain(
+            &url("https://www.Example.com/"),
+            "example.com"
+        ));
+        assert!(!within_domain(
+            &url("https://badexample.com/"),
+            "example.com"
+        ));
+        assert!(!within_domain(
+            &url("https://example.com.evil/"),
+            "example.com"
+        ));
+    }
+}
@@ -0,0 +1,179 @@
+use async_trait::async_trait;
+use reqwest::Client;
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::config::SearchConfig;
+use crate::content::url_key;
+use crate::search::{Document, SearchError, SearchHit, SearchIndex};
+
+/// The maximum length of a snippet
+const SNIPPET_LEN: usize = 200;
+
+#[derive(Deserialize)]
+struct SearchResponse {
+    hits: Hits,
+}
+
+#[derive(Deserialize)]
+struct Hits {
+    hits: Vec<Hit>,
+}
+
+#[derive(Deserialize)]
+struct Hit {
+    #[serde(rename = "_score")]
+    score: Option<f64>,
+    #[serde(rename = "_source")]
+    source: Source,
+    #[serde(default)]
+    highlight: Highlight,
+}
+
+#[derive(Deserialize)]
+struct Source {
+    url: String,
+    title: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+struct Highlight {
+    #[serde(default)]
+    text: Vec<String>,
+}
+
+impl From<SearchResponse> for Vec<SearchHit> {
+    fn from(response: SearchResponse) -> Self {
+        response
+            .hits
+            .hits
+            .into_iter()
+            .map(|hit| SearchHit {
+                url: hit.source.url,
+                title: hit.source.title,
+                score: hit.score.unwrap_or_default(),
+                snippet: hit.highlight.text.into_iter().next(),
+            })
+            .collect()
+    }
+}
+
+/// A search index stored in Elasticsearch
+///
+/// Documents are identified by the hash of their URL, as with stored page content
+pub struct SearchIndexElastic {
+    client: Client,
+    url: String,
+    index: String,
+}
+
+impl SearchIndexElastic {
+    pub fn new(config: &SearchConfig, url: String) -> SearchIndexElastic {
+        SearchIndexElastic {
+            client: Client::new(),
+            url: url.trim_end_matches('/').to_string(),
+            index: config.index.clone(),
+        }
+    }
+}
+
+#[async_trait(?Send)]
+impl SearchIndex for SearchIndexElastic {
+    async fn index(&self, document: &Document) -> Result<(), SearchError> {
+        let url = format!(
+            "{}/{}/_doc/{}",
+            self.url,
+            self.index,
+            url_key(&document.url)
+        );
+
+        self.client
+            .put(&url)
+            .json(document)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    async fn search(&self, query: &str, limit: usize) -> Result<Vec<SearchHit>, SearchError> {
+        let url = format!("{}/{}/_search", self.url, self.index);
+        let body = json!({
+            "size": limit,
+            "_source": ["url", "title"],
+            "query": {
+                "multi_match": {
+                    "query": query,
+                    "fields": ["title^2", "text"]
+                }
+            },
+            "highlight": {
+                "fields": {
+                    "text": { "fragment_size": SNIPPET_LEN, "number_of_fragments": 1 }
+                }
+            }
+        });
+
+        let response: SearchResponse = self
+            .client
+            .post(&url)
+            .json(&body)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        Ok(response.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hits() {
+        let response: SearchResponse = serde_json::from_str(
+            r#"{
+                "took": 3,
+                "hits": {
+                    "total": {"value": 2, "relation": "eq"},
+                    "hits": [
+                        {
+                            "_id": "a",
+                            "_score": 2.5,
+                            "_source": {"url": "https://example.com", "title": "Example"},
+                            "highlight": {"text": ["an <em>example</em> page"]}
+                        },
+                        {
+                            "_id": "b",
+                            "_score": 1.0,
+                            "_source": {"url": "https://example.com/b", "title": null}
+                        }
+                    ]
+                }
+            }"#,
+        )
+        .unwrap();
+
+        let hits: Vec<SearchHit> = response.into();
+        assert_eq!(
+            hits,
+            vec![
+                SearchHit {
+                    url: "https://example.com".to_string(),
+                    title: Some("Example".to_string()),
+                    score: 2.5,
+                    snippet: Some("an <em>example</em> page".to_string()),
+                },
+                SearchHit {
+                    url: "https://example.com/b".to_string(),
+                    title: None,
+                    score: 1.0,
+                    snippet: None,
+                }
+            ]
+        );
+    }
+}
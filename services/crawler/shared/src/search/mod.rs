@@ -0,0 +1,53 @@
+use async_trait::async_trait;
+use derive_more::Display;
+use serde::Serialize;
+
+pub use elastic::SearchIndexElastic;
+
+mod elastic;
+
+#[derive(Debug, Display)]
+pub struct SearchError {
+    message: String,
+}
+
+impl std::error::Error for SearchError {}
+
+impl From<reqwest::Error> for SearchError {
+    fn from(e: reqwest::Error) -> Self {
+        SearchError {
+            message: e.to_string(),
+        }
+    }
+}
+
+/// The visible text of a crawled page
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Document {
+    pub url: String,
+    pub title: Option<String>,
+    pub text: String,
+    /// The unix time the page was crawled
+    pub crawled_at: u64,
+}
+
+/// A page matching a search query
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SearchHit {
+    pub url: String,
+    pub title: Option<String>,
+    /// The relevance of the page to the query, higher is more relevant
+    pub score: f64,
+    /// An excerpt of the page's text matching the query, with matching terms wrapped
+    /// in `<em>` tags
+    pub snippet: Option<String>,
+}
+
+#[async_trait(?Send)]
+pub trait SearchIndex {
+    /// Indexes `document`, replacing any previous document of the same URL
+    async fn index(&self, document: &Document) -> Result<(), SearchError>;
+
+    /// Returns up to `limit` pages matching `query`, most relevant first
+    async fn search(&self, query: &str, limit: usize) -> Result<Vec<SearchHit>, SearchError>;
+}
@@ -1,34 +1,119 @@
-use rocket::http::Status;
-use rocket::{Route, State};
-use rocket_contrib::json::{Json, JsonValue};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use bytes::Bytes;
+use futures::{future, Future, Stream, StreamExt};
+use prometheus::{register_gauge, Gauge};
+use rocket::data::Data;
+use rocket::http::{ContentType, Status};
+use rocket::response::status::Custom;
+use rocket::response::{self, content, Responder};
+use rocket::{Request, Route, State};
+use rocket_contrib::json::Json;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tokio::io::{stream_reader, AsyncBufReadExt, AsyncRead, BufReader};
 
-use kinesis::producer::{Error, Producer, RawRecord};
-use rocket_util::Authenticated;
+use kinesis::producer::{Ack, Error, Producer, RawRecord};
+use kinesis::{PipelineHandler, PipelineStats};
+use openapi::OpenApi;
+use rocket_util::RequestSpan;
 use telemetry::Measure;
-use tracing::error;
+use tracing::{error, Instrument, Span};
+
+use crate::auth::WriteAccess;
+use crate::binary::{avro_key, confluent_frame, protobuf_key, AvroSchemas, SchemaId};
+use crate::config::{BinaryConfig, BodyConfig, KinesisConfig, StreamConfig};
+use crate::encoding::{read_bytes, read_json, ContentEncoding};
+use crate::schema::{RecordErrors, Schemas};
 
 lazy_static! {
     static ref COMPUTE_MEASURE: Measure = Measure::new("controller", "compute");
+    static ref SATURATION: Gauge = register_gauge!(
+        "kinesis_producer_saturation",
+        "The fraction of its capacity held by the fullest queue in the pipeline"
+    )
+    .unwrap();
+}
+
+/// The state of a single destination stream of the pipeline
+#[derive(Serialize, JsonSchema)]
+struct StreamStatus {
+    /// Records waiting to enter the pipeline
+    queued: usize,
+    /// Records waiting for their retry backoff to elapse
+    retrying: usize,
+    /// Seconds since a request to the stream last succeeded
+    #[serde(skip_serializing_if = "Option::is_none")]
+    since_success_secs: Option<f64>,
+    /// Incremented each time the stream's shards change
+    #[serde(skip_serializing_if = "Option::is_none")]
+    topology_generation: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    open_shards: Option<usize>,
 }
 
-#[get("/status")]
-fn status() -> JsonValue {
-    json!({ "status": "ok" })
+#[derive(Serialize, JsonSchema)]
+struct PipelineStatus {
+    alive: bool,
+    in_flight: i64,
+    streams: HashMap<String, StreamStatus>,
+}
+
+impl From<PipelineStats> for PipelineStatus {
+    fn from(stats: PipelineStats) -> PipelineStatus {
+        let streams = stats
+            .streams
+            .into_iter()
+            .map(|(name, stream)| {
+                let status = StreamStatus {
+                    queued: stream.queued,
+                    retrying: stream.retrying,
+                    since_success_secs: stream.since_success.map(|x| x.as_secs_f64()),
+                    topology_generation: stream.topology.as_ref().map(|x| x.generation),
+                    open_shards: stream.topology.as_ref().map(|x| x.open_shards),
+                };
+                (name, status)
+            })
+            .collect();
+
+        PipelineStatus {
+            alive: stats.alive,
+            in_flight: stats.in_flight,
+            streams,
+        }
+    }
+}
+
+/// Returns the state of the pipeline, responding 503 if its worker has exited and
+/// submitted records will therefore never be delivered
+#[get("/api/v1/status")]
+fn pipeline_status(handler: State<'_, Arc<PipelineHandler>>) -> Custom<Json<PipelineStatus>> {
+    let status = PipelineStatus::from(handler.stats());
+    if status.alive {
+        Custom(Status::Ok, Json(status))
+    } else {
+        error!("pipeline worker has exited");
+        Custom(Status::ServiceUnavailable, Json(status))
+    }
 }
 
 #[get("/metrics")]
-fn metrics() -> Result<String, Status> {
+fn metrics(producer: State<'_, Producer>) -> Result<String, Status> {
+    SATURATION.set(producer.saturation());
     telemetry::encode().map_err(|_| Status::InternalServerError)
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, JsonSchema)]
 struct PutRecords {
     records: Vec<RawRecord>,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, JsonSchema)]
 struct PutRecordsResponseItem {
+    /// The HTTP status code corresponding to the outcome of this record
+    status: u16,
     #[serde(skip_serializing_if = "Option::is_none")]
     sequence_number: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -37,52 +122,706 @@ struct PutRecordsResponseItem {
     error: Option<String>,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, JsonSchema)]
 struct PutRecordsResponse {
+    failed_record_count: usize,
     results: Vec<PutRecordsResponseItem>,
 }
 
-#[post("/api/v1/records", format = "json", data = "<request>")]
-async fn submit(
-    _authenticated: Authenticated,
-    request: Json<PutRecords>,
-    producer: State<'_, Producer>,
-) -> Result<Json<PutRecordsResponse>, ()> {
-    let results = producer
-        .inner()
-        .clone()
-        .submit(request.0.records.into_iter())
-        .await;
-
-    let results = results
-        .into_iter()
-        .map(|x| match x {
+fn error_status(e: &Error) -> (Status, &'static str) {
+    match e {
+        Error::RecordTooLarge => (Status::PayloadTooLarge, "Record too large"),
+        Error::InvalidRecord => (Status::BadRequest, "Invalid record"),
+        Error::UnknownShard => (Status::BadRequest, "Unknown shard"),
+        Error::RetriesExhausted | Error::WorkerDead | Error::AckDropped => {
+            (Status::InternalServerError, "Internal Server Error")
+        }
+        Error::Busy => (Status::TooManyRequests, "Too Many Requests"),
+        Error::BufferFull | Error::Shutdown | Error::Overflow => {
+            (Status::ServiceUnavailable, "Service Unavailable")
+        }
+        Error::Expired => (Status::GatewayTimeout, "Gateway Timeout"),
+    }
+}
+
+impl PutRecordsResponseItem {
+    fn error(status: Status, msg: &str) -> PutRecordsResponseItem {
+        PutRecordsResponseItem {
+            status: status.code,
+            sequence_number: None,
+            shard_id: None,
+            error: Some(msg.to_string()),
+        }
+    }
+
+    fn new(result: Result<Ack, Error>) -> PutRecordsResponseItem {
+        match result {
             Ok(ack) => PutRecordsResponseItem {
+                status: Status::Ok.code,
                 sequence_number: Some(ack.sequence_number),
-                shard_id: Some(ack.shard_id.to_string()),
+                shard_id: ack.shard_id.map(|x| x.to_string()),
                 error: None,
             },
             Err(e) => {
                 error!("producer error: {:?}", e);
-                let msg = match e {
-                    Error::RecordTooLarge => "Record too large",
-                    Error::WorkerDead => "Internal Server Error",
-                    Error::AckDropped => "Internal Server Error",
-                }
-                .to_string();
+                let (status, msg) = error_status(&e);
+                PutRecordsResponseItem::error(status, msg)
+            }
+        }
+    }
+}
 
-                PutRecordsResponseItem {
-                    sequence_number: None,
-                    shard_id: None,
-                    error: Some(msg),
-                }
+impl PutRecordsResponse {
+    fn new(results: Vec<Result<Ack, Error>>) -> PutRecordsResponse {
+        let results: Vec<_> = results
+            .into_iter()
+            .map(PutRecordsResponseItem::new)
+            .collect();
+
+        PutRecordsResponse {
+            failed_record_count: results.iter().filter(|x| x.error.is_some()).count(),
+            results,
+        }
+    }
+
+    /// Returns if any records were rejected because the pipeline is saturated
+    fn saturated(&self) -> bool {
+        self.results
+            .iter()
+            .any(|x| x.status == Status::TooManyRequests.code)
+    }
+
+    /// Returns the status of the response as a whole
+    ///
+    /// This is 200 if every record succeeded, the records' status if they all failed
+    /// with the same status, and otherwise 207 with the outcome of each record in the body
+    fn status(&self) -> Status {
+        if self.failed_record_count == 0 {
+            return Status::Ok;
+        }
+
+        match self.results.split_first() {
+            Some((first, rest))
+                if self.failed_record_count == self.results.len()
+                    && rest.iter().all(|x| x.status == first.status) =>
+            {
+                Status::from_code(first.status).unwrap_or(Status::InternalServerError)
+            }
+            _ => Status::MultiStatus,
+        }
+    }
+}
+
+#[derive(Serialize, JsonSchema)]
+struct InvalidRecords {
+    invalid: Vec<RecordErrors>,
+}
+
+#[derive(Responder)]
+enum ApiError {
+    Status(Status),
+    /// Records whose payloads do not satisfy the stream's JSON Schema
+    #[response(status = 422)]
+    InvalidRecords(Json<InvalidRecords>),
+}
+
+impl From<Status> for ApiError {
+    fn from(status: Status) -> Self {
+        ApiError::Status(status)
+    }
+}
+
+/// A response to a request that may have had records rejected because the pipeline
+/// is saturated, in which case a Retry-After header is added
+struct Backpressure<R> {
+    inner: R,
+    retry_after: Option<u64>,
+}
+
+impl<'r, 'o: 'r, R: Responder<'r, 'o>> Responder<'r, 'o> for Backpressure<R> {
+    fn respond_to(self, request: &'r Request<'_>) -> response::Result<'o> {
+        let mut response = self.inner.respond_to(request)?;
+        if let Some(retry_after) = self.retry_after {
+            response.set_raw_header("Retry-After", retry_after.to_string());
+        }
+        Ok(response)
+    }
+}
+
+/// Submits records to `stream` within the span of the request, failing those the
+/// pipeline has no capacity for rather than waiting
+async fn try_submit(
+    producer: &Producer,
+    stream: &str,
+    records: Vec<RawRecord>,
+    span: Span,
+) -> PutRecordsResponse {
+    let mut producer = producer.clone();
+    let records = records.into_iter().map(|record| route(record, stream));
+    let results = producer.try_submit(records).instrument(span).await;
+    SATURATION.set(producer.saturation());
+
+    PutRecordsResponse::new(results)
+}
+
+/// Fails with 422 if any record's payload does not satisfy the stream's schema
+fn validate(schemas: &Schemas, stream: &str, records: &[RawRecord]) -> Result<(), ApiError> {
+    schemas
+        .validate_all(stream, records.iter().map(|record| record.data.as_ref()))
+        .map_err(|invalid| ApiError::InvalidRecords(Json(InvalidRecords { invalid })))
+}
+
+/// Fails with 404 if the service does not serve `stream`, or 403 if the request
+/// may not write to it
+fn authorize(access: &WriteAccess, stream: &str, kinesis: &KinesisConfig) -> Result<(), Status> {
+    if !kinesis.serves(stream) {
+        return Err(Status::NotFound);
+    }
+
+    if !access.allows(stream) {
+        return Err(Status::Forbidden);
+    }
+    Ok(())
+}
+
+/// Routes records to `stream`, overriding any stream set on the record itself
+fn route(mut record: RawRecord, stream: &str) -> RawRecord {
+    record.stream = Some(stream.to_string());
+    record
+}
+
+/// Submits records, the request body may be compressed as indicated by its
+/// `Content-Encoding` header
+#[post("/api/v1/streams/<stream>/records", format = "json", data = "<data>")]
+async fn submit(
+    access: WriteAccess,
+    stream: String,
+    encoding: ContentEncoding,
+    data: Data,
+    producer: State<'_, Producer>,
+    kinesis: State<'_, KinesisConfig>,
+    schemas: State<'_, Arc<Schemas>>,
+    body: State<'_, BodyConfig>,
+    span: RequestSpan,
+) -> Result<Backpressure<Custom<Json<PutRecordsResponse>>>, ApiError> {
+    authorize(&access, &stream, &kinesis)?;
+    let request: PutRecords = read_json(data, encoding, &body).await?;
+    validate(&schemas, &stream, &request.records)?;
+
+    Ok(respond(&producer, &stream, request.records, &kinesis, span.0).await)
+}
+
+/// Submits `records`, responding 429 if any were rejected because the pipeline is saturated
+async fn respond(
+    producer: &Producer,
+    stream: &str,
+    records: Vec<RawRecord>,
+    kinesis: &KinesisConfig,
+    span: Span,
+) -> Backpressure<Custom<Json<PutRecordsResponse>>> {
+    let response = try_submit(producer, stream, records, span).await;
+    let (status, retry_after) = if response.saturated() {
+        (Status::TooManyRequests, Some(kinesis.retry_after_secs))
+    } else {
+        (Status::Ok, None)
+    };
+
+    Backpressure {
+        inner: Custom(status, Json(response)),
+        retry_after,
+    }
+}
+
+/// Reads a single binary record submitted to `stream`, whose payload is passed through
+/// unchanged with its partition key extracted by `key`
+///
+/// Fails with 415 if the stream has a JSON Schema, as binary payloads cannot be validated
+/// against it, and 422 if the partition key cannot be extracted
+async fn read_binary<F>(
+    stream: &str,
+    encoding: ContentEncoding,
+    data: Data,
+    schemas: &Schemas,
+    body: &BodyConfig,
+    key: F,
+) -> Result<RawRecord, ApiError>
+where
+    F: FnOnce(&[u8]) -> Result<String, String>,
+{
+    if schemas.contains(stream) {
+        return Err(Status::UnsupportedMediaType.into());
+    }
+
+    let data = read_bytes(data, encoding, body).await?;
+    let partition_key = key(&data).map_err(|e| {
+        ApiError::InvalidRecords(Json(InvalidRecords {
+            invalid: vec![RecordErrors {
+                index: 0,
+                errors: vec![e],
+            }],
+        }))
+    })?;
+
+    Ok(RawRecord {
+        partition_key,
+        data: Bytes::from(data),
+        explicit_hash_key: None,
+        stream: None,
+        idempotency_id: None,
+        ttl_ms: None,
+    })
+}
+
+/// Submits a single protobuf encoded record, taking its partition key from the
+/// string field configured for the stream
+#[post(
+    "/api/v1/streams/<stream>/records",
+    format = "application/x-protobuf",
+    data = "<data>"
+)]
+async fn submit_protobuf(
+    access: WriteAccess,
+    stream: String,
+    encoding: ContentEncoding,
+    data: Data,
+    producer: State<'_, Producer>,
+    kinesis: State<'_, KinesisConfig>,
+    schemas: State<'_, Arc<Schemas>>,
+    body: State<'_, BodyConfig>,
+    binary: State<'_, BinaryConfig>,
+    span: RequestSpan,
+) -> Result<Backpressure<Custom<Json<PutRecordsResponse>>>, ApiError> {
+    authorize(&access, &stream, &kinesis)?;
+    let field = *binary
+        .protobuf_key_fields
+        .get(&stream)
+        .ok_or(Status::UnsupportedMediaType)?;
+
+    let record = read_binary(&stream, encoding, data, &schemas, &body, |data| {
+        protobuf_key(data, field)
+    })
+    .await?;
+
+    Ok(respond(&producer, &stream, vec![record], &kinesis, span.0).await)
+}
+
+/// Submits a single Avro encoded record, written with the schema identified by the
+/// `X-Schema-Id` header, taking its partition key from the field configured for the stream
+///
+/// The record is put in the Confluent wire format, prefixed with the schema id, so that
+/// consumers can decode it
+#[post(
+    "/api/v1/streams/<stream>/records",
+    format = "application/avro",
+    data = "<data>"
+)]
+async fn submit_avro(
+    access: WriteAccess,
+    stream: String,
+    schema_id: SchemaId,
+    encoding: ContentEncoding,
+    data: Data,
+    producer: State<'_, Producer>,
+    kinesis: State<'_, KinesisConfig>,
+    schemas: State<'_, Arc<Schemas>>,
+    body: State<'_, BodyConfig>,
+    binary: State<'_, BinaryConfig>,
+    avro_schemas: State<'_, AvroSchemas>,
+    span: RequestSpan,
+) -> Result<Backpressure<Custom<Json<PutRecordsResponse>>>, ApiError> {
+    authorize(&access, &stream, &kinesis)?;
+    let field = binary
+        .avro_key_fields
+        .get(&stream)
+        .ok_or(Status::UnsupportedMediaType)?;
+    let schema = avro_schemas.get(&schema_id).ok_or(Status::BadRequest)?;
+
+    let mut record = read_binary(&stream, encoding, data, &schemas, &body, |data| {
+        avro_key(schema, data, field)
+    })
+    .await?;
+    record.data = Bytes::from(confluent_frame(&schema_id, &record.data));
+
+    Ok(respond(&producer, &stream, vec![record], &kinesis, span.0).await)
+}
+
+/// Submits a JSON array of records, responding with the outcome of each record
+///
+/// Unlike `submit` the response status reflects the outcome of the records, with
+/// a Retry-After header if any were rejected because the pipeline is saturated
+#[post(
+    "/api/v1/streams/<stream>/records/batch",
+    format = "json",
+    data = "<data>"
+)]
+async fn submit_batch(
+    access: WriteAccess,
+    stream: String,
+    encoding: ContentEncoding,
+    data: Data,
+    producer: State<'_, Producer>,
+    kinesis: State<'_, KinesisConfig>,
+    schemas: State<'_, Arc<Schemas>>,
+    body: State<'_, BodyConfig>,
+    span: RequestSpan,
+) -> Result<Backpressure<Custom<Json<PutRecordsResponse>>>, ApiError> {
+    authorize(&access, &stream, &kinesis)?;
+    let records: Vec<RawRecord> = read_json(data, encoding, &body).await?;
+    validate(&schemas, &stream, &records)?;
+
+    let response = try_submit(&producer, &stream, records, span.0).await;
+    let retry_after = Some(kinesis.retry_after_secs).filter(|_| response.saturated());
+
+    Ok(Backpressure {
+        inner: Custom(response.status(), Json(response)),
+        retry_after,
+    })
+}
+
+#[derive(Serialize, JsonSchema)]
+struct StreamAck {
+    /// The position of the record in the request body
+    index: usize,
+    #[serde(flatten)]
+    result: PutRecordsResponseItem,
+}
+
+/// Returns a stream of newline-delimited JSON acks for the newline-delimited JSON
+/// records read from `body`, in the order they are acknowledged
+///
+/// At most `max_in_flight` records are submitted at once, beyond which reading of
+/// the body is paused until acks are received
+fn stream_acks<R, F, Fut>(
+    body: R,
+    max_in_flight: usize,
+    submit: F,
+) -> impl Stream<Item = std::io::Result<Bytes>>
+where
+    R: AsyncRead,
+    F: Fn(RawRecord) -> Fut,
+    Fut: Future<Output = PutRecordsResponseItem>,
+{
+    BufReader::new(body)
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| future::ready(!matches!(line, Ok(line) if line.trim().is_empty())))
+        .map(move |(index, line)| {
+            let record = line
+                .map_err(|e| format!("Invalid request body: {}", e))
+                .and_then(|line| {
+                    serde_json::from_str::<RawRecord>(&line).map_err(|e| e.to_string())
+                });
+
+            let submitted = record.map(|record| submit(record));
+            async move {
+                let result = match submitted {
+                    Ok(submitted) => submitted.await,
+                    Err(e) => {
+                        error!("invalid streamed record: {}", e);
+                        PutRecordsResponseItem::error(Status::BadRequest, "Invalid record")
+                    }
+                };
+                StreamAck { index, result }
             }
         })
-        .collect();
+        .buffer_unordered(max_in_flight)
+        .map(|ack| {
+            let mut line = serde_json::to_vec(&ack).expect("failed to serialize ack");
+            line.push(b'\n');
+            Ok(Bytes::from(line))
+        })
+}
 
-    Ok(Json(PutRecordsResponse { results }))
+/// Submits a stream of newline-delimited JSON records, responding with a stream of
+/// newline-delimited JSON acks as the records are acknowledged
+///
+/// Acks are not necessarily in the order of the records, and contain the index of
+/// the record they acknowledge. Records whose payloads do not satisfy the stream's
+/// schema are acknowledged with a 422 status
+#[post(
+    "/api/v1/streams/<stream>/records/stream",
+    format = "application/x-ndjson",
+    data = "<data>"
+)]
+async fn submit_stream(
+    access: WriteAccess,
+    stream: String,
+    encoding: ContentEncoding,
+    data: Data,
+    producer: State<'_, Producer>,
+    kinesis: State<'_, KinesisConfig>,
+    config: State<'_, StreamConfig>,
+    schemas: State<'_, Arc<Schemas>>,
+    span: RequestSpan,
+) -> Result<content::Custom<rocket::response::Stream<impl AsyncRead>>, Status> {
+    authorize(&access, &stream, &kinesis)?;
+
+    let producer = producer.inner().clone();
+    let schemas = schemas.inner().clone();
+    let body = encoding.open(data, config.max_bytes);
+    let acks = stream_acks(body, config.max_in_flight, move |record| {
+        let valid = schemas.validate(&stream, &record.data);
+        let record = route(record, &stream);
+        let mut producer = producer.clone();
+        let span = span.0.clone();
+        async move {
+            match valid {
+                Ok(_) => {
+                    let submitted = producer.submit_one(record).instrument(span).await;
+                    PutRecordsResponseItem::new(submitted)
+                }
+                Err(errors) => {
+                    PutRecordsResponseItem::error(Status::UnprocessableEntity, &errors.join("; "))
+                }
+            }
+        }
+    });
+
+    Ok(content::Custom(
+        ContentType::new("application", "x-ndjson"),
+        rocket::response::Stream::from(stream_reader(Box::pin(acks))),
+    ))
 }
 
 pub fn routes() -> Vec<Route> {
-    routes![status, metrics, submit]
+    let mut routes = routes![
+        pipeline_status,
+        metrics,
+        submit,
+        submit_protobuf,
+        submit_avro,
+        submit_batch,
+        submit_stream
+    ];
+    routes.append(&mut health::routes());
+    routes
+}
+
+/// Adds the operations of the routes to an OpenAPI document
+pub fn document(api: &mut OpenApi) {
+    let status = OpenApi::json(api.schema::<PipelineStatus>());
+    let records = api.schema::<PutRecords>();
+    let record = api.schema::<RawRecord>();
+    let response = OpenApi::json(api.schema::<PutRecordsResponse>());
+    let ack = api.schema::<StreamAck>();
+    let invalid = OpenApi::json(api.schema::<InvalidRecords>());
+    let stream = OpenApi::path_parameter("stream", "The stream to submit the records to");
+    let binary = json!({ "schema": { "type": "string", "format": "binary" } });
+    let rate_limited = json!({
+        "description": "Records were rejected because the pipeline is saturated",
+        "headers": {
+            "Retry-After": {
+                "description": "The number of seconds to wait before retrying",
+                "schema": { "type": "integer" },
+            },
+        },
+        "content": response,
+    });
+
+    api.operation(
+        "get",
+        "/api/v1/status",
+        json!({
+            "summary": "Returns the state of the pipeline and its destination streams",
+            "operationId": "status",
+            "responses": {
+                "200": { "description": "The pipeline is running", "content": status },
+                "503": {
+                    "description": "The pipeline worker has exited, and submitted records \
+                        will never be delivered",
+                    "content": status,
+                },
+            },
+        }),
+    );
+    api.operation(
+        "post",
+        "/api/v1/streams/{stream}/records",
+        json!({
+            "summary": "Submits records to a stream",
+            "description": "A protobuf or Avro body is a single record whose partition key is \
+                taken from the field configured for the stream, where Avro records name the \
+                schema they were written with in the X-Schema-Id header, and are put in the \
+                Confluent wire format prefixed with its id. The body may be compressed as \
+                given by its Content-Encoding header",
+            "operationId": "submit",
+            "security": [{ "bearer": [] }],
+            "parameters": [stream],
+            "requestBody": {
+                "required": true,
+                "content": {
+                    "application/json": { "schema": records },
+                    "application/x-protobuf": binary,
+                    "application/avro": binary,
+                },
+            },
+            "responses": {
+                "200": { "description": "The outcome of each record", "content": response },
+                "400": {
+                    "description": "The X-Schema-Id header is missing or names an unknown schema",
+                },
+                "401": { "description": "The JWT is missing or invalid" },
+                "403": { "description": "The token may not write to the stream" },
+                "404": { "description": "The stream isn't served" },
+                "415": {
+                    "description": "The stream has a JSON Schema, or no key field for the format",
+                },
+                "422": {
+                    "description": "Record payloads don't satisfy the stream's JSON Schema, \
+                        or a partition key can't be extracted",
+                    "content": invalid,
+                },
+                "429": rate_limited,
+            },
+        }),
+    );
+    api.operation(
+        "post",
+        "/api/v1/streams/{stream}/records/batch",
+        json!({
+            "summary": "Submits a batch of records to a stream",
+            "description": "Unlike submit, the status reflects the outcome of the records. \
+                The body may be compressed as given by its Content-Encoding header",
+            "operationId": "submitBatch",
+            "security": [{ "bearer": [] }],
+            "parameters": [stream],
+            "requestBody": {
+                "required": true,
+                "content": OpenApi::json(json!({ "type": "array", "items": record })),
+            },
+            "responses": {
+                "200": { "description": "Every record was submitted", "content": response },
+                "207": {
+                    "description": "Some records failed, with the outcome of each",
+                    "content": response,
+                },
+                "401": { "description": "The JWT is missing or invalid" },
+                "403": { "description": "The token may not write to the stream" },
+                "404": { "description": "The stream isn't served" },
+                "422": {
+                    "description": "Record payloads don't satisfy the stream's JSON Schema",
+                    "content": invalid,
+                },
+                "429": rate_limited,
+            },
+        }),
+    );
+    api.operation(
+        "post",
+        "/api/v1/streams/{stream}/records/stream",
+        json!({
+            "summary": "Submits a stream of newline-delimited JSON records to a stream",
+            "description": "Acks are streamed as the records are acknowledged, not necessarily \
+                in order, each with the index of its record",
+            "operationId": "submitStream",
+            "security": [{ "bearer": [] }],
+            "parameters": [stream],
+            "requestBody": {
+                "required": true,
+                "content": { "application/x-ndjson": { "schema": record } },
+            },
+            "responses": {
+                "200": {
+                    "description": "The acks of the records",
+                    "content": { "application/x-ndjson": { "schema": ack } },
+                },
+                "401": { "description": "The JWT is missing or invalid" },
+                "403": { "description": "The token may not write to the stream" },
+                "404": { "description": "The stream isn't served" },
+            },
+        }),
+    );
+    openapi::metrics(api);
+    health::document(api);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(results: Vec<Result<(), Error>>) -> PutRecordsResponse {
+        PutRecordsResponse::new(
+            results
+                .into_iter()
+                .map(|x| {
+                    x.map(|_| Ack {
+                        shard_id: None,
+                        sequence_number: "1".to_string(),
+                    })
+                })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_document() {
+        let doc = OpenApi::default().with(document).build("Producer", "");
+        assert_eq!(
+            openapi::undocumented_routes(&doc, &routes()),
+            Vec::<String>::new()
+        );
+    }
+
+    #[test]
+    fn test_status() {
+        assert_eq!(response(vec![]).status(), Status::Ok);
+        assert_eq!(response(vec![Ok(()), Ok(())]).status(), Status::Ok);
+
+        let partial = response(vec![Ok(()), Err(Error::Busy)]);
+        assert_eq!(partial.failed_record_count, 1);
+        assert_eq!(partial.status(), Status::MultiStatus);
+        assert_eq!(partial.results[1].status, 429);
+        assert!(partial.saturated());
+
+        let failed = response(vec![Err(Error::Overflow), Err(Error::Shutdown)]);
+        assert_eq!(failed.status(), Status::ServiceUnavailable);
+        assert!(!failed.saturated());
+
+        let saturated = response(vec![Err(Error::Busy), Err(Error::Busy)]);
+        assert_eq!(saturated.status(), Status::TooManyRequests);
+
+        let mixed = response(vec![Err(Error::Busy), Err(Error::InvalidRecord)]);
+        assert_eq!(mixed.status(), Status::MultiStatus);
+    }
+
+    #[tokio::test]
+    async fn test_stream_acks() {
+        let body = concat!(
+            r#"{"partition_key":"a","data":[1]}"#,
+            "\n",
+            "not json\n",
+            "\n",
+            r#"{"partition_key":"b","data":[2]}"#,
+            "\n",
+        );
+
+        let acks = stream_acks(body.as_bytes(), 2, |record| async move {
+            PutRecordsResponseItem::new(match record.partition_key.as_str() {
+                "a" => Ok(Ack {
+                    shard_id: None,
+                    sequence_number: "1".to_string(),
+                }),
+                _ => Err(Error::Busy),
+            })
+        });
+
+        let lines: Vec<_> = acks.map(|x| x.unwrap()).collect().await;
+        let mut acks: Vec<serde_json::Value> = lines
+            .iter()
+            .map(|x| {
+                assert!(x.ends_with(b"\n"));
+                serde_json::from_slice(x).unwrap()
+            })
+            .collect();
+        acks.sort_by_key(|x| x["index"].as_u64());
+
+        assert_eq!(acks.len(), 3);
+        assert_eq!(acks[0]["index"], 0);
+        assert_eq!(acks[0]["status"], 200);
+        assert_eq!(acks[0]["sequence_number"], "1");
+        assert_eq!(acks[1]["index"], 1);
+        assert_eq!(acks[1]["status"], 400);
+        assert_eq!(acks[2]["index"], 3);
+        assert_eq!(acks[2]["status"], 429);
+    }
 }
@@ -0,0 +1,38 @@
+use rocket_util::{ResourceAccess, ScopePrefix};
+
+/// The prefix of scopes granting write access to the stream they are suffixed with
+#[derive(Debug)]
+pub struct WriteScope;
+
+impl ScopePrefix for WriteScope {
+    const PREFIX: &'static str = "kinesis:write:";
+}
+
+/// The streams a request may write to, granted by `kinesis:write:<stream>` scopes,
+/// or all streams if it has the superuser scope
+pub type WriteAccess = ResourceAccess<WriteScope>;
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use jwt::Scope;
+
+    use super::*;
+
+    fn scopes(scopes: &str) -> HashSet<Scope> {
+        jwt::tag::parse_space_delimited(scopes).unwrap()
+    }
+
+    #[test]
+    fn test_write_access() {
+        let access = WriteAccess::new(&scopes("kinesis:write:a kinesis:read:b offline_access"));
+
+        assert!(access.allows("a"));
+        assert!(!access.allows("b"));
+        assert!(!access.allows("kinesis:write:a"));
+
+        let superuser = WriteAccess::new(&scopes("superuser"));
+        assert!(superuser.allows("b"));
+    }
+}
@@ -0,0 +1,206 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use avro_rs::types::Value;
+use avro_rs::Schema;
+use prost::encoding::decode_varint;
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome};
+use rocket::Request;
+
+#[derive(Debug)]
+pub enum AvroSchemaError {
+    Io(std::io::Error),
+    Invalid(String),
+}
+
+/// Returns the value of the top-level string field numbered `field` of an encoded
+/// protobuf message, without requiring the message's schema
+///
+/// If the field is repeated the last value is returned, as for a singular field
+pub fn protobuf_key(mut data: &[u8], field: u32) -> Result<String, String> {
+    let mut key = None;
+    while !data.is_empty() {
+        let tag = decode_varint(&mut data).map_err(|e| e.to_string())?;
+        let (number, wire_type) = ((tag >> 3) as u32, tag & 0x7);
+
+        let len = match wire_type {
+            0 => {
+                decode_varint(&mut data).map_err(|e| e.to_string())?;
+                continue;
+            }
+            1 => 8,
+            2 => decode_varint(&mut data).map_err(|e| e.to_string())? as usize,
+            5 => 4,
+            _ => return Err(format!("unsupported wire type {}", wire_type)),
+        };
+
+        if len > data.len() {
+            return Err("message is truncated".to_string());
+        }
+
+        let (value, rest) = data.split_at(len);
+        if number == field && wire_type == 2 {
+            key = Some(value);
+        }
+        data = rest;
+    }
+
+    let key = key.ok_or_else(|| format!("missing field {}", field))?;
+    String::from_utf8(key.to_vec()).map_err(|_| format!("field {} is not a string", field))
+}
+
+/// Returns the value of the top-level string field `field` of an Avro record
+/// encoded with `schema`
+pub fn avro_key(schema: &Schema, mut data: &[u8], field: &str) -> Result<String, String> {
+    let value = avro_rs::from_avro_datum(schema, &mut data, None).map_err(|e| e.to_string())?;
+    let fields = match value {
+        Value::Record(fields) => fields,
+        _ => return Err("payload is not a record".to_string()),
+    };
+
+    let value = fields
+        .into_iter()
+        .find(|(name, _)| name == field)
+        .map(|(_, value)| value);
+
+    // Optional fields are encoded as a union with null
+    let value = match value {
+        Some(Value::Union(inner)) => Some(*inner),
+        value => value,
+    };
+
+    match value {
+        Some(Value::String(key)) => Ok(key),
+        Some(_) => Err(format!("field {} is not a string", field)),
+        None => Err(format!("missing field {}", field)),
+    }
+}
+
+/// The magic byte starting a record in the Confluent wire format
+const CONFLUENT_MAGIC: u8 = 0;
+
+/// Returns an Avro record encoded with the schema `id` in the Confluent wire format,
+/// the magic byte and the big-endian schema registry id followed by the record, so
+/// that consumers can find the schema it was written with
+pub fn confluent_frame(id: &SchemaId, data: &[u8]) -> Vec<u8> {
+    let mut framed = Vec::with_capacity(data.len() + 5);
+    framed.push(CONFLUENT_MAGIC);
+    framed.extend_from_slice(&id.0.to_be_bytes());
+    framed.extend_from_slice(data);
+    framed
+}
+
+/// The Avro schemas records may be encoded with, keyed by schema registry id
+pub struct AvroSchemas {
+    schemas: HashMap<u32, Schema>,
+}
+
+impl AvroSchemas {
+    pub fn load(paths: &HashMap<String, PathBuf>) -> Result<AvroSchemas, AvroSchemaError> {
+        let schemas = paths
+            .iter()
+            .map(|(id, path)| {
+                let id = id.parse().map_err(|_| {
+                    AvroSchemaError::Invalid(format!("invalid schema registry id: {}", id))
+                })?;
+                let schema = std::fs::read_to_string(path).map_err(AvroSchemaError::Io)?;
+                let schema = Schema::parse_str(&schema)
+                    .map_err(|e| AvroSchemaError::Invalid(e.to_string()))?;
+                Ok((id, schema))
+            })
+            .collect::<Result<_, AvroSchemaError>>()?;
+
+        Ok(AvroSchemas { schemas })
+    }
+
+    pub fn get(&self, id: &SchemaId) -> Option<&Schema> {
+        self.schemas.get(&id.0)
+    }
+}
+
+/// The schema registry id of an Avro request body, from the `X-Schema-Id` header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SchemaId(pub u32);
+
+#[rocket::async_trait]
+impl<'a, 'r> FromRequest<'a, 'r> for SchemaId {
+    type Error = ();
+
+    async fn from_request(request: &'a Request<'r>) -> Outcome<SchemaId, Self::Error> {
+        let header = request.headers().get_one("X-Schema-Id");
+        match header.and_then(|id| id.trim().parse().ok()) {
+            Some(id) => Outcome::Success(SchemaId(id)),
+            None => Outcome::Failure((Status::BadRequest, ())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use prost::encoding::{bytes, int64, string};
+
+    use super::*;
+
+    #[test]
+    fn test_protobuf_key() {
+        let mut data = Vec::new();
+        int64::encode(1, &-5, &mut data);
+        string::encode(2, &"key".to_string(), &mut data);
+        bytes::encode(3, &vec![0xff, 0xfe], &mut data);
+
+        assert_eq!(protobuf_key(&data, 2).unwrap(), "key");
+        assert_eq!(
+            protobuf_key(&data, 3).unwrap_err(),
+            "field 3 is not a string"
+        );
+        assert_eq!(protobuf_key(&data, 4).unwrap_err(), "missing field 4");
+
+        // Field 1 is a varint
+        assert_eq!(protobuf_key(&data, 1).unwrap_err(), "missing field 1");
+        assert!(protobuf_key(&data[..data.len() - 1], 2).is_err());
+    }
+
+    #[test]
+    fn test_avro_key() {
+        let schema = Schema::parse_str(
+            r#"{
+                "type": "record",
+                "name": "test",
+                "fields": [
+                    {"name": "id", "type": "long"},
+                    {"name": "key", "type": "string"},
+                    {"name": "tenant", "type": ["null", "string"]}
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        let record = Value::Record(vec![
+            ("id".to_string(), Value::Long(1)),
+            ("key".to_string(), Value::String("a".to_string())),
+            (
+                "tenant".to_string(),
+                Value::Union(Box::new(Value::String("b".to_string()))),
+            ),
+        ]);
+        let data = avro_rs::to_avro_datum(&schema, record).unwrap();
+
+        assert_eq!(avro_key(&schema, &data, "key").unwrap(), "a");
+        assert_eq!(avro_key(&schema, &data, "tenant").unwrap(), "b");
+        assert_eq!(
+            avro_key(&schema, &data, "id").unwrap_err(),
+            "field id is not a string"
+        );
+        assert_eq!(
+            avro_key(&schema, &data, "missing").unwrap_err(),
+            "missing field missing"
+        );
+    }
+
+    #[test]
+    fn test_confluent_frame() {
+        let framed = confluent_frame(&SchemaId(258), &[1, 2]);
+        assert_eq!(framed, vec![0, 0, 0, 1, 2, 1, 2]);
+    }
+}
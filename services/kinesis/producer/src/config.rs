@@ -1,23 +1,119 @@
-use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
 
 use jwt::ValidatorConfig;
+use kinesis::consumer::{Consumer, ConsumerBuilder};
 use kinesis::producer::Producer;
 use kinesis::{PipelineBuilder, PipelineHandler};
+use settings::{Error, Validate};
+use shutdown::ShutdownConfig;
+use telemetry::trace::TracingConfig;
 
-#[derive(Debug, Deserialize, Clone, Default)]
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
 #[serde(default)]
 pub struct Config {
     pub validator: ValidatorConfig,
     pub kinesis: KinesisConfig,
+    pub stream: StreamConfig,
+    pub body: BodyConfig,
+    pub binary: BinaryConfig,
+    /// The path of the JSON Schema that the payloads of records submitted to a
+    /// stream must satisfy, keyed by stream name
+    pub schemas: HashMap<String, PathBuf>,
+    pub shutdown: ShutdownConfig,
+    pub tracing: TracingConfig,
+}
+
+impl Validate for Config {
+    fn validate(&self) -> Result<(), Error> {
+        let protobuf = &self.binary.protobuf_key_fields;
+        let avro = &self.binary.avro_key_fields;
+        let streams = self
+            .schemas
+            .keys()
+            .map(|x| ("schemas", x))
+            .chain(protobuf.keys().map(|x| ("binary.protobuf_key_fields", x)))
+            .chain(avro.keys().map(|x| ("binary.avro_key_fields", x)));
+
+        for (key, stream) in streams {
+            if !self.kinesis.serves(stream) {
+                let message = format!("stream {} isn't served", stream);
+                return Err(Error::invalid(key, message));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Configures the submission of protobuf and Avro records, whose payloads are passed
+/// through unchanged
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct BinaryConfig {
+    /// The field number of the top-level string field holding the partition key of
+    /// protobuf records, keyed by stream name
+    pub protobuf_key_fields: HashMap<String, u32>,
+    /// The name of the top-level string field holding the partition key of Avro
+    /// records, keyed by stream name
+    pub avro_key_fields: HashMap<String, String>,
+    /// The path of each Avro schema, keyed by numeric schema registry id
+    pub avro_schemas: HashMap<String, PathBuf>,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+/// Limits the size of request bodies, which may be compressed
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct BodyConfig {
+    /// The maximum size of a request body as sent
+    pub max_encoded_bytes: u64,
+    /// The maximum size of a request body once decompressed
+    pub max_bytes: u64,
+}
+
+impl Default for BodyConfig {
+    fn default() -> BodyConfig {
+        BodyConfig {
+            max_encoded_bytes: 1024 * 1024,
+            max_bytes: 8 * 1024 * 1024,
+        }
+    }
+}
+
+/// Configures the streaming ingestion endpoint
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct StreamConfig {
+    /// The maximum number of records from a single stream awaiting acknowledgement
+    pub max_in_flight: usize,
+    /// The maximum number of bytes read from a single stream, both as sent and
+    /// once decompressed
+    pub max_bytes: u64,
+}
+
+impl Default for StreamConfig {
+    fn default() -> StreamConfig {
+        StreamConfig {
+            max_in_flight: 1000,
+            max_bytes: 1024 * 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
 #[serde(default)]
 pub struct KinesisConfig {
     pub region: String,
     pub endpoint: Option<String>,
     pub stream_name: String,
+    /// Additional streams served alongside `stream_name`
+    pub streams: Vec<String>,
     pub local: bool,
+    pub localstack: bool,
+    /// The number of seconds clients are asked to wait before retrying records
+    /// rejected because the pipeline is saturated
+    pub retry_after_secs: u64,
 }
 
 impl Default for KinesisConfig {
@@ -26,23 +122,64 @@ impl Default for KinesisConfig {
             region: "us-east-1".to_string(),
             stream_name: "kinesis".to_string(),
             endpoint: None,
+            streams: vec![],
             local: false,
+            localstack: false,
+            retry_after_secs: 1,
         }
     }
 }
 
 impl KinesisConfig {
+    /// Returns if records can be submitted to `stream`
+    pub fn serves(&self, stream: &str) -> bool {
+        self.stream_name == stream || self.streams.iter().any(|x| x == stream)
+    }
+
     pub fn pipeline(&self) -> (Producer, PipelineHandler) {
         let mut builder = PipelineBuilder::new(self.region.clone(), self.stream_name.clone());
 
+        for stream in &self.streams {
+            builder.add_stream(stream.clone());
+        }
+
         if self.local {
             builder.local();
         }
 
+        if self.localstack {
+            builder.localstack();
+        }
+
         if let Some(endpoint) = self.endpoint.as_ref() {
             builder.endpoint(endpoint.clone());
         }
 
         builder.build()
     }
+
+    /// Returns a consumer of each stream served, keyed by stream name, with which to
+    /// check that the streams can be described
+    pub fn consumers(&self) -> HashMap<String, Consumer> {
+        std::iter::once(&self.stream_name)
+            .chain(&self.streams)
+            .map(|stream| {
+                let mut builder = ConsumerBuilder::new(self.region.clone(), stream.clone());
+
+                if self.local {
+                    builder.local();
+                }
+
+                if self.localstack {
+                    builder.localstack();
+                }
+
+                if let Some(endpoint) = self.endpoint.as_ref() {
+                    builder.endpoint(endpoint.clone());
+                }
+
+                (stream.clone(), builder.build())
+            })
+            .collect()
+    }
 }
@@ -0,0 +1,263 @@
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use async_compression::tokio_02::bufread::{GzipDecoder, ZstdDecoder};
+use rocket::data::{Data, ToByteUnit};
+use rocket::http::Status;
+use rocket::request::{FromRequest, Outcome};
+use rocket::Request;
+use serde::de::DeserializeOwned;
+use tokio::io::{AsyncRead, AsyncReadExt, BufReader};
+use tracing::warn;
+
+use crate::config::BodyConfig;
+
+/// The `Content-Encoding` of a request body
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentEncoding {
+    Identity,
+    Gzip,
+    Zstd,
+}
+
+impl ContentEncoding {
+    fn parse(header: Option<&str>) -> Option<ContentEncoding> {
+        let header = match header {
+            Some(header) => header.trim().to_ascii_lowercase(),
+            None => return Some(ContentEncoding::Identity),
+        };
+
+        match header.as_str() {
+            "identity" => Some(ContentEncoding::Identity),
+            "gzip" | "x-gzip" => Some(ContentEncoding::Gzip),
+            "zstd" => Some(ContentEncoding::Zstd),
+            _ => None,
+        }
+    }
+
+    /// Returns a reader of the decoded contents of `body`
+    pub fn decode<R>(self, body: R) -> Pin<Box<dyn AsyncRead + Send>>
+    where
+        R: AsyncRead + Send + 'static,
+    {
+        match self {
+            ContentEncoding::Identity => Box::pin(body),
+            ContentEncoding::Gzip => Box::pin(GzipDecoder::new(BufReader::new(body))),
+            ContentEncoding::Zstd => Box::pin(ZstdDecoder::new(BufReader::new(body))),
+        }
+    }
+
+    /// Returns a reader of the decoded request body, which fails if the body is longer
+    /// than `max_bytes` either before or after decoding
+    pub fn open(self, data: Data, max_bytes: u64) -> impl AsyncRead + Send {
+        let encoded = Box::pin(data.open((max_bytes + 1).bytes()));
+        Limited::new(self.decode(Limited::new(encoded, max_bytes)), max_bytes)
+    }
+}
+
+/// A reader that fails once more than `max_bytes` have been read from `inner`, and
+/// then reports the end of the body
+struct Limited<R> {
+    inner: R,
+    remaining: u64,
+    exceeded: bool,
+}
+
+impl<R> Limited<R> {
+    fn new(inner: R, max_bytes: u64) -> Self {
+        Limited {
+            inner,
+            remaining: max_bytes,
+            exceeded: false,
+        }
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for Limited<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        if self.exceeded || buf.is_empty() {
+            return Poll::Ready(Ok(0));
+        }
+
+        // Read one byte past the limit so that a body of exactly `max_bytes` is allowed
+        let len = buf.len().min(self.remaining.saturating_add(1) as usize);
+        let read = match Pin::new(&mut self.inner).poll_read(cx, &mut buf[..len]) {
+            Poll::Ready(Ok(read)) => read,
+            other => return other,
+        };
+
+        if read as u64 > self.remaining {
+            self.exceeded = true;
+            return Poll::Ready(Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "request body too large",
+            )));
+        }
+        self.remaining -= read as u64;
+        Poll::Ready(Ok(read))
+    }
+}
+
+#[rocket::async_trait]
+impl<'a, 'r> FromRequest<'a, 'r> for ContentEncoding {
+    type Error = ();
+
+    async fn from_request(request: &'a Request<'r>) -> Outcome<ContentEncoding, Self::Error> {
+        let header = request.headers().get_one("Content-Encoding");
+        match ContentEncoding::parse(header) {
+            Some(encoding) => Outcome::Success(encoding),
+            None => {
+                warn!(?header, "unsupported content encoding");
+                Outcome::Failure((Status::UnsupportedMediaType, ()))
+            }
+        }
+    }
+}
+
+/// Reads `body` to the end, failing with 413 if it is longer than `max_bytes`
+async fn read_limited<R: AsyncRead + Unpin>(body: R, max_bytes: u64) -> Result<Vec<u8>, Status> {
+    let mut buf = Vec::new();
+    body.take(max_bytes + 1)
+        .read_to_end(&mut buf)
+        .await
+        .map_err(|e| {
+            warn!("error decoding request body: {}", e);
+            Status::BadRequest
+        })?;
+
+    if buf.len() as u64 > max_bytes {
+        return Err(Status::PayloadTooLarge);
+    }
+    Ok(buf)
+}
+
+/// Reads a request body in the given encoding
+///
+/// Fails with 413 if the body exceeds the configured limits either before or after
+/// decoding, and 400 if it cannot be decoded
+pub async fn read_bytes(
+    data: Data,
+    encoding: ContentEncoding,
+    config: &BodyConfig,
+) -> Result<Vec<u8>, Status> {
+    let encoded = read_limited(
+        data.open((config.max_encoded_bytes + 1).bytes()),
+        config.max_encoded_bytes,
+    )
+    .await?;
+
+    read_limited(
+        encoding.decode(std::io::Cursor::new(encoded)),
+        config.max_bytes,
+    )
+    .await
+}
+
+/// Reads and deserializes a JSON request body in the given encoding
+///
+/// Fails as `read_bytes`, or with 400 if the body cannot be deserialized
+pub async fn read_json<T: DeserializeOwned>(
+    data: Data,
+    encoding: ContentEncoding,
+    config: &BodyConfig,
+) -> Result<T, Status> {
+    let decoded = read_bytes(data, encoding, config).await?;
+
+    serde_json::from_slice(&decoded).map_err(|e| {
+        warn!("invalid request body: {}", e);
+        Status::BadRequest
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use async_compression::tokio_02::bufread::{GzipEncoder, ZstdEncoder};
+
+    use super::*;
+
+    async fn roundtrip(
+        encoding: ContentEncoding,
+        data: &[u8],
+        max_bytes: u64,
+    ) -> Result<Vec<u8>, Status> {
+        let mut encoded = Vec::new();
+        match encoding {
+            ContentEncoding::Identity => encoded.extend_from_slice(data),
+            ContentEncoding::Gzip => {
+                GzipEncoder::new(data)
+                    .read_to_end(&mut encoded)
+                    .await
+                    .unwrap();
+            }
+            ContentEncoding::Zstd => {
+                ZstdEncoder::new(data)
+                    .read_to_end(&mut encoded)
+                    .await
+                    .unwrap();
+            }
+        };
+
+        read_limited(encoding.decode(std::io::Cursor::new(encoded)), max_bytes).await
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            ContentEncoding::parse(None),
+            Some(ContentEncoding::Identity)
+        );
+        assert_eq!(
+            ContentEncoding::parse(Some("GZIP")),
+            Some(ContentEncoding::Gzip)
+        );
+        assert_eq!(
+            ContentEncoding::parse(Some("zstd")),
+            Some(ContentEncoding::Zstd)
+        );
+        assert_eq!(ContentEncoding::parse(Some("br")), None);
+    }
+
+    #[tokio::test]
+    async fn test_decode() {
+        let data = br#"[{"partition_key":"a","data":[1]}]"#.repeat(100);
+        let len = data.len() as u64;
+
+        for encoding in &[
+            ContentEncoding::Identity,
+            ContentEncoding::Gzip,
+            ContentEncoding::Zstd,
+        ] {
+            assert_eq!(roundtrip(*encoding, &data, len).await.unwrap(), data);
+
+            // The limit applies to the decoded body
+            let result = roundtrip(*encoding, &data, len - 1).await;
+            assert_eq!(result.unwrap_err(), Status::PayloadTooLarge);
+        }
+
+        let result = read_limited(ContentEncoding::Gzip.decode(&b"not gzip"[..]), len).await;
+        assert_eq!(result.unwrap_err(), Status::BadRequest);
+    }
+
+    #[tokio::test]
+    async fn test_limited() {
+        let data = b"0123456789";
+
+        let mut buf = Vec::new();
+        let mut reader = Limited::new(&data[..], 10);
+        reader.read_to_end(&mut buf).await.unwrap();
+        assert_eq!(&buf, data);
+
+        buf.clear();
+        let mut reader = Limited::new(&data[..], 9);
+        let err = reader.read_to_end(&mut buf).await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        // The end of the body is reported after the error
+        assert_eq!(reader.read(&mut [0; 4]).await.unwrap(), 0);
+    }
+}
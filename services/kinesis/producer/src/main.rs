@@ -2,38 +2,99 @@
 extern crate lazy_static;
 #[macro_use]
 extern crate rocket;
-#[macro_use]
-extern crate rocket_contrib;
 
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use health::{Health, Probe};
 use jwt::Validator;
-use tracing_subscriber::{EnvFilter, FmtSubscriber};
+use kinesis::consumer::Consumer;
+use openapi::{OpenApi, Spec};
+use rocket_util::RequestTracer;
+use shutdown::{GracefulShutdown, Phase};
+
+use crate::binary::AvroSchemas;
+use crate::schema::Schemas;
 
 mod api;
+mod auth;
+mod binary;
 mod config;
+mod encoding;
+mod schema;
 
-#[rocket::main]
-async fn main() {
-    let subscriber = FmtSubscriber::builder()
-        .with_env_filter(EnvFilter::from_default_env())
-        .finish();
+/// Returns the checks of the keys tokens are validated with, and that each stream can
+/// be described
+fn health(validator: Validator, consumers: HashMap<String, Consumer>) -> Health {
+    let probes = [Probe::Ready, Probe::Startup];
+    let health = Health::new().register("jwks", &probes, move || {
+        let result = validator.check();
+        async move { result }
+    });
 
-    tracing::subscriber::set_global_default(subscriber).expect("setting default subscriber failed");
+    consumers
+        .into_iter()
+        .fold(health, |health, (stream, consumer)| {
+            health.register(format!("kinesis:{}", stream), &probes, move || {
+                let consumer = consumer.clone();
+                async move { consumer.check().await.map_err(|e| format!("{:?}", e)) }
+            })
+        })
+}
 
-    let figment = rocket_util::figment();
-    let config: config::Config = figment.extract().unwrap();
+#[rocket::main]
+async fn main() {
+    let (config, figment) = settings::load_with::<config::Config>(rocket::Config::figment());
+    let _exporter = telemetry::trace::init("producer", env!("CARGO_PKG_VERSION"), &config.tracing);
 
-    let (producer, handle) = config.kinesis.pipeline();
+    let (producer, handler) = config.kinesis.pipeline();
+    let handler = Arc::new(handler);
 
     let validator = Validator::new(&config.validator).expect("Failed to load JWT validator");
+    let health = health(validator.clone(), config.kinesis.consumers());
+    let schemas = Arc::new(Schemas::load(&config.schemas).expect("Failed to load JSON schemas"));
+    let avro_schemas =
+        AvroSchemas::load(&config.binary.avro_schemas).expect("Failed to load Avro schemas");
+
+    let doc = OpenApi::default().with(api::document).build(
+        "Kinesis Producer",
+        "Submits records to the Kinesis streams it serves",
+    );
 
-    let result = rocket::custom(figment)
+    let rocket = rocket::custom(figment)
         .manage(validator)
+        .manage(health)
         .manage(producer)
+        .manage(handler.clone())
+        .manage(config.kinesis)
+        .manage(config.stream)
+        .manage(config.body)
+        .manage(config.binary)
+        .manage(avro_schemas)
+        .manage(schemas)
+        .manage(Spec::new(&doc))
+        .attach(RequestTracer)
         .mount("/", api::routes())
-        .launch()
-        .await;
+        .mount("/", openapi::routes());
 
-    handle.shutdown().await.unwrap();
+    // On being asked to stop, rocket stops taking requests and is given until the
+    // deadline to finish those in flight, after which the records they submitted
+    // are flushed
+    let server = rocket.shutdown();
+    let result = GracefulShutdown::new(config.shutdown.deadline())
+        .on(Phase::StopIntake, async move { server.shutdown() })
+        .on(Phase::Close, async {
+            // The handler is no longer shared once rocket has shut down, unless
+            // requests were abandoned at the deadline
+            match Arc::try_unwrap(handler) {
+                Ok(handler) => handler.shutdown().await.unwrap(),
+                Err(_) => tracing::error!("Pipeline handler still shared after shutdown"),
+            }
+        })
+        .run_until_signal(rocket.launch())
+        .await;
 
-    assert!(result.is_ok());
+    if let Some(result) = result {
+        assert!(result.is_ok());
+    }
 }
@@ -0,0 +1,139 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use jsonschema::JSONSchema;
+use schemars::JsonSchema;
+use serde::Serialize;
+use serde_json::Value;
+
+#[derive(Debug)]
+pub enum SchemaError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    Invalid(String),
+}
+
+/// The validation failures of a single record
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct RecordErrors {
+    /// The position of the record in the request
+    pub index: usize,
+    pub errors: Vec<String>,
+}
+
+/// The JSON Schemas the payloads of records submitted to each stream must satisfy
+///
+/// Streams without a schema accept any payload
+pub struct Schemas {
+    schemas: HashMap<String, JSONSchema<'static>>,
+}
+
+impl Schemas {
+    /// Loads the schema file configured for each stream
+    pub fn load(paths: &HashMap<String, PathBuf>) -> Result<Schemas, SchemaError> {
+        let schemas = paths
+            .iter()
+            .map(|(stream, path)| {
+                let file = std::fs::File::open(path).map_err(SchemaError::Io)?;
+                let schema = serde_json::from_reader(file).map_err(SchemaError::Json)?;
+                Ok((stream.clone(), compile(schema)?))
+            })
+            .collect::<Result<_, SchemaError>>()?;
+
+        Ok(Schemas { schemas })
+    }
+
+    /// Returns true if records submitted to `stream` must satisfy a schema
+    pub fn contains(&self, stream: &str) -> bool {
+        self.schemas.contains_key(stream)
+    }
+
+    /// Validates the payload of a record submitted to `stream`, returning the
+    /// reasons it is invalid if any
+    pub fn validate(&self, stream: &str, data: &[u8]) -> Result<(), Vec<String>> {
+        let schema = match self.schemas.get(stream) {
+            Some(schema) => schema,
+            None => return Ok(()),
+        };
+
+        let instance: Value = serde_json::from_slice(data)
+            .map_err(|e| vec![format!("payload is not valid JSON: {}", e)])?;
+
+        schema
+            .validate(&instance)
+            .map_err(|errors| errors.map(|e| e.to_string()).collect())
+    }
+
+    /// Validates the payloads of records submitted to `stream`, returning the
+    /// failures of every invalid record
+    pub fn validate_all<'a>(
+        &self,
+        stream: &str,
+        payloads: impl Iterator<Item = &'a [u8]>,
+    ) -> Result<(), Vec<RecordErrors>> {
+        let invalid: Vec<_> = payloads
+            .enumerate()
+            .filter_map(|(index, data)| {
+                let errors = self.validate(stream, data).err()?;
+                Some(RecordErrors { index, errors })
+            })
+            .collect();
+
+        if !invalid.is_empty() {
+            return Err(invalid);
+        }
+        Ok(())
+    }
+}
+
+/// Compiles `schema`, which lives for the lifetime of the service
+fn compile(schema: Value) -> Result<JSONSchema<'static>, SchemaError> {
+    let schema: &'static Value = Box::leak(Box::new(schema));
+    JSONSchema::compile(schema).map_err(|e| SchemaError::Invalid(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        let schema = json!({
+            "type": "object",
+            "properties": {
+                "user": { "type": "string" },
+                "value": { "type": "integer" }
+            },
+            "required": ["user"]
+        });
+
+        let mut schemas = HashMap::new();
+        schemas.insert("events".to_string(), compile(schema).unwrap());
+        let schemas = Schemas { schemas };
+
+        assert!(schemas
+            .validate("events", br#"{"user":"a","value":1}"#)
+            .is_ok());
+        assert!(schemas.validate("other", b"not json").is_ok());
+        assert_eq!(
+            schemas.validate("events", b"not json").unwrap_err().len(),
+            1
+        );
+        assert_eq!(
+            schemas
+                .validate("events", br#"{"value":"1"}"#)
+                .unwrap_err()
+                .len(),
+            2
+        );
+
+        let payloads = vec![&br#"{"user":"a"}"#[..], &b"{}"[..]];
+        let invalid = schemas
+            .validate_all("events", payloads.into_iter())
+            .unwrap_err();
+        assert_eq!(invalid.len(), 1);
+        assert_eq!(invalid[0].index, 1);
+    }
+}
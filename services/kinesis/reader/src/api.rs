@@ -0,0 +1,262 @@
+use std::collections::HashMap;
+
+use bytes::Bytes;
+use futures::stream::{self, BoxStream};
+use futures::{future, Stream, StreamExt};
+use rocket::http::{ContentType, Status};
+use rocket::request::{FromRequest, Outcome};
+use rocket::response::content;
+use rocket::{Request, Route, State};
+use rocket_contrib::json::Json;
+use serde::Serialize;
+use tokio::io::{stream_reader, AsyncRead};
+use tokio::time::{interval, timeout, Duration};
+use tracing::{error, warn};
+
+use kinesis::consumer::Consumer;
+use kinesis::deaggregator::UserRecord;
+use kinesis::ShardId;
+
+use crate::auth::ReadAccess;
+use crate::checkpoint::Checkpoint;
+use crate::config::PollConfig;
+
+/// How long a long-poll request that has received a record waits for another
+const LINGER: Duration = Duration::from_millis(100);
+
+/// A record delivered to a client
+#[derive(Serialize)]
+struct Record {
+    shard_id: String,
+    sequence_number: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sub_sequence_number: Option<u64>,
+    partition_key: String,
+    data: Bytes,
+}
+
+impl Record {
+    fn new(shard_id: ShardId, record: UserRecord) -> Record {
+        Record {
+            shard_id: shard_id.to_string(),
+            sequence_number: record.sequence_number,
+            sub_sequence_number: record.sub_sequence_number,
+            partition_key: record.partition_key,
+            data: record.data,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct PollResponse {
+    records: Vec<Record>,
+    /// The token to resume reading from after these records
+    token: String,
+}
+
+/// The `Last-Event-ID` header sent by clients reconnecting to an event stream
+struct LastEventId(String);
+
+#[rocket::async_trait]
+impl<'a, 'r> FromRequest<'a, 'r> for LastEventId {
+    type Error = ();
+
+    async fn from_request(request: &'a Request<'r>) -> Outcome<LastEventId, Self::Error> {
+        match request.headers().get_one("Last-Event-ID") {
+            Some(id) => Outcome::Success(LastEventId(id.to_string())),
+            None => Outcome::Forward(()),
+        }
+    }
+}
+
+/// Fails with 404 if the service does not serve `stream`, or 403 if the request
+/// may not read from it
+fn authorize<'a>(
+    access: &ReadAccess,
+    stream: &str,
+    consumers: &'a HashMap<String, Consumer>,
+) -> Result<&'a Consumer, Status> {
+    let consumer = consumers.get(stream).ok_or(Status::NotFound)?;
+    if !access.allows(stream) {
+        return Err(Status::Forbidden);
+    }
+    Ok(consumer)
+}
+
+/// Decodes the checkpoint a client is resuming from, or starts reading from now
+fn checkpoint(token: Option<&str>) -> Result<Checkpoint, Status> {
+    match token {
+        Some(token) => Checkpoint::decode(token).map_err(|e| {
+            warn!("invalid checkpoint token: {:?}", e);
+            Status::BadRequest
+        }),
+        None => Ok(Checkpoint::now()),
+    }
+}
+
+/// Returns the records of the stream after `checkpoint`, along with the shard each is from
+///
+/// Shards are read concurrently and so records are only ordered within a shard. Shards
+/// created after the stream is listed, such as by resharding, are not read
+async fn read(
+    consumer: &Consumer,
+    checkpoint: &Checkpoint,
+) -> Result<BoxStream<'static, (ShardId, UserRecord)>, Status> {
+    let shards = consumer.shards().await.map_err(|e| {
+        error!("error listing shards: {:?}", e);
+        Status::BadGateway
+    })?;
+
+    let streams = shards.into_iter().map(|shard_id| {
+        let checkpoint = checkpoint.clone();
+        consumer
+            .shard(shard_id, checkpoint.starting_position(shard_id))
+            .filter_map(move |result| {
+                // Errors are logged by the consumer, which retries after its poll interval
+                let record = match result {
+                    Ok(record) if !checkpoint.delivered(shard_id, &record) => {
+                        Some((shard_id, record))
+                    }
+                    _ => None,
+                };
+                future::ready(record)
+            })
+    });
+
+    Ok(stream::select_all(streams).boxed())
+}
+
+/// Waits up to `wait` for an item from `items`, returning it along with up to `limit`
+/// items in total that follow it in quick succession
+async fn collect<S: Stream + Unpin>(items: &mut S, limit: usize, wait: Duration) -> Vec<S::Item> {
+    let mut batch = Vec::new();
+    let mut wait = wait;
+    while batch.len() < limit {
+        match timeout(wait, items.next()).await {
+            Ok(Some(item)) => batch.push(item),
+            _ => break,
+        }
+        wait = LINGER;
+    }
+    batch
+}
+
+/// Returns the records written to the stream after `token`, waiting up to `wait_ms`
+/// for one to arrive if there are none, along with the token to read from next
+///
+/// Clients without a token receive records written after their first request
+#[get("/api/v1/streams/<stream>/records?<token>&<limit>&<wait_ms>")]
+async fn poll(
+    access: ReadAccess,
+    stream: String,
+    token: Option<String>,
+    limit: Option<usize>,
+    wait_ms: Option<u64>,
+    consumers: State<'_, HashMap<String, Consumer>>,
+    config: State<'_, PollConfig>,
+) -> Result<Json<PollResponse>, Status> {
+    let consumer = authorize(&access, &stream, &consumers)?;
+    let mut checkpoint = checkpoint(token.as_deref())?;
+
+    let limit = limit.unwrap_or(config.max_records).min(config.max_records);
+    let wait = wait_ms
+        .unwrap_or(config.max_wait_ms)
+        .min(config.max_wait_ms);
+
+    let mut records = read(consumer, &checkpoint).await?;
+    let batch = collect(&mut records, limit, Duration::from_millis(wait)).await;
+
+    let records = batch
+        .into_iter()
+        .map(|(shard_id, record)| {
+            checkpoint.advance(shard_id, &record);
+            Record::new(shard_id, record)
+        })
+        .collect();
+
+    Ok(Json(PollResponse {
+        records,
+        token: checkpoint.encode(),
+    }))
+}
+
+/// Formats a server-sent event delivering `record`, identified by the token to resume
+/// reading from after it
+fn event(token: &str, record: &Record) -> Bytes {
+    let data = serde_json::to_string(record).expect("record is serializable");
+    Bytes::from(format!("id: {}\ndata: {}\n\n", token, data))
+}
+
+/// Streams the records written to the stream after `token` as server-sent events
+///
+/// Each event's id is the token to resume reading from after it, which browsers send
+/// as the `Last-Event-ID` header when reconnecting
+#[get("/api/v1/streams/<stream>/events?<token>")]
+async fn events(
+    access: ReadAccess,
+    stream: String,
+    token: Option<String>,
+    last_event_id: Option<LastEventId>,
+    consumers: State<'_, HashMap<String, Consumer>>,
+    config: State<'_, PollConfig>,
+) -> Result<content::Custom<rocket::response::Stream<impl AsyncRead>>, Status> {
+    let consumer = authorize(&access, &stream, &consumers)?;
+    let token = last_event_id.map(|id| id.0).or(token);
+    let mut checkpoint = checkpoint(token.as_deref())?;
+
+    let records = read(consumer, &checkpoint).await?;
+    let events = records.map(move |(shard_id, record)| {
+        checkpoint.advance(shard_id, &record);
+        event(&checkpoint.encode(), &Record::new(shard_id, record))
+    });
+
+    // Comments keep idle connections from being closed by intermediate proxies
+    let keep_alive = interval(Duration::from_secs(config.keep_alive_secs))
+        .map(|_| Bytes::from_static(b": keep-alive\n\n"));
+
+    let body = stream::select(events, keep_alive).map(Ok::<_, std::io::Error>);
+
+    Ok(content::Custom(
+        ContentType::new("text", "event-stream"),
+        rocket::response::Stream::from(stream_reader(Box::pin(body))),
+    ))
+}
+
+pub fn routes() -> Vec<Route> {
+    let mut routes = routes![poll, events];
+    routes.append(&mut health::routes());
+    routes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_collect() {
+        let mut items = stream::iter(0..5).chain(stream::pending());
+        let wait = Duration::from_millis(10);
+
+        assert_eq!(collect(&mut items, 3, wait).await, vec![0, 1, 2]);
+        assert_eq!(collect(&mut items, 10, wait).await, vec![3, 4]);
+        assert!(collect(&mut items, 10, wait).await.is_empty());
+    }
+
+    #[test]
+    fn test_event() {
+        let record = Record {
+            shard_id: "shardId-000000000001".to_string(),
+            sequence_number: "1".to_string(),
+            sub_sequence_number: None,
+            partition_key: "key".to_string(),
+            data: Bytes::from_static(b"a"),
+        };
+
+        assert_eq!(
+            event("token", &record),
+            Bytes::from_static(
+                b"id: token\ndata: {\"shard_id\":\"shardId-000000000001\",\"sequence_number\":\"1\",\"partition_key\":\"key\",\"data\":[97]}\n\n"
+            )
+        );
+    }
+}
@@ -0,0 +1,38 @@
+use rocket_util::{ResourceAccess, ScopePrefix};
+
+/// The prefix of scopes granting read access to the stream they are suffixed with
+#[derive(Debug)]
+pub struct ReadScope;
+
+impl ScopePrefix for ReadScope {
+    const PREFIX: &'static str = "kinesis:read:";
+}
+
+/// The streams a request may read from, granted by `kinesis:read:<stream>` scopes,
+/// or all streams if it has the superuser scope
+pub type ReadAccess = ResourceAccess<ReadScope>;
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use jwt::Scope;
+
+    use super::*;
+
+    fn scopes(scopes: &str) -> HashSet<Scope> {
+        jwt::tag::parse_space_delimited(scopes).unwrap()
+    }
+
+    #[test]
+    fn test_read_access() {
+        let access = ReadAccess::new(&scopes("kinesis:read:a kinesis:write:b offline_access"));
+
+        assert!(access.allows("a"));
+        assert!(!access.allows("b"));
+        assert!(!access.allows("kinesis:read:a"));
+
+        let superuser = ReadAccess::new(&scopes("superuser"));
+        assert!(superuser.allows("b"));
+    }
+}
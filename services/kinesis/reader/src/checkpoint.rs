@@ -0,0 +1,151 @@
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use kinesis::consumer::StartingPosition;
+use kinesis::deaggregator::UserRecord;
+use kinesis::ShardId;
+
+#[derive(Debug)]
+pub enum TokenError {
+    Base64(base64::DecodeError),
+    Json(serde_json::Error),
+}
+
+/// The last record delivered from a shard
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Position {
+    sequence_number: String,
+    /// The position of the record within its aggregated parent, None if not aggregated
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sub_sequence_number: Option<u64>,
+}
+
+/// An opaque token identifying the records a client has received, allowing it to
+/// resume reading a stream from where it left off
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    /// The seconds since the unix epoch that reading started, shards without a
+    /// position are read from this time
+    since: f64,
+    /// The last record delivered from each shard, keyed by shard id
+    shards: HashMap<String, Position>,
+}
+
+impl Checkpoint {
+    /// Returns a checkpoint that reads records written from now onwards
+    pub fn now() -> Checkpoint {
+        let since = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("system time before unix epoch")
+            .as_secs_f64();
+
+        Checkpoint {
+            since,
+            shards: Default::default(),
+        }
+    }
+
+    pub fn decode(token: &str) -> Result<Checkpoint, TokenError> {
+        let json =
+            base64::decode_config(token, base64::URL_SAFE_NO_PAD).map_err(TokenError::Base64)?;
+        serde_json::from_slice(&json).map_err(TokenError::Json)
+    }
+
+    pub fn encode(&self) -> String {
+        let json = serde_json::to_vec(self).expect("checkpoint is serializable");
+        base64::encode_config(&json, base64::URL_SAFE_NO_PAD)
+    }
+
+    /// Returns the position to start reading `shard_id` from
+    ///
+    /// Shards positioned within an aggregated record are read from the start of that
+    /// record, with the sub-records already delivered skipped by `delivered`
+    pub fn starting_position(&self, shard_id: ShardId) -> StartingPosition {
+        match self.shards.get(&shard_id.to_string()) {
+            Some(Position {
+                sequence_number,
+                sub_sequence_number: Some(_),
+            }) => StartingPosition::AtSequenceNumber(sequence_number.clone()),
+            Some(position) => {
+                StartingPosition::AfterSequenceNumber(position.sequence_number.clone())
+            }
+            None => StartingPosition::AtTimestamp(self.since),
+        }
+    }
+
+    /// Returns true if `record` was delivered before this checkpoint was taken
+    pub fn delivered(&self, shard_id: ShardId, record: &UserRecord) -> bool {
+        match self.shards.get(&shard_id.to_string()) {
+            Some(position) => {
+                position.sequence_number == record.sequence_number
+                    && record.sub_sequence_number <= position.sub_sequence_number
+            }
+            None => false,
+        }
+    }
+
+    /// Records the delivery of `record` from `shard_id`
+    pub fn advance(&mut self, shard_id: ShardId, record: &UserRecord) {
+        self.shards.insert(
+            shard_id.to_string(),
+            Position {
+                sequence_number: record.sequence_number.clone(),
+                sub_sequence_number: record.sub_sequence_number,
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::*;
+
+    fn record(sequence_number: &str, sub_sequence_number: Option<u64>) -> UserRecord {
+        UserRecord {
+            partition_key: "key".to_string(),
+            explicit_hash_key: None,
+            data: Bytes::new(),
+            sequence_number: sequence_number.to_string(),
+            sub_sequence_number,
+        }
+    }
+
+    #[test]
+    fn test_checkpoint() {
+        let a: ShardId = "shardId-000000000001".parse().unwrap();
+        let b: ShardId = "shardId-000000000002".parse().unwrap();
+
+        let mut checkpoint = Checkpoint::now();
+        assert!(matches!(
+            checkpoint.starting_position(a),
+            StartingPosition::AtTimestamp(_)
+        ));
+
+        checkpoint.advance(a, &record("1", None));
+        checkpoint.advance(b, &record("2", Some(1)));
+
+        let decoded = Checkpoint::decode(&checkpoint.encode()).unwrap();
+        assert_eq!(decoded, checkpoint);
+
+        assert!(matches!(
+            decoded.starting_position(a),
+            StartingPosition::AfterSequenceNumber(s) if s == "1"
+        ));
+        assert!(matches!(
+            decoded.starting_position(b),
+            StartingPosition::AtSequenceNumber(s) if s == "2"
+        ));
+
+        // Only the sub-records of the aggregated record already delivered are skipped
+        assert!(decoded.delivered(b, &record("2", Some(0))));
+        assert!(decoded.delivered(b, &record("2", Some(1))));
+        assert!(!decoded.delivered(b, &record("2", Some(2))));
+        assert!(!decoded.delivered(b, &record("3", None)));
+
+        assert!(Checkpoint::decode("not a token").is_err());
+    }
+}
@@ -0,0 +1,105 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use tokio::time::Duration;
+
+use jwt::ValidatorConfig;
+use kinesis::consumer::{Consumer, ConsumerBuilder};
+use settings::{Error, Validate};
+use telemetry::trace::TracingConfig;
+
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct Config {
+    pub validator: ValidatorConfig,
+    pub kinesis: KinesisConfig,
+    pub poll: PollConfig,
+    pub tracing: TracingConfig,
+}
+
+impl Validate for Config {
+    fn validate(&self) -> Result<(), Error> {
+        if self.poll.max_records == 0 {
+            return Err(Error::invalid("poll.max_records", "must be positive"));
+        }
+        if self.poll.keep_alive_secs == 0 {
+            return Err(Error::invalid("poll.keep_alive_secs", "must be positive"));
+        }
+        Ok(())
+    }
+}
+
+/// Configures the delivery of records to clients
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct PollConfig {
+    /// The maximum number of records returned by a single long-poll request
+    pub max_records: usize,
+    /// The maximum time a long-poll request waits for records to arrive
+    pub max_wait_ms: u64,
+    /// The interval between keep-alive comments sent on an event stream
+    pub keep_alive_secs: u64,
+}
+
+impl Default for PollConfig {
+    fn default() -> PollConfig {
+        PollConfig {
+            max_records: 1000,
+            max_wait_ms: 20000,
+            keep_alive_secs: 15,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct KinesisConfig {
+    pub region: String,
+    pub endpoint: Option<String>,
+    /// The streams clients may read from
+    pub streams: Vec<String>,
+    pub local: bool,
+    pub localstack: bool,
+    /// The delay between GetRecords calls to a shard that returned no records
+    pub poll_interval_ms: u64,
+}
+
+impl Default for KinesisConfig {
+    fn default() -> KinesisConfig {
+        KinesisConfig {
+            region: "us-east-1".to_string(),
+            endpoint: None,
+            streams: vec![],
+            local: false,
+            localstack: false,
+            poll_interval_ms: 1000,
+        }
+    }
+}
+
+impl KinesisConfig {
+    /// Returns a consumer for each stream, keyed by stream name
+    pub fn consumers(&self) -> HashMap<String, Consumer> {
+        self.streams
+            .iter()
+            .map(|stream| {
+                let mut builder = ConsumerBuilder::new(self.region.clone(), stream.clone());
+                builder.poll_interval(Duration::from_millis(self.poll_interval_ms));
+
+                if self.local {
+                    builder.local();
+                }
+
+                if self.localstack {
+                    builder.localstack();
+                }
+
+                if let Some(endpoint) = self.endpoint.as_ref() {
+                    builder.endpoint(endpoint.clone());
+                }
+
+                (stream.clone(), builder.build())
+            })
+            .collect()
+    }
+}
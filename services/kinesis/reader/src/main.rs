@@ -0,0 +1,55 @@
+#[macro_use]
+extern crate rocket;
+
+use std::collections::HashMap;
+
+use health::{Health, Probe};
+use jwt::Validator;
+use kinesis::consumer::Consumer;
+use rocket_util::RequestTracer;
+
+mod api;
+mod auth;
+mod checkpoint;
+mod config;
+
+/// Returns the checks of the keys tokens are validated with, and that each stream can
+/// be described
+fn health(validator: Validator, consumers: HashMap<String, Consumer>) -> Health {
+    let probes = [Probe::Ready, Probe::Startup];
+    let health = Health::new().register("jwks", &probes, move || {
+        let result = validator.check();
+        async move { result }
+    });
+
+    consumers
+        .into_iter()
+        .fold(health, |health, (stream, consumer)| {
+            health.register(format!("kinesis:{}", stream), &probes, move || {
+                let consumer = consumer.clone();
+                async move { consumer.check().await.map_err(|e| format!("{:?}", e)) }
+            })
+        })
+}
+
+#[rocket::main]
+async fn main() {
+    let (config, figment) = settings::load_with::<config::Config>(rocket::Config::figment());
+    let _exporter = telemetry::trace::init("reader", env!("CARGO_PKG_VERSION"), &config.tracing);
+
+    let validator = Validator::new(&config.validator).expect("Failed to load JWT validator");
+    let consumers = config.kinesis.consumers();
+    let health = health(validator.clone(), consumers.clone());
+
+    let result = rocket::custom(figment)
+        .manage(validator)
+        .manage(health)
+        .manage(consumers)
+        .manage(config.poll)
+        .attach(RequestTracer)
+        .mount("/", api::routes())
+        .launch()
+        .await;
+
+    assert!(result.is_ok());
+}